@@ -37,6 +37,9 @@ use emulator::link::try_pull_msg;
 struct CliArgs {
     #[clap(flatten)]
     global_opts: GlobalOpts,
+
+    #[clap(flatten, next_help_heading = "Fault injection")]
+    faults: emulator::fault::FaultConfig,
 }
 
 #[derive(Debug, Args)]
@@ -155,6 +158,7 @@ async fn main() -> Result<(), emulator::Error> {
         emulator::utils::model::get_entropy(&args.global_opts.entropy),
     )
     .await?;
+    emulator.faults.configure(args.faults);
 
     let output_settings = OutputSettingsBuilder::new().scale(1).build();
 
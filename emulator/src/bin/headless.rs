@@ -0,0 +1,156 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::{Path, PathBuf};
+
+use env_logger::Env;
+
+use clap::{Args, Parser};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct CliArgs {
+    #[clap(flatten)]
+    global_opts: GlobalOpts,
+
+    #[clap(flatten, next_help_heading = "Fault injection")]
+    faults: emulator::fault::FaultConfig,
+}
+
+#[derive(Debug, Args)]
+struct GlobalOpts {
+    /// Path of the UNIX socket the RPC server listens on
+    #[clap(long, short = 'l', default_value = "./emulator-rpc.socket")]
+    rpc_socket: PathBuf,
+
+    /// Path for the UNIX socket of QEMU's serial port
+    ///
+    /// Used only when `--no-auto-qemu` is enabled, otherwise an instance of QEMU
+    /// is spawned internally
+    #[clap(long, short = 's', default_value = "./firmware/serial1.socket")]
+    emulator_socket: PathBuf,
+
+    #[clap(
+        long,
+        short = 'f',
+        default_value = "./firmware/target/thumbv7em-none-eabihf/debug/firmware"
+    )]
+    /// Path of the firmware ELF file
+    ///
+    /// Used when spawning a QEMU instance internally
+    firmware: PathBuf,
+
+    /// Do not launch QEMU internally
+    ///
+    /// This will make the emulator connect to the UNIX socket specified with `--emulator-socket`
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value_t = false)]
+    no_auto_qemu: bool,
+
+    /// Whether to print emulated firmware logs to the emulator's stderr
+    ///
+    /// This only has an effect when spawning QEMU internally. When disabled the logs
+    /// can be retrieved with the `logs` RPC method.
+    #[clap(long, short = 'j', action = clap::ArgAction::SetTrue, default_value_t = false)]
+    join_logs: bool,
+
+    /// File backing the flash memory
+    ///
+    /// If unspecified the flash data will only be kept in memory temporarily.
+    #[clap(long)]
+    flash_file: Option<PathBuf>,
+
+    /// Entropy used to seed the device
+    ///
+    /// If unspecified it will be generated randomly. Must be a 32-byte hex string
+    #[clap(long, short = 'e', value_parser = emulator::utils::model::parse_entropy)]
+    entropy: Option<emulator::utils::model::Entropy>,
+
+    /// Record the session to a transcript file
+    #[clap(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay a transcript instead of starting the RPC server
+    ///
+    /// Every difference in the screens or NFC replies is printed to stdout as a JSON line and
+    /// the process exits with an error if any was found.
+    #[clap(long)]
+    replay: Option<PathBuf>,
+
+    /// How many ticks the display is allowed to lag behind the transcript during a replay
+    #[clap(long, default_value_t = 16)]
+    display_tolerance: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), emulator::Error> {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = CliArgs::parse();
+
+    if !args.global_opts.no_auto_qemu && !Path::new(&args.global_opts.firmware).exists() {
+        return Err(format!(
+            "Chosen firmware file doesn't exist: {}",
+            args.global_opts.firmware.display()
+        )
+        .into());
+    }
+
+    let mut emulator = emulator::utils::get_emulator_instance(
+        !args.global_opts.no_auto_qemu,
+        &args.global_opts.emulator_socket,
+        &args.global_opts.firmware,
+        args.global_opts.join_logs,
+        args.global_opts
+            .flash_file
+            .map(|f| emulator::utils::get_flash_file(&f))
+            .transpose()?,
+        None,
+        false,
+        emulator::utils::model::get_entropy(&args.global_opts.entropy),
+    )
+    .await?;
+    emulator.faults.configure(args.faults);
+
+    if let Some(replay) = &args.global_opts.replay {
+        let entries = emulator::transcript::load_transcript(replay)?;
+        let mismatches = emulator::transcript::replay(
+            &mut emulator,
+            &entries,
+            args.global_opts.display_tolerance,
+        )
+        .await?;
+
+        for mismatch in &mismatches {
+            println!("{}", serde_json::to_string(mismatch)?);
+        }
+
+        return match mismatches.len() {
+            0 => {
+                log::info!("Replayed {} entries successfully", entries.len());
+                Ok(())
+            }
+            n => Err(format!("Found {} differences while replaying", n).into()),
+        };
+    }
+
+    emulator::rpc::run_rpc_server(
+        emulator,
+        &args.global_opts.rpc_socket,
+        args.global_opts.record.as_deref(),
+    )
+    .await
+}
@@ -0,0 +1,157 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Fault injection for the emulated hardware
+//!
+//! Used to exercise the code that recovers from NFC errors and power losses (retries, update
+//! checkpoints, partial configs) without having to yank a real card out of the field. All the
+//! faults are disabled by default.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, clap::Args)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// Probability of dropping an NFC fragment, in either direction
+    #[clap(long, default_value_t = 0.0)]
+    pub nfc_drop_rate: f64,
+
+    /// Maximum latency added to every NFC fragment received from the device, in milliseconds
+    ///
+    /// The actual latency is chosen randomly between zero and this value.
+    #[clap(long, default_value_t = 0)]
+    pub nfc_max_latency: u64,
+
+    /// Reset the device right after the n-th flash write, as if the power was lost
+    ///
+    /// Only happens once, writes are counted from the moment the faults are configured.
+    #[clap(long)]
+    pub reset_after_writes: Option<u32>,
+
+    /// Probability of losing a flash write
+    #[clap(long, default_value_t = 0.0)]
+    pub flash_failure_rate: f64,
+
+    /// Seed for the random faults, to reproduce a failing run
+    #[clap(long)]
+    pub fault_seed: Option<u64>,
+}
+
+/// What to do with a flash write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashFault {
+    None,
+    /// The data is not written
+    Lost,
+    /// The data is written and the device is reset right after
+    Reset,
+}
+
+struct State {
+    config: FaultConfig,
+    rng: StdRng,
+    flash_writes: u32,
+}
+
+pub struct FaultInjector {
+    state: Mutex<State>,
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        FaultInjector::new(FaultConfig::default())
+    }
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig) -> Self {
+        FaultInjector {
+            state: Mutex::new(State::new(config)),
+        }
+    }
+
+    /// Replace the current configuration, starting to count the flash writes again
+    pub fn configure(&self, config: FaultConfig) {
+        if config != FaultConfig::default() {
+            log::info!("Injecting faults: {:?}", config);
+        }
+
+        *self.state.lock().expect("Lock poisoned") = State::new(config);
+    }
+
+    pub fn config(&self) -> FaultConfig {
+        self.state.lock().expect("Lock poisoned").config.clone()
+    }
+
+    /// Whether the next NFC fragment should be dropped
+    pub fn drop_nfc(&self) -> bool {
+        let mut state = self.state.lock().expect("Lock poisoned");
+        let rate = state.config.nfc_drop_rate;
+        state.chance(rate)
+    }
+
+    /// Latency to add to the next NFC fragment
+    pub fn nfc_latency(&self) -> Duration {
+        let mut state = self.state.lock().expect("Lock poisoned");
+        match state.config.nfc_max_latency {
+            0 => Duration::ZERO,
+            max => Duration::from_millis(state.rng.gen_range(0..=max)),
+        }
+    }
+
+    /// Decide what happens to a flash write
+    pub fn flash_write(&self) -> FlashFault {
+        let mut state = self.state.lock().expect("Lock poisoned");
+        state.flash_writes += 1;
+
+        let rate = state.config.flash_failure_rate;
+        if state.chance(rate) {
+            return FlashFault::Lost;
+        }
+        if state.config.reset_after_writes == Some(state.flash_writes) {
+            state.config.reset_after_writes = None;
+            return FlashFault::Reset;
+        }
+
+        FlashFault::None
+    }
+}
+
+impl State {
+    fn new(config: FaultConfig) -> Self {
+        let rng = match config.fault_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        State {
+            config,
+            rng,
+            flash_writes: 0,
+        }
+    }
+
+    fn chance(&mut self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.gen_bool(rate.min(1.0))
+    }
+}
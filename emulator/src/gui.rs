@@ -128,6 +128,8 @@ pub fn init_gui(
                     } else {
                         Some(password)
                     },
+                    false,
+                    None,
                 )
                 .await
             {
@@ -227,6 +229,7 @@ pub fn init_gui(
                     } else {
                         Some(password)
                     },
+                    false,
                 )
                 .await
             {
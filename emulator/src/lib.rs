@@ -15,11 +15,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod fault;
 #[cfg(feature = "gui")]
 pub mod gui;
 pub mod link;
+pub mod rpc;
+pub mod screenshot;
 #[cfg(test)]
 mod tests;
+pub mod transcript;
 pub mod utils;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -24,6 +24,7 @@ use embedded_graphics::prelude::*;
 
 use model::emulator::{CardMessage, EmulatorMessage};
 
+use crate::fault::FlashFault;
 use crate::utils::{EmulatorInstance, ReadWrite};
 
 pub enum FlashMessage {
@@ -189,7 +190,15 @@ where
             }
             FlashMessage::Write(data) => {
                 append_to_console("< ", "WriteFlash", arg);
-                handle_write_flash(&mut emulator.flash, &data)?;
+                match emulator.faults.flash_write() {
+                    FlashFault::None => handle_write_flash(&mut emulator.flash, &data)?,
+                    FlashFault::Lost => log::info!("Injected fault: flash write lost"),
+                    FlashFault::Reset => {
+                        handle_write_flash(&mut emulator.flash, &data)?;
+                        log::info!("Injected fault: reset after flash write");
+                        emulator.card.send(EmulatorMessage::Reset)?;
+                    }
+                }
             }
         }
     }
@@ -0,0 +1,413 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! JSON-RPC control API for the headless emulator
+//!
+//! Requests and responses are JSON-RPC 2.0 objects, one per line, exchanged over a UNIX socket.
+//! Every request is processed concurrently, so that for example a `nfc` request waiting for the
+//! user confirmation can be followed by a `hold` request on the same connection.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, oneshot};
+
+use portal::PortalSdk;
+
+use model::emulator::EmulatorMessage;
+
+use crate::fault::{FaultConfig, FaultInjector};
+use crate::link::{manage_hw, try_pull_msg};
+use crate::transcript::{display_png, Recorder, TranscriptEvent};
+use crate::utils::model::NfcAction;
+use crate::utils::EmulatorInstance;
+
+// Standard JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Implementation-defined error codes
+const SDK_ERROR: i64 = -32000;
+const EMULATOR_ERROR: i64 = -32001;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Operations that need exclusive access to the emulator instance
+enum Control {
+    Input(bool),
+    Reset,
+    Framebuffer(oneshot::Sender<String>),
+    WaitTicks(usize, oneshot::Sender<()>),
+    Logs(oneshot::Sender<Vec<String>>),
+    Record(TranscriptEvent),
+}
+
+#[derive(Debug, Deserialize)]
+struct HoldParams {
+    ticks: usize,
+}
+#[derive(Debug, Deserialize)]
+struct InputParams {
+    value: bool,
+}
+
+pub(crate) async fn run_nfc_action(
+    sdk: &PortalSdk,
+    action: NfcAction,
+) -> Result<Value, portal::SdkError> {
+    Ok(match action {
+        NfcAction::GetStatus => {
+            let status = sdk.get_status().await?;
+            json!({
+                "initialized": status.initialized,
+                "unverified": status.unverified,
+                "unlocked": status.unlocked,
+                "network": status.network.map(|n| n.to_string()),
+                "version": status.version,
+                "fingerprint": status.fingerprint.map(|f| f.to_string()),
+            })
+        }
+        NfcAction::SignPsbt(psbt) => json!(sdk.sign_psbt(psbt).await?),
+        NfcAction::GenerateMnemonic(num_words, network, pair_code) => {
+            let num_words = match num_words {
+                model::NumWordsMnemonic::Words12 => portal::GenerateMnemonicWords::Words12,
+                model::NumWordsMnemonic::Words24 => portal::GenerateMnemonicWords::Words24,
+            };
+            sdk.generate_mnemonic(num_words, network, pair_code, false, None)
+                .await?;
+            Value::Null
+        }
+        NfcAction::RestoreMnemonic(words, network, pair_code) => {
+            sdk.restore_mnemonic(words, network, pair_code, false)
+                .await?;
+            Value::Null
+        }
+        NfcAction::RequestDescriptors => {
+            let descriptors = sdk.public_descriptors().await?;
+            json!({ "external": descriptors.external, "internal": descriptors.internal })
+        }
+        NfcAction::DisplayAddress(index) => json!(sdk.display_address(index).await?.to_string()),
+        NfcAction::Unlock(pwd) => {
+            sdk.unlock(pwd).await?;
+            Value::Null
+        }
+        NfcAction::Resume => {
+            sdk.resume().await?;
+            Value::Null
+        }
+        NfcAction::GetXpub(path) => {
+            let path = path
+                .parse()
+                .map_err(|_| portal::SdkError::DeserializationError)?;
+            let xpub = sdk.get_xpub(path).await?;
+            json!({
+                "xpub": xpub.xpub,
+                "bsms": {
+                    "version": xpub.bsms.version,
+                    "token": xpub.bsms.token,
+                    "key_name": xpub.bsms.key_name,
+                    "signature": xpub.bsms.signature,
+                },
+            })
+        }
+        NfcAction::SetDescriptor(desc, bsms) => {
+            let bsms = bsms.map(|data| portal::SetDescriptorBsmsData {
+                first_address: data.first_address,
+                version: "1.0".into(),
+                path_restrictions: "/0/*,/1/*".into(),
+            });
+            sdk.set_descriptor(desc, bsms).await?;
+            Value::Null
+        }
+        NfcAction::Raw(data) => {
+            sdk.debug_send_raw(data).await?;
+            Value::Null
+        }
+    })
+}
+
+async fn call_control<T>(
+    control: &mpsc::UnboundedSender<Control>,
+    make: impl FnOnce(oneshot::Sender<T>) -> Control,
+) -> Result<T, RpcError> {
+    let (s, r) = oneshot::channel();
+    control
+        .send(make(s))
+        .map_err(|e| RpcError::new(EMULATOR_ERROR, e))?;
+    r.await.map_err(|e| RpcError::new(EMULATOR_ERROR, e))
+}
+
+async fn process_request(
+    req: &RpcRequest,
+    sdk: &PortalSdk,
+    faults: &FaultInjector,
+    control: &mpsc::UnboundedSender<Control>,
+) -> Result<Value, RpcError> {
+    fn parse_params<T: serde::de::DeserializeOwned>(params: &Value) -> Result<T, RpcError> {
+        serde_json::from_value(params.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+    }
+    let send = |msg: Control| {
+        control
+            .send(msg)
+            .map_err(|e| RpcError::new(EMULATOR_ERROR, e))
+    };
+
+    match req.method.as_str() {
+        "nfc" => {
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+            let action: NfcAction = parse_params(&req.params)?;
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            send(Control::Record(TranscriptEvent::NfcRequest {
+                id,
+                action: action.clone(),
+            }))?;
+
+            let result = run_nfc_action(sdk, action).await;
+            send(Control::Record(TranscriptEvent::NfcReply {
+                id,
+                result: result.as_ref().cloned().map_err(|e| e.to_string()),
+            }))?;
+
+            result.map_err(|e| RpcError::new(SDK_ERROR, e))
+        }
+        "input" => {
+            let InputParams { value } = parse_params(&req.params)?;
+            send(Control::Input(value))?;
+            Ok(Value::Null)
+        }
+        "hold" => {
+            let HoldParams { ticks } = parse_params(&req.params)?;
+            send(Control::Input(true))?;
+            call_control(control, |s| Control::WaitTicks(ticks, s)).await?;
+            send(Control::Input(false))?;
+            Ok(Value::Null)
+        }
+        "wait_ticks" => {
+            let HoldParams { ticks } = parse_params(&req.params)?;
+            call_control(control, |s| Control::WaitTicks(ticks, s)).await?;
+            Ok(Value::Null)
+        }
+        "framebuffer" => Ok(json!(call_control(control, Control::Framebuffer).await?)),
+        "logs" => Ok(json!(call_control(control, Control::Logs).await?)),
+        "reset" => {
+            send(Control::Reset)?;
+            Ok(Value::Null)
+        }
+        "faults" => {
+            let config: FaultConfig = parse_params(&req.params)?;
+            faults.configure(config);
+            Ok(Value::Null)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", req.method),
+        )),
+    }
+}
+
+async fn handle_line(
+    line: &str,
+    sdk: &PortalSdk,
+    faults: &FaultInjector,
+    control: &mpsc::UnboundedSender<Control>,
+) -> Value {
+    let req: RpcRequest = match serde_json::from_str(line) {
+        Ok(req) => req,
+        Err(e) => {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": e.to_string() },
+            })
+        }
+    };
+
+    log::debug!("RPC request: {:?}", req);
+
+    match process_request(&req, sdk, faults, control).await {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": req.id, "result": result }),
+        Err(RpcError { code, message }) => json!({
+            "jsonrpc": "2.0",
+            "id": req.id,
+            "error": { "code": code, "message": message },
+        }),
+    }
+}
+
+fn spawn_server(
+    listener: UnixListener,
+    sdk: Arc<PortalSdk>,
+    faults: Arc<FaultInjector>,
+    control: mpsc::UnboundedSender<Control>,
+) {
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(v) => v,
+                Err(e) => {
+                    log::error!("Accept error: {:?}", e);
+                    break;
+                }
+            };
+            log::info!("New RPC client connected");
+
+            let (reader, mut writer) = stream.into_split();
+            let (reply_s, mut reply_r) = mpsc::unbounded_channel::<Value>();
+            tokio::spawn(async move {
+                while let Some(reply) = reply_r.recv().await {
+                    let mut line = reply.to_string();
+                    line.push('\n');
+                    if writer.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let sdk = Arc::clone(&sdk);
+            let faults = Arc::clone(&faults);
+            let control = control.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let sdk = Arc::clone(&sdk);
+                    let faults = Arc::clone(&faults);
+                    let control = control.clone();
+                    let reply_s = reply_s.clone();
+                    tokio::spawn(async move {
+                        let reply = handle_line(&line, &sdk, &faults, &control).await;
+                        let _ = reply_s.send(reply);
+                    });
+                }
+
+                log::info!("RPC client disconnected");
+            });
+        }
+    });
+}
+
+/// Run the emulator, accepting RPC connections on the UNIX socket at `path`
+///
+/// If `record` is set the session is saved to a transcript that can later be replayed with
+/// [`crate::transcript::replay`].
+pub async fn run_rpc_server(
+    mut emulator: EmulatorInstance,
+    path: &Path,
+    record: Option<&Path>,
+) -> Result<(), crate::Error> {
+    let mut recorder = record.map(Recorder::create).transpose()?;
+
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    log::info!("Listening for RPC connections on {}", path.display());
+
+    let (control_s, mut control_r) = mpsc::unbounded_channel();
+    spawn_server(
+        listener,
+        Arc::clone(&emulator.sdk),
+        Arc::clone(&emulator.faults),
+        control_s,
+    );
+
+    let mut ticks = 0;
+    let mut tick_waiters: Vec<(usize, oneshot::Sender<()>)> = vec![];
+    let mut logs = vec![];
+
+    loop {
+        manage_hw(&mut emulator, |_, _, _| {}, &mut (), false, false).await?;
+
+        while try_pull_msg::<()>(&mut emulator.msgs.tick)?.is_some() {
+            ticks += 1;
+            for (remaining, _) in &mut tick_waiters {
+                *remaining = remaining.saturating_sub(1);
+            }
+        }
+        let (ready, pending) = tick_waiters
+            .drain(..)
+            .partition::<Vec<_>, _>(|(remaining, _)| *remaining == 0);
+        tick_waiters = pending;
+        for (_, s) in ready {
+            let _ = s.send(());
+        }
+
+        while let Ok(line) = emulator.logs.try_recv() {
+            logs.push(line);
+        }
+
+        while let Some(msg) = try_pull_msg(&mut control_r)? {
+            match msg {
+                Control::Input(value) => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(&emulator, ticks, TranscriptEvent::Input(value))?;
+                    }
+                    emulator.card.send(EmulatorMessage::Tsc(value))?
+                }
+                Control::Reset => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(&emulator, ticks, TranscriptEvent::Reset)?;
+                    }
+                    emulator.card.send(EmulatorMessage::Reset)?
+                }
+                Control::Framebuffer(s) => {
+                    let _ = s.send(display_png(&emulator)?);
+                }
+                Control::WaitTicks(ticks, s) => tick_waiters.push((ticks, s)),
+                Control::Logs(s) => {
+                    let _ = s.send(std::mem::take(&mut logs));
+                }
+                Control::Record(event) => {
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record(&emulator, ticks, event)?;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Golden images and text matching for the emulated display
+//!
+//! Golden images are PNG files stored by name in `./golden` (or in `$GOLDEN_DIR`). When
+//! `$UPDATE_GOLDEN` is set they are written with the content of the display instead of being
+//! compared with it, so that they can be regenerated after an intentional change to the UI.
+//!
+//! Text is matched by rendering it with the same fonts used by the `gui` crate and looking for
+//! the result anywhere on the display, either in white on black or inverted.
+
+use std::path::PathBuf;
+
+use embedded_graphics::mono_font::{ascii, MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+use embedded_graphics_simulator::{OutputSettingsBuilder, SimulatorDisplay};
+
+const GOLDEN_DIR: &str = "./golden";
+
+/// Fonts used by the pages in the `gui` crate
+const FONTS: &[&MonoFont<'static>] = &[
+    &ascii::FONT_9X15_BOLD,
+    &ascii::FONT_8X13_BOLD,
+    &ascii::FONT_6X10,
+    &ascii::FONT_5X8,
+    &ascii::FONT_5X7,
+];
+
+pub fn golden_path(name: &str) -> PathBuf {
+    std::env::var_os("GOLDEN_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(GOLDEN_DIR))
+        .join(format!("{}.png", name))
+}
+
+/// Whether golden images should be written instead of compared
+pub fn update_golden() -> bool {
+    std::env::var_os("UPDATE_GOLDEN").is_some()
+}
+
+/// Load the golden image called `name`, returns `None` if it doesn't exist
+pub fn load_golden(name: &str) -> Result<Option<SimulatorDisplay<BinaryColor>>, crate::Error> {
+    let path = golden_path(name);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(SimulatorDisplay::load_png(path)?))
+}
+
+pub fn save_golden(
+    display: &SimulatorDisplay<BinaryColor>,
+    name: &str,
+) -> Result<(), crate::Error> {
+    let path = golden_path(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let output_settings = OutputSettingsBuilder::new().scale(1).build();
+    display
+        .to_grayscale_output_image(&output_settings)
+        .save_png(&path)?;
+    log::info!("Saved golden image to {}", path.display());
+
+    Ok(())
+}
+
+pub fn same_content(a: &SimulatorDisplay<BinaryColor>, b: &SimulatorDisplay<BinaryColor>) -> bool {
+    a.size() == b.size() && a.to_be_bytes() == b.to_be_bytes()
+}
+
+/// Pixels of a display or of some rendered text, `true` when lit
+struct Bitmap {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    fn from_display(display: &SimulatorDisplay<BinaryColor>) -> Self {
+        let size = display.size();
+        let pixels = (0..size.height as i32)
+            .flat_map(|y| (0..size.width as i32).map(move |x| Point::new(x, y)))
+            .map(|p| display.get_pixel(p).is_on())
+            .collect();
+
+        Bitmap {
+            width: size.width as usize,
+            height: size.height as usize,
+            pixels,
+        }
+    }
+
+    fn render(text: &str, font: &MonoFont<'static>) -> Self {
+        let len = text.chars().count() as u32;
+        let width =
+            len * font.character_size.width + len.saturating_sub(1) * font.character_spacing;
+        let mut display = SimulatorDisplay::new(Size::new(width, font.character_size.height));
+        Text::with_baseline(
+            text,
+            Point::zero(),
+            MonoTextStyle::new(font, BinaryColor::On),
+            Baseline::Top,
+        )
+        .draw(&mut display)
+        .expect("Infallible");
+
+        Bitmap::from_display(&display)
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.pixels[x + y * self.width]
+    }
+
+    /// Whether `pattern` appears anywhere, either as it is or inverted
+    fn find(&self, pattern: &Bitmap) -> bool {
+        if pattern.width == 0 || pattern.width > self.width || pattern.height > self.height {
+            return false;
+        }
+
+        let matches_at = |x: usize, y: usize, inverted: bool| {
+            (0..pattern.height).all(|py| {
+                (0..pattern.width)
+                    .all(|px| self.get(x + px, y + py) == pattern.get(px, py) ^ inverted)
+            })
+        };
+
+        (0..=self.height - pattern.height).any(|y| {
+            (0..=self.width - pattern.width)
+                .any(|x| matches_at(x, y, false) || matches_at(x, y, true))
+        })
+    }
+}
+
+/// Whether every line of `text` is shown on the display, in any of the fonts of the UI
+pub fn contains_text(display: &SimulatorDisplay<BinaryColor>, text: &str) -> bool {
+    let screen = Bitmap::from_display(display);
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .all(|line| {
+            FONTS
+                .iter()
+                .any(|font| screen.find(&Bitmap::render(line, font)))
+        })
+}
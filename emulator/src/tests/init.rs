@@ -26,6 +26,11 @@ async fn test_generate_mnemonic_12words(mut tester: Tester) -> Result<(), crate:
         .nfc_assertion(model::Reply::Info(model::DeviceInfo {
             initialized: model::InitializationStatus::Uninitialized,
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -62,6 +67,7 @@ async fn test_generate_mnemonic_12words(mut tester: Tester) -> Result<(), crate:
         .nfc_assertion(model::Reply::Descriptor {
             external: "wpkh([2bd3bdd7/84'/1'/0']tpubDCPMyXQR36y1uRVgsLGeNgN3awiqucyHGUa7pjQygcRbrbbWCMeRKnShL2hRfvE4zcQ9m9fjMMZHjSoQVatYyuwKqp6AyszbRt6s4iSXChJ/0/*)#klvmrneg".into(),
             internal: Some("wpkh([2bd3bdd7/84'/1'/0']tpubDCPMyXQR36y1uRVgsLGeNgN3awiqucyHGUa7pjQygcRbrbbWCMeRKnShL2hRfvE4zcQ9m9fjMMZHjSoQVatYyuwKqp6AyszbRt6s4iSXChJ/1/*)#8tf67xfs".into()),
+            multipath: Some("wpkh([2bd3bdd7/84'/1'/0']tpubDCPMyXQR36y1uRVgsLGeNgN3awiqucyHGUa7pjQygcRbrbbWCMeRKnShL2hRfvE4zcQ9m9fjMMZHjSoQVatYyuwKqp6AyszbRt6s4iSXChJ/<0;1>/*)#4tjvu7z0".into()),
         })
         .await?;
 
@@ -78,8 +84,19 @@ async fn test_load_config(mut tester: Tester) -> Result<(), crate::Error> {
                 unlocked: true,
                 network: model::bitcoin::Network::Signet,
                 fingerprint: Some([115, 197, 218, 10]),
+                descriptor: Some(model::DescriptorSummary {
+                    script_type: model::ScriptType::NativeSegwit,
+                    threshold: 1,
+                    num_keys: 1,
+                    is_sorted: false,
+                }),
             },
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -96,8 +113,14 @@ async fn test_locked(mut tester: Tester) -> Result<(), crate::Error> {
                 unlocked: false,
                 network: model::bitcoin::Network::Signet,
                 fingerprint: None,
+                descriptor: None,
             },
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -118,8 +141,19 @@ async fn test_locked(mut tester: Tester) -> Result<(), crate::Error> {
                 unlocked: true,
                 network: model::bitcoin::Network::Signet,
                 fingerprint: Some([115, 197, 218, 10]),
+                descriptor: Some(model::DescriptorSummary {
+                    script_type: model::ScriptType::NativeSegwit,
+                    threshold: 1,
+                    num_keys: 1,
+                    is_sorted: false,
+                }),
             },
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -133,6 +167,11 @@ async fn test_restore_mnemonic(mut tester: Tester) -> Result<(), crate::Error> {
         .nfc_assertion(model::Reply::Info(model::DeviceInfo {
             initialized: model::InitializationStatus::Uninitialized,
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -143,7 +182,11 @@ async fn test_restore_mnemonic(mut tester: Tester) -> Result<(), crate::Error> {
             None,
         ))
         .await?;
-    tester.display_assertion("iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAABJ0lEQVR4nO2Y0Q6DMAhFy/9/NNtoAalacV23ZLk+aKoFjhTLjVR+fAAAAAB4D4BHdnzLKQB8LlMqstg8Y7DYHoyJ5Ua8nwDIc6tXtdiMW2y57uaNAZjyGfAA/fjs+rEluAr0DwBjDF/jszXfj7M1oGVbLgjEa1/125rovoJ8Bm58jOgFAAAA9MASAE7BrNMDrx0wuQuu0QPJbXipHiiUW/tF7TgpiJYBaHzoAegBAAAAAAD4PgADYDdu7UyeqOyz1if/Uswq6CLrphw7ZLOop2IORwB6Uh4na3Rm5b052tYp5kL+AJnTMUDZEAeAil5frJwBKIVJB7fQB1ymM8B0CcAUAFpKUhmYAzDbOQAT89wXYYzo6vCwCE23dU6xD6AXAAAAAABAOB6LknRGg9zGfwAAAABJRU5ErkJggg==", None).await?;
+    tester
+        .text_assertion("Seed strength\n12 words\n128 bits", Some(100))
+        .await?;
+    tester.tsc(true).await?;
+    tester.display_assertion("iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAABJ0lEQVR4nO2Y0Q6DMAhFy/9/NNtoAalacV23ZLk+aKoFjhTLjVR+fAAAAAB4D4BHdnzLKQB8LlMqstg8Y7DYHoyJ5Ua8nwDIc6tXtdiMW2y57uaNAZjyGfAA/fjs+rEluAr0DwBjDF/jszXfj7M1oGVbLgjEa1/125rovoJ8Bm58jOgFAAAA9MASAE7BrNMDrx0wuQuu0QPJbXipHiiUW/tF7TgpiJYBaHzoAegBAAAAAAD4PgADYDdu7UyeqOyz1if/Uswq6CLrphw7ZLOop2IORwB6Uh4na3Rm5b052tYp5kL+AJnTMUDZEAeAil5frJwBKIVJB7fQB1ymM8B0CcAUAFpKUhmYAzDbOQAT89wXYYzo6vCwCE23dU6xD6AXAAAAAABAOB6LknRGg9zGfwAAAABJRU5ErkJggg==", Some(100)).await?;
 
     const SCREENS: [&'static str; 6] = [
         "iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAABM0lEQVR4nO2X2xKDIAxE4f8/emshCRcVYxmGh67T6oAkOYbYbGPYfBCAAAT4DQAjO7xySoBoVi4aWXbEQFp+MY5IE+28LwOI3seHeO/HEjtdT+seATzxkY00QD++u7oA4NqBcaApAE8KFgJ8g+t3DJD3+G7Pz2NvBkoVP9RAhHyqqq9ronsL3mzBizeBzYgA1APUA0sA3N1wkR5wgy/SA4h79cARHVv1gE8TrhUkKQP79IDoEeoBNiMCEIAAfwgAApzG0s7SHZV91V9nSC+0SdVFQbphQNshxSKfgjkcAehJeQqZ0JlV6c2tbV5iLpLgM6djgFARNwAZPT9YuANQCpMOxUJvIExnAPERALEBkJS4MjAHYLZzACbm0RdhG7Gow8siNN3WOeXvAHsBAQhAAAI0xwcWgXVG1P2MRwAAAABJRU5ErkJggg==",
@@ -169,6 +212,7 @@ async fn test_restore_mnemonic(mut tester: Tester) -> Result<(), crate::Error> {
         .nfc_assertion(model::Reply::Descriptor {
             external: super::WPKH_EXTERNAL_DESC.to_string(),
             internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
+            multipath: Some(super::WPKH_MULTIPATH_DESC.to_string()),
         })
         .await?;
 
@@ -182,6 +226,11 @@ async fn test_restore_mnemonic_pair_code(mut tester: Tester) -> Result<(), crate
         .nfc_assertion(model::Reply::Info(model::DeviceInfo {
             initialized: model::InitializationStatus::Uninitialized,
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -192,7 +241,11 @@ async fn test_restore_mnemonic_pair_code(mut tester: Tester) -> Result<(), crate
             Some("pair code".into()),
         ))
         .await?;
-    tester.display_assertion("iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAABJ0lEQVR4nO2Y0Q6DMAhFy/9/NNtoAalacV23ZLk+aKoFjhTLjVR+fAAAAAB4D4BHdnzLKQB8LlMqstg8Y7DYHoyJ5Ua8nwDIc6tXtdiMW2y57uaNAZjyGfAA/fjs+rEluAr0DwBjDF/jszXfj7M1oGVbLgjEa1/125rovoJ8Bm58jOgFAAAA9MASAE7BrNMDrx0wuQuu0QPJbXipHiiUW/tF7TgpiJYBaHzoAegBAAAAAAD4PgADYDdu7UyeqOyz1if/Uswq6CLrphw7ZLOop2IORwB6Uh4na3Rm5b052tYp5kL+AJnTMUDZEAeAil5frJwBKIVJB7fQB1ymM8B0CcAUAFpKUhmYAzDbOQAT89wXYYzo6vCwCE23dU6xD6AXAAAAAABAOB6LknRGg9zGfwAAAABJRU5ErkJggg==", None).await?;
+    tester
+        .text_assertion("Seed strength\n12 words\n128 bits", Some(100))
+        .await?;
+    tester.tsc(true).await?;
+    tester.display_assertion("iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAABJ0lEQVR4nO2Y0Q6DMAhFy/9/NNtoAalacV23ZLk+aKoFjhTLjVR+fAAAAAB4D4BHdnzLKQB8LlMqstg8Y7DYHoyJ5Ua8nwDIc6tXtdiMW2y57uaNAZjyGfAA/fjs+rEluAr0DwBjDF/jszXfj7M1oGVbLgjEa1/125rovoJ8Bm58jOgFAAAA9MASAE7BrNMDrx0wuQuu0QPJbXipHiiUW/tF7TgpiJYBaHzoAegBAAAAAAD4PgADYDdu7UyeqOyz1if/Uswq6CLrphw7ZLOop2IORwB6Uh4na3Rm5b052tYp5kL+AJnTMUDZEAeAil5frJwBKIVJB7fQB1ymM8B0CcAUAFpKUhmYAzDbOQAT89wXYYzo6vCwCE23dU6xD6AXAAAAAABAOB6LknRGg9zGfwAAAABJRU5ErkJggg==", Some(100)).await?;
 
     const SCREENS: [&'static str; 7] = [
         "iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAABM0lEQVR4nO2X2xKDIAxE4f8/emshCRcVYxmGh67T6oAkOYbYbGPYfBCAAAT4DQAjO7xySoBoVi4aWXbEQFp+MY5IE+28LwOI3seHeO/HEjtdT+seATzxkY00QD++u7oA4NqBcaApAE8KFgJ8g+t3DJD3+G7Pz2NvBkoVP9RAhHyqqq9ronsL3mzBizeBzYgA1APUA0sA3N1wkR5wgy/SA4h79cARHVv1gE8TrhUkKQP79IDoEeoBNiMCEIAAfwgAApzG0s7SHZV91V9nSC+0SdVFQbphQNshxSKfgjkcAehJeQqZ0JlV6c2tbV5iLpLgM6djgFARNwAZPT9YuANQCpMOxUJvIExnAPERALEBkJS4MjAHYLZzACbm0RdhG7Gow8siNN3WOeXvAHsBAQhAAAI0xwcWgXVG1P2MRwAAAABJRU5ErkJggg==",
@@ -219,6 +272,7 @@ async fn test_restore_mnemonic_pair_code(mut tester: Tester) -> Result<(), crate
         .nfc_assertion(model::Reply::Descriptor {
             external: super::WPKH_EXTERNAL_DESC.to_string(),
             internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
+            multipath: Some(super::WPKH_MULTIPATH_DESC.to_string()),
         })
         .await?;
 
@@ -236,6 +290,11 @@ async fn test_unverified(mut tester: Tester) -> Result<(), crate::Error> {
                 network: model::bitcoin::Network::Signet,
             },
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -266,8 +325,19 @@ async fn test_unverified(mut tester: Tester) -> Result<(), crate::Error> {
                 unlocked: true,
                 network: model::bitcoin::Network::Signet,
                 fingerprint: Some([115, 197, 218, 10]),
+                descriptor: Some(model::DescriptorSummary {
+                    script_type: model::ScriptType::NativeSegwit,
+                    threshold: 1,
+                    num_keys: 1,
+                    is_sorted: false,
+                }),
             },
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -277,6 +347,7 @@ async fn test_unverified(mut tester: Tester) -> Result<(), crate::Error> {
         .nfc_assertion(model::Reply::Descriptor {
             external: super::WPKH_EXTERNAL_DESC.to_string(),
             internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
+            multipath: Some(super::WPKH_MULTIPATH_DESC.to_string()),
         })
         .await?;
 
@@ -292,6 +363,11 @@ async fn test_reset_during_generate_mnemonic(mut tester: Tester) -> Result<(), c
         .nfc_assertion(model::Reply::Info(model::DeviceInfo {
             initialized: model::InitializationStatus::Uninitialized,
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -318,6 +394,11 @@ async fn test_reset_during_generate_mnemonic(mut tester: Tester) -> Result<(), c
                 network: model::bitcoin::Network::Signet,
             },
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
@@ -333,6 +414,11 @@ async fn test_send_raw_getinfo_msg(mut tester: Tester) -> Result<(), crate::Erro
         .nfc_assertion(model::Reply::Info(model::DeviceInfo {
             initialized: model::InitializationStatus::Uninitialized,
             firmware_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            protocol_version: Some(model::PROTOCOL_VERSION),
+            firmware_hash: Some(super::GIT_HASH.to_string()),
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(model::CAPABILITIES),
         }))
         .await?;
 
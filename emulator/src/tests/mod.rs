@@ -20,11 +20,13 @@ use std::sync::{Arc, Once, OnceLock};
 
 use tokio::sync::mpsc;
 
-use embedded_graphics_simulator::OutputSettingsBuilder;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics_simulator::{OutputSettingsBuilder, SimulatorDisplay};
 
 use model::emulator::EmulatorMessage;
 
 use crate::link::{manage_hw, try_pull_msg};
+use crate::screenshot;
 
 use crate::utils::model::*;
 use crate::utils::EmulatorInstance;
@@ -33,15 +35,58 @@ mod bitcoin;
 mod init;
 mod set_descriptor;
 
+/// The emulator is built from the same tree as the firmware
+pub const GIT_HASH: &'static str = fetch_git_hash::fetch_git_hash!();
+
 pub const PORTAL_READY: &'static str = "iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAAAx0lEQVR4nO3V0Q6DMAhAUfn/j2bLqoUyWNqH2cTcvTgt0gOtKsfmHwAAAAAAAAAAAAAAAAAA+AZoBdN4Uef9WkZKHqvVwGTaZwC0jb//tl5fSbTfN6R1cUc8ymcwLawG2LErLL4lCoArbsDKWcsKoFduEF+vSWIHkg76QlY6EDvhBlyiApBPfCNAiqWU7MFdBIyJfi6BX/tzf7hkEwD3FLjdYbu6nxf3DbPH9ux4FU/vgT8Ksvn4GgIAAAAAAAAAAAAAAGA74AWxK4JB071edwAAAABJRU5ErkJggg==";
 pub const LOADING: &'static str = "iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAAAj0lEQVR4nO3VsQ6AIAxFUfr/H/1UsAJhYbDAcBlsKpCeaEVLmwcAAAAAAAAAAAAAAAAAAJgDyNfJ9ygHpSa/o8pNq+t+BgwF5FXr1HOpSRBAbfEOUPIzABE9MAHIkwsA7+axB9yw6wnk/jSPWwEBTSibAQR9Bd877w6cZQcR/wIAAAAAAAAAAAAAAAAACBwX0C1tQf0U+LsAAAAASUVORK5CYII=";
 pub const LOCKED: &'static str = "iVBORw0KGgoAAAANSUhEUgAAAIAAAABACAAAAAD3vSCjAAAAfklEQVR4nO3VsQ7AIAhFUfj/j34dgFg3TVMZvC5oongSFd2aGwAAAAAAAAAAAAAAAAAAWAeo5irXZVT0XZlQe3n3Aa8NPWKNfUzUcuIvgBGnPY8CbLJ0ASJLXY1GwJVHcP4SyhufoVXl6SlE/AUAAAAAAAAAAAAAAAAA8FN7APK2WUEuePxjAAAAAElFTkSuQmCC";
 
 pub const WPKH_EXTERNAL_DESC: &'static str = "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/0/*)#2ag6nxcd";
 pub const WPKH_INTERNAL_DESC: &'static str = "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/1/*)#mfdmwng4";
+pub const WPKH_MULTIPATH_DESC: &'static str = "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/<0;1>/*)#gwycrcrh";
 
 static INIT_LOG: Once = Once::new();
 
+/// Wait until `check` passes on the display
+///
+/// Fails once the display hasn't changed for `timeout_ticks` ticks (16 by default), returning
+/// its content.
+async fn wait_display(
+    emulator: &mut EmulatorInstance,
+    timeout_ticks: Option<usize>,
+    check: impl Fn(&SimulatorDisplay<BinaryColor>) -> bool,
+) -> Result<Option<AssertionResult>, crate::Error> {
+    let output_settings = OutputSettingsBuilder::new().scale(1).build();
+    let start = std::time::Instant::now();
+    let mut tick_counter = 0;
+    let timeout = timeout_ticks.unwrap_or(16);
+
+    loop {
+        if manage_hw(emulator, |_, _, _| {}, &mut (), false, false).await? {
+            // Reset counter when the display is updated
+            tick_counter = 0;
+        }
+
+        if check(&emulator.display) {
+            return Ok(None);
+        }
+
+        while let Some(_) = try_pull_msg::<()>(&mut emulator.msgs.tick)? {
+            tick_counter += 1;
+        }
+
+        if tick_counter > timeout || start.elapsed().as_secs() > 5 {
+            return Ok(Some(AssertionResult::WrongDisplay(
+                emulator
+                    .display
+                    .to_grayscale_output_image(&output_settings)
+                    .to_base64_png()?,
+            )));
+        }
+    }
+}
+
 async fn run_script(
     mut script: mpsc::Receiver<TestOp>,
     result_chan: mpsc::Sender<Result<(), AssertionResult>>,
@@ -112,13 +157,15 @@ async fn run_script(
                                 }
                             };
                             let _ = cloned_sdk
-                                .generate_mnemonic(num_words, network, pair_code)
+                                .generate_mnemonic(num_words, network, pair_code, false, None)
                                 .await;
                         })
                     }
                     NfcAction::RestoreMnemonic(words, network, pair_code) => {
                         tokio::spawn(async move {
-                            let _ = cloned_sdk.restore_mnemonic(words, network, pair_code).await;
+                            let _ = cloned_sdk
+                                .restore_mnemonic(words, network, pair_code, false)
+                                .await;
                         })
                     }
                     NfcAction::SignPsbt(psbt) => tokio::spawn(async move {
@@ -161,38 +208,45 @@ async fn run_script(
                 content,
                 timeout_ticks,
             }) => {
-                let start = std::time::Instant::now();
-                let mut tick_counter = 0;
-                let timeout = timeout_ticks.unwrap_or(16);
-
                 let expected_fb = base64::decode(content)?;
                 let expected_fb = image::load_from_memory(&expected_fb)?.to_rgb8();
 
-                loop {
-                    if manage_hw(emulator, |_, _, _| {}, &mut (), false, false).await? {
-                        // Reset counter when the display is updated
-                        tick_counter = 0;
-                    }
-
-                    let actual_fb = emulator.display.to_rgb_output_image(&output_settings);
-                    if actual_fb.as_image_buffer().as_raw() == &expected_fb.as_raw().deref() {
-                        break None;
-                    }
-
-                    while let Some(_) = try_pull_msg::<()>(&mut emulator.msgs.tick)? {
-                        tick_counter += 1;
-                    }
-
-                    if tick_counter > timeout || start.elapsed().as_secs() > 5 {
-                        break Some(AssertionResult::WrongDisplay(
-                            emulator
-                                .display
-                                .to_grayscale_output_image(&output_settings)
-                                .to_base64_png()?,
-                        ));
+                wait_display(emulator, *timeout_ticks, |display| {
+                    let actual_fb = display.to_rgb_output_image(&output_settings);
+                    actual_fb.as_image_buffer().as_raw() == &expected_fb.as_raw().deref()
+                })
+                .await?
+            }
+            TestOp::Assertion(TestAssertion::Screenshot {
+                name,
+                timeout_ticks,
+            }) => {
+                if screenshot::update_golden() {
+                    // Wait for the display to settle and save whatever is shown
+                    wait_display(emulator, *timeout_ticks, |_| false).await?;
+                    screenshot::save_golden(&emulator.display, name)?;
+                    None
+                } else {
+                    match screenshot::load_golden(name)? {
+                        Some(golden) => {
+                            wait_display(emulator, *timeout_ticks, |display| {
+                                screenshot::same_content(display, &golden)
+                            })
+                            .await?
+                        }
+                        None => Some(AssertionResult::MissingGolden(name.clone())),
                     }
                 }
             }
+            TestOp::Assertion(TestAssertion::Text {
+                text,
+                timeout_ticks,
+            }) => {
+                wait_display(emulator, *timeout_ticks, |display| {
+                    screenshot::contains_text(display, text)
+                })
+                .await?
+            }
             TestOp::Assertion(TestAssertion::NfcResponse(expected, send_ping)) => {
                 'outer: loop {
                     use ::model::Reply;
@@ -209,7 +263,10 @@ async fn run_script(
                             Ok(Ok(portal::DebugMessage::Out(_)))
                             | Ok(Ok(portal::DebugMessage::RawOut(_))) => continue,
                             Ok(Ok(portal::DebugMessage::In(r)))
-                                if matches!(r, Reply::Pong | Reply::DelayedReply) =>
+                                if matches!(
+                                    r,
+                                    Reply::Pong | Reply::DelayedReply | Reply::Progress { .. }
+                                ) =>
                             {
                                 if *send_ping {
                                     let ping =
@@ -347,6 +404,48 @@ impl Tester {
         Ok(())
     }
 
+    /// Compare the display with the golden image `golden/<name>.png`
+    ///
+    /// The image is written instead when the tests run with `UPDATE_GOLDEN` set.
+    pub async fn screenshot_assertion(
+        &mut self,
+        name: &str,
+        timeout_ticks: Option<usize>,
+    ) -> Result<(), crate::Error> {
+        self.op_sender
+            .send(
+                TestAssertion::Screenshot {
+                    name: name.to_string(),
+                    timeout_ticks,
+                }
+                .into(),
+            )
+            .await?;
+        self.expect_reply().await?;
+
+        Ok(())
+    }
+
+    /// Check that every line of `text` is shown on the display
+    pub async fn text_assertion(
+        &mut self,
+        text: &str,
+        timeout_ticks: Option<usize>,
+    ) -> Result<(), crate::Error> {
+        self.op_sender
+            .send(
+                TestAssertion::Text {
+                    text: text.to_string(),
+                    timeout_ticks,
+                }
+                .into(),
+            )
+            .await?;
+        self.expect_reply().await?;
+
+        Ok(())
+    }
+
     pub async fn tsc(&mut self, value: bool) -> Result<(), crate::Error> {
         self.op_sender.send(TestAction::Input(value).into()).await?;
         self.expect_reply().await?;
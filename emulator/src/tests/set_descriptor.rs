@@ -40,9 +40,20 @@ async fn test_get_xpub(mut tester: Tester) -> Result<(), crate::Error> {
 
     tester.display_assertion(super::PORTAL_READY, None).await?;
 
+    let derived =
+        bip32::ExtendedPubKey::from_str(DERIVED_BIP48_XPUB.split(']').nth(1).unwrap()).unwrap();
     tester
         .nfc_assertion(model::Reply::Xpub {
             xpub: DERIVED_BIP48_XPUB.into(),
+            key: Some(model::XpubKey {
+                key: derived.into(),
+                parent_fingerprint: derived.parent_fingerprint.into(),
+                master_fingerprint: 0x73C5DA0A.into(),
+                origin_path: bip32::DerivationPath::from_str("m/48'/1'/0'/2'")
+                    .unwrap()
+                    .into(),
+                network: model::bitcoin::Network::Signet,
+            }),
             bsms: model::BsmsRound1 {
                 version: "1.0".into(),
                 token: "00".into(),
@@ -110,6 +121,7 @@ async fn test_set_descriptor_sorted_multisig(mut tester: Tester) -> Result<(), c
         .nfc_assertion(model::Reply::Descriptor {
             external: "wsh(sortedmulti(1,[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/0/*,[3977ad96/48'/1'/0'/2']tpubDE2WqbYnigRFTi6h4Km571hyX5umkEUvgLUa8kuB7tWXeBD6ffvbXqM2adiWoX9cpwQC9EQakVhy82yeCvwy1RHJVzFaC1ffhNVmEphWuEk/0/*))#4m4ang0j".into(),
             internal: Some("wsh(sortedmulti(1,[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/1/*,[3977ad96/48'/1'/0'/2']tpubDE2WqbYnigRFTi6h4Km571hyX5umkEUvgLUa8kuB7tWXeBD6ffvbXqM2adiWoX9cpwQC9EQakVhy82yeCvwy1RHJVzFaC1ffhNVmEphWuEk/1/*))#vgxeam68".into()),
+            multipath: Some("wsh(sortedmulti(1,[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/<0;1>/*,[3977ad96/48'/1'/0'/2']tpubDE2WqbYnigRFTi6h4Km571hyX5umkEUvgLUa8kuB7tWXeBD6ffvbXqM2adiWoX9cpwQC9EQakVhy82yeCvwy1RHJVzFaC1ffhNVmEphWuEk/<0;1>/*))#l9yws7ar".into()),
         })
         .await?;
 
@@ -136,7 +148,7 @@ async fn test_set_descriptor_sorted_multisig_missing_key(
     tester.display_assertion(super::PORTAL_READY, None).await?;
 
     tester
-        .nfc_assertion(model::Reply::Error("Local key missing".into()))
+        .nfc_assertion(model::Reply::error(model::ErrorCode::LocalKeyMissing))
         .await?;
 
     tester.nfc(NfcAction::RequestDescriptors).await?;
@@ -145,6 +157,7 @@ async fn test_set_descriptor_sorted_multisig_missing_key(
         .nfc_assertion(model::Reply::Descriptor {
             external: super::WPKH_EXTERNAL_DESC.to_string(),
             internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
+            multipath: Some(super::WPKH_MULTIPATH_DESC.to_string()),
         })
         .await?;
 
@@ -162,15 +175,71 @@ fn get_self_extended_key() -> model::ExtendedKey {
 // mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
 #[functional_test_wrapper::functional_test(flash_file = "./test-vector/initialized.bin")]
 async fn test_set_descriptor_non_sorted_multisig(mut tester: Tester) -> Result<(), crate::Error> {
+    tester.display_assertion(super::PORTAL_READY, None).await?;
+
+    // Our key comes second, which gives a different script than `sortedmulti`
+    tester
+        .nfc(NfcAction::SetDescriptor(
+            format!(
+                "wsh(multi(1,{}/*,{}/*))",
+                EXTERNAL_BIP48_XPUB, DERIVED_BIP48_XPUB
+            ),
+            None,
+        ))
+        .await?;
+
+    let sequence = [
+        "Wallet policy\nMulti-sig",
+        "Address type\nNative Segwit",
+        "Threshold\n1 of 2",
+        "Key 1 of 2\nKey 3977ad96\nm/48'/1'/0'/2'",
+        "Key 2 of 2\nThis device\nm/48'/1'/0'/2'",
+    ];
+
+    for text in sequence {
+        tester.text_assertion(text, None).await?;
+        tester.tsc(true).await?;
+    }
+
+    tester.text_assertion("Confirm first address", None).await?;
+    tester.tsc(true).await?;
+
+    tester
+        .text_assertion("Save new\nconfiguration?", None)
+        .await?;
+    tester.tsc(true).await?;
+
+    tester.display_assertion(super::PORTAL_READY, None).await?;
+
+    tester.nfc_assertion(model::Reply::Ok).await?;
+
+    tester.nfc(NfcAction::RequestDescriptors).await?;
+    tester.tsc(true).await?;
+    tester
+        .nfc_assertion(model::Reply::Descriptor {
+            external: "wsh(multi(1,[3977ad96/48'/1'/0'/2']tpubDE2WqbYnigRFTi6h4Km571hyX5umkEUvgLUa8kuB7tWXeBD6ffvbXqM2adiWoX9cpwQC9EQakVhy82yeCvwy1RHJVzFaC1ffhNVmEphWuEk/0/*,[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/0/*))#gpp3plef".into(),
+            internal: Some("wsh(multi(1,[3977ad96/48'/1'/0'/2']tpubDE2WqbYnigRFTi6h4Km571hyX5umkEUvgLUa8kuB7tWXeBD6ffvbXqM2adiWoX9cpwQC9EQakVhy82yeCvwy1RHJVzFaC1ffhNVmEphWuEk/1/*,[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/1/*))#3jj40vvu".into()),
+            multipath: Some("wsh(multi(1,[3977ad96/48'/1'/0'/2']tpubDE2WqbYnigRFTi6h4Km571hyX5umkEUvgLUa8kuB7tWXeBD6ffvbXqM2adiWoX9cpwQC9EQakVhy82yeCvwy1RHJVzFaC1ffhNVmEphWuEk/<0;1>/*,[73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/<0;1>/*))#5fd5zqnq".into()),
+        })
+        .await?;
+
+    Ok(())
+}
+
+// mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+#[functional_test_wrapper::functional_test(flash_file = "./test-vector/initialized.bin")]
+async fn test_set_descriptor_multisig_invalid_threshold(
+    mut tester: Tester,
+) -> Result<(), crate::Error> {
     use model::*;
 
     tester.display_assertion(super::PORTAL_READY, None).await?;
 
     let msg = Request::SetDescriptor {
         variant: SetDescriptorVariant::MultiSig {
-            threshold: 1,
+            threshold: 2,
             keys: vec![get_self_extended_key()],
-            is_sorted: false,
+            is_sorted: true,
         },
         script_type: ScriptType::NativeSegwit,
         bsms: None,
@@ -181,7 +250,7 @@ async fn test_set_descriptor_non_sorted_multisig(mut tester: Tester) -> Result<(
 
     tester
         .nfc_assertion_raw(
-            model::Reply::Error("Unsorted multisig descriptors are not supported yet".into()),
+            model::Reply::error(model::ErrorCode::InvalidThreshold),
             true,
         )
         .await?;
@@ -194,6 +263,7 @@ async fn test_set_descriptor_non_sorted_multisig(mut tester: Tester) -> Result<(
         .nfc_assertion(model::Reply::Descriptor {
             external: super::WPKH_EXTERNAL_DESC.to_string(),
             internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
+            multipath: Some(super::WPKH_MULTIPATH_DESC.to_string()),
         })
         .await?;
 
@@ -202,18 +272,18 @@ async fn test_set_descriptor_non_sorted_multisig(mut tester: Tester) -> Result<(
 
 // mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
 #[functional_test_wrapper::functional_test(flash_file = "./test-vector/initialized.bin")]
-async fn test_set_descriptor_multisig_invalid_threshold(
-    mut tester: Tester,
-) -> Result<(), crate::Error> {
+async fn test_set_descriptor_tap_tree_not_taproot(mut tester: Tester) -> Result<(), crate::Error> {
     use model::*;
 
     tester.display_assertion(super::PORTAL_READY, None).await?;
 
     let msg = Request::SetDescriptor {
-        variant: SetDescriptorVariant::MultiSig {
-            threshold: 2,
-            keys: vec![get_self_extended_key()],
-            is_sorted: true,
+        variant: SetDescriptorVariant::TapTree {
+            internal_key: None,
+            leaves: vec![TapLeaf {
+                depth: 0,
+                script: TapLeafScript::Key(get_self_extended_key()),
+            }],
         },
         script_type: ScriptType::NativeSegwit,
         bsms: None,
@@ -224,22 +294,67 @@ async fn test_set_descriptor_multisig_invalid_threshold(
 
     tester
         .nfc_assertion_raw(
-            model::Reply::Error("Invalid threshold for multisig".into()),
+            model::Reply::error_with_detail(
+                model::ErrorCode::UnsupportedDescriptor,
+                "Script trees are only supported with taproot",
+            ),
             true,
         )
         .await?;
 
     tester.display_assertion(super::PORTAL_READY, None).await?;
 
-    tester.nfc(NfcAction::RequestDescriptors).await?;
-    tester.tsc(true).await?;
+    Ok(())
+}
+
+// mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+#[functional_test_wrapper::functional_test(flash_file = "./test-vector/initialized.bin")]
+async fn test_register_wallet_policy_invalid_name(mut tester: Tester) -> Result<(), crate::Error> {
+    use model::*;
+
+    tester.display_assertion(super::PORTAL_READY, None).await?;
+
+    let msg = Request::RegisterWalletPolicy {
+        name: "Savings\n".to_string(),
+        variant: SetDescriptorVariant::SingleSig(get_self_extended_key()),
+        script_type: ScriptType::NativeSegwit,
+    };
+    let msg = model::minicbor::to_vec(&msg).unwrap();
+
+    tester.nfc(NfcAction::Raw(msg)).await?;
+
     tester
-        .nfc_assertion(model::Reply::Descriptor {
-            external: super::WPKH_EXTERNAL_DESC.to_string(),
-            internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
-        })
+        .nfc_assertion_raw(
+            model::Reply::error(model::ErrorCode::InvalidWalletName),
+            true,
+        )
+        .await?;
+
+    tester.display_assertion(super::PORTAL_READY, None).await?;
+
+    Ok(())
+}
+
+// mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+#[functional_test_wrapper::functional_test(flash_file = "./test-vector/initialized.bin")]
+async fn test_use_wallet_policy_not_registered(mut tester: Tester) -> Result<(), crate::Error> {
+    use model::*;
+
+    tester.display_assertion(super::PORTAL_READY, None).await?;
+
+    let msg = Request::UseWalletPolicy {
+        hmac: Box::new([0x42; 32].into()),
+    };
+    let msg = model::minicbor::to_vec(&msg).unwrap();
+
+    tester.nfc(NfcAction::Raw(msg)).await?;
+
+    tester
+        .nfc_assertion(model::Reply::error(model::ErrorCode::WalletPolicyMismatch))
         .await?;
 
+    tester.display_assertion(super::PORTAL_READY, None).await?;
+
     Ok(())
 }
 
@@ -278,6 +393,7 @@ async fn test_set_descriptor_pkh(mut tester: Tester) -> Result<(), crate::Error>
         .nfc_assertion(model::Reply::Descriptor {
             external: "pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/0/*)#j4l5ela5".into(),
             internal: Some("pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/1/*)#rp64y2dv".into()),
+            multipath: Some("pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/<0;1>/*)#e4dxfyhw".into()),
         })
         .await?;
 
@@ -299,7 +415,7 @@ async fn test_set_descriptor_pkh_external_key(mut tester: Tester) -> Result<(),
     tester.display_assertion(super::PORTAL_READY, None).await?;
 
     tester
-        .nfc_assertion(model::Reply::Error("Local key missing".into()))
+        .nfc_assertion(model::Reply::error(model::ErrorCode::LocalKeyMissing))
         .await?;
 
     tester.nfc(NfcAction::RequestDescriptors).await?;
@@ -308,6 +424,7 @@ async fn test_set_descriptor_pkh_external_key(mut tester: Tester) -> Result<(),
         .nfc_assertion(model::Reply::Descriptor {
             external: super::WPKH_EXTERNAL_DESC.to_string(),
             internal: Some(super::WPKH_INTERNAL_DESC.to_string()),
+            multipath: Some(super::WPKH_MULTIPATH_DESC.to_string()),
         })
         .await?;
 
@@ -354,6 +471,7 @@ async fn test_set_descriptor_pkh_locked(mut tester: Tester) -> Result<(), crate:
         .nfc_assertion(model::Reply::Descriptor {
             external: "pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/0/*)#j4l5ela5".into(),
             internal: Some("pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/1/*)#rp64y2dv".into()),
+            multipath: Some("pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/<0;1>/*)#e4dxfyhw".into()),
         })
         .await?;
 
@@ -373,6 +491,7 @@ async fn test_set_descriptor_pkh_locked(mut tester: Tester) -> Result<(), crate:
         .nfc_assertion(model::Reply::Descriptor {
             external: "pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/0/*)#j4l5ela5".into(),
             internal: Some("pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/1/*)#rp64y2dv".into()),
+            multipath: Some("pkh([73c5da0a/48'/1'/0'/2']tpubDFH9dgzveyD8zTbPUFuLrGmCydNvxehyNdUXKJAQN8x4aZ4j6UZqGfnqFrD4NqyaTVGKbvEW54tsvPTK2UoSbCC1PJY8iCNiwTL3RWZEheQ/<0;1>/*)#e4dxfyhw".into()),
         })
         .await?;
 
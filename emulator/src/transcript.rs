@@ -0,0 +1,275 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Record and replay emulator sessions
+//!
+//! A transcript is a JSON file with one `TranscriptEntry` per line. Every entry is stamped with
+//! the number of ticks elapsed since the beginning of the session and the content of the display
+//! at that moment, so that when it's replayed against a different firmware build we can check
+//! that the device shows the same screens and returns the same replies.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use tokio::sync::mpsc;
+
+use embedded_graphics_simulator::OutputSettingsBuilder;
+
+use model::emulator::EmulatorMessage;
+
+use crate::link::{manage_hw, try_pull_msg};
+use crate::utils::model::NfcAction;
+use crate::utils::EmulatorInstance;
+
+/// Give up waiting for a reply from the device after this long
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum TranscriptEvent {
+    Input(bool),
+    Reset,
+    NfcRequest {
+        id: usize,
+        action: NfcAction,
+    },
+    NfcReply {
+        id: usize,
+        result: Result<Value, String>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TranscriptEntry {
+    /// Ticks elapsed since the beginning of the session
+    pub ticks: usize,
+    /// Wall-clock time elapsed since the beginning of the session, only for reference
+    pub elapsed_ms: u64,
+    /// Base64-encoded PNG of the display when the event happened
+    pub display: String,
+    pub event: TranscriptEvent,
+}
+
+pub struct Recorder {
+    file: File,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self, crate::Error> {
+        Ok(Recorder {
+            file: File::create(path)?,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        emulator: &EmulatorInstance,
+        ticks: usize,
+        event: TranscriptEvent,
+    ) -> Result<(), crate::Error> {
+        let entry = TranscriptEntry {
+            ticks,
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            display: display_png(emulator)?,
+            event,
+        };
+
+        // Flush every line so that the transcript survives a crash of the emulator
+        writeln!(self.file, "{}", serde_json::to_string(&entry)?)?;
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
+
+pub fn load_transcript(path: &Path) -> Result<Vec<TranscriptEntry>, crate::Error> {
+    let mut entries = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        entries.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(entries)
+}
+
+pub(crate) fn display_png(emulator: &EmulatorInstance) -> Result<String, crate::Error> {
+    let output_settings = OutputSettingsBuilder::new().scale(1).build();
+    Ok(emulator
+        .display
+        .to_grayscale_output_image(&output_settings)
+        .to_base64_png()?)
+}
+
+/// Difference between the transcript and the replayed session
+#[derive(Debug, Clone, Serialize)]
+pub enum Mismatch {
+    Display {
+        entry: usize,
+        expected: String,
+        actual: String,
+    },
+    Reply {
+        entry: usize,
+        expected: Result<Value, String>,
+        actual: Result<Value, String>,
+    },
+    NoReply {
+        entry: usize,
+    },
+}
+
+struct Replayer<'e> {
+    emulator: &'e mut EmulatorInstance,
+    ticks: usize,
+}
+
+impl<'e> Replayer<'e> {
+    async fn step(&mut self) -> Result<(), crate::Error> {
+        manage_hw(self.emulator, |_, _, _| {}, &mut (), false, true).await?;
+        while try_pull_msg::<()>(&mut self.emulator.msgs.tick)?.is_some() {
+            self.ticks += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn wait_until(&mut self, ticks: usize) -> Result<(), crate::Error> {
+        while self.ticks < ticks {
+            self.step().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the display to match `expected`, allowing it to be late by up to `tolerance` ticks
+    async fn check_display(
+        &mut self,
+        expected: &str,
+        tolerance: usize,
+    ) -> Result<Option<String>, crate::Error> {
+        let deadline = self.ticks + tolerance;
+        loop {
+            let actual = display_png(self.emulator)?;
+            if actual == expected {
+                return Ok(None);
+            } else if self.ticks >= deadline {
+                return Ok(Some(actual));
+            }
+
+            self.step().await?;
+        }
+    }
+}
+
+/// Replay a transcript, returning the list of differences found
+///
+/// Events are replayed at the same tick at which they were recorded and the screen is compared
+/// before each one of them, giving the firmware `display_tolerance` extra ticks to catch up.
+/// NFC replies are compared once the replayed request completes.
+pub async fn replay(
+    emulator: &mut EmulatorInstance,
+    entries: &[TranscriptEntry],
+    display_tolerance: usize,
+) -> Result<Vec<Mismatch>, crate::Error> {
+    let sdk = Arc::clone(&emulator.sdk);
+    sdk.new_tag().await?;
+
+    let (result_s, mut result_r) = mpsc::unbounded_channel();
+    let mut results = HashMap::new();
+    let mut mismatches = vec![];
+
+    let mut replayer = Replayer { emulator, ticks: 0 };
+
+    for (index, entry) in entries.iter().enumerate() {
+        log::debug!("Replaying entry {}: {:?}", index, entry.event);
+
+        replayer.wait_until(entry.ticks).await?;
+
+        if let TranscriptEvent::NfcReply { id, result } = &entry.event {
+            let start = Instant::now();
+            let actual = loop {
+                while let Some((id, result)) = try_pull_msg(&mut result_r)? {
+                    results.insert(id, result);
+                }
+                if let Some(actual) = results.remove(id) {
+                    break Some(actual);
+                }
+                if start.elapsed() > REPLY_TIMEOUT {
+                    break None;
+                }
+
+                replayer.step().await?;
+            };
+
+            match actual {
+                Some(actual) if &actual != result => mismatches.push(Mismatch::Reply {
+                    entry: index,
+                    expected: result.clone(),
+                    actual,
+                }),
+                Some(_) => {}
+                None => mismatches.push(Mismatch::NoReply { entry: index }),
+            }
+
+            continue;
+        }
+
+        if let Some(actual) = replayer
+            .check_display(&entry.display, display_tolerance)
+            .await?
+        {
+            mismatches.push(Mismatch::Display {
+                entry: index,
+                expected: entry.display.clone(),
+                actual,
+            });
+        }
+
+        match &entry.event {
+            TranscriptEvent::Input(value) => {
+                replayer.emulator.card.send(EmulatorMessage::Tsc(*value))?
+            }
+            TranscriptEvent::Reset => replayer.emulator.card.send(EmulatorMessage::Reset)?,
+            TranscriptEvent::NfcRequest { id, action } => {
+                let (id, action) = (*id, action.clone());
+                let sdk = Arc::clone(&sdk);
+                let result_s = result_s.clone();
+                tokio::spawn(async move {
+                    let result = crate::rpc::run_nfc_action(&sdk, action)
+                        .await
+                        .map_err(|e| e.to_string());
+                    let _ = result_s.send((id, result));
+                });
+            }
+            TranscriptEvent::NfcReply { .. } => unreachable!(),
+        }
+    }
+
+    Ok(mismatches)
+}
@@ -40,6 +40,7 @@ use ::model::emulator::{CardMessage, EmulatorMessage};
 pub mod model;
 pub mod report;
 
+use crate::fault::FaultInjector;
 use crate::link::EmulatorStreams;
 
 pub fn get_qemu_instance(
@@ -219,7 +220,8 @@ pub async fn get_emulator_instance(
 
         let (card, card_r) = mpsc::unbounded_channel();
         EmulatorInstance::spawn_card_writer(card_r, Box::pin(nfc));
-        let sdk = EmulatorInstance::attach_sdk(nfc_r, card.clone());
+        let faults = Arc::new(FaultInjector::default());
+        let sdk = EmulatorInstance::attach_sdk(nfc_r, card.clone(), Arc::clone(&faults));
 
         Ok(EmulatorInstance {
             card,
@@ -229,6 +231,7 @@ pub async fn get_emulator_instance(
             flash,
             sdk,
             entropy,
+            faults,
 
             _qemu_handle: None,
         })
@@ -283,6 +286,8 @@ pub struct EmulatorInstance {
     pub flash: Box<dyn ReadWrite + Send>,
     pub sdk: Arc<PortalSdk>,
     pub entropy: [u8; 32],
+    /// Faults injected in the NFC link and the flash, none by default
+    pub faults: Arc<FaultInjector>,
 
     pub(super) _qemu_handle: Option<Child>,
 }
@@ -309,7 +314,8 @@ impl EmulatorInstance {
         tokio::time::timeout(std::time::Duration::from_secs(2), msgs.finish_boot.recv()).await?;
         // Send new entropy
         card.send(EmulatorMessage::Entropy(entropy)).unwrap();
-        let sdk = Self::attach_sdk(nfc, card.clone());
+        let faults = Arc::new(FaultInjector::default());
+        let sdk = Self::attach_sdk(nfc, card.clone(), Arc::clone(&faults));
 
         Ok(EmulatorInstance {
             card,
@@ -319,6 +325,7 @@ impl EmulatorInstance {
             flash,
             sdk,
             entropy,
+            faults,
             _qemu_handle: Some(_qemu_handle),
         })
     }
@@ -326,6 +333,7 @@ impl EmulatorInstance {
     pub fn attach_sdk(
         mut nfc_r: mpsc::UnboundedReceiver<Vec<u8>>,
         nfc_w: mpsc::UnboundedSender<EmulatorMessage>,
+        faults: Arc<FaultInjector>,
     ) -> Arc<PortalSdk> {
         log::trace!("Attaching SDK");
 
@@ -336,7 +344,11 @@ impl EmulatorInstance {
                 let out = cloned_sdk.poll().await.unwrap();
 
                 log::trace!("> {:02X?}", out.data);
-                nfc_w.send(EmulatorMessage::Nfc(out.data)).unwrap();
+                if faults.drop_nfc() {
+                    log::debug!("Injected fault: dropping NFC fragment sent to the device");
+                } else {
+                    nfc_w.send(EmulatorMessage::Nfc(out.data)).unwrap();
+                }
 
                 let incoming =
                     match tokio::time::timeout(std::time::Duration::from_secs(5), nfc_r.recv())
@@ -353,6 +365,12 @@ impl EmulatorInstance {
                         }
                     };
 
+                tokio::time::sleep(faults.nfc_latency()).await;
+                if faults.drop_nfc() {
+                    log::debug!("Injected fault: dropping NFC fragment received from the device");
+                    continue;
+                }
+
                 log::trace!("< {:02X?}", incoming);
                 cloned_sdk
                     .incoming_data(out.msg_index, incoming)
@@ -103,6 +103,16 @@ pub enum TestAssertion {
         content: String,
         timeout_ticks: Option<usize>,
     },
+    /// Compare the display with the golden image called `name`
+    Screenshot {
+        name: String,
+        timeout_ticks: Option<usize>,
+    },
+    /// Look for every line of `text` on the display
+    Text {
+        text: String,
+        timeout_ticks: Option<usize>,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -110,6 +120,7 @@ pub enum AssertionResult {
     WrongDisplay(String),
     WrongReply(String),
     NoReply,
+    MissingGolden(String),
 }
 impl fmt::Display for AssertionResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
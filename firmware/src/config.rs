@@ -17,60 +17,471 @@
 
 use hal::flash::{self, Read, WriteErase};
 
-use model::Config;
+use rand::RngCore;
 
-use crate::hw::Flash;
+use model::attestation::AttestationKey;
+use model::config_log;
+use model::keywrap::{self, WrappingKey};
+use model::{Config, WipeReport};
+
+use crate::hw::{boot, Flash};
 
 const PAGE_SIZE: usize = 2048;
-const CONFIG_PAGE: usize = 255;
+/// Pages of each bank holding the config log
+///
+/// Page 255 is also where older firmwares stored the config as a single blob, which is migrated
+/// the first time it's read.
+pub use model::flash::CONFIG_PAGES;
+const LEGACY_CONFIG_PAGE: usize = 255;
+
+fn read_page(prog: &flash::FlashProgramming, page: usize) -> alloc::vec::Vec<u8> {
+    let mut buf = alloc::vec![0u8; PAGE_SIZE];
+    prog.read(flash::FlashPage(page).to_address(), &mut buf);
+    buf
+}
+
+fn decode_record(data: &[u8]) -> Result<Config, ConfigError> {
+    let data = wrapping_key()?
+        .unwrap(data)
+        .map_err(|_| ConfigError::CorruptedConfig)?;
+    Ok(minicbor::decode(&data)?)
+}
+
+/// Headers of the config pages, newest first
+fn config_log_state(
+    prog: &flash::FlashProgramming,
+) -> alloc::vec::Vec<(usize, config_log::PageHeader)> {
+    let mut pages = CONFIG_PAGES
+        .iter()
+        .filter_map(|page| {
+            config_log::PageHeader::parse(&read_page(prog, *page)).map(|header| (*page, header))
+        })
+        .collect::<alloc::vec::Vec<_>>();
+    if pages.len() == 2 && pages[1].1.is_newer_than(&pages[0].1) {
+        pages.swap(0, 1);
+    }
+
+    pages
+}
+
+/// Newest config that can be decoded in `page`, with its sequence
+fn newest_record(prog: &flash::FlashProgramming, page: usize) -> Option<(u32, Config)> {
+    let buf = read_page(prog, page);
+    let (records, _) = config_log::records(&buf);
+    records
+        .iter()
+        .filter_map(|r| {
+            decode_record(r.data)
+                .ok()
+                .map(|config| (r.sequence, config))
+        })
+        .max_by_key(|(sequence, _)| *sequence)
+}
 
 pub async fn read_config(flash: &mut Flash) -> Result<Config, ConfigError> {
-    let flash = &mut flash.parts;
+    let parts = &mut flash.parts;
+    let prog = parts.keyr.unlock_flash(&mut parts.sr, &mut parts.cr)?;
 
-    let prog = flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr)?;
+    let pages = config_log_state(&prog);
+    if pages.is_empty() {
+        // Written by an older firmware: move it to the log right away
+        let buf = read_page(&prog, LEGACY_CONFIG_PAGE);
+        let len = u16::from_be_bytes(buf[..2].try_into().unwrap()) as usize;
+        if len >= PAGE_SIZE - 2 {
+            return Err(ConfigError::CorruptedConfig);
+        }
+        let data = &buf[2..2 + len];
 
-    let last_page = flash::FlashPage(CONFIG_PAGE).to_address();
+        let config = if keywrap::is_wrapped(data) {
+            decode_record(data)?
+        } else {
+            minicbor::decode(data)?
+        };
+        drop(prog);
 
-    let mut buf = [0u8; PAGE_SIZE];
-    prog.read(last_page, &mut buf);
-    let len = u16::from_be_bytes(buf[..2].try_into().unwrap()) as usize;
-    if len >= PAGE_SIZE - 2 {
-        return Err(ConfigError::CorruptedConfig);
+        write_config(flash, &config).await?;
+        log::info!("Config migrated to the log format");
+
+        return Ok(config);
+    }
+
+    // Both pages hold a copy of the config. The newest sequence wins, and if the two copies
+    // disagree we lost power in the middle of a write or a page went bad: write the config again
+    // so that there are two copies of it
+    let bad_pages = pages
+        .iter()
+        .fold(0, |mask, (_, header)| mask | header.bad_pages);
+    let newest = pages
+        .iter()
+        .map(|(page, _)| newest_record(&prog, *page))
+        .collect::<alloc::vec::Vec<_>>();
+    let in_sync = newest.len() == CONFIG_PAGES.len()
+        && newest[0].as_ref().map(|(sequence, _)| *sequence)
+            == newest[1].as_ref().map(|(sequence, _)| *sequence);
+    let (sequence, config) = newest
+        .into_iter()
+        .flatten()
+        .reduce(|best, other| if other.0 > best.0 { other } else { best })
+        .ok_or(ConfigError::CorruptedConfig)?;
+    drop(prog);
+
+    let healthy_pages = CONFIG_PAGES.len() - bad_pages.count_ones() as usize;
+    if in_sync || healthy_pages != CONFIG_PAGES.len() {
+        return Ok(config);
+    }
+    log::warn!("Config copies out of sync, rewriting sequence {}", sequence);
+    // The config was read fine, so don't fail just because it can't be copied
+    if let Err(e) = write_config(flash, &config).await {
+        log::warn!("Unable to rewrite the config: {:?}", e);
     }
 
-    let config = minicbor::decode(&buf[2..2 + len])?;
     Ok(config)
 }
 
+/// Program `data` at `offset` of `page` and read it back
+fn write_verify(
+    prog: &mut flash::FlashProgramming,
+    page: usize,
+    offset: usize,
+    data: &[u8],
+) -> Result<(), ConfigError> {
+    let address = flash::FlashPage(page).to_address() + offset;
+    prog.write(address, data)?;
+
+    let mut written = alloc::vec![0u8; data.len()];
+    prog.read(address, &mut written);
+    if written != data {
+        log::warn!("Config page {} failed to verify", page);
+        return Err(ConfigError::BadPage);
+    }
+
+    Ok(())
+}
+
+/// Save `config` in both config pages
+///
+/// The copy is first written to the page that doesn't hold the newest record and only then to
+/// the other one, so at any time at least one page holds either the previous config or the new
+/// one in full: a power loss can't leave the device without a config or with a record that
+/// decodes to something else, since every record is checked with its CRC. A page is only erased
+/// to compact it when it's full, and pages that fail to verify are marked as bad and skipped: the
+/// mask is stored in the header the next time a page is erased.
 pub async fn write_config(flash: &mut Flash, config: &Config) -> Result<(), ConfigError> {
+    let serialized = wrapping_key()?.wrap(&minicbor::to_vec(config).expect("always succeed"));
+
+    let fb_mode = flash.fb_mode;
     let flash = &mut flash.parts;
+    let mut prog = flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr)?;
 
+    let pages = config_log_state(&prog);
+    let mut generation = pages
+        .first()
+        .map(|(_, header)| header.generation)
+        .unwrap_or(0);
+    let mut bad_pages = pages
+        .iter()
+        .fold(0, |mask, (_, header)| mask | header.bad_pages);
+    let page_bit = |page: usize| 1 << CONFIG_PAGES.iter().position(|p| *p == page).unwrap();
+
+    // Highest sequence in each page
+    let sequences = CONFIG_PAGES.map(|page| {
+        let buf = read_page(&prog, page);
+        config_log::PageHeader::parse(&buf)
+            .and_then(|_| config_log::records(&buf).0.iter().map(|r| r.sequence).max())
+    });
+    let sequence = sequences
+        .iter()
+        .flatten()
+        .max()
+        .map(|s| s.wrapping_add(1))
+        .unwrap_or(0);
+    let record = config_log::encode_record(sequence, &serialized);
+    if record.len() > PAGE_SIZE - config_log::HEADER_LEN {
+        return Err(ConfigError::CorruptedConfig);
+    }
+
+    // Start from the page with the oldest copy, the newest one must survive until the new record
+    // is safely written somewhere else. Pages without records have no sequence, so their
+    // generation decides
+    let newest_page = pages.first().map(|(page, _)| *page);
+    let mut order = CONFIG_PAGES;
+    if (sequences[0], newest_page == Some(order[0])) > (sequences[1], newest_page == Some(order[1]))
+    {
+        order.swap(0, 1);
+    }
+
+    let mut copies = 0;
+    for page in order {
+        if bad_pages & page_bit(page) != 0 {
+            continue;
+        }
+
+        // Append to the page if there's enough space left, otherwise erase it and start again
+        let buf = read_page(&prog, page);
+        let append_at = config_log::PageHeader::parse(&buf).and_then(|_| {
+            let (_, end) = config_log::records(&buf);
+            (end + record.len() <= PAGE_SIZE).then_some(end)
+        });
+        let result = match append_at {
+            Some(end) => write_verify(&mut prog, page, end, &record),
+            None => {
+                // The erase doesn't follow the bank mapping, unlike the reads and the writes
+                generation = generation.wrapping_add(1);
+                prog.erase_page(boot::physical_page(fb_mode, page))?;
+                let header = config_log::PageHeader {
+                    generation,
+                    bad_pages,
+                };
+                write_verify(&mut prog, page, 0, &header.encode())
+                    .and_then(|_| write_verify(&mut prog, page, config_log::HEADER_LEN, &record))
+            }
+        };
+        match result {
+            Ok(()) => copies += 1,
+            Err(ConfigError::BadPage) => bad_pages |= page_bit(page),
+            Err(e) => return Err(e),
+        }
+    }
+
+    match copies {
+        0 => return Err(ConfigError::BadPage),
+        1 => log::warn!("Config saved in a single copy, bad pages: {:#b}", bad_pages),
+        _ => {}
+    }
+
+    // The copy in the other bank is stale now. It's always mapped at the second half of the
+    // flash, but it's erased by its physical page
+    for page in CONFIG_PAGES {
+        if read_page(&prog, page + 256).iter().any(|b| *b != 0xFF) {
+            prog.erase_page(boot::physical_page(!fb_mode, page))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Programming access to both banks, as seen by the running image
+struct Banks<'a> {
+    prog: flash::FlashProgramming<'a>,
+    fb_mode: bool,
+}
+
+impl model::flash::Banks for Banks<'_> {
+    type Error = ConfigError;
+
+    fn fb_mode(&self) -> bool {
+        self.fb_mode
+    }
+
+    fn read_page(&self, page: usize) -> alloc::vec::Vec<u8> {
+        read_page(&self.prog, page)
+    }
+
+    fn erase_page(&mut self, bank2: bool, page: usize) -> Result<(), ConfigError> {
+        Ok(self.prog.erase_page(boot::physical_page(bank2, page))?)
+    }
+}
+
+/// Erase every page that may hold secrets, which brings the device back to the uninitialized
+/// state
+///
+/// These are the config pages of both banks and, when `update_checkpoints` is set, the first
+/// page of the spare bank with the checkpoints of an interrupted update. Every page is read back
+/// to make sure it's blank. The wrapping key, the attestation key and the minimum version are
+/// not touched.
+pub async fn wipe_config(
+    flash: &mut Flash,
+    update_checkpoints: bool,
+) -> Result<WipeReport, ConfigError> {
+    let fb_mode = flash.fb_mode;
+    let flash = &mut flash.parts;
+    let prog = flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr)?;
+
+    let report = model::flash::wipe(&mut Banks { prog, fb_mode }, update_checkpoints)?;
+    for page in &report.failed_pages {
+        log::warn!(
+            "Page {} is not blank after {} passes",
+            page,
+            model::flash::WIPE_PASSES
+        );
+    }
+
+    Ok(report)
+}
+
+/// Area of the OTP memory reserved to the minimum firmware version
+///
+/// Every time the minimum version is raised a new double-word with the version and its
+/// complement is programmed, so the current value is the last one written. Since the OTP can't
+/// be erased the counter can only go forward.
+const MIN_VERSION_OTP_START: usize = 0x1FFF_7200;
+const MIN_VERSION_OTP_END: usize = 0x1FFF_7400;
+
+fn min_version_entries() -> impl Iterator<Item = (usize, Option<u32>)> {
+    (MIN_VERSION_OTP_START..MIN_VERSION_OTP_END)
+        .step_by(8)
+        .map(|address| {
+            let entry = unsafe { core::ptr::read_volatile(address as *const [u8; 8]) };
+            let version = u32::from_be_bytes(entry[..4].try_into().unwrap());
+            let check = u32::from_be_bytes(entry[4..].try_into().unwrap());
+
+            (address, (version == !check).then_some(version))
+        })
+}
+
+pub async fn read_min_version(_flash: &mut Flash) -> Result<u32, ConfigError> {
+    Ok(min_version_entries()
+        .map_while(|(_, version)| version)
+        .last()
+        .unwrap_or(0))
+}
+
+pub async fn write_min_version(flash: &mut Flash, version: u32) -> Result<(), ConfigError> {
+    if read_min_version(flash).await? >= version {
+        return Ok(());
+    }
+
+    let (address, _) = min_version_entries()
+        .find(|(_, version)| version.is_none())
+        .ok_or(ConfigError::CorruptedConfig)?;
+
+    let flash = &mut flash.parts;
     let mut prog = flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr)?;
 
-    let mut data = alloc::vec![0x00, 0x00];
-    let serialized = minicbor::to_vec(config).expect("always succeed");
+    let mut data = [0u8; 8];
+    data[..4].copy_from_slice(&version.to_be_bytes());
+    data[4..].copy_from_slice(&(!version).to_be_bytes());
+    prog.write(address, &data)?;
+
+    Ok(())
+}
+
+/// Area of the OTP memory reserved to the attestation key
+///
+/// The first 64 bytes hold the serial number, the attestation key follows as a length-prefixed CBOR
+/// blob and is programmed only once at manufacture.
+const ATTESTATION_OTP_START: usize = 0x1FFF_7040;
+const ATTESTATION_OTP_END: usize = 0x1FFF_71E0;
+
+/// Area of the OTP memory holding the random secret of the wrapping key
+const WRAPPING_SECRET_OTP_START: usize = 0x1FFF_71E0;
+const WRAPPING_SECRET_LEN: usize = 32;
+
+/// Unique device ID, 96 bits
+const UID_ADDRESS: usize = 0x1FFF_7590;
+const UID_LEN: usize = 12;
+
+fn wrapping_secret() -> Option<&'static [u8]> {
+    let secret = unsafe {
+        core::slice::from_raw_parts(WRAPPING_SECRET_OTP_START as *const u8, WRAPPING_SECRET_LEN)
+    };
+    secret.iter().any(|b| *b != 0xFF).then_some(secret)
+}
+
+/// Key used to wrap the config, bound to this specific MCU
+///
+/// It's derived from a random secret programmed in the OTP the first time the device boots and
+/// from the unique ID of the MCU. Both can only be read by the firmware once read-out protection
+/// is enabled, which makes a copy of the config page useless on any other device.
+///
+/// A secure element would replace this with its own wrap/unwrap operations, without the key ever
+/// leaving it.
+fn wrapping_key() -> Result<WrappingKey, ConfigError> {
+    let secret = wrapping_secret().ok_or(ConfigError::CorruptedConfig)?;
+    let uid = unsafe { core::slice::from_raw_parts(UID_ADDRESS as *const u8, UID_LEN) };
+
+    Ok(WrappingKey::new(&[secret, uid]))
+}
 
-    if serialized.len() > PAGE_SIZE - 2 {
+/// Generate the secret of the wrapping key, if it wasn't generated already
+pub async fn init_wrapping_key(
+    flash: &mut Flash,
+    rng: &mut impl RngCore,
+) -> Result<(), ConfigError> {
+    if wrapping_secret().is_some() {
+        return Ok(());
+    }
+
+    let mut secret = [0u8; WRAPPING_SECRET_LEN];
+    rng.fill_bytes(&mut secret);
+
+    let flash = &mut flash.parts;
+    let mut prog = flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr)?;
+    prog.write(WRAPPING_SECRET_OTP_START, &secret)?;
+
+    log::info!("Wrapping key initialized");
+
+    Ok(())
+}
+
+fn attestation_area() -> &'static [u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            ATTESTATION_OTP_START as *const u8,
+            ATTESTATION_OTP_END - ATTESTATION_OTP_START,
+        )
+    }
+}
+
+pub async fn read_attestation_key(
+    _flash: &mut Flash,
+) -> Result<Option<AttestationKey>, ConfigError> {
+    let area = attestation_area();
+    let len = u16::from_be_bytes(area[..2].try_into().unwrap()) as usize;
+    if len == 0xFFFF {
+        return Ok(None);
+    } else if len > area.len() - 2 {
         return Err(ConfigError::CorruptedConfig);
     }
 
-    let len = (serialized.len() as u16).to_be_bytes();
+    Ok(Some(minicbor::decode(&area[2..2 + len])?))
+}
+
+pub async fn write_attestation_key(
+    flash: &mut Flash,
+    key: &AttestationKey,
+) -> Result<(), ConfigError> {
+    // Never program over a partially written key
+    if attestation_area().iter().any(|b| *b != 0xFF) {
+        return Err(ConfigError::CorruptedConfig);
+    }
+
+    let serialized = minicbor::to_vec(key).expect("always succeed");
+    let mut data = alloc::vec::Vec::from((serialized.len() as u16).to_be_bytes());
     data.extend(serialized);
-    (&mut data[..2]).copy_from_slice(&len);
-    data.resize(PAGE_SIZE, 0x00);
+    data.resize(data.len().next_multiple_of(8), 0x00);
+    if data.len() > ATTESTATION_OTP_END - ATTESTATION_OTP_START {
+        return Err(ConfigError::CorruptedConfig);
+    }
 
-    let page = flash::FlashPage(CONFIG_PAGE);
-    prog.erase_page(page)?;
-    prog.erase_page(flash::FlashPage(CONFIG_PAGE + 256))?; // Erase on both banks
-    prog.write(page.to_address(), &data)?;
+    let flash = &mut flash.parts;
+    let mut prog = flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr)?;
+    prog.write(ATTESTATION_OTP_START, &data)?;
 
     Ok(())
 }
 
+/// Hash of the firmware area of the running bank, which is always mapped at the beginning of the flash
+pub fn firmware_hash(_flash: &mut Flash) -> [u8; 32] {
+    use bitcoin_hashes::{sha256, Hash};
+
+    const FIRMWARE_PAGES: usize = 253;
+
+    let firmware = unsafe {
+        core::slice::from_raw_parts(
+            flash::FlashPage(0).to_address() as *const u8,
+            FIRMWARE_PAGES * PAGE_SIZE,
+        )
+    };
+    sha256::Hash::hash(firmware).into_inner()
+}
+
 #[derive(Debug)]
 pub enum ConfigError {
     CorruptedConfig,
     Deserialization,
+    /// A config page failed to verify after writing it
+    BadPage,
 
     Flash(flash::Error),
 }
@@ -17,19 +17,106 @@
 
 use super::hw::Flash;
 
-use model::Config;
+use rand::RngCore;
+
+use model::attestation::AttestationKey;
+use model::keywrap::{self, WrappingKey};
+use model::{Config, WipeReport};
+
+/// The emulator has no unique ID or OTP memory, so every instance uses the same key
+fn wrapping_key() -> WrappingKey {
+    WrappingKey::new(&[b"emulator"])
+}
+
+pub async fn init_wrapping_key(
+    _flash: &mut Flash,
+    _rng: &mut impl RngCore,
+) -> Result<(), ConfigError> {
+    Ok(())
+}
 
 pub async fn read_config(flash: &mut Flash) -> Result<Config, ConfigError> {
-    let flash = flash.read().await;
-    Ok(minicbor::decode(&flash).map_err(|_| ConfigError::CorruptedConfig)?)
+    let mut data = flash.read().await;
+    // Configs saved by older versions of the emulator are not wrapped
+    if keywrap::is_wrapped(&data) {
+        data = wrapping_key()
+            .unwrap(&data)
+            .map_err(|_| ConfigError::CorruptedConfig)?;
+    }
+
+    Ok(minicbor::decode(&data).map_err(|_| ConfigError::CorruptedConfig)?)
 }
 
 pub async fn write_config(flash: &mut Flash, config: &Config) -> Result<(), ConfigError> {
-    let buf = minicbor::to_vec(config).unwrap();
+    let buf = wrapping_key().wrap(&minicbor::to_vec(config).unwrap());
     flash.write(&buf);
     Ok(())
 }
 
+/// Number of times the config is erased before giving up on reading it back empty
+const WIPE_PASSES: u32 = 3;
+
+/// The emulator keeps the config in a single blob and has no update checkpoints, so the wipe
+/// writes an empty blob until it reads back empty
+pub async fn wipe_config(
+    flash: &mut Flash,
+    _update_checkpoints: bool,
+) -> Result<WipeReport, ConfigError> {
+    let mut report = WipeReport {
+        pages_checked: 1,
+        ..Default::default()
+    };
+    if flash.read().await.is_empty() {
+        return Ok(report);
+    }
+
+    report.pages_erased = 1;
+    for pass in 1..=WIPE_PASSES {
+        flash.write(&[]);
+        if flash.read().await.is_empty() {
+            report.passes = pass;
+            return Ok(report);
+        }
+    }
+
+    log::warn!("Config is not empty after {} passes", WIPE_PASSES);
+    report.passes = WIPE_PASSES;
+    report.failed_pages.push(0);
+    Ok(report)
+}
+
+pub async fn read_min_version(flash: &mut Flash) -> Result<u32, ConfigError> {
+    Ok(flash.min_version)
+}
+
+pub async fn write_min_version(flash: &mut Flash, version: u32) -> Result<(), ConfigError> {
+    flash.min_version = core::cmp::max(flash.min_version, version);
+    Ok(())
+}
+
+pub async fn read_attestation_key(
+    flash: &mut Flash,
+) -> Result<Option<AttestationKey>, ConfigError> {
+    Ok(flash.attestation_key.clone())
+}
+
+pub async fn write_attestation_key(
+    flash: &mut Flash,
+    key: &AttestationKey,
+) -> Result<(), ConfigError> {
+    if flash.attestation_key.is_some() {
+        return Err(ConfigError::CorruptedConfig);
+    }
+
+    flash.attestation_key = Some(key.clone());
+    Ok(())
+}
+
+/// The emulator doesn't run from flash, so there's no image to hash
+pub fn firmware_hash(_flash: &mut Flash) -> [u8; 32] {
+    [0x00; 32]
+}
+
 #[derive(Debug)]
 pub enum ConfigError {
     CorruptedConfig,
@@ -18,7 +18,7 @@
 use alloc::rc::Rc;
 use alloc::vec::Vec;
 
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
 use embedded_graphics_core::pixelcolor::BinaryColor;
 use rand::SeedableRng;
@@ -29,8 +29,9 @@ use stm32f4xx_hal::serial;
 use embedded_graphics_core::geometry::OriginDimensions;
 use embedded_graphics_core::prelude::*;
 
+use model::attestation::AttestationKey;
 use model::emulator as emu_model;
-use model::{reg::NS_REG, Message, MessageFragment, Reply, Request};
+use model::{reg::NS_REG, Message, MessageError, MessageFragment, Reply, Request};
 
 use super::*;
 use crate::hw_common;
@@ -40,6 +41,7 @@ pub type NfcInterrupt = hw_common::ChannelSender<()>;
 
 pub struct EmulatorChannels {
     pub tsc: hw_common::ChannelSender<bool>,
+    pub tsc_levels: Rc<Cell<hw_common::TscLevels>>,
     pub flash: hw_common::ChannelSender<Vec<u8>>,
     pub emulated_nt3h: EmulatedNT3H,
 }
@@ -97,14 +99,23 @@ pub fn init_peripherals(
     ))
 }
 
+/// Readings reported by the emulated TSC, so that the calibration can be run in the emulator
+pub const EMULATED_RELEASED_READING: u16 = 1600;
+pub const EMULATED_TOUCHED_READING: u16 = 800;
+
 pub struct Tsc {
     enabled: Rc<RefCell<bool>>,
+    levels: Rc<Cell<hw_common::TscLevels>>,
 }
 
 impl Tsc {
     fn new() -> Self {
         Tsc {
             enabled: Rc::new(RefCell::new(false)),
+            levels: Rc::new(Cell::new(hw_common::TscLevels {
+                last_reading: EMULATED_RELEASED_READING,
+                ..Default::default()
+            })),
         }
     }
 
@@ -119,6 +130,10 @@ impl Tsc {
     pub fn get_enabled_ref(&self) -> Rc<RefCell<bool>> {
         Rc::clone(&self.enabled)
     }
+
+    pub fn get_levels_ref(&self) -> Rc<Cell<hw_common::TscLevels>> {
+        Rc::clone(&self.levels)
+    }
 }
 
 pub struct NfcIc {
@@ -211,15 +226,27 @@ impl NfcIc {
         Ok(())
     }
 
+    /// Read and decrypt the next request
+    ///
+    /// Requests unknown to this firmware are answered here with `ErrorCode::UnsupportedRequest`
+    /// and returned as `MessageError::UnsupportedMessage`.
     pub async fn accept_request(
         &mut self,
         decrypt: &mut ::model::encryption::CipherState,
+        encrypt: &mut ::model::encryption::CipherState,
     ) -> Result<Request, Error> {
         let msg = self.read_raw_message().await?;
         let mut decrypt_buf = alloc::vec::Vec::new();
 
         match msg.deserialize(&mut decrypt_buf, decrypt) {
             Ok(v) => Ok(v),
+            Err(MessageError::UnsupportedMessage) => {
+                let reply = Reply::error(model::ErrorCode::UnsupportedRequest);
+                let message = Message::new_serialize(&reply, encrypt)?;
+                self.write_to_mailbox(message.get_fragments().into_iter())
+                    .await?;
+                Err(MessageError::UnsupportedMessage.into())
+            }
             Err(e) => {
                 self.write_to_mailbox([MessageFragment::new_failed_decryption()].into_iter())
                     .await?;
@@ -238,7 +265,7 @@ impl NfcIc {
             .await?;
 
         match reply {
-            Reply::Pong | Reply::DelayedReply => {}
+            Reply::Pong | Reply::DelayedReply | Reply::Progress { .. } => {}
             _ => {
                 let _ = self.finished.send(()).await;
             }
@@ -344,6 +371,11 @@ impl Display {
     }
 }
 
+/// The emulated display has no brightness control
+pub fn set_brightness(_: &mut Display, _: model::settings::Brightness) -> Result<(), crate::Error> {
+    Ok(())
+}
+
 impl OriginDimensions for Display {
     fn size(&self) -> Size {
         Size::new(128, 64)
@@ -380,6 +412,10 @@ impl DrawTarget for Display {
 pub struct Flash {
     channel: RefCell<Option<hw_common::ChannelReceiver<Vec<u8>>>>,
     pub fb_mode: bool,
+    /// The emulator has no OTP memory, so the minimum version only lasts until the next reset
+    pub min_version: u32,
+    /// Same for the attestation key, which has to be provisioned again after every reset
+    pub attestation_key: Option<AttestationKey>,
 }
 
 impl Flash {
@@ -387,6 +423,8 @@ impl Flash {
         Flash {
             channel: RefCell::new(None),
             fb_mode: true,
+            min_version: 0,
+            attestation_key: None,
         }
     }
 
@@ -428,4 +466,14 @@ unsafe fn create_fake_clocks_pclk2_8mhz() -> hal::rcc::Clocks {
     unreachable!()
 }
 
+/// The entropy comes from the host, so there's no hardware RNG to test
+pub fn rng_self_test() -> model::selftest::TestOutcome {
+    model::selftest::TestOutcome::Skipped
+}
+
+/// There's no real microcontroller to identify
+pub fn hardware_revision() -> Option<alloc::string::String> {
+    None
+}
+
 pub fn enable_debug_during_sleep(_: &mut hal::pac::Peripherals) {}
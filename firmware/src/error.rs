@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+#[cfg(not(test))]
 use hal::i2c;
 
 use crate::config;
@@ -33,8 +34,14 @@ pub enum Error {
 
     Wallet,
     Unknown,
+    /// The health tests of the hardware RNG failed
+    RngFailure,
+    /// Nobody touched the device for too long while it was waiting for a confirmation, see
+    /// `model::settings::OperationTimeout`. Not fatal, the operation is aborted
+    Timeout,
 
     FlashError,
+    #[cfg(not(test))]
     I2c(i2c::Error),
     // State(state::StateError),
     Config(config::ConfigError),
@@ -42,6 +49,7 @@ pub enum Error {
     Display(display_interface::DisplayError),
 }
 
+#[cfg(not(test))]
 impl From<i2c::Error> for Error {
     fn from(e: i2c::Error) -> Self {
         Error::I2c(e)
@@ -0,0 +1,119 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::ToString;
+
+use futures::prelude::*;
+
+use gui::{i18n::Label, LoadingPage, Page, SummaryPage};
+use model::address_book::{TrustedAddress, MAX_TRUSTED_ADDRESSES};
+use model::{Config, ErrorCode, Reply};
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+/// Add an address to the address book, after showing it in full
+///
+/// Outputs paying to it are then grouped on a single page when signing, so it must be checked
+/// as carefully as a payment.
+pub async fn handle_add_trusted_address(
+    wallet: &mut Rc<PortalWallet>,
+    address: &str,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_add_trusted_address");
+
+    let address = match model::address_book::parse_address(address, wallet.network()) {
+        Some(address) => address,
+        None => {
+            peripherals
+                .nfc
+                .send(Reply::error(ErrorCode::InvalidAddress))
+                .await
+                .unwrap();
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
+    let trusted = TrustedAddress::new(&address);
+
+    if wallet.config.trusted_addresses().contains(&trusted) {
+        peripherals.nfc.send(Reply::Ok).await.unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+    if wallet.config.trusted_addresses().len() >= MAX_TRUSTED_ADDRESSES {
+        peripherals
+            .nfc
+            .send(Reply::error(ErrorCode::TooManyAddresses))
+            .await
+            .unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    peripherals.nfc.send(Reply::DelayedReply).await.unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    confirm_address(
+        &address.to_string(),
+        Label::TrustAddressTitle.get(),
+        Label::HoldForNextPage.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    let mut page = SummaryPage::new(Label::TrustAddress.get(), Label::HoldToAdd.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let mut config = wallet.config.clone();
+    config
+        .secret
+        .trusted_addresses
+        .get_or_insert_with(Default::default)
+        .push(trusted);
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(config.clone().lock())),
+    )
+    .await?;
+    log::debug!("Address {} trusted", address);
+
+    let new_wallet = super::init::make_wallet_from_xprv(wallet.xprv, wallet.network(), config)?;
+    peripherals.nfc.send(Reply::Ok).await.unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::new(new_wallet),
+    })
+}
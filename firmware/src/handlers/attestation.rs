@@ -0,0 +1,94 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::string::ToString;
+
+use model::attestation::AttestationKey;
+use model::bitcoin::secp256k1::Secp256k1;
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+/// Sign the challenge with the attestation key
+///
+/// This doesn't change the state of the device, so it can be handled from any state in which the
+/// user hasn't been asked anything yet.
+pub async fn handle_attest(
+    challenge: &[u8; 32],
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    log::info!("handle_attest");
+
+    let key = match config::read_attestation_key(&mut peripherals.flash).await? {
+        Some(key) => key,
+        None => {
+            peripherals
+                .nfc
+                .send(model::Reply::error(model::ErrorCode::NotProvisioned))
+                .await
+                .unwrap();
+            peripherals.nfc_finished.recv().await.unwrap();
+
+            return Ok(());
+        }
+    };
+
+    // Hashing the whole firmware takes a moment
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    let firmware_hash = config::firmware_hash(&mut peripherals.flash);
+    let reply = match key.attest(&Secp256k1::signing_only(), challenge, &firmware_hash) {
+        Ok(attestation) => model::Reply::Attestation(attestation),
+        Err(e) => {
+            model::Reply::error_with_detail(model::ErrorCode::AttestationFailed, e.to_string())
+        }
+    };
+    peripherals.nfc.send(reply).await.unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+
+    Ok(())
+}
+
+/// Store the attestation key, which can only be done once
+pub async fn handle_provision_attestation(
+    key: &AttestationKey,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    log::info!("handle_provision_attestation");
+
+    let reply = if config::read_attestation_key(&mut peripherals.flash)
+        .await
+        .map_or(true, |key| key.is_some())
+    {
+        model::Reply::error(model::ErrorCode::AlreadyProvisioned)
+    } else if let Err(e) = key.validate(&Secp256k1::signing_only()) {
+        model::Reply::error_with_detail(model::ErrorCode::InvalidAttestationKey, e.to_string())
+    } else {
+        config::write_attestation_key(&mut peripherals.flash, key).await?;
+        model::Reply::Ok
+    };
+
+    peripherals.nfc.send(reply).await.unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+
+    Ok(())
+}
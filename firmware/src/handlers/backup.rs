@@ -0,0 +1,358 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+
+use futures::prelude::*;
+
+use rand::RngCore;
+
+use gui::{i18n::Label, ConfirmPairCodePage, LoadingPage, Page, SummaryPage};
+use model::backup::{Backup, BackupContents, Kdf};
+use model::{Config, ErrorCode, UnlockedConfig};
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+fn draw_loading(peripherals: &mut HandlerPeripherals) -> Result<(), Error> {
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    Ok(())
+}
+
+pub async fn handle_export_backup(
+    wallet: &mut Rc<PortalWallet>,
+    password: &str,
+    include_seed: bool,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_export_backup");
+
+    if include_seed && !wallet.config.seed_export_allowed() {
+        peripherals
+            .nfc
+            .send(model::Reply::error(ErrorCode::SeedExportDisabled))
+            .await
+            .unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    let contents = if include_seed {
+        Label::SettingsAndSeed.get()
+    } else {
+        Label::SettingsOnly.get()
+    };
+    confirm_page(
+        Label::ExportBackupTitle.get(),
+        contents,
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    let mut page = SummaryPage::new(Label::ExportBackup.get(), Label::HoldToExport.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    draw_loading(peripherals)?;
+
+    let contents = BackupContents {
+        network: wallet.network(),
+        descriptor: wallet.config.secret.descriptor.clone(),
+        fingerprint: wallet.xprv.fingerprint(wallet.secp_ctx()).into_bytes(),
+        mnemonic: include_seed.then(|| wallet.config.secret.mnemonic.clone()),
+    };
+    let mut salt = [0; 16];
+    peripherals.rng.fill_bytes(&mut salt);
+    let mut nonce = [0; 12];
+    peripherals.rng.fill_bytes(&mut nonce);
+
+    let backup = Backup::new(&contents, password, Kdf::default(), salt, nonce);
+    peripherals
+        .nfc
+        .send(model::Reply::Backup(backup.serialize().into()))
+        .await
+        .unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+/// Restore a backup
+///
+/// With an unlocked `wallet` only the descriptor is restored, and the backup must have been made
+/// with the same seed. Without one the device is new and the backup must include the seed.
+pub async fn handle_restore_backup(
+    wallet: Option<Rc<PortalWallet>>,
+    backup: &[u8],
+    password: &str,
+    pair_code: Option<String>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_restore_backup");
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    // Deriving the key takes a moment
+    draw_loading(peripherals)?;
+
+    let previous_state = |wallet: Option<Rc<PortalWallet>>| match wallet {
+        Some(wallet) => CurrentState::Idle { wallet },
+        None => CurrentState::Init,
+    };
+
+    let checks_result = (|| -> Result<_, ErrorCode> {
+        let contents = Backup::deserialize(backup).and_then(|backup| backup.decrypt(password))?;
+
+        let (xprv, unlocked) = match &wallet {
+            Some(wallet) => {
+                if contents.network != wallet.network()
+                    || contents.fingerprint
+                        != wallet.xprv.fingerprint(wallet.secp_ctx()).into_bytes()
+                {
+                    return Err(ErrorCode::BackupWalletMismatch);
+                }
+
+                let mut config = wallet.config.clone();
+                config.secret.descriptor = contents.descriptor.clone();
+                // Backups don't carry the name, the policy has to be registered again
+                config.secret.wallet_policy = None;
+                (wallet.xprv, config)
+            }
+            None => {
+                let entropy = contents
+                    .mnemonic
+                    .clone()
+                    .ok_or(ErrorCode::BackupMissingSeed)?;
+                let mnemonic = entropy.mnemonic().map_err(|_| ErrorCode::InvalidSeed)?;
+                let xprv = bip32::ExtendedPrivKey::new_master(
+                    contents.network,
+                    &mnemonic.to_seed_normalized(""),
+                )
+                .map_err(|_| ErrorCode::InvalidSeed)?;
+
+                let mut salt = [0; 8];
+                peripherals.rng.fill_bytes(&mut salt);
+                let config = UnlockedConfig::new(
+                    entropy,
+                    xprv.into(),
+                    contents.descriptor.clone(),
+                    contents.network,
+                    pair_code.as_deref(),
+                    salt,
+                );
+                (xprv, config)
+            }
+        };
+
+        let mut new_wallet = super::init::make_wallet_from_xprv(xprv, contents.network, unlocked)
+            .map_err(|_| ErrorCode::WalletCreationFailed)?;
+        if new_wallet
+            .xprv
+            .fingerprint(new_wallet.secp_ctx())
+            .into_bytes()
+            != contents.fingerprint
+        {
+            return Err(ErrorCode::BackupCorrupted);
+        }
+        let first_address = new_wallet
+            .get_address(bdk::wallet::AddressIndex::Peek(0))
+            .address;
+
+        Ok((new_wallet, first_address))
+    })();
+
+    let (new_wallet, first_address) = match checks_result {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("Checks failed: {}", e);
+
+            peripherals.nfc.send(e.into()).await.unwrap();
+            return Ok(previous_state(wallet));
+        }
+    };
+
+    peripherals.tsc_enabled.enable();
+
+    let fingerprint = new_wallet
+        .xprv
+        .fingerprint(new_wallet.secp_ctx())
+        .to_string();
+    confirm_page(
+        Label::RestoreBackupTitle.get(),
+        &fingerprint,
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    confirm_page(
+        Label::WalletPolicy.get(),
+        new_wallet.config.secret.descriptor.variant.variant_name(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    confirm_page(
+        Label::AddressType.get(),
+        new_wallet
+            .config
+            .secret
+            .descriptor
+            .script_type
+            .display_name(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    let address_str = first_address.to_string();
+    confirm_address(
+        &address_str,
+        Label::ConfirmFirstAddress.get(),
+        Label::HoldForNextPage.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    if let (None, Some(pair_code)) = (&wallet, &pair_code) {
+        let mut page = ConfirmPairCodePage::new(pair_code);
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    }
+
+    let mut page = SummaryPage::new(Label::RestoreBackup.get(), Label::HoldToApplyChanges.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    draw_loading(peripherals)?;
+
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(new_wallet.config.clone().lock())),
+    )
+    .await?;
+    log::debug!("Backup restored!");
+
+    peripherals.nfc.send(model::Reply::Ok).await.unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::new(new_wallet),
+    })
+}
+
+/// Allow or forbid exporting the seed
+///
+/// Disabling the export protects the seed even from someone holding the unlocked device, so the
+/// change is confirmed on more pages than a normal setting.
+pub async fn handle_set_seed_export(
+    wallet: &mut Rc<PortalWallet>,
+    allowed: bool,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_set_seed_export");
+
+    if wallet.config.seed_export_allowed() == allowed {
+        peripherals.nfc.send(model::Reply::Ok).await.unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    let change = if allowed {
+        Label::Allow.get()
+    } else {
+        Label::NeverAllow.get()
+    };
+    confirm_page(Label::SeedExport.get(), change, &mut events, peripherals).await?;
+    let fingerprint = wallet.xprv.fingerprint(wallet.secp_ctx()).to_string();
+    confirm_page(Label::Wallet.get(), &fingerprint, &mut events, peripherals).await?;
+    if !allowed {
+        confirm_page(
+            Label::NoSeedInBackups.get(),
+            Label::AreYouSure.get(),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+    }
+
+    let mut page = SummaryPage::new(
+        Label::ChangeSeedExport.get(),
+        Label::HoldToApplyChanges.get(),
+    );
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    draw_loading(peripherals)?;
+
+    let mut config = wallet.config.clone();
+    config.secret.disable_seed_export = Some(!allowed);
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(config.clone().lock())),
+    )
+    .await?;
+    log::debug!("Seed export allowed: {}", allowed);
+
+    let new_wallet = super::init::make_wallet_from_xprv(wallet.xprv, wallet.network(), config)?;
+    peripherals.nfc.send(model::Reply::Ok).await.unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::new(new_wallet),
+    })
+}
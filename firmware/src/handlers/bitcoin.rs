@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::rc::Rc;
 use alloc::string::ToString;
@@ -22,22 +23,31 @@ use alloc::vec::Vec;
 
 use futures::prelude::*;
 
+use rand::RngCore;
+
+use bdk::bitcoin::util::sighash::SighashCache;
 use bdk::bitcoin::util::{bip32, psbt, taproot};
-use bdk::bitcoin::{Address, Amount, PublicKey, XOnlyPublicKey};
+use bdk::bitcoin::{
+    Amount, EcdsaSighashType, PublicKey, SchnorrSighashType, TxOut, Txid, XOnlyPublicKey,
+};
 use bdk::descriptor::{
     DerivedDescriptor, DescriptorError, DescriptorXKey, ExtendedDescriptor, TapKeyOrigins, Wildcard,
 };
 use bdk::keys::SinglePubKey;
 use bdk::miniscript::descriptor::{DescriptorType, InnerXKey};
 use bdk::miniscript::{DescriptorPublicKey, ForEachKey};
+use bdk::signer::{InputSigner as _, SignerError};
 use bdk::HdKeyPaths;
 
 use gui::{
-    GenericTwoLinePage, LoadingPage, Page, ShowScrollingAddressPage, SigningTxPage, SummaryPage,
-    TxOutputPage, TxSummaryPage,
+    i18n::Label, ErrorPage, GenericThreeLinePage, GenericTwoLinePage, LoadingPage, OpReturnPage,
+    Page, SigningProgressPage, SingleLineTextPage, SummaryPage, TxOutputPage, TxSummaryPage,
+};
+use model::settings::{
+    AmountUnit, ChangeOutputs, NonDefaultSighash, ReviewInputs, TextSize, UtxoVerification,
 };
 use model::{
-    DescriptorVariant, ExtendedKey, MultisigKey, ScriptType, SerializedDerivationPath,
+    DescriptorVariant, ErrorCode, ExtendedKey, MultisigKey, ScriptType, SerializedDerivationPath,
     SetDescriptorVariant, WalletDescriptor,
 };
 
@@ -51,6 +61,7 @@ struct CurrentSignatures {
     partial_sigs: BTreeSet<PublicKey>,
     tap_key_sig: bool,
     tap_script_sigs: BTreeSet<(XOnlyPublicKey, taproot::TapLeafHash)>,
+    musig2: BTreeSet<psbt::raw::Key>,
 }
 
 impl CurrentSignatures {
@@ -61,6 +72,12 @@ impl CurrentSignatures {
                 partial_sigs: i.partial_sigs.iter().map(|(k, _)| k.clone()).collect(),
                 tap_key_sig: i.tap_key_sig.is_some(),
                 tap_script_sigs: i.tap_script_sigs.iter().map(|(k, _)| k.clone()).collect(),
+                musig2: i
+                    .unknown
+                    .keys()
+                    .filter(|k| model::musig2::is_signing_field(k))
+                    .cloned()
+                    .collect(),
             })
             .collect()
     }
@@ -73,10 +90,13 @@ impl CurrentSignatures {
                 i.partial_sigs.retain(|k, _| !s.partial_sigs.contains(k));
                 i.tap_script_sigs
                     .retain(|k, _| !s.tap_script_sigs.contains(k));
+                i.unknown
+                    .retain(|k, _| model::musig2::is_signing_field(k) && !s.musig2.contains(k));
 
                 let mut input = psbt::Input::default();
                 input.partial_sigs = i.partial_sigs;
                 input.tap_script_sigs = i.tap_script_sigs;
+                input.unknown = i.unknown;
                 input.tap_key_sig = match (i.tap_key_sig, s.tap_key_sig) {
                     (Some(sig), false) => Some(sig),
                     _ => None,
@@ -88,135 +108,2261 @@ impl CurrentSignatures {
     }
 }
 
-pub async fn handle_sign_request(
-    wallet: &mut Rc<PortalWallet>,
-    psbt: &[u8],
-    mut events: impl Stream<Item = Event> + Unpin,
-    peripherals: &mut HandlerPeripherals,
-) -> Result<CurrentState, Error> {
-    log::info!("handle_sign_request");
+/// Whether `utxo`, spent by `psbt_in`, belongs to the wallet
+fn is_our_input(wallet: &PortalWallet, psbt_in: &psbt::Input, utxo: &TxOut) -> bool {
+    [bdk::KeychainKind::External, bdk::KeychainKind::Internal]
+        .into_iter()
+        .any(|keychain| {
+            wallet
+                .get_descriptor_for_keychain(keychain)
+                .derive_from_psbt_input(psbt_in, wallet.secp_ctx())
+                .is_some_and(|derived| derived.script_pubkey() == utxo.script_pubkey)
+        })
+}
 
-    peripherals
-        .nfc
-        .send(model::Reply::DelayedReply)
-        .await
-        .unwrap();
+/// Whether `out`, described by `psbt_out`, goes back to the wallet, either as change or to one of
+/// its receive addresses
+fn is_our_output(wallet: &PortalWallet, psbt_out: &psbt::Output, out: &TxOut) -> bool {
+    [bdk::KeychainKind::External, bdk::KeychainKind::Internal]
+        .into_iter()
+        .any(|keychain| {
+            wallet
+                .get_descriptor_for_keychain(keychain)
+                .derive_from_psbt_output(psbt_out, wallet.secp_ctx())
+                .is_some_and(|derived| derived.script_pubkey() == out.script_pubkey)
+        })
+}
 
-    let mut psbt: psbt::PartiallySignedTransaction =
-        bdk::bitcoin::consensus::encode::deserialize(&psbt).unwrap();
+/// Whether an output is change of the wallet, which isn't shown among the outputs by default
+fn is_change(wallet: &PortalWallet, psbt_out: &psbt::Output) -> bool {
+    wallet
+        .get_descriptor_for_keychain(bdk::KeychainKind::Internal)
+        .derive_from_psbt_output(psbt_out, &wallet.secp_ctx())
+        .is_some()
+}
 
-    let allow_witness_utxo = matches!(
-        wallet
-            .public_descriptor(bdk::KeychainKind::External)
-            .unwrap(),
-        bdk::miniscript::Descriptor::Tr(_)
-    );
+/// Fingerprints of all the keys of a multisig wallet and its threshold, `None` for single-sig
+///
+/// External keys without an origin are identified by their own fingerprint, like in the key
+/// origins of the PSBTs.
+fn multisig_cosigners(wallet: &PortalWallet) -> Option<(Vec<bip32::Fingerprint>, usize)> {
+    match &wallet.config.secret.descriptor.variant {
+        DescriptorVariant::SingleSig(_) => None,
+        DescriptorVariant::MultiSig {
+            threshold, keys, ..
+        } => {
+            let local = wallet.xprv.fingerprint(wallet.secp_ctx());
+            let fingerprints = keys
+                .iter()
+                .map(|key| match key {
+                    MultisigKey::Local(_) => local,
+                    MultisigKey::External(ExtendedKey {
+                        origin: Some((fingerprint, _)),
+                        ..
+                    }) => fingerprint.clone().into(),
+                    MultisigKey::External(ExtendedKey { key, .. }) => key
+                        .as_xpub()
+                        .expect("The key was checked when setting the config")
+                        .fingerprint(),
+                })
+                .collect();
+            Some((fingerprints, *threshold))
+        }
+        // Every path of a tree has its own threshold
+        DescriptorVariant::TapTree { .. } => None,
+    }
+}
 
-    let prev_utxos = psbt
-        .unsigned_tx
-        .input
-        .iter()
+/// Flag the inputs and the outputs of `psbt` that belong to the wallet
+fn ours(
+    wallet: &PortalWallet,
+    psbt: &psbt::PartiallySignedTransaction,
+    allow_witness_utxo: bool,
+) -> Result<(Vec<bool>, Vec<bool>), model::psbt::PsbtError> {
+    let our_inputs = model::psbt::prev_utxos(psbt, allow_witness_utxo)?
+        .into_iter()
         .zip(psbt.inputs.iter())
-        .map(|(txin, input)| {
-            if let Some(prev_tx) = &input.non_witness_utxo {
-                if prev_tx.txid() == txin.previous_output.txid
-                    && prev_tx.output.len() > txin.previous_output.vout as usize
-                {
-                    Ok(&prev_tx.output[txin.previous_output.vout as usize])
-                } else {
-                    Err("Invalid non_witness_utxo")
-                }
-            } else if allow_witness_utxo && input.witness_utxo.is_some() {
-                Ok(input.witness_utxo.as_ref().unwrap())
-            } else {
-                Err("Missing NonWitnessUtxo")
-            }
-        })
-        .collect::<Result<alloc::vec::Vec<_>, _>>()
-        .unwrap();
-    let total_input_value = prev_utxos.iter().fold(0, |sum, utxo| sum + utxo.value);
-    let total_output_value = psbt
+        .map(|(utxo, psbt_in)| is_our_input(wallet, psbt_in, utxo))
+        .collect();
+    let our_outputs = psbt
         .unsigned_tx
         .output
         .iter()
-        .fold(0, |sum, utxo| sum + utxo.value);
-    let fees = total_input_value.checked_sub(total_output_value).unwrap();
+        .zip(psbt.outputs.iter())
+        .map(|(out, psbt_out)| is_our_output(wallet, psbt_out, out))
+        .collect();
 
-    peripherals.tsc_enabled.enable();
+    Ok((our_inputs, our_outputs))
+}
 
-    for (out, psbt_out) in psbt.unsigned_tx.output.iter().zip(psbt.outputs.iter()) {
-        if wallet
-            .get_descriptor_for_keychain(bdk::KeychainKind::Internal)
-            .derive_from_psbt_output(psbt_out, &wallet.secp_ctx())
-            .is_some()
-        {
-            // Hide our change outputs
-            continue;
+/// Whether `witness_utxo` can be trusted, which is only the case for taproot wallets, unless the
+/// settings ask for the previous transactions anyway
+fn allows_witness_utxo(wallet: &PortalWallet, settings: &model::settings::DeviceSettings) -> bool {
+    settings.utxo_verification == UtxoVerification::Standard
+        && matches!(
+            wallet
+                .public_descriptor(bdk::KeychainKind::External)
+                .unwrap(),
+            bdk::miniscript::Descriptor::Tr(_)
+        )
+}
+
+fn amount(unit: AmountUnit, sat: u64) -> alloc::string::String {
+    unit.format(Amount::from_sat(sat))
+}
+
+/// Like `amount`, with the sign always shown
+fn signed_amount(unit: AmountUnit, sat: i64) -> alloc::string::String {
+    let sign = if sat < 0 { "-" } else { "+" };
+    alloc::format!("{}{}", sign, amount(unit, sat.unsigned_abs()))
+}
+
+/// Show what the wallet sends to and receives from a transaction shared with other participants
+async fn confirm_net_flow(
+    flow: model::psbt::NetFlow,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    confirm_page(
+        Label::CoinjoinYouSend.get(),
+        &amount(peripherals.settings.amount_unit, flow.sent),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    confirm_page(
+        Label::YouReceive.get(),
+        &amount(peripherals.settings.amount_unit, flow.received),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    confirm_page(
+        Label::NetChange.get(),
+        &signed_amount(peripherals.settings.amount_unit, flow.net()),
+        &mut events,
+        peripherals,
+    )
+    .await
+}
+
+/// Show when the transaction can be mined and whether it can be replaced
+async fn confirm_timelock(
+    timelock: model::psbt::TxTimelock,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    let lock_time = match timelock.lock_time {
+        model::psbt::TxLockTime::None => Label::NoLocktime.get().into(),
+        model::psbt::TxLockTime::Height(height) => {
+            alloc::format!("{} {}", Label::Block.get(), height)
+        }
+        model::psbt::TxLockTime::Time(time) => model::psbt::format_timestamp(time),
+    };
+    let rbf = if timelock.rbf {
+        Label::Replaceable.get()
+    } else {
+        Label::NotReplaceable.get()
+    };
+
+    confirm_page_with_note(
+        Label::LocktimeRbf.get(),
+        &lock_time,
+        rbf,
+        &mut events,
+        peripherals,
+    )
+    .await
+}
+
+/// Show a path of a taproot output through which the inputs are spent, with the timelocks of its
+/// leaf
+async fn confirm_spending_path(
+    path: &model::taproot::SpendingPath,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    use model::taproot::SpendingPath;
+
+    match path {
+        SpendingPath::KeyPath => {
+            confirm_page(
+                Label::SpendingVia.get(),
+                Label::KeyPath.get(),
+                events,
+                peripherals,
+            )
+            .await
+        }
+        SpendingPath::ScriptPath { timelocks, .. } if timelocks.is_empty() => {
+            confirm_page(
+                Label::SpendingVia.get(),
+                Label::ScriptPath.get(),
+                events,
+                peripherals,
+            )
+            .await
+        }
+        SpendingPath::ScriptPath { timelocks, .. } => {
+            let note = timelocks
+                .iter()
+                .map(|timelock| leaf_timelock(*timelock))
+                .collect::<Vec<_>>()
+                .join(", ");
+            confirm_page_with_note(
+                Label::SpendingVia.get(),
+                Label::ScriptPath.get(),
+                &note,
+                events,
+                peripherals,
+            )
+            .await
+        }
+    }
+}
+
+/// Text of the timelock of a tapscript leaf, e.g. "after 144 blocks"
+fn leaf_timelock(timelock: model::taproot::LeafTimelock) -> alloc::string::String {
+    use model::taproot::LeafTimelock;
+
+    match timelock {
+        LeafTimelock::Blocks(blocks) => {
+            alloc::format!("{} {} {}", Label::After.get(), blocks, Label::Blocks.get())
+        }
+        LeafTimelock::Seconds(seconds) => alloc::format!(
+            "{} {}h {:02}m",
+            Label::After.get(),
+            seconds / 3600,
+            seconds % 3600 / 60
+        ),
+        LeafTimelock::Height(height) => {
+            alloc::format!("{} {}", Label::FromBlock.get(), height)
         }
+        LeafTimelock::Time(time) => alloc::format!(
+            "{} {}",
+            Label::From.get(),
+            model::psbt::format_timestamp(time)
+        ),
+    }
+}
 
-        let address = Address::from_script(&out.script_pubkey, wallet.network()).unwrap();
-        let value = Amount::from_sat(out.value);
+/// Show an output that carries data (`OP_RETURN`), warning first if it also burns some value
+async fn confirm_op_return(
+    data: &[u8],
+    value: u64,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    if value > 0 {
+        confirm_page_with_note(
+            Label::Warning.get(),
+            &amount(peripherals.settings.amount_unit, value),
+            Label::BurntByOpReturn.get(),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+    }
 
-        let mut page = TxOutputPage::new(&address, value);
+    if peripherals.settings.text_size == TextSize::Large {
+        let text = match core::str::from_utf8(data) {
+            Ok(text) if !text.is_empty() && text.chars().all(|c| (' '..='~').contains(&c)) => {
+                alloc::string::String::from(text)
+            }
+            _ => bdk::bitcoin::hashes::hex::ToHex::to_hex(data),
+        };
+        confirm_large_text(
+            "OP_RETURN",
+            &text,
+            Label::HoldToContinue.get(),
+            50,
+            &mut events,
+            peripherals,
+        )
+        .await
+    } else {
+        let mut page = OpReturnPage::new(
+            data,
+            Amount::from_sat(value),
+            peripherals.settings.amount_unit,
+        );
         page.init_display(&mut peripherals.display)?;
         page.draw_to(&mut peripherals.display)?;
         peripherals.display.flush()?;
 
-        manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await
+    }
+}
+
+/// Estimate the fee rate of a transaction in sat/vB
+///
+/// The inputs of the wallet are estimated from its descriptor, the others from the script they
+/// spend. `None` if one of them spends a script that can't be estimated.
+fn estimate_fee_rate(
+    wallet: &PortalWallet,
+    psbt: &psbt::PartiallySignedTransaction,
+    fees: u64,
+    our_inputs: &[bool],
+    allow_witness_utxo: bool,
+) -> Result<Option<f32>, model::psbt::PsbtError> {
+    let our_weight = wallet
+        .get_descriptor_for_keychain(bdk::KeychainKind::External)
+        .max_satisfaction_weight()
+        .ok()
+        .map(|w| w as u64);
+    let weights = model::psbt::prev_utxos(psbt, allow_witness_utxo)?
+        .into_iter()
+        .zip(our_inputs)
+        .map(|(utxo, ours)| {
+            our_weight
+                .filter(|_| *ours)
+                .or_else(|| model::psbt::satisfaction_weight(&utxo.script_pubkey))
+        })
+        .collect::<Option<Vec<_>>>();
+
+    Ok(weights
+        .map(|weights| model::psbt::fee_rate(fees, model::psbt::estimate_vsize(psbt, weights))))
+}
+
+/// Warning shown before the fees and its note, if they are above the thresholds in the settings
+fn fee_warning(
+    settings: &model::settings::DeviceSettings,
+    psbt: &psbt::PartiallySignedTransaction,
+    fees: u64,
+    fee_rate: Option<f32>,
+) -> Option<(&'static str, alloc::string::String)> {
+    // Can't overflow, the fees were already computed
+    let output_value = psbt.unsigned_tx.output.iter().map(|out| out.value).sum();
+
+    if settings.fee_warning_percent.exceeded(fees, output_value) {
+        let note = match output_value {
+            0 => Label::NothingSent.get().into(),
+            _ => alloc::format!(
+                "{:.1}% {}",
+                fees as f32 * 100.0 / output_value as f32,
+                Label::OfOutputs.get()
+            ),
+        };
+        Some((Label::HighFees.get(), note))
+    } else {
+        fee_rate
+            .filter(|rate| settings.fee_warning_rate.exceeded(*rate))
+            .map(|rate| {
+                (
+                    Label::HighFeeRate.get(),
+                    alloc::format!("{:.0} sat/vB", rate),
+                )
+            })
+    }
+}
+
+/// Show the fees of the transaction and its fee rate, holding the button signs it
+async fn confirm_fees(
+    fees: u64,
+    fee_rate: Option<f32>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    if peripherals.settings.text_size == TextSize::Large {
+        let mut text = amount(peripherals.settings.amount_unit, fees);
+        if let Some(fee_rate) = fee_rate {
+            text += &alloc::format!(" {:.1} sat/vB", fee_rate);
+        }
+        confirm_large_text(
+            Label::TransactionFee.get(),
+            &text,
+            Label::HoldToSignTx.get(),
+            80,
+            &mut events,
+            peripherals,
+        )
+        .await
+    } else {
+        let mut page = TxSummaryPage::new(
+            Amount::from_sat(fees),
+            fee_rate,
+            peripherals.settings.amount_unit,
+        );
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await
     }
+}
+
+/// How long the "Invalid transaction" page stays on the screen
+pub(super) const INVALID_TX_TICKS: usize = 3000 / crate::TIMER_TICK_MILLIS as usize;
 
-    let mut page = TxSummaryPage::new(Amount::from_sat(fees));
+/// Show that the transaction was rejected, before going back to idle
+async fn show_invalid_transaction(
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    let page = ErrorPage::new(Label::InvalidTransaction.get());
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
 
-    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    let nfc = RefCell::new(&mut peripherals.nfc);
+    wait_ticks(events, &nfc, INVALID_TX_TICKS).await;
+
+    Ok(())
+}
+
+async fn reply_invalid_psbt(
+    e: model::psbt::PsbtError,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    log::warn!("Invalid PSBT: {}", e);
+
+    peripherals
+        .nfc
+        .send(Reply::error_with_detail(e.error_code(), e.to_string()))
+        .await
+        .unwrap();
+
+    show_invalid_transaction(events, peripherals).await
+}
+
+/// How the host wants `SignPsbt` to be answered, from the optional fields of the request
+#[derive(Clone, Copy, Default)]
+pub struct ReplyOptions {
+    /// The whole signed PSBT instead of the signatures only
+    pub full_psbt: bool,
+    /// The finalized transaction, if the device adds the last signatures needed
+    pub finalize: bool,
+}
+
+/// What a PSBT sent with `SignPsbt` is for, depending on the request that preceded it
+#[derive(Clone, Copy)]
+pub enum PsbtKind {
+    /// A new transaction, after `BeginSignPsbt`
+    Payment,
+    /// A payjoin proposal built on the last payment, after `BeginSignPayjoin`
+    Payjoin,
+    /// A payjoin proposal built by the wallet as the receiver, after `BeginSignPayjoinReceiver`
+    PayjoinReceiver,
+    /// One of the transactions of `BeginSignPsbtBatch`
+    Batch(SignBatch),
+    /// A new transaction signed with the nonces tweaked by the host, after
+    /// `BeginSignPsbtAntiExfil` with this host commitment
+    AntiExfil([u8; 32]),
+    /// A proof of reserves, after `BeginSignProofOfReserves`
+    ProofOfReserves,
+}
+
+/// Transactions of a batch signed so far, see `Request::BeginSignPsbtBatch`
+#[derive(Clone, Copy)]
+pub struct SignBatch {
+    count: u32,
+    signed: u32,
+    /// Fees of the transactions signed so far
+    fees: u64,
+}
+
+impl SignBatch {
+    pub fn new(count: u32) -> Self {
+        SignBatch {
+            count,
+            signed: 0,
+            fees: 0,
+        }
+    }
+
+    pub(super) fn add(self, fees: u64) -> Self {
+        SignBatch {
+            signed: self.signed + 1,
+            fees: self.fees.saturating_add(fees),
+            ..self
+        }
+    }
+}
+
+/// Wait for the next transaction of `batch`, or show the summary of the batch once it's complete
+async fn next_in_batch(
+    wallet: &mut Rc<PortalWallet>,
+    batch: SignBatch,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    if batch.signed < batch.count {
+        return Ok(CurrentState::WaitingForPsbt {
+            wallet: Rc::clone(wallet),
+            kind: PsbtKind::Batch(batch),
+        });
+    }
+
+    confirm_page_with_note(
+        Label::BatchSigned.get(),
+        &alloc::format!("{} {}", batch.signed, Label::Transactions.get()),
+        &alloc::format!(
+            "{} {}",
+            amount(peripherals.settings.amount_unit, batch.fees),
+            Label::InFees.get()
+        ),
+        events,
+        peripherals,
+    )
+    .await?;
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+/// Secret nonce generated for a MuSig2 signature, waiting for the nonces of the other participants
+struct Musig2Session {
+    input: usize,
+    participant: secp256k1::PublicKey,
+    aggregate_key: secp256k1::PublicKey,
+    leaf_hash: Option<taproot::TapLeafHash>,
+    /// Message the nonce was generated for, which must not change in the second round
+    message: secp256k1::Message,
+    pubnonce: model::musig2::PubNonce,
+    secnonce: model::musig2::SecNonce,
+}
+
+/// Signatures made for a transaction, kept in memory while the inputs are signed one at a time
+///
+/// If the same transaction is sent again before the signatures are delivered to the host (for
+/// example after the connection dropped), signing resumes from the first input not signed yet.
+///
+/// The secret nonces of MuSig2 signatures are also kept here once the signatures are delivered,
+/// until the same transaction comes back with the nonces of the other participants.
+pub struct SigningCheckpoint {
+    txid: Txid,
+    /// Signatures of the inputs signed so far, in order
+    inputs: Vec<psbt::Input>,
+    musig2: Vec<Musig2Session>,
+}
+
+impl SigningCheckpoint {
+    fn new(txid: Txid) -> Self {
+        SigningCheckpoint {
+            txid,
+            inputs: Vec::new(),
+            musig2: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, input: &psbt::Input) {
+        self.inputs.push(psbt::Input {
+            partial_sigs: input.partial_sigs.clone(),
+            tap_key_sig: input.tap_key_sig,
+            tap_script_sigs: input.tap_script_sigs.clone(),
+            unknown: input
+                .unknown
+                .iter()
+                .filter(|(k, _)| model::musig2::is_signing_field(k))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            ..Default::default()
+        });
+    }
+
+    /// What's left to keep once the signatures are delivered: the MuSig2 nonces still unused
+    fn into_pending(self) -> Option<Self> {
+        match self.musig2.is_empty() {
+            true => None,
+            false => Some(SigningCheckpoint {
+                inputs: Vec::new(),
+                ..self
+            }),
+        }
+    }
+}
+
+/// Options used to sign a PSBT after it's been reviewed
+///
+/// `witness_utxo` is trusted when the previous transaction of every input was checked while the
+/// PSBT was received, see `model::psbt_stream`.
+fn sign_options(allow_all_sighashes: bool, utxos_checked: bool) -> bdk::SignOptions {
+    bdk::SignOptions {
+        try_finalize: false,
+        allow_all_sighashes,
+        trust_witness_utxo: utxos_checked,
+        ..Default::default()
+    }
+}
+
+/// Same checks made by `bdk::Wallet::sign` on the whole PSBT before signing
+fn check_signable(
+    psbt: &psbt::PartiallySignedTransaction,
+    sign_options: &bdk::SignOptions,
+) -> Result<(), SignerError> {
+    if !sign_options.trust_witness_utxo
+        && psbt
+            .inputs
+            .iter()
+            .filter(|i| i.final_script_witness.is_none() && i.final_script_sig.is_none())
+            .filter(|i| i.tap_internal_key.is_none() && i.tap_merkle_root.is_none())
+            .any(|i| i.non_witness_utxo.is_none())
+    {
+        return Err(SignerError::MissingNonWitnessUtxo);
+    }
+
+    if !sign_options.allow_all_sighashes
+        && !psbt.inputs.iter().all(|i| {
+            i.sighash_type.is_none()
+                || i.sighash_type == Some(EcdsaSighashType::All.into())
+                || i.sighash_type == Some(SchnorrSighashType::All.into())
+                || i.sighash_type == Some(SchnorrSighashType::Default.into())
+        })
+    {
+        return Err(SignerError::NonStandardSighash);
+    }
+
+    Ok(())
+}
+
+/// Paths of the taproot outputs through which our inputs are spent, each one only once
+///
+/// The leaves about to be signed are found like in `sign_tap_scripts`, see
+/// `model::taproot::spending_paths` for how the signatures already in the PSBT are used.
+fn spending_paths(
+    wallet: &PortalWallet,
+    psbt: &psbt::PartiallySignedTransaction,
+    our_inputs: &[bool],
+) -> Vec<model::taproot::SpendingPath> {
+    let secp = wallet.secp_ctx();
+    let utxos = match model::psbt::prev_utxos(psbt, true) {
+        Ok(utxos) => utxos,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut paths = BTreeSet::new();
+    for ((input, utxo), _) in psbt
+        .inputs
+        .iter()
+        .zip(utxos)
+        .zip(our_inputs)
+        .filter(|(_, ours)| **ours)
+    {
+        let signed_leaves = input
+            .tap_key_origins
+            .iter()
+            .filter(|(xonly, (_, key_source))| {
+                descriptor_key(wallet, key_source).is_some_and(|key| {
+                    secp256k1::KeyPair::from_secret_key(secp, &key)
+                        .x_only_public_key()
+                        .0
+                        == **xonly
+                })
+            })
+            .flat_map(|(xonly, _)| model::taproot::signable_leaves(secp, input, utxo, xonly))
+            .map(|(leaf_hash, _)| leaf_hash)
+            .collect::<Vec<_>>();
+        paths.extend(model::taproot::spending_paths(input, &signed_leaves));
+    }
+
+    paths.into_iter().collect()
+}
+
+/// Private key for `key_source`, if it's one of the keys of the wallet descriptor
+///
+/// Like the BDK signer, the path must be the origin of one of the local keys followed by its
+/// derivation path and the index, so a PSBT can't get signatures from any other key of the seed.
+fn descriptor_key(
+    wallet: &PortalWallet,
+    key_source: &bip32::KeySource,
+) -> Option<secp256k1::SecretKey> {
+    let secp = wallet.secp_ctx();
+    wallet.signers.iter().find_map(|signer| {
+        signer.matches(key_source, secp)?;
+
+        let origin_len = signer
+            .origin
+            .as_ref()
+            .map(|(_, path)| path.as_ref().len())
+            .unwrap_or(0);
+        let path = bip32::DerivationPath::from(&key_source.1[origin_len..]);
+        signer
+            .xkey
+            .derive_priv(secp, &path)
+            .ok()
+            .map(|derived| derived.private_key)
+    })
+}
+
+/// Sign the tapscript leaves of input `index` that contain keys of the wallet
+///
+/// The BDK signer only signs script paths when the internal key is ours, so the leaves of
+/// descriptors like `tr(NUMS, multi_a(...))` are signed here, from the key origins and the scripts
+/// in the PSBT. See `model::taproot::signable_leaves` for the checks made on them.
+fn sign_tap_scripts(
+    wallet: &PortalWallet,
+    psbt: &mut psbt::PartiallySignedTransaction,
+    index: usize,
+    aux_rand: &[u8; 32],
+) -> Result<(), SignerError> {
+    let secp = wallet.secp_ctx();
+    let input = &psbt.inputs[index];
+    if input.tap_scripts.is_empty() {
+        return Ok(());
+    }
+
+    let utxos = model::psbt::prev_utxos(psbt, true).map_err(|_| SignerError::MissingWitnessUtxo)?;
+    let sighash_type = input
+        .schnorr_hash_ty()
+        .map_err(|_| SignerError::InvalidSighash)?;
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+
+    let mut signatures = Vec::new();
+    for (xonly, (_, key_source)) in &input.tap_key_origins {
+        let key = match descriptor_key(wallet, key_source) {
+            Some(key) => key,
+            None => continue,
+        };
+        let keypair = secp256k1::KeyPair::from_secret_key(secp, &key);
+        if keypair.x_only_public_key().0 != *xonly {
+            continue;
+        }
+
+        for (leaf_hash, _) in model::taproot::signable_leaves(secp, input, utxos[index], xonly) {
+            if input.tap_script_sigs.contains_key(&(*xonly, leaf_hash)) {
+                continue;
+            }
+
+            let sighash = cache
+                .taproot_script_spend_signature_hash(
+                    index,
+                    &bdk::bitcoin::util::sighash::Prevouts::All(utxos.as_slice()),
+                    leaf_hash,
+                    sighash_type,
+                )
+                .map_err(SignerError::SighashError)?;
+            let message =
+                secp256k1::Message::from_slice(&sighash[..]).expect("Sighashes are 32 bytes long");
+            let sig = secp.sign_schnorr_with_aux_rand(&message, &keypair, aux_rand);
+            signatures.push((
+                (*xonly, leaf_hash),
+                bdk::bitcoin::SchnorrSig {
+                    sig,
+                    hash_ty: sighash_type,
+                },
+            ));
+        }
+    }
+
+    psbt.inputs[index].tap_script_sigs.extend(signatures);
+    Ok(())
+}
+
+/// Take part in the MuSig2 signatures of input `index`, for the aggregate keys of BIP-373 that
+/// include keys of the wallet
+///
+/// The first time the transaction is signed a nonce is generated and added to the PSBT, and the
+/// secret nonce is kept in `sessions`. Once the transaction comes back with the nonces of all the
+/// participants the partial signature is added, and the secret nonce is dropped so that it's never
+/// used again.
+fn sign_musig2(
+    wallet: &PortalWallet,
+    psbt: &mut psbt::PartiallySignedTransaction,
+    index: usize,
+    sessions: &mut Vec<Musig2Session>,
+    rng: &mut impl RngCore,
+) -> Result<(), SignerError> {
+    use model::musig2;
+
+    let secp = wallet.secp_ctx();
+    let input = &psbt.inputs[index];
+    let participants = musig2::participants(input);
+    if participants.is_empty() {
+        return Ok(());
+    }
+
+    let utxos = model::psbt::prev_utxos(psbt, true).map_err(|_| SignerError::MissingWitnessUtxo)?;
+    let sighash_type = input
+        .schnorr_hash_ty()
+        .map_err(|_| SignerError::InvalidSighash)?;
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+    let prevouts = bdk::bitcoin::util::sighash::Prevouts::All(utxos.as_slice());
+
+    let mut fields = Vec::new();
+    for (aggregate_key, keys) in &participants {
+        for (participant, key_source) in &input.bip32_derivation {
+            if !keys.contains(participant) {
+                continue;
+            }
+            let key = match descriptor_key(wallet, key_source) {
+                Some(key) => key,
+                None => continue,
+            };
+            if key.public_key(secp) != *participant {
+                continue;
+            }
+
+            for path in musig2::spend_paths(secp, input, utxos[index], aggregate_key, keys) {
+                let sighash = match path.leaf_hash {
+                    None => cache.taproot_key_spend_signature_hash(index, &prevouts, sighash_type),
+                    Some(leaf_hash) => cache.taproot_script_spend_signature_hash(
+                        index,
+                        &prevouts,
+                        leaf_hash,
+                        sighash_type,
+                    ),
+                }
+                .map_err(SignerError::SighashError)?;
+                let message = secp256k1::Message::from_slice(&sighash[..])
+                    .expect("Sighashes are 32 bytes long");
+                let is_session = |s: &Musig2Session| {
+                    s.input == index
+                        && s.participant == *participant
+                        && s.aggregate_key == *aggregate_key
+                        && s.leaf_hash == path.leaf_hash
+                };
+
+                let pubnonce =
+                    match musig2::pub_nonce(input, participant, aggregate_key, path.leaf_hash) {
+                        Some(pubnonce) => pubnonce,
+                        None => {
+                            // First round: add our nonce
+                            let mut rand = [0u8; 32];
+                            rng.fill_bytes(&mut rand);
+                            let (secnonce, pubnonce) = musig2::nonce_gen(
+                                secp,
+                                &rand,
+                                &key,
+                                &path.key_agg.aggregate_key().x_only_public_key().0,
+                                &message,
+                            );
+
+                            sessions.retain(|s| !is_session(s));
+                            sessions.push(Musig2Session {
+                                input: index,
+                                participant: *participant,
+                                aggregate_key: *aggregate_key,
+                                leaf_hash: path.leaf_hash,
+                                message,
+                                pubnonce,
+                                secnonce,
+                            });
+                            fields.push((
+                                musig2::field_key(
+                                    musig2::PSBT_IN_MUSIG2_PUB_NONCE,
+                                    participant,
+                                    aggregate_key,
+                                    path.leaf_hash,
+                                ),
+                                pubnonce.serialize().to_vec(),
+                            ));
+                            continue;
+                        }
+                    };
+                if musig2::has_partial_sig(input, participant, aggregate_key, path.leaf_hash) {
+                    continue;
+                }
+
+                // Second round: sign once every participant has sent its nonce
+                let nonces = keys
+                    .iter()
+                    .map(|k| musig2::pub_nonce(input, k, aggregate_key, path.leaf_hash))
+                    .collect::<Option<Vec<_>>>();
+                let nonces = match nonces {
+                    Some(nonces) => nonces,
+                    None => continue,
+                };
+                let session = match sessions
+                    .iter()
+                    .position(|s| is_session(s) && s.pubnonce == pubnonce)
+                {
+                    Some(position) => sessions.remove(position),
+                    None => {
+                        log::warn!("No MuSig2 nonce for input {}", index);
+                        continue;
+                    }
+                };
+                if session.message != message {
+                    return Err(SignerError::InvalidSighash);
+                }
+
+                let partial_sig = musig2::aggregate_nonces(&nonces)
+                    .and_then(|aggregate_nonce| {
+                        musig2::sign(
+                            secp,
+                            session.secnonce,
+                            &key,
+                            &path.key_agg,
+                            &aggregate_nonce,
+                            &message,
+                        )
+                    })
+                    .map_err(|_| SignerError::InvalidKey)?;
+                fields.push((
+                    musig2::field_key(
+                        musig2::PSBT_IN_MUSIG2_PARTIAL_SIG,
+                        participant,
+                        aggregate_key,
+                        path.leaf_hash,
+                    ),
+                    partial_sig.to_vec(),
+                ));
+            }
+        }
+    }
 
-    let page = SigningTxPage::new();
+    psbt.inputs[index].unknown.extend(fields);
+    Ok(())
+}
+
+/// Sign `psbt` and send the new signatures to the host
+///
+/// The inputs are signed one at a time, updating the progress bar and the checkpoint after each
+/// one, so that a transaction with many inputs doesn't leave the device unresponsive.
+///
+/// Inputs with a sighash other than `SIGHASH_ALL` are only signed with `allow_all_sighashes` in
+/// `sign_options`, once the user has been warned about them.
+///
+/// Returns whether the PSBT was signed.
+async fn sign_and_reply(
+    wallet: &PortalWallet,
+    mut psbt: psbt::PartiallySignedTransaction,
+    sign_options: &bdk::SignOptions,
+    reply_options: ReplyOptions,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<bool, Error> {
+    let total = psbt.inputs.len() as u32;
+    let mut page = SigningProgressPage::new(total);
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
 
     let current_sigs = CurrentSignatures::from_psbt(&psbt);
 
-    wallet
-        .sign(
-            &mut psbt,
-            bdk::SignOptions {
-                try_finalize: false,
-                ..Default::default()
-            },
-        )
-        .unwrap();
+    let txid = psbt.unsigned_tx.txid();
+    let mut checkpoint = match peripherals.signing_checkpoint.take() {
+        Some(checkpoint) if checkpoint.txid == txid => {
+            log::debug!("Resuming from input {}", checkpoint.inputs.len());
+            checkpoint
+        }
+        _ => SigningCheckpoint::new(txid),
+    };
+
+    for (input, signed) in psbt.inputs.iter_mut().zip(checkpoint.inputs.iter()) {
+        input.combine(signed.clone());
+    }
+    page.add_confirm(checkpoint.inputs.len() as u32);
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
 
-    let diff = CurrentSignatures::diff(&current_sigs, psbt);
+    // Errors of a single input come with its index
+    let mut result = check_signable(&psbt, sign_options).map_err(|e| (None, e));
+    let mut next_input = checkpoint.inputs.len();
+    while result.is_ok() && next_input < psbt.inputs.len() {
+        let mut aux_rand = [0u8; 32];
+        peripherals.rng.fill_bytes(&mut aux_rand);
+        result = wallet
+            .signers
+            .iter()
+            .try_for_each(|signer| {
+                signer.sign_input(&mut psbt, next_input, sign_options, &wallet.secp_ctx())
+            })
+            .and_then(|_| sign_tap_scripts(wallet, &mut psbt, next_input, &aux_rand))
+            .and_then(|_| {
+                sign_musig2(
+                    wallet,
+                    &mut psbt,
+                    next_input,
+                    &mut checkpoint.musig2,
+                    &mut peripherals.rng,
+                )
+            })
+            .map_err(|e| (Some(next_input), e));
+        if result.is_ok() {
+            checkpoint.push(&psbt.inputs[next_input]);
+            next_input += 1;
 
-    #[rustfmt::skip]
-    let mut empty_psbt = alloc::vec![
-        0x70, 0x73, 0x62, 0x74, 0xFF, // PSBT magic
-            0x01, 0x00, 0x33, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, // Empty raw tx
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
-            0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00 // End global map
-    ];
+            page.add_confirm(1);
+            page.draw_to(&mut peripherals.display)?;
+            peripherals.display.flush()?;
+            report_progress(peripherals, next_input as u32, total);
+        }
+    }
 
-    use bdk::bitcoin::consensus::encode::Encodable;
-    for input in &diff {
-        input
-            .consensus_encode(&mut empty_psbt)
-            .expect("Encoding succeeds");
+    if let Err((input, e)) = result {
+        // Tell the host what failed, so that it can fix the PSBT
+        let detail = match input {
+            Some(i) => alloc::format!("Input #{}: {:?}", i + 1, e),
+            None => alloc::format!("{:?}", e),
+        };
+        log::warn!("Unable to sign: {}", detail);
+
+        peripherals
+            .nfc
+            .send(Reply::error_with_detail(ErrorCode::SigningFailed, detail))
+            .await
+            .unwrap();
+        show_invalid_transaction(events, peripherals).await?;
+        return Ok(false);
     }
+    peripherals.signing_checkpoint = Some(checkpoint);
+
+    let reply = signed_reply(wallet, &current_sigs, psbt, sign_options, reply_options);
+
+    peripherals.nfc.send(reply).await.unwrap();
+
+    peripherals.nfc_finished.recv().await.unwrap();
+    peripherals.signing_checkpoint = peripherals
+        .signing_checkpoint
+        .take()
+        .and_then(SigningCheckpoint::into_pending);
+
+    Ok(true)
+}
+
+/// Key of the wallet that signs an input with ECDSA, and the message it signs
+struct EcdsaSigningKey {
+    input: usize,
+    pubkey: PublicKey,
+    key: secp256k1::SecretKey,
+    message: secp256k1::Message,
+    sighash_type: EcdsaSighashType,
+}
+
+/// Find the keys of the wallet that sign each input, from the key origins in the PSBT
+///
+/// Only legacy and segwit v0 inputs are supported, and only keys of the wallet descriptor (see
+/// `descriptor_key`). Returns `None` if a message can't be computed.
+fn ecdsa_signing_keys(
+    wallet: &PortalWallet,
+    psbt: &psbt::PartiallySignedTransaction,
+) -> Option<Vec<EcdsaSigningKey>> {
+    let secp = wallet.secp_ctx();
+    let utxos = model::psbt::prev_utxos(psbt, true).ok()?;
+    let mut cache = SighashCache::new(&psbt.unsigned_tx);
+
+    let mut keys = Vec::new();
+    for (index, (input, utxo)) in psbt.inputs.iter().zip(utxos).enumerate() {
+        let sighash_type = input.ecdsa_hash_ty().ok()?;
+        for (pubkey, key_source) in &input.bip32_derivation {
+            let key = match descriptor_key(wallet, key_source) {
+                Some(key) => key,
+                None => continue,
+            };
+            if key.public_key(secp) != *pubkey {
+                continue;
+            }
+
+            let pubkey = PublicKey::new(*pubkey);
+            let message =
+                model::psbt::ecdsa_sighash(&mut cache, index, utxo, input, &pubkey, sighash_type)?;
+            keys.push(EcdsaSigningKey {
+                input: index,
+                pubkey,
+                key,
+                message,
+                sighash_type,
+            });
+        }
+    }
+
+    Some(keys)
+}
+
+/// Reply to `SignPsbt` once `psbt` is signed, as asked by the host
+///
+/// By default only the signatures added since `current_sigs` are sent, which the host merges into
+/// its own copy. A transaction is only sent if all of its inputs can be finalized, otherwise the
+/// host gets the signatures as usual.
+fn signed_reply(
+    wallet: &PortalWallet,
+    current_sigs: &Vec<CurrentSignatures>,
+    psbt: psbt::PartiallySignedTransaction,
+    sign_options: &bdk::SignOptions,
+    reply_options: ReplyOptions,
+) -> Reply {
+    if reply_options.finalize {
+        // Finalizing clears the signatures of the inputs it completes, so it's done on a copy
+        let mut finalized = psbt.clone();
+        match wallet.finalize_psbt(&mut finalized, sign_options.clone()) {
+            Ok(true) => {
+                let tx = bdk::bitcoin::consensus::encode::serialize(&finalized.extract_tx());
+                return Reply::SignedTransaction(tx.into());
+            }
+            Ok(false) => log::debug!("Some inputs can't be finalized yet"),
+            Err(e) => log::warn!("Unable to finalize: {:?}", e),
+        }
+    }
+
+    let signed = if reply_options.full_psbt {
+        bdk::bitcoin::consensus::encode::serialize(&psbt)
+    } else {
+        model::sig_diff::encode(&CurrentSignatures::diff(current_sigs, psbt))
+    };
+    Reply::SignedPsbt(signed.into())
+}
+
+/// Like `sign_and_reply`, with the nonces tweaked by the host, see `model::anti_exfil`
+///
+/// The nonces are committed to with `Reply::SignerCommitments` first, and the PSBT is only signed
+/// once the host reveals the data matching `host_commitment`.
+async fn sign_anti_exfil_and_reply(
+    wallet: &PortalWallet,
+    mut psbt: psbt::PartiallySignedTransaction,
+    host_commitment: [u8; 32],
+    sign_options: &bdk::SignOptions,
+    reply_options: ReplyOptions,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<bool, Error> {
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let keys = match check_signable(&psbt, sign_options) {
+        Ok(()) => ecdsa_signing_keys(wallet, &psbt)
+            .ok_or_else(|| alloc::string::String::from("Unable to compute a sighash")),
+        Err(e) => Err(alloc::format!("{:?}", e)),
+    };
+    let keys = match keys {
+        Ok(keys) => keys,
+        Err(detail) => {
+            log::warn!("Unable to sign: {}", detail);
+
+            peripherals
+                .nfc
+                .send(Reply::error_with_detail(ErrorCode::SigningFailed, detail))
+                .await
+                .unwrap();
+            show_invalid_transaction(events, peripherals).await?;
+            return Ok(false);
+        }
+    };
+
+    let secp = wallet.secp_ctx();
+    let commitments = keys
+        .iter()
+        .map(|k| model::SignerCommitment {
+            input: k.input as u32,
+            pubkey: Box::new(k.pubkey.inner.serialize().into()),
+            nonce: Box::new(
+                model::anti_exfil::signer_commitment(secp, &k.key, &k.message, &host_commitment)
+                    .serialize()
+                    .into(),
+            ),
+        })
+        .collect();
+    peripherals
+        .nfc
+        .send(Reply::SignerCommitments(commitments))
+        .await
+        .unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+
+    let host_data = {
+        let requests = only_requests(&mut events);
+        pin_mut!(requests);
+        match requests.next().await {
+            Some(model::Request::AntiExfilHostData(host_data)) => **host_data,
+            _ => {
+                peripherals
+                    .nfc
+                    .send(Reply::UnexpectedMessage)
+                    .await
+                    .unwrap();
+                peripherals.nfc_finished.recv().await.unwrap();
+
+                return Err(Error::BrokenProtocol);
+            }
+        }
+    };
+    if model::anti_exfil::host_commitment(&host_data) != host_commitment {
+        peripherals
+            .nfc
+            .send(Reply::error_with_detail(
+                ErrorCode::SigningFailed,
+                "The host data doesn't match its commitment",
+            ))
+            .await
+            .unwrap();
+        show_invalid_transaction(events, peripherals).await?;
+        return Ok(false);
+    }
+
+    let current_sigs = CurrentSignatures::from_psbt(&psbt);
+    for k in keys {
+        let sig = model::anti_exfil::sign(secp, &k.key, &k.message, &host_data);
+        psbt.inputs[k.input].partial_sigs.insert(
+            k.pubkey,
+            bdk::bitcoin::EcdsaSig {
+                sig,
+                hash_ty: k.sighash_type,
+            },
+        );
+    }
+
+    let reply = signed_reply(wallet, &current_sigs, psbt, sign_options, reply_options);
+
+    peripherals.nfc.send(reply).await.unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+
+    Ok(true)
+}
+
+pub async fn handle_sign_request(
+    wallet: &mut Rc<PortalWallet>,
+    psbt: model::psbt_stream::PsbtStream,
+    kind: PsbtKind,
+    reply_options: ReplyOptions,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_sign_request");
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    // Only sent with `BeginSignPsbt`
+    let cpfp = peripherals
+        .cpfp
+        .take()
+        .filter(|_| matches!(kind, PsbtKind::Payment));
+
+    let checks_result = (|| {
+        let (psbt, utxos_checked) = psbt.finish()?;
+        if let Some(cpfp) = &cpfp {
+            model::psbt::check_cpfp_parent(&psbt, &cpfp.parent_txid())?;
+        }
+        let allow_witness_utxo =
+            utxos_checked || allows_witness_utxo(wallet, &peripherals.settings);
+        let fees = model::psbt::fees(&psbt, allow_witness_utxo)?;
+        let destinations = model::psbt::output_destinations(&psbt, wallet.network())?;
+        let (our_inputs, our_outputs) = ours(wallet, &psbt, allow_witness_utxo)?;
+        let fee_rate = estimate_fee_rate(wallet, &psbt, fees, &our_inputs, allow_witness_utxo)?;
+
+        let sighashes = model::psbt::non_default_sighashes(&psbt, &our_inputs);
+        if !sighashes.is_empty()
+            && peripherals.settings.non_default_sighash == NonDefaultSighash::Reject
+        {
+            return Err(model::psbt::PsbtError::NonDefaultSighash);
+        }
+
+        // With inputs from other participants most outputs aren't ours, so only the net flow is
+        // shown
+        let flow = if model::psbt::is_collaborative(&our_inputs) {
+            Some(model::psbt::net_flow(
+                &psbt,
+                allow_witness_utxo,
+                &our_inputs,
+                &our_outputs,
+            )?)
+        } else {
+            None
+        };
+        // A payment entirely funded by the wallet can be turned into a payjoin by the receiver, or
+        // replaced with higher fees
+        let checkpoint = if our_inputs.iter().all(|ours| *ours) {
+            Some(model::psbt::PaymentCheckpoint::new(
+                &psbt,
+                allow_witness_utxo,
+                &our_outputs,
+            )?)
+        } else {
+            None
+        };
+
+        let bump = peripherals
+            .last_payment
+            .as_ref()
+            .and_then(|last| model::psbt::fee_bump(last, &psbt, fees, &our_outputs));
+        let utxos = model::psbt::prev_utxos(&psbt, allow_witness_utxo)?;
+        let reused = model::psbt::reused_addresses(&psbt, &utxos);
+        let inputs = utxos
+            .into_iter()
+            .map(|utxo| utxo.value)
+            .zip(our_inputs)
+            .collect::<Vec<_>>();
+
+        Ok::<_, model::psbt::PsbtError>((
+            psbt,
+            utxos_checked,
+            fees,
+            fee_rate,
+            destinations,
+            flow,
+            checkpoint,
+            bump,
+            inputs,
+            reused,
+            sighashes,
+        ))
+    })();
+
+    let (
+        psbt,
+        utxos_checked,
+        fees,
+        fee_rate,
+        destinations,
+        flow,
+        checkpoint,
+        bump,
+        inputs,
+        reused,
+        sighashes,
+    ) = match checks_result {
+        Ok(v) => v,
+        Err(e) => {
+            reply_invalid_psbt(e, &mut events, peripherals).await?;
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
+
+    // Only sent with `BeginSignPsbt`, and only useful when the outputs are shown one by one
+    let fiat_rate = peripherals
+        .fiat_rate
+        .take()
+        .filter(|_| matches!(kind, PsbtKind::Payment) && flow.is_none());
+
+    // Transactions of a batch are all reviewed in full, and fee bumps aren't signed with
+    // anti-exfil
+    if let Some(bump) = bump.filter(|_| matches!(kind, PsbtKind::Payment)) {
+        return sign_fee_bump(
+            wallet,
+            psbt,
+            utxos_checked,
+            bump,
+            checkpoint,
+            reply_options,
+            events,
+            peripherals,
+        )
+        .await;
+    }
+
+    let review_inputs = peripherals.settings.review_inputs == ReviewInputs::On;
+    let foreign_count = inputs.iter().filter(|(_, ours)| !ours).count();
+    let our_inputs = inputs.iter().map(|(_, ours)| *ours).collect::<Vec<_>>();
+    let foreign_inputs = model::psbt::foreign_inputs(&our_inputs);
+    // Outputs with a script tree can be spent through more than one path, e.g. a recovery key
+    // after a timelock
+    let paths = spending_paths(wallet, &psbt, &our_inputs);
+    // Context for multisig ceremonies, where the PSBT goes from one cosigner to the next
+    let cosigners = multisig_cosigners(wallet).map(|(fingerprints, threshold)| {
+        model::psbt::cosigner_signatures(
+            &psbt,
+            &our_inputs,
+            &fingerprints,
+            wallet.xprv.fingerprint(wallet.secp_ctx()),
+            threshold,
+        )
+    });
+
+    // Number of outputs that aren't change and their total value, so that an obviously wrong
+    // transaction can be refused before going through all of them. The net flow is already a
+    // summary.
+    let overview = flow.is_none().then(|| {
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .zip(psbt.outputs.iter())
+            .filter(|(_, psbt_out)| !is_change(wallet, psbt_out))
+            .fold((0usize, 0u64), |(count, value), (out, _)| {
+                (count + 1, value.saturating_add(out.value))
+            })
+    });
+    // Outputs paying to the address book, which share a single page instead of one each
+    let trusted = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(psbt.outputs.iter())
+        .map(|(out, psbt_out)| {
+            flow.is_none()
+                && !is_change(wallet, psbt_out)
+                && model::address_book::is_trusted(
+                    wallet.config.trusted_addresses(),
+                    &out.script_pubkey,
+                )
+        })
+        .collect::<Vec<_>>();
+    let (trusted_count, trusted_value) = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(trusted.iter())
+        .filter(|(_, trusted)| **trusted)
+        .fold((0usize, 0u64), |(count, value), (out, _)| {
+            (count + 1, value.saturating_add(out.value))
+        });
+
+    // One step for parsing, one for the name of the wallet if registered, one for the overview if
+    // shown, one for the parent of a CPFP if sent by the host, one for the signatures of the
+    // cosigners of a multisig, one per input if they are reviewed, one for the warning about
+    // foreign inputs if needed, one for the warning about reused addresses if needed, one for the
+    // exchange rate if sent by the host, one per output (or for the whole net flow) with a single
+    // one for all the trusted outputs, one per path of a script tree, one for the locktime, one for
+    // the warning about high fees if needed, one per input with a non-default sighash and a final
+    // one for the fees, after which we sign
+    let input_steps = if review_inputs {
+        inputs.len() as u32
+    } else {
+        0
+    };
+    let review_steps = match flow {
+        Some(_) => 1,
+        None => (psbt.unsigned_tx.output.len() - trusted_count) as u32 + (trusted_count > 0) as u32,
+    };
+    let warning = fee_warning(&peripherals.settings, &psbt, fees, fee_rate);
+    let policy_name = wallet
+        .config
+        .wallet_policy()
+        .map(|policy| policy.name.as_str());
+    let total_steps = policy_name.is_some() as u32
+        + overview.is_some() as u32
+        + cpfp.is_some() as u32
+        + cosigners.is_some() as u32
+        + input_steps
+        + foreign_inputs.is_some() as u32
+        + reused.is_some() as u32
+        + fiat_rate.is_some() as u32
+        + review_steps
+        + paths.len() as u32
+        + warning.is_some() as u32
+        + sighashes.len() as u32
+        + 3;
+    let mut current_step = 1;
+    report_progress(peripherals, current_step, total_steps);
+
+    peripherals.tsc_enabled.enable();
+
+    if let Some(name) = policy_name {
+        current_step += 1;
+
+        confirm_page(Label::Wallet.get(), name, &mut events, peripherals).await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    if let Some((recipients, value)) = overview {
+        current_step += 1;
+
+        let title = alloc::format!(
+            "{} {} {}",
+            Label::SendingTo.get(),
+            recipients,
+            if recipients == 1 {
+                Label::Recipient.get()
+            } else {
+                Label::Recipients.get()
+            }
+        );
+        let note = alloc::format!(
+            "{} {}",
+            Label::Fee.get(),
+            amount(peripherals.settings.amount_unit, fees)
+        );
+        confirm_page_with_note(
+            &title,
+            &amount(peripherals.settings.amount_unit, value),
+            &note,
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    // The parent is spent by one of the inputs, but its fees are only known to the host
+    if let Some(cpfp) = &cpfp {
+        current_step += 1;
+
+        let txid = cpfp.parent_txid().to_string();
+        // The rate is the effective one of the parent and the child together
+        confirm_page_with_note(
+            Label::BumpingParentTx.get(),
+            &alloc::format!("{:.1} sat/vB", cpfp.fee_rate),
+            &alloc::format!("{}..{}", &txid[..8], &txid[txid.len() - 8..]),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    if let Some(signatures) = cosigners {
+        current_step += 1;
+
+        let mut signers: Vec<_> = signatures.listed.iter().map(|fp| fp.to_string()).collect();
+        if signatures.more > 0 {
+            signers.push(alloc::format!("+{} {}", signatures.more, Label::More.get()));
+        }
+        signers.push(if signatures.local {
+            Label::ThisDeviceSigned.get().into()
+        } else {
+            Label::ThisDevicePending.get().into()
+        });
+        confirm_page_with_note(
+            Label::MultisigSignatures.get(),
+            &alloc::format!(
+                "{} {} {}",
+                signatures.count,
+                Label::Of.get(),
+                signatures.threshold
+            ),
+            &signers.join(", "),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    if review_inputs {
+        for (i, (txin, (value, ours))) in psbt.unsigned_tx.input.iter().zip(inputs).enumerate() {
+            current_step += 1;
+
+            let title = alloc::format!(
+                "{} #{} ({})",
+                Label::InputTitle.get(),
+                i + 1,
+                if ours {
+                    Label::Yours.get()
+                } else {
+                    Label::External.get()
+                }
+            );
+            let txid = txin.previous_output.txid.to_string();
+            let outpoint = alloc::format!(
+                "{}..{}:{}",
+                &txid[..8],
+                &txid[txid.len() - 8..],
+                txin.previous_output.vout
+            );
+            confirm_page_with_note(
+                &title,
+                &amount(peripherals.settings.amount_unit, value),
+                &outpoint,
+                &mut events,
+                peripherals,
+            )
+            .await?;
+            report_progress(peripherals, current_step, total_steps);
+        }
+    }
+
+    // The device can't account for the value of inputs that aren't ours, so point them out even
+    // if the inputs aren't reviewed one by one
+    if let Some(list) = foreign_inputs {
+        current_step += 1;
+
+        let value = if foreign_count == 1 {
+            Label::ExternalInput.get()
+        } else {
+            Label::ExternalInputs.get()
+        };
+        confirm_page_with_note(
+            Label::Warning.get(),
+            value,
+            &list.format(Label::More.get()),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    // Outputs paying back to an address being spent, which the user may not expect
+    if let Some(list) = reused {
+        current_step += 1;
+
+        confirm_page_with_note(
+            Label::Warning.get(),
+            Label::AddressReuse.get(),
+            &list.format(Label::More.get()),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    // The rate can't be checked by the device, so make it clear where it comes from
+    if let Some(rate) = &fiat_rate {
+        current_step += 1;
+
+        confirm_page_with_note(
+            Label::FiatValues.get(),
+            &alloc::format!("{:.2} {}", rate.rate, rate.currency),
+            Label::PerBtcFromHost.get(),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    if let Some(flow) = flow {
+        confirm_net_flow(flow, &mut events, peripherals).await?;
+        current_step += 1;
+        report_progress(peripherals, current_step, total_steps);
+    } else {
+        if trusted_count > 0 {
+            current_step += 1;
+
+            let note = alloc::format!(
+                "{} {}",
+                trusted_count,
+                if trusted_count == 1 {
+                    Label::Output.get()
+                } else {
+                    Label::Outputs.get()
+                }
+            );
+            confirm_page_with_note(
+                Label::TrustedAddresses.get(),
+                &amount(peripherals.settings.amount_unit, trusted_value),
+                &note,
+                &mut events,
+                peripherals,
+            )
+            .await?;
+            report_progress(peripherals, current_step, total_steps);
+        }
+
+        for (((out, psbt_out), destination), trusted) in psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .zip(psbt.outputs.iter())
+            .zip(destinations.iter())
+            .zip(trusted.iter())
+        {
+            if *trusted {
+                continue;
+            }
+            current_step += 1;
+
+            if is_change(wallet, psbt_out) {
+                // Our change outputs are hidden, unless the settings ask to show them
+                if let (ChangeOutputs::Show, model::psbt::OutputDestination::Address(address)) =
+                    (peripherals.settings.change_outputs, destination)
+                {
+                    let fingerprint = wallet.xprv.fingerprint(wallet.secp_ctx());
+                    let title = match model::psbt::output_derivation_index(psbt_out, fingerprint) {
+                        Some(index) => alloc::format!("{} #{}", Label::Change.get(), index),
+                        None => Label::Change.get().into(),
+                    };
+                    confirm_address(
+                        &address.to_string(),
+                        &title,
+                        Label::HoldForNextPage.get(),
+                        &mut events,
+                        peripherals,
+                    )
+                    .await?;
+                    report_progress(peripherals, current_step, total_steps);
+                }
+                continue;
+            }
+
+            // Only a hint from the host, the address and the amount are still shown after it
+            if let Some(label) = model::psbt::output_label(psbt_out) {
+                confirm_page(Label::OutputLabel.get(), label, &mut events, peripherals).await?;
+            }
+
+            let address = match destination {
+                model::psbt::OutputDestination::Address(address) => address,
+                model::psbt::OutputDestination::OpReturn(data) => {
+                    confirm_op_return(data, out.value, &mut events, peripherals).await?;
+                    report_progress(peripherals, current_step, total_steps);
+                    continue;
+                }
+            };
+
+            let output_type = model::psbt::OutputType::from_address(address);
+            if output_type.needs_warning(out.value) {
+                confirm_page_with_note(
+                    Label::Warning.get(),
+                    Label::LargeAmount.get(),
+                    &alloc::format!("{} {}", Label::AddressType.get(), output_type.name()),
+                    &mut events,
+                    peripherals,
+                )
+                .await?;
+            }
+
+            let value = Amount::from_sat(out.value);
+            let fiat = fiat_rate.as_ref().map(|rate| rate.format(value));
+
+            if peripherals.settings.text_size == TextSize::Large {
+                let title = alloc::format!("{} ({})", Label::Address.get(), output_type.name());
+                let address = address.to_string();
+                confirm_large_text(
+                    &title,
+                    &address,
+                    Label::HoldForAmount.get(),
+                    50,
+                    &mut events,
+                    peripherals,
+                )
+                .await?;
+                let mut value = peripherals.settings.amount_unit.format(value);
+                if let Some(fiat) = &fiat {
+                    value = alloc::format!("{}\n{}", value, fiat);
+                }
+                confirm_large_text(
+                    Label::Amount.get(),
+                    &value,
+                    Label::HoldToContinue.get(),
+                    50,
+                    &mut events,
+                    peripherals,
+                )
+                .await?;
+            } else {
+                let mut page = TxOutputPage::new(
+                    address,
+                    value,
+                    peripherals.settings.amount_unit,
+                    fiat.as_deref(),
+                );
+                page.init_display(&mut peripherals.display)?;
+                page.draw_to(&mut peripherals.display)?;
+                peripherals.display.flush()?;
+
+                manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+            }
+            report_progress(peripherals, current_step, total_steps);
+        }
+    }
+
+    for path in &paths {
+        current_step += 1;
+
+        confirm_spending_path(path, &mut events, peripherals).await?;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    confirm_timelock(
+        model::psbt::TxTimelock::new(&psbt),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    current_step += 1;
+    report_progress(peripherals, current_step, total_steps);
+
+    // Needs its own confirmation, so that holding the button through the outputs doesn't also
+    // accept the fees
+    if let Some((warning, note)) = warning {
+        confirm_page_with_note(
+            Label::Warning.get(),
+            warning,
+            &note,
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        current_step += 1;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    // The signatures of these inputs don't commit to the whole transaction, which can be changed
+    // after signing
+    for (i, sighash) in &sighashes {
+        confirm_page_with_note(
+            Label::Warning.get(),
+            &model::psbt::sighash_name(*sighash),
+            &alloc::format!("{} #{}", Label::SighashOfInput.get(), i + 1),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        current_step += 1;
+        report_progress(peripherals, current_step, total_steps);
+    }
+
+    confirm_fees(fees, fee_rate, &mut events, peripherals).await?;
+    report_progress(peripherals, total_steps, total_steps);
+
+    let sign_options = sign_options(!sighashes.is_empty(), utxos_checked);
+    let signed = match kind {
+        PsbtKind::AntiExfil(host_commitment) => {
+            sign_anti_exfil_and_reply(
+                wallet,
+                psbt,
+                host_commitment,
+                &sign_options,
+                reply_options,
+                &mut events,
+                peripherals,
+            )
+            .await?
+        }
+        _ => {
+            sign_and_reply(
+                wallet,
+                psbt,
+                &sign_options,
+                reply_options,
+                &mut events,
+                peripherals,
+            )
+            .await?
+        }
+    };
+    if !signed {
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+    peripherals.last_payment = checkpoint;
+
+    match kind {
+        PsbtKind::Batch(batch) => next_in_batch(wallet, batch.add(fees), events, peripherals).await,
+        _ => Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        }),
+    }
+}
+
+/// Sign a transaction that only raises the fees of the last payment signed
+///
+/// The inputs and the payments are the same that the user just reviewed, so only the old and the
+/// new fees are shown.
+async fn sign_fee_bump(
+    wallet: &mut Rc<PortalWallet>,
+    psbt: psbt::PartiallySignedTransaction,
+    utxos_checked: bool,
+    bump: model::psbt::FeeBump,
+    checkpoint: Option<model::psbt::PaymentCheckpoint>,
+    reply_options: ReplyOptions,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("Fee bump from {} to {} sats", bump.old_fees, bump.new_fees);
+
+    report_progress(peripherals, 1, 2);
+
+    peripherals.tsc_enabled.enable();
+
+    let new_fees = amount(peripherals.settings.amount_unit, bump.new_fees);
+    let old_fees = alloc::format!(
+        "{} {}",
+        Label::Was.get(),
+        amount(peripherals.settings.amount_unit, bump.old_fees)
+    );
+    let mut page = GenericThreeLinePage::new(
+        Label::FeeBump.get(),
+        &new_fees,
+        &old_fees,
+        Label::HoldToSignTx.get(),
+        80,
+    );
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    report_progress(peripherals, 2, 2);
+
+    let sign_options = sign_options(false, utxos_checked);
+    if sign_and_reply(
+        wallet,
+        psbt,
+        &sign_options,
+        reply_options,
+        &mut events,
+        peripherals,
+    )
+    .await?
+    {
+        // Further bumps are compared with this transaction
+        peripherals.last_payment = checkpoint;
+    }
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+/// Sign a payjoin proposal (BIP-78) built on the last payment signed
+///
+/// The payment itself was already reviewed, so only what the receiver changed is shown: the
+/// inputs it added, the change that goes back to the wallet, what the wallet pays on top of the
+/// original payment and the new fees.
+pub async fn handle_sign_payjoin(
+    wallet: &mut Rc<PortalWallet>,
+    psbt: model::psbt_stream::PsbtStream,
+    reply_options: ReplyOptions,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_sign_payjoin");
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    let checks_result = (|| {
+        let checkpoint = peripherals
+            .last_payment
+            .as_ref()
+            .ok_or(model::psbt::PsbtError::PayjoinMismatch)?;
+        let (psbt, utxos_checked) = psbt.finish()?;
+        let allow_witness_utxo =
+            utxos_checked || allows_witness_utxo(wallet, &peripherals.settings);
+        let (our_inputs, our_outputs) = ours(wallet, &psbt, allow_witness_utxo)?;
+        // The original payment was reviewed to be signed with `SIGHASH_ALL`
+        if !model::psbt::non_default_sighashes(&psbt, &our_inputs).is_empty() {
+            return Err(model::psbt::PsbtError::NonDefaultSighash);
+        }
+        let delta = model::psbt::payjoin_delta(
+            checkpoint,
+            &psbt,
+            allow_witness_utxo,
+            &our_inputs,
+            &our_outputs,
+        )?;
+        let fee_rate =
+            estimate_fee_rate(wallet, &psbt, delta.fees, &our_inputs, allow_witness_utxo)?;
+
+        Ok::<_, model::psbt::PsbtError>((psbt, utxos_checked, delta, fee_rate))
+    })();
+
+    let (psbt, utxos_checked, delta, fee_rate) = match checks_result {
+        Ok(v) => v,
+        Err(e) => {
+            reply_invalid_psbt(e, &mut events, peripherals).await?;
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
+
+    // One step for parsing, three for the changes, one for the warning about high fees if needed
+    // and a final one for the fees
+    let warning = fee_warning(&peripherals.settings, &psbt, delta.fees, fee_rate);
+    let total_steps = 5 + warning.is_some() as u32;
+    report_progress(peripherals, 1, total_steps);
+
+    peripherals.tsc_enabled.enable();
+
+    confirm_page_with_note(
+        Label::PayjoinInputs.get(),
+        &alloc::format!(
+            "+{} {}",
+            delta.added_inputs,
+            if delta.added_inputs == 1 {
+                Label::Input.get()
+            } else {
+                Label::Inputs.get()
+            }
+        ),
+        Label::FromReceiver.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 2, total_steps);
+    confirm_page(
+        Label::YourChange.get(),
+        &amount(peripherals.settings.amount_unit, delta.after.received),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 3, total_steps);
+    confirm_page(
+        Label::ExtraCost.get(),
+        &signed_amount(peripherals.settings.amount_unit, delta.extra_cost()),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 4, total_steps);
+
+    if let Some((warning, note)) = warning {
+        confirm_page_with_note(
+            Label::Warning.get(),
+            warning,
+            &note,
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, total_steps - 1, total_steps);
+    }
+
+    confirm_fees(delta.fees, fee_rate, &mut events, peripherals).await?;
+    report_progress(peripherals, total_steps, total_steps);
+
+    let sign_options = sign_options(false, utxos_checked);
+    if sign_and_reply(
+        wallet,
+        psbt,
+        &sign_options,
+        reply_options,
+        &mut events,
+        peripherals,
+    )
+    .await?
+    {
+        // The proposal can only be signed once
+        peripherals.last_payment = None;
+    }
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+/// Sign a payjoin proposal (BIP-78) built by the wallet as the receiver
+///
+/// Instead of the outputs, the user sees what the wallet receives once the inputs it adds are
+/// spent, the value of those inputs and how much of the payment goes to the fees they add.
+pub async fn handle_sign_payjoin_receiver(
+    wallet: &mut Rc<PortalWallet>,
+    psbt: model::psbt_stream::PsbtStream,
+    reply_options: ReplyOptions,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_sign_payjoin_receiver");
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    let original = peripherals.payjoin_original.take();
+    let checks_result = (|| {
+        let original = original.ok_or(model::psbt::PsbtError::PayjoinMismatch)?;
+        let (psbt, utxos_checked) = psbt.finish()?;
+        let allow_witness_utxo =
+            utxos_checked || allows_witness_utxo(wallet, &peripherals.settings);
+        let (our_inputs, our_outputs) = ours(wallet, &psbt, allow_witness_utxo)?;
+        // The inputs added are signed with `SIGHASH_ALL`, like the payer did
+        if !model::psbt::non_default_sighashes(&psbt, &our_inputs).is_empty() {
+            return Err(model::psbt::PsbtError::NonDefaultSighash);
+        }
+        let receipt = model::psbt::payjoin_receipt(
+            &original,
+            &psbt,
+            allow_witness_utxo,
+            &our_inputs,
+            &our_outputs,
+        )?;
+        let fee_rate =
+            estimate_fee_rate(wallet, &psbt, receipt.fees, &our_inputs, allow_witness_utxo)?;
+
+        Ok::<_, model::psbt::PsbtError>((psbt, utxos_checked, receipt, fee_rate))
+    })();
+
+    let (psbt, utxos_checked, receipt, fee_rate) = match checks_result {
+        Ok(v) => v,
+        Err(e) => {
+            reply_invalid_psbt(e, &mut events, peripherals).await?;
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
+
+    // One step for parsing, three for the summary, one for the warning about high fees if needed
+    // and a final one for the fees
+    let warning = fee_warning(&peripherals.settings, &psbt, receipt.fees, fee_rate);
+    let total_steps = 5 + warning.is_some() as u32;
+    report_progress(peripherals, 1, total_steps);
+
+    peripherals.tsc_enabled.enable();
+
+    confirm_page(
+        Label::PayjoinReceiving.get(),
+        &amount(peripherals.settings.amount_unit, receipt.flow.net() as u64),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 2, total_steps);
+    confirm_page_with_note(
+        Label::Contributing.get(),
+        &amount(peripherals.settings.amount_unit, receipt.flow.sent),
+        &alloc::format!(
+            "{} {}",
+            receipt.contributed_inputs,
+            if receipt.contributed_inputs == 1 {
+                Label::Input.get()
+            } else {
+                Label::Inputs.get()
+            }
+        ),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 3, total_steps);
+    confirm_page(
+        Label::YourFeeShare.get(),
+        &signed_amount(peripherals.settings.amount_unit, receipt.fee_share()),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 4, total_steps);
+
+    if let Some((warning, note)) = warning {
+        confirm_page_with_note(
+            Label::Warning.get(),
+            warning,
+            &note,
+            &mut events,
+            peripherals,
+        )
+        .await?;
+        report_progress(peripherals, total_steps - 1, total_steps);
+    }
+
+    confirm_fees(receipt.fees, fee_rate, &mut events, peripherals).await?;
+    report_progress(peripherals, total_steps, total_steps);
+
+    let sign_options = sign_options(false, utxos_checked);
+    sign_and_reply(
+        wallet,
+        psbt,
+        &sign_options,
+        reply_options,
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+/// Sign a proof of reserves (BIP-127), which can't move any funds
+///
+/// Instead of the outputs and the fees, the user sees the message committed to and the total of
+/// the inputs of the wallet whose ownership is proven.
+pub async fn handle_sign_proof_of_reserves(
+    wallet: &mut Rc<PortalWallet>,
+    psbt: model::psbt_stream::PsbtStream,
+    reply_options: ReplyOptions,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_sign_proof_of_reserves");
 
     peripherals
         .nfc
-        .send(model::Reply::SignedPsbt(empty_psbt.into()))
+        .send(model::Reply::DelayedReply)
         .await
         .unwrap();
 
-    peripherals.nfc_finished.recv().await.unwrap();
+    let message = peripherals.reserves_message.take();
+    let checks_result = (|| {
+        let message = message.ok_or(model::psbt::PsbtError::ReservesMismatch)?;
+        let (psbt, _) = psbt.finish()?;
+        let utxos = model::psbt::proof_of_reserves(&psbt, &message)?;
+
+        // The commitment isn't ours, nor anyone's
+        let our_inputs = core::iter::once(false)
+            .chain(
+                utxos
+                    .iter()
+                    .zip(psbt.inputs.iter().skip(1))
+                    .map(|(utxo, psbt_in)| is_our_input(wallet, psbt_in, utxo)),
+            )
+            .collect::<Vec<_>>();
+        // Without `SIGHASH_ALL` the commitment could be removed, and the transaction broadcast
+        if !model::psbt::non_default_sighashes(&psbt, &our_inputs).is_empty() {
+            return Err(model::psbt::PsbtError::NonDefaultSighash);
+        }
+        let (count, total) = utxos
+            .iter()
+            .zip(our_inputs.iter().skip(1))
+            .filter(|(_, ours)| **ours)
+            .try_fold((0usize, 0u64), |(count, total), (utxo, _)| {
+                Some((count + 1, total.checked_add(utxo.value)?))
+            })
+            .ok_or(model::psbt::PsbtError::InvalidAmount)?;
+
+        Ok::<_, model::psbt::PsbtError>((psbt, message, count, total))
+    })();
+
+    let (psbt, message, count, total) = match checks_result {
+        Ok(v) => v,
+        Err(e) => {
+            reply_invalid_psbt(e, &mut events, peripherals).await?;
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
+
+    // One step for parsing, one for the warning, one for the message and a final one for the
+    // reserves
+    report_progress(peripherals, 1, 4);
+
+    peripherals.tsc_enabled.enable();
+
+    confirm_page(
+        Label::ProofOfReserves.get(),
+        Label::CannotMoveFunds.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 2, 4);
+
+    let (title, text) = displayed_message(&message);
+    confirm_address(
+        &text,
+        title,
+        Label::HoldForNextPage.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    report_progress(peripherals, 3, 4);
+
+    let reserves = amount(peripherals.settings.amount_unit, total);
+    let inputs = alloc::format!(
+        "{} {}",
+        count,
+        if count == 1 {
+            Label::Input.get()
+        } else {
+            Label::Inputs.get()
+        }
+    );
+    let mut page = GenericThreeLinePage::new(
+        Label::Reserves.get(),
+        &reserves,
+        &inputs,
+        Label::HoldToSign.get(),
+        80,
+    );
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    report_progress(peripherals, 4, 4);
+
+    // The amounts can't be abused, see `model::psbt::proof_of_reserves`
+    let sign_options = sign_options(false, true);
+    sign_and_reply(
+        wallet,
+        psbt,
+        &sign_options,
+        reply_options,
+        &mut events,
+        peripherals,
+    )
+    .await?;
 
     Ok(CurrentState::Idle {
         wallet: Rc::clone(wallet),
@@ -225,34 +2371,79 @@ pub async fn handle_sign_request(
 
 pub async fn handle_waiting_for_psbt(
     wallet: &mut Rc<PortalWallet>,
+    kind: PsbtKind,
     mut events: impl Stream<Item = Event> + Unpin,
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
-    let page = LoadingPage::new();
-    page.init_display(&mut peripherals.display)?;
-    page.draw_to(&mut peripherals.display)?;
+    if let PsbtKind::Batch(batch) = kind {
+        let progress = alloc::format!("TX {}/{}", batch.signed + 1, batch.count);
+        let page = SingleLineTextPage::new(&progress);
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+    } else {
+        let page = LoadingPage::new();
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+    }
     peripherals.display.flush()?;
 
-    peripherals.nfc.send(model::Reply::Ok).await.unwrap();
-    peripherals.nfc_finished.recv().await.unwrap();
+    // Within a batch the previous `SignPsbt` was already answered with its signatures
+    let first_request = !matches!(kind, PsbtKind::Batch(batch) if batch.signed > 0);
+    if first_request && !peripherals.wallet_policy.allows_signing(wallet) {
+        log::warn!("The registered wallet policy wasn't referenced");
+        peripherals
+            .nfc
+            .send(model::Reply::error(ErrorCode::WalletPolicyMismatch))
+            .await
+            .unwrap();
+        peripherals.nfc_finished.recv().await.unwrap();
+
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+    if first_request {
+        peripherals.nfc.send(model::Reply::Ok).await.unwrap();
+        peripherals.nfc_finished.recv().await.unwrap();
+    }
 
     let events = only_requests(&mut events);
     pin_mut!(events);
 
-    match events.next().await {
-        Some(model::Request::SignPsbt(psbt)) => Ok(CurrentState::SignPsbt {
-            psbt: psbt.into(),
-            wallet: Rc::clone(wallet),
-        }),
-        _ => {
-            peripherals
-                .nfc
-                .send(model::Reply::UnexpectedMessage)
-                .await
-                .unwrap();
-            peripherals.nfc_finished.recv().await.unwrap();
+    // Large PSBTs are received in parts, each one parsed and dropped before the next
+    let mut stream = model::psbt_stream::PsbtStream::new();
+    loop {
+        match events.next().await {
+            Some(model::Request::SignPsbtPart(part)) => {
+                stream.push(&part);
+                drop(part);
 
-            Err(Error::BrokenProtocol)
+                peripherals.nfc.send(model::Reply::Ok).await.unwrap();
+                peripherals.nfc_finished.recv().await.unwrap();
+            }
+            Some(model::Request::SignPsbt(psbt, full_psbt, finalize)) => {
+                stream.push(&psbt);
+
+                break Ok(CurrentState::SignPsbt {
+                    psbt: stream,
+                    wallet: Rc::clone(wallet),
+                    kind,
+                    reply_options: ReplyOptions {
+                        full_psbt: full_psbt.unwrap_or(false),
+                        finalize: finalize.unwrap_or(false),
+                    },
+                });
+            }
+            _ => {
+                peripherals
+                    .nfc
+                    .send(model::Reply::UnexpectedMessage)
+                    .await
+                    .unwrap();
+                peripherals.nfc_finished.recv().await.unwrap();
+
+                break Err(Error::BrokenProtocol);
+            }
         }
     }
 }
@@ -274,27 +2465,51 @@ pub async fn handle_display_address_request(
     peripherals.tsc_enabled.enable();
 
     let s = alloc::format!("Display\nAddress #{}?", index);
-    let mut page = SummaryPage::new_with_threshold(&s, "HOLD BTN TO CONTINUE", 50);
+    let mut page = SummaryPage::new_with_threshold(&s, Label::HoldToContinue.get(), 50);
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
     manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
 
-    let addr = Rc::get_mut(wallet)
+    let address = Rc::get_mut(wallet)
         .unwrap()
         .get_address(bdk::wallet::AddressIndex::Peek(index));
-    let addr = addr.to_string();
+    let addr = address.to_string();
+
+    let descriptor = &wallet.config.secret.descriptor;
+    let local_path = match &descriptor.variant {
+        DescriptorVariant::SingleSig(path) => Some(path),
+        variant => variant.keys().into_iter().find_map(|key| match key {
+            MultisigKey::Local(path) => Some(path),
+            MultisigKey::External(_) => None,
+        }),
+    };
+    let info = local_path.map(|path| {
+        let mut path = path.clone();
+        path.value.extend([0, index]);
+
+        model::AddressInfo {
+            keychain: model::Keychain::External,
+            index,
+            path,
+            script_type: descriptor.script_type.clone(),
+            script_pubkey: address.script_pubkey().to_bytes().into(),
+        }
+    });
 
     let message = alloc::format!("Address #{}", index);
-    let mut page = ShowScrollingAddressPage::new(&addr, &message, "HOLD BTN TO EXIT");
-    page.init_display(&mut peripherals.display)?;
-    page.draw_to(&mut peripherals.display)?;
-    peripherals.display.flush()?;
-    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    confirm_address(
+        &addr,
+        &message,
+        Label::HoldToExit.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
 
     peripherals
         .nfc
-        .send(model::Reply::Address(addr))
+        .send(model::Reply::Address(addr, info))
         .await
         .unwrap();
 
@@ -303,6 +2518,11 @@ pub async fn handle_display_address_request(
     })
 }
 
+/// Export the public descriptors for a watch-only wallet
+///
+/// Their checksums are shown on the device before sending them, so that the user can compare them
+/// with the ones in the watch-only wallet, unless the confirmation policy allows exporting them
+/// silently.
 pub async fn handle_public_descriptor_request(
     wallet: &mut Rc<PortalWallet>,
     mut events: impl Stream<Item = Event> + Unpin,
@@ -316,15 +2536,6 @@ pub async fn handle_public_descriptor_request(
         .await
         .unwrap();
 
-    peripherals.tsc_enabled.enable();
-
-    let mut page = SummaryPage::new("Allow watch\nonly access?", "HOLD BTN TO EXPORT DESC");
-    page.init_display(&mut peripherals.display)?;
-    page.draw_to(&mut peripherals.display)?;
-    peripherals.display.flush()?;
-
-    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
-
     let descriptor = wallet
         .public_descriptor(bdk::KeychainKind::External)
         .unwrap();
@@ -334,12 +2545,44 @@ pub async fn handle_public_descriptor_request(
         .public_descriptor(bdk::KeychainKind::Internal)
         .unwrap();
     let internal_descriptor = internal_descriptor.to_string();
+    let multipath = model::multipath::combine(&descriptor, &internal_descriptor);
+
+    let silent = wallet.config.confirmation_policy().silent_public_descriptor
+        && peripherals.host.is_trusted();
+    if !silent {
+        peripherals.tsc_enabled.enable();
+
+        let mut page = SummaryPage::new(Label::AllowWatchOnly.get(), Label::HoldToExportDesc.get());
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+        // The checksum is appended after the `#`: showing it lets the user check that the
+        // descriptors imported in the watch-only wallet weren't changed by the host
+        let checksum = |descriptor: &str| {
+            descriptor
+                .rsplit_once('#')
+                .map(|(_, checksum)| checksum.to_string())
+                .unwrap_or_default()
+        };
+        confirm_page_with_note(
+            Label::DescriptorChecksum.get(),
+            &alloc::format!("{} {}", Label::Receive.get(), checksum(&descriptor)),
+            &alloc::format!("{} {}", Label::Change.get(), checksum(&internal_descriptor)),
+            &mut events,
+            peripherals,
+        )
+        .await?;
+    }
 
     peripherals
         .nfc
         .send(model::Reply::Descriptor {
             external: descriptor,
             internal: Some(internal_descriptor),
+            multipath,
         })
         .await
         .unwrap();
@@ -349,13 +2592,92 @@ pub async fn handle_public_descriptor_request(
     })
 }
 
-pub async fn handle_get_xpub_request(
+/// Longest message shown as it is, longer ones are shown as their SHA256
+const MAX_DISPLAYED_MESSAGE_LEN: usize = 256;
+
+/// Title and text of the page showing a message to sign
+fn displayed_message(message: &str) -> (&'static str, alloc::string::String) {
+    let printable = message
+        .chars()
+        .all(|c| c == '\n' || (' '..='~').contains(&c));
+    if printable && message.len() <= MAX_DISPLAYED_MESSAGE_LEN {
+        (Label::Message.get(), message.replace('\n', " "))
+    } else {
+        use bdk::bitcoin::hashes::{sha256, Hash};
+        (
+            Label::MessageSha256.get(),
+            sha256::Hash::hash(message.as_bytes()).to_string(),
+        )
+    }
+}
+
+/// Format of a message signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// BIP-322 simple format, see `model::bip322`
+    Bip322,
+    /// Bitcoin Core's `signmessage`, see `model::signmessage`
+    Legacy,
+}
+
+impl MessageFormat {
+    fn supports(&self, script_type: &ScriptType) -> bool {
+        match self {
+            MessageFormat::Bip322 => {
+                matches!(script_type, ScriptType::NativeSegwit | ScriptType::Taproot)
+            }
+            MessageFormat::Legacy => {
+                matches!(script_type, ScriptType::Legacy | ScriptType::NativeSegwit)
+            }
+        }
+    }
+
+    fn unsupported_detail(&self) -> &'static str {
+        match self {
+            MessageFormat::Bip322 => {
+                "Only single-sig native segwit and taproot wallets can sign messages"
+            }
+            MessageFormat::Legacy => {
+                "Only single-sig legacy and native segwit wallets can sign legacy messages"
+            }
+        }
+    }
+}
+
+/// Sign a message with the key of an external address
+///
+/// The message is shown before the address, as it is when it's short and printable or as its
+/// SHA256 otherwise. Only single-sig wallets with a script type supported by `format` can sign
+/// messages.
+pub async fn handle_sign_message(
     wallet: &mut Rc<PortalWallet>,
-    derivation_path: bip32::DerivationPath,
+    index: u32,
+    message: alloc::string::String,
+    format: MessageFormat,
     mut events: impl Stream<Item = Event> + Unpin,
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
-    log::info!("handle_get_xpub_request");
+    log::info!("handle_sign_message");
+
+    let descriptor = &wallet.config.secret.descriptor;
+    let path = match &descriptor.variant {
+        DescriptorVariant::SingleSig(path) if format.supports(&descriptor.script_type) => {
+            path.clone()
+        }
+        _ => {
+            peripherals
+                .nfc
+                .send(model::Reply::error_with_detail(
+                    ErrorCode::UnsupportedDescriptor,
+                    format.unsupported_detail(),
+                ))
+                .await
+                .unwrap();
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
 
     peripherals
         .nfc
@@ -365,25 +2687,145 @@ pub async fn handle_get_xpub_request(
 
     peripherals.tsc_enabled.enable();
 
-    let display_path = derivation_path.to_string();
-    let mut page = GenericTwoLinePage::new(
-        "Export public key?",
-        &display_path,
-        "HOLD BTN TO CONFIRM",
-        100,
-    );
+    let s = alloc::format!("{} #{}?", Label::SignMessageWithAddress.get(), index);
+    let mut page = SummaryPage::new_with_threshold(&s, Label::HoldToContinue.get(), 50);
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
     manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
 
+    let (title, text) = displayed_message(&message);
+    confirm_address(
+        &text,
+        title,
+        Label::HoldForNextPage.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    let address = Rc::get_mut(wallet)
+        .unwrap()
+        .get_address(bdk::wallet::AddressIndex::Peek(index));
+    let addr = address.to_string();
+    let title = alloc::format!("{} #{}", Label::Address.get(), index);
+    confirm_address(
+        &addr,
+        &title,
+        Label::HoldToSign.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let mut path = path;
+    path.value.extend([0, index]);
+    let path: bip32::DerivationPath = path.into();
+    let key = wallet
+        .xprv
+        .derive_priv(wallet.secp_ctx(), &path)
+        .map_err(|_| Error::Wallet)?;
+
+    let script_pubkey = address.script_pubkey();
+    let signature = match format {
+        MessageFormat::Bip322 => {
+            let mut aux_rand = [0u8; 32];
+            peripherals.rng.fill_bytes(&mut aux_rand);
+            model::bip322::sign(
+                wallet.secp_ctx(),
+                &script_pubkey,
+                message.as_bytes(),
+                &key.private_key,
+                &aux_rand,
+            )
+            .map(|witness| model::bip322::encode_simple(&witness))
+            .map_err(|e| alloc::format!("{:?}", e))
+        }
+        MessageFormat::Legacy => model::signmessage::sign(
+            wallet.secp_ctx(),
+            &script_pubkey,
+            &message,
+            &key.private_key,
+        )
+        .map(|signature| signature.to_vec())
+        .map_err(|e| alloc::format!("{:?}", e)),
+    };
+    let reply = match signature {
+        Ok(signature) => model::Reply::MessageSignature {
+            address: addr,
+            signature: signature.into(),
+        },
+        Err(e) => model::Reply::error_with_detail(ErrorCode::SigningFailed, e),
+    };
+    peripherals.nfc.send(reply).await.unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+pub async fn handle_get_xpub_request(
+    wallet: &mut Rc<PortalWallet>,
+    derivation_path: bip32::DerivationPath,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_get_xpub_request");
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    // Keys at non-standard paths are always confirmed, even when the policy allows exporting
+    // them silently
+    let is_standard =
+        model::paths::is_standard_path(&derivation_path, wallet.network(), None, false);
+    let silent = is_standard
+        && wallet.config.confirmation_policy().silent_get_xpub
+        && peripherals.host.is_trusted();
+    if !silent {
+        peripherals.tsc_enabled.enable();
+
+        if !is_standard {
+            warn_non_standard_path(&mut events, peripherals).await?;
+        }
+
+        let display_path = derivation_path.to_string();
+        let mut page = GenericTwoLinePage::new(
+            Label::ExportPublicKey.get(),
+            &display_path,
+            Label::HoldToConfirm.get(),
+            100,
+        );
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    }
+
     let derived = wallet
         .xprv
         .derive_priv(wallet.secp_ctx(), &derivation_path)
         .map_err(|_| Error::Wallet)?;
+    let xkey = bip32::ExtendedPubKey::from_priv(wallet.secp_ctx(), &derived);
+    let master_fingerprint = wallet.xprv.fingerprint(wallet.secp_ctx());
+    let structured = model::XpubKey {
+        key: xkey.into(),
+        parent_fingerprint: xkey.parent_fingerprint.into(),
+        master_fingerprint: master_fingerprint.into(),
+        origin_path: derivation_path.clone().into(),
+        network: wallet.network(),
+    };
     let key = DescriptorXKey {
-        origin: Some((wallet.xprv.fingerprint(wallet.secp_ctx()), derivation_path)),
-        xkey: bip32::ExtendedPubKey::from_priv(wallet.secp_ctx(), &derived),
+        origin: Some((master_fingerprint, derivation_path)),
+        xkey,
         derivation_path: Default::default(),
         wildcard: Wildcard::None,
     };
@@ -394,7 +2836,7 @@ pub async fn handle_get_xpub_request(
         "00",
         alloc::format!(
             "Portal {:08X}",
-            u32::from_be_bytes(wallet.xprv.fingerprint(wallet.secp_ctx()).to_bytes())
+            u32::from_be_bytes(master_fingerprint.to_bytes())
         ),
         &xpub,
         &derived.private_key,
@@ -403,7 +2845,11 @@ pub async fn handle_get_xpub_request(
 
     peripherals
         .nfc
-        .send(model::Reply::Xpub { xpub, bsms })
+        .send(model::Reply::Xpub {
+            xpub,
+            bsms,
+            key: Some(structured),
+        })
         .await
         .unwrap();
 
@@ -412,22 +2858,46 @@ pub async fn handle_get_xpub_request(
     })
 }
 
+/// Who holds `key` and its path, for the pages that confirm a new descriptor
+fn describe_key(key: &MultisigKey) -> (alloc::string::String, alloc::string::String) {
+    match key {
+        MultisigKey::Local(path) => (
+            Label::ThisDevice.get().into(),
+            <SerializedDerivationPath as Into<bip32::DerivationPath>>::into(path.clone())
+                .to_string(),
+        ),
+        MultisigKey::External(key) => {
+            let fingerprint = key
+                .origin
+                .as_ref()
+                .map(|(f, _)| f.clone().into())
+                .unwrap_or_else(|| key.key.as_xpub().unwrap().fingerprint());
+            (
+                alloc::format!("{} {}", Label::Key.get(), fingerprint),
+                <SerializedDerivationPath as Into<bip32::DerivationPath>>::into(key.full_path())
+                    .to_string(),
+            )
+        }
+    }
+}
+
 pub async fn handle_set_descriptor_request(
     wallet: &mut Rc<PortalWallet>,
     variant: SetDescriptorVariant,
     script_type: ScriptType,
     bsms: Option<model::BsmsRound2>,
+    name: Option<alloc::string::String>,
     mut events: impl Stream<Item = Event> + Unpin,
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
-    let is_local_key = |key: &ExtendedKey| -> Result<bool, String> {
-        let xpub = key.key.as_xpub().map_err(|_| "Invalid xpub".to_string())?;
+    let is_local_key = |key: &ExtendedKey| -> Result<bool, ErrorCode> {
+        let xpub = key.key.as_xpub().map_err(|_| ErrorCode::InvalidKey)?;
 
         // The network must match
         if (xpub.network == model::bitcoin::Network::Bitcoin)
             != (wallet.network() == model::bitcoin::Network::Bitcoin)
         {
-            return Err("Invalid key network".to_string());
+            return Err(ErrorCode::WrongNetwork);
         }
 
         // The fingerprint should match
@@ -456,7 +2926,7 @@ pub async fn handle_set_descriptor_request(
         let derived = wallet
             .xprv
             .derive_priv(wallet.secp_ctx(), &origin_path)
-            .map_err(|_| "Error deriving key".to_string())?;
+            .map_err(|_| ErrorCode::InvalidKey)?;
         let derived = bip32::ExtendedPubKey::from_priv(wallet.secp_ctx(), &derived);
         Ok(derived.encode() == xpub.encode())
     };
@@ -469,23 +2939,33 @@ pub async fn handle_set_descriptor_request(
         .await
         .unwrap();
 
-    let checks_result = (|| -> Result<_, String> {
+    let checks_result = (|| -> Result<_, Reply> {
+        if name
+            .as_deref()
+            .map_or(false, |name| !model::wallet_policy::is_valid_name(name))
+        {
+            return Err(ErrorCode::InvalidWalletName.into());
+        }
+
         let variant = match variant {
             SetDescriptorVariant::SingleSig(key) if is_local_key(&key)? => {
                 DescriptorVariant::SingleSig(key.full_path().into())
             }
-            SetDescriptorVariant::SingleSig(_) => return Err("Local key missing".to_string()),
+            SetDescriptorVariant::SingleSig(_) => return Err(ErrorCode::LocalKeyMissing.into()),
             SetDescriptorVariant::MultiSig {
                 threshold,
                 keys,
                 is_sorted,
             } => {
-                if !is_sorted {
-                    return Err("Unsorted multisig descriptors are not supported yet".to_string());
+                if let ScriptType::Taproot = script_type {
+                    return Err(Reply::error_with_detail(
+                        ErrorCode::UnsupportedDescriptor,
+                        "Taproot multisig descriptors are not supported yet",
+                    ));
                 }
 
                 if threshold > keys.len() {
-                    return Err("Invalid threshold for multisig".to_string());
+                    return Err(ErrorCode::InvalidThreshold.into());
                 }
 
                 let keys: Vec<MultisigKey> = keys
@@ -497,11 +2977,11 @@ pub async fn handle_set_descriptor_request(
                             Ok(MultisigKey::External(key))
                         }
                     })
-                    .collect::<Result<_, String>>()?;
+                    .collect::<Result<_, ErrorCode>>()?;
 
                 // Make sure our key only appears somewhere
                 if !keys.iter().any(|k| matches!(k, MultisigKey::Local(_))) {
-                    return Err("Local key missing".into());
+                    return Err(ErrorCode::LocalKeyMissing.into());
                 }
 
                 DescriptorVariant::MultiSig {
@@ -510,6 +2990,76 @@ pub async fn handle_set_descriptor_request(
                     is_sorted,
                 }
             }
+            SetDescriptorVariant::TapTree {
+                internal_key,
+                leaves,
+            } => {
+                if !matches!(script_type, ScriptType::Taproot) {
+                    return Err(Reply::error_with_detail(
+                        ErrorCode::UnsupportedDescriptor,
+                        "Script trees are only supported with taproot",
+                    ));
+                }
+                if model::TapLeaf::fold_tree(&leaves, |_| (), |_, _| ()).is_none() {
+                    return Err(Reply::error_with_detail(
+                        ErrorCode::UnsupportedDescriptor,
+                        "Invalid script tree",
+                    ));
+                }
+                if leaves.iter().any(|leaf| {
+                    let threshold = leaf.script.threshold();
+                    threshold == 0 || threshold > leaf.script.keys().len()
+                }) {
+                    return Err(ErrorCode::InvalidThreshold.into());
+                }
+
+                let map_key = |key: ExtendedKey| -> Result<MultisigKey, ErrorCode> {
+                    if is_local_key(&key)? {
+                        Ok(MultisigKey::Local(key.full_path().into()))
+                    } else {
+                        Ok(MultisigKey::External(key))
+                    }
+                };
+                let internal_key = internal_key.map(map_key).transpose()?;
+                let leaves = leaves
+                    .into_iter()
+                    .map(|leaf| {
+                        let script = match leaf.script {
+                            model::TapLeafScript::Key(key) => {
+                                model::TapLeafScript::Key(map_key(key)?)
+                            }
+                            model::TapLeafScript::MultiA { threshold, keys } => {
+                                model::TapLeafScript::MultiA {
+                                    threshold,
+                                    keys: keys
+                                        .into_iter()
+                                        .map(map_key)
+                                        .collect::<Result<_, _>>()?,
+                                }
+                            }
+                        };
+                        Ok(model::TapLeaf {
+                            depth: leaf.depth,
+                            script,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, ErrorCode>>()?;
+
+                let variant = DescriptorVariant::TapTree {
+                    internal_key,
+                    leaves,
+                };
+                // Our key must be in at least one of the spending paths
+                if !variant
+                    .keys()
+                    .iter()
+                    .any(|k| matches!(k, MultisigKey::Local(_)))
+                {
+                    return Err(ErrorCode::LocalKeyMissing.into());
+                }
+
+                variant
+            }
         };
 
         let mut new_config = wallet.config.clone();
@@ -517,17 +3067,25 @@ pub async fn handle_set_descriptor_request(
             variant,
             script_type,
         };
+        // A policy only covers the descriptor it was registered with
+        new_config.secret.wallet_policy = name.map(|name| {
+            model::wallet_policy::WalletPolicy::new(
+                &model::wallet_policy::hmac_key(&wallet.xprv),
+                name,
+                &new_config.secret.descriptor,
+            )
+        });
 
         let mut new_wallet =
             super::init::make_wallet_from_xprv(wallet.xprv, wallet.network(), new_config)
-                .map_err(|_| "Unable to create wallet")?;
+                .map_err(|_| ErrorCode::WalletCreationFailed)?;
         let wallet_address = new_wallet
             .get_address(bdk::wallet::AddressIndex::Peek(0))
             .address;
 
         if let Some(bsms) = bsms {
             if bsms.first_address != wallet_address.to_string() {
-                return Err("BSMS address doesn't match".to_string());
+                return Err(ErrorCode::BsmsAddressMismatch.into());
             }
         }
 
@@ -537,9 +3095,9 @@ pub async fn handle_set_descriptor_request(
     let (new_wallet, first_address) = match checks_result {
         Ok(v) => v,
         Err(e) => {
-            log::warn!("Checks failed: {}", e);
+            log::warn!("Checks failed: {:?}", e);
 
-            peripherals.nfc.send(model::Reply::Error(e)).await.unwrap();
+            peripherals.nfc.send(e).await.unwrap();
             return Ok(CurrentState::Idle {
                 wallet: Rc::clone(wallet),
             });
@@ -548,10 +3106,59 @@ pub async fn handle_set_descriptor_request(
 
     peripherals.tsc_enabled.enable();
 
+    let descriptor = &new_wallet.config.secret.descriptor;
+    let is_standard = |path: &SerializedDerivationPath, multisig| {
+        model::paths::is_standard_path(
+            &path.clone().into(),
+            new_wallet.network(),
+            Some(&descriptor.script_type),
+            multisig,
+        )
+    };
+    let standard_paths = match &descriptor.variant {
+        DescriptorVariant::SingleSig(path) => is_standard(path, false),
+        DescriptorVariant::MultiSig { keys, .. } => keys.iter().all(|key| match key {
+            MultisigKey::Local(path) => is_standard(path, true),
+            MultisigKey::External(_) => true,
+        }),
+        // Only the internal key can be a single-sig key
+        DescriptorVariant::TapTree {
+            internal_key,
+            leaves,
+        } => {
+            internal_key.iter().all(|key| match key {
+                MultisigKey::Local(path) => is_standard(path, false),
+                MultisigKey::External(_) => true,
+            }) && leaves
+                .iter()
+                .flat_map(|leaf| leaf.script.keys())
+                .all(|key| match key {
+                    MultisigKey::Local(path) => is_standard(path, true),
+                    MultisigKey::External(_) => true,
+                })
+        }
+    };
+    if !standard_paths {
+        warn_non_standard_path(&mut events, peripherals).await?;
+    }
+
+    if let Some(policy) = new_wallet.config.wallet_policy() {
+        let mut page = GenericTwoLinePage::new(
+            Label::WalletName.get(),
+            &policy.name,
+            Label::HoldForNextPage.get(),
+            50,
+        );
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    }
+
     let mut page = GenericTwoLinePage::new(
-        "Wallet policy",
+        Label::WalletPolicy.get(),
         new_wallet.config.secret.descriptor.variant.variant_name(),
-        "HOLD BTN FOR NEXT PAGE",
+        Label::HoldForNextPage.get(),
         50,
     );
     page.init_display(&mut peripherals.display)?;
@@ -560,14 +3167,14 @@ pub async fn handle_set_descriptor_request(
     manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
 
     let mut page = GenericTwoLinePage::new(
-        "Address type",
+        Label::AddressType.get(),
         new_wallet
             .config
             .secret
             .descriptor
             .script_type
             .display_name(),
-        "HOLD BTN FOR NEXT PAGE",
+        Label::HoldForNextPage.get(),
         50,
     );
     page.init_display(&mut peripherals.display)?;
@@ -581,9 +3188,9 @@ pub async fn handle_set_descriptor_request(
                 <SerializedDerivationPath as Into<bip32::DerivationPath>>::into(path.clone())
                     .to_string();
             let mut page = GenericTwoLinePage::new(
-                "Key derivation",
+                Label::KeyDerivation.get(),
                 &path_display,
-                "HOLD BTN FOR NEXT PAGE",
+                Label::HoldForNextPage.get(),
                 50,
             );
             page.init_display(&mut peripherals.display)?;
@@ -592,13 +3199,15 @@ pub async fn handle_set_descriptor_request(
             manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
         }
         DescriptorVariant::MultiSig {
-            threshold, keys, ..
+            threshold,
+            keys,
+            is_sorted,
         } => {
             let threshold_display = alloc::format!("{} of {}", threshold, keys.len());
             let mut page = GenericTwoLinePage::new(
-                "Threshold",
+                Label::Threshold.get(),
                 &threshold_display,
-                "HOLD BTN FOR NEXT PAGE",
+                Label::HoldForNextPage.get(),
                 50,
             );
             page.init_display(&mut peripherals.display)?;
@@ -607,56 +3216,134 @@ pub async fn handle_set_descriptor_request(
             manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
 
             for (i, key) in keys.iter().enumerate() {
-                let key_name = alloc::format!("Key #{}", i + 1);
-
-                let second_line = match key {
-                    MultisigKey::Local(path) => {
-                        alloc::format!(
-                            "This device\n{}",
-                            <SerializedDerivationPath as Into<bip32::DerivationPath>>::into(
-                                path.clone()
-                            )
-                        )
-                    }
-                    MultisigKey::External(key) => {
-                        let fingerprint = key
-                            .origin
-                            .as_ref()
-                            .map(|(f, _)| f.clone().into())
-                            .unwrap_or_else(|| key.key.as_xpub().unwrap().fingerprint());
-                        alloc::format!(
-                            "Key {}\n{}",
-                            fingerprint,
-                            <SerializedDerivationPath as Into<bip32::DerivationPath>>::into(
-                                key.full_path()
-                            )
-                        )
-                    }
+                // With `multi()` the order of the keys is part of the script
+                let key_name = if *is_sorted {
+                    alloc::format!("{} #{}", Label::Key.get(), i + 1)
+                } else {
+                    alloc::format!(
+                        "{} {} {} {}",
+                        Label::Key.get(),
+                        i + 1,
+                        Label::Of.get(),
+                        keys.len()
+                    )
                 };
 
-                let mut page =
-                    GenericTwoLinePage::new(&key_name, &second_line, "HOLD BTN FOR NEXT PAGE", 50);
+                let (holder, path) = describe_key(key);
+
+                let mut page = GenericThreeLinePage::new(
+                    &key_name,
+                    &holder,
+                    &path,
+                    Label::HoldForNextPage.get(),
+                    50,
+                );
                 page.init_display(&mut peripherals.display)?;
                 page.draw_to(&mut peripherals.display)?;
                 peripherals.display.flush()?;
                 manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
             }
         }
+        DescriptorVariant::TapTree {
+            internal_key,
+            leaves,
+        } => {
+            match internal_key {
+                Some(key) => {
+                    let (holder, path) = describe_key(key);
+                    confirm_page_with_note(
+                        Label::KeyPath.get(),
+                        &holder,
+                        &path,
+                        &mut events,
+                        peripherals,
+                    )
+                    .await?;
+                }
+                None => {
+                    confirm_page(
+                        Label::KeyPath.get(),
+                        Label::UnspendableKeyPath.get(),
+                        &mut events,
+                        peripherals,
+                    )
+                    .await?;
+                }
+            }
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let leaf_name = alloc::format!(
+                    "{} {} {} {}",
+                    Label::ScriptPathTitle.get(),
+                    i + 1,
+                    Label::Of.get(),
+                    leaves.len()
+                );
+                match &leaf.script {
+                    model::TapLeafScript::Key(key) => {
+                        let (holder, path) = describe_key(key);
+                        confirm_page_with_note(
+                            &leaf_name,
+                            &holder,
+                            &path,
+                            &mut events,
+                            peripherals,
+                        )
+                        .await?;
+                    }
+                    model::TapLeafScript::MultiA { threshold, keys } => {
+                        let threshold_display = alloc::format!(
+                            "{} {} {} {}",
+                            threshold,
+                            Label::Of.get(),
+                            keys.len(),
+                            Label::Keys.get()
+                        );
+                        confirm_page(&leaf_name, &threshold_display, &mut events, peripherals)
+                            .await?;
+
+                        // The order of the keys is part of the script, like with `multi()`
+                        for (j, key) in keys.iter().enumerate() {
+                            let key_name = alloc::format!(
+                                "{} {} {} {} {} {}",
+                                Label::Path.get(),
+                                i + 1,
+                                Label::PathKey.get(),
+                                j + 1,
+                                Label::Of.get(),
+                                keys.len()
+                            );
+                            let (holder, path) = describe_key(key);
+                            confirm_page_with_note(
+                                &key_name,
+                                &holder,
+                                &path,
+                                &mut events,
+                                peripherals,
+                            )
+                            .await?;
+                        }
+                    }
+                }
+            }
+        }
     }
 
     log::debug!("First address: {}", first_address);
     let address_str = first_address.to_string();
-    let mut page = ShowScrollingAddressPage::new(
+    confirm_address(
         &address_str,
-        "Confirm first address",
-        "HOLD BTN FOR NEXT PAGE",
-    );
-    page.init_display(&mut peripherals.display)?;
-    page.draw_to(&mut peripherals.display)?;
-    peripherals.display.flush()?;
-    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+        Label::ConfirmFirstAddress.get(),
+        Label::HoldForNextPage.get(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
 
-    let mut page = SummaryPage::new("Save new\nconfiguration?", "HOLD BTN TO APPLY CHANGES");
+    let mut page = SummaryPage::new(
+        Label::SaveConfiguration.get(),
+        Label::HoldToApplyChanges.get(),
+    );
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
@@ -666,12 +3353,16 @@ pub async fn handle_set_descriptor_request(
     // log::debug!("Saving new config: {:?}", encrypted_config);
     crate::config::write_config(
         &mut peripherals.flash,
-        &model::Config::Initialized(encrypted_config),
+        &model::Config::Initialized(Box::new(encrypted_config)),
     )
     .await?;
     log::debug!("Config saved!");
 
-    peripherals.nfc.send(model::Reply::Ok).await.unwrap();
+    let reply = match new_wallet.config.wallet_policy() {
+        Some(policy) => model::Reply::WalletPolicy(Box::new((*policy.hmac).into())),
+        None => model::Reply::Ok,
+    };
+    peripherals.nfc.send(reply).await.unwrap();
 
     Ok(CurrentState::Idle {
         wallet: Rc::new(new_wallet),
@@ -705,6 +3396,11 @@ pub(crate) trait DescriptorMeta {
         psbt_output: &psbt::Output,
         secp: &'s SecpCtx,
     ) -> Option<DerivedDescriptor>;
+    fn derive_from_psbt_input<'s>(
+        &self,
+        psbt_input: &psbt::Input,
+        secp: &'s SecpCtx,
+    ) -> Option<DerivedDescriptor>;
 }
 
 impl DescriptorMeta for ExtendedDescriptor {
@@ -876,4 +3572,19 @@ impl DescriptorMeta for ExtendedDescriptor {
 
         None
     }
+
+    fn derive_from_psbt_input<'s>(
+        &self,
+        psbt_input: &psbt::Input,
+        secp: &'s SecpCtx,
+    ) -> Option<DerivedDescriptor> {
+        if let Some(derived) = self.derive_from_hd_keypaths(&psbt_input.bip32_derivation, secp) {
+            return Some(derived);
+        }
+        if let Some(derived) = self.derive_from_tap_key_origins(&psbt_input.tap_key_origins, secp) {
+            return Some(derived);
+        }
+
+        None
+    }
 }
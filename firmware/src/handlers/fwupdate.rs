@@ -15,13 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-#![cfg_attr(feature = "emulator", allow(dead_code, unused_variables))]
+#![cfg_attr(not(feature = "device"), allow(dead_code, unused_variables))]
 
 use alloc::boxed::Box;
 use core::{ops::Deref, str::FromStr};
 
 use futures::prelude::*;
 
+#[cfg(not(test))]
 use rtic_monotonics::systick::ExtU32;
 
 #[cfg(feature = "device")]
@@ -31,9 +32,10 @@ use bitcoin_hashes::{sha256, Hash, HashEngine};
 
 use minicbor::bytes::ByteArray;
 
-use gui::{FwUpdateProgressPage, SingleLineTextPage, SummaryPage};
+use gui::{i18n::Label, FwUpdateProgressPage, ShowScrollingAddressPage, SingleLineTextPage};
 
 use super::*;
+use crate::config;
 use crate::version;
 use crate::Error;
 
@@ -45,16 +47,12 @@ const FIRMWARE_SIGNING_KEY: &'static str =
 const FIRMWARE_SIGNING_KEY: &'static str =
     "1608bd04cf3212070b3de57f4a2ad8e5108a103af037f878ec75f4a2068de610";
 
-const CHECKPOINT_PAGE_INTERVAL: usize = 4;
-
-// #[cfg_attr(feature = "emulator", allow(dead_code))]
-// const FLASH_OPTKEY1: u32 = 0x0819_2A3B;
-// #[cfg_attr(feature = "emulator", allow(dead_code))]
-// const FLASH_OPTKEY2: u32 = 0x4C5D_6E7F;
+/// Checkpoints are padded to a multiple of the flash programming unit
+const CHECKPOINT_ALIGN: usize = 8;
 
 #[cfg(feature = "device")]
 type UnlockedFlash<'a> = flash::FlashProgramming<'a>;
-#[cfg(feature = "emulator")]
+#[cfg(not(feature = "device"))]
 type UnlockedFlash = ();
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -144,13 +142,85 @@ struct Checkpoint {
     tail: [u8; version::TAIL_SIZE],
 }
 
-#[cfg_attr(feature = "emulator", allow(dead_code))]
+impl Checkpoint {
+    fn matches(&self, header: &FwUpdateHeader) -> bool {
+        // Verify we are still talking about the same FW
+        self.first_page_midstate == header.first_page_midstate && self.signature == header.signature
+    }
+}
+
+/// Find the most recent checkpoint in the first page of the spare bank
+///
+/// A checkpoint is saved after every chunk, appending it to the previous ones so that the page
+/// only has to be erased once it's full. Every record is prefixed by its length and a power loss
+/// while writing one can only corrupt the last record, so we keep the last one that decodes.
+///
+/// Returns the checkpoint and the offset at which the next one should be written.
+fn find_checkpoint(page: &[u8]) -> (Option<Checkpoint>, usize) {
+    let mut checkpoint = None;
+    let mut offset = 0;
+
+    while offset + 2 <= page.len() {
+        let len = u16::from_be_bytes([page[offset], page[offset + 1]]) as usize;
+        if len == 0xFFFF || offset + 2 + len > page.len() {
+            // Erased flash or garbage
+            break;
+        }
+
+        if let Ok(ckpt) = minicbor::decode(&page[offset + 2..offset + 2 + len]) {
+            checkpoint = Some(ckpt);
+        }
+        offset += (2 + len).next_multiple_of(CHECKPOINT_ALIGN);
+    }
+
+    (checkpoint, offset)
+}
+
+#[cfg(feature = "device")]
+fn read_checkpoint(bank_to_flash: &BankToFlash) -> (Option<Checkpoint>, usize) {
+    let address = bank_to_flash.get_logical_address(BankStatus::Spare, 0);
+    // The spare bank is always mapped, no need to unlock the flash just to read it
+    let page = unsafe { core::slice::from_raw_parts(address as *const u8, 2048) };
+    find_checkpoint(page)
+}
+
+#[cfg(not(feature = "device"))]
+fn read_checkpoint(_bank_to_flash: &BankToFlash) -> (Option<Checkpoint>, usize) {
+    (None, 0)
+}
+
+fn spare_bank(peripherals: &HandlerPeripherals) -> BankToFlash {
+    let bank_to_flash = match peripherals.flash.fb_mode {
+        false => FlashBank::Bank2,
+        true => FlashBank::Bank1,
+    };
+    BankToFlash::new(bank_to_flash)
+}
+
+/// Whether the spare bank holds the checkpoints of an interrupted update
+///
+/// Once an update completes the page holds the first page of the image instead, which is left
+/// alone by the wipe.
+pub fn has_checkpoints(peripherals: &HandlerPeripherals) -> bool {
+    read_checkpoint(&spare_bank(peripherals)).0.is_some()
+}
+
+/// Page from which the update described by `header` would start, accounting for a checkpoint
+fn resume_page(header: &FwUpdateHeader, bank_to_flash: &BankToFlash) -> usize {
+    match read_checkpoint(bank_to_flash) {
+        (Some(ckpt), _) if ckpt.matches(header) => ckpt.next_page,
+        _ => 1,
+    }
+}
+
+#[cfg_attr(not(feature = "device"), allow(dead_code))]
 struct FwUpdater<'h> {
     header: &'h FwUpdateHeader,
     hash: sha256::HashEngine,
     page: usize,
     bank_to_flash: BankToFlash,
     prev_checkpoint: Option<usize>,
+    checkpoint_offset: usize,
     tail: [u8; version::TAIL_SIZE],
 }
 
@@ -161,36 +231,8 @@ impl<'h> FwUpdater<'h> {
         header: &'h FwUpdateHeader,
         bank_to_flash: BankToFlash,
     ) -> Result<Self, Error> {
-        #[cfg(feature = "device")]
-        let checkpoint: Option<Checkpoint> = {
-            let mut buf = alloc::vec![0x00; 2048];
-            flash.read(
-                bank_to_flash.get_logical_address(BankStatus::Spare, 0),
-                &mut buf,
-            );
-
-            let len = u16::from_be_bytes(buf[..2].try_into().unwrap()) as usize;
-            if len >= 2048 - 2 {
-                None
-            } else if let Ok(ckpt) = minicbor::decode(&buf[2..2 + len]) {
-                Some(ckpt)
-            } else {
-                None
-            }
-        };
-        #[cfg(feature = "emulator")]
-        let checkpoint: Option<Checkpoint> = None;
-
-        let checkpoint = checkpoint.and_then(|ckpt| {
-            // Verify we are still talking about the same FW
-            if ckpt.first_page_midstate != header.first_page_midstate
-                || ckpt.signature != header.signature
-            {
-                None
-            } else {
-                Some(ckpt)
-            }
-        });
+        let (checkpoint, checkpoint_offset) = read_checkpoint(&bank_to_flash);
+        let checkpoint = checkpoint.filter(|ckpt| ckpt.matches(header));
 
         let (midstate, midstate_len) = match &checkpoint {
             Some(ckpt) => {
@@ -260,23 +302,23 @@ impl<'h> FwUpdater<'h> {
 
         #[cfg(feature = "device")]
         {
-            const CONFIG_PAGE: usize = 255;
-
-            let mut buf = alloc::vec![0x00; 2048];
-            flash.read(
-                bank_to_flash.get_logical_address(BankStatus::Active, CONFIG_PAGE),
-                &mut buf,
-            );
+            for page in crate::config::CONFIG_PAGES {
+                let mut buf = alloc::vec![0x00; 2048];
+                flash.read(
+                    bank_to_flash.get_logical_address(BankStatus::Active, page),
+                    &mut buf,
+                );
 
-            flash
-                .erase_page(bank_to_flash.get_physical_page(BankStatus::Spare, CONFIG_PAGE))
-                .map_err(|_| Error::FlashError)?;
-            flash
-                .write(
-                    bank_to_flash.get_logical_address(BankStatus::Spare, CONFIG_PAGE),
-                    &buf,
-                )
-                .map_err(|e| Error::FlashError)?;
+                flash
+                    .erase_page(bank_to_flash.get_physical_page(BankStatus::Spare, page))
+                    .map_err(|_| Error::FlashError)?;
+                flash
+                    .write(
+                        bank_to_flash.get_logical_address(BankStatus::Spare, page),
+                        &buf,
+                    )
+                    .map_err(|e| Error::FlashError)?;
+            }
             log::debug!("Configuration copied successfully");
         }
 
@@ -286,6 +328,11 @@ impl<'h> FwUpdater<'h> {
             page: checkpoint.as_ref().map(|ckpt| ckpt.next_page).unwrap_or(1),
             bank_to_flash,
             prev_checkpoint: checkpoint.as_ref().map(|ckpt| ckpt.next_page),
+            // After a mass-erase the checkpoint page is empty
+            checkpoint_offset: match checkpoint {
+                Some(_) => checkpoint_offset,
+                None => 0,
+            },
             tail: checkpoint
                 .map(|ckpt| ckpt.tail)
                 .unwrap_or([0u8; version::TAIL_SIZE]),
@@ -293,7 +340,7 @@ impl<'h> FwUpdater<'h> {
     }
 
     #[cfg(feature = "device")]
-    fn save_checkpoint(&self, flash: &mut UnlockedFlash) -> Result<(), Error> {
+    fn save_checkpoint(&mut self, flash: &mut UnlockedFlash) -> Result<(), Error> {
         let checkpoint = Checkpoint {
             first_page_midstate: self.header.first_page_midstate.clone(),
             signature: self.header.signature.clone(),
@@ -302,27 +349,30 @@ impl<'h> FwUpdater<'h> {
             tail: self.tail,
         };
 
-        let mut data = alloc::vec![0x00, 0x00];
         let serialized = minicbor::to_vec(checkpoint).expect("always succeed");
-        let len = (serialized.len() as u16).to_be_bytes();
+        let mut data = alloc::vec::Vec::from((serialized.len() as u16).to_be_bytes());
         data.extend(serialized);
-        (&mut data[..2]).copy_from_slice(&len);
-        data.resize(2048, 0x00);
+        data.resize(data.len().next_multiple_of(CHECKPOINT_ALIGN), 0x00);
 
-        flash
-            .erase_page(self.bank_to_flash.get_physical_page(BankStatus::Spare, 0))
-            .map_err(|_| Error::FlashError)?;
+        if self.checkpoint_offset + data.len() > 2048 {
+            flash
+                .erase_page(self.bank_to_flash.get_physical_page(BankStatus::Spare, 0))
+                .map_err(|_| Error::FlashError)?;
+            self.checkpoint_offset = 0;
+        }
         flash
             .write(
-                self.bank_to_flash.get_logical_address(BankStatus::Spare, 0),
+                self.bank_to_flash.get_logical_address(BankStatus::Spare, 0)
+                    + self.checkpoint_offset,
                 &data,
             )
             .map_err(|_| Error::FlashError)?;
+        self.checkpoint_offset += data.len();
 
         Ok(())
     }
 
-    #[cfg_attr(feature = "emulator", allow(unused_variables))]
+    #[cfg_attr(not(feature = "device"), allow(unused_variables))]
     fn chunk(&mut self, flash: &mut UnlockedFlash, data: &[u8]) -> Result<(), Error> {
         if self.page * 2048 > self.header.size {
             return Err(Error::InvalidFirmware);
@@ -332,27 +382,29 @@ impl<'h> FwUpdater<'h> {
 
         #[cfg(feature = "device")]
         {
-            // If we are restarting from a checkpoint we don't know exactly where the previous update
-            // stopped, so we may be rewriting over existing pages. Make sure to individually erase the
-            // next `CHECKPOINT_PAGE_INTERVAL` pages before writing to them
-            match self.prev_checkpoint {
-                Some(x) if self.page < x + CHECKPOINT_PAGE_INTERVAL => {
-                    flash
-                        .erase_page(
-                            self.bank_to_flash
-                                .get_physical_page(BankStatus::Spare, self.page),
-                        )
-                        .map_err(|_| Error::FlashError)?;
-                }
-                _ => {}
+            // If we are restarting from a checkpoint the previous update may have been interrupted
+            // while writing the page right after it, so erase it before writing again
+            if self.prev_checkpoint == Some(self.page) {
+                flash
+                    .erase_page(
+                        self.bank_to_flash
+                            .get_physical_page(BankStatus::Spare, self.page),
+                    )
+                    .map_err(|_| Error::FlashError)?;
+            }
+
+            let address = self
+                .bank_to_flash
+                .get_logical_address(BankStatus::Spare, self.page);
+            flash.write(address, data).map_err(|_| Error::FlashError)?;
+
+            // Only checkpoint pages that we know were written correctly
+            let mut written = alloc::vec![0x00; 2048];
+            flash.read(address, &mut written);
+            if written != data {
+                log::warn!("Page {} doesn't match after writing it", self.page);
+                return Err(Error::FlashError);
             }
-            flash
-                .write(
-                    self.bank_to_flash
-                        .get_logical_address(BankStatus::Spare, self.page),
-                    data,
-                )
-                .map_err(|_| Error::FlashError)?;
         }
 
         log::debug!("Done!");
@@ -374,8 +426,9 @@ impl<'h> FwUpdater<'h> {
 
         self.tail = tail;
 
+        // Save a checkpoint after every page, the host will only see the next page once it's durable
         #[cfg(feature = "device")]
-        if self.page % CHECKPOINT_PAGE_INTERVAL == 0 {
+        {
             self.save_checkpoint(flash)?;
             log::debug!("Saved checkpoint");
         }
@@ -401,6 +454,12 @@ impl<'h> FwUpdater<'h> {
         let hash = sha256::Hash::from_engine(self.hash.clone());
         log::debug!("FW hash: {:02X?}", hash);
 
+        // Make sure the host wasn't lying when it asked for confirmation
+        if hash.into_inner() != **header.hash.deref() {
+            log::warn!("Image hash doesn't match the header");
+            return Err(Error::InvalidFirmware);
+        }
+
         let signing_key = secp256k1::XOnlyPublicKey::from_str(FIRMWARE_SIGNING_KEY)
             .expect("Valid signing pubkey");
         let message = secp256k1::Message::from_slice(&hash).expect("Correct length");
@@ -420,7 +479,10 @@ impl<'h> FwUpdater<'h> {
 
         // Check version
         let parsed = version::UpdateTail::parse(&self.tail);
-        if parsed.version > version::CURRENT_VERSION && parsed.variant == version::CURRENT_VARIANT {
+        if parsed.version > version::CURRENT_VERSION
+            && parsed.version == header.version
+            && parsed.variant == version::CURRENT_VARIANT
+        {
             log::info!(
                 "FW Variant {:02X}, upgrading from {} to {}",
                 version::CURRENT_VARIANT,
@@ -444,19 +506,28 @@ impl<'h> FwUpdater<'h> {
                     data,
                 )
                 .map_err(|_| Error::FlashError)?;
+
+            // The new image has to confirm itself once it boots, or we'll roll back
+            use crate::hw::boot;
+            boot::write_slot_state(
+                flash,
+                self.bank_to_flash
+                    .get_logical_address(BankStatus::Spare, boot::SLOT_STATE_PAGE),
+                self.bank_to_flash
+                    .get_physical_page(BankStatus::Spare, boot::SLOT_STATE_PAGE),
+                boot::SlotState::Pending { attempts: 0 },
+            )?;
         }
 
         Ok(())
     }
 
     fn switch_and_reboot(self, flash: &mut UnlockedFlash) -> ! {
+        // Boot from the new image, keeping the current one around in case it fails to boot
         #[cfg(feature = "device")]
-        {
-            // Wipe the boot sector of the booted bank to force the switch
-            let page = self.bank_to_flash.get_physical_page(BankStatus::Active, 0);
-            flash.erase_page(page).unwrap();
-        }
+        crate::hw::boot::boot_from_bank(flash, self.bank_to_flash.physical == FlashBank::Bank2);
 
+        #[cfg(not(feature = "device"))]
         cortex_m::peripheral::SCB::sys_reset();
     }
 }
@@ -468,10 +539,27 @@ pub async fn handle_begin_fw_update(
 ) -> Result<CurrentState, Error> {
     log::info!("handle_begin_fw_update");
 
-    if header.size > 510 * 2048 {
+    if header.size > model::flash::MAX_FIRMWARE_SIZE {
+        peripherals
+            .nfc
+            .send(model::Reply::error(model::ErrorCode::FirmwareTooBig))
+            .await
+            .unwrap();
+        return Err(Error::InvalidFirmware);
+    }
+
+    let min_version = config::read_min_version(&mut peripherals.flash).await?;
+    if header.version <= version::CURRENT_VERSION || header.version < min_version {
+        log::warn!(
+            "Refusing to downgrade: version {}, current {}, minimum {}",
+            header.version,
+            version::CURRENT_VERSION,
+            min_version
+        );
+
         peripherals
             .nfc
-            .send(model::Reply::Error("Firmware file too big".into()))
+            .send(model::Reply::error(model::ErrorCode::FirmwareTooOld))
             .await
             .unwrap();
         return Err(Error::InvalidFirmware);
@@ -483,7 +571,17 @@ pub async fn handle_begin_fw_update(
         .await
         .unwrap();
 
-    let mut page = SummaryPage::new_with_threshold("Update FW?", "HOLD BTN TO BEGIN", 70);
+    let versions = alloc::format!(
+        "{} -> {}",
+        version::format_version(version::CURRENT_VERSION),
+        version::format_version(header.version)
+    );
+    let hash = header
+        .hash
+        .iter()
+        .map(|b| alloc::format!("{:02x}", b))
+        .collect::<alloc::string::String>();
+    let mut page = ShowScrollingAddressPage::new(&hash, &versions, Label::HoldToBegin.get());
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
@@ -510,15 +608,12 @@ pub async fn handle_begin_fw_update(
             &mut peripherals.flash.parts.cr,
         )
         .map_err(|_| Error::FlashError)?;
-    #[cfg(feature = "emulator")]
+    #[cfg(not(feature = "device"))]
     let mut lock = ();
 
-    let bank_to_flash = match peripherals.flash.fb_mode {
-        false => FlashBank::Bank2,
-        true => FlashBank::Bank1,
-    };
-    log::debug!("Flashing to bank: {:?}", bank_to_flash);
-    let mut updater = FwUpdater::new(&mut lock, header, BankToFlash::new(bank_to_flash))?;
+    let bank_to_flash = spare_bank(peripherals);
+    log::debug!("Flashing to bank: {:?}", bank_to_flash.physical);
+    let mut updater = FwUpdater::new(&mut lock, header, bank_to_flash)?;
     page.add_confirm((2048 * updater.page) as u32); // account for the potential checkpoint
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
@@ -564,14 +659,34 @@ pub async fn handle_begin_fw_update(
         }
     }
 
-    let page = SingleLineTextPage::new("UPDATE COMPLETE");
+    let page = SingleLineTextPage::new(Label::UpdateComplete.get());
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
 
+    #[cfg(not(test))]
     rtic_monotonics::systick::Systick::delay(1000_u32.millis()).await;
 
     peripherals.nfc_finished.recv().await.unwrap();
 
     updater.switch_and_reboot(&mut lock);
 }
+
+pub async fn handle_get_fw_update_offset(
+    header: &FwUpdateHeader,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), Error> {
+    log::info!("handle_get_fw_update_offset");
+
+    let page = resume_page(header, &spare_bank(peripherals));
+    log::debug!("Update would resume from page {}", page);
+
+    peripherals
+        .nfc
+        .send(model::Reply::NextPage(page))
+        .await
+        .unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::sync::atomic::Ordering;
+
+use futures::prelude::*;
+
+use rand::RngCore;
+
+use gui::{i18n::Label, LoadingPage, Page, SummaryPage};
+use model::host::{TrustedHost, MAX_TRUSTED_HOSTS};
+use model::{Config, ErrorCode, Reply};
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+pub(super) fn current_session() -> u32 {
+    hw_common::NFC_SESSION.load(Ordering::Relaxed)
+}
+
+/// Host identified in the current NFC session, see `model::host`
+#[derive(Debug, Default)]
+pub struct HostSession {
+    /// Last challenge sent to the host and the session it was sent in
+    challenge: Option<(u32, [u8; 32])>,
+    /// Session in which a paired host identified itself
+    trusted: Option<u32>,
+}
+
+impl HostSession {
+    /// Whether a paired host identified itself in the current session
+    pub fn is_trusted(&self) -> bool {
+        self.trusted == Some(current_session())
+    }
+
+    pub fn forget(&mut self) {
+        *self = Default::default();
+    }
+}
+
+/// Send a new challenge to the host, replacing the previous one
+pub async fn handle_host_challenge(peripherals: &mut HandlerPeripherals) {
+    log::info!("handle_host_challenge");
+
+    let mut challenge = [0u8; 32];
+    peripherals.rng.fill_bytes(&mut challenge);
+    peripherals.host.challenge = Some((current_session(), challenge));
+
+    peripherals
+        .nfc
+        .send(Reply::HostChallenge(Box::new(challenge.into())))
+        .await
+        .unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+}
+
+/// Check the answer of the host to the last challenge, pairing it if it's unknown
+///
+/// The challenge can only be answered once and only in the session it was sent in, so a
+/// signature can't be replayed by another host.
+pub async fn handle_identify_host(
+    wallet: &mut Rc<PortalWallet>,
+    pubkey: &[u8; 33],
+    signature: &[u8; 64],
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_identify_host");
+
+    let session = current_session();
+    let host = peripherals
+        .host
+        .challenge
+        .take()
+        .filter(|(s, _)| *s == session)
+        .and_then(|(_, challenge)| {
+            model::host::verify_challenge(wallet.secp_ctx(), &challenge, pubkey, signature)
+        })
+        .map(|pubkey| TrustedHost::new(&pubkey));
+    let host = match host {
+        Some(host) => host,
+        None => {
+            log::warn!("Invalid host signature");
+            peripherals
+                .nfc
+                .send(Reply::error(ErrorCode::InvalidHostSignature))
+                .await
+                .unwrap();
+            return Ok(CurrentState::Idle {
+                wallet: Rc::clone(wallet),
+            });
+        }
+    };
+
+    if wallet.config.trusted_hosts().contains(&host) {
+        log::debug!("Host {} identified", host.fingerprint());
+        peripherals.host.trusted = Some(session);
+        peripherals.nfc.send(Reply::Ok).await.unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+    if wallet.config.trusted_hosts().len() >= MAX_TRUSTED_HOSTS {
+        peripherals
+            .nfc
+            .send(Reply::error(ErrorCode::TooManyHosts))
+            .await
+            .unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    peripherals.nfc.send(Reply::DelayedReply).await.unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    confirm_page(
+        Label::PairHostTitle.get(),
+        &host.fingerprint(),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
+    let mut page = SummaryPage::new(Label::PairHost.get(), Label::HoldToPair.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let mut config = wallet.config.clone();
+    config
+        .secret
+        .trusted_hosts
+        .get_or_insert_with(Default::default)
+        .push(host.clone());
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(config.clone().lock())),
+    )
+    .await?;
+    log::debug!("Host {} paired", host.fingerprint());
+
+    let new_wallet = super::init::make_wallet_from_xprv(wallet.xprv, wallet.network(), config)?;
+    peripherals.host.trusted = Some(session);
+    peripherals.nfc.send(Reply::Ok).await.unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::new(new_wallet),
+    })
+}
@@ -16,15 +16,23 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use alloc::rc::Rc;
+use alloc::string::ToString;
 
 use futures::prelude::*;
 
-use gui::InitialPage;
+use gui::{i18n::Label, FingerprintPage, InitialPage};
+use model::settings::IdleScreen;
 use model::{DeviceInfo, Reply};
 
 use super::*;
 use crate::Error;
 
+/// How long the button must be held to open the settings menu
+const SETTINGS_HOLD_TICKS: usize = 2;
+/// Releasing the button after holding it at least this long, but not long enough to open the
+/// settings, shows the quick info pages
+const QUICK_INFO_HOLD_TICKS: usize = 1;
+
 pub async fn handle_idle(
     wallet: &mut Rc<PortalWallet>,
     mut events: impl Stream<Item = Event> + Unpin,
@@ -32,67 +40,337 @@ pub async fn handle_idle(
 ) -> Result<CurrentState, Error> {
     log::info!("handle_idle");
 
-    let page = InitialPage::new("Portal ready", "");
-    page.init_display(&mut peripherals.display)?;
-    page.draw_to(&mut peripherals.display)?;
+    match wallet.config.settings.idle_screen {
+        IdleScreen::Plain => {
+            let welcome = match wallet.config.wallet_policy() {
+                Some(policy) => alloc::format!("{}\n{}", Label::PortalReady.get(), policy.name),
+                None => Label::PortalReady.get().to_string(),
+            };
+            let page = InitialPage::new(&welcome, "");
+            page.init_display(&mut peripherals.display)?;
+            page.draw_to(&mut peripherals.display)?;
+        }
+        IdleScreen::Fingerprint => {
+            let page =
+                FingerprintPage::new(wallet.xprv.fingerprint(wallet.secp_ctx()).into_bytes());
+            page.init_display(&mut peripherals.display)?;
+            page.draw_to(&mut peripherals.display)?;
+        }
+    }
     peripherals.display.flush()?;
 
-    let events = only_requests(&mut events);
-    pin_mut!(events);
+    // Keep the TSC on, holding the button opens the settings menu
+    peripherals.tsc_enabled.enable();
+
+    let lock_after_ticks = match wallet.config.settings.auto_lock.seconds() {
+        Some(seconds) if wallet.config.has_pair_code() => {
+            Some(seconds as usize * 1000 / crate::TIMER_TICK_MILLIS as usize)
+        }
+        _ => None,
+    };
+    let mut idle_ticks = 0;
+    let mut holding_ticks = None;
 
     loop {
-        match events.next().await {
-            Some(model::Request::GetInfo) => {
-                peripherals
-                    .nfc
-                    .send(Reply::Info(DeviceInfo::new_unlocked_initialized(
+        let request = match events.next().await.expect("Event") {
+            Event::Request(request) => {
+                idle_ticks = 0;
+                request
+            }
+            Event::Input(pressing) => {
+                // The TSC keeps reporting while the button is held, only start counting once
+                holding_ticks = match (pressing, holding_ticks) {
+                    (true, None) => Some(0),
+                    (true, ticks) => ticks,
+                    (false, Some(ticks)) if ticks >= QUICK_INFO_HOLD_TICKS => {
+                        break Ok(CurrentState::QuickInfo {
+                            wallet: Rc::clone(wallet),
+                        });
+                    }
+                    (false, _) => None,
+                };
+                continue;
+            }
+            Event::Tick => {
+                idle_ticks += 1;
+
+                if let Some(ticks) = holding_ticks.as_mut() {
+                    *ticks += 1;
+                    if *ticks >= SETTINGS_HOLD_TICKS {
+                        break Ok(CurrentState::Settings {
+                            wallet: Rc::clone(wallet),
+                        });
+                    }
+                }
+                if lock_after_ticks.map_or(false, |max| idle_ticks >= max) {
+                    log::info!("Auto-locking after {} ticks", idle_ticks);
+                    peripherals.last_payment = None;
+                    peripherals.payjoin_original = None;
+                    peripherals.reserves_message = None;
+                    peripherals.signing_checkpoint = None;
+                    peripherals.host.forget();
+                    peripherals.wallet_policy.forget();
+                    break Ok(CurrentState::Locked {
+                        config: wallet.config.clone().lock(),
+                    });
+                }
+                continue;
+            }
+        };
+
+        match request {
+            model::Request::GetInfo => {
+                send_device_info(
+                    DeviceInfo::new_unlocked_initialized(
                         wallet.network(),
                         wallet.xprv.fingerprint(wallet.secp_ctx()).into_bytes(),
+                        &wallet.config.secret.descriptor,
                         env!("CARGO_PKG_VERSION"),
-                    )))
+                    ),
+                    peripherals,
+                )
+                .await;
+                continue;
+            }
+            model::Request::Attest(challenge) => {
+                attestation::handle_attest(&challenge, peripherals).await?;
+                continue;
+            }
+            model::Request::DisplayAddress(index) => {
+                break Ok(CurrentState::DisplayAddress {
+                    index,
+                    wallet: Rc::clone(wallet),
+                });
+            }
+            model::Request::BeginSignPsbt { fiat_rate, cpfp } => {
+                // Not worth failing the request over, the amounts in bitcoin are still shown
+                peripherals.fiat_rate = fiat_rate.filter(model::FiatRate::is_valid);
+                peripherals.cpfp = cpfp.filter(model::CpfpInfo::is_valid);
+                break Ok(CurrentState::WaitingForPsbt {
+                    wallet: Rc::clone(wallet),
+                    kind: bitcoin::PsbtKind::Payment,
+                });
+            }
+            model::Request::BeginSignPsbtBatch { count: 0 } => {
+                peripherals
+                    .nfc
+                    .send(model::Reply::UnexpectedMessage)
                     .await
                     .unwrap();
                 peripherals.nfc_finished.recv().await.unwrap();
                 continue;
             }
-            Some(model::Request::DisplayAddress(index)) => {
-                break Ok(CurrentState::DisplayAddress {
-                    index,
+            model::Request::BeginSignPsbtBatch { count } => {
+                break Ok(CurrentState::WaitingForPsbt {
                     wallet: Rc::clone(wallet),
+                    kind: bitcoin::PsbtKind::Batch(bitcoin::SignBatch::new(count)),
                 });
             }
-            Some(model::Request::BeginSignPsbt) => {
+            model::Request::BeginSignPsbtAntiExfil { .. }
+                if matches!(
+                    wallet.config.secret.descriptor.script_type,
+                    model::ScriptType::Taproot
+                ) =>
+            {
+                peripherals
+                    .nfc
+                    .send(model::Reply::error_with_detail(
+                        model::ErrorCode::UnsupportedDescriptor,
+                        "Anti-exfil is only supported for ECDSA signatures",
+                    ))
+                    .await
+                    .unwrap();
+                peripherals.nfc_finished.recv().await.unwrap();
+                continue;
+            }
+            model::Request::BeginSignPsbtAntiExfil { host_commitment } => {
                 break Ok(CurrentState::WaitingForPsbt {
                     wallet: Rc::clone(wallet),
+                    kind: bitcoin::PsbtKind::AntiExfil(**host_commitment),
                 });
             }
-            Some(model::Request::PublicDescriptor) => {
+            model::Request::BeginSignPayjoin if peripherals.last_payment.is_none() => {
+                peripherals
+                    .nfc
+                    .send(model::Reply::error(model::ErrorCode::NoPaymentToPayjoin))
+                    .await
+                    .unwrap();
+                peripherals.nfc_finished.recv().await.unwrap();
+                continue;
+            }
+            model::Request::BeginSignPayjoin => {
+                break Ok(CurrentState::WaitingForPsbt {
+                    wallet: Rc::clone(wallet),
+                    kind: bitcoin::PsbtKind::Payjoin,
+                });
+            }
+            model::Request::BeginSignPayjoinReceiver { original } => {
+                match model::psbt::PayjoinOriginal::parse(&original) {
+                    Ok(original) => {
+                        peripherals.payjoin_original = Some(original);
+                        break Ok(CurrentState::WaitingForPsbt {
+                            wallet: Rc::clone(wallet),
+                            kind: bitcoin::PsbtKind::PayjoinReceiver,
+                        });
+                    }
+                    Err(e) => {
+                        peripherals
+                            .nfc
+                            .send(model::Reply::error_with_detail(
+                                e.error_code(),
+                                e.to_string(),
+                            ))
+                            .await
+                            .unwrap();
+                        peripherals.nfc_finished.recv().await.unwrap();
+                        continue;
+                    }
+                }
+            }
+            model::Request::BeginSignProofOfReserves { message } => {
+                peripherals.reserves_message = Some(message);
+                break Ok(CurrentState::WaitingForPsbt {
+                    wallet: Rc::clone(wallet),
+                    kind: bitcoin::PsbtKind::ProofOfReserves,
+                });
+            }
+            model::Request::PublicDescriptor => {
                 break Ok(CurrentState::PublicDescriptor {
                     wallet: Rc::clone(wallet),
                 });
             }
-            Some(model::Request::GetXpub(derivation_path)) => {
+            model::Request::GetXpub(derivation_path) => {
                 break Ok(CurrentState::GetXpub {
                     wallet: Rc::clone(wallet),
                     derivation_path: derivation_path.into(),
                 });
             }
-            Some(model::Request::SetDescriptor {
+            model::Request::SetDescriptor {
                 variant,
                 script_type,
                 bsms,
-            }) => {
+            } => {
                 break Ok(CurrentState::SetDescriptor {
                     wallet: Rc::clone(wallet),
                     variant,
                     script_type,
                     bsms,
+                    name: None,
+                });
+            }
+            model::Request::RegisterWalletPolicy {
+                name,
+                variant,
+                script_type,
+            } => {
+                break Ok(CurrentState::SetDescriptor {
+                    wallet: Rc::clone(wallet),
+                    variant,
+                    script_type,
+                    bsms: None,
+                    name: Some(name),
                 });
             }
-            Some(model::Request::BeginFwUpdate(header)) => {
+            model::Request::UseWalletPolicy { hmac } => {
+                wallet_policy::handle_use_wallet_policy(wallet, &hmac, peripherals).await;
+                continue;
+            }
+            model::Request::ExportBackup {
+                password,
+                include_seed,
+            } => {
+                break Ok(CurrentState::ExportBackup {
+                    wallet: Rc::clone(wallet),
+                    password,
+                    include_seed,
+                });
+            }
+            model::Request::RestoreBackup {
+                backup, password, ..
+            } => {
+                break Ok(CurrentState::RestoreBackup {
+                    wallet: Some(Rc::clone(wallet)),
+                    backup: backup.into(),
+                    password,
+                    pair_code: None,
+                });
+            }
+            model::Request::SelfTest => {
+                break Ok(CurrentState::SelfTest {
+                    wallet: Some(Rc::clone(wallet)),
+                });
+            }
+            model::Request::SetSeedExport { allowed } => {
+                break Ok(CurrentState::SetSeedExport {
+                    wallet: Rc::clone(wallet),
+                    allowed,
+                });
+            }
+            model::Request::SetConfirmationPolicy(policy) => {
+                break Ok(CurrentState::SetConfirmationPolicy {
+                    wallet: Rc::clone(wallet),
+                    policy,
+                });
+            }
+            model::Request::GetHostChallenge => {
+                host::handle_host_challenge(peripherals).await;
+                continue;
+            }
+            model::Request::IdentifyHost { pubkey, signature } => {
+                break Ok(CurrentState::IdentifyHost {
+                    wallet: Rc::clone(wallet),
+                    pubkey: alloc::boxed::Box::new(**pubkey),
+                    signature: alloc::boxed::Box::new(**signature),
+                });
+            }
+            model::Request::AddTrustedAddress { address } => {
+                break Ok(CurrentState::AddTrustedAddress {
+                    wallet: Rc::clone(wallet),
+                    address,
+                });
+            }
+            model::Request::SignMessage { index, message } => {
+                break Ok(CurrentState::SignMessage {
+                    wallet: Rc::clone(wallet),
+                    index,
+                    message,
+                    format: bitcoin::MessageFormat::Bip322,
+                });
+            }
+            model::Request::SignMessageLegacy { index, message } => {
+                break Ok(CurrentState::SignMessage {
+                    wallet: Rc::clone(wallet),
+                    index,
+                    message,
+                    format: bitcoin::MessageFormat::Legacy,
+                });
+            }
+            model::Request::GetLogs => {
+                break Ok(CurrentState::GetLogs {
+                    wallet: Rc::clone(wallet),
+                });
+            }
+            model::Request::DeriveNodeSeed { index } => {
+                break Ok(CurrentState::DeriveNodeSeed {
+                    wallet: Rc::clone(wallet),
+                    index,
+                });
+            }
+            model::Request::BeginWipe => {
+                break Ok(CurrentState::Wipe {
+                    previous: alloc::boxed::Box::new(CurrentState::Idle {
+                        wallet: Rc::clone(wallet),
+                    }),
+                });
+            }
+            model::Request::BeginFwUpdate(header) => {
                 break Ok(CurrentState::UpdatingFw { header });
             }
-            Some(_) => {
+            model::Request::GetFwUpdateOffset(header) => {
+                super::fwupdate::handle_get_fw_update_offset(&header, peripherals).await?;
+                continue;
+            }
+            _ => {
                 peripherals
                     .nfc
                     .send(model::Reply::UnexpectedMessage)
@@ -101,7 +379,6 @@ pub async fn handle_idle(
                 peripherals.nfc_finished.recv().await.unwrap();
                 continue;
             }
-            _ => unreachable!(),
         }
     }
 }
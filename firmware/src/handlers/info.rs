@@ -0,0 +1,174 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use futures::prelude::*;
+
+use gui::{i18n::Label, GenericTwoLinePage, Page, SummaryPage};
+use model::bitcoin::Network;
+use model::{DescriptorVariant, ErrorCode, Reply};
+
+use super::*;
+use crate::Error;
+
+/// Go back to the idle screen if the button isn't touched for this long
+const QUICK_INFO_TIMEOUT_TICKS: usize = 20;
+
+fn network_name(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => Label::Mainnet.get(),
+        Network::Testnet => Label::Testnet.get(),
+        Network::Signet => Label::Signet.get(),
+        Network::Regtest => Label::Regtest.get(),
+    }
+}
+
+/// Title and value of every page, in the order they are shown
+fn info_pages(wallet: &PortalWallet) -> Vec<(&'static str, String)> {
+    let descriptor = &wallet.config.secret.descriptor;
+    let variant = match &descriptor.variant {
+        DescriptorVariant::SingleSig(_) => Label::SingleSig.get().into(),
+        DescriptorVariant::MultiSig {
+            threshold, keys, ..
+        } => alloc::format!(
+            "{} {} {} {}",
+            threshold,
+            Label::Of.get(),
+            keys.len(),
+            Label::Multisig.get()
+        ),
+        DescriptorVariant::TapTree { leaves, .. } => {
+            alloc::format!("{} {}", leaves.len(), Label::ScriptPaths.get())
+        }
+    };
+
+    alloc::vec![
+        (
+            Label::Fingerprint.get(),
+            alloc::format!(
+                "{:08X}",
+                u32::from_be_bytes(wallet.xprv.fingerprint(wallet.secp_ctx()).to_bytes())
+            ),
+        ),
+        (Label::Firmware.get(), env!("CARGO_PKG_VERSION").into()),
+        (Label::Network.get(), network_name(wallet.network()).into()),
+        (Label::WalletPolicy.get(), variant),
+        (
+            Label::AddressType.get(),
+            descriptor.script_type.display_name().into(),
+        ),
+    ]
+}
+
+/// Show some information about the wallet without a host, opened from the idle screen
+///
+/// Tapping the button moves to the next page, after the last one (or when the button isn't
+/// touched for a while) the device goes back to the idle screen. Requests are answered with
+/// `Reply::Busy` and also bring the device back to the idle screen, so that the host can retry.
+pub async fn handle_quick_info(
+    wallet: &mut Rc<PortalWallet>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_quick_info");
+
+    peripherals.tsc_enabled.enable();
+
+    'pages: for (title, value) in info_pages(wallet) {
+        let page = GenericTwoLinePage::new(title, &value, Label::TapForNext.get(), 50);
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+
+        let mut pressing = false;
+        let mut idle_ticks = 0;
+        loop {
+            match events.next().await.expect("Event") {
+                Event::Request(_) => {
+                    peripherals
+                        .nfc
+                        .send(Reply::Busy)
+                        .await
+                        .expect("Send should work");
+                    break 'pages;
+                }
+                Event::Input(true) => {
+                    pressing = true;
+                    idle_ticks = 0;
+                }
+                Event::Input(false) if pressing => continue 'pages,
+                Event::Input(false) => {}
+                Event::Tick => {
+                    idle_ticks += 1;
+                    if idle_ticks >= QUICK_INFO_TIMEOUT_TICKS {
+                        break 'pages;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
+
+/// Export the log lines kept in RAM, see `crate::log_buffer`
+///
+/// The lines are redacted, but they still tell what the device was used for, so they are only
+/// sent after a confirmation.
+pub async fn handle_get_logs(
+    wallet: &mut Rc<PortalWallet>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_get_logs");
+
+    if !crate::log_buffer::is_enabled() {
+        peripherals
+            .nfc
+            .send(Reply::error(ErrorCode::LogsDisabled))
+            .await
+            .unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    peripherals.nfc.send(Reply::DelayedReply).await.unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    let mut page = SummaryPage::new(Label::ExportLogs.get(), Label::HoldToExportLogs.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    peripherals
+        .nfc
+        .send(Reply::Logs(crate::log_buffer::contents()))
+        .await
+        .unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
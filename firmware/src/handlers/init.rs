@@ -15,14 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use core::str::FromStr;
-
+use alloc::boxed::Box;
 use alloc::string::ToString;
 use futures::prelude::*;
 
 use rand::RngCore;
 
-use gui::{ConfirmPairCodePage, SingleLineTextPage};
+use gui::{i18n::Label, ConfirmPairCodePage, SingleLineTextPage};
+use model::mnemonic::MnemonicLanguage;
 use model::{
     Entropy, ExtendedKey, InitializedConfig, MultisigKey, ScriptType, UnlockedConfig,
     UnverifiedConfig, WalletDescriptor,
@@ -31,16 +31,18 @@ use model::{
 use bdk::bitcoin::util::bip32;
 use bdk::bitcoin::Network;
 use bdk::descriptor::{DescriptorXKey, IntoWalletDescriptor};
-use bdk::keys::bip39::Mnemonic;
 use bdk::keys::{
     DescriptorKey, DescriptorPublicKey, DescriptorSecretKey, ScriptContext, ValidNetworks,
 };
+use bdk::miniscript::descriptor::DescriptorType;
+use bdk::signer::{SignerContext, SignerWrapper};
 
 use gui::{GeneratingMnemonicPage, LoadingPage, MnemonicPage, Page, WelcomePage};
 use model::{Config, DeviceInfo};
 
 use super::*;
 use crate::config;
+use crate::version;
 use crate::Error;
 
 fn map_err_config<X>(_: X) -> config::ConfigError {
@@ -65,8 +67,52 @@ impl IntoWalletDescriptor for SkipNetworkChecks {
     }
 }
 
+/// Paths of the local keys of `descriptor` that can start from a cached account key
+///
+/// Only the paths made of hardened steps are cached, the others are used as they are.
+fn cacheable_paths(descriptor: &WalletDescriptor) -> alloc::vec::Vec<bip32::DerivationPath> {
+    let paths = match &descriptor.variant {
+        model::DescriptorVariant::SingleSig(path) => alloc::vec![path.clone()],
+        variant => variant
+            .keys()
+            .into_iter()
+            .filter_map(|key| match key {
+                MultisigKey::Local(path) => Some(path.clone()),
+                MultisigKey::External(_) => None,
+            })
+            .collect(),
+    };
+
+    paths
+        .into_iter()
+        .map(bip32::DerivationPath::from)
+        .filter(|path| path.into_iter().all(|c| c.is_hardened()))
+        .collect()
+}
+
+/// Make sure the derived keys cached in `secret` cover the local keys of its descriptor, returns
+/// whether the cache had to be rebuilt
+pub(super) fn refresh_derived_keys(
+    xprv: &bip32::ExtendedPrivKey,
+    secret: &mut model::SecretData,
+) -> Result<bool, Error> {
+    let paths = cacheable_paths(&secret.descriptor);
+    if let Some(cache) = &secret.derived_keys {
+        if cache.covers(&paths) {
+            return Ok(false);
+        }
+    }
+
+    let secp = secp256k1::Secp256k1::new();
+    let cache = model::DerivedKeysCache::new(&secp, xprv, &paths).map_err(|_| Error::Wallet)?;
+    secret.derived_keys = Some(cache);
+
+    Ok(true)
+}
+
 fn build_bdk_descriptor(
     xprv: &bip32::ExtendedPrivKey,
+    cache: Option<&model::DerivedKeysCache>,
     descriptor: model::WalletDescriptor,
     keychain: bdk::KeychainKind,
 ) -> Result<bdk::descriptor::template::DescriptorTemplateOut, Error> {
@@ -83,11 +129,27 @@ fn build_bdk_descriptor(
         path.extend(&[bip32::ChildNumber::Normal { index }])
     }
 
-    fn make_local_key<Ctx: ScriptContext>(
+    fn local_secret_key(
         derivation_path: bip32::DerivationPath,
         xprv: &bip32::ExtendedPrivKey,
+        cache: Option<&model::DerivedKeysCache>,
         keychain: bdk::KeychainKind,
-    ) -> DescriptorKey<Ctx> {
+    ) -> DescriptorSecretKey {
+        // Same key as below, but the hardened steps are already derived
+        let cached = cache.and_then(|cache| {
+            cache
+                .account_key(&derivation_path)
+                .map(|key| (cache.master_fingerprint.clone().into(), key))
+        });
+        if let Some((fingerprint, account_key)) = cached {
+            return DescriptorSecretKey::XPrv(DescriptorXKey {
+                origin: Some((fingerprint, derivation_path)),
+                xkey: account_key,
+                derivation_path: extend_path(bip32::DerivationPath::master(), keychain),
+                wildcard: bdk::descriptor::Wildcard::Unhardened,
+            });
+        }
+
         let secp = secp256k1::Secp256k1::new();
 
         let split_position = derivation_path
@@ -98,26 +160,50 @@ fn build_bdk_descriptor(
         let origin_path = derivation_path[..split_position].into();
         let derivation_path = derivation_path[split_position..].into();
 
+        DescriptorSecretKey::XPrv(DescriptorXKey {
+            origin: Some((xprv.fingerprint(&secp), origin_path)),
+            xkey: *xprv,
+            derivation_path: extend_path(derivation_path, keychain),
+            wildcard: bdk::descriptor::Wildcard::Unhardened,
+        })
+    }
+
+    fn make_local_key<Ctx: ScriptContext>(
+        derivation_path: bip32::DerivationPath,
+        xprv: &bip32::ExtendedPrivKey,
+        cache: Option<&model::DerivedKeysCache>,
+        keychain: bdk::KeychainKind,
+    ) -> DescriptorKey<Ctx> {
         bdk::keys::DescriptorKey::from_secret(
-            DescriptorSecretKey::XPrv(DescriptorXKey {
-                origin: Some((xprv.fingerprint(&secp), origin_path)),
-                xkey: *xprv,
-                derivation_path: extend_path(derivation_path, keychain),
-                wildcard: bdk::descriptor::Wildcard::Unhardened,
-            }),
+            local_secret_key(derivation_path, xprv, cache, keychain),
             ValidNetworks::new(),
         )
     }
 
+    fn external_public_key(key: ExtendedKey, keychain: bdk::KeychainKind) -> DescriptorPublicKey {
+        let ExtendedKey { origin, key, path } = key;
+        DescriptorPublicKey::XPub(DescriptorXKey {
+            origin: origin.map(|(fingerprint, path)| (fingerprint.into(), path.into())),
+            xkey: key
+                .as_xpub()
+                .expect("The key was checked when setting the config"),
+            derivation_path: extend_path(path.into(), keychain),
+            wildcard: bdk::descriptor::Wildcard::Unhardened,
+        })
+    }
+
     match (descriptor.variant, descriptor.script_type) {
         (model::DescriptorVariant::SingleSig(path), ScriptType::NativeSegwit) => Ok(
-            bdk::descriptor!(wpkh(make_local_key(path.into(), xprv, keychain)))?,
+            bdk::descriptor!(wpkh(make_local_key(path.into(), xprv, cache, keychain)))?,
         ),
         (model::DescriptorVariant::SingleSig(path), ScriptType::WrappedSegwit) => Ok(
-            bdk::descriptor!(sh(wpkh(make_local_key(path.into(), xprv, keychain))))?,
+            bdk::descriptor!(sh(wpkh(make_local_key(path.into(), xprv, cache, keychain))))?,
         ),
         (model::DescriptorVariant::SingleSig(path), ScriptType::Legacy) => Ok(bdk::descriptor!(
-            pkh(make_local_key(path.into(), xprv, keychain))
+            pkh(make_local_key(path.into(), xprv, cache, keychain))
+        )?),
+        (model::DescriptorVariant::SingleSig(path), ScriptType::Taproot) => Ok(bdk::descriptor!(
+            tr(make_local_key(path.into(), xprv, cache, keychain))
         )?),
 
         (
@@ -131,88 +217,176 @@ fn build_bdk_descriptor(
             fn get_keys_vector<Ctx: ScriptContext>(
                 keys: alloc::vec::Vec<MultisigKey>,
                 xprv: &bip32::ExtendedPrivKey,
+                cache: Option<&model::DerivedKeysCache>,
                 keychain: bdk::KeychainKind,
             ) -> alloc::vec::Vec<DescriptorKey<Ctx>> {
                 keys.into_iter()
                     .map(|key| match key {
                         MultisigKey::Local(path) => {
-                            make_local_key(path.clone().into(), xprv, keychain)
-                        }
-                        MultisigKey::External(ExtendedKey { origin, key, path }) => {
-                            bdk::keys::DescriptorKey::from_public(
-                                DescriptorPublicKey::XPub(DescriptorXKey {
-                                    origin: origin.map(|(fingerprint, path)| {
-                                        (fingerprint.into(), path.into())
-                                    }),
-                                    xkey: key
-                                        .as_xpub()
-                                        .expect("The key was checked when setting the config"),
-                                    derivation_path: extend_path(path.into(), keychain),
-                                    wildcard: bdk::descriptor::Wildcard::Unhardened,
-                                }),
-                                ValidNetworks::new(),
-                            )
+                            make_local_key(path.clone().into(), xprv, cache, keychain)
                         }
+                        MultisigKey::External(key) => bdk::keys::DescriptorKey::from_public(
+                            external_public_key(key, keychain),
+                            ValidNetworks::new(),
+                        ),
                     })
                     .collect()
             }
 
             // Unfortunately we have to duplicate this piece of code because we can't create a fragment for a "sortedmulti"
-            if is_sorted {
-                let keys = get_keys_vector(keys, xprv, keychain);
-
-                match script_type {
-                    ScriptType::NativeSegwit => {
-                        Ok(bdk::descriptor!(wsh(sortedmulti_vec(threshold, keys)))?)
-                    }
-                    ScriptType::WrappedSegwit => {
-                        Ok(bdk::descriptor!(sh(wsh(sortedmulti_vec(threshold, keys))))?)
-                    }
-                    ScriptType::Legacy => Err(Error::Config(config::ConfigError::CorruptedConfig)),
+            let keys = get_keys_vector(keys, xprv, cache, keychain);
+            match (script_type, is_sorted) {
+                (ScriptType::NativeSegwit, true) => {
+                    Ok(bdk::descriptor!(wsh(sortedmulti_vec(threshold, keys)))?)
+                }
+                (ScriptType::WrappedSegwit, true) => {
+                    Ok(bdk::descriptor!(sh(wsh(sortedmulti_vec(threshold, keys))))?)
+                }
+                // The keys stay in the order in which they were set, which is part of the script
+                (ScriptType::NativeSegwit, false) => {
+                    Ok(bdk::descriptor!(wsh(multi_vec(threshold, keys)))?)
+                }
+                (ScriptType::WrappedSegwit, false) => {
+                    Ok(bdk::descriptor!(sh(wsh(multi_vec(threshold, keys))))?)
+                }
+                // `sortedmulti_a` can't be expressed with this version of miniscript, these
+                // configs are refused by `SetDescriptor`
+                (ScriptType::Legacy | ScriptType::Taproot, _) => {
+                    Err(Error::Config(config::ConfigError::CorruptedConfig))
                 }
-            } else {
-                return Err(Error::Wallet);
-
-                // This adds way too much size to the binary, it needs to be investigated further...
-
-                // match script_type {
-                //     ScriptType::NativeSegwit => Ok(bdk::descriptor!(wsh(multi_vec(
-                //         threshold,
-                //         get_keys_vector(keys, xprv, keychain)
-                //     )))?),
-                //     ScriptType::WrappedSegwit => Ok(bdk::descriptor!(sh(wsh(multi_vec(
-                //         threshold,
-                //         get_keys_vector(keys, xprv, keychain)
-                //     ))))?),
-                //     ScriptType::Legacy => Ok(bdk::descriptor!(sh(multi_vec(
-                //         threshold,
-                //         get_keys_vector(keys, xprv, keychain)
-                //     )))?),
-                // }
             }
         }
+
+        // The shape of the tree is only known at runtime, so the descriptor is parsed from its
+        // string with the local keys as xprvs, which also fills the keymap
+        (
+            model::DescriptorVariant::TapTree {
+                internal_key,
+                leaves,
+            },
+            ScriptType::Taproot,
+        ) => {
+            use bdk::bitcoin::hashes::hex::ToHex;
+
+            let key = |key: &MultisigKey| match key {
+                MultisigKey::Local(path) => {
+                    local_secret_key(path.clone().into(), xprv, cache, keychain).to_string()
+                }
+                MultisigKey::External(key) => {
+                    external_public_key(key.clone(), keychain).to_string()
+                }
+            };
+            let internal_key = match &internal_key {
+                Some(internal_key) => key(internal_key),
+                None => model::taproot::NUMS_KEY.to_hex(),
+            };
+            let tree = model::TapLeaf::fold_tree(
+                &leaves,
+                |leaf| match &leaf.script {
+                    model::TapLeafScript::Key(k) => alloc::format!("pk({})", key(k)),
+                    model::TapLeafScript::MultiA { threshold, keys } => alloc::format!(
+                        "multi_a({},{})",
+                        threshold,
+                        keys.iter()
+                            .map(key)
+                            .collect::<alloc::vec::Vec<_>>()
+                            .join(",")
+                    ),
+                },
+                |left, right| alloc::format!("{{{},{}}}", left, right),
+            )
+            .ok_or(Error::Config(config::ConfigError::CorruptedConfig))?;
+
+            let secp = secp256k1::Secp256k1::new();
+            let (descriptor, keymap) = bdk::descriptor::ExtendedDescriptor::parse_descriptor(
+                &secp,
+                &alloc::format!("tr({},{})", internal_key, tree),
+            )
+            .map_err(|_| Error::Config(config::ConfigError::CorruptedConfig))?;
+
+            Ok((descriptor, keymap, ValidNetworks::new()))
+        }
+        (model::DescriptorVariant::TapTree { .. }, _) => {
+            Err(Error::Config(config::ConfigError::CorruptedConfig))
+        }
     }
 }
 
 pub(super) fn make_wallet_from_xprv(
     xprv: bip32::ExtendedPrivKey,
     network: Network,
-    config: model::UnlockedConfig,
+    mut config: model::UnlockedConfig,
 ) -> Result<PortalWallet, Error> {
+    refresh_derived_keys(&xprv, &mut config.secret)?;
+
     let descriptor_external = SkipNetworkChecks(build_bdk_descriptor(
         &xprv,
+        config.secret.derived_keys.as_ref(),
         config.secret.descriptor.clone(),
         bdk::KeychainKind::External,
     )?);
     let descriptor_internal = SkipNetworkChecks(build_bdk_descriptor(
         &xprv,
+        config.secret.derived_keys.as_ref(),
         config.secret.descriptor.clone(),
         bdk::KeychainKind::Internal,
     )?);
+    let signers = input_signers(&descriptor_external.0)
+        .chain(input_signers(&descriptor_internal.0))
+        .collect();
 
     let wallet = bdk::Wallet::new(descriptor_external, Some(descriptor_internal), (), network)?;
 
-    Ok(PortalWallet::new(wallet, xprv, config))
+    Ok(PortalWallet::new(wallet, xprv, config, signers))
+}
+
+/// Local keys of `descriptor`, with the context needed to sign with them
+fn input_signers(
+    descriptor: &bdk::descriptor::template::DescriptorTemplateOut,
+) -> impl Iterator<Item = InputSigner> + '_ {
+    let (descriptor, keymap, _) = descriptor;
+    // Keys in the leaves of a tree are signed for by `sign_tap_scripts`
+    let ctx = move |key: &DescriptorPublicKey| match descriptor {
+        bdk::descriptor::ExtendedDescriptor::Tr(tr) => SignerContext::Tap {
+            is_internal_key: tr.internal_key() == key,
+        },
+        _ => match descriptor.desc_type() {
+            DescriptorType::Bare
+            | DescriptorType::Pkh
+            | DescriptorType::Sh
+            | DescriptorType::ShSortedMulti => SignerContext::Legacy,
+            _ => SignerContext::Segwitv0,
+        },
+    };
+
+    keymap.iter().filter_map(move |(public, key)| match key {
+        DescriptorSecretKey::XPrv(xkey) => Some(SignerWrapper::new(xkey.clone(), ctx(public))),
+        _ => None,
+    })
+}
+
+/// Make the wallet of a config read from the flash, saving it again if the derived keys weren't
+/// cached yet so that the next boot can skip them
+async fn load_wallet(
+    xprv: bip32::ExtendedPrivKey,
+    network: Network,
+    mut unlocked: UnlockedConfig,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<PortalWallet, Error> {
+    if refresh_derived_keys(&xprv, &mut unlocked.secret)? {
+        log::debug!("Saving the derived keys");
+        // Only a cache, the wallet works the same without it
+        if let Err(e) = config::write_config(
+            &mut peripherals.flash,
+            &Config::Initialized(Box::new(unlocked.clone().lock())),
+        )
+        .await
+        {
+            log::warn!("Unable to save the derived keys: {:?}", e);
+        }
+    }
+
+    make_wallet_from_xprv(xprv, network, unlocked)
 }
 
 pub async fn handle_por(peripherals: &mut HandlerPeripherals) -> Result<CurrentState, Error> {
@@ -221,6 +395,25 @@ pub async fn handle_por(peripherals: &mut HandlerPeripherals) -> Result<CurrentS
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
 
+    // We made it this far, so this firmware is good: never go back to an older one
+    #[cfg(feature = "device")]
+    if let Err(e) = crate::hw::boot::confirm_slot(&mut peripherals.flash) {
+        log::warn!("Unable to confirm the firmware slot: {:?}", e);
+    }
+    if let Err(e) =
+        config::write_min_version(&mut peripherals.flash, version::CURRENT_VERSION).await
+    {
+        log::warn!("Unable to update the minimum version: {:?}", e);
+    }
+    // Keys generated with a broken RNG can't be trusted, so don't even try to use it
+    if let model::selftest::TestOutcome::Failed(e) = hw::rng_self_test() {
+        log::error!("{}", e);
+        return Err(Error::RngFailure);
+    }
+
+    // Without the wrapping key we can't read or write the config, so this one is fatal
+    config::init_wrapping_key(&mut peripherals.flash, &mut peripherals.rng).await?;
+
     let config = match config::read_config(&mut peripherals.flash).await {
         Ok(config) => config,
         Err(e) => {
@@ -228,37 +421,45 @@ pub async fn handle_por(peripherals: &mut HandlerPeripherals) -> Result<CurrentS
             return Ok(CurrentState::Init);
         }
     };
-    match config {
-        Config::Initialized(InitializedConfig {
+    if let Config::Initialized(initialized) = &config {
+        apply_settings(peripherals, initialized.settings.unwrap_or_default())?;
+    }
+
+    let initialized = match config {
+        Config::Initialized(initialized) => *initialized,
+        Config::Unverified(unverified) => {
+            return Ok(CurrentState::UnverifiedConfig { config: unverified })
+        }
+    };
+
+    match initialized {
+        InitializedConfig {
             secret: model::MaybeEncrypted::Unencrypted(secret),
             network,
+            settings,
             ..
-        }) => {
+        } => {
             log::debug!("Unencrypted config loaded");
 
             let xprv = secret.cached_xprv.as_xprv().map_err(map_err_config)?;
+            let mut unlocked = UnlockedConfig::from_secret_data_unencrypted(*secret, network);
+            unlocked.settings = settings.unwrap_or_default();
             Ok(CurrentState::Idle {
-                wallet: Rc::new(make_wallet_from_xprv(
-                    xprv,
-                    network,
-                    UnlockedConfig::from_secret_data_unencrypted(secret, network),
-                )?),
+                wallet: Rc::new(load_wallet(xprv, network, unlocked, peripherals).await?),
             })
         }
-        Config::Initialized(
-            initialized @ InitializedConfig {
-                secret: model::MaybeEncrypted::Encrypted { .. },
-                ..
-            },
-        ) => Ok(CurrentState::Locked {
+        initialized @ InitializedConfig {
+            secret: model::MaybeEncrypted::Encrypted { .. },
+            ..
+        } => Ok(CurrentState::Locked {
             config: initialized,
         }),
-        Config::Unverified(unverified) => Ok(CurrentState::UnverifiedConfig { config: unverified }),
     }
 }
 
+/// Serial number programmed at manufacture
 #[cfg(feature = "device")]
-fn read_serial() -> alloc::string::String {
+pub(super) fn read_serial() -> Option<alloc::string::String> {
     const OPTION_BYTES: usize = 0x1FFF_7000;
     const SERIAL_OFFSET: usize = 4;
     const SERIAL_LEN: usize = 20;
@@ -274,17 +475,19 @@ fn read_serial() -> alloc::string::String {
     }
 
     if buf[0] == 0xFF || buf[0] == 0x00 {
-        alloc::string::String::from("NO_SERIAL")
+        None
     } else {
-        buf.into_iter()
-            .take_while(|v| *v != 0x00)
-            .map(|v| v as char)
-            .collect()
+        Some(
+            buf.into_iter()
+                .take_while(|v| *v != 0x00)
+                .map(|v| v as char)
+                .collect(),
+        )
     }
 }
-#[cfg(feature = "emulator")]
-fn read_serial() -> alloc::string::String {
-    Default::default()
+#[cfg(not(feature = "device"))]
+pub(super) fn read_serial() -> Option<alloc::string::String> {
+    None
 }
 
 pub async fn handle_init(
@@ -292,8 +495,13 @@ pub async fn handle_init(
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
     let serial = read_serial();
+    let serial = match &serial {
+        Some(serial) => serial.as_str(),
+        None if cfg!(feature = "device") => "NO_SERIAL",
+        None => "",
+    };
 
-    let page = WelcomePage::new(&serial);
+    let page = WelcomePage::new(serial);
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
@@ -304,42 +512,73 @@ pub async fn handle_init(
     loop {
         match events.next().await {
             Some(model::Request::GetInfo) => {
-                peripherals
-                    .nfc
-                    .send(model::Reply::Info(DeviceInfo::new_locked_uninitialized(
-                        env!("CARGO_PKG_VERSION"),
-                    )))
-                    .await
-                    .unwrap();
-                peripherals.nfc_finished.recv().await.unwrap();
+                send_device_info(
+                    DeviceInfo::new_locked_uninitialized(env!("CARGO_PKG_VERSION")),
+                    peripherals,
+                )
+                .await;
+                continue;
+            }
+            Some(model::Request::Attest(challenge)) => {
+                attestation::handle_attest(&challenge, peripherals).await?;
+                continue;
+            }
+            Some(model::Request::ProvisionAttestation(key)) => {
+                attestation::handle_provision_attestation(&key, peripherals).await?;
                 continue;
             }
             Some(model::Request::GenerateMnemonic {
                 num_words,
                 network,
                 password,
+                disable_seed_export,
+                language,
             }) => {
                 break Ok(CurrentState::GenerateSeed {
                     num_words,
                     network,
                     password,
+                    disable_seed_export: disable_seed_export.unwrap_or(false),
+                    language: language.unwrap_or_default(),
                 });
             }
             Some(model::Request::SetMnemonic {
                 mnemonic,
                 network,
                 password,
+                disable_seed_export,
             }) => {
                 break Ok(CurrentState::ImportSeed {
                     mnemonic,
                     network,
                     password,
+                    disable_seed_export: disable_seed_export.unwrap_or(false),
                 });
             }
+            Some(model::Request::RestoreBackup {
+                backup,
+                password,
+                pair_code,
+            }) => {
+                break Ok(CurrentState::RestoreBackup {
+                    wallet: None,
+                    backup: backup.into(),
+                    password,
+                    pair_code,
+                });
+            }
+            Some(model::Request::SelfTest) => {
+                break Ok(CurrentState::SelfTest { wallet: None });
+            }
             #[cfg(feature = "emulator")]
             Some(model::Request::BeginFwUpdate(header)) => {
                 break Ok(CurrentState::UpdatingFw { header });
             }
+            #[cfg(feature = "emulator")]
+            Some(model::Request::GetFwUpdateOffset(header)) => {
+                super::fwupdate::handle_get_fw_update_offset(&header, peripherals).await?;
+                continue;
+            }
             Some(_) => {
                 peripherals
                     .nfc
@@ -359,7 +598,7 @@ pub async fn handle_locked(
     mut events: impl Stream<Item = Event> + Unpin,
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
-    let page = SingleLineTextPage::new("LOCKED");
+    let page = SingleLineTextPage::new(Label::Locked.get());
     page.init_display(&mut peripherals.display)?;
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
@@ -370,15 +609,15 @@ pub async fn handle_locked(
     loop {
         match events.next().await {
             Some(model::Request::GetInfo) => {
-                peripherals
-                    .nfc
-                    .send(model::Reply::Info(DeviceInfo::new_locked_initialized(
-                        config.network,
-                        env!("CARGO_PKG_VERSION"),
-                    )))
-                    .await
-                    .unwrap();
-                peripherals.nfc_finished.recv().await.unwrap();
+                send_device_info(
+                    DeviceInfo::new_locked_initialized(config.network, env!("CARGO_PKG_VERSION")),
+                    peripherals,
+                )
+                .await;
+                continue;
+            }
+            Some(model::Request::Attest(challenge)) => {
+                attestation::handle_attest(&challenge, peripherals).await?;
                 continue;
             }
             Some(model::Request::Unlock { password }) => {
@@ -407,8 +646,15 @@ pub async fn handle_locked(
                     .map_err(map_err_config)?;
                 peripherals.nfc.send(model::Reply::Ok).await.unwrap();
 
+                let network = unlocked.network;
                 break Ok(CurrentState::Idle {
-                    wallet: Rc::new(make_wallet_from_xprv(xprv, unlocked.network, unlocked)?),
+                    wallet: Rc::new(load_wallet(xprv, network, unlocked, peripherals).await?),
+                });
+            }
+            // Lets the user start over when the pair code is lost
+            Some(model::Request::BeginWipe) => {
+                break Ok(CurrentState::Wipe {
+                    previous: alloc::boxed::Box::new(CurrentState::Locked { config }),
                 });
             }
             Some(_) => {
@@ -428,8 +674,15 @@ pub async fn display_mnemonic(
 ) -> Result<CurrentState, Error> {
     peripherals.tsc_enabled.enable();
 
-    let mnemonic = Mnemonic::from_entropy(&config.entropy.bytes).map_err(map_err_config)?;
-    let mnemonic_str = mnemonic.word_iter().collect::<alloc::vec::Vec<_>>();
+    let mnemonic = config.entropy.mnemonic().map_err(map_err_config)?;
+    let words = mnemonic
+        .word_iter()
+        .map(model::mnemonic::compose)
+        .collect::<alloc::vec::Vec<_>>();
+    let mnemonic_str = words
+        .iter()
+        .map(alloc::string::String::as_str)
+        .collect::<alloc::vec::Vec<_>>();
     for (chunk_index, words) in mnemonic_str.chunks(2).enumerate().skip(config.page) {
         let mut page = MnemonicPage::new((chunk_index * 2) as u8, &words);
         page.init_display(&mut peripherals.display)?;
@@ -461,7 +714,11 @@ pub async fn display_mnemonic(
 
     let network = config.network;
     let (initialized, unlocked, xprv) = config.upgrade(salt);
-    config::write_config(&mut peripherals.flash, &Config::Initialized(initialized)).await?;
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(initialized)),
+    )
+    .await?;
 
     peripherals.nfc.send(model::Reply::Ok).await.unwrap();
     peripherals.nfc_finished.recv().await.unwrap();
@@ -489,6 +746,8 @@ pub async fn handle_generate_seed(
     num_words: model::NumWordsMnemonic,
     network: Network,
     password: Option<&str>,
+    disable_seed_export: bool,
+    language: MnemonicLanguage,
     events: impl Stream<Item = Event> + Unpin,
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
@@ -509,11 +768,13 @@ pub async fn handle_generate_seed(
     let unverified_config = UnverifiedConfig {
         entropy: Entropy {
             bytes: alloc::vec::Vec::from(entropy).into(),
+            language: Some(language),
         },
         network,
         pair_code: password.map(ToString::to_string),
         descriptor,
         page: 0,
+        disable_seed_export: Some(disable_seed_export),
     };
     let unverified_config = save_unverified_config(unverified_config, peripherals).await?;
     display_mnemonic(unverified_config, events, peripherals).await
@@ -523,7 +784,8 @@ pub async fn handle_import_seed(
     mnemonic: &str,
     network: Network,
     password: Option<&str>,
-    events: impl Stream<Item = Event> + Unpin,
+    disable_seed_export: bool,
+    mut events: impl Stream<Item = Event> + Unpin,
     peripherals: &mut HandlerPeripherals,
 ) -> Result<CurrentState, Error> {
     let page = LoadingPage::new();
@@ -531,20 +793,44 @@ pub async fn handle_import_seed(
     page.draw_to(&mut peripherals.display)?;
     peripherals.display.flush()?;
 
-    let mnemonic = Mnemonic::from_str(mnemonic).map_err(map_err_config)?;
+    // Any length allowed by BIP-39 (12 to 24 words) is accepted, as long as the checksum is valid
+    let (mnemonic, language) = match model::mnemonic::parse(mnemonic) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            log::warn!("Invalid mnemonic: {:?}", e);
+            peripherals
+                .nfc
+                .send(model::Reply::error(model::ErrorCode::InvalidSeed))
+                .await
+                .unwrap();
+            return Ok(CurrentState::Init);
+        }
+    };
     let (entropy, len) = mnemonic.to_entropy_array();
     let entropy = &entropy[..len];
 
+    peripherals.tsc_enabled.enable();
+    confirm_page_with_note(
+        Label::SeedStrength.get(),
+        &alloc::format!("{} {}", mnemonic.word_count(), Label::Words.get()),
+        &alloc::format!("{} {}", len * 8, Label::Bits.get()),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+
     let descriptor = WalletDescriptor::make_bip84(network);
 
     let unverified_config = UnverifiedConfig {
         entropy: Entropy {
             bytes: alloc::vec::Vec::from(entropy).into(),
+            language: Some(language),
         },
         network,
         pair_code: password.map(ToString::to_string),
         descriptor,
         page: 0,
+        disable_seed_export: Some(disable_seed_export),
     };
     let unverified_config = save_unverified_config(unverified_config, peripherals).await?;
     display_mnemonic(unverified_config, events, peripherals).await
@@ -567,16 +853,15 @@ pub async fn handle_unverified_config(
         loop {
             match req_events.next().await {
                 Some(model::Request::GetInfo) => {
-                    peripherals
-                        .nfc
-                        .send(model::Reply::Info(DeviceInfo::new_unverified_config(
+                    send_device_info(
+                        DeviceInfo::new_unverified_config(
                             config.network,
                             config.pair_code.is_some(),
                             env!("CARGO_PKG_VERSION"),
-                        )))
-                        .await
-                        .unwrap();
-                    peripherals.nfc_finished.recv().await.unwrap();
+                        ),
+                        peripherals,
+                    )
+                    .await;
                     continue;
                 }
                 Some(model::Request::Resume) => {
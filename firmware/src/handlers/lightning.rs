@@ -0,0 +1,77 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::rc::Rc;
+use alloc::string::ToString;
+
+use futures::prelude::*;
+
+use gui::{i18n::Label, LoadingPage, Page, SummaryPage};
+use model::{bip85, Reply};
+
+use super::*;
+use crate::Error;
+
+/// Derive the seed of the Lightning node number `index` and send it to the host
+///
+/// Anyone holding the node seed controls the funds of the node, so it's only sent after the
+/// user confirms the index and the wallet it's derived from.
+pub async fn handle_derive_node_seed(
+    wallet: &mut Rc<PortalWallet>,
+    index: u32,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_derive_node_seed");
+
+    peripherals.nfc.send(Reply::DelayedReply).await.unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    confirm_page(
+        Label::LightningNode.get(),
+        &alloc::format!("#{}", index),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    let fingerprint = wallet.xprv.fingerprint(wallet.secp_ctx()).to_string();
+    confirm_page(Label::Wallet.get(), &fingerprint, &mut events, peripherals).await?;
+
+    let mut page = SummaryPage::new(Label::ExportNodeSeed.get(), Label::HoldToExport.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let seed =
+        bip85::node_seed(wallet.secp_ctx(), &wallet.xprv, index).map_err(|_| Error::Wallet)?;
+    peripherals
+        .nfc
+        .send(Reply::NodeSeed(seed.to_vec().into()))
+        .await
+        .unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::clone(wallet),
+    })
+}
@@ -22,33 +22,59 @@ use core::cell::RefCell;
 use futures::pin_mut;
 use futures::prelude::*;
 
-use gui::{ConfirmBarPage, ErrorPage, MainContent, Page};
+use gui::{
+    i18n::Label, ConfirmBarPage, ErrorPage, GenericThreeLinePage, GenericTwoLinePage,
+    LargeTextPage, MainContent, Page, ShowScrollingAddressPage,
+};
 use model::bitcoin::util::bip32;
 use model::{FwUpdateHeader, NumWordsMnemonic, Reply};
 
 use crate::{hw, hw_common, Error};
 
-#[allow(dead_code)]
 const GIT_HASH: &'static str = fetch_git_hash::fetch_git_hash!();
 
+mod address_book;
+mod attestation;
+mod backup;
 mod bitcoin;
 mod fwupdate;
+mod host;
 mod idle;
+mod info;
 mod init;
+mod lightning;
+mod policy;
+mod selftest;
+mod settings;
+#[cfg(test)]
+mod tests;
+mod wallet_policy;
+mod wipe;
 
 pub struct PortalWallet {
     pub bdk: bdk::Wallet,
     pub xprv: bip32::ExtendedPrivKey,
     pub config: model::UnlockedConfig,
+    /// Local keys of both keychains, to sign the inputs one at a time
+    pub signers: alloc::vec::Vec<InputSigner>,
 }
 
+pub type InputSigner =
+    bdk::signer::SignerWrapper<bdk::descriptor::DescriptorXKey<bip32::ExtendedPrivKey>>;
+
 impl PortalWallet {
     pub fn new(
         bdk: bdk::Wallet,
         xprv: bip32::ExtendedPrivKey,
         config: model::UnlockedConfig,
+        signers: alloc::vec::Vec<InputSigner>,
     ) -> Self {
-        PortalWallet { bdk, xprv, config }
+        PortalWallet {
+            bdk,
+            xprv,
+            config,
+            signers,
+        }
     }
 }
 
@@ -78,21 +104,29 @@ pub enum CurrentState {
         num_words: NumWordsMnemonic,
         network: bdk::bitcoin::Network,
         password: Option<String>,
+        disable_seed_export: bool,
+        language: model::mnemonic::MnemonicLanguage,
     },
     /// Importing seed
     ImportSeed {
         mnemonic: String,
         network: bdk::bitcoin::Network,
         password: Option<String>,
+        disable_seed_export: bool,
     },
     /// Device ready
     Idle { wallet: Rc<PortalWallet> },
-    /// Waiting to receive the PSBT
-    WaitingForPsbt { wallet: Rc<PortalWallet> },
+    /// Waiting to receive the PSBT, see `bitcoin::PsbtKind`
+    WaitingForPsbt {
+        wallet: Rc<PortalWallet>,
+        kind: bitcoin::PsbtKind,
+    },
     /// Sign request
     SignPsbt {
         wallet: Rc<PortalWallet>,
-        psbt: alloc::vec::Vec<u8>,
+        psbt: model::psbt_stream::PsbtStream,
+        kind: bitcoin::PsbtKind,
+        reply_options: bitcoin::ReplyOptions,
     },
     /// Display an address
     DisplayAddress {
@@ -107,18 +141,108 @@ pub enum CurrentState {
         variant: model::SetDescriptorVariant,
         script_type: model::ScriptType,
         bsms: Option<model::BsmsRound2>,
+        /// Name of the policy, when registered with `Request::RegisterWalletPolicy`
+        name: Option<String>,
     },
     /// Request a derived XPUB
     GetXpub {
         wallet: Rc<PortalWallet>,
         derivation_path: bip32::DerivationPath,
     },
+    /// Export an encrypted backup of the config
+    ExportBackup {
+        wallet: Rc<PortalWallet>,
+        password: String,
+        include_seed: bool,
+    },
+    /// Restore a backup, either on a new device or on top of an unlocked one
+    RestoreBackup {
+        wallet: Option<Rc<PortalWallet>>,
+        backup: alloc::vec::Vec<u8>,
+        password: String,
+        pair_code: Option<String>,
+    },
+    /// Run the hardware self-test
+    SelfTest { wallet: Option<Rc<PortalWallet>> },
+    /// Settings menu, opened from the device
+    Settings { wallet: Rc<PortalWallet> },
+    /// Fingerprint, version, network and descriptor of the wallet, opened from the device
+    QuickInfo { wallet: Rc<PortalWallet> },
+    /// Allow or forbid exporting the seed
+    SetSeedExport {
+        wallet: Rc<PortalWallet>,
+        allowed: bool,
+    },
+    /// Change which requests are answered without a confirmation
+    SetConfirmationPolicy {
+        wallet: Rc<PortalWallet>,
+        policy: model::ConfirmationPolicy,
+    },
+    /// Check the answer of the host to the challenge, pairing it if needed
+    IdentifyHost {
+        wallet: Rc<PortalWallet>,
+        pubkey: alloc::boxed::Box<[u8; 33]>,
+        signature: alloc::boxed::Box<[u8; 64]>,
+    },
+    /// Add an address to the address book
+    AddTrustedAddress {
+        wallet: Rc<PortalWallet>,
+        address: String,
+    },
+    /// Export the log lines kept in RAM
+    GetLogs { wallet: Rc<PortalWallet> },
+    /// Sign a message with the key of an address
+    SignMessage {
+        wallet: Rc<PortalWallet>,
+        index: u32,
+        message: String,
+        format: bitcoin::MessageFormat,
+    },
+    /// Derive the seed of a Lightning node
+    DeriveNodeSeed {
+        wallet: Rc<PortalWallet>,
+        index: u32,
+    },
+    /// Wipe the device, `previous` is the state to go back to if it's cancelled
+    Wipe {
+        previous: alloc::boxed::Box<CurrentState>,
+    },
     /// Updating firmware
     UpdatingFw { header: FwUpdateHeader },
     /// Error
     Error,
 }
 
+impl CurrentState {
+    /// Wallet loaded in this state, if any
+    fn wallet(&self) -> Option<Rc<PortalWallet>> {
+        match self {
+            CurrentState::Idle { wallet }
+            | CurrentState::WaitingForPsbt { wallet, .. }
+            | CurrentState::SignPsbt { wallet, .. }
+            | CurrentState::DisplayAddress { wallet, .. }
+            | CurrentState::PublicDescriptor { wallet }
+            | CurrentState::SetDescriptor { wallet, .. }
+            | CurrentState::GetXpub { wallet, .. }
+            | CurrentState::ExportBackup { wallet, .. }
+            | CurrentState::Settings { wallet }
+            | CurrentState::QuickInfo { wallet }
+            | CurrentState::SetSeedExport { wallet, .. }
+            | CurrentState::SetConfirmationPolicy { wallet, .. }
+            | CurrentState::IdentifyHost { wallet, .. }
+            | CurrentState::AddTrustedAddress { wallet, .. }
+            | CurrentState::GetLogs { wallet }
+            | CurrentState::SignMessage { wallet, .. }
+            | CurrentState::DeriveNodeSeed { wallet, .. } => Some(Rc::clone(wallet)),
+            CurrentState::RestoreBackup { wallet, .. } | CurrentState::SelfTest { wallet } => {
+                wallet.clone()
+            }
+            CurrentState::Wipe { previous } => previous.wallet(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Event {
     Tick,
@@ -133,6 +257,40 @@ pub struct HandlerPeripherals {
     pub rng: rand_chacha::ChaCha20Rng,
     pub flash: hw::Flash,
     pub tsc_enabled: hw_common::TscEnable,
+    /// Settings currently in use, loaded from the config
+    pub settings: model::settings::DeviceSettings,
+    /// Last payment signed, kept in memory to review a payjoin proposal or a fee bump built on it
+    pub last_payment: Option<model::psbt::PaymentCheckpoint>,
+    /// Transaction of the payer of the payjoin being received, see
+    /// `Request::BeginSignPayjoinReceiver`
+    pub payjoin_original: Option<model::psbt::PayjoinOriginal>,
+    /// Message committed to by the proof of reserves being signed, see
+    /// `Request::BeginSignProofOfReserves`
+    pub reserves_message: Option<String>,
+    /// Exchange rate sent with `Request::BeginSignPsbt`, for the transaction being reviewed
+    pub fiat_rate: Option<model::FiatRate>,
+    /// Parent of the CPFP sent with `Request::BeginSignPsbt`, for the transaction being reviewed
+    pub cpfp: Option<model::CpfpInfo>,
+    /// Inputs signed so far of the last transaction, see `bitcoin::SigningCheckpoint`
+    pub signing_checkpoint: Option<bitcoin::SigningCheckpoint>,
+    /// Host identified in the current session, see `host::HostSession`
+    pub host: host::HostSession,
+    /// Wallet policy referenced in the current session, see `wallet_policy::PolicySession`
+    pub wallet_policy: wallet_policy::PolicySession,
+}
+
+/// Start using `settings`, either loaded from the config or just changed by the user
+fn apply_settings(
+    peripherals: &mut HandlerPeripherals,
+    settings: model::settings::DeviceSettings,
+) -> Result<(), Error> {
+    peripherals.settings = settings;
+    gui::i18n::set_language(settings.language);
+    crate::log_buffer::set_enabled(settings.debug_logs == model::settings::DebugLogs::On);
+    peripherals
+        .tsc_enabled
+        .set_threshold(settings.touch_threshold());
+    hw::set_brightness(&mut peripherals.display, settings.brightness)
 }
 
 #[allow(dead_code)]
@@ -164,7 +322,6 @@ fn only_input<'s>(
         })
 }
 
-#[allow(dead_code)]
 async fn wait_ticks<'s>(
     stream: impl Stream<Item = Event> + 's,
     nfc: &'s RefCell<&'s mut hw_common::ChannelSender<Reply>>,
@@ -188,6 +345,25 @@ async fn wait_ticks<'s>(
     while let Some(_) = stream.next().await {}
 }
 
+/// Reply to `GetInfo` with `info`, adding the details of the firmware build and of the hardware
+async fn send_device_info(info: model::DeviceInfo, peripherals: &mut HandlerPeripherals) {
+    let info = info.with_device_details(
+        Some(GIT_HASH.into()),
+        hw::hardware_revision(),
+        init::read_serial(),
+    );
+    peripherals.nfc.send(Reply::Info(info)).await.unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+}
+
+/// Report the progress of a long operation to the host
+///
+/// The update is picked up by the next `Ping` from the host. If the previous update hasn't
+/// been read yet this one is simply dropped, so this never blocks the handler.
+fn report_progress(peripherals: &mut HandlerPeripherals, current: u32, total: u32) {
+    let _ = peripherals.nfc.try_send(Reply::Progress { current, total });
+}
+
 pub async fn dispatch_handler(
     current_state: &mut CurrentState,
     events: impl Stream<Item = Event> + Unpin,
@@ -197,6 +373,14 @@ pub async fn dispatch_handler(
 
     let mut moved_state = CurrentState::Init;
     core::mem::swap(&mut moved_state, current_state);
+
+    // Needed to go back to idle if the operation times out
+    let wallet = moved_state.wallet();
+    // The menus opened from the device don't have a host waiting for their reply
+    let from_device = matches!(
+        moved_state,
+        CurrentState::Settings { .. } | CurrentState::QuickInfo { .. }
+    );
     let result = match moved_state {
         CurrentState::POR => init::handle_por(peripherals).await,
         CurrentState::Init => init::handle_init(events, peripherals).await,
@@ -208,6 +392,8 @@ pub async fn dispatch_handler(
             num_words,
             network,
             password,
+            disable_seed_export,
+            language,
         } => {
             peripherals
                 .nfc
@@ -215,13 +401,22 @@ pub async fn dispatch_handler(
                 .await
                 .unwrap();
 
-            init::handle_generate_seed(num_words, network, password.as_deref(), events, peripherals)
-                .await
+            init::handle_generate_seed(
+                num_words,
+                network,
+                password.as_deref(),
+                disable_seed_export,
+                language,
+                events,
+                peripherals,
+            )
+            .await
         }
         CurrentState::ImportSeed {
             mnemonic,
             network,
             password,
+            disable_seed_export,
         } => {
             peripherals
                 .nfc
@@ -229,19 +424,56 @@ pub async fn dispatch_handler(
                 .await
                 .unwrap();
 
-            init::handle_import_seed(&mnemonic, network, password.as_deref(), events, peripherals)
-                .await
+            init::handle_import_seed(
+                &mnemonic,
+                network,
+                password.as_deref(),
+                disable_seed_export,
+                events,
+                peripherals,
+            )
+            .await
         }
         CurrentState::Idle { ref mut wallet } => {
             idle::handle_idle(wallet, events, peripherals).await
         }
-        CurrentState::WaitingForPsbt { ref mut wallet } => {
-            bitcoin::handle_waiting_for_psbt(wallet, events, peripherals).await
+        CurrentState::WaitingForPsbt {
+            ref mut wallet,
+            kind,
+        } => bitcoin::handle_waiting_for_psbt(wallet, kind, events, peripherals).await,
+        CurrentState::SignPsbt {
+            ref mut wallet,
+            psbt,
+            kind: bitcoin::PsbtKind::Payjoin,
+            reply_options,
+        } => bitcoin::handle_sign_payjoin(wallet, psbt, reply_options, events, peripherals).await,
+        CurrentState::SignPsbt {
+            ref mut wallet,
+            psbt,
+            kind: bitcoin::PsbtKind::PayjoinReceiver,
+            reply_options,
+        } => {
+            bitcoin::handle_sign_payjoin_receiver(wallet, psbt, reply_options, events, peripherals)
+                .await
+        }
+        CurrentState::SignPsbt {
+            ref mut wallet,
+            psbt,
+            kind: bitcoin::PsbtKind::ProofOfReserves,
+            reply_options,
+        } => {
+            bitcoin::handle_sign_proof_of_reserves(wallet, psbt, reply_options, events, peripherals)
+                .await
         }
         CurrentState::SignPsbt {
             ref mut wallet,
             psbt,
-        } => bitcoin::handle_sign_request(wallet, &psbt, events, peripherals).await,
+            kind,
+            reply_options,
+        } => {
+            bitcoin::handle_sign_request(wallet, psbt, kind, reply_options, events, peripherals)
+                .await
+        }
         CurrentState::DisplayAddress {
             ref mut wallet,
             index,
@@ -254,12 +486,14 @@ pub async fn dispatch_handler(
             variant,
             script_type,
             bsms,
+            name,
         } => {
             bitcoin::handle_set_descriptor_request(
                 wallet,
                 variant,
                 script_type,
                 bsms,
+                name,
                 events,
                 peripherals,
             )
@@ -269,6 +503,71 @@ pub async fn dispatch_handler(
             ref mut wallet,
             derivation_path,
         } => bitcoin::handle_get_xpub_request(wallet, derivation_path, events, peripherals).await,
+        CurrentState::ExportBackup {
+            ref mut wallet,
+            password,
+            include_seed,
+        } => {
+            backup::handle_export_backup(wallet, &password, include_seed, events, peripherals).await
+        }
+        CurrentState::RestoreBackup {
+            wallet,
+            backup,
+            password,
+            pair_code,
+        } => {
+            backup::handle_restore_backup(
+                wallet,
+                &backup,
+                &password,
+                pair_code,
+                events,
+                peripherals,
+            )
+            .await
+        }
+        CurrentState::SelfTest { wallet } => {
+            selftest::handle_self_test(wallet, events, peripherals).await
+        }
+        CurrentState::Settings { ref mut wallet } => {
+            settings::handle_settings(wallet, events, peripherals).await
+        }
+        CurrentState::QuickInfo { ref mut wallet } => {
+            info::handle_quick_info(wallet, events, peripherals).await
+        }
+        CurrentState::SetSeedExport {
+            ref mut wallet,
+            allowed,
+        } => backup::handle_set_seed_export(wallet, allowed, events, peripherals).await,
+        CurrentState::SetConfirmationPolicy {
+            ref mut wallet,
+            policy,
+        } => policy::handle_set_confirmation_policy(wallet, policy, events, peripherals).await,
+        CurrentState::IdentifyHost {
+            ref mut wallet,
+            pubkey,
+            signature,
+        } => host::handle_identify_host(wallet, &pubkey, &signature, events, peripherals).await,
+        CurrentState::AddTrustedAddress {
+            ref mut wallet,
+            address,
+        } => address_book::handle_add_trusted_address(wallet, &address, events, peripherals).await,
+        CurrentState::GetLogs { ref mut wallet } => {
+            info::handle_get_logs(wallet, events, peripherals).await
+        }
+        CurrentState::SignMessage {
+            ref mut wallet,
+            index,
+            message,
+            format,
+        } => {
+            bitcoin::handle_sign_message(wallet, index, message, format, events, peripherals).await
+        }
+        CurrentState::DeriveNodeSeed {
+            ref mut wallet,
+            index,
+        } => lightning::handle_derive_node_seed(wallet, index, events, peripherals).await,
+        CurrentState::Wipe { previous } => wipe::handle_wipe(previous, events, peripherals).await,
         CurrentState::UpdatingFw { header } => {
             fwupdate::handle_begin_fw_update(&header, events, peripherals).await
         }
@@ -285,27 +584,63 @@ pub async fn dispatch_handler(
 
     *current_state = match result {
         Ok(new_state) => new_state,
+        Err(Error::Timeout) => handle_timeout(wallet, from_device, peripherals).await,
         Err(e) => handle_error(e, peripherals).await,
     }
 }
 
+/// Abort an operation that waited too long for the user
+///
+/// The host is told with `ErrorCode::Timeout` and the device goes back to idle, or starts over
+/// from the config if no wallet was loaded yet. Settings tried out in the menu are reverted.
+async fn handle_timeout(
+    wallet: Option<Rc<PortalWallet>>,
+    from_device: bool,
+    peripherals: &mut HandlerPeripherals,
+) -> CurrentState {
+    log::warn!("Operation timed out");
+
+    if !from_device {
+        peripherals
+            .nfc
+            .send(Reply::error(model::ErrorCode::Timeout))
+            .await
+            .unwrap();
+        peripherals.nfc_finished.recv().await.unwrap();
+    }
+
+    match wallet {
+        Some(wallet) => {
+            if let Err(e) = apply_settings(peripherals, wallet.config.settings) {
+                handle_error(e, peripherals).await
+            }
+            CurrentState::Idle { wallet }
+        }
+        None => CurrentState::POR,
+    }
+}
+
 async fn handle_error(err: Error, peripherals: &mut HandlerPeripherals) -> ! {
     #[cfg(feture = "panic-log")]
     log::error!("{:?}", _err);
 
     let try_draw_message = |peripherals: &mut HandlerPeripherals| -> Result<(), Error> {
         let error_msg = match err {
-            Error::InvalidFirmware => "Invalid Firmware",
-            Error::InvalidPassword => "Invalid Pair Code",
+            Error::InvalidFirmware => Label::InvalidFirmware.get(),
+            Error::InvalidPassword => Label::InvalidPairCode.get(),
             Error::BrokenProtocol
             | Error::HandshakeError
             | Error::LostRf
             | Error::TooManyNacks
-            | Error::Message(_) => "Communication Error",
-            Error::Config(_) | Error::FlashError => "Memory Error",
-            Error::Display(_) | Error::I2c(_) => "Display Error",
-            Error::Wallet => "Wallet Error",
-            Error::Unknown => "General Failure",
+            | Error::Message(_) => Label::CommunicationError.get(),
+            Error::Config(_) | Error::FlashError => Label::MemoryError.get(),
+            Error::Display(_) => Label::DisplayError.get(),
+            #[cfg(not(test))]
+            Error::I2c(_) => Label::DisplayError.get(),
+            Error::Wallet => Label::WalletError.get(),
+            Error::Unknown => Label::GeneralFailure.get(),
+            Error::RngFailure => Label::RngFailure.get(),
+            Error::Timeout => Label::TimedOut.get(),
         };
 
         let page = ErrorPage::new(error_msg);
@@ -329,12 +664,29 @@ async fn manage_confirmation_loop<'s, C: MainContent>(
     #[cfg(feature = "device")]
     let mut released_first = false;
     let mut pressing = false;
+    let mut ticks = 0;
     let mut draw;
 
+    let timeout_ticks = peripherals
+        .settings
+        .operation_timeout
+        .seconds()
+        .map(|seconds| seconds as usize * 1000 / crate::TIMER_TICK_MILLIS as usize);
+    let mut idle_ticks = 0;
+
     while !page.is_confirmed() {
         draw = false;
 
-        match events.next().await.expect("Event") {
+        let event = events.next().await.expect("Event");
+        match &event {
+            Event::Tick if !pressing => idle_ticks += 1,
+            _ => idle_ticks = 0,
+        }
+        if matches!(timeout_ticks, Some(timeout) if idle_ticks >= timeout) {
+            return Err(Error::Timeout);
+        }
+
+        match event {
             Event::Request(_) => {
                 peripherals
                     .nfc
@@ -355,10 +707,13 @@ async fn manage_confirmation_loop<'s, C: MainContent>(
                 }
             }
             Event::Tick => {
-                draw = page.tick();
+                ticks += 1;
+                for _ in 0..peripherals.settings.scroll_speed.steps(ticks) {
+                    draw |= page.tick();
+                }
 
                 if pressing {
-                    page.add_confirm(15);
+                    page.add_confirm(peripherals.settings.confirm_speed.step());
                     draw = true;
                 }
             }
@@ -373,3 +728,81 @@ async fn manage_confirmation_loop<'s, C: MainContent>(
 
     Ok(())
 }
+
+/// Show a `title` and its `value`, holding the button moves to the next page
+async fn confirm_page(
+    title: &str,
+    value: &str,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), crate::Error> {
+    let mut page = GenericTwoLinePage::new(title, value, Label::HoldForNextPage.get(), 50);
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(events, peripherals, &mut page).await
+}
+
+/// Like `confirm_page`, with a `note` in a smaller font below the value
+async fn confirm_page_with_note(
+    title: &str,
+    value: &str,
+    note: &str,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), crate::Error> {
+    let mut page = GenericThreeLinePage::new(title, value, note, Label::HoldForNextPage.get(), 50);
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(events, peripherals, &mut page).await
+}
+
+/// Warn the user about a key derived outside of the standard templates, see `model::paths`
+async fn warn_non_standard_path(
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), crate::Error> {
+    confirm_page(
+        Label::Warning.get(),
+        Label::NonStandardPath.get(),
+        events,
+        peripherals,
+    )
+    .await
+}
+
+/// Show `text` with the large font, split over as many pages as needed
+async fn confirm_large_text(
+    title: &str,
+    text: &str,
+    idle_text: &str,
+    threshold: u32,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), crate::Error> {
+    let mut page = LargeTextPage::new(title, text, idle_text, threshold);
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(events, peripherals, &mut page).await
+}
+
+/// Show an address, using the text size chosen in the settings
+async fn confirm_address(
+    address: &str,
+    message: &str,
+    bar_message: &'static str,
+    events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<(), crate::Error> {
+    if peripherals.settings.text_size == model::settings::TextSize::Large {
+        return confirm_large_text(message, address, bar_message, 100, events, peripherals).await;
+    }
+
+    let mut page = ShowScrollingAddressPage::new(address, message, bar_message);
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(events, peripherals, &mut page).await
+}
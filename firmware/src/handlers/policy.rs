@@ -0,0 +1,117 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::ToString;
+
+use futures::prelude::*;
+
+use gui::{i18n::Label, LoadingPage, Page, SummaryPage};
+use model::{Config, ConfirmationPolicy, ErrorCode, Reply};
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+/// Change which requests are answered without a confirmation
+///
+/// Without a pair code anyone nearby could talk to the device, so confirmations can only be
+/// skipped on devices that have one.
+pub async fn handle_set_confirmation_policy(
+    wallet: &mut Rc<PortalWallet>,
+    policy: ConfirmationPolicy,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_set_confirmation_policy");
+
+    if wallet.config.confirmation_policy() == policy {
+        peripherals.nfc.send(Reply::Ok).await.unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+    if policy.is_relaxed() && !wallet.config.has_pair_code() {
+        peripherals
+            .nfc
+            .send(Reply::error(ErrorCode::PairCodeRequired))
+            .await
+            .unwrap();
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    peripherals.nfc.send(Reply::DelayedReply).await.unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    let describe = |silent| {
+        if silent {
+            Label::NoConfirmation.get()
+        } else {
+            Label::Confirm.get()
+        }
+    };
+    confirm_page(
+        Label::ExportXpub.get(),
+        describe(policy.silent_get_xpub),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    confirm_page(
+        Label::ExportDescriptor.get(),
+        describe(policy.silent_public_descriptor),
+        &mut events,
+        peripherals,
+    )
+    .await?;
+    let fingerprint = wallet.xprv.fingerprint(wallet.secp_ctx()).to_string();
+    confirm_page(Label::Wallet.get(), &fingerprint, &mut events, peripherals).await?;
+
+    let mut page = SummaryPage::new(
+        Label::ChangeConfirmations.get(),
+        Label::HoldToApplyChanges.get(),
+    );
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let mut config = wallet.config.clone();
+    config.secret.confirmation_policy = Some(policy);
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(config.clone().lock())),
+    )
+    .await?;
+    log::debug!("Confirmation policy: {:?}", policy);
+
+    let new_wallet = super::init::make_wallet_from_xprv(wallet.xprv, wallet.network(), config)?;
+    peripherals.nfc.send(Reply::Ok).await.unwrap();
+
+    Ok(CurrentState::Idle {
+        wallet: Rc::new(new_wallet),
+    })
+}
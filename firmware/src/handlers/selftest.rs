@@ -0,0 +1,141 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::rc::Rc;
+
+use futures::prelude::*;
+
+use gui::{i18n::Label, Page, SingleLineTextPage, TestPattern, TestPatternPage};
+use model::selftest::{SelfTestReport, TestOutcome};
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+/// How long every test pattern stays on the screen
+const PATTERN_TICKS: usize = 2;
+/// How long we wait for the user to touch the button
+const TOUCH_TIMEOUT_TICKS: usize = 20;
+
+fn draw_page(page: &impl Page, peripherals: &mut HandlerPeripherals) -> Result<(), Error> {
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    Ok(())
+}
+
+/// Wait for `ticks` ticks, returning early with `true` if the user touches the button
+async fn wait_for_touch(
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+    mut ticks: usize,
+) -> bool {
+    while ticks > 0 {
+        match events.next().await.expect("Event") {
+            Event::Request(_) => {
+                peripherals
+                    .nfc
+                    .send(Reply::Busy)
+                    .await
+                    .expect("Send should work");
+            }
+            Event::Input(true) => return true,
+            Event::Tick => ticks -= 1,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// Write the current config again, which is read back and verified
+///
+/// A new device has nothing to write, so the test is skipped.
+async fn test_flash(has_config: bool, peripherals: &mut HandlerPeripherals) -> TestOutcome {
+    if !has_config {
+        return TestOutcome::Skipped;
+    }
+
+    let result = match config::read_config(&mut peripherals.flash).await {
+        Ok(config) => config::write_config(&mut peripherals.flash, &config).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => TestOutcome::Passed,
+        Err(e) => TestOutcome::Failed(alloc::format!("{:?}", e)),
+    }
+}
+
+/// Run the hardware self-test and reply with the report
+///
+/// `wallet` is the unlocked wallet to go back to, if any.
+pub async fn handle_self_test(
+    wallet: Option<Rc<PortalWallet>>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_self_test");
+
+    peripherals
+        .nfc
+        .send(model::Reply::DelayedReply)
+        .await
+        .unwrap();
+
+    let flash = test_flash(wallet.is_some(), peripherals).await;
+    let rng = hw::rng_self_test();
+
+    let mut display = TestOutcome::Passed;
+    for pattern in [TestPattern::Filled, TestPattern::Checkerboard] {
+        if let Err(e) = draw_page(&TestPatternPage::new(pattern), peripherals) {
+            display = TestOutcome::Failed(alloc::format!("{:?}", e));
+            break;
+        }
+        wait_for_touch(&mut events, peripherals, PATTERN_TICKS).await;
+    }
+
+    // The touch controller is only enabled now, so the patterns can't be skipped
+    peripherals.tsc_enabled.enable();
+    let _ = draw_page(
+        &SingleLineTextPage::new(Label::TouchButton.get()),
+        peripherals,
+    );
+    let touch = if wait_for_touch(&mut events, peripherals, TOUCH_TIMEOUT_TICKS).await {
+        TestOutcome::Passed
+    } else {
+        TestOutcome::Failed("No touch detected".into())
+    };
+
+    let report = SelfTestReport {
+        flash,
+        rng,
+        display,
+        touch,
+    };
+    log::debug!("Self-test report: {:?}", report);
+    peripherals
+        .nfc
+        .send(model::Reply::SelfTest(report))
+        .await
+        .unwrap();
+
+    Ok(match wallet {
+        Some(wallet) => CurrentState::Idle { wallet },
+        None => CurrentState::Init,
+    })
+}
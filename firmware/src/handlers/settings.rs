@@ -0,0 +1,362 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use futures::prelude::*;
+
+use gui::{i18n::Label, GenericTwoLinePage, LoadingPage, Page, SummaryPage};
+use model::settings::{SettingValue, TouchCalibration};
+use model::Config;
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+enum ButtonAction {
+    Tap,
+    Hold,
+}
+
+/// Wait until the button is either tapped, or held long enough to fill the confirmation bar
+///
+/// The menu is always entered with the button held down, so the first release is ignored.
+async fn wait_tap_or_hold<'s, C: MainContent>(
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+    page: &mut ConfirmBarPage<'s, C>,
+) -> Result<ButtonAction, Error> {
+    let mut released_first = false;
+    let mut pressing = false;
+
+    loop {
+        match events.next().await.expect("Event") {
+            Event::Request(_) => {
+                peripherals
+                    .nfc
+                    .send(Reply::Busy)
+                    .await
+                    .expect("Send should work");
+            }
+            Event::Input(v) if !released_first => {
+                released_first = !v;
+            }
+            Event::Input(v) if v != pressing => {
+                pressing = v;
+                if !v {
+                    return Ok(ButtonAction::Tap);
+                }
+            }
+            Event::Tick if pressing => {
+                if page.add_confirm(peripherals.settings.confirm_speed.step()) {
+                    return Ok(ButtonAction::Hold);
+                }
+
+                page.draw_to(&mut peripherals.display)?;
+                peripherals.display.flush()?;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ticks the touch sensor is sampled for, in each step of the calibration
+const CALIBRATION_TICKS: usize = 8;
+
+/// Sample the touch sensor at every tick, showing `message` in the meantime
+///
+/// The button is not expected to react while this runs, so every input is ignored.
+async fn sample_touch(
+    message: &str,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<Vec<u16>, Error> {
+    let page = GenericTwoLinePage::new(
+        Label::TouchCalibration.get(),
+        message,
+        Label::Measuring.get(),
+        50,
+    );
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let mut samples = Vec::with_capacity(CALIBRATION_TICKS);
+    while samples.len() < CALIBRATION_TICKS {
+        match events.next().await.expect("Event") {
+            Event::Request(_) => {
+                peripherals
+                    .nfc
+                    .send(Reply::Busy)
+                    .await
+                    .expect("Send should work");
+            }
+            Event::Tick => samples.push(peripherals.tsc_enabled.last_reading()),
+            Event::Input(_) => {}
+        }
+    }
+
+    samples.sort_unstable();
+    Ok(samples)
+}
+
+/// Measure the readings of the touch sensor with and without a finger on the button
+///
+/// The first samples of each step are taken while the user is still moving the finger, so only
+/// the steadiest half is kept: the highest readings without a touch and the lowest with it.
+/// Returns `None` if the two are too close to be told apart.
+async fn calibrate_touch(
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<Option<TouchCalibration>, Error> {
+    let average = |samples: &[u16]| {
+        (samples.iter().map(|s| *s as u32).sum::<u32>() / samples.len() as u32) as u16
+    };
+
+    let released = sample_touch(Label::ReleaseButton.get(), &mut events, peripherals).await?;
+    let touched = sample_touch(Label::TouchAndHoldButton.get(), &mut events, peripherals).await?;
+
+    let calibration = TouchCalibration::new(
+        average(&released[CALIBRATION_TICKS / 2..]),
+        average(&touched[..CALIBRATION_TICKS / 2]),
+    );
+    log::debug!("Touch calibration: {:?}", calibration);
+
+    Ok(calibration)
+}
+
+/// Show the current `value`, tapping the button moves to the next one and holding it confirms
+async fn choose_value<T: SettingValue>(
+    title: &str,
+    mut value: T,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<T, Error> {
+    loop {
+        let mut page =
+            GenericTwoLinePage::new(title, value.name(), Label::TapChangeHoldNext.get(), 50);
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+
+        match wait_tap_or_hold(&mut events, peripherals, &mut page).await? {
+            ButtonAction::Tap => value = value.next(),
+            ButtonAction::Hold => return Ok(value),
+        }
+    }
+}
+
+pub async fn handle_settings(
+    wallet: &mut Rc<PortalWallet>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_settings");
+
+    peripherals.tsc_enabled.enable();
+
+    let current = wallet.config.settings;
+    let mut settings = model::settings::DeviceSettings {
+        confirm_speed: choose_value(
+            Label::ConfirmSpeed.get(),
+            current.confirm_speed,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        scroll_speed: choose_value(
+            Label::ScrollSpeed.get(),
+            current.scroll_speed,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        auto_lock: choose_value(
+            Label::AutoLock.get(),
+            current.auto_lock,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        brightness: choose_value(
+            Label::Brightness.get(),
+            current.brightness,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        language: choose_value(
+            Label::Language.get(),
+            current.language,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        text_size: choose_value(
+            Label::TextSize.get(),
+            current.text_size,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        idle_screen: choose_value(
+            Label::IdleScreen.get(),
+            current.idle_screen,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        review_inputs: choose_value(
+            Label::ReviewInputs.get(),
+            current.review_inputs,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        touch_sensitivity: choose_value(
+            Label::TouchSensitivity.get(),
+            current.touch_sensitivity,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        touch_calibration: current.touch_calibration,
+        operation_timeout: choose_value(
+            Label::Timeout.get(),
+            current.operation_timeout,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        debug_logs: choose_value(
+            Label::DebugLogs.get(),
+            current.debug_logs,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        fee_warning_percent: choose_value(
+            Label::FeeWarning.get(),
+            current.fee_warning_percent,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        fee_warning_rate: choose_value(
+            Label::FeeRateWarning.get(),
+            current.fee_warning_rate,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        non_default_sighash: choose_value(
+            Label::OtherSighashes.get(),
+            current.non_default_sighash,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        utxo_verification: choose_value(
+            Label::UtxoChecks.get(),
+            current.utxo_verification,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        change_outputs: choose_value(
+            Label::ChangeOutputs.get(),
+            current.change_outputs,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+        amount_unit: choose_value(
+            Label::AmountUnit.get(),
+            current.amount_unit,
+            &mut events,
+            peripherals,
+        )
+        .await?,
+    };
+
+    let mut page = SummaryPage::new(Label::CalibrateTouch.get(), Label::TapSkipHoldStart.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    let calibration = match wait_tap_or_hold(&mut events, peripherals, &mut page).await? {
+        ButtonAction::Hold => Some(calibrate_touch(&mut events, peripherals).await?),
+        ButtonAction::Tap => None,
+    };
+    if let Some(Some(calibration)) = calibration {
+        settings.touch_calibration = Some(calibration);
+    }
+    // Use the new threshold right away, so that it can be tried out on the next pages
+    peripherals
+        .tsc_enabled
+        .set_threshold(settings.touch_threshold());
+
+    if let Some(calibration) = calibration {
+        let result = match calibration {
+            Some(_) => Label::Calibrated.get(),
+            None => Label::CalibrationFailed.get(),
+        };
+        let mut page = GenericTwoLinePage::new(
+            Label::TouchCalibration.get(),
+            result,
+            Label::HoldToContinue.get(),
+            50,
+        );
+        page.init_display(&mut peripherals.display)?;
+        page.draw_to(&mut peripherals.display)?;
+        peripherals.display.flush()?;
+        manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+    }
+
+    let mut page = SummaryPage::new(Label::SaveSettings.get(), Label::TapDiscardHoldSave.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    if let ButtonAction::Tap = wait_tap_or_hold(&mut events, peripherals, &mut page).await? {
+        log::debug!("Settings discarded");
+        apply_settings(peripherals, current)?;
+        return Ok(CurrentState::Idle {
+            wallet: Rc::clone(wallet),
+        });
+    }
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let mut config = wallet.config.clone();
+    config.settings = settings;
+    config::write_config(
+        &mut peripherals.flash,
+        &Config::Initialized(Box::new(config.clone().lock())),
+    )
+    .await?;
+    apply_settings(peripherals, settings)?;
+    log::debug!("Settings saved: {:?}", settings);
+
+    let new_wallet = super::init::make_wallet_from_xprv(wallet.xprv, wallet.network(), config)?;
+    Ok(CurrentState::Idle {
+        wallet: Rc::new(new_wallet),
+    })
+}
@@ -0,0 +1,991 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use futures::executor::block_on;
+use futures::future::Either;
+
+use gui::SummaryPage;
+use model::bitcoin::Network;
+use model::mnemonic::MnemonicLanguage;
+use model::{
+    Entropy, FwVariant, InitializationStatus, Request, SecretData, UnlockedConfig, WalletDescriptor,
+};
+
+use super::*;
+use crate::mock::{self, hw};
+use crate::version;
+
+fn make_wallet(network: Network) -> Rc<PortalWallet> {
+    let xprv = bip32::ExtendedPrivKey::new_master(network, &[0x42; 32]).unwrap();
+    let secret = SecretData {
+        mnemonic: Entropy {
+            bytes: alloc::vec![0x42; 16].into(),
+            language: None,
+        },
+        cached_xprv: xprv.into(),
+        descriptor: WalletDescriptor::make_bip84(network),
+        disable_seed_export: None,
+        confirmation_policy: None,
+        derived_keys: None,
+        trusted_hosts: None,
+        trusted_addresses: None,
+        wallet_policy: None,
+    };
+    let config = UnlockedConfig::from_secret_data_unencrypted(secret, network);
+
+    Rc::new(init::make_wallet_from_xprv(xprv, network, config).unwrap())
+}
+
+#[test]
+fn test_derived_keys_cache() {
+    let secp = bdk::bitcoin::secp256k1::Secp256k1::new();
+    let mut wallet = make_wallet(Network::Signet);
+
+    let account = "m/84'/1'/0'".parse::<bip32::DerivationPath>().unwrap();
+    let cache = wallet.config.secret.derived_keys.clone().unwrap();
+    assert_eq!(
+        cache.account_key(&account),
+        Some(wallet.xprv.derive_priv(&secp, &account).unwrap())
+    );
+
+    // Same address as the one derived from the master key
+    let path = "m/84'/1'/0'/0/0".parse::<bip32::DerivationPath>().unwrap();
+    let key = wallet.xprv.derive_priv(&secp, &path).unwrap();
+    let expected = bdk::bitcoin::Address::p2wpkh(
+        &bdk::bitcoin::PublicKey::new(key.private_key.public_key(&secp)),
+        Network::Signet,
+    )
+    .unwrap();
+    let address = Rc::get_mut(&mut wallet)
+        .unwrap()
+        .get_address(bdk::wallet::AddressIndex::Peek(0))
+        .address;
+    assert_eq!(address, expected);
+}
+
+#[test]
+fn test_por_empty_flash() {
+    let (mut peripherals, _host) = mock::make_peripherals(hw::Flash::empty());
+
+    let state = block_on(init::handle_por(&mut peripherals)).unwrap();
+    assert!(matches!(state, CurrentState::Init));
+    assert!(peripherals.display.flush_count > 0);
+}
+
+#[test]
+fn test_init_get_info() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let events = mock::events([Event::Request(Request::GetInfo)]);
+
+    let handler = init::handle_init(events, &mut peripherals);
+    pin_mut!(handler);
+
+    match mock::run_until_reply(handler.as_mut(), &mut host) {
+        Either::Right(Reply::Info(info)) => {
+            assert!(matches!(
+                info.initialized,
+                InitializationStatus::Uninitialized
+            ))
+        }
+        _ => panic!("Expected Reply::Info"),
+    }
+}
+
+#[test]
+fn test_init_generate_mnemonic() {
+    let (mut peripherals, _host) = mock::make_peripherals(hw::Flash::empty());
+    let events = mock::events([Event::Request(Request::GenerateMnemonic {
+        num_words: NumWordsMnemonic::Words12,
+        network: Network::Signet,
+        password: None,
+        disable_seed_export: None,
+        language: Some(MnemonicLanguage::Spanish),
+    })]);
+
+    let state = block_on(init::handle_init(events, &mut peripherals)).unwrap();
+    assert!(matches!(
+        state,
+        CurrentState::GenerateSeed {
+            num_words: NumWordsMnemonic::Words12,
+            network: Network::Signet,
+            password: None,
+            disable_seed_export: false,
+            language: MnemonicLanguage::Spanish,
+        }
+    ));
+}
+
+#[test]
+fn test_import_invalid_mnemonic() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+
+    // Valid words, wrong checksum
+    let handler = init::handle_import_seed(
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon",
+        Network::Signet,
+        None,
+        false,
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidSeed),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Init))
+    ));
+}
+
+#[test]
+fn test_confirmation_loop() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut events = mock::events(
+        [Event::Input(true), Event::Request(Request::GetInfo)]
+            .into_iter()
+            .chain(core::iter::repeat_with(|| Event::Tick).take(5)),
+    );
+
+    let mut page = SummaryPage::new_with_threshold("Test", "HOLD BTN", 70);
+    block_on(manage_confirmation_loop(
+        &mut events,
+        &mut peripherals,
+        &mut page,
+    ))
+    .unwrap();
+
+    assert!(page.is_confirmed());
+    assert!(!peripherals.display.is_blank());
+    assert!(matches!(host.replies.try_recv(), Ok(Reply::Busy)));
+}
+
+#[test]
+fn test_confirmation_loop_timeout() {
+    let (mut peripherals, _host) = mock::make_peripherals(hw::Flash::empty());
+    peripherals.settings.operation_timeout = model::settings::OperationTimeout::OneMinute;
+    let timeout_ticks = 60 * 1000 / crate::TIMER_TICK_MILLIS as usize;
+
+    // Touching the button restarts the window
+    let mut events = mock::events(
+        core::iter::repeat_with(|| Event::Tick)
+            .take(timeout_ticks - 1)
+            .chain([Event::Input(true), Event::Input(false)])
+            .chain(core::iter::repeat_with(|| Event::Tick).take(timeout_ticks)),
+    );
+
+    let mut page = SummaryPage::new_with_threshold("Test", "HOLD BTN", 70);
+    let result = block_on(manage_confirmation_loop(
+        &mut events,
+        &mut peripherals,
+        &mut page,
+    ));
+
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert!(!page.is_confirmed());
+}
+
+#[test]
+fn test_dispatch_timeout() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    peripherals.settings.operation_timeout = model::settings::OperationTimeout::OneMinute;
+    let timeout_ticks = 60 * 1000 / crate::TIMER_TICK_MILLIS as usize;
+
+    let mut state = CurrentState::DisplayAddress {
+        wallet: make_wallet(Network::Signet),
+        index: 0,
+    };
+    let events = mock::events(core::iter::repeat_with(|| Event::Tick).take(timeout_ticks));
+    {
+        let handler = dispatch_handler(&mut state, events, &mut peripherals);
+        pin_mut!(handler);
+
+        assert!(matches!(
+            mock::run_until_reply(handler.as_mut(), &mut host),
+            Either::Right(Reply::DelayedReply)
+        ));
+        assert!(matches!(
+            mock::run_until_reply(handler.as_mut(), &mut host),
+            Either::Right(Reply::Error {
+                code: Some(model::ErrorCode::Timeout),
+                ..
+            })
+        ));
+        assert!(matches!(
+            mock::run_until_reply(handler.as_mut(), &mut host),
+            Either::Left(())
+        ));
+    }
+    assert!(matches!(state, CurrentState::Idle { .. }));
+}
+
+#[test]
+fn test_sign_invalid_psbt() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+
+    let mut psbt = model::psbt_stream::PsbtStream::new();
+    psbt.push(&[0x70, 0x73, 0x62, 0x74, 0xFF]);
+    let handler = bitcoin::handle_sign_request(
+        &mut wallet,
+        psbt,
+        bitcoin::PsbtKind::Payment,
+        bitcoin::ReplyOptions::default(),
+        mock::events(core::iter::repeat_with(|| Event::Tick).take(bitcoin::INVALID_TX_TICKS)),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidPsbt),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_sign_payjoin_without_payment() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+    assert!(peripherals.last_payment.is_none());
+
+    let mut psbt = model::psbt_stream::PsbtStream::new();
+    psbt.push(&[0x70, 0x73, 0x62, 0x74, 0xFF]);
+    let handler = bitcoin::handle_sign_payjoin(
+        &mut wallet,
+        psbt,
+        bitcoin::ReplyOptions::default(),
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidPsbt),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sign_payjoin_receiver_without_original() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+    assert!(peripherals.payjoin_original.is_none());
+
+    let mut psbt = model::psbt_stream::PsbtStream::new();
+    psbt.push(&[0x70, 0x73, 0x62, 0x74, 0xFF]);
+    let handler = bitcoin::handle_sign_payjoin_receiver(
+        &mut wallet,
+        psbt,
+        bitcoin::ReplyOptions::default(),
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidPsbt),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sign_proof_of_reserves_wrong_message() {
+    use model::bitcoin::{PackedLockTime, Transaction, TxIn, TxOut};
+
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+    peripherals.reserves_message = Some("Reserves of 2024-01-01".into());
+
+    // The first input commits to another message
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: alloc::vec![
+            TxIn {
+                previous_output: model::psbt::reserves_commitment("Another message"),
+                ..Default::default()
+            },
+            TxIn::default(),
+        ],
+        output: alloc::vec![TxOut::default()],
+    };
+    let data = model::bitcoin::consensus::encode::serialize(
+        &model::bitcoin::util::psbt::PartiallySignedTransaction::from_unsigned_tx(tx).unwrap(),
+    );
+    let mut psbt = model::psbt_stream::PsbtStream::new();
+    psbt.push(&data);
+    let handler = bitcoin::handle_sign_proof_of_reserves(
+        &mut wallet,
+        psbt,
+        bitcoin::ReplyOptions::default(),
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidPsbt),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sign_batch_next_psbt() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+
+    // The previous transaction of the batch was answered with its signatures, so the next one is
+    // expected right away
+    let batch = bitcoin::SignBatch::new(2).add(1000);
+    let handler = bitcoin::handle_waiting_for_psbt(
+        &mut wallet,
+        bitcoin::PsbtKind::Batch(batch),
+        mock::events([Event::Request(Request::SignPsbt(
+            alloc::vec![0x70, 0x73, 0x62, 0x74, 0xFF].into(),
+            None,
+            None,
+        ))]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::SignPsbt {
+            kind: bitcoin::PsbtKind::Batch(_),
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_sign_psbt_parts() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+
+    let batch = bitcoin::SignBatch::new(2).add(1000);
+    let handler = bitcoin::handle_waiting_for_psbt(
+        &mut wallet,
+        bitcoin::PsbtKind::Batch(batch),
+        mock::events([
+            Event::Request(Request::SignPsbtPart(alloc::vec![0x70, 0x73].into())),
+            Event::Request(Request::SignPsbt(
+                alloc::vec![0x62, 0x74, 0xFF].into(),
+                Some(true),
+                None,
+            )),
+        ]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    // Every part is acknowledged, the last one comes with `SignPsbt`
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Ok)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::SignPsbt {
+            kind: bitcoin::PsbtKind::Batch(_),
+            reply_options: bitcoin::ReplyOptions {
+                full_psbt: true,
+                finalize: false,
+            },
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_sign_message_legacy_taproot() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+    Rc::get_mut(&mut wallet)
+        .unwrap()
+        .config
+        .secret
+        .descriptor
+        .script_type = model::ScriptType::Taproot;
+
+    let handler = bitcoin::handle_sign_message(
+        &mut wallet,
+        0,
+        "Hello World".into(),
+        bitcoin::MessageFormat::Legacy,
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::UnsupportedDescriptor),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_fw_update_downgrade() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    peripherals.flash.min_version = version::CURRENT_VERSION + 2;
+
+    for version in [version::CURRENT_VERSION, version::CURRENT_VERSION + 1] {
+        let header = FwUpdateHeader {
+            variant: FwVariant::VANILLA,
+            signature: Box::new([0; 64].into()),
+            size: 4096,
+            first_page_midstate: Box::new([0; 32].into()),
+            version,
+            hash: Box::new([0; 32].into()),
+        };
+
+        let handler = fwupdate::handle_begin_fw_update(&header, mock::events([]), &mut peripherals);
+        pin_mut!(handler);
+
+        assert!(matches!(
+            mock::run_until_reply(handler.as_mut(), &mut host),
+            Either::Right(Reply::Error {
+                code: Some(model::ErrorCode::FirmwareTooOld),
+                ..
+            })
+        ));
+        assert!(matches!(
+            mock::run_until_reply(handler.as_mut(), &mut host),
+            Either::Left(Err(Error::InvalidFirmware))
+        ));
+    }
+}
+
+#[test]
+fn test_fw_update_offset_fresh() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let header = FwUpdateHeader {
+        variant: FwVariant::VANILLA,
+        signature: Box::new([0; 64].into()),
+        size: 4096,
+        first_page_midstate: Box::new([0; 32].into()),
+        version: version::CURRENT_VERSION + 1,
+        hash: Box::new([0; 32].into()),
+    };
+
+    let handler = fwupdate::handle_get_fw_update_offset(&header, &mut peripherals);
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::NextPage(1))
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(()))
+    ));
+}
+
+#[test]
+fn test_attest_unprovisioned() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+
+    let handler = attestation::handle_attest(&[0x42; 32], &mut peripherals);
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::NotProvisioned),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_provision_and_attest() {
+    use model::attestation::{AttestationCertificate, AttestationKey};
+    use model::bitcoin::secp256k1::{KeyPair, Secp256k1};
+
+    let ctx = Secp256k1::new();
+    let root = KeyPair::from_seckey_slice(&ctx, &[0x01; 32]).unwrap();
+    let device = KeyPair::from_seckey_slice(&ctx, &[0x02; 32]).unwrap();
+    let key = AttestationKey {
+        secret_key: Box::new(device.secret_bytes().into()),
+        chain: alloc::vec![AttestationCertificate::new(
+            &ctx,
+            &root,
+            &device.x_only_public_key().0
+        )],
+    };
+
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    for expected_ok in [true, false] {
+        let handler = attestation::handle_provision_attestation(&key, &mut peripherals);
+        pin_mut!(handler);
+
+        match mock::run_until_reply(handler.as_mut(), &mut host) {
+            Either::Right(Reply::Ok) => assert!(expected_ok),
+            Either::Right(Reply::Error { .. }) => assert!(!expected_ok),
+            _ => panic!("Unexpected reply"),
+        }
+    }
+
+    let handler = attestation::handle_attest(&[0x42; 32], &mut peripherals);
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    match mock::run_until_reply(handler.as_mut(), &mut host) {
+        Either::Right(Reply::Attestation(attestation)) => {
+            attestation
+                .verify(&ctx, &root.x_only_public_key().0, &[0x42; 32])
+                .unwrap();
+        }
+        _ => panic!("Expected Reply::Attestation"),
+    }
+}
+
+#[test]
+fn test_config_is_wrapped() {
+    let (mut peripherals, _host) = mock::make_peripherals(hw::Flash::empty());
+    let config = model::Config::Unverified(model::UnverifiedConfig {
+        entropy: Entropy {
+            bytes: alloc::vec![0x42; 16].into(),
+            language: None,
+        },
+        network: Network::Signet,
+        pair_code: None,
+        descriptor: WalletDescriptor::make_bip84(Network::Signet),
+        page: 0,
+        disable_seed_export: None,
+    });
+
+    block_on(crate::config::write_config(&mut peripherals.flash, &config)).unwrap();
+    assert!(model::keywrap::is_wrapped(&peripherals.flash.data));
+    assert!(!peripherals.flash.data.windows(16).any(|w| w == [0x42; 16]));
+
+    let state = block_on(init::handle_por(&mut peripherals)).unwrap();
+    assert!(matches!(state, CurrentState::UnverifiedConfig { .. }));
+}
+
+#[test]
+fn test_wipe_config_report() {
+    let (mut peripherals, _host) = mock::make_peripherals(hw::Flash::empty());
+    peripherals.flash.data = alloc::vec![0x42; 64];
+
+    let report = block_on(crate::config::wipe_config(&mut peripherals.flash, false)).unwrap();
+    assert!(peripherals.flash.data.is_empty());
+    assert_eq!(report.pages_erased, 1);
+    assert!(report.is_verified());
+
+    let report = block_on(crate::config::wipe_config(&mut peripherals.flash, false)).unwrap();
+    assert_eq!(report.pages_checked, 1);
+    assert_eq!(report.pages_erased, 0);
+}
+
+#[test]
+fn test_restore_backup_without_seed() {
+    use model::backup::{Backup, BackupContents, Kdf};
+
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let wallet = make_wallet(Network::Signet);
+    let contents = BackupContents {
+        network: Network::Signet,
+        descriptor: WalletDescriptor::make_bip84(Network::Signet),
+        fingerprint: wallet.xprv.fingerprint(wallet.secp_ctx()).into_bytes(),
+        mnemonic: None,
+    };
+    let kdf = Kdf::Pbkdf2Sha256 { iterations: 16 };
+    let backup = Backup::new(&contents, "password", kdf, [0x01; 16], [0x02; 12]).serialize();
+
+    // A new device can only restore a backup that includes the seed
+    let handler = backup::handle_restore_backup(
+        None,
+        &backup,
+        "password",
+        None,
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error { .. })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Init))
+    ));
+}
+
+#[test]
+fn test_export_backup_seed_disabled() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let wallet = make_wallet(Network::Signet);
+    let mut config = wallet.config.clone();
+    config.secret.disable_seed_export = Some(true);
+    let mut wallet =
+        Rc::new(init::make_wallet_from_xprv(wallet.xprv, Network::Signet, config).unwrap());
+
+    let handler = backup::handle_export_backup(
+        &mut wallet,
+        "password",
+        true,
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::SeedExportDisabled),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_wipe_wrong_code() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let events = mock::events([Event::Request(Request::ConfirmWipe {
+        code: "wrong".into(),
+    })]);
+    let previous = CurrentState::Idle {
+        wallet: make_wallet(Network::Signet),
+    };
+
+    let handler = wipe::handle_wipe(alloc::boxed::Box::new(previous), events, &mut peripherals);
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Ok)
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::WrongWipeCode),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_confirmation_policy_requires_pair_code() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+    let policy = model::ConfirmationPolicy {
+        silent_get_xpub: true,
+        silent_public_descriptor: false,
+    };
+
+    let handler = policy::handle_set_confirmation_policy(
+        &mut wallet,
+        policy,
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::PairCodeRequired),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_add_trusted_address_wrong_network() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+
+    let handler = address_book::handle_add_trusted_address(
+        &mut wallet,
+        "bc1q0hzrflaz2988h6zrne85tnq47k2grgarycgjrke7qje92wfxdzxq0ymtq9",
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidAddress),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_identify_host_without_challenge() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+
+    // A valid signature of a challenge the device never sent
+    let secp = model::bitcoin::secp256k1::Secp256k1::new();
+    let key = model::bitcoin::secp256k1::SecretKey::from_slice(&[0x42; 32]).unwrap();
+    let signature = secp
+        .sign_ecdsa(&model::host::challenge_message(&[0x01; 32]), &key)
+        .serialize_compact();
+
+    let handler = host::handle_identify_host(
+        &mut wallet,
+        &key.public_key(&secp).serialize(),
+        &signature,
+        mock::events([]),
+        &mut peripherals,
+    );
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::Error {
+            code: Some(model::ErrorCode::InvalidHostSignature),
+            ..
+        })
+    ));
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_derive_node_seed() {
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+    let hold = |ticks| {
+        [Event::Input(true)]
+            .into_iter()
+            .chain(core::iter::repeat_with(|| Event::Tick).take(ticks))
+    };
+    let events = mock::events(hold(4).chain(hold(4)).chain(hold(7)));
+    let expected = model::bip85::node_seed(wallet.secp_ctx(), &wallet.xprv, 1).unwrap();
+
+    let handler = lightning::handle_derive_node_seed(&mut wallet, 1, events, &mut peripherals);
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    match mock::run_until_reply(handler.as_mut(), &mut host) {
+        Either::Right(Reply::NodeSeed(seed)) => assert_eq!(&seed[..], &expected[..]),
+        _ => panic!("Expected Reply::NodeSeed"),
+    }
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Left(Ok(CurrentState::Idle { .. }))
+    ));
+}
+
+#[test]
+fn test_self_test() {
+    use model::selftest::TestOutcome;
+
+    let (mut peripherals, mut host) = mock::make_peripherals(hw::Flash::empty());
+    let events = mock::events(
+        core::iter::repeat_with(|| Event::Tick)
+            .take(4)
+            .chain([Event::Input(true)]),
+    );
+
+    let handler = selftest::handle_self_test(None, events, &mut peripherals);
+    pin_mut!(handler);
+
+    assert!(matches!(
+        mock::run_until_reply(handler.as_mut(), &mut host),
+        Either::Right(Reply::DelayedReply)
+    ));
+    match mock::run_until_reply(handler.as_mut(), &mut host) {
+        Either::Right(Reply::SelfTest(report)) => {
+            // Nothing to write back on a new device
+            assert_eq!(report.flash, TestOutcome::Skipped);
+            assert_eq!(report.display, TestOutcome::Passed);
+            assert_eq!(report.touch, TestOutcome::Passed);
+        }
+        _ => panic!("Expected Reply::SelfTest"),
+    }
+}
+
+#[test]
+fn test_settings() {
+    use model::settings::{
+        AmountUnit, Brightness, ChangeOutputs, ConfirmSpeed, DebugLogs, FeeWarningPercent,
+        FeeWarningRate, IdleScreen, NonDefaultSighash, OperationTimeout, ReviewInputs, ScrollSpeed,
+        TextSize, TouchSensitivity, UtxoVerification,
+    };
+
+    let (mut peripherals, _host) = mock::make_peripherals(hw::Flash::empty());
+    let mut wallet = make_wallet(Network::Signet);
+
+    // Every page starts by waiting for the button to be released, since it's held when moving to
+    // the next page
+    let tap = || [Event::Input(false), Event::Input(true), Event::Input(false)];
+    let hold = |ticks| {
+        [Event::Input(false), Event::Input(true)]
+            .into_iter()
+            .chain(core::iter::repeat_with(|| Event::Tick).take(ticks))
+    };
+    let events = mock::events(
+        tap()
+            .into_iter()
+            .chain(hold(4))
+            .chain(hold(4))
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(hold(4))
+            .chain(hold(4))
+            .chain(hold(4))
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(4))
+            .chain(tap())
+            .chain(hold(7)),
+    );
+
+    let state = block_on(settings::handle_settings(
+        &mut wallet,
+        events,
+        &mut peripherals,
+    ))
+    .unwrap();
+    assert!(matches!(state, CurrentState::Idle { .. }));
+
+    assert_eq!(peripherals.settings.confirm_speed, ConfirmSpeed::Fast);
+    assert_eq!(peripherals.settings.scroll_speed, ScrollSpeed::Normal);
+    assert_eq!(peripherals.display.brightness, Brightness::Medium);
+    assert_eq!(peripherals.settings.text_size, TextSize::Large);
+    assert_eq!(peripherals.settings.idle_screen, IdleScreen::Fingerprint);
+    assert_eq!(peripherals.settings.review_inputs, ReviewInputs::On);
+    assert_eq!(
+        peripherals.settings.touch_sensitivity,
+        TouchSensitivity::High
+    );
+    assert_eq!(peripherals.settings.touch_calibration, None);
+    assert_eq!(
+        peripherals.settings.operation_timeout,
+        OperationTimeout::FiveMinutes
+    );
+    assert_eq!(peripherals.settings.debug_logs, DebugLogs::On);
+    assert_eq!(
+        peripherals.settings.fee_warning_percent,
+        FeeWarningPercent::Ten
+    );
+    assert_eq!(
+        peripherals.settings.fee_warning_rate,
+        FeeWarningRate::FiveHundred
+    );
+    assert_eq!(
+        peripherals.settings.non_default_sighash,
+        NonDefaultSighash::Reject
+    );
+    assert_eq!(
+        peripherals.settings.utxo_verification,
+        UtxoVerification::Standard
+    );
+    assert_eq!(peripherals.settings.change_outputs, ChangeOutputs::Hide);
+    assert_eq!(peripherals.settings.amount_unit, AmountUnit::Sats);
+
+    match block_on(crate::config::read_config(&mut peripherals.flash)).unwrap() {
+        model::Config::Initialized(config) => {
+            assert_eq!(config.settings, Some(peripherals.settings))
+        }
+        _ => panic!("Expected an initialized config"),
+    }
+}
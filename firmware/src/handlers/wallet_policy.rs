@@ -0,0 +1,66 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::rc::Rc;
+
+use model::{ErrorCode, Reply};
+
+use super::*;
+
+/// Session in which the host referenced the registered policy, see `model::wallet_policy`
+#[derive(Debug, Default)]
+pub struct PolicySession {
+    referenced: Option<u32>,
+}
+
+impl PolicySession {
+    /// Whether the PSBTs of `wallet` can be signed in the current session
+    ///
+    /// Always true when no policy is registered.
+    pub fn allows_signing(&self, wallet: &PortalWallet) -> bool {
+        wallet.config.wallet_policy().is_none() || self.referenced == Some(host::current_session())
+    }
+
+    pub fn forget(&mut self) {
+        *self = Default::default();
+    }
+}
+
+/// Check the HMAC of the policy referenced by the host against the registered one
+pub async fn handle_use_wallet_policy(
+    wallet: &Rc<PortalWallet>,
+    hmac: &[u8; 32],
+    peripherals: &mut HandlerPeripherals,
+) {
+    log::info!("handle_use_wallet_policy");
+
+    let reply = match wallet.config.wallet_policy() {
+        Some(policy) if &*policy.hmac == hmac => {
+            log::debug!("Using wallet policy {}", policy.name);
+            peripherals.wallet_policy.referenced = Some(host::current_session());
+            Reply::Ok
+        }
+        _ => {
+            log::warn!("Wallet policy doesn't match");
+            peripherals.wallet_policy.forget();
+            Reply::error(ErrorCode::WalletPolicyMismatch)
+        }
+    };
+
+    peripherals.nfc.send(reply).await.unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+}
@@ -0,0 +1,122 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::boxed::Box;
+
+use futures::prelude::*;
+
+use rand::RngCore;
+
+use gui::{i18n::Label, GenericTwoLinePage, LoadingPage, Page, SummaryPage};
+use model::{ErrorCode, Reply};
+
+use super::*;
+use crate::config;
+use crate::Error;
+
+/// Number of digits of the code shown before wiping the device
+const WIPE_CODE_DIGITS: usize = 6;
+
+/// Wipe the device, after the host echoes the code shown on the screen
+///
+/// The code is only shown on the screen, so a host can't wipe the device on its own while the
+/// user happens to be holding the button. A wrong code or any other request cancels the wipe and
+/// goes back to `previous`.
+pub async fn handle_wipe(
+    previous: Box<CurrentState>,
+    mut events: impl Stream<Item = Event> + Unpin,
+    peripherals: &mut HandlerPeripherals,
+) -> Result<CurrentState, Error> {
+    log::info!("handle_wipe");
+
+    let code = alloc::format!(
+        "{:0width$}",
+        peripherals.rng.next_u32() % 10u32.pow(WIPE_CODE_DIGITS as u32),
+        width = WIPE_CODE_DIGITS
+    );
+
+    let page = GenericTwoLinePage::new(
+        Label::WipeCode.get(),
+        &code,
+        Label::EnterItInTheApp.get(),
+        100,
+    );
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    peripherals.nfc.send(Reply::Ok).await.unwrap();
+    peripherals.nfc_finished.recv().await.unwrap();
+
+    let request = {
+        let events = only_requests(&mut events);
+        pin_mut!(events);
+        events.next().await
+    };
+    match request {
+        Some(model::Request::ConfirmWipe { code: echoed }) if echoed == code => {}
+        Some(model::Request::ConfirmWipe { .. }) => {
+            log::warn!("Wrong wipe code");
+            peripherals
+                .nfc
+                .send(Reply::error(ErrorCode::WrongWipeCode))
+                .await
+                .unwrap();
+            peripherals.nfc_finished.recv().await.unwrap();
+            return Ok(*previous);
+        }
+        _ => {
+            peripherals
+                .nfc
+                .send(Reply::UnexpectedMessage)
+                .await
+                .unwrap();
+            peripherals.nfc_finished.recv().await.unwrap();
+            return Ok(*previous);
+        }
+    }
+
+    peripherals.nfc.send(Reply::DelayedReply).await.unwrap();
+
+    peripherals.tsc_enabled.enable();
+
+    let mut page = SummaryPage::new(Label::WipeDevice.get(), Label::HoldToWipe.get());
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+    manage_confirmation_loop(&mut events, peripherals, &mut page).await?;
+
+    let page = LoadingPage::new();
+    page.init_display(&mut peripherals.display)?;
+    page.draw_to(&mut peripherals.display)?;
+    peripherals.display.flush()?;
+
+    let update_checkpoints = fwupdate::has_checkpoints(peripherals);
+    let report = config::wipe_config(&mut peripherals.flash, update_checkpoints).await?;
+    apply_settings(peripherals, Default::default())?;
+    peripherals.last_payment = None;
+    peripherals.payjoin_original = None;
+    peripherals.reserves_message = None;
+    peripherals.signing_checkpoint = None;
+    peripherals.host.forget();
+    peripherals.wallet_policy.forget();
+    log::info!("Device wiped: {:?}", report);
+
+    peripherals.nfc.send(Reply::Wiped(report)).await.unwrap();
+
+    Ok(CurrentState::Init)
+}
@@ -0,0 +1,224 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A/B firmware slots
+//!
+//! Each of the two flash banks holds a complete firmware image, and the `BFB2` option bit selects
+//! which one boots. Updates are always written to the bank that isn't running, which is then
+//! marked as `Pending` and selected for the next boot, leaving the previous image untouched.
+//!
+//! A pending image has `BOOT_WATCHDOG_SECS` to reach `confirm_slot()` before the watchdog resets
+//! the MCU, and `MAX_BOOT_ATTEMPTS` attempts to do so: after that we switch back to the other
+//! bank.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use hal::flash::{self, Read, WriteErase};
+use hal::stm32;
+
+use crate::Error;
+
+/// Page of each bank that holds the `SlotState` of the image in that bank
+pub use model::flash::SLOT_STATE_PAGE;
+const SLOT_STATE_MAGIC: [u8; 4] = *b"SLOT";
+
+const MAX_BOOT_ATTEMPTS: u8 = 3;
+const BOOT_WATCHDOG_SECS: u32 = 10;
+
+const FLASH_OPTKEY1: u32 = 0x0819_2A3B;
+const FLASH_OPTKEY2: u32 = 0x4C5D_6E7F;
+
+/// Set once the running image is confirmed, from then on the watchdog is fed by the timer
+static CONFIRMED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, minicbor::Encode, minicbor::Decode)]
+pub enum SlotState {
+    #[n(0)]
+    Confirmed,
+    #[n(1)]
+    Pending {
+        #[n(0)]
+        attempts: u8,
+    },
+}
+
+/// Physical page to erase or program `page` of a bank, which doesn't depend on the bank mapping
+pub fn physical_page(bank2: bool, page: usize) -> flash::FlashPage {
+    match bank2 {
+        false => flash::FlashPage(page),
+        true => flash::FlashPage(page + 256),
+    }
+}
+
+/// Read the state of the image in the bank mapped at `address`
+pub fn read_slot_state(prog: &flash::FlashProgramming, address: usize) -> Option<SlotState> {
+    let mut buf = [0u8; 64];
+    prog.read(address, &mut buf);
+
+    if buf[..4] != SLOT_STATE_MAGIC {
+        return None;
+    }
+    let len = buf[4] as usize;
+    if len > buf.len() - 5 {
+        return None;
+    }
+
+    minicbor::decode(&buf[5..5 + len]).ok()
+}
+
+/// Write the state of an image
+///
+/// `address` is the logical address of the state page, while `page` is its physical location.
+pub fn write_slot_state(
+    prog: &mut flash::FlashProgramming,
+    address: usize,
+    page: flash::FlashPage,
+    state: SlotState,
+) -> Result<(), Error> {
+    let serialized = minicbor::to_vec(state).expect("always succeed");
+
+    let mut data = alloc::vec::Vec::from(SLOT_STATE_MAGIC);
+    data.push(serialized.len() as u8);
+    data.extend(serialized);
+    data.resize(64, 0x00);
+
+    prog.erase_page(page).map_err(|_| Error::FlashError)?;
+    prog.write(address, &data).map_err(|_| Error::FlashError)?;
+
+    Ok(())
+}
+
+/// Check the state of the running image, rolling back if it failed to boot too many times
+///
+/// Must be called early during boot.
+pub fn check_slot(flash: &mut flash::Parts, fb_mode: bool) {
+    let mut prog = match flash.keyr.unlock_flash(&mut flash.sr, &mut flash.cr) {
+        Ok(prog) => prog,
+        Err(e) => {
+            log::warn!("Unable to unlock the flash: {:?}", e);
+            return;
+        }
+    };
+
+    let address = physical_page(false, SLOT_STATE_PAGE).to_address();
+    match read_slot_state(&prog, address) {
+        Some(SlotState::Pending { attempts }) if attempts >= MAX_BOOT_ATTEMPTS => {
+            if other_bank_is_valid() {
+                log::warn!("Image failed to boot {} times, rolling back", attempts);
+                boot_from_bank(&mut prog, !fb_mode);
+            } else {
+                // Nothing to go back to: keep trying with this one
+                log::warn!("Image failed to boot but the other bank is empty");
+                CONFIRMED.store(true, Ordering::Relaxed);
+            }
+        }
+        Some(SlotState::Pending { attempts }) => {
+            log::info!("Booting pending image, attempt {}", attempts + 1);
+
+            let page = physical_page(fb_mode, SLOT_STATE_PAGE);
+            let state = SlotState::Pending {
+                attempts: attempts + 1,
+            };
+            if let Err(e) = write_slot_state(&mut prog, address, page, state) {
+                log::warn!("Unable to update the slot state: {:?}", e);
+            }
+
+            start_watchdog();
+        }
+        _ => CONFIRMED.store(true, Ordering::Relaxed),
+    }
+}
+
+/// Mark the running image as good
+pub fn confirm_slot(flash: &mut super::Flash) -> Result<(), Error> {
+    if CONFIRMED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let parts = &mut flash.parts;
+    let mut prog = parts
+        .keyr
+        .unlock_flash(&mut parts.sr, &mut parts.cr)
+        .map_err(|_| Error::FlashError)?;
+    write_slot_state(
+        &mut prog,
+        physical_page(false, SLOT_STATE_PAGE).to_address(),
+        physical_page(flash.fb_mode, SLOT_STATE_PAGE),
+        SlotState::Confirmed,
+    )?;
+
+    log::info!("Image confirmed");
+    CONFIRMED.store(true, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Check that the bank we are not running from starts with a plausible vector table
+fn other_bank_is_valid() -> bool {
+    // The other bank is always mapped right after the one we booted from
+    let base = physical_page(true, 0).to_address();
+    let sp = unsafe { core::ptr::read_volatile(base as *const u32) };
+    let reset = unsafe { core::ptr::read_volatile((base + 4) as *const u32) };
+
+    matches!(sp, 0x1000_0000..=0x1000_8000 | 0x2000_0000..=0x2001_8000)
+        && matches!(reset, 0x0800_0000..=0x0807_F000)
+}
+
+/// Select the bank to boot from and reset
+pub fn boot_from_bank(_prog: &mut flash::FlashProgramming, bank2: bool) -> ! {
+    let regs = unsafe { &*stm32::FLASH::ptr() };
+    let wait = || while regs.sr.read().bsy().bit_is_set() {};
+
+    // The flash is already unlocked by `_prog`, now unlock the option bytes
+    regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPTKEY1) });
+    regs.optkeyr.write(|w| unsafe { w.bits(FLASH_OPTKEY2) });
+
+    wait();
+    regs.optr.modify(|_, w| w.bfb2().bit(bank2));
+    regs.cr.modify(|_, w| w.optstrt().set_bit());
+    wait();
+
+    // Reload the option bytes, which also resets the MCU
+    regs.cr.modify(|_, w| w.obl_launch().set_bit());
+
+    loop {}
+}
+
+fn start_watchdog() {
+    let iwdg = unsafe { &*stm32::IWDG::ptr() };
+
+    // Start the watchdog and enable access to its registers
+    iwdg.kr.write(|w| unsafe { w.key().bits(0xCCCC) });
+    iwdg.kr.write(|w| unsafe { w.key().bits(0x5555) });
+    // LSI at 32KHz divided by 256: 125 counts per second
+    iwdg.pr.write(|w| unsafe { w.pr().bits(0b110) });
+    iwdg.rlr
+        .write(|w| unsafe { w.rl().bits((BOOT_WATCHDOG_SECS * 125) as u16) });
+    while iwdg.sr.read().bits() != 0 {}
+
+    iwdg.kr.write(|w| unsafe { w.key().bits(0xAAAA) });
+}
+
+/// Reload the watchdog, unless we are still waiting for the image to be confirmed
+///
+/// Once started the watchdog can't be stopped, so this must be called periodically.
+pub fn feed_watchdog() {
+    if CONFIRMED.load(Ordering::Relaxed) {
+        let iwdg = unsafe { &*stm32::IWDG::ptr() };
+        iwdg.kr.write(|w| unsafe { w.key().bits(0xAAAA) });
+    }
+}
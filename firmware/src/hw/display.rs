@@ -0,0 +1,216 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Display interface that sends data to the SSD1306 in the background
+//!
+//! `ssd1306` draws into its own framebuffer and `flush()` sends the area that changed, one row of
+//! pages at a time. Instead of blocking on the I2C bus, every transfer is copied into a queue
+//! and sent by DMA1 channel 4: the I2C2 event interrupt starts the next transfer as soon as the
+//! STOP of the previous one is detected. The queue fits two full frames, so `flush()` only waits
+//! when it's called again while an older frame is still being sent.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{free, Mutex};
+
+use display_interface::{DataFormat, DisplayError, WriteOnlyDataCommand};
+
+use hal::rcc::{Enable, AHB1};
+use hal::stm32;
+
+const SSD1306_ADDR: u16 = 0x3C;
+
+const CONTROL_COMMAND: u8 = 0x00;
+const CONTROL_DATA: u8 = 0x40;
+
+/// Request number of I2C2_TX on DMA1 channel 4
+const DMA_REQUEST_I2C2_TX: u8 = 0b0011;
+/// Longest transfer that fits in `NBYTES`, including the control byte. We don't use the reload
+/// mode, longer writes are split in multiple transfers.
+const MAX_TRANSFER_LEN: usize = 255;
+/// Two full frames sent one row of pages at a time, plus the commands to set the draw area
+const QUEUE_LEN: usize = 2 * (8 * (128 + 2) + 32);
+
+struct Queue {
+    i2c: stm32::I2C2,
+    dma: stm32::DMA1,
+    /// Transfers waiting to be sent, each one prefixed by its length
+    buffer: [u8; QUEUE_LEN],
+    /// Start of the next transfer to send
+    head: usize,
+    /// End of the last transfer queued
+    tail: usize,
+    busy: bool,
+}
+
+static QUEUE: Mutex<RefCell<Option<Queue>>> = Mutex::new(RefCell::new(None));
+
+impl Queue {
+    fn start_next(&mut self) {
+        if self.head == self.tail {
+            // Everything was sent, start again from the beginning of the buffer
+            self.busy = false;
+            self.head = 0;
+            self.tail = 0;
+            return;
+        }
+
+        let len = self.buffer[self.head] as usize;
+        let start = self.head + 1;
+        self.head = start + len;
+
+        self.dma.ccr4.modify(|_, w| w.en().clear_bit());
+        self.dma
+            .cmar4
+            .write(|w| unsafe { w.ma().bits(self.buffer[start..].as_ptr() as u32) });
+        self.dma
+            .cndtr4
+            .write(|w| unsafe { w.ndt().bits(len as u16) });
+        self.dma.ccr4.modify(|_, w| w.en().set_bit());
+
+        self.i2c.cr2.write(|w| unsafe {
+            w.sadd()
+                .bits(SSD1306_ADDR << 1)
+                .rd_wrn()
+                .clear_bit()
+                .nbytes()
+                .bits(len as u8)
+                .autoend()
+                .set_bit()
+                .start()
+                .set_bit()
+        });
+        self.busy = true;
+    }
+
+    /// Move on to the next transfer if the current one is over
+    ///
+    /// A NACK also ends with a STOP because of `AUTOEND`: the transfer is lost, but the following
+    /// ones are still sent.
+    fn service(&mut self) {
+        let isr = self.i2c.isr.read();
+        if isr.nackf().bit_is_set() {
+            self.i2c.icr.write(|w| w.nackcf().set_bit());
+        }
+        if isr.stopf().bit_is_set() {
+            self.i2c.icr.write(|w| w.stopcf().set_bit());
+            self.start_next();
+        }
+    }
+
+    fn push(&mut self, control: u8, data: &[u8]) -> bool {
+        let len = data.len() + 1;
+        if self.tail + 1 + len > QUEUE_LEN {
+            return false;
+        }
+
+        self.buffer[self.tail] = len as u8;
+        self.buffer[self.tail + 1] = control;
+        self.buffer[self.tail + 2..self.tail + 1 + len].copy_from_slice(data);
+        self.tail += 1 + len;
+
+        if !self.busy {
+            self.start_next();
+        }
+        true
+    }
+}
+
+fn enqueue(control: u8, data: &[u8]) {
+    for chunk in data.chunks(MAX_TRANSFER_LEN - 1) {
+        // Also poll the status here, since this may run with interrupts disabled during `init`
+        while !free(|cs| {
+            let mut queue = QUEUE.borrow(cs).borrow_mut();
+            let queue = queue.as_mut().expect("Display queue is initialized");
+            queue.service();
+            queue.push(control, chunk)
+        }) {}
+    }
+}
+
+/// Handle the I2C2 event interrupt
+pub fn on_interrupt() {
+    free(|cs| {
+        if let Some(queue) = QUEUE.borrow(cs).borrow_mut().as_mut() {
+            queue.service();
+        }
+    });
+}
+
+pub struct DmaInterface {
+    _private: (),
+}
+
+impl DmaInterface {
+    /// Take over `i2c`, which must already be configured, and DMA1 channel 4
+    pub fn new(i2c: stm32::I2C2, dma: stm32::DMA1, ahb1: &mut AHB1) -> Self {
+        stm32::DMA1::enable(ahb1);
+
+        dma.cselr
+            .modify(|_, w| unsafe { w.c4s().bits(DMA_REQUEST_I2C2_TX) });
+        dma.cpar4
+            .write(|w| unsafe { w.pa().bits(&i2c.txdr as *const _ as u32) });
+        dma.ccr4.write(|w| unsafe {
+            w.dir()
+                .set_bit() // Read from memory
+                .minc()
+                .set_bit()
+                .pinc()
+                .clear_bit()
+                .msize()
+                .bits(0b00) // 8 bits
+                .psize()
+                .bits(0b00) // 8 bits
+                .pl()
+                .bits(0b00) // Low priority
+        });
+
+        i2c.cr1
+            .modify(|_, w| w.txdmaen().set_bit().stopie().set_bit());
+
+        free(|cs| {
+            QUEUE.borrow(cs).replace(Some(Queue {
+                i2c,
+                dma,
+                buffer: [0; QUEUE_LEN],
+                head: 0,
+                tail: 0,
+                busy: false,
+            }));
+        });
+
+        DmaInterface { _private: () }
+    }
+}
+
+impl WriteOnlyDataCommand for DmaInterface {
+    fn send_commands(&mut self, cmd: DataFormat<'_>) -> Result<(), DisplayError> {
+        match cmd {
+            DataFormat::U8(data) => enqueue(CONTROL_COMMAND, data),
+            _ => return Err(DisplayError::DataFormatNotImplemented),
+        }
+        Ok(())
+    }
+
+    fn send_data(&mut self, buf: DataFormat<'_>) -> Result<(), DisplayError> {
+        match buf {
+            DataFormat::U8(data) => enqueue(CONTROL_DATA, data),
+            _ => return Err(DisplayError::DataFormatNotImplemented),
+        }
+        Ok(())
+    }
+}
@@ -21,8 +21,10 @@ use hal::rcc::{Enable, MsiFreq};
 use hal::{flash, gpio, stm32};
 use rand::prelude::*;
 
-use ssd1306::{mode::BufferedGraphicsMode, prelude::*, I2CDisplayInterface, Ssd1306};
+use ssd1306::{mode::BufferedGraphicsMode, prelude::*, Ssd1306};
 
+pub mod boot;
+pub mod display;
 pub mod nt3h;
 pub mod tsc;
 
@@ -32,6 +34,27 @@ pub type AltOpenDrain<const A: u8> = gpio::Alternate<gpio::OpenDrain, A>;
 pub type AltPushPull<const A: u8> = gpio::Alternate<gpio::PushPull, A>;
 pub type FloatingInput = gpio::Input<gpio::Floating>;
 
+static RNG_HEALTHY: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(true);
+
+/// Result of the health tests run on the hardware RNG at boot
+pub fn rng_self_test() -> model::selftest::TestOutcome {
+    if RNG_HEALTHY.load(core::sync::atomic::Ordering::Relaxed) {
+        model::selftest::TestOutcome::Passed
+    } else {
+        model::selftest::TestOutcome::Failed("Health tests failed at boot".into())
+    }
+}
+
+/// Device and revision identifiers of the microcontroller, read from `DBGMCU_IDCODE`
+pub fn hardware_revision() -> Option<alloc::string::String> {
+    let idcode = unsafe { &*stm32::DBGMCU::ptr() }.idcode.read();
+    Some(alloc::format!(
+        "{:03X}-{:04X}",
+        idcode.dev_id().bits(),
+        idcode.rev_id().bits()
+    ))
+}
+
 pub fn enable_debug_during_sleep(dp: &mut stm32::Peripherals) {
     // Allow debugging during sleep
     dp.DBGMCU.cr.modify(|_, w| {
@@ -57,21 +80,23 @@ pub type NfcIc = Nt3h<
         gpio::gpiob::PB9<AltOpenDrain<4>>,
     ),
 >;
-pub type Display = Ssd1306<
-    I2CInterface<
-        I2c<
-            stm32::I2C2,
-            (
-                gpio::gpiob::PB13<AltOpenDrain<4>>,
-                gpio::gpiob::PB14<AltOpenDrain<4>>,
-            ),
-        >,
-    >,
-    DisplaySize128x64,
-    BufferedGraphicsMode<DisplaySize128x64>,
->;
+pub type Display =
+    Ssd1306<display::DmaInterface, DisplaySize128x64, BufferedGraphicsMode<DisplaySize128x64>>;
 pub type NfcInterrupt = nt3h::NfcInterrupt<gpio::gpioa::PA6<FloatingInput>>;
 
+pub fn set_brightness(
+    display: &mut Display,
+    brightness: model::settings::Brightness,
+) -> Result<(), crate::Error> {
+    let brightness = match brightness {
+        model::settings::Brightness::Low => Brightness::DIMMEST,
+        model::settings::Brightness::Medium => Brightness::NORMAL,
+        model::settings::Brightness::High => Brightness::BRIGHTEST,
+    };
+    display.set_brightness(brightness)?;
+    Ok(())
+}
+
 pub fn init_peripherals(
     mut dp: stm32::Peripherals,
     cp: cortex_m::Peripherals,
@@ -136,8 +161,21 @@ pub fn init_peripherals(
 
         let mut stm32_rng = dp.RNG.enable(&mut rcc.ahb2, clocks);
 
+        // The hardware RNG is only read here: every key, nonce and checkpoint key is generated
+        // by the ChaCha20 seeded with it. The health tests run continuously on everything we
+        // read, including the seed itself, and the device refuses to start if they fail.
+        let mut monitor = model::selftest::RngHealthMonitor::new();
+        let mut samples = [0u8; 1024];
+        stm32_rng.fill_bytes(&mut samples);
         let mut seed = [0u8; 32];
         stm32_rng.fill_bytes(&mut seed);
+        if let Err(e) = monitor
+            .feed_all(&samples)
+            .and_then(|_| monitor.feed_all(&seed))
+        {
+            log::warn!("RNG health tests failed: {}", e);
+            RNG_HEALTHY.store(false, core::sync::atomic::Ordering::Relaxed);
+        }
 
         hal::stm32::RNG::disable(&mut rcc.ahb2);
 
@@ -156,10 +194,11 @@ pub fn init_peripherals(
         .msi(MsiFreq::RANGE24M)
         .freeze(&mut flash.acr, &mut pwr);
 
-    let flash = Flash {
+    let mut flash = Flash {
         parts: flash,
         fb_mode: dp.SYSCFG.memrmp.read().fb_mode().bit(),
     };
+    boot::check_slot(&mut flash.parts, flash.fb_mode);
 
     // Init systick
     let systick_token = rtic_monotonics::create_systick_token!();
@@ -203,7 +242,9 @@ pub fn init_peripherals(
         &mut rcc.apb1r1,
     );
 
-    let interface = I2CDisplayInterface::new(i2c2);
+    // The pins stay configured after `free()`, we only take over the registers
+    let (i2c2, _) = i2c2.free();
+    let interface = display::DmaInterface::new(i2c2, dp.DMA1, &mut rcc.ahb1);
 
     display_reset.set_high();
 
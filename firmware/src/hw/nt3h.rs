@@ -25,7 +25,7 @@ use rtic_monotonics::systick::*;
 
 use model::reg::*;
 use model::write_buffer::*;
-use model::{Message, MessageFragment, Reply, Request};
+use model::{Message, MessageError, MessageFragment, Reply, Request};
 
 use crate::hw_common;
 use crate::Error;
@@ -339,15 +339,27 @@ where
         Ok(())
     }
 
+    /// Read and decrypt the next request
+    ///
+    /// Requests unknown to this firmware are answered here with `ErrorCode::UnsupportedRequest`
+    /// and returned as `MessageError::UnsupportedMessage`.
     pub async fn accept_request(
         &mut self,
         decrypt: &mut ::model::encryption::CipherState,
+        encrypt: &mut ::model::encryption::CipherState,
     ) -> Result<Request, Error> {
         let msg = self.read_raw_message().await?;
         let mut decrypt_buf = alloc::vec::Vec::new();
 
         match msg.deserialize(&mut decrypt_buf, decrypt) {
             Ok(v) => Ok(v),
+            Err(MessageError::UnsupportedMessage) => {
+                let reply = Reply::error(model::ErrorCode::UnsupportedRequest);
+                let message = Message::new_serialize(&reply, encrypt)?;
+                self.write_to_mailbox(message.get_fragments().into_iter())
+                    .await?;
+                Err(MessageError::UnsupportedMessage.into())
+            }
             Err(e) => {
                 self.write_to_mailbox([MessageFragment::new_failed_decryption()].into_iter())
                     .await?;
@@ -366,7 +378,7 @@ where
             .await?;
 
         match reply {
-            Reply::Pong | Reply::DelayedReply => {}
+            Reply::Pong | Reply::DelayedReply | Reply::Progress { .. } => {}
             _ => {
                 let _ = self.finished.send(()).await;
             }
@@ -16,16 +16,17 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use alloc::rc::Rc;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
 use hal::{stm32, tsc};
 
-const TSC_THRESHOLD: u16 = 1200;
+use crate::hw_common::TscLevels;
 
 pub struct Tsc<SAMPLE_PIN, CHANNEL_PIN> {
     tsc: tsc::Tsc<SAMPLE_PIN>,
     channel_pin: CHANNEL_PIN,
     enabled: Rc<RefCell<bool>>,
+    levels: Rc<Cell<TscLevels>>,
 }
 
 impl<SAMPLE_PIN, CHANNEL_PIN> Tsc<SAMPLE_PIN, CHANNEL_PIN>
@@ -38,6 +39,7 @@ where
             tsc,
             channel_pin,
             enabled: Rc::new(RefCell::new(false)),
+            levels: Rc::new(Cell::new(TscLevels::default())),
         }
     }
 
@@ -56,6 +58,10 @@ where
         Rc::clone(&self.enabled)
     }
 
+    pub fn get_levels_ref(&self) -> Rc<Cell<TscLevels>> {
+        Rc::clone(&self.levels)
+    }
+
     pub fn start_acquisition(&mut self) {
         if !self.tsc.in_progress() {
             self.tsc.start(&mut self.channel_pin);
@@ -63,6 +69,10 @@ where
     }
 
     pub fn perform_read(&self) -> bool {
-        self.tsc.read_unchecked() < TSC_THRESHOLD
+        let mut levels = self.levels.get();
+        levels.last_reading = self.tsc.read_unchecked();
+        self.levels.set(levels);
+
+        levels.last_reading < levels.threshold
     }
 }
@@ -16,7 +16,8 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use alloc::rc::Rc;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::AtomicU32;
 
 use model::{Reply, Request};
 
@@ -25,6 +26,11 @@ use cortex_m::peripheral::NVIC;
 #[cfg(feature = "device")]
 use hal::interrupt;
 
+/// Counter of the NFC sessions, incremented after every completed handshake
+///
+/// Used to tie state that must not outlive a session (e.g. an identified host) to it.
+pub static NFC_SESSION: AtomicU32 = AtomicU32::new(0);
+
 pub type ChannelSender<T> = rtic_sync::channel::Sender<'static, T, 1>;
 pub type ChannelReceiver<T> = rtic_sync::channel::Receiver<'static, T, 1>;
 
@@ -53,13 +59,43 @@ pub fn make_nfc_channels() -> (NfcChannelsLocal, NfcChannelsShared) {
     (local, shared)
 }
 
+/// Readings of the TSC, shared with the interrupt that performs them
+#[derive(Debug, Clone, Copy)]
+pub struct TscLevels {
+    /// Readings below this value count as a touch, see `DeviceSettings::touch_threshold()`
+    pub threshold: u16,
+    /// Last reading, which drops while the button is touched
+    pub last_reading: u16,
+}
+
+impl Default for TscLevels {
+    fn default() -> Self {
+        TscLevels {
+            threshold: model::settings::DEFAULT_TOUCH_THRESHOLD,
+            last_reading: u16::MAX,
+        }
+    }
+}
+
 pub struct TscEnable {
     bool_ref: Rc<RefCell<bool>>,
+    levels: Rc<Cell<TscLevels>>,
 }
 
 impl TscEnable {
-    pub fn new(bool_ref: Rc<RefCell<bool>>) -> Self {
-        TscEnable { bool_ref }
+    pub fn new(bool_ref: Rc<RefCell<bool>>, levels: Rc<Cell<TscLevels>>) -> Self {
+        TscEnable { bool_ref, levels }
+    }
+
+    pub fn set_threshold(&self, threshold: u16) {
+        let mut levels = self.levels.get();
+        levels.threshold = threshold;
+        self.levels.set(levels);
+    }
+
+    /// Last raw reading of the TSC, only updated while it's enabled
+    pub fn last_reading(&self) -> u16 {
+        self.levels.get().last_reading
     }
 
     pub fn enable(&self) {
@@ -0,0 +1,117 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Logger that keeps the last lines in RAM, see `model::logs`
+//!
+//! It's installed as the global logger and forwards every record to the logger of the platform,
+//! if there's one. Only records of level `Info` and above are kept, and only while the logs are
+//! enabled in the settings.
+
+use alloc::string::String;
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use critical_section::Mutex;
+
+use model::logs::LogBuffer;
+
+/// Longer lines are truncated
+const MAX_LINE_LEN: usize = 160;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUFFER: Mutex<RefCell<LogBuffer>> = Mutex::new(RefCell::new(LogBuffer::new()));
+static FORWARD: Mutex<Cell<Option<&'static dyn log::Log>>> = Mutex::new(Cell::new(None));
+static LOGGER: BufferLogger = BufferLogger;
+
+struct BufferLogger;
+
+/// A single line, formatted without allocating
+struct Line {
+    data: [u8; MAX_LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    fn as_str(&self) -> &str {
+        match core::str::from_utf8(&self.data[..self.len]) {
+            Ok(s) => s,
+            // Truncated in the middle of a character
+            Err(e) => core::str::from_utf8(&self.data[..e.valid_up_to()]).expect("Valid prefix"),
+        }
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = s.len().min(MAX_LINE_LEN - self.len);
+        self.data[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+impl log::Log for BufferLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Some(forward) = critical_section::with(|cs| FORWARD.borrow(cs).get()) {
+            forward.log(record);
+        }
+
+        if ENABLED.load(Ordering::Relaxed) && record.level() <= log::Level::Info {
+            let mut line = Line {
+                data: [0; MAX_LINE_LEN],
+                len: 0,
+            };
+            let _ = write!(line, "{} {}", record.level(), record.args());
+            critical_section::with(|cs| BUFFER.borrow_ref_mut(cs).push_line(line.as_str()));
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(forward) = critical_section::with(|cs| FORWARD.borrow(cs).get()) {
+            forward.flush();
+        }
+    }
+}
+
+/// Install the logger, forwarding every record to `forward`
+pub fn init(forward: Option<&'static dyn log::Log>, level: log::LevelFilter) {
+    critical_section::with(|cs| FORWARD.borrow(cs).set(forward));
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Start or stop keeping the lines, the ones already kept are dropped when stopping
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        critical_section::with(|cs| BUFFER.borrow_ref_mut(cs).clear());
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn contents() -> String {
+    critical_section::with(|cs| BUFFER.borrow_ref(cs).contents())
+}
@@ -15,10 +15,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
+// Most of the imports are only used by the RTIC app, which isn't built for the tests
+#![cfg_attr(test, allow(unused_imports))]
 
 extern crate alloc;
 extern crate cortex_m;
@@ -30,6 +32,8 @@ extern crate embedded_hal_1 as ehal;
 
 #[cfg(all(feature = "device", feature = "emulator"))]
 compile_error!("Cannot enable both the `device` and `emulator` features at the same time");
+#[cfg(all(test, any(feature = "device", feature = "emulator")))]
+compile_error!("Unit tests run on the host, build them with `--no-default-features`");
 
 #[cfg(feature = "emulator")]
 extern crate stm32f4xx_hal as hal;
@@ -47,14 +51,20 @@ mod handlers;
 #[cfg(feature = "device")]
 mod hw;
 mod hw_common;
+mod log_buffer;
+#[cfg(test)]
+mod mock;
 mod version;
 #[cfg(feature = "emulator")]
 pub use emulator::*;
+#[cfg(test)]
+pub use mock::*;
 
 use core::cell::RefCell;
 use core::mem::MaybeUninit;
 use core::ops::DerefMut;
 
+#[cfg(not(test))]
 use embedded_alloc::Heap;
 
 use rand::RngCore;
@@ -62,6 +72,7 @@ use rand::RngCore;
 use futures::prelude::*;
 use futures::{pin_mut, select_biased};
 
+#[cfg(not(test))]
 use rtic_monotonics::systick::ExtU32;
 
 use crate::handlers::*;
@@ -84,6 +95,7 @@ type SemihostingLogger = Logger<Semihosting<InterruptFree, emulator::Semihosting
 #[cfg(feature = "emulator")]
 static mut LOGGER: MaybeUninit<SemihostingLogger> = MaybeUninit::uninit();
 
+#[cfg(not(test))]
 #[global_allocator]
 static HEAP: Heap = Heap::empty();
 
@@ -122,6 +134,7 @@ impl core::fmt::Debug for KeyDebugWrapper {
     }
 }
 
+#[cfg(not(test))]
 #[rtic::app(device = unified_hal, peripherals = true, dispatchers = [CAN1_RX0, CAN1_RX1])]
 mod app {
     use crate::hw_common::TscEnable;
@@ -153,6 +166,9 @@ mod app {
     fn init(cx: init::Context) -> (Shared, Local) {
         #[cfg(feature = "device-log")]
         rtt_log::init();
+        // With RTT the logs are read through the debug probe instead
+        #[cfg(all(feature = "device", not(feature = "device-log")))]
+        log_buffer::init(None, log::LevelFilter::Info);
         #[cfg(feature = "emulator")]
         unsafe {
             let logger = Logger {
@@ -162,7 +178,7 @@ mod app {
             *LOGGER.as_mut_ptr() = logger;
             let logger_ref = &*LOGGER.as_ptr();
 
-            cortex_m_log::log::init(logger_ref).expect("To set logger");
+            log_buffer::init(Some(logger_ref), log::LevelFilter::Trace);
         };
 
         log::info!("Hello, world!");
@@ -188,7 +204,7 @@ mod app {
         let (mut nfc, nfc_interrupt, nfc_finished, display, tsc, mut rng, flash) =
             hw::init_peripherals(dp, cp).unwrap();
 
-        let tsc_enabled = TscEnable::new(tsc.get_enabled_ref());
+        let tsc_enabled = TscEnable::new(tsc.get_enabled_ref(), tsc.get_levels_ref());
 
         type Empty = ();
         let (nfc_local, nfc_shared) = hw_common::make_nfc_channels();
@@ -228,6 +244,7 @@ mod app {
 
             hw::EmulatorChannels {
                 tsc: tsc_sender.clone(),
+                tsc_levels: tsc.get_levels_ref(),
                 emulated_nt3h: EmulatedNT3H::new(nfc_interrupt.clone(), &mut nfc),
                 flash: flash_sender,
             }
@@ -253,6 +270,15 @@ mod app {
                     nfc: nfc_shared.outgoing,
                     nfc_finished,
                     tsc_enabled,
+                    settings: Default::default(),
+                    last_payment: None,
+                    payjoin_original: None,
+                    reserves_message: None,
+                    fiat_rate: None,
+                    cpfp: None,
+                    signing_checkpoint: None,
+                    host: Default::default(),
+                    wallet_policy: Default::default(),
                 },
 
                 #[cfg(feature = "emulator")]
@@ -343,7 +369,10 @@ mod app {
                 }
 
                 match do_handshake(&mut noise_rng, nfc).await {
-                    Ok(v) => break v,
+                    Ok(v) => {
+                        hw_common::NFC_SESSION.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+                        break v;
+                    }
                     Err(e) => {
                         log::warn!("Handshake error: {:?}", e);
                         continue;
@@ -352,8 +381,13 @@ mod app {
             };
 
             'inner: loop {
-                let req = match nfc.accept_request(&mut decrypt).await {
+                let req = match nfc.accept_request(&mut decrypt, &mut encrypt).await {
                     Ok(req) => req,
+                    Err(Error::Message(model::MessageError::UnsupportedMessage)) => {
+                        // Sent by a newer host, `accept_request` already replied with an error
+                        log::warn!("Unsupported request");
+                        continue 'inner;
+                    }
                     Err(e) => {
                         // `accept_request` sends a special packet back to the RF side to
                         // let them know we couldn't decrypt the message, so we don't reply
@@ -364,6 +398,15 @@ mod app {
                     }
                 };
 
+                // Heartbeats never reach the handlers
+                if let model::Request::Heartbeat = req {
+                    if let Err(e) = nfc.send_reply(&model::Reply::Pong, &mut encrypt).await {
+                        log::error!("Error writing heartbeat reply: {:?}", e);
+                    }
+
+                    continue 'inner;
+                }
+
                 // Manage pings here transparently
                 if let model::Request::Ping = req {
                     let reply = select_biased! {
@@ -402,6 +445,9 @@ mod app {
             rtic_monotonics::systick::Systick::delay(TIMER_TICK_MILLIS.millis()).await;
             let _ = cx.local.timer_sender.try_send(());
 
+            #[cfg(feature = "device")]
+            hw::boot::feed_watchdog();
+
             // Report the tick to the emulator to synchronize tests
             #[cfg(feature = "emulator")]
             hw::report_tick();
@@ -422,6 +468,14 @@ mod app {
                 let data = emulator::read_serial();
                 let v = data[0] == 0x01;
 
+                let levels = &_cx.local.emulator_channels.tsc_levels;
+                levels.set(hw_common::TscLevels {
+                    last_reading: match v {
+                        true => hw::EMULATED_TOUCHED_READING,
+                        false => hw::EMULATED_RELEASED_READING,
+                    },
+                    ..levels.get()
+                });
                 let _ = _cx.local.emulator_channels.tsc.try_send(v);
             }
             Some(emulator::PeripheralIncomingMsg::Reset) => {
@@ -446,6 +500,12 @@ mod app {
         }
     }
 
+    #[task(binds = I2C2_EV, priority = 3)]
+    fn display_interrupt(_cx: display_interrupt::Context) {
+        #[cfg(feature = "device")]
+        hw::display::on_interrupt();
+    }
+
     #[task(binds = TSC, local = [tsc])]
     fn tsc_interrupt(_cx: tsc_interrupt::Context) {
         #[cfg(feature = "device")]
@@ -460,6 +520,7 @@ mod app {
     }
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     log::error!("PANIC LOCATION: {:?}", info.location());
@@ -0,0 +1,98 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::hw::Flash;
+
+use rand::RngCore;
+
+use model::attestation::AttestationKey;
+use model::keywrap::WrappingKey;
+use model::{Config, WipeReport};
+
+fn wrapping_key() -> WrappingKey {
+    WrappingKey::new(&[b"mock"])
+}
+
+pub async fn init_wrapping_key(
+    _flash: &mut Flash,
+    _rng: &mut impl RngCore,
+) -> Result<(), ConfigError> {
+    Ok(())
+}
+
+pub async fn read_config(flash: &mut Flash) -> Result<Config, ConfigError> {
+    let data = wrapping_key()
+        .unwrap(&flash.data)
+        .map_err(|_| ConfigError::CorruptedConfig)?;
+    Ok(minicbor::decode(&data).map_err(|_| ConfigError::CorruptedConfig)?)
+}
+
+pub async fn write_config(flash: &mut Flash, config: &Config) -> Result<(), ConfigError> {
+    flash.data = wrapping_key().wrap(&minicbor::to_vec(config).unwrap());
+    Ok(())
+}
+
+pub async fn wipe_config(
+    flash: &mut Flash,
+    _update_checkpoints: bool,
+) -> Result<WipeReport, ConfigError> {
+    let pages_erased = if flash.data.is_empty() { 0 } else { 1 };
+    flash.data.clear();
+
+    Ok(WipeReport {
+        pages_checked: 1,
+        pages_erased,
+        passes: pages_erased,
+        ..Default::default()
+    })
+}
+
+pub async fn read_min_version(flash: &mut Flash) -> Result<u32, ConfigError> {
+    Ok(flash.min_version)
+}
+
+pub async fn write_min_version(flash: &mut Flash, version: u32) -> Result<(), ConfigError> {
+    flash.min_version = core::cmp::max(flash.min_version, version);
+    Ok(())
+}
+
+pub async fn read_attestation_key(
+    flash: &mut Flash,
+) -> Result<Option<AttestationKey>, ConfigError> {
+    Ok(flash.attestation_key.clone())
+}
+
+pub async fn write_attestation_key(
+    flash: &mut Flash,
+    key: &AttestationKey,
+) -> Result<(), ConfigError> {
+    if flash.attestation_key.is_some() {
+        return Err(ConfigError::CorruptedConfig);
+    }
+
+    flash.attestation_key = Some(key.clone());
+    Ok(())
+}
+
+pub fn firmware_hash(_flash: &mut Flash) -> [u8; 32] {
+    [0x00; 32]
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    CorruptedConfig,
+}
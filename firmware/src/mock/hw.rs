@@ -0,0 +1,121 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+
+use model::attestation::AttestationKey;
+
+use embedded_graphics_core::geometry::OriginDimensions;
+use embedded_graphics_core::pixelcolor::BinaryColor;
+use embedded_graphics_core::prelude::*;
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 64;
+
+/// Display backed by an in-memory framebuffer
+///
+/// `flush()` copies the framebuffer to `flushed`, so that tests only look at what would actually
+/// be visible on the screen.
+pub struct Display {
+    framebuffer: [[bool; WIDTH]; HEIGHT],
+    pub flushed: [[bool; WIDTH]; HEIGHT],
+    pub flush_count: usize,
+    pub brightness: model::settings::Brightness,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display {
+            framebuffer: [[false; WIDTH]; HEIGHT],
+            flushed: [[false; WIDTH]; HEIGHT],
+            flush_count: 0,
+            brightness: Default::default(),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<(), crate::Error> {
+        self.flushed = self.framebuffer;
+        self.flush_count += 1;
+        Ok(())
+    }
+
+    /// Whether any pixel was turned on the last time the display was flushed
+    pub fn is_blank(&self) -> bool {
+        self.flushed.iter().flatten().all(|p| !p)
+    }
+}
+
+pub fn set_brightness(
+    display: &mut Display,
+    brightness: model::settings::Brightness,
+) -> Result<(), crate::Error> {
+    display.brightness = brightness;
+    Ok(())
+}
+
+impl OriginDimensions for Display {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+impl DrawTarget for Display {
+    type Color = BinaryColor;
+    type Error = crate::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0 || point.y < 0 || point.x >= WIDTH as i32 || point.y >= HEIGHT as i32 {
+                continue;
+            }
+
+            self.framebuffer[point.y as usize][point.x as usize] = color.is_on();
+        }
+
+        Ok(())
+    }
+}
+
+/// Flash holding only the serialized config
+pub struct Flash {
+    pub data: Vec<u8>,
+    pub fb_mode: bool,
+    pub min_version: u32,
+    pub attestation_key: Option<AttestationKey>,
+}
+
+impl Flash {
+    pub fn empty() -> Self {
+        Flash {
+            data: Vec::new(),
+            fb_mode: true,
+            min_version: 0,
+            attestation_key: None,
+        }
+    }
+}
+
+/// The tests use a seeded software RNG
+pub fn rng_self_test() -> model::selftest::TestOutcome {
+    model::selftest::TestOutcome::Skipped
+}
+
+pub fn hardware_revision() -> Option<alloc::string::String> {
+    None
+}
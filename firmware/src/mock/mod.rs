@@ -0,0 +1,110 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! In-memory peripherals used to unit-test the handlers on the host
+//!
+//! This module replaces `hw` when building the tests: the handlers run on the host with an
+//! executor from the `futures` crate, while the test plays the role of the NFC host by reading
+//! the replies and acknowledging them.
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+
+use futures::future::{self, Either};
+use futures::prelude::*;
+
+use rand::SeedableRng;
+
+use model::Reply;
+
+use crate::handlers::{Event, HandlerPeripherals};
+use crate::hw_common::{ChannelReceiver, ChannelSender, TscEnable};
+
+pub mod config;
+pub mod hw;
+
+/// Ends of the NFC channels held by the host
+pub struct HostChannels {
+    pub replies: ChannelReceiver<Reply>,
+    pub nfc_finished: ChannelSender<()>,
+}
+
+fn make_channel<T: 'static>() -> (ChannelSender<T>, ChannelReceiver<T>) {
+    // Leak the channel to get the same `'static` lifetime of `make_channel!()` without its
+    // "only call once" check, which would fail as soon as a second test runs
+    Box::leak(Box::new(rtic_sync::channel::Channel::new())).split()
+}
+
+pub fn make_peripherals(flash: hw::Flash) -> (HandlerPeripherals, HostChannels) {
+    let (nfc, replies) = make_channel();
+    let (finished_sender, nfc_finished) = make_channel();
+
+    let peripherals = HandlerPeripherals {
+        nfc,
+        nfc_finished,
+        display: hw::Display::new(),
+        rng: rand_chacha::ChaCha20Rng::from_seed([0u8; 32]),
+        flash,
+        tsc_enabled: TscEnable::new(Rc::new(RefCell::new(false)), Default::default()),
+        settings: Default::default(),
+        last_payment: None,
+        payjoin_original: None,
+        reserves_message: None,
+        fiat_rate: None,
+        cpfp: None,
+        signing_checkpoint: None,
+        host: Default::default(),
+        wallet_policy: Default::default(),
+    };
+    let host = HostChannels {
+        replies,
+        nfc_finished: finished_sender,
+    };
+
+    (peripherals, host)
+}
+
+/// Stream that yields `events` and then never ends, like the one fed to the handlers on the device
+pub fn events(events: impl IntoIterator<Item = Event>) -> impl Stream<Item = Event> + Unpin {
+    stream::iter(events.into_iter().collect::<alloc::vec::Vec<_>>()).chain(stream::pending())
+}
+
+/// Run `handler` until it either returns or sends a reply to the host
+///
+/// When a final reply is received the "finished" signal is sent back, like the NFC task would do
+/// once the host reads it.
+pub fn run_until_reply<F: Future + Unpin>(
+    handler: F,
+    host: &mut HostChannels,
+) -> Either<F::Output, Reply> {
+    futures::executor::block_on(async {
+        match future::select(handler, host.replies.recv().boxed_local()).await {
+            Either::Left((output, _)) => Either::Left(output),
+            Either::Right((reply, _)) => {
+                let reply = reply.expect("Channel is always alive");
+                if !matches!(
+                    reply,
+                    Reply::Pong | Reply::DelayedReply | Reply::Progress { .. }
+                ) {
+                    let _ = host.nfc_finished.try_send(());
+                }
+                Either::Right(reply)
+            }
+        }
+    })
+}
@@ -36,6 +36,16 @@ const fn get_current_version() -> u32 {
 }
 
 pub const CURRENT_VERSION: u32 = get_current_version();
+
+/// Format a version number as `major.minor.patch`
+pub fn format_version(version: u32) -> alloc::string::String {
+    alloc::format!(
+        "{}.{}.{}",
+        version / 10000,
+        (version / 100) % 100,
+        version % 100
+    )
+}
 pub const CURRENT_VARIANT: u8 = 0x00;
 
 pub const TAIL_SIZE: usize = 5;
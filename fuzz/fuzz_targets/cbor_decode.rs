@@ -0,0 +1,33 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use model::{Reply, Request};
+
+// Requests are decoded by the device, replies by the SDK: both come from the other side of
+// the NFC link
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = model::minicbor::decode::<Request>(data) {
+        let _ = model::minicbor::to_vec(&request).expect("Encoding succeeds");
+    }
+    if let Ok(reply) = model::minicbor::decode::<Reply>(data) {
+        let _ = model::minicbor::to_vec(&reply).expect("Encoding succeeds");
+    }
+});
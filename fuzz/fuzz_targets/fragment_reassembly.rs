@@ -0,0 +1,41 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use model::{Message, MessageFragment, MAX_FRAGMENT_LEN};
+
+fuzz_target!(|data: &[u8]| {
+    let mut message = Message::empty();
+    for chunk in data.chunks(MAX_FRAGMENT_LEN) {
+        match message.push_fragment(MessageFragment::from(chunk)) {
+            Ok(false) => continue,
+            Ok(true) | Err(_) => break,
+        }
+    }
+
+    // Splitting the data again and reassembling it must give back the same message
+    let mut reassembled = Message::empty();
+    for fragment in Message::from_slice(message.data()).get_fragments() {
+        reassembled
+            .push_fragment(fragment)
+            .expect("Valid fragments");
+    }
+    assert_eq!(reassembled.data(), message.data());
+});
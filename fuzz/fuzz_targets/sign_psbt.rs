@@ -0,0 +1,36 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use model::bitcoin::Network;
+use model::psbt;
+
+// Same checks performed by the firmware in `handle_sign_request` before showing anything
+// to the user
+fuzz_target!(|data: &[u8]| {
+    let psbt = match psbt::parse_psbt(data) {
+        Ok(psbt) => psbt,
+        Err(_) => return,
+    };
+
+    let _ = psbt::fees(&psbt, false);
+    let _ = psbt::fees(&psbt, true);
+    let _ = psbt::output_addresses(&psbt, Network::Bitcoin);
+});
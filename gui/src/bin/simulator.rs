@@ -45,6 +45,7 @@ fn main() -> Result<(), std::convert::Infallible> {
         "mnemonic" => mnemonic_page(&mut window, &mut display)?,
         "output" => output_page(&mut window, &mut display)?,
         "tx_summary" => tx_summary_page(&mut window, &mut display)?,
+        "op_return" => op_return_page(&mut window, &mut display)?,
         p => panic!("Invalid page selected: {}", p),
     }
 
@@ -170,7 +171,12 @@ fn output_page(
     )
     .unwrap();
     let value = model::bitcoin::Amount::from_sat(30004732);
-    let mut p = TxOutputPage::new(&address, value);
+    let mut p = TxOutputPage::new(
+        &address,
+        value,
+        model::settings::AmountUnit::Btc,
+        Some("~19502.08 USD"),
+    );
 
     loop {
         std::thread::sleep(Duration::from_millis(250));
@@ -188,12 +194,40 @@ fn output_page(
         p.draw_to(display)?;
     }
 }
+fn op_return_page(
+    window: &mut Window,
+    display: &mut SimulatorDisplay<BinaryColor>,
+) -> Result<(), std::convert::Infallible> {
+    let value = model::bitcoin::Amount::from_sat(0);
+    let mut p = OpReturnPage::new(
+        b"Hello from the Portal simulator",
+        value,
+        model::settings::AmountUnit::Btc,
+    );
+
+    loop {
+        std::thread::sleep(Duration::from_millis(250));
+        p.next();
+
+        window.update(&display);
+
+        for event in window.events() {
+            match event {
+                SimulatorEvent::Quit => std::process::exit(0),
+                _ => {}
+            }
+        }
+
+        p.draw_to(display)?;
+    }
+}
+
 fn tx_summary_page(
     window: &mut Window,
     display: &mut SimulatorDisplay<BinaryColor>,
 ) -> Result<(), std::convert::Infallible> {
     let value = model::bitcoin::Amount::from_sat(1230);
-    let p = TxSummaryPage::new(value);
+    let p = TxSummaryPage::new(value, Some(12.3), model::settings::AmountUnit::Sats);
     confirm_bar_page(window, display, p)
 }
 
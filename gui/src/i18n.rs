@@ -0,0 +1,272 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Text shown on the device, in every language it's translated to
+//!
+//! The language is set once when the settings are applied, so that pages don't have to carry it
+//! around. Translations must be ASCII only, like the fonts they're drawn with.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+pub use model::settings::Language;
+
+static LANGUAGE: AtomicU8 = AtomicU8::new(Language::English as u8);
+
+/// Show the text in `language` from now on
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        1 => Language::Italian,
+        _ => Language::English,
+    }
+}
+
+macro_rules! labels {
+    ($($label:ident => [$en:literal, $it:literal],)*) => {
+        /// A string shown on the device, translated with [`Label::get`]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Label {
+            $($label,)*
+        }
+
+        impl Label {
+            /// The text in the current language, see [`set_language`]
+            pub fn get(self) -> &'static str {
+                self.translate(language())
+            }
+
+            pub fn translate(self, language: Language) -> &'static str {
+                let texts: [&'static str; 2] = match self {
+                    $(Label::$label => [$en, $it],)*
+                };
+                texts[language as usize]
+            }
+        }
+    };
+}
+
+labels! {
+    // Hints below the confirm bar, at most 25 characters per line
+    HoldForNextPage => ["HOLD BTN FOR NEXT PAGE", "TIENI PREMUTO: AVANTI"],
+    HoldToContinue => ["HOLD BTN TO CONTINUE", "TIENI PREMUTO: CONTINUA"],
+    HoldToApplyChanges => ["HOLD BTN TO APPLY CHANGES", "TIENI PREMUTO: APPLICA"],
+    HoldToSign => ["HOLD BTN TO SIGN", "TIENI PREMUTO: FIRMA"],
+    HoldToSignTx => ["HOLD BTN TO SIGN TX", "TIENI PREMUTO: FIRMA TX"],
+    HoldToConfirm => ["HOLD BTN TO CONFIRM", "TIENI PREMUTO: CONFERMA"],
+    HoldToExport => ["HOLD BTN TO EXPORT", "TIENI PREMUTO: ESPORTA"],
+    HoldToExportLogs => ["HOLD BTN TO EXPORT LOGS", "TIENI PREMUTO: ESP. LOG"],
+    HoldToExportDesc => ["HOLD BTN TO EXPORT DESC", "TIENI PREMUTO: ESP. DESC"],
+    HoldToWipe => ["HOLD BTN TO WIPE", "TIENI PREMUTO: CANCELLA"],
+    HoldToPair => ["HOLD BTN TO PAIR", "TIENI PREMUTO: ASSOCIA"],
+    HoldToExit => ["HOLD BTN TO EXIT", "TIENI PREMUTO: ESCI"],
+    HoldToBegin => ["HOLD BTN TO BEGIN", "TIENI PREMUTO: INIZIA"],
+    HoldToAdd => ["HOLD BTN TO ADD", "TIENI PREMUTO: AGGIUNGI"],
+    HoldForAmount => ["HOLD BTN FOR AMOUNT", "TIENI PREMUTO: IMPORTO"],
+    TapForNext => ["TAP FOR NEXT", "TOCCA: AVANTI"],
+    TapChangeHoldNext => ["TAP: CHANGE, HOLD: NEXT", "TOCCA: CAMBIA, TIENI: OK"],
+    TapSkipHoldStart => ["TAP: SKIP, HOLD: START", "TOCCA: SALTA, TIENI: VIA"],
+    TapDiscardHoldSave => ["TAP: DISCARD, HOLD: SAVE", "TOCCA: NO, TIENI: SALVA"],
+    KeepHolding => ["KEEP HOLDING...", "CONTINUA A PREMERE..."],
+    Measuring => ["MEASURING...", "MISURAZIONE..."],
+    EnterItInTheApp => ["ENTER IT IN THE APP", "INSERISCILO NELL'APP"],
+    UpdateInProgress => ["UPDATE IN PROGRESS", "AGGIORNAMENTO IN CORSO"],
+    SigningInputs => ["SIGNING INPUTS", "FIRMA DEGLI INPUT"],
+    UseAppToInitialize => ["USE APP TO INITIALIZE", "USA L'APP PER INIZIARE"],
+    // Single line pages, at most 16 characters per line
+    Loading => ["LOADING", "CARICAMENTO"],
+    Locked => ["LOCKED", "BLOCCATO"],
+    UpdateComplete => ["UPDATE COMPLETE", "AGGIORNATO"],
+    TouchButton => ["TOUCH BTN", "TOCCA IL TASTO"],
+    // Summaries, at most 14 characters per line
+    Welcome => ["Welcome", "Benvenuto"],
+    PortalReady => ["Portal ready", "Pronto"],
+    SigningTx => ["Signing tx...", "Firma tx..."],
+    ExportBackup => ["Export\nbackup?", "Esportare il\nbackup?"],
+    RestoreBackup => ["Restore\nbackup?", "Ripristinare\nil backup?"],
+    ChangeSeedExport => ["Change seed\nexport?", "Cambiare\nl'export seed?"],
+    ExportLogs => ["Export\nlogs?", "Esportare\ni log?"],
+    ExportNodeSeed => ["Export node\nseed?", "Esportare il\nseed del nodo?"],
+    PairHost => ["Pair this\nhost?", "Associare\nquesto host?"],
+    WipeDevice => ["Wipe\ndevice?", "Cancellare\nil device?"],
+    TrustAddress => ["Trust this\naddress?", "Aggiungere\nl'indirizzo?"],
+    ChangeConfirmations => ["Change\nconfirmations?", "Cambiare le\nconferme?"],
+    AllowWatchOnly => ["Allow watch\nonly access?", "Consentire\nwatch only?"],
+    SaveConfiguration => ["Save new\nconfiguration?", "Salvare la\nnuova config?"],
+    CalibrateTouch => ["Calibrate\ntouch?", "Calibrare\nil tocco?"],
+    SaveSettings => ["Save\nsettings?", "Salvare le\nimpostazioni?"],
+    SignMessageWithAddress => ["Sign message\nwith address", "Firmare con\nl'indirizzo"],
+    // Titles, at most 21 characters per line
+    Warning => ["WARNING", "ATTENZIONE"],
+    ErrorTryAgain => ["ERROR\nTRY AGAIN", "ERRORE\nRIPROVA"],
+    Wallet => ["Wallet", "Wallet"],
+    WalletName => ["Wallet name", "Nome wallet"],
+    WalletPolicy => ["Wallet policy", "Policy wallet"],
+    AddressType => ["Address type", "Tipo indirizzo"],
+    KeyDerivation => ["Key derivation", "Derivazione chiave"],
+    Threshold => ["Threshold", "Soglia"],
+    ConfirmFirstAddress => ["Confirm first address", "Primo indirizzo"],
+    ExportPublicKey => ["Export public key?", "Esportare la chiave?"],
+    TouchCalibration => ["Touch calibration", "Calibrazione tocco"],
+    Fingerprint => ["Fingerprint", "Fingerprint"],
+    Firmware => ["Firmware", "Firmware"],
+    Network => ["Network", "Rete"],
+    SeedStrength => ["Seed strength", "Robustezza seed"],
+    LightningNode => ["Lightning node", "Nodo Lightning"],
+    WipeCode => ["Wipe code", "Codice cancellazione"],
+    PairCode => ["Pair Code", "Codice associazione"],
+    PairHostTitle => ["Pair host", "Associa host"],
+    TrustAddressTitle => ["Trust address", "Indirizzo fidato"],
+    ExportBackupTitle => ["Export backup", "Esporta backup"],
+    RestoreBackupTitle => ["Restore backup", "Ripristina backup"],
+    SeedExport => ["Seed export", "Export del seed"],
+    NoSeedInBackups => ["No seed in backups", "Backup senza seed"],
+    ExportXpub => ["Export xpub", "Esporta xpub"],
+    ExportDescriptor => ["Export descriptor", "Esporta descriptor"],
+    DescriptorChecksum => ["Descriptor checksum", "Checksum descriptor"],
+    TransactionFee => ["Transaction Fee", "Fee transazione"],
+    LocktimeRbf => ["Locktime & RBF", "Locktime & RBF"],
+    SpendingVia => ["Spending via", "Speso tramite"],
+    SendingTo => ["Sending to", "Verso"],
+    Recipient => ["recipient", "destinatario"],
+    Recipients => ["recipients", "destinatari"],
+    Amount => ["Amount", "Importo"],
+    Change => ["Change", "Resto"],
+    OutputLabel => ["Label", "Etichetta"],
+    Multisig => ["Multisig", "Multisig"],
+    MultisigSignatures => ["Multisig signatures", "Firme multisig"],
+    InputTitle => ["Input", "Input"],
+    Yours => ["yours", "tuo"],
+    External => ["external", "esterno"],
+    Address => ["Address", "Indirizzo"],
+    Key => ["Key", "Chiave"],
+    ScriptPathTitle => ["Script path", "Script path"],
+    Path => ["Path", "Path"],
+    PathKey => ["key", "chiave"],
+    FiatValues => ["Fiat values", "Valori in valuta"],
+    TrustedAddresses => ["Trusted addresses", "Indirizzi fidati"],
+    BumpingParentTx => ["Bumping parent tx", "Bump della tx padre"],
+    FeeBump => ["Fee bump", "Bump della fee"],
+    BatchSigned => ["Batch signed", "Batch firmato"],
+    CoinjoinYouSend => ["Coinjoin: you send", "Coinjoin: invii"],
+    YouReceive => ["You receive", "Ricevi"],
+    NetChange => ["Net change", "Variazione netta"],
+    PayjoinInputs => ["Payjoin inputs", "Input payjoin"],
+    YourChange => ["Your change", "Il tuo resto"],
+    ExtraCost => ["Extra cost", "Costo extra"],
+    PayjoinReceiving => ["Payjoin receiving", "Payjoin in entrata"],
+    Contributing => ["Contributing", "Contributo"],
+    YourFeeShare => ["Your fee share", "Tua quota di fee"],
+    ProofOfReserves => ["Proof of reserves", "Prova di riserve"],
+    Reserves => ["Reserves", "Riserve"],
+    Message => ["Message", "Messaggio"],
+    MessageSha256 => ["Message SHA256", "SHA256 messaggio"],
+    // Values, at most 16 characters per line
+    ReleaseButton => ["Release\nthe button", "Rilascia\nil tasto"],
+    TouchAndHoldButton => ["Touch and hold\nthe button", "Tieni premuto\nil tasto"],
+    Calibrated => ["Calibrated", "Calibrato"],
+    CalibrationFailed => ["Failed, kept\nthe previous one", "Fallita, resta\nla precedente"],
+    SettingsAndSeed => ["Settings and seed", "Impostazioni\ne seed"],
+    SettingsOnly => ["Settings only", "Solo\nimpostazioni"],
+    Allow => ["Allow", "Consenti"],
+    NeverAllow => ["Never allow", "Non consentire"],
+    AreYouSure => ["Are you sure?", "Sei sicuro?"],
+    NoConfirmation => ["No confirmation", "Senza conferma"],
+    Confirm => ["Confirm", "Conferma"],
+    NoLocktime => ["No locktime", "Nessun locktime"],
+    Block => ["Block", "Blocco"],
+    Replaceable => ["Replaceable", "Sostituibile"],
+    NotReplaceable => ["Not replaceable", "Non sostituibile"],
+    KeyPath => ["Key path", "Percorso chiave"],
+    ScriptPath => ["Script path", "Percorso script"],
+    CannotMoveFunds => ["Cannot move funds", "Fondi non\nspendibili"],
+    UnspendableKeyPath => ["Unspendable\nScript paths only", "Non spendibile\nSolo script path"],
+    NonStandardPath => ["Non-standard\nderivation path", "Derivazione\nnon standard"],
+    HighFees => ["High fees", "Fee elevate"],
+    HighFeeRate => ["High fee rate", "Fee rate elevato"],
+    Transactions => ["transactions", "transazioni"],
+    AddressReuse => ["Address reuse", "Riuso indirizzo"],
+    ExternalInput => ["External input", "Input esterno"],
+    ExternalInputs => ["External inputs", "Input esterni"],
+    LargeAmount => ["Large amount", "Importo elevato"],
+    Of => ["of", "di"],
+    Receive => ["Receive", "Ricevi"],
+    ThisDevice => ["This device", "Questo device"],
+    Keys => ["keys", "chiavi"],
+    ScriptPaths => ["script paths", "script path"],
+    Mainnet => ["Mainnet", "Mainnet"],
+    Testnet => ["Testnet", "Testnet"],
+    Signet => ["Signet", "Signet"],
+    Regtest => ["Regtest", "Regtest"],
+    SingleSig => ["Single-sig", "Single-sig"],
+    Words => ["words", "parole"],
+    Empty => ["(empty)", "(vuoto)"],
+    // Parts of the notes below a value, at most 21 characters per line with the rest of the note
+    After => ["after", "dopo"],
+    Blocks => ["blocks", "blocchi"],
+    FromBlock => ["from block", "dal blocco"],
+    From => ["from", "dal"],
+    NothingSent => ["nothing sent", "nulla inviato"],
+    OfOutputs => ["of outputs", "degli output"],
+    BurntByOpReturn => ["burnt by OP_RETURN", "bruciati da OP_RETURN"],
+    SighashOfInput => ["sighash of input", "sighash dell'input"],
+    InFees => ["in fees", "di fee"],
+    More => ["more", "altri"],
+    Fee => ["Fee", "Fee"],
+    ThisDeviceSigned => ["this device signed", "firmato dal device"],
+    ThisDevicePending => ["this device pending", "device in attesa"],
+    PerBtcFromHost => ["per BTC, from host", "per BTC, dall'host"],
+    Output => ["output", "output"],
+    Outputs => ["outputs", "output"],
+    Input => ["input", "input"],
+    Inputs => ["inputs", "input"],
+    Was => ["was", "prima"],
+    FromReceiver => ["from receiver", "dal ricevente"],
+    Bits => ["bits", "bit"],
+    Bytes => ["bytes", "byte"],
+    // Error messages, at most 25 characters per line
+    InvalidTransaction => ["Invalid transaction", "Transazione non valida"],
+    InvalidFirmware => ["Invalid Firmware", "Firmware non valido"],
+    InvalidPairCode => ["Invalid Pair Code", "Codice non valido"],
+    CommunicationError => ["Communication Error", "Errore di comunicazione"],
+    MemoryError => ["Memory Error", "Errore di memoria"],
+    DisplayError => ["Display Error", "Errore del display"],
+    WalletError => ["Wallet Error", "Errore del wallet"],
+    GeneralFailure => ["General Failure", "Errore generico"],
+    RngFailure => ["RNG Failure", "Errore del RNG"],
+    TimedOut => ["Timed Out", "Tempo scaduto"],
+    // Settings menu, at most 21 characters per line
+    ConfirmSpeed => ["Confirm speed", "Velocita conferma"],
+    ScrollSpeed => ["Scroll speed", "Velocita scorrimento"],
+    AutoLock => ["Auto-lock", "Blocco automatico"],
+    Brightness => ["Brightness", "Luminosita"],
+    Language => ["Language", "Lingua"],
+    TextSize => ["Text size", "Dimensione testo"],
+    IdleScreen => ["Idle screen", "Schermata di attesa"],
+    ReviewInputs => ["Review inputs", "Verifica input"],
+    TouchSensitivity => ["Touch sensitivity", "Sensibilita tocco"],
+    Timeout => ["Timeout", "Timeout"],
+    DebugLogs => ["Debug logs", "Log di debug"],
+    FeeWarning => ["Fee warning", "Avviso fee"],
+    FeeRateWarning => ["Fee rate warning", "Avviso fee rate"],
+    OtherSighashes => ["Other sighashes", "Altri sighash"],
+    UtxoChecks => ["UTXO checks", "Controlli UTXO"],
+    ChangeOutputs => ["Change outputs", "Output di resto"],
+    AmountUnit => ["Amount unit", "Unita degli importi"],
+}
@@ -19,14 +19,19 @@
 
 extern crate alloc;
 
+pub mod i18n;
+
 use embedded_graphics::draw_target::Clipped;
-use embedded_graphics::mono_font::{ascii, MonoTextStyle};
+use embedded_graphics::mono_font::{ascii, iso_8859_1, MonoTextStyle};
 use embedded_graphics::pixelcolor::BinaryColor::{self, *};
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
 use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
 
-use model::bitcoin::{Address, Amount, Denomination};
+use model::bitcoin::{Address, Amount};
+use model::settings::AmountUnit;
+
+use i18n::Label;
 
 const AMOUNT_Y_OFFSET: i32 = 6;
 
@@ -76,7 +81,7 @@ impl<'s> Page for WelcomePage<'s> {
         self.reset(target)?;
 
         Text::with_text_style(
-            "Welcome",
+            Label::Welcome.get(),
             screen_size.center(),
             MonoTextStyle::new(&ascii::FONT_9X15_BOLD, On),
             TextStyleBuilder::new()
@@ -87,7 +92,7 @@ impl<'s> Page for WelcomePage<'s> {
         .draw(target)?;
 
         Text::with_text_style(
-            "USE APP TO INITIALIZE",
+            Label::UseAppToInitialize.get(),
             screen_size.center() + Point::new(0, 4),
             MonoTextStyle::new(&ascii::FONT_5X8, On),
             TextStyleBuilder::new()
@@ -210,6 +215,84 @@ impl<'s> Page for InitialPage<'s> {
     }
 }
 
+/// Size in pixels of the cells of the identicon
+const IDENTICON_CELL: u32 = 8;
+
+/// Idle page showing the fingerprint of the wallet and an identicon derived from it
+///
+/// The identicon is a 5x5 grid, mirrored horizontally, filled with the first 15 bits of the
+/// fingerprint.
+pub struct FingerprintPage {
+    fingerprint: [u8; 4],
+    fingerprint_str: alloc::string::String,
+}
+
+impl FingerprintPage {
+    pub fn new(fingerprint: [u8; 4]) -> Self {
+        FingerprintPage {
+            fingerprint,
+            fingerprint_str: fingerprint
+                .iter()
+                .map(|b| alloc::format!("{:02x}", b))
+                .collect(),
+        }
+    }
+
+    fn is_cell_on(&self, row: u32, col: u32) -> bool {
+        let col = core::cmp::min(col, 4 - col);
+        let bits = u32::from_be_bytes(self.fingerprint);
+        (bits >> (31 - (row * 3 + col))) & 1 == 1
+    }
+}
+
+impl Page for FingerprintPage {
+    fn draw_to<T>(&self, target: &mut T) -> Result<(), <T as DrawTarget>::Error>
+    where
+        T: DrawTarget<Color = BinaryColor>,
+    {
+        let origin = Point::new(8, 12);
+        for row in 0..5 {
+            for col in 0..5 {
+                if !self.is_cell_on(row, col) {
+                    continue;
+                }
+
+                Rectangle::new(
+                    origin
+                        + Point::new((col * IDENTICON_CELL) as i32, (row * IDENTICON_CELL) as i32),
+                    Size::new(IDENTICON_CELL, IDENTICON_CELL),
+                )
+                .into_styled(PrimitiveStyle::with_fill(On))
+                .draw(target)?;
+            }
+        }
+
+        let ready = Text::with_text_style(
+            Label::PortalReady.get(),
+            Point::new(92, 30),
+            MonoTextStyle::new(&ascii::FONT_6X10, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Bottom)
+                .build(),
+        );
+        ready.draw(target)?;
+
+        let fingerprint = Text::with_text_style(
+            &self.fingerprint_str,
+            Point::new(92, 34),
+            MonoTextStyle::new(&ascii::FONT_8X13_BOLD, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Top)
+                .build(),
+        );
+        fingerprint.draw(target)?;
+
+        Ok(())
+    }
+}
+
 pub struct GeneratingMnemonicPage(SingleLineTextPage<'static>);
 impl_wrapper_page!(GeneratingMnemonicPage, SingleLineTextPage<'static>);
 impl GeneratingMnemonicPage {
@@ -226,15 +309,7 @@ pub struct LoadingPage(SingleLineTextPage<'static>);
 impl_wrapper_page!(LoadingPage, SingleLineTextPage<'static>);
 impl LoadingPage {
     pub fn new() -> Self {
-        LoadingPage(SingleLineTextPage::new("LOADING"))
-    }
-}
-
-pub struct SigningTxPage(SingleLineTextPage<'static>);
-impl_wrapper_page!(SigningTxPage, SingleLineTextPage<'static>);
-impl SigningTxPage {
-    pub fn new() -> Self {
-        SigningTxPage(SingleLineTextPage::new("Signing tx..."))
+        LoadingPage(SingleLineTextPage::new(Label::Loading.get()))
     }
 }
 
@@ -403,7 +478,26 @@ impl FwUpdateProgressPage {
             threshold,
             EmptyContent,
             "",
-            "UPDATE IN PROGRESS",
+            Label::UpdateInProgress.get(),
+            52,
+            true,
+        ))
+    }
+}
+
+/// Progress of the signature, the bar fills as the inputs are signed
+pub struct SigningProgressPage(ConfirmBarPage<'static, SummaryPageContent<'static>>);
+impl_wrapper_page!(
+    SigningProgressPage,
+    ConfirmBarPage<'static, SummaryPageContent<'static>>
+);
+impl SigningProgressPage {
+    pub fn new(inputs: u32) -> Self {
+        SigningProgressPage(ConfirmBarPage::new(
+            inputs.max(1),
+            SummaryPageContent(Label::SigningTx.get()),
+            "",
+            Label::SigningInputs.get(),
             52,
             true,
         ))
@@ -452,7 +546,7 @@ impl<'s> SummaryPage<'s> {
             threshold,
             SummaryPageContent(summary),
             idle_text,
-            "KEEP HOLDING...",
+            Label::KeepHolding.get(),
         ))
     }
 }
@@ -483,6 +577,8 @@ impl<'s, const FACTOR: usize, const WAIT_TIME: usize, const MAX_CHARS: usize>
 pub struct TxOutputPageContent<'s> {
     address: &'s Address,
     value: Amount,
+    unit: AmountUnit,
+    fiat: Option<&'s str>,
     iteration: usize,
 }
 
@@ -512,8 +608,12 @@ impl<'s> MainContent for TxOutputPageContent<'s> {
         );
         address_text.draw(target)?;
 
-        let address_summary =
-            alloc::format!("{:.8} ... {:.8}", &address, &address[address.len() - 8..]);
+        let address_summary = alloc::format!(
+            "{} {:.7}..{:.7}",
+            model::psbt::OutputType::from_address(self.address).name(),
+            &address,
+            &address[address.len() - 7..]
+        );
         let address_summary = Text::with_text_style(
             &address_summary,
             Point::new(64, 17),
@@ -525,7 +625,20 @@ impl<'s> MainContent for TxOutputPageContent<'s> {
         );
         address_summary.draw(target)?;
 
-        let value = alloc::format!("{:.8} BTC", self.value.display_in(Denomination::Bitcoin));
+        if let Some(fiat) = self.fiat {
+            let fiat_text = Text::with_text_style(
+                fiat,
+                Point::new(64, 25),
+                MonoTextStyle::new(&ascii::FONT_5X8, On),
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Center)
+                    .baseline(Baseline::Top)
+                    .build(),
+            );
+            fiat_text.draw(target)?;
+        }
+
+        let value = self.unit.format(self.value);
         let scroll = ScrollText::<1, 5, 15>::new(&value);
         let value_text = Text::with_text_style(
             &scroll.compute(self.iteration),
@@ -552,16 +665,137 @@ impl_wrapper_page!(
     ConfirmBarPage<'static, TxOutputPageContent<'s>>
 );
 impl<'s> TxOutputPage<'s> {
-    pub fn new(address: &'s Address, value: Amount) -> Self {
+    /// `fiat` is the approximate value in fiat, shown in small under the address
+    pub fn new(
+        address: &'s Address,
+        value: Amount,
+        unit: AmountUnit,
+        fiat: Option<&'s str>,
+    ) -> Self {
         TxOutputPage(ConfirmBarPage::new(
             50,
             TxOutputPageContent {
                 address,
                 value,
+                unit,
+                fiat,
+                iteration: 0,
+            },
+            Label::HoldToContinue.get(),
+            Label::KeepHolding.get(),
+            52,
+            false,
+        ))
+    }
+
+    pub fn next(&mut self) {
+        self.0.main_content.iteration += 1;
+    }
+}
+
+/// Whether the data of an `OP_RETURN` output can be shown as text
+fn is_printable(data: &[u8]) -> bool {
+    !data.is_empty() && data.iter().all(|c| (b' '..=b'~').contains(c))
+}
+
+pub struct OpReturnPageContent<'s> {
+    data: &'s [u8],
+    value: Amount,
+    unit: AmountUnit,
+    iteration: usize,
+}
+
+impl<'s> MainContent for OpReturnPageContent<'s> {
+    fn draw_to<T>(&self, target: &mut T) -> Result<(), <T as DrawTarget>::Error>
+    where
+        T: DrawTarget<Color = BinaryColor>,
+    {
+        use model::bitcoin::hashes::hex::ToHex;
+
+        let screen_size = target.bounding_box();
+        let rectangle = Rectangle::new(Point::new(0, 2), Size::new(screen_size.size.width, 25))
+            .into_styled(PrimitiveStyle::with_fill(Off));
+        rectangle.draw(target)?;
+
+        // The text when it's printable, with the hex below. Otherwise only the hex
+        let hex = self.data.to_hex();
+        let (main, summary) = if is_printable(self.data) {
+            let text = core::str::from_utf8(self.data).expect("Printable ASCII");
+            (
+                alloc::string::String::from(text),
+                alloc::format!("OP_RETURN {:.14}", hex),
+            )
+        } else if self.data.is_empty() {
+            (Label::Empty.get().into(), "OP_RETURN".into())
+        } else {
+            (
+                hex,
+                alloc::format!("OP_RETURN {} {}", self.data.len(), Label::Bytes.get()),
+            )
+        };
+
+        let scroll = ScrollText::<1, 5, 15>::new(&main);
+        let data_text = Text::with_text_style(
+            scroll.compute(self.iteration),
+            Point::new(64, 2),
+            MonoTextStyle::new(&ascii::FONT_8X13_BOLD, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Top)
+                .build(),
+        );
+        data_text.draw(target)?;
+
+        let summary_text = Text::with_text_style(
+            &summary,
+            Point::new(64, 17),
+            MonoTextStyle::new(&ascii::FONT_5X8, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Top)
+                .build(),
+        );
+        summary_text.draw(target)?;
+
+        let value = self.unit.format(self.value);
+        let value_text = Text::with_text_style(
+            &value,
+            Point::new(64, 46),
+            MonoTextStyle::new(&ascii::FONT_8X13_BOLD, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Bottom)
+                .build(),
+        );
+        value_text.draw(target)?;
+
+        Ok(())
+    }
+
+    fn tick(&mut self) -> bool {
+        self.iteration += 1;
+        true
+    }
+}
+
+/// Output that carries data (`OP_RETURN`) instead of paying an address
+pub struct OpReturnPage<'s>(ConfirmBarPage<'static, OpReturnPageContent<'s>>);
+impl_wrapper_page!(
+    OpReturnPage<'s>,
+    ConfirmBarPage<'static, OpReturnPageContent<'s>>
+);
+impl<'s> OpReturnPage<'s> {
+    pub fn new(data: &'s [u8], value: Amount, unit: AmountUnit) -> Self {
+        OpReturnPage(ConfirmBarPage::new(
+            50,
+            OpReturnPageContent {
+                data,
+                value,
+                unit,
                 iteration: 0,
             },
-            "HOLD BTN TO CONTINUE",
-            "KEEP HOLDING...",
+            Label::HoldToContinue.get(),
+            Label::KeepHolding.get(),
             52,
             false,
         ))
@@ -616,6 +850,47 @@ impl<'s, 'l> MainContent for TwoLinesText<'s, 'l> {
     }
 }
 
+/// Like [`TwoLinesText`], with a `note` in the small font below the large text
+pub struct ThreeLinesText<'s> {
+    small: &'s str,
+    large: &'s str,
+    note: &'s str,
+}
+
+impl<'s> ThreeLinesText<'s> {
+    pub fn new(small: &'s str, large: &'s str, note: &'s str) -> Self {
+        ThreeLinesText { small, large, note }
+    }
+}
+
+impl<'s> MainContent for ThreeLinesText<'s> {
+    fn draw_to<T>(&self, target: &mut T) -> Result<(), <T as DrawTarget>::Error>
+    where
+        T: DrawTarget<Color = BinaryColor>,
+    {
+        let small_style = MonoTextStyle::new(&ascii::FONT_6X10, On);
+        let top = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Top)
+            .build();
+
+        Text::with_text_style(self.small, Point::new(64, 4), small_style, top).draw(target)?;
+        Text::with_text_style(
+            self.large,
+            Point::new(64, 28),
+            MonoTextStyle::new(&ascii::FONT_8X13_BOLD, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Bottom)
+                .build(),
+        )
+        .draw(target)?;
+        Text::with_text_style(self.note, Point::new(64, 30), small_style, top).draw(target)?;
+
+        Ok(())
+    }
+}
+
 pub struct ConfirmPairCodePage<'s>(ConfirmBarPage<'static, TwoLinesText<'static, 's>>);
 impl_wrapper_page!(
     ConfirmPairCodePage<'s>,
@@ -625,9 +900,9 @@ impl<'s> ConfirmPairCodePage<'s> {
     pub fn new(pair_code: &'s str) -> Self {
         ConfirmPairCodePage(ConfirmBarPage::new_default_bar(
             100,
-            TwoLinesText::new("Pair Code", pair_code),
-            "HOLD BTN TO CONFIRM",
-            "KEEP HOLDING...",
+            TwoLinesText::new(Label::PairCode.get(), pair_code),
+            Label::HoldToConfirm.get(),
+            Label::KeepHolding.get(),
         ))
     }
 }
@@ -643,7 +918,29 @@ impl<'s> GenericTwoLinePage<'s> {
             threshold,
             TwoLinesText::new(small, large),
             &confirm_text,
-            "KEEP HOLDING...",
+            Label::KeepHolding.get(),
+        ))
+    }
+}
+
+pub struct GenericThreeLinePage<'s>(ConfirmBarPage<'s, ThreeLinesText<'s>>);
+impl_wrapper_page!(
+    GenericThreeLinePage<'s>,
+    ConfirmBarPage<'s, ThreeLinesText<'s>>
+);
+impl<'s> GenericThreeLinePage<'s> {
+    pub fn new(
+        small: &'s str,
+        large: &'s str,
+        note: &'s str,
+        confirm_text: &'s str,
+        threshold: u32,
+    ) -> Self {
+        GenericThreeLinePage(ConfirmBarPage::new_default_bar(
+            threshold,
+            ThreeLinesText::new(small, large, note),
+            confirm_text,
+            Label::KeepHolding.get(),
         ))
     }
 }
@@ -718,33 +1015,162 @@ impl<'s> ShowScrollingAddressPage<'s> {
             100,
             ShowScrollingAddressContent::new(address, message),
             bar_message,
-            "KEEP HOLDING...",
+            Label::KeepHolding.get(),
+        ))
+    }
+}
+
+/// Characters of `FONT_10X20` that fit in a line
+const LARGE_CHARS_PER_LINE: usize = 12;
+/// Lines of `FONT_10X20` that fit above the confirmation bar
+const LARGE_LINES_PER_PAGE: usize = 2;
+/// How long every page of large text stays on the screen
+const LARGE_PAGE_TICKS: usize = 4;
+
+/// Split `text` in lines of at most `max_chars`, breaking at spaces when possible
+fn wrap_text(text: &str, max_chars: usize) -> alloc::vec::Vec<alloc::string::String> {
+    let mut lines: alloc::vec::Vec<alloc::string::String> = alloc::vec::Vec::new();
+    for mut word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= max_chars => {
+                line.push(' ');
+                line.push_str(word);
+                continue;
+            }
+            _ => {}
+        }
+
+        while !word.is_empty() {
+            let (head, tail) = word.split_at(core::cmp::min(max_chars, word.len()));
+            lines.push(head.into());
+            word = tail;
+        }
+    }
+
+    lines
+}
+
+pub struct LargeTextContent<'s> {
+    title: &'s str,
+    lines: alloc::vec::Vec<alloc::string::String>,
+    iteration: usize,
+}
+
+impl<'s> LargeTextContent<'s> {
+    fn num_pages(&self) -> usize {
+        core::cmp::max(1, self.lines.len().div_ceil(LARGE_LINES_PER_PAGE))
+    }
+}
+
+impl<'s> MainContent for LargeTextContent<'s> {
+    fn draw_to<T>(&self, target: &mut T) -> Result<(), <T as DrawTarget>::Error>
+    where
+        T: DrawTarget<Color = BinaryColor>,
+    {
+        let screen_size = target.bounding_box();
+        let rectangle = Rectangle::new(Point::new(0, 0), Size::new(screen_size.size.width, 52))
+            .into_styled(PrimitiveStyle::with_fill(Off));
+        rectangle.draw(target)?;
+
+        let num_pages = self.num_pages();
+        let page = (self.iteration / LARGE_PAGE_TICKS) % num_pages;
+        let title = match num_pages {
+            1 => alloc::string::String::from(self.title),
+            _ => alloc::format!("{} ({}/{})", self.title, page + 1, num_pages),
+        };
+        let title_text = Text::with_text_style(
+            &title,
+            Point::new(64, 0),
+            MonoTextStyle::new(&ascii::FONT_6X10, On),
+            TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Top)
+                .build(),
+        );
+        title_text.draw(target)?;
+
+        let lines = self
+            .lines
+            .iter()
+            .skip(page * LARGE_LINES_PER_PAGE)
+            .take(LARGE_LINES_PER_PAGE);
+        for (i, line) in lines.enumerate() {
+            let line_text = Text::with_text_style(
+                line,
+                Point::new(64, 11 + 20 * i as i32),
+                MonoTextStyle::new(&ascii::FONT_10X20, On),
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Center)
+                    .baseline(Baseline::Top)
+                    .build(),
+            );
+            line_text.draw(target)?;
+        }
+
+        Ok(())
+    }
+
+    fn tick(&mut self) -> bool {
+        let page = self.iteration / LARGE_PAGE_TICKS;
+        self.iteration += 1;
+        self.iteration / LARGE_PAGE_TICKS != page
+    }
+}
+
+/// Large font alternative to the pages showing amounts, addresses and fees
+///
+/// The text is split over as many pages as needed, which are cycled automatically.
+pub struct LargeTextPage<'s>(ConfirmBarPage<'s, LargeTextContent<'s>>);
+impl_wrapper_page!(LargeTextPage<'s>, ConfirmBarPage<'s, LargeTextContent<'s>>);
+impl<'s> LargeTextPage<'s> {
+    pub fn new(title: &'s str, text: &str, idle_text: &'s str, threshold: u32) -> Self {
+        LargeTextPage(ConfirmBarPage::new(
+            threshold,
+            LargeTextContent {
+                title,
+                lines: wrap_text(text, LARGE_CHARS_PER_LINE),
+                iteration: 0,
+            },
+            idle_text,
+            Label::KeepHolding.get(),
+            52,
+            false,
         ))
     }
 }
 
 pub struct TxSummaryPageContent {
     fees: Amount,
+    fee_rate: Option<f32>,
+    unit: AmountUnit,
 }
 impl MainContent for TxSummaryPageContent {
     fn draw_to<T>(&self, target: &mut T) -> Result<(), <T as DrawTarget>::Error>
     where
         T: DrawTarget<Color = BinaryColor>,
     {
-        let fees_str = alloc::format!("{:.8} BTC", self.fees.display_in(Denomination::Bitcoin));
-        let content = TwoLinesText::new("Transaction Fee", &fees_str);
+        let mut fees_str = self.unit.format(self.fees);
+        if let Some(fee_rate) = self.fee_rate {
+            fees_str += &alloc::format!("\n{:.1} sat/vB", fee_rate);
+        }
+        let content = TwoLinesText::new(Label::TransactionFee.get(), &fees_str);
         content.draw_to(target)
     }
 }
+/// Fees of the transaction, with the fee rate in sat/vB when it could be estimated
 pub struct TxSummaryPage(ConfirmBarPage<'static, TxSummaryPageContent>);
 impl_wrapper_page!(TxSummaryPage, ConfirmBarPage<'static, TxSummaryPageContent>);
 impl TxSummaryPage {
-    pub fn new(fees: Amount) -> Self {
+    pub fn new(fees: Amount, fee_rate: Option<f32>, unit: AmountUnit) -> Self {
         TxSummaryPage(ConfirmBarPage::new_default_bar(
             80,
-            TxSummaryPageContent { fees },
-            "HOLD BTN TO SIGN TX",
-            "KEEP HOLDING...",
+            TxSummaryPageContent {
+                fees,
+                fee_rate,
+                unit,
+            },
+            Label::HoldToSignTx.get(),
+            Label::KeepHolding.get(),
         ))
     }
 }
@@ -759,7 +1185,8 @@ impl<'w, 'l> MainContent for MnemonicPageContent<'w, 'l> {
         T: DrawTarget<Color = BinaryColor>,
     {
         let number_font = MonoTextStyle::new(&ascii::FONT_6X10, On);
-        let word_font = MonoTextStyle::new(&ascii::FONT_8X13_BOLD, On);
+        // Same glyphs as the ASCII font, plus the accented letters of the French and Spanish words
+        let word_font = MonoTextStyle::new(&iso_8859_1::FONT_8X13_BOLD, On);
         let number_style = TextStyleBuilder::new()
             .alignment(Alignment::Right)
             .baseline(Baseline::Bottom)
@@ -802,8 +1229,8 @@ impl<'w, 'l> MnemonicPage<'w, 'l> {
         MnemonicPage(ConfirmBarPage::new_default_bar(
             50,
             MnemonicPageContent { words, offset },
-            "HOLD BTN TO CONTINUE",
-            "KEEP HOLDING...",
+            Label::HoldToContinue.get(),
+            Label::KeepHolding.get(),
         ))
     }
 }
@@ -835,7 +1262,7 @@ impl Page for FwUpdatePage {
         let screen_size = target.bounding_box();
 
         let text = Text::with_text_style(
-            "UPDATE IN PROGRESS",
+            Label::UpdateInProgress.get(),
             screen_size.center(),
             MonoTextStyle::new(&ascii::FONT_5X8, On),
             TextStyleBuilder::new()
@@ -875,7 +1302,7 @@ impl<'s> Page for ErrorPage<'s> {
         let screen_size = target.bounding_box();
 
         let text = Text::with_text_style(
-            "ERROR\nTRY AGAIN",
+            Label::ErrorTryAgain.get(),
             screen_size.center() - Point::new(0, 16),
             MonoTextStyle::new(&ascii::FONT_6X10, On),
             TextStyleBuilder::new()
@@ -899,3 +1326,56 @@ impl<'s> Page for ErrorPage<'s> {
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// Every pixel on
+    Filled,
+    /// Alternating 8x8 squares
+    Checkerboard,
+}
+
+/// Full-screen pattern used by the self-test to spot dead pixels or a damaged panel
+#[derive(Debug)]
+pub struct TestPatternPage {
+    pattern: TestPattern,
+}
+
+impl TestPatternPage {
+    pub fn new(pattern: TestPattern) -> Self {
+        TestPatternPage { pattern }
+    }
+}
+
+impl Page for TestPatternPage {
+    fn draw_to<T>(&self, target: &mut T) -> Result<(), <T as DrawTarget>::Error>
+    where
+        T: DrawTarget<Color = BinaryColor>,
+    {
+        let screen_size = target.bounding_box();
+
+        match self.pattern {
+            TestPattern::Filled => {
+                screen_size
+                    .into_styled(PrimitiveStyle::with_fill(On))
+                    .draw(target)?;
+            }
+            TestPattern::Checkerboard => {
+                const SQUARE: u32 = 8;
+
+                for y in 0..screen_size.size.height / SQUARE {
+                    for x in (y % 2..screen_size.size.width / SQUARE).step_by(2) {
+                        Rectangle::new(
+                            Point::new((x * SQUARE) as i32, (y * SQUARE) as i32),
+                            Size::new(SQUARE, SQUARE),
+                        )
+                        .into_styled(PrimitiveStyle::with_fill(On))
+                        .draw(target)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
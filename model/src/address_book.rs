@@ -0,0 +1,85 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Addresses trusted by the user, whose outputs are reviewed together
+//!
+//! An address is added with `Request::AddTrustedAddress` after a confirmation on the device, and
+//! is kept with the rest of `SecretData`, encrypted when the device has a pair code. When
+//! signing, the outputs paying to trusted addresses are shown on a single page with their total
+//! value instead of one by one.
+
+use core::str::FromStr;
+
+use bitcoin::{Address, Network, Script};
+
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+
+/// Maximum number of addresses in the address book
+pub const MAX_TRUSTED_ADDRESSES: usize = 16;
+
+/// Address in the address book, stored as its script so that outputs can be compared directly
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct TrustedAddress {
+    #[cbor(n(0))]
+    pub script_pubkey: ByteVec,
+}
+
+impl TrustedAddress {
+    pub fn new(address: &Address) -> Self {
+        TrustedAddress {
+            script_pubkey: address.script_pubkey().to_bytes().into(),
+        }
+    }
+}
+
+/// Parse an address sent by the host, `None` if it's invalid or for another network
+pub fn parse_address(address: &str, network: Network) -> Option<Address> {
+    Address::from_str(address)
+        .ok()
+        .filter(|address| address.is_valid_for_network(network))
+}
+
+/// Whether `script_pubkey` pays to one of the addresses of `book`
+pub fn is_trusted(book: &[TrustedAddress], script_pubkey: &Script) -> bool {
+    book.iter()
+        .any(|trusted| trusted.script_pubkey.as_slice() == script_pubkey.as_bytes())
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bc1q0hzrflaz2988h6zrne85tnq47k2grgarycgjrke7qje92wfxdzxq0ymtq9";
+
+    #[test]
+    fn test_parse_address() {
+        assert!(parse_address(ADDRESS, Network::Bitcoin).is_some());
+        assert!(parse_address(ADDRESS, Network::Signet).is_none());
+        assert!(parse_address("not an address", Network::Bitcoin).is_none());
+    }
+
+    #[test]
+    fn test_is_trusted() {
+        let address = parse_address(ADDRESS, Network::Bitcoin).unwrap();
+        let book = [TrustedAddress::new(&address)];
+
+        assert!(is_trusted(&book, &address.script_pubkey()));
+        assert!(!is_trusted(&book, &Script::new()));
+        assert!(!is_trusted(&[], &address.script_pubkey()));
+    }
+}
@@ -0,0 +1,222 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Anti-exfil ECDSA signatures, which prove that the device didn't pick its nonces to leak its keys
+//!
+//! This is the sign-to-contract scheme of libsecp256k1-zkp (`ecdsa_s2c`), also used by Jade:
+//!
+//! 1. the host picks 32 random bytes of `host_data` and sends their `host_commitment()`;
+//! 2. the device derives a nonce from its key, the message and the commitment, and sends the
+//!    nonce point as its `signer_commitment()`;
+//! 3. the host reveals `host_data` and the device signs with its nonce tweaked by the hash of the
+//!    nonce point and `host_data`;
+//! 4. the host checks with `verify()` that the signature uses the tweaked nonce.
+//!
+//! The device commits to its nonce before it knows `host_data`, so it can't pick the final nonce,
+//! and the host can't either since it doesn't know the original one.
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::ffi::types::{c_int, c_uchar, c_uint, c_void};
+use bitcoin::secp256k1::ffi::{self, CPtr};
+use bitcoin::secp256k1::{
+    ecdsa, Message, PublicKey, Scalar, Secp256k1, SecretKey, Signing, Verification,
+};
+
+const DATA_TAG: &[u8] = b"s2c/ecdsa/data";
+const POINT_TAG: &[u8] = b"s2c/ecdsa/point";
+const NONCE_TAG: &[u8] = b"portal/anti-exfil/nonce";
+
+fn tagged_hash(tag: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for part in parts {
+        engine.input(part);
+    }
+
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Commitment to `host_data` sent by the host before the device commits to its nonce
+pub fn host_commitment(host_data: &[u8; 32]) -> [u8; 32] {
+    tagged_hash(DATA_TAG, &[host_data])
+}
+
+/// Nonce of the device before the tweak
+///
+/// It's derived deterministically, like RFC6979, so that it can be computed again once the host
+/// reveals its data. The host commitment makes it different for every session.
+fn original_nonce(key: &SecretKey, message: &Message, host_commitment: &[u8; 32]) -> SecretKey {
+    let hash = tagged_hash(
+        NONCE_TAG,
+        &[&key.secret_bytes(), message.as_ref(), host_commitment],
+    );
+    SecretKey::from_slice(&hash).expect("Negligible probability")
+}
+
+fn nonce_tweak(nonce_point: &PublicKey, host_data: &[u8; 32]) -> Scalar {
+    let hash = tagged_hash(POINT_TAG, &[&nonce_point.serialize(), host_data]);
+    Scalar::from_be_bytes(hash).expect("Negligible probability")
+}
+
+/// Nonce point the device commits to for the signature of `message` with `key`
+pub fn signer_commitment<C: Signing>(
+    secp: &Secp256k1<C>,
+    key: &SecretKey,
+    message: &Message,
+    host_commitment: &[u8; 32],
+) -> PublicKey {
+    PublicKey::from_secret_key(secp, &original_nonce(key, message, host_commitment))
+}
+
+/// Nonce function for `secp256k1_ecdsa_sign` that returns the nonce passed as its data
+unsafe extern "C" fn fixed_nonce(
+    nonce32: *mut c_uchar,
+    _msg32: *const c_uchar,
+    _key32: *const c_uchar,
+    _algo16: *const c_uchar,
+    data: *mut c_void,
+    attempt: c_uint,
+) -> c_int {
+    // libsecp256k1 only asks again if the nonce gives an invalid signature
+    if attempt > 0 {
+        return 0;
+    }
+    core::ptr::copy_nonoverlapping(data as *const c_uchar, nonce32, 32);
+    1
+}
+
+/// Sign `message` with `key` and the nonce committed to with `signer_commitment()`, tweaked with
+/// `host_data`
+///
+/// The caller must check that `host_data` matches the commitment received from the host.
+pub fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    key: &SecretKey,
+    message: &Message,
+    host_data: &[u8; 32],
+) -> ecdsa::Signature {
+    let original = original_nonce(key, message, &host_commitment(host_data));
+    let nonce_point = PublicKey::from_secret_key(secp, &original);
+    let nonce = original
+        .add_tweak(&nonce_tweak(&nonce_point, host_data))
+        .expect("Negligible probability");
+
+    // SAFETY: every pointer is valid for the duration of the call, and `fixed_nonce` only reads
+    // the 32 bytes of `nonce`. `signature` is only used if the call succeeds.
+    let (ret, signature) = unsafe {
+        let mut signature = ffi::Signature::new();
+        let ret = ffi::secp256k1_ecdsa_sign(
+            *secp.ctx(),
+            &mut signature,
+            message.as_c_ptr(),
+            key.as_c_ptr(),
+            Some(fixed_nonce),
+            nonce.as_c_ptr() as *const c_void,
+        );
+        (ret, signature)
+    };
+    // Only fails if `r` or `s` are zero
+    assert_eq!(ret, 1, "Negligible probability");
+
+    signature.into()
+}
+
+/// Check that `signature` is valid and uses the nonce committed to with `signer_commitment`,
+/// tweaked with `host_data`
+pub fn verify<C: Verification>(
+    secp: &Secp256k1<C>,
+    pubkey: &PublicKey,
+    message: &Message,
+    signature: &ecdsa::Signature,
+    signer_commitment: &PublicKey,
+    host_data: &[u8; 32],
+) -> bool {
+    if secp.verify_ecdsa(message, signature, pubkey).is_err() {
+        return false;
+    }
+
+    let nonce_point =
+        match signer_commitment.add_exp_tweak(secp, &nonce_tweak(signer_commitment, host_data)) {
+            Ok(point) => point,
+            Err(_) => return false,
+        };
+    // `r` is the x coordinate of the nonce point modulo the order of the curve. Coordinates above
+    // the order are so unlikely that they are simply rejected.
+    signature.serialize_compact()[..32] == nonce_point.serialize()[1..]
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &key);
+        let message = Message::from_slice(&[0x01; 32]).unwrap();
+        let host_data = [0x07; 32];
+
+        let commitment = signer_commitment(&secp, &key, &message, &host_commitment(&host_data));
+        let signature = sign(&secp, &key, &message, &host_data);
+        assert!(verify(
+            &secp,
+            &pubkey,
+            &message,
+            &signature,
+            &commitment,
+            &host_data
+        ));
+
+        // A different host data gives a different nonce
+        assert!(!verify(
+            &secp,
+            &pubkey,
+            &message,
+            &signature,
+            &commitment,
+            &[0x08; 32]
+        ));
+        // A signature with any other nonce is rejected, even if it's valid
+        let other = secp.sign_ecdsa(&message, &key);
+        assert!(!verify(
+            &secp,
+            &pubkey,
+            &message,
+            &other,
+            &commitment,
+            &host_data
+        ));
+    }
+
+    #[test]
+    fn test_commitment_depends_on_host() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let message = Message::from_slice(&[0x01; 32]).unwrap();
+
+        let first = signer_commitment(&secp, &key, &message, &host_commitment(&[0x07; 32]));
+        let second = signer_commitment(&secp, &key, &message, &host_commitment(&[0x08; 32]));
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            signer_commitment(&secp, &key, &message, &host_commitment(&[0x07; 32]))
+        );
+    }
+}
@@ -0,0 +1,325 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Device attestation
+//!
+//! Every device is provisioned at manufacture with its own attestation key and a chain of
+//! certificates linking it to the manufacturer's root key: each certificate holds a public key
+//! and a BIP340 signature of it made with the key of the previous certificate, or with the root
+//! key for the first one.
+//!
+//! When asked to attest the device signs the challenge chosen by the host together with the hash
+//! of the firmware it's running, so that a genuine device can't be cloned by replaying old replies.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, KeyPair, Message, Secp256k1, XOnlyPublicKey};
+
+use minicbor::bytes::ByteArray;
+use minicbor::{Decode, Encode};
+
+/// Maximum number of certificates between the root key and the device key
+pub const MAX_CHAIN_LEN: usize = 3;
+
+const CERTIFICATE_TAG: &[u8] = b"Portal/AttestationCertificate";
+const ATTESTATION_TAG: &[u8] = b"Portal/Attestation";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttestationError {
+    InvalidKey,
+    InvalidChain,
+    InvalidSignature,
+}
+
+impl core::fmt::Display for AttestationError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            AttestationError::InvalidKey => "Invalid attestation key",
+            AttestationError::InvalidChain => "Invalid certificate chain",
+            AttestationError::InvalidSignature => "Invalid attestation signature",
+        };
+        f.write_str(msg)
+    }
+}
+#[cfg(not(feature = "stm32"))]
+impl std::error::Error for AttestationError {}
+
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttestationCertificate {
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "super::serde_bytevec::serialize",
+            deserialize_with = "super::serde_bytevec::deserialize_array"
+        )
+    )]
+    #[cbor(n(0))]
+    pub pubkey: Box<ByteArray<32>>,
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "super::serde_bytevec::serialize",
+            deserialize_with = "super::serde_bytevec::deserialize_array"
+        )
+    )]
+    #[cbor(n(1))]
+    pub signature: Box<ByteArray<64>>,
+}
+
+/// Key and certificates provisioned in the device at manufacture
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttestationKey {
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "super::serde_bytevec::serialize",
+            deserialize_with = "super::serde_bytevec::deserialize_array"
+        )
+    )]
+    #[cbor(n(0))]
+    pub secret_key: Box<ByteArray<32>>,
+    #[cbor(n(1))]
+    pub chain: Vec<AttestationCertificate>,
+}
+
+/// Reply of the device to an attestation request
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attestation {
+    #[cbor(n(0))]
+    pub chain: Vec<AttestationCertificate>,
+    /// SHA256 of the firmware area of the flash
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "super::serde_bytevec::serialize",
+            deserialize_with = "super::serde_bytevec::deserialize_array"
+        )
+    )]
+    #[cbor(n(1))]
+    pub firmware_hash: Box<ByteArray<32>>,
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "super::serde_bytevec::serialize",
+            deserialize_with = "super::serde_bytevec::deserialize_array"
+        )
+    )]
+    #[cbor(n(2))]
+    pub signature: Box<ByteArray<64>>,
+}
+
+fn tagged_message(tag: &[u8], data: &[&[u8]]) -> Message {
+    let tag = sha256::Hash::hash(tag);
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag);
+    engine.input(&tag);
+    for d in data {
+        engine.input(d);
+    }
+
+    Message::from_slice(&sha256::Hash::from_engine(engine)).expect("Correct length")
+}
+
+fn certificate_message(pubkey: &[u8; 32]) -> Message {
+    tagged_message(CERTIFICATE_TAG, &[pubkey])
+}
+
+fn attestation_message(challenge: &[u8; 32], firmware_hash: &[u8; 32]) -> Message {
+    tagged_message(ATTESTATION_TAG, &[challenge, firmware_hash])
+}
+
+fn verify_signature<C: secp256k1::Verification>(
+    ctx: &Secp256k1<C>,
+    signer: &XOnlyPublicKey,
+    message: &Message,
+    signature: &[u8; 64],
+) -> bool {
+    secp256k1::schnorr::Signature::from_slice(signature)
+        .and_then(|sig| ctx.verify_schnorr(&sig, message, signer))
+        .is_ok()
+}
+
+impl AttestationCertificate {
+    /// Certify `pubkey` with the key of the parent certificate (or the root key)
+    pub fn new<C: secp256k1::Signing>(
+        ctx: &Secp256k1<C>,
+        parent: &KeyPair,
+        pubkey: &XOnlyPublicKey,
+    ) -> Self {
+        let pubkey = pubkey.serialize();
+        let signature = ctx.sign_schnorr_no_aux_rand(&certificate_message(&pubkey), parent);
+
+        AttestationCertificate {
+            pubkey: Box::new(pubkey.into()),
+            signature: Box::new((*signature.as_ref()).into()),
+        }
+    }
+}
+
+impl AttestationKey {
+    fn keypair<C: secp256k1::Signing>(
+        &self,
+        ctx: &Secp256k1<C>,
+    ) -> Result<KeyPair, AttestationError> {
+        KeyPair::from_seckey_slice(ctx, &self.secret_key[..])
+            .map_err(|_| AttestationError::InvalidKey)
+    }
+
+    /// Check that the chain is not too long and that it certifies this key
+    pub fn validate<C: secp256k1::Signing>(
+        &self,
+        ctx: &Secp256k1<C>,
+    ) -> Result<(), AttestationError> {
+        let (pubkey, _) = self.keypair(ctx)?.x_only_public_key();
+
+        match self.chain.last() {
+            Some(cert)
+                if self.chain.len() <= MAX_CHAIN_LEN && **cert.pubkey == pubkey.serialize() =>
+            {
+                Ok(())
+            }
+            _ => Err(AttestationError::InvalidChain),
+        }
+    }
+
+    pub fn attest<C: secp256k1::Signing>(
+        &self,
+        ctx: &Secp256k1<C>,
+        challenge: &[u8; 32],
+        firmware_hash: &[u8; 32],
+    ) -> Result<Attestation, AttestationError> {
+        let keypair = self.keypair(ctx)?;
+        let signature =
+            ctx.sign_schnorr_no_aux_rand(&attestation_message(challenge, firmware_hash), &keypair);
+
+        Ok(Attestation {
+            chain: self.chain.clone(),
+            firmware_hash: Box::new((*firmware_hash).into()),
+            signature: Box::new((*signature.as_ref()).into()),
+        })
+    }
+}
+
+impl Attestation {
+    /// Verify the chain up to `root` and the signature over `challenge`
+    ///
+    /// Returns the attestation key of the device, which uniquely identifies it.
+    pub fn verify<C: secp256k1::Verification>(
+        &self,
+        ctx: &Secp256k1<C>,
+        root: &XOnlyPublicKey,
+        challenge: &[u8; 32],
+    ) -> Result<XOnlyPublicKey, AttestationError> {
+        if self.chain.is_empty() || self.chain.len() > MAX_CHAIN_LEN {
+            return Err(AttestationError::InvalidChain);
+        }
+
+        let mut signer = *root;
+        for cert in &self.chain {
+            if !verify_signature(
+                ctx,
+                &signer,
+                &certificate_message(&cert.pubkey),
+                &cert.signature,
+            ) {
+                return Err(AttestationError::InvalidChain);
+            }
+
+            signer = XOnlyPublicKey::from_slice(&cert.pubkey[..])
+                .map_err(|_| AttestationError::InvalidChain)?;
+        }
+
+        let message = attestation_message(challenge, &self.firmware_hash);
+        if !verify_signature(ctx, &signer, &message, &self.signature) {
+            return Err(AttestationError::InvalidSignature);
+        }
+
+        Ok(signer)
+    }
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    fn provision(ctx: &Secp256k1<secp256k1::All>) -> (XOnlyPublicKey, AttestationKey) {
+        let root = KeyPair::from_seckey_slice(ctx, &[0x01; 32]).unwrap();
+        let batch = KeyPair::from_seckey_slice(ctx, &[0x02; 32]).unwrap();
+        let device = KeyPair::from_seckey_slice(ctx, &[0x03; 32]).unwrap();
+
+        let key = AttestationKey {
+            secret_key: Box::new(device.secret_bytes().into()),
+            chain: vec![
+                AttestationCertificate::new(ctx, &root, &batch.x_only_public_key().0),
+                AttestationCertificate::new(ctx, &batch, &device.x_only_public_key().0),
+            ],
+        };
+
+        (root.x_only_public_key().0, key)
+    }
+
+    #[test]
+    fn test_attest() {
+        let ctx = Secp256k1::new();
+        let (root, key) = provision(&ctx);
+        key.validate(&ctx).unwrap();
+
+        let attestation = key.attest(&ctx, &[0x42; 32], &[0xAA; 32]).unwrap();
+        let device = attestation.verify(&ctx, &root, &[0x42; 32]).unwrap();
+        assert_eq!(device.serialize(), **key.chain[1].pubkey);
+    }
+
+    #[test]
+    fn test_wrong_challenge() {
+        let ctx = Secp256k1::new();
+        let (root, key) = provision(&ctx);
+
+        let attestation = key.attest(&ctx, &[0x42; 32], &[0xAA; 32]).unwrap();
+        assert_eq!(
+            attestation.verify(&ctx, &root, &[0x43; 32]),
+            Err(AttestationError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_wrong_root() {
+        let ctx = Secp256k1::new();
+        let (_, key) = provision(&ctx);
+        let other = KeyPair::from_seckey_slice(&ctx, &[0x04; 32]).unwrap();
+
+        let attestation = key.attest(&ctx, &[0x42; 32], &[0xAA; 32]).unwrap();
+        assert_eq!(
+            attestation.verify(&ctx, &other.x_only_public_key().0, &[0x42; 32]),
+            Err(AttestationError::InvalidChain)
+        );
+    }
+
+    #[test]
+    fn test_validate_mismatched_key() {
+        let ctx = Secp256k1::new();
+        let (_, mut key) = provision(&ctx);
+        key.chain.pop();
+
+        assert_eq!(key.validate(&ctx), Err(AttestationError::InvalidChain));
+    }
+}
@@ -0,0 +1,344 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Encrypted backups of the device configuration
+//!
+//! A backup is exported by the device as an opaque blob, the CBOR serialization of `Backup`. The
+//! contents are encrypted with AES-256-GCM using a key derived from a password chosen by the
+//! user, so that the backup can be restored on a different device. The key is derived with
+//! PBKDF2-HMAC-SHA256 and the parameters are saved in the backup, so that the cost can be raised
+//! later without breaking the older backups.
+//!
+//! The `version` is bumped every time the format of `BackupContents` changes in a way that older
+//! firmwares can't read, and backups with an unknown version are refused.
+
+use alloc::vec::Vec;
+
+use aes_gcm::aead::AeadMut;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+
+use minicbor::bytes::ByteVec;
+use minicbor::{Decode, Encode};
+
+use crate::{Entropy, WalletDescriptor};
+
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Iterations of PBKDF2 used for new backups
+pub const DEFAULT_KDF_ITERATIONS: u32 = 100_000;
+/// Backups asking for more iterations are refused, since the device would hang for minutes
+pub const MAX_KDF_ITERATIONS: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupError {
+    InvalidEncoding,
+    UnsupportedVersion,
+    WrongPassword,
+}
+
+impl core::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            BackupError::InvalidEncoding => "Invalid backup encoding",
+            BackupError::UnsupportedVersion => "Unsupported backup version",
+            BackupError::WrongPassword => "Wrong backup password",
+        };
+        f.write_str(msg)
+    }
+}
+#[cfg(not(feature = "stm32"))]
+impl std::error::Error for BackupError {}
+
+impl From<BackupError> for crate::ErrorCode {
+    fn from(e: BackupError) -> Self {
+        match e {
+            BackupError::InvalidEncoding => crate::ErrorCode::InvalidBackup,
+            BackupError::UnsupportedVersion => crate::ErrorCode::UnsupportedBackupVersion,
+            BackupError::WrongPassword => crate::ErrorCode::WrongBackupPassword,
+        }
+    }
+}
+
+/// Data saved in the backup
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct BackupContents {
+    #[cbor(with = "crate::cbor_bitcoin_network")]
+    #[cbor(n(0))]
+    pub network: bitcoin::Network,
+    #[cbor(n(1))]
+    pub descriptor: WalletDescriptor,
+    /// Fingerprint of the seed the descriptor belongs to
+    #[cbor(n(2))]
+    pub fingerprint: [u8; 4],
+    /// Only present if the user chose to include the seed
+    #[cbor(n(3))]
+    pub mnemonic: Option<Entropy>,
+}
+
+/// Function used to derive the encryption key from the password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Kdf {
+    #[n(0)]
+    Pbkdf2Sha256 {
+        #[n(0)]
+        iterations: u32,
+    },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::Pbkdf2Sha256 {
+            iterations: DEFAULT_KDF_ITERATIONS,
+        }
+    }
+}
+
+impl Kdf {
+    fn derive_key(&self, password: &str, salt: &[u8]) -> Result<[u8; 32], BackupError> {
+        match *self {
+            Kdf::Pbkdf2Sha256 { iterations }
+                if iterations == 0 || iterations > MAX_KDF_ITERATIONS =>
+            {
+                Err(BackupError::InvalidEncoding)
+            }
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                Ok(pbkdf2_sha256(password.as_bytes(), salt, iterations))
+            }
+        }
+    }
+}
+
+/// First block of PBKDF2-HMAC-SHA256, which is all we need for a 256-bit key
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    // The engine keyed with the password is cloned for every round, so that the key is only
+    // hashed once
+    let keyed = hmac::HmacEngine::<sha256::Hash>::new(password);
+
+    let mut engine = keyed.clone();
+    engine.input(salt);
+    engine.input(&1u32.to_be_bytes());
+    let mut u = hmac::Hmac::from_engine(engine).into_inner();
+
+    let mut result = u;
+    for _ in 1..iterations {
+        let mut engine = keyed.clone();
+        engine.input(&u);
+        u = hmac::Hmac::from_engine(engine).into_inner();
+
+        result.iter_mut().zip(u.iter()).for_each(|(r, u)| *r ^= u);
+    }
+
+    result
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct Backup {
+    #[cbor(n(0))]
+    pub version: u32,
+    #[cbor(n(1))]
+    pub salt: [u8; 16],
+    #[cbor(n(2))]
+    pub nonce: [u8; 12],
+    #[cbor(n(3))]
+    pub data: ByteVec,
+    #[cbor(n(4))]
+    pub kdf: Kdf,
+}
+
+impl Backup {
+    /// Encrypt `contents`, `salt` and `nonce` must be freshly generated for every backup
+    pub fn new(
+        contents: &BackupContents,
+        password: &str,
+        kdf: Kdf,
+        salt: [u8; 16],
+        nonce: [u8; 12],
+    ) -> Self {
+        let key = kdf
+            .derive_key(password, &salt)
+            .expect("Valid KDF parameters");
+        let data = minicbor::to_vec(contents).expect("Always serializable");
+        let encrypted = Aes256Gcm::new_from_slice(&key)
+            .expect("Correct length")
+            .encrypt(Nonce::from_slice(&nonce), data.as_slice())
+            .expect("Always ok");
+
+        Backup {
+            version: BACKUP_VERSION,
+            salt,
+            nonce,
+            data: encrypted.into(),
+            kdf,
+        }
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<BackupContents, BackupError> {
+        if self.version != BACKUP_VERSION {
+            return Err(BackupError::UnsupportedVersion);
+        }
+
+        let key = self.kdf.derive_key(password, &self.salt)?;
+        let data = Aes256Gcm::new_from_slice(&key)
+            .expect("Correct length")
+            .decrypt(Nonce::from_slice(&self.nonce), self.data.as_slice())
+            .map_err(|_| BackupError::WrongPassword)?;
+        minicbor::decode(&data).map_err(|_| BackupError::InvalidEncoding)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        minicbor::to_vec(self).expect("Always serializable")
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, BackupError> {
+        minicbor::decode(data).map_err(|_| BackupError::InvalidEncoding)
+    }
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    const TEST_KDF: Kdf = Kdf::Pbkdf2Sha256 { iterations: 16 };
+
+    fn make_contents(with_seed: bool) -> BackupContents {
+        BackupContents {
+            network: bitcoin::Network::Testnet,
+            descriptor: WalletDescriptor::make_bip84(bitcoin::Network::Testnet),
+            fingerprint: [0x01, 0x02, 0x03, 0x04],
+            mnemonic: with_seed.then(|| Entropy {
+                bytes: vec![0x42; 16].into(),
+                language: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_backup_roundtrip() {
+        let backup = Backup::new(
+            &make_contents(true),
+            "password",
+            TEST_KDF,
+            [0x01; 16],
+            [0x02; 12],
+        );
+        let backup = Backup::deserialize(&backup.serialize()).unwrap();
+
+        let contents = backup.decrypt("password").unwrap();
+        assert_eq!(contents.network, bitcoin::Network::Testnet);
+        assert_eq!(contents.fingerprint, [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(contents.mnemonic.unwrap().bytes.as_slice(), &[0x42; 16]);
+    }
+
+    #[test]
+    fn test_backup_without_seed() {
+        let backup = Backup::new(
+            &make_contents(false),
+            "password",
+            TEST_KDF,
+            [0x01; 16],
+            [0x02; 12],
+        );
+
+        assert!(backup.decrypt("password").unwrap().mnemonic.is_none());
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let backup = Backup::new(
+            &make_contents(true),
+            "password",
+            TEST_KDF,
+            [0x01; 16],
+            [0x02; 12],
+        );
+
+        assert_eq!(
+            backup.decrypt("other").unwrap_err(),
+            BackupError::WrongPassword
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let mut backup = Backup::new(
+            &make_contents(true),
+            "password",
+            TEST_KDF,
+            [0x01; 16],
+            [0x02; 12],
+        );
+        backup.version += 1;
+
+        assert_eq!(
+            backup.decrypt("password").unwrap_err(),
+            BackupError::UnsupportedVersion
+        );
+    }
+
+    #[test]
+    fn test_kdf_parameters() {
+        let mut backup = Backup::new(
+            &make_contents(true),
+            "password",
+            TEST_KDF,
+            [0x01; 16],
+            [0x02; 12],
+        );
+        let backup_ser = Backup::deserialize(&backup.serialize()).unwrap();
+        assert_eq!(backup_ser.kdf, TEST_KDF);
+
+        // The key depends on the number of iterations saved in the backup
+        backup.kdf = Kdf::Pbkdf2Sha256 { iterations: 17 };
+        assert_eq!(
+            backup.decrypt("password").unwrap_err(),
+            BackupError::WrongPassword
+        );
+
+        backup.kdf = Kdf::Pbkdf2Sha256 {
+            iterations: MAX_KDF_ITERATIONS + 1,
+        };
+        assert_eq!(
+            backup.decrypt("password").unwrap_err(),
+            BackupError::InvalidEncoding
+        );
+    }
+
+    #[test]
+    fn test_pbkdf2_sha256() {
+        use bitcoin::hashes::hex::ToHex;
+
+        // Test vectors from RFC 7914 and RFC 6070 adapted to SHA256, truncated to the first block
+        assert_eq!(
+            pbkdf2_sha256(b"passwd", b"salt", 1).to_hex(),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+        assert_eq!(
+            pbkdf2_sha256(b"password", b"salt", 1).to_hex(),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+        assert_eq!(
+            pbkdf2_sha256(b"password", b"salt", 2).to_hex(),
+            "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+        );
+        assert_eq!(
+            pbkdf2_sha256(b"password", b"salt", 4096).to_hex(),
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Generic message signing (BIP-322)
+//!
+//! Only the "simple" format is supported: the signature is the witness of the `to_sign`
+//! transaction, which is all that's needed for single-key native segwit addresses (P2WPKH, and
+//! P2TR with a BIP-86 key). Wrapped segwit and multisig addresses would need the "full" format
+//! and legacy ones the legacy format, so they are not supported.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{
+    ecdsa, schnorr, KeyPair, Message, Secp256k1, SecretKey, Signing, Verification, XOnlyPublicKey,
+};
+use bitcoin::util::schnorr::TapTweak;
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::{
+    EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, SchnorrSighashType, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+const TAG: &[u8] = b"BIP0322-signed-message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bip322Error {
+    /// Only P2WPKH and P2TR addresses are supported
+    UnsupportedAddress,
+    /// The key doesn't match the address
+    KeyMismatch,
+}
+
+/// Tagged hash of the message, committed to by the `to_spend` transaction
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message);
+
+    sha256::Hash::from_engine(engine)
+}
+
+/// Virtual transaction that creates an output with `script_pubkey` committing to the message
+pub fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: Txid::all_zeros(),
+                vout: 0xFFFFFFFF,
+            },
+            script_sig: Builder::new()
+                .push_opcode(opcodes::all::OP_PUSHBYTES_0)
+                .push_slice(&message_hash(message)[..])
+                .into_script(),
+            sequence: Sequence(0),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// Virtual transaction spending `to_spend`, the signature is its witness
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: to_spend.txid(),
+                vout: 0,
+            },
+            script_sig: Script::new(),
+            sequence: Sequence(0),
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_RETURN)
+                .into_script(),
+        }],
+    }
+}
+
+fn p2wpkh_script_code(pubkey: &PublicKey) -> Script {
+    Script::new_p2pkh(&pubkey.pubkey_hash())
+}
+
+fn sighash_message(
+    script_pubkey: &Script,
+    message: &[u8],
+    script_code: Option<&Script>,
+) -> Message {
+    let to_spend = to_spend(script_pubkey, message);
+    let to_sign = to_sign(&to_spend);
+    let mut cache = SighashCache::new(&to_sign);
+
+    let sighash = match script_code {
+        Some(script_code) => cache
+            .segwit_signature_hash(0, script_code, 0, EcdsaSighashType::All)
+            .expect("Valid input")
+            .into_inner(),
+        None => cache
+            .taproot_key_spend_signature_hash(
+                0,
+                &Prevouts::All(&to_spend.output),
+                SchnorrSighashType::Default,
+            )
+            .expect("Valid input")
+            .into_inner(),
+    };
+
+    Message::from_slice(&sighash).expect("Correct length")
+}
+
+/// Sign `message` for the address of `script_pubkey` with `key`, returning the witness
+///
+/// `aux_rand` is only used for taproot signatures.
+pub fn sign<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    script_pubkey: &Script,
+    message: &[u8],
+    key: &SecretKey,
+    aux_rand: &[u8; 32],
+) -> Result<Witness, Bip322Error> {
+    if script_pubkey.is_v1_p2tr() {
+        let keypair = KeyPair::from_secret_key(secp, key);
+        let (internal_key, _) = keypair.x_only_public_key();
+        if Script::new_v1_p2tr(secp, internal_key, None) != *script_pubkey {
+            return Err(Bip322Error::KeyMismatch);
+        }
+
+        let msg = sighash_message(script_pubkey, message, None);
+        let tweaked = keypair.tap_tweak(secp, None).to_inner();
+        let signature = secp.sign_schnorr_with_aux_rand(&msg, &tweaked, aux_rand);
+
+        Ok(Witness::from_vec(vec![signature.as_ref().to_vec()]))
+    } else if script_pubkey.is_v0_p2wpkh() {
+        let pubkey = PublicKey::new(key.public_key(secp));
+        let wpubkey_hash = pubkey.wpubkey_hash().expect("Compressed key");
+        if Script::new_v0_p2wpkh(&wpubkey_hash) != *script_pubkey {
+            return Err(Bip322Error::KeyMismatch);
+        }
+
+        let msg = sighash_message(script_pubkey, message, Some(&p2wpkh_script_code(&pubkey)));
+        let mut signature = secp.sign_ecdsa_low_r(&msg, key).serialize_der().to_vec();
+        signature.push(EcdsaSighashType::All as u8);
+
+        Ok(Witness::from_vec(vec![signature, pubkey.to_bytes()]))
+    } else {
+        Err(Bip322Error::UnsupportedAddress)
+    }
+}
+
+/// Check a signature in the simple format made by `sign`
+pub fn verify<C: Verification>(
+    secp: &Secp256k1<C>,
+    script_pubkey: &Script,
+    message: &[u8],
+    witness: &Witness,
+) -> bool {
+    let stack = witness.to_vec();
+
+    if script_pubkey.is_v1_p2tr() {
+        let (pubkey, signature) = match (
+            XOnlyPublicKey::from_slice(&script_pubkey[2..]),
+            stack.as_slice(),
+        ) {
+            (Ok(pubkey), [signature]) => match schnorr::Signature::from_slice(signature) {
+                Ok(signature) => (pubkey, signature),
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+
+        let msg = sighash_message(script_pubkey, message, None);
+        secp.verify_schnorr(&signature, &msg, &pubkey).is_ok()
+    } else if script_pubkey.is_v0_p2wpkh() {
+        let (signature, pubkey) = match stack.as_slice() {
+            [signature, pubkey] => (signature, pubkey),
+            _ => return false,
+        };
+        let pubkey = match PublicKey::from_slice(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        let signature = match signature.split_last() {
+            Some((sighash, der)) if *sighash == EcdsaSighashType::All as u8 => {
+                match ecdsa::Signature::from_der(der) {
+                    Ok(signature) => signature,
+                    Err(_) => return false,
+                }
+            }
+            _ => return false,
+        };
+        if pubkey.wpubkey_hash().map(|h| Script::new_v0_p2wpkh(&h)) != Some(script_pubkey.clone()) {
+            return false;
+        }
+
+        let msg = sighash_message(script_pubkey, message, Some(&p2wpkh_script_code(&pubkey)));
+        secp.verify_ecdsa(&msg, &signature, &pubkey.inner).is_ok()
+    } else {
+        false
+    }
+}
+
+/// Signature in the simple format, the consensus encoding of the witness
+pub fn encode_simple(witness: &Witness) -> Vec<u8> {
+    bitcoin::consensus::encode::serialize(witness)
+}
+
+pub fn decode_simple(data: &[u8]) -> Option<Witness> {
+    bitcoin::consensus::encode::deserialize(data).ok()
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use core::str::FromStr;
+
+    use bitcoin::{Address, Network, PrivateKey};
+
+    // Test vectors from BIP-322
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const P2WPKH_ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR_ADDRESS: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    #[test]
+    fn test_message_hash() {
+        assert_eq!(
+            message_hash(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_sign_p2wpkh() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let script_pubkey = Address::from_str(P2WPKH_ADDRESS).unwrap().script_pubkey();
+        assert_eq!(
+            Address::p2wpkh(&key.public_key(&secp), Network::Bitcoin)
+                .unwrap()
+                .script_pubkey(),
+            script_pubkey
+        );
+
+        let witness = sign(&secp, &script_pubkey, b"", &key.inner, &[0; 32]).unwrap();
+        assert!(verify(&secp, &script_pubkey, b"", &witness));
+        assert!(!verify(&secp, &script_pubkey, b"Hello World", &witness));
+
+        // Signatures are deterministic, so this is the exact one from the test vectors
+        let encoded = encode_simple(&witness);
+        assert_eq!(
+            bitcoin::hashes::hex::ToHex::to_hex(&encoded[..]),
+            "024730440220336801010aaf657d79662cac98a990a43ac6f376af2c84f8f76401ccb9d0231602201693a4e683db4a91944ca5cb11527840366daf583a2c695fccf8e93483b52e34012102c7f12003196442943d8588e01aee840423cc54fc1521526a3b85c2b0cbd58872"
+        );
+        assert_eq!(decode_simple(&encoded).unwrap(), witness);
+    }
+
+    #[test]
+    fn test_sign_p2tr() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let script_pubkey = Address::from_str(P2TR_ADDRESS).unwrap().script_pubkey();
+
+        let witness = sign(&secp, &script_pubkey, b"Hello World", &key.inner, &[1; 32]).unwrap();
+        assert!(verify(&secp, &script_pubkey, b"Hello World", &witness));
+        assert!(!verify(&secp, &script_pubkey, b"", &witness));
+    }
+
+    #[test]
+    fn test_unsupported_address() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let p2pkh = Address::p2pkh(&key.public_key(&secp), Network::Bitcoin).script_pubkey();
+        assert_eq!(
+            sign(&secp, &p2pkh, b"", &key.inner, &[0; 32]),
+            Err(Bip322Error::UnsupportedAddress)
+        );
+
+        let other = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let script_pubkey = Address::from_str(P2WPKH_ADDRESS).unwrap().script_pubkey();
+        assert_eq!(
+            sign(&secp, &script_pubkey, b"", &other, &[0; 32]),
+            Err(Bip322Error::KeyMismatch)
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Deterministic entropy derived from the wallet seed, as described in BIP-85
+//!
+//! Used to give a Lightning node its own seed: the node can run on a different machine and be
+//! restored at any time from the device, without ever learning the seed of the wallet.
+
+use bitcoin::hashes::{hmac, sha512, Hash, HashEngine};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::util::bip32;
+
+const BIP85_PURPOSE: u32 = 83696968;
+const HMAC_KEY: &[u8] = b"bip-entropy-from-k";
+/// Application number of raw entropy encoded as hex
+const APP_HEX: u32 = 128169;
+
+/// Length of the seed derived for a Lightning node
+pub const NODE_SEED_LEN: usize = 32;
+
+/// Derive the entropy for the key at `path`, which must be fully hardened
+pub fn derive_entropy<C: Signing>(
+    secp: &Secp256k1<C>,
+    xprv: &bip32::ExtendedPrivKey,
+    path: &bip32::DerivationPath,
+) -> Result<[u8; 64], bip32::Error> {
+    let derived = xprv.derive_priv(secp, path)?;
+
+    let mut engine = hmac::HmacEngine::<sha512::Hash>::new(HMAC_KEY);
+    engine.input(&derived.private_key.secret_bytes());
+
+    Ok(hmac::Hmac::from_engine(engine).into_inner())
+}
+
+/// Path of the HEX application, which gives `num_bytes` of raw entropy
+pub fn hex_path(num_bytes: u32, index: u32) -> bip32::DerivationPath {
+    [BIP85_PURPOSE, APP_HEX, num_bytes, index]
+        .into_iter()
+        .map(|i| bip32::ChildNumber::from_hardened_idx(i).expect("Valid index"))
+        .collect::<alloc::vec::Vec<_>>()
+        .into()
+}
+
+/// Seed of the Lightning node number `index`, which can be given to LDK's `KeysManager`
+pub fn node_seed<C: Signing>(
+    secp: &Secp256k1<C>,
+    xprv: &bip32::ExtendedPrivKey,
+    index: u32,
+) -> Result<[u8; NODE_SEED_LEN], bip32::Error> {
+    let entropy = derive_entropy(secp, xprv, &hex_path(NODE_SEED_LEN as u32, index))?;
+    Ok(entropy[..NODE_SEED_LEN].try_into().expect("Correct length"))
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    const MASTER: &str = "xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb";
+
+    fn to_hex(data: &[u8]) -> alloc::string::String {
+        data.iter().map(|b| alloc::format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_bip85_vector() {
+        let secp = Secp256k1::new();
+        let xprv = bip32::ExtendedPrivKey::from_str(MASTER).unwrap();
+        let path = bip32::DerivationPath::from_str("m/83696968'/0'/0'").unwrap();
+
+        assert_eq!(
+            to_hex(&derive_entropy(&secp, &xprv, &path).unwrap()),
+            "efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f00b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7"
+        );
+    }
+
+    #[test]
+    fn test_bip85_hex_vector() {
+        let secp = Secp256k1::new();
+        let xprv = bip32::ExtendedPrivKey::from_str(MASTER).unwrap();
+
+        assert_eq!(
+            to_hex(&derive_entropy(&secp, &xprv, &hex_path(64, 0)).unwrap()),
+            "492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f878555d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c"
+        );
+    }
+
+    #[test]
+    fn test_node_seeds_differ() {
+        let secp = Secp256k1::new();
+        let xprv = bip32::ExtendedPrivKey::from_str(MASTER).unwrap();
+
+        assert_ne!(
+            node_seed(&secp, &xprv, 0).unwrap(),
+            node_seed(&secp, &xprv, 1).unwrap()
+        );
+    }
+}
@@ -0,0 +1,247 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Log-structured format of the config pages
+//!
+//! Instead of erasing the page every time the config is saved, new versions are appended to it
+//! and the page is only erased once it's full. Every page starts with a `PageHeader`, followed by
+//! the records: a big-endian `u16` length and the data, padded to a double-word since that's the
+//! smallest unit the flash can program.
+//!
+//! Every record also carries a sequence number and a CRC-32 of the sequence and the data, and
+//! it's written to both config pages: the highest sequence with a valid CRC is the current config,
+//! and a record only partially written because of a power loss is simply skipped.
+
+use alloc::vec::Vec;
+
+pub const PAGE_MAGIC: [u8; 4] = *b"CFGL";
+pub const HEADER_LEN: usize = 8;
+pub const ALIGN: usize = 8;
+/// Length of the sequence and the CRC that precede the data of a record
+const RECORD_META_LEN: usize = 8;
+
+const ERASED_LEN: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHeader {
+    /// Incremented every time we move to a new page, the highest one holds the current config
+    pub generation: u16,
+    /// Bitmask of the config pages that failed to verify after being written
+    pub bad_pages: u8,
+}
+
+impl PageHeader {
+    pub fn parse(page: &[u8]) -> Option<Self> {
+        if page.len() < HEADER_LEN || page[..4] != PAGE_MAGIC {
+            return None;
+        }
+
+        Some(PageHeader {
+            generation: u16::from_be_bytes([page[4], page[5]]),
+            bad_pages: page[6],
+        })
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut data = [0xFF; HEADER_LEN];
+        data[..4].copy_from_slice(&PAGE_MAGIC);
+        data[4..6].copy_from_slice(&self.generation.to_be_bytes());
+        data[6] = self.bad_pages;
+        data
+    }
+
+    /// Whether this header was written after `other`, accounting for the generation wrapping around
+    pub fn is_newer_than(&self, other: &PageHeader) -> bool {
+        (self.generation.wrapping_sub(other.generation) as i16) > 0
+    }
+}
+
+/// CRC-32 (IEEE 802.3) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+fn record_crc(sequence: u32, data: &[u8]) -> u32 {
+    let mut checked = Vec::with_capacity(4 + data.len());
+    checked.extend_from_slice(&sequence.to_be_bytes());
+    checked.extend_from_slice(data);
+    crc32(&checked)
+}
+
+/// A record read from a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub sequence: u32,
+    pub data: &'a [u8],
+}
+
+/// Serialize a record with its `sequence` and CRC, padding it to a multiple of `ALIGN`
+pub fn encode_record(sequence: u32, data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::from((data.len() as u16).to_be_bytes());
+    record.extend_from_slice(&sequence.to_be_bytes());
+    record.extend_from_slice(&record_crc(sequence, data).to_be_bytes());
+    record.extend_from_slice(data);
+    record.resize(record.len().next_multiple_of(ALIGN), 0x00);
+    record
+}
+
+/// Return the records in the page, in the order they were written, and the offset of the first
+/// free byte
+///
+/// The page must start with a valid header. Records with a wrong CRC are skipped. If the page
+/// contains garbage after the last record it's considered full, since we can't write over it
+/// without erasing it.
+pub fn records(page: &[u8]) -> (Vec<Record<'_>>, usize) {
+    let mut records = Vec::new();
+    let mut offset = HEADER_LEN;
+
+    while offset + 2 <= page.len() {
+        let len = u16::from_be_bytes([page[offset], page[offset + 1]]);
+        if len == ERASED_LEN {
+            break;
+        } else if offset + 2 + RECORD_META_LEN + len as usize > page.len() {
+            offset = page.len();
+            break;
+        }
+
+        let meta = &page[offset + 2..offset + 2 + RECORD_META_LEN];
+        let start = offset + 2 + RECORD_META_LEN;
+        let data = &page[start..start + len as usize];
+        offset += (2 + RECORD_META_LEN + len as usize).next_multiple_of(ALIGN);
+
+        let sequence = u32::from_be_bytes(meta[..4].try_into().unwrap());
+        let crc = u32::from_be_bytes(meta[4..].try_into().unwrap());
+        if crc == record_crc(sequence, data) {
+            records.push(Record { sequence, data });
+        }
+    }
+
+    (records, core::cmp::min(offset, page.len()))
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 256;
+
+    fn header(generation: u16) -> PageHeader {
+        PageHeader {
+            generation,
+            bad_pages: 0,
+        }
+    }
+
+    fn make_page(header: PageHeader, records: &[&[u8]]) -> Vec<u8> {
+        let mut page = vec![0xFF; PAGE_SIZE];
+        page[..HEADER_LEN].copy_from_slice(&header.encode());
+
+        let mut offset = HEADER_LEN;
+        for (i, r) in records.iter().enumerate() {
+            let r = encode_record(i as u32 + 1, r);
+            page[offset..offset + r.len()].copy_from_slice(&r);
+            offset += r.len();
+        }
+
+        page
+    }
+
+    #[test]
+    fn test_header() {
+        let header = PageHeader {
+            generation: 42,
+            bad_pages: 0b10,
+        };
+        let page = make_page(header, &[]);
+
+        assert_eq!(PageHeader::parse(&page), Some(header));
+        assert_eq!(PageHeader::parse(&[0xFF; PAGE_SIZE]), None);
+    }
+
+    #[test]
+    fn test_generation_wraps() {
+        let old = header(u16::MAX);
+        let new = header(0);
+
+        assert!(new.is_newer_than(&old));
+        assert!(!old.is_newer_than(&new));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn test_records() {
+        let page = make_page(header(1), &[b"first", b"second record"]);
+
+        let (records, end) = records(&page);
+        assert_eq!(
+            records,
+            vec![
+                Record {
+                    sequence: 1,
+                    data: b"first"
+                },
+                Record {
+                    sequence: 2,
+                    data: b"second record"
+                }
+            ]
+        );
+        assert_eq!(end, HEADER_LEN + 16 + 24);
+    }
+
+    #[test]
+    fn test_corrupted_record() {
+        let mut page = make_page(header(1), &[b"first", b"second record"]);
+        // Flip a bit in the data of the first record, the second one is still read
+        page[HEADER_LEN + 10] ^= 0x01;
+
+        let (records, end) = records(&page);
+        assert_eq!(
+            records,
+            vec![Record {
+                sequence: 2,
+                data: b"second record"
+            }]
+        );
+        assert_eq!(end, HEADER_LEN + 16 + 24);
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut page = make_page(header(1), &[b"first"]);
+        // Length of a record that doesn't fit in the page
+        page[HEADER_LEN + 16..HEADER_LEN + 18].copy_from_slice(&1024u16.to_be_bytes());
+
+        let (records, end) = records(&page);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data, b"first");
+        assert_eq!(end, PAGE_SIZE);
+    }
+}
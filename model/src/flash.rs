@@ -0,0 +1,211 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Layout of the flash, shared with the host to check firmware images
+//!
+//! The flash is made of two banks, one with the running image and the other one with the spare
+//! image written by updates. The last pages of each bank are reserved for the config log and the
+//! boot state of its image.
+//!
+//! Pages are read through the bank mapping, which always puts the running bank at the first half
+//! of the flash, but they are erased by their physical bank: the two differ when booting from
+//! bank 2.
+
+use alloc::vec::Vec;
+
+use crate::WipeReport;
+
+pub const PAGE_SIZE: usize = 2048;
+/// Pages of each bank
+pub const BANK_PAGES: usize = 256;
+/// Page of each bank that holds the boot state of the image in that bank
+pub const SLOT_STATE_PAGE: usize = 254;
+/// Pages of each bank that hold the config log
+pub const CONFIG_PAGES: [usize; 2] = [253, 255];
+
+const FIRST_RESERVED_PAGE: usize = if CONFIG_PAGES[0] < SLOT_STATE_PAGE {
+    CONFIG_PAGES[0]
+} else {
+    SLOT_STATE_PAGE
+};
+/// Largest firmware image, which has to fit in its bank below the reserved pages
+pub const MAX_FIRMWARE_SIZE: usize = FIRST_RESERVED_PAGE * PAGE_SIZE;
+
+/// Number of times a page is erased before giving up on reading it back blank
+pub const WIPE_PASSES: u32 = 3;
+
+/// Flash with the two banks
+pub trait Banks {
+    type Error;
+
+    /// Whether the running image was booted from bank 2
+    fn fb_mode(&self) -> bool;
+    /// Read `page` through the bank mapping, pages from `BANK_PAGES` on are in the other bank
+    fn read_page(&self, page: usize) -> Vec<u8>;
+    /// Erase `page` of bank 2 if `bank2` is set, of bank 1 otherwise
+    fn erase_page(&mut self, bank2: bool, page: usize) -> Result<(), Self::Error>;
+}
+
+/// Erase the mapped `page` until it reads back blank, and add the outcome to `report`
+fn erase_verified<B: Banks>(
+    flash: &mut B,
+    page: usize,
+    report: &mut WipeReport,
+) -> Result<(), B::Error> {
+    let is_blank = |data: &[u8]| data.iter().all(|b| *b == 0xFF);
+
+    report.pages_checked += 1;
+    if is_blank(&flash.read_page(page)) {
+        return Ok(());
+    }
+
+    let bank2 = flash.fb_mode() != (page >= BANK_PAGES);
+    report.pages_erased += 1;
+    for pass in 1..=WIPE_PASSES {
+        flash.erase_page(bank2, page % BANK_PAGES)?;
+        if is_blank(&flash.read_page(page)) {
+            report.passes = core::cmp::max(report.passes, pass);
+            return Ok(());
+        }
+    }
+
+    report.passes = WIPE_PASSES;
+    report.failed_pages.push(page as u32);
+    Ok(())
+}
+
+/// Erase the config pages of both banks and, when `update_checkpoints` is set, the first page of
+/// the spare bank with the checkpoints of an interrupted update
+///
+/// Every page is read back to make sure it's blank, the ones that aren't are listed in the
+/// report by their mapped page.
+pub fn wipe<B: Banks>(flash: &mut B, update_checkpoints: bool) -> Result<WipeReport, B::Error> {
+    let mut report = WipeReport::default();
+    for page in CONFIG_PAGES
+        .iter()
+        .flat_map(|page| [*page, page + BANK_PAGES])
+    {
+        erase_verified(flash, page, &mut report)?;
+    }
+
+    if update_checkpoints {
+        erase_verified(flash, BANK_PAGES, &mut report)?;
+        report.update_checkpoints = true;
+    }
+
+    Ok(report)
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    /// Flash with small pages and an optional page that can't be erased
+    struct MockFlash {
+        fb_mode: bool,
+        banks: [Vec<Vec<u8>>; 2],
+        stuck: Option<(bool, usize)>,
+    }
+
+    impl MockFlash {
+        fn new(fb_mode: bool) -> Self {
+            MockFlash {
+                fb_mode,
+                banks: [
+                    vec![vec![0xFF; 16]; BANK_PAGES],
+                    vec![vec![0xFF; 16]; BANK_PAGES],
+                ],
+                stuck: None,
+            }
+        }
+
+        fn physical(&mut self, bank2: bool, page: usize) -> &mut Vec<u8> {
+            &mut self.banks[bank2 as usize][page]
+        }
+    }
+
+    impl Banks for MockFlash {
+        type Error = ();
+
+        fn fb_mode(&self) -> bool {
+            self.fb_mode
+        }
+
+        fn read_page(&self, page: usize) -> Vec<u8> {
+            let bank2 = self.fb_mode != (page >= BANK_PAGES);
+            self.banks[bank2 as usize][page % BANK_PAGES].clone()
+        }
+
+        fn erase_page(&mut self, bank2: bool, page: usize) -> Result<(), ()> {
+            if self.stuck != Some((bank2, page)) {
+                self.physical(bank2, page).fill(0xFF);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_wipe_swapped_banks() {
+        for fb_mode in [false, true] {
+            let mut flash = MockFlash::new(fb_mode);
+            // Both copies of the config, the running image and its state, and the checkpoints of
+            // an update in the spare bank
+            for bank2 in [false, true] {
+                for page in CONFIG_PAGES {
+                    flash.physical(bank2, page).fill(0x42);
+                }
+            }
+            flash.physical(fb_mode, 0).fill(0x42);
+            flash.physical(fb_mode, SLOT_STATE_PAGE).fill(0x42);
+            flash.physical(!fb_mode, 0).fill(0x42);
+
+            let report = wipe(&mut flash, true).unwrap();
+            assert_eq!(report.pages_checked, 5);
+            assert_eq!(report.pages_erased, 5);
+            assert_eq!(report.passes, 1);
+            assert!(report.is_verified());
+            assert!(report.update_checkpoints);
+
+            for bank2 in [false, true] {
+                for page in CONFIG_PAGES {
+                    assert!(flash.physical(bank2, page).iter().all(|b| *b == 0xFF));
+                }
+            }
+            assert!(flash.physical(!fb_mode, 0).iter().all(|b| *b == 0xFF));
+            assert!(flash.physical(fb_mode, 0).iter().all(|b| *b == 0x42));
+            assert!(flash
+                .physical(fb_mode, SLOT_STATE_PAGE)
+                .iter()
+                .all(|b| *b == 0x42));
+        }
+    }
+
+    #[test]
+    fn test_wipe_stuck_page() {
+        let mut flash = MockFlash::new(true);
+        flash.physical(true, CONFIG_PAGES[0]).fill(0x42);
+        flash.physical(false, CONFIG_PAGES[0]).fill(0x42);
+        flash.stuck = Some((true, CONFIG_PAGES[0]));
+
+        let report = wipe(&mut flash, false).unwrap();
+        assert_eq!(report.pages_checked, 4);
+        assert_eq!(report.pages_erased, 2);
+        assert_eq!(report.passes, WIPE_PASSES);
+        assert_eq!(report.failed_pages, vec![CONFIG_PAGES[0] as u32]);
+        assert!(!report.update_checkpoints);
+    }
+}
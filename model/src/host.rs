@@ -0,0 +1,127 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Identification of the hosts paired with the device
+//!
+//! A host has a static secp256k1 key. It identifies itself by signing a random challenge sent by
+//! the device for the current NFC session (`Request::GetHostChallenge`, followed by
+//! `Request::IdentifyHost`). The first time, the fingerprint of the key is shown on the device and
+//! the user confirms the pairing; afterwards the key is recognized silently, until the session
+//! ends.
+
+use alloc::string::String;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, Verification};
+
+use minicbor::bytes::ByteArray;
+use minicbor::{Decode, Encode};
+
+/// Maximum number of hosts that can be paired with the device
+pub const MAX_TRUSTED_HOSTS: usize = 8;
+
+const CHALLENGE_TAG: &[u8] = b"portal-host-challenge";
+
+/// Key of a host paired with the device
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct TrustedHost {
+    #[cbor(n(0))]
+    pub pubkey: ByteArray<33>,
+}
+
+impl TrustedHost {
+    pub fn new(pubkey: &PublicKey) -> Self {
+        TrustedHost {
+            pubkey: pubkey.serialize().into(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.pubkey)
+    }
+}
+
+/// Short identifier of a host key, shown on the device while pairing
+///
+/// The first 4 bytes of the SHA256 of the compressed key, in hex.
+pub fn fingerprint(pubkey: &[u8; 33]) -> String {
+    let hash = sha256::Hash::hash(pubkey);
+    hash[..4]
+        .iter()
+        .map(|b| alloc::format!("{:02X}", b))
+        .collect()
+}
+
+/// Message signed by the host to answer `challenge`
+pub fn challenge_message(challenge: &[u8; 32]) -> Message {
+    let mut engine = sha256::Hash::engine();
+    engine.input(CHALLENGE_TAG);
+    engine.input(challenge);
+    let hash = sha256::Hash::from_engine(engine);
+
+    Message::from_slice(&hash[..]).expect("Correct length")
+}
+
+/// Check the answer of a host to `challenge`, returning its key
+pub fn verify_challenge<C: Verification>(
+    secp: &Secp256k1<C>,
+    challenge: &[u8; 32],
+    pubkey: &[u8; 33],
+    signature: &[u8; 64],
+) -> Option<PublicKey> {
+    let pubkey = PublicKey::from_slice(pubkey).ok()?;
+    let signature = Signature::from_compact(signature).ok()?;
+    secp.verify_ecdsa(&challenge_message(challenge), &signature, &pubkey)
+        .ok()?;
+
+    Some(pubkey)
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use bitcoin::secp256k1::SecretKey;
+
+    #[test]
+    fn test_verify_challenge() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pubkey = key.public_key(&secp).serialize();
+        let challenge = [0x01; 32];
+
+        let signature = secp
+            .sign_ecdsa(&challenge_message(&challenge), &key)
+            .serialize_compact();
+        assert_eq!(
+            verify_challenge(&secp, &challenge, &pubkey, &signature),
+            Some(key.public_key(&secp))
+        );
+        assert_eq!(
+            verify_challenge(&secp, &[0x02; 32], &pubkey, &signature),
+            None
+        );
+        assert_eq!(
+            verify_challenge(&secp, &challenge, &[0x02; 33], &signature),
+            None
+        );
+
+        let host = TrustedHost::new(&key.public_key(&secp));
+        assert_eq!(host.fingerprint().len(), 8);
+        assert_eq!(host.fingerprint(), fingerprint(&pubkey));
+    }
+}
@@ -0,0 +1,137 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Wrapping of the stored configuration with a key bound to the device
+//!
+//! Wrapped data is stored as `WRAP_MAGIC || nonce || AES-256-GCM(data)`. The nonce is derived
+//! from the key and the plaintext, so that the config can be written without an RNG and the
+//! same nonce is never used for two different plaintexts.
+
+use alloc::vec::Vec;
+
+use aes_gcm::aead::AeadMut;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+pub const WRAP_MAGIC: [u8; 4] = *b"WRP1";
+
+const NONCE_LEN: usize = 12;
+const KEY_TAG: &[u8] = b"Portal/WrappingKey";
+const NONCE_TAG: &[u8] = b"Portal/WrappingNonce";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapError {
+    NotWrapped,
+    InvalidData,
+}
+
+#[derive(Clone)]
+pub struct WrappingKey([u8; 32]);
+
+impl core::fmt::Debug for WrappingKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("WrappingKey(..)")
+    }
+}
+
+fn tagged_hash(tag: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag);
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag);
+    engine.input(&tag);
+    for part in parts {
+        engine.input(part);
+    }
+
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+impl WrappingKey {
+    /// Derive the key from secret material bound to the device
+    pub fn new(material: &[&[u8]]) -> Self {
+        WrappingKey(tagged_hash(KEY_TAG, material))
+    }
+
+    pub fn wrap(&self, data: &[u8]) -> Vec<u8> {
+        let nonce = tagged_hash(NONCE_TAG, &[&self.0, data]);
+        let nonce = &nonce[..NONCE_LEN];
+
+        let encrypted = Aes256Gcm::new_from_slice(&self.0)
+            .expect("Correct length")
+            .encrypt(Nonce::from_slice(nonce), data)
+            .expect("Always ok");
+
+        let mut wrapped = Vec::from(WRAP_MAGIC);
+        wrapped.extend_from_slice(nonce);
+        wrapped.extend(encrypted);
+        wrapped
+    }
+
+    pub fn unwrap(&self, data: &[u8]) -> Result<Vec<u8>, WrapError> {
+        if !is_wrapped(data) {
+            return Err(WrapError::NotWrapped);
+        }
+        let data = &data[WRAP_MAGIC.len()..];
+        if data.len() < NONCE_LEN {
+            return Err(WrapError::InvalidData);
+        }
+        let (nonce, encrypted) = data.split_at(NONCE_LEN);
+
+        Aes256Gcm::new_from_slice(&self.0)
+            .expect("Correct length")
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| WrapError::InvalidData)
+    }
+}
+
+/// Whether `data` was written by `WrappingKey::wrap()`, as opposed to a plain config
+pub fn is_wrapped(data: &[u8]) -> bool {
+    data.starts_with(&WRAP_MAGIC)
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let key = WrappingKey::new(&[b"secret", b"uid"]);
+        let wrapped = key.wrap(b"config");
+
+        assert!(is_wrapped(&wrapped));
+        assert!(!wrapped.windows(6).any(|w| w == b"config"));
+        assert_eq!(key.unwrap(&wrapped).unwrap(), b"config");
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let wrapped = WrappingKey::new(&[b"secret", b"uid"]).wrap(b"config");
+        let other = WrappingKey::new(&[b"secret", b"other uid"]);
+
+        assert_eq!(other.unwrap(&wrapped), Err(WrapError::InvalidData));
+    }
+
+    #[test]
+    fn test_not_wrapped() {
+        let key = WrappingKey::new(&[b"secret"]);
+
+        assert_eq!(key.unwrap(&[0x82, 0x00]), Err(WrapError::NotWrapped));
+        assert_eq!(key.unwrap(b"WRP1abc"), Err(WrapError::InvalidData));
+    }
+}
@@ -46,10 +46,68 @@ pub const DEFAULT_PASSWORD_ITERATIONS: usize = 1024;
 
 pub const HARDENED_FLAG: u32 = 0x80000000;
 
+/// Version of the `Request`/`Reply` encoding, reported by the device in `DeviceInfo`
+///
+/// Messages stay compatible across versions as long as:
+/// - indices of variants and fields are never reused or renumbered;
+/// - new fields are added with a new index and an `Option` type, so that they decode as `None`
+///   when missing. Fields unknown to the receiver are skipped;
+/// - new variants are only sent to devices or hosts that advertise a version that supports them.
+///   A device that receives a request it doesn't know replies with
+///   `ErrorCode::UnsupportedRequest`, a host that receives an unknown reply fails the request.
+///
+/// Bump this every time a variant is added to `Request` or `Reply`.
+pub const PROTOCOL_VERSION: u32 = 22;
+
+/// Features reported by the device in `DeviceInfo::capabilities`, one bit each
+///
+/// Unlike new variants, new optional fields of existing requests are silently ignored by older
+/// firmwares, so hosts check for the matching bit before relying on them.
+pub mod capabilities {
+    /// `Request::SignPsbt` can ask for the whole signed PSBT
+    pub const FULL_SIGNED_PSBT: u32 = 1 << 0;
+    /// `Request::SignPsbt` can ask for the finalized transaction
+    pub const FINALIZE_PSBT: u32 = 1 << 1;
+    /// `Request::BeginSignPsbt` can carry an exchange rate to show fiat values
+    pub const FIAT_RATE: u32 = 1 << 2;
+    /// `Request::BeginSignPsbt` can carry the parent of a CPFP
+    pub const CPFP_INFO: u32 = 1 << 3;
+}
+
+/// Capabilities of this firmware, see `capabilities`
+pub const CAPABILITIES: u32 = capabilities::FULL_SIGNED_PSBT
+    | capabilities::FINALIZE_PSBT
+    | capabilities::FIAT_RATE
+    | capabilities::CPFP_INFO;
+
+pub mod address_book;
+pub mod anti_exfil;
+pub mod attestation;
+pub mod backup;
+pub mod bip322;
+pub mod bip85;
+pub mod config_log;
 #[cfg(feature = "emulator")]
 pub mod emulator;
 pub mod encryption;
+pub mod flash;
+pub mod host;
+pub mod keywrap;
+pub mod logs;
+pub mod mnemonic;
+pub mod multipath;
+pub mod musig2;
+pub mod paths;
+pub mod psbt;
+pub mod psbt_stream;
+pub mod psbt_v2;
 pub mod reg;
+pub mod selftest;
+pub mod settings;
+pub mod sig_diff;
+pub mod signmessage;
+pub mod taproot;
+pub mod wallet_policy;
 pub mod write_buffer;
 
 #[derive(Debug)]
@@ -96,7 +154,8 @@ impl MessageFragment {
 
     #[inline(always)]
     pub fn len(&self) -> usize {
-        self.buf[1] as usize
+        // The length byte comes from the other side, never trust it to be within bounds
+        core::cmp::min(self.buf[1] as usize, MAX_FRAGMENT_LEN - 2)
     }
 
     pub(crate) fn get_filled_data(&self) -> &[u8] {
@@ -231,12 +290,7 @@ impl Message {
     }
 
     fn iter_chunks<'s>(&'s self, chunk_size: usize) -> impl Iterator<Item = (&'s [u8], bool)> + 's {
-        let last_chunk = self.buf.len() / chunk_size
-            - if self.buf.len() % chunk_size == 0 {
-                1
-            } else {
-                0
-            };
+        let last_chunk = self.buf.len().saturating_sub(1) / chunk_size;
         self.buf
             .chunks(chunk_size)
             .enumerate()
@@ -271,6 +325,16 @@ impl AsRef<[u8]> for Message {
 pub struct Entropy {
     #[cbor(n(0))]
     pub bytes: ByteVec,
+    /// Wordlist of the mnemonic, missing for seeds created before other wordlists were supported
+    #[cbor(n(1))]
+    pub language: Option<mnemonic::MnemonicLanguage>,
+}
+
+impl Entropy {
+    /// Mnemonic of the seed, in the wordlist it was created with
+    pub fn mnemonic(&self) -> Result<bip39::Mnemonic, bip39::Error> {
+        bip39::Mnemonic::from_entropy_in(self.language.unwrap_or_default().wordlist(), &self.bytes)
+    }
 }
 
 #[derive(Debug, Encode, Decode, Clone)]
@@ -294,7 +358,7 @@ impl From<bip32::ExtendedPrivKey> for SerializedXprv {
 #[derive(Debug, Encode, Decode)]
 pub enum Config {
     #[cbor(n(0))]
-    Initialized(#[cbor(n(0))] InitializedConfig),
+    Initialized(#[cbor(n(0))] Box<InitializedConfig>),
     #[cbor(n(1))]
     Unverified(#[cbor(n(0))] UnverifiedConfig),
 }
@@ -312,6 +376,8 @@ pub struct UnverifiedConfig {
     pub descriptor: WalletDescriptor,
     #[cbor(n(4))]
     pub page: usize,
+    #[cbor(n(5))]
+    pub disable_seed_export: Option<bool>,
 }
 
 #[derive(Debug, Clone, Encode, Decode)]
@@ -352,6 +418,10 @@ pub enum ScriptType {
     WrappedSegwit,
     #[cbor(n(2))]
     NativeSegwit,
+    /// Single key spent through the key path, or `sortedmulti_a` behind an unspendable internal
+    /// key (see `taproot`)
+    #[cbor(n(3))]
+    Taproot,
 }
 
 impl ScriptType {
@@ -360,6 +430,7 @@ impl ScriptType {
             ScriptType::Legacy => "Legacy",
             ScriptType::WrappedSegwit => "Wrapped Segwit",
             ScriptType::NativeSegwit => "Native Segwit",
+            ScriptType::Taproot => "Taproot",
         }
     }
 }
@@ -397,6 +468,90 @@ pub enum MultisigKey {
     External(#[cbor(n(0))] ExtendedKey),
 }
 
+/// Script of a leaf of a taproot tree, with keys of type `K`
+#[derive(Debug, Clone, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub enum TapLeafScript<K> {
+    /// `pk(key)`
+    #[cbor(n(0))]
+    Key(#[cbor(n(0))] K),
+    /// `multi_a(threshold, keys...)`, with the keys in this order
+    #[cbor(n(1))]
+    MultiA {
+        #[cbor(n(0))]
+        threshold: usize,
+        #[cbor(n(1))]
+        keys: Vec<K>,
+    },
+}
+
+impl<K> TapLeafScript<K> {
+    pub fn keys(&self) -> &[K] {
+        match self {
+            TapLeafScript::Key(key) => core::slice::from_ref(key),
+            TapLeafScript::MultiA { keys, .. } => keys,
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        match self {
+            TapLeafScript::Key(_) => 1,
+            TapLeafScript::MultiA { threshold, .. } => *threshold,
+        }
+    }
+}
+
+/// Leaf of a taproot tree
+///
+/// A tree is sent as the list of its leaves in depth-first order, each with its depth, like in
+/// the `PSBT_OUT_TAP_TREE` field of BIP-371.
+#[derive(Debug, Clone, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct TapLeaf<K> {
+    #[cbor(n(0))]
+    pub depth: u8,
+    #[cbor(n(1))]
+    pub script: TapLeafScript<K>,
+}
+
+impl<K> TapLeaf<K> {
+    /// Rebuild the tree described by `leaves`, calling `leaf` on every leaf and `branch` to join
+    /// two nodes with the same parent
+    ///
+    /// Returns `None` if the depths don't describe a complete binary tree.
+    pub fn fold_tree<T>(
+        leaves: &[Self],
+        mut leaf: impl FnMut(&Self) -> T,
+        mut branch: impl FnMut(T, T) -> T,
+    ) -> Option<T> {
+        // Nodes waiting for their sibling, with their depth
+        let mut stack: Vec<(u8, T)> = Vec::new();
+        for l in leaves {
+            if l.depth > bitcoin::util::taproot::TAPROOT_CONTROL_MAX_NODE_COUNT as u8 {
+                return None;
+            }
+
+            let mut node = (l.depth, leaf(l));
+            while let Some((depth, _)) = stack.last() {
+                if *depth != node.0 {
+                    break;
+                }
+                let (depth, left) = stack.pop().expect("Just checked");
+                if depth == 0 {
+                    return None;
+                }
+                node = (depth - 1, branch(left, node.1));
+            }
+            stack.push(node);
+        }
+
+        match (stack.pop(), stack.is_empty()) {
+            (Some((0, root)), true) => Some(root),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Encode, Decode)]
 #[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
 pub struct SerializedFingerprint {
@@ -449,6 +604,61 @@ impl From<bip32::ExtendedPubKey> for SerializedXpub {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub enum Keychain {
+    #[cbor(n(0))]
+    External,
+    #[cbor(n(1))]
+    Internal,
+}
+
+/// How the address sent with `Reply::Address` was derived
+///
+/// Lets the host derive the address on its own from the descriptor it registered, instead of
+/// trusting the string.
+#[derive(Debug, Clone, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddressInfo {
+    #[cbor(n(0))]
+    pub keychain: Keychain,
+    #[cbor(n(1))]
+    pub index: u32,
+    /// Full path of the key of the device, including the keychain and the index
+    #[cbor(n(2))]
+    pub path: SerializedDerivationPath,
+    #[cbor(n(3))]
+    pub script_type: ScriptType,
+    /// Output script the address pays to
+    #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+    #[cbor(n(4))]
+    pub script_pubkey: ByteVec,
+}
+
+/// Structured form of the key sent with `Reply::Xpub`
+///
+/// Carries the same key as the string, so that hosts can build descriptors without parsing it.
+#[derive(Debug, Clone, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct XpubKey {
+    /// BIP-32 serialization of the key
+    #[cbor(n(0))]
+    pub key: SerializedXpub,
+    /// Fingerprint of the parent of the key, zero for the master key
+    #[cbor(n(1))]
+    pub parent_fingerprint: SerializedFingerprint,
+    /// Fingerprint of the master key the path starts from
+    #[cbor(n(2))]
+    pub master_fingerprint: SerializedFingerprint,
+    #[cbor(n(3))]
+    pub origin_path: SerializedDerivationPath,
+    /// Network of the wallet, the serialization alone doesn't tell testnet, signet and regtest
+    /// apart
+    #[cbor(with = "cbor_bitcoin_network")]
+    #[cbor(n(4))]
+    pub network: bitcoin::Network,
+}
+
 #[derive(Debug, Clone, Encode, Decode)]
 #[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
 pub struct SerializedDerivationPath {
@@ -484,6 +694,15 @@ pub enum DescriptorVariant {
         #[cbor(n(2))]
         is_sorted: bool,
     },
+    /// `tr()` with a script tree, only with `ScriptType::Taproot`
+    #[cbor(n(2))]
+    TapTree {
+        /// `None` for the unspendable `taproot::NUMS_KEY`
+        #[cbor(n(0))]
+        internal_key: Option<MultisigKey>,
+        #[cbor(n(1))]
+        leaves: Vec<TapLeaf<MultisigKey>>,
+    },
 }
 
 impl DescriptorVariant {
@@ -496,6 +715,23 @@ impl DescriptorVariant {
             DescriptorVariant::MultiSig {
                 is_sorted: false, ..
             } => "Multi-sig",
+            DescriptorVariant::TapTree { .. } => "Taproot tree",
+        }
+    }
+
+    /// Keys of a multisig or of a taproot tree, starting from the internal key. Empty for
+    /// single-sig descriptors
+    pub fn keys(&self) -> Vec<&MultisigKey> {
+        match self {
+            DescriptorVariant::SingleSig(_) => Vec::new(),
+            DescriptorVariant::MultiSig { keys, .. } => keys.iter().collect(),
+            DescriptorVariant::TapTree {
+                internal_key,
+                leaves,
+            } => internal_key
+                .iter()
+                .chain(leaves.iter().flat_map(|l| l.script.keys()))
+                .collect(),
         }
     }
 }
@@ -514,6 +750,15 @@ pub enum SetDescriptorVariant {
         #[cbor(n(2))]
         is_sorted: bool,
     },
+    /// `tr()` with a script tree, only with `ScriptType::Taproot`. Needs protocol version 21
+    #[cbor(n(2))]
+    TapTree {
+        /// `None` for the unspendable `taproot::NUMS_KEY`
+        #[cbor(n(0))]
+        internal_key: Option<ExtendedKey>,
+        #[cbor(n(1))]
+        leaves: Vec<TapLeaf<ExtendedKey>>,
+    },
 }
 
 impl UnverifiedConfig {
@@ -521,12 +766,12 @@ impl UnverifiedConfig {
         self,
         salt: [u8; 8],
     ) -> (InitializedConfig, UnlockedConfig, bip32::ExtendedPrivKey) {
-        let mnemonic = bip39::Mnemonic::from_entropy(&self.entropy.bytes).expect("Valid entropy");
+        let mnemonic = self.entropy.mnemonic().expect("Valid entropy");
         let xprv =
             bip32::ExtendedPrivKey::new_master(self.network, &mnemonic.to_seed_normalized(""))
                 .expect("Valid entropy");
 
-        let unlocked = UnlockedConfig::new(
+        let mut unlocked = UnlockedConfig::new(
             self.entropy,
             xprv.into(),
             self.descriptor,
@@ -534,6 +779,7 @@ impl UnverifiedConfig {
             self.pair_code.as_deref(),
             salt,
         );
+        unlocked.secret.disable_seed_export = self.disable_seed_export;
 
         (unlocked.clone().lock(), unlocked, xprv)
     }
@@ -548,6 +794,9 @@ pub struct InitializedConfig {
     pub network: bitcoin::Network,
     #[cbor(n(2))]
     pub pair_code: Password,
+    /// Missing in configs saved before the settings menu was added
+    #[cbor(n(3))]
+    pub settings: Option<settings::DeviceSettings>,
 }
 
 impl InitializedConfig {
@@ -568,7 +817,7 @@ impl InitializedConfig {
         }
 
         let (secret, encryption_key) = match self.secret {
-            MaybeEncrypted::Unencrypted(inner) => (inner, None),
+            MaybeEncrypted::Unencrypted(inner) => (*inner, None),
             MaybeEncrypted::Encrypted { data, nonce } => {
                 let encryption_key = EncryptionKey::new(password, nonce);
                 (
@@ -583,6 +832,7 @@ impl InitializedConfig {
             network: self.network,
             password: self.pair_code,
             encryption_key,
+            settings: self.settings.unwrap_or_default(),
         })
     }
 }
@@ -593,6 +843,7 @@ pub struct UnlockedConfig {
     pub network: bitcoin::Network,
     pub password: Password,
     encryption_key: Option<EncryptionKey>,
+    pub settings: settings::DeviceSettings,
 }
 
 impl UnlockedConfig {
@@ -609,10 +860,17 @@ impl UnlockedConfig {
                 mnemonic,
                 cached_xprv,
                 descriptor,
+                disable_seed_export: None,
+                confirmation_policy: None,
+                derived_keys: None,
+                trusted_hosts: None,
+                trusted_addresses: None,
+                wallet_policy: None,
             },
             network,
             password: password.map(|p| Password::new(p, salt)).unwrap_or_default(),
             encryption_key: password.map(|p| EncryptionKey::new(p, 0)),
+            settings: Default::default(),
         }
     }
 
@@ -622,12 +880,43 @@ impl UnlockedConfig {
             network,
             password: Default::default(),
             encryption_key: None,
+            settings: Default::default(),
         }
     }
 
+    /// Whether the config is protected by a pair code, so the device can be locked
+    pub fn has_pair_code(&self) -> bool {
+        self.encryption_key.is_some()
+    }
+
+    /// Whether the seed can leave the device, see `SecretData::disable_seed_export`
+    pub fn seed_export_allowed(&self) -> bool {
+        self.secret.disable_seed_export != Some(true)
+    }
+
+    /// Requests answered without a confirmation, none for configs saved by older firmwares
+    pub fn confirmation_policy(&self) -> ConfirmationPolicy {
+        self.secret.confirmation_policy.unwrap_or_default()
+    }
+
+    /// Hosts paired with the device, see `model::host`
+    pub fn trusted_hosts(&self) -> &[host::TrustedHost] {
+        self.secret.trusted_hosts.as_deref().unwrap_or_default()
+    }
+
+    /// Address book of the device, see `model::address_book`
+    pub fn trusted_addresses(&self) -> &[address_book::TrustedAddress] {
+        self.secret.trusted_addresses.as_deref().unwrap_or_default()
+    }
+
+    /// Policy registered for the descriptor, see `model::wallet_policy`
+    pub fn wallet_policy(&self) -> Option<&wallet_policy::WalletPolicy> {
+        self.secret.wallet_policy.as_ref()
+    }
+
     pub fn lock(mut self) -> InitializedConfig {
         let secret = match self.encryption_key {
-            None => MaybeEncrypted::Unencrypted(self.secret),
+            None => MaybeEncrypted::Unencrypted(Box::new(self.secret)),
             Some(ref mut encryption_key) => {
                 let data = minicbor::to_vec(self.secret).expect("Always serializable");
                 encryption_key
@@ -644,6 +933,7 @@ impl UnlockedConfig {
             secret,
             network: self.network,
             pair_code: self.password,
+            settings: Some(self.settings),
         }
     }
 }
@@ -767,6 +1057,178 @@ pub struct SecretData {
     pub cached_xprv: SerializedXprv,
     #[cbor(n(2))]
     pub descriptor: WalletDescriptor,
+    /// Refuse to export the seed, even in an encrypted backup
+    ///
+    /// Kept with the secret data, so that it can't be changed without unlocking the device.
+    #[cbor(n(3))]
+    pub disable_seed_export: Option<bool>,
+    /// Since protocol version 7, see `UnlockedConfig::confirmation_policy`
+    #[cbor(n(4))]
+    pub confirmation_policy: Option<ConfirmationPolicy>,
+    /// Missing in configs saved before the cache was added, or until the device boots again
+    #[cbor(n(5))]
+    pub derived_keys: Option<DerivedKeysCache>,
+    /// Hosts paired with `Request::IdentifyHost`, missing in configs saved before pairing was
+    /// added
+    #[cbor(n(6))]
+    pub trusted_hosts: Option<Vec<host::TrustedHost>>,
+    /// Addresses added with `Request::AddTrustedAddress`, missing in configs saved before the
+    /// address book was added
+    #[cbor(n(7))]
+    pub trusted_addresses: Option<Vec<address_book::TrustedAddress>>,
+    /// Name of the descriptor, when it was set with `Request::RegisterWalletPolicy`
+    #[cbor(n(8))]
+    pub wallet_policy: Option<wallet_policy::WalletPolicy>,
+}
+
+/// Keys derived from `SecretData::cached_xprv`, so that the hardened derivations don't have to
+/// be repeated on every boot and for every input that's signed
+///
+/// Like `cached_xprv` it's only a cache: it's rebuilt from the master key whenever it's missing or
+/// doesn't cover the local keys of the descriptor.
+#[derive(Debug, Encode, Decode, Clone)]
+pub struct DerivedKeysCache {
+    #[cbor(n(0))]
+    pub master_fingerprint: SerializedFingerprint,
+    #[cbor(n(1))]
+    pub account_keys: Vec<CachedAccountKey>,
+}
+
+/// Private key at the end of the hardened part of a local key path, e.g. `m/84'/0'/0'`
+#[derive(Debug, Encode, Decode, Clone)]
+pub struct CachedAccountKey {
+    #[cbor(n(0))]
+    pub path: SerializedDerivationPath,
+    #[cbor(n(1))]
+    pub xprv: SerializedXprv,
+}
+
+impl DerivedKeysCache {
+    /// Derive the account keys of `xprv` at each of `paths`
+    pub fn new<C: bitcoin::secp256k1::Signing>(
+        secp: &bitcoin::secp256k1::Secp256k1<C>,
+        xprv: &bip32::ExtendedPrivKey,
+        paths: &[bip32::DerivationPath],
+    ) -> Result<Self, bip32::Error> {
+        let account_keys = paths
+            .iter()
+            .map(|path| {
+                Ok(CachedAccountKey {
+                    path: path.clone().into(),
+                    xprv: xprv.derive_priv(secp, path)?.into(),
+                })
+            })
+            .collect::<Result<_, bip32::Error>>()?;
+
+        Ok(DerivedKeysCache {
+            master_fingerprint: xprv.fingerprint(secp).into(),
+            account_keys,
+        })
+    }
+
+    /// Cached key at `path`, `None` if it's not in the cache
+    pub fn account_key(&self, path: &bip32::DerivationPath) -> Option<bip32::ExtendedPrivKey> {
+        self.account_keys
+            .iter()
+            .find(|k| {
+                k.path
+                    .value
+                    .iter()
+                    .copied()
+                    .eq(path.into_iter().map(|c| u32::from(*c)))
+            })
+            .and_then(|k| k.xprv.as_xprv().ok())
+            .filter(|xprv| xprv.depth as usize == path.len())
+    }
+
+    /// Whether every one of `paths` is in the cache
+    pub fn covers(&self, paths: &[bip32::DerivationPath]) -> bool {
+        paths.iter().all(|path| self.account_key(path).is_some())
+    }
+}
+
+/// Requests answered without a confirmation on the device, approved once with
+/// `Request::SetConfirmationPolicy`
+///
+/// Meant for hosts that re-derive watch-only data on every start. Confirmations are only skipped
+/// for hosts paired with the device and identified in the current session (see `host`). Signing
+/// always requires a confirmation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConfirmationPolicy {
+    /// Answer `Request::GetXpub` without a confirmation
+    #[cbor(n(0))]
+    pub silent_get_xpub: bool,
+    /// Answer `Request::PublicDescriptor` without a confirmation
+    #[cbor(n(1))]
+    pub silent_public_descriptor: bool,
+}
+
+impl ConfirmationPolicy {
+    /// Whether some requests skip the confirmation
+    pub fn is_relaxed(&self) -> bool {
+        self.silent_get_xpub || self.silent_public_descriptor
+    }
+}
+
+/// Nonce committed to by the device for one of its signatures, see `anti_exfil`
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignerCommitment {
+    /// Index of the input signed
+    #[cbor(n(0))]
+    pub input: u32,
+    /// Compressed public key that makes the signature
+    #[cbor(n(1))]
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    pub pubkey: Box<ByteArray<33>>,
+    /// Compressed nonce point, see `anti_exfil::signer_commitment()`
+    #[cbor(n(2))]
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    pub nonce: Box<ByteArray<33>>,
+}
+
+/// Outcome of a wipe, see `Reply::Wiped`
+///
+/// Every page that may hold secrets is erased and read back, and erased again if it isn't
+/// blank yet. The pages that were already blank are checked but not counted as erased.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct WipeReport {
+    /// Pages read back after the wipe
+    #[cbor(n(0))]
+    pub pages_checked: u32,
+    /// Pages that held some data and had to be erased
+    #[cbor(n(1))]
+    pub pages_erased: u32,
+    /// Highest number of erase passes needed by a page
+    #[cbor(n(2))]
+    pub passes: u32,
+    /// Pages that still weren't blank after the last pass
+    #[cbor(n(3))]
+    pub failed_pages: Vec<u32>,
+    /// Whether the checkpoints of an interrupted firmware update were erased as well
+    #[cbor(n(4))]
+    pub update_checkpoints: bool,
+}
+
+impl WipeReport {
+    /// Whether every page read back blank
+    pub fn is_verified(&self) -> bool {
+        self.failed_pages.is_empty()
+    }
 }
 
 #[derive(Debug, Encode, Decode, Clone)]
@@ -779,7 +1241,7 @@ pub enum MaybeEncrypted {
         nonce: u32,
     },
     #[cbor(n(1))]
-    Unencrypted(#[cbor(n(0))] SecretData),
+    Unencrypted(#[cbor(n(0))] Box<SecretData>),
 }
 
 #[derive(Clone, Debug, Encode, Decode)]
@@ -789,6 +1251,21 @@ pub struct DeviceInfo {
     pub initialized: InitializationStatus,
     #[cbor(n(1))]
     pub firmware_version: Option<String>,
+    /// `PROTOCOL_VERSION` of the firmware, `None` for firmwares that predate versioning
+    #[cbor(n(2))]
+    pub protocol_version: Option<u32>,
+    /// Git hash the firmware was built from
+    #[cbor(n(3))]
+    pub firmware_hash: Option<String>,
+    /// Identifier and revision of the microcontroller
+    #[cbor(n(4))]
+    pub hardware_revision: Option<String>,
+    /// Serial number programmed at manufacture
+    #[cbor(n(5))]
+    pub serial: Option<String>,
+    /// Bits from `capabilities`, `None` for firmwares that predate them
+    #[cbor(n(6))]
+    pub capabilities: Option<u32>,
 }
 
 #[derive(Clone, Debug, Encode, Decode)]
@@ -806,6 +1283,9 @@ pub enum InitializationStatus {
         /// Since v0.3.0
         #[cbor(n(2))]
         fingerprint: Option<[u8; 4]>,
+        /// Only sent while unlocked
+        #[cbor(n(3))]
+        descriptor: Option<DescriptorSummary>,
     },
     #[cbor(n(2))]
     Unverified {
@@ -822,6 +1302,11 @@ impl DeviceInfo {
         DeviceInfo {
             initialized: InitializationStatus::Uninitialized,
             firmware_version: Some(version.to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            firmware_hash: None,
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(CAPABILITIES),
         }
     }
 
@@ -831,8 +1316,14 @@ impl DeviceInfo {
                 unlocked: false,
                 network,
                 fingerprint: None,
+                descriptor: None,
             },
             firmware_version: Some(version.to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            firmware_hash: None,
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(CAPABILITIES),
         }
     }
 
@@ -844,12 +1335,18 @@ impl DeviceInfo {
         DeviceInfo {
             initialized: InitializationStatus::Unverified { with_code, network },
             firmware_version: Some(version.to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            firmware_hash: None,
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(CAPABILITIES),
         }
     }
 
     pub fn new_unlocked_initialized(
         network: bitcoin::Network,
         fingerprint: [u8; 4],
+        descriptor: &WalletDescriptor,
         version: &'static str,
     ) -> Self {
         DeviceInfo {
@@ -857,8 +1354,87 @@ impl DeviceInfo {
                 unlocked: true,
                 network,
                 fingerprint: Some(fingerprint),
+                descriptor: Some(descriptor.into()),
             },
             firmware_version: Some(version.to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            firmware_hash: None,
+            hardware_revision: None,
+            serial: None,
+            capabilities: Some(CAPABILITIES),
+        }
+    }
+
+    /// Add the details of the firmware build and of the hardware
+    pub fn with_device_details(
+        mut self,
+        firmware_hash: Option<String>,
+        hardware_revision: Option<String>,
+        serial: Option<String>,
+    ) -> Self {
+        self.firmware_hash = firmware_hash;
+        self.hardware_revision = hardware_revision;
+        self.serial = serial;
+        self
+    }
+
+    /// Whether the device supports a feature from `capabilities`
+    pub fn has_capability(&self, capability: u32) -> bool {
+        self.capabilities
+            .is_some_and(|capabilities| capabilities & capability != 0)
+    }
+}
+
+/// Short summary of the wallet descriptor, see `DeviceInfo`
+///
+/// Enough for the host to check that the device is set up as expected, without the keys of
+/// `Request::PublicDescriptor`. Single-sig descriptors are reported as 1-of-1, taproot trees with
+/// the lowest threshold among their spending paths and the number of keys in all of them.
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct DescriptorSummary {
+    #[cbor(n(0))]
+    pub script_type: ScriptType,
+    #[cbor(n(1))]
+    pub threshold: usize,
+    #[cbor(n(2))]
+    pub num_keys: usize,
+    #[cbor(n(3))]
+    pub is_sorted: bool,
+}
+
+impl From<&WalletDescriptor> for DescriptorSummary {
+    fn from(descriptor: &WalletDescriptor) -> Self {
+        let (threshold, num_keys, is_sorted) = match &descriptor.variant {
+            DescriptorVariant::SingleSig(_) => (1, 1, false),
+            DescriptorVariant::MultiSig {
+                threshold,
+                keys,
+                is_sorted,
+            } => (*threshold, keys.len(), *is_sorted),
+            DescriptorVariant::TapTree {
+                internal_key,
+                leaves,
+            } => {
+                let threshold = match internal_key {
+                    Some(_) => 1,
+                    None => leaves
+                        .iter()
+                        .map(|l| l.script.threshold())
+                        .min()
+                        .unwrap_or(0),
+                };
+                let num_keys = internal_key.iter().count()
+                    + leaves.iter().map(|l| l.script.keys().len()).sum::<usize>();
+                (threshold, num_keys, false)
+            }
+        };
+
+        DescriptorSummary {
+            script_type: descriptor.script_type.clone(),
+            threshold,
+            num_keys,
+            is_sorted,
         }
     }
 }
@@ -905,6 +1481,87 @@ pub struct FwUpdateHeader {
     )]
     #[cbor(n(3))]
     pub first_page_midstate: Box<ByteArray<32>>,
+    /// Version read from the tail of the image, checked against it once the update is complete
+    #[cbor(n(4))]
+    pub version: u32,
+    /// SHA256 of the whole image, checked against it once the update is complete
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    #[cbor(n(5))]
+    pub hash: Box<ByteArray<32>>,
+}
+
+/// Exchange rate sent by the host, used to show the approximate fiat value of the outputs
+///
+/// The device has no way to check it, so it's only shown as an estimate next to the amounts in
+/// bitcoin, together with a page saying that it comes from the host.
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct FiatRate {
+    /// Value of one bitcoin in `currency`
+    #[cbor(n(0))]
+    pub rate: f64,
+    /// ISO 4217 code, like "USD"
+    #[cbor(n(1))]
+    pub currency: String,
+}
+
+impl FiatRate {
+    /// Whether the rate is positive and the currency is a three letter code
+    pub fn is_valid(&self) -> bool {
+        self.rate.is_finite()
+            && self.rate > 0.0
+            && self.currency.len() == 3
+            && self.currency.bytes().all(|c| c.is_ascii_uppercase())
+    }
+
+    /// Approximate value of `amount`, like "~12.34 USD"
+    pub fn format(&self, amount: bitcoin::Amount) -> String {
+        alloc::format!("~{:.2} {}", amount.to_btc() * self.rate, self.currency)
+    }
+}
+
+/// Context sent by the host when the transaction pays for an unconfirmed parent (CPFP)
+///
+/// The device checks that the parent is spent by one of the inputs, but it can't know the fees
+/// of the parent: the fee rate is shown as computed by the host.
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpfpInfo {
+    #[cbor(n(0))]
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    pub parent_txid: Box<ByteArray<32>>,
+    /// Fee rate of the parent and this transaction together, in sat/vB
+    #[cbor(n(1))]
+    pub fee_rate: f32,
+}
+
+impl CpfpInfo {
+    pub fn new(parent_txid: &bitcoin::Txid, fee_rate: f32) -> Self {
+        CpfpInfo {
+            parent_txid: Box::new(parent_txid.into_inner().into()),
+            fee_rate,
+        }
+    }
+
+    pub fn parent_txid(&self) -> bitcoin::Txid {
+        bitcoin::Txid::from_inner(**self.parent_txid)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.fee_rate.is_finite() && self.fee_rate > 0.0
+    }
 }
 
 #[derive(Debug, Clone, Encode, Decode)]
@@ -921,6 +1578,12 @@ pub enum Request {
         network: bitcoin::Network,
         #[cbor(n(2))]
         password: Option<String>,
+        /// Since protocol version 2, see `SecretData::disable_seed_export`
+        #[cbor(n(3))]
+        disable_seed_export: Option<bool>,
+        /// Since protocol version 8, English if missing
+        #[cbor(n(4))]
+        language: Option<mnemonic::MnemonicLanguage>,
     },
     #[cbor(n(2))]
     SetMnemonic {
@@ -931,14 +1594,37 @@ pub enum Request {
         network: bitcoin::Network,
         #[cbor(n(2))]
         password: Option<String>,
+        /// Since protocol version 2, see `SecretData::disable_seed_export`
+        #[cbor(n(3))]
+        disable_seed_export: Option<bool>,
     },
     #[cbor(n(3))]
     UpdateFirmware,
     #[cbor(n(4))]
-    BeginSignPsbt,
+    BeginSignPsbt {
+        /// Approximate fiat values shown with the outputs, on devices with
+        /// `capabilities::FIAT_RATE`
+        #[cbor(n(0))]
+        fiat_rate: Option<FiatRate>,
+        /// Parent bumped by the transaction, shown before the outputs on devices with
+        /// `capabilities::CPFP_INFO`
+        #[cbor(n(1))]
+        cpfp: Option<CpfpInfo>,
+    },
     #[cbor(n(5))]
-    #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
-    SignPsbt(#[cbor(n(0))] ByteVec),
+    SignPsbt(
+        #[cbor(n(0))]
+        #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+        ByteVec,
+        /// Reply with the whole signed PSBT instead of only the signatures added, on devices with
+        /// `capabilities::FULL_SIGNED_PSBT`
+        #[cbor(n(1))]
+        Option<bool>,
+        /// Finalize the inputs and reply with `Reply::SignedTransaction` if the device adds the
+        /// last signatures needed, on devices with `capabilities::FINALIZE_PSBT`
+        #[cbor(n(2))]
+        Option<bool>,
+    ),
     #[cbor(n(6))]
     DisplayAddress(#[cbor(n(0))] u32),
     #[cbor(n(7))]
@@ -983,6 +1669,243 @@ pub enum Request {
         #[cbor(n(2))]
         bsms: Option<BsmsRound2>,
     },
+    /// Query the page an interrupted update of the same image would resume from
+    #[cbor(n(16))]
+    GetFwUpdateOffset(#[cbor(n(0))] FwUpdateHeader),
+    /// Prove that the device is genuine by signing the challenge
+    #[cbor(n(17))]
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    Attest(#[cbor(n(0))] Box<ByteArray<32>>),
+    /// Store the attestation key, only accepted once at manufacture
+    #[cbor(n(18))]
+    ProvisionAttestation(#[cbor(n(0))] attestation::AttestationKey),
+    /// Export the config encrypted with `password`, optionally including the seed
+    #[cbor(n(19))]
+    ExportBackup {
+        #[cbor(n(0))]
+        password: String,
+        #[cbor(n(1))]
+        include_seed: bool,
+    },
+    /// Restore a backup made with `ExportBackup`
+    ///
+    /// On a new device the backup must include the seed and `pair_code` is set as the new pair
+    /// code. On an unlocked device only the settings are restored and `pair_code` is ignored.
+    #[cbor(n(20))]
+    RestoreBackup {
+        #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+        #[cbor(n(0))]
+        backup: ByteVec,
+        #[cbor(n(1))]
+        password: String,
+        #[cbor(n(2))]
+        pair_code: Option<String>,
+    },
+    /// Run the hardware self-test, which asks the user to touch the button
+    #[cbor(n(21))]
+    SelfTest,
+    /// Allow or forbid exporting the seed, after a confirmation on the device
+    #[cbor(n(22))]
+    SetSeedExport {
+        #[cbor(n(0))]
+        allowed: bool,
+    },
+    /// Start wiping the device, which shows a random code on the screen
+    #[cbor(n(23))]
+    BeginWipe,
+    /// Echo the code shown after `BeginWipe`, the wipe is then confirmed on the device
+    ///
+    /// Firmwares that predate protocol version 9 reply with `Reply::Ok` instead of
+    /// `Reply::Wiped`.
+    #[cbor(n(24))]
+    ConfirmWipe {
+        #[cbor(n(0))]
+        code: String,
+    },
+    /// Check that the device is still there, answered right away with `Reply::Pong`
+    ///
+    /// Unlike `Ping` it never returns the reply of a pending request, and unlike `GetInfo` it
+    /// doesn't reach the handlers: the screen isn't touched and it doesn't reset the auto-lock.
+    #[cbor(n(25))]
+    Heartbeat,
+    /// Derive the seed of a Lightning node with BIP-85, after a confirmation on the device
+    #[cbor(n(26))]
+    DeriveNodeSeed {
+        #[cbor(n(0))]
+        index: u32,
+    },
+    /// Like `BeginSignPsbt`, for a payjoin proposal (BIP-78) built on the last payment signed
+    ///
+    /// The proposal is then sent with `SignPsbt`, and only the changes made by the receiver are
+    /// confirmed on the device.
+    #[cbor(n(27))]
+    BeginSignPayjoin,
+    /// Change which requests are answered without a confirmation, after a confirmation on the
+    /// device
+    #[cbor(n(28))]
+    SetConfirmationPolicy(#[cbor(n(0))] ConfirmationPolicy),
+    /// Ask for a random challenge to sign with `IdentifyHost`, valid for the current session
+    #[cbor(n(29))]
+    GetHostChallenge,
+    /// Identify the host with its static key, see `host`
+    ///
+    /// `signature` is the compact ECDSA signature of `host::challenge_message()`. Unknown keys are
+    /// paired after a confirmation on the device.
+    #[cbor(n(30))]
+    IdentifyHost {
+        #[cbor(n(0))]
+        #[cfg_attr(
+            feature = "emulator",
+            serde(
+                serialize_with = "serde_bytevec::serialize",
+                deserialize_with = "serde_bytevec::deserialize_array"
+            )
+        )]
+        pubkey: Box<ByteArray<33>>,
+        #[cbor(n(1))]
+        #[cfg_attr(
+            feature = "emulator",
+            serde(
+                serialize_with = "serde_bytevec::serialize",
+                deserialize_with = "serde_bytevec::deserialize_array"
+            )
+        )]
+        signature: Box<ByteArray<64>>,
+    },
+    /// Ask for the log lines kept in RAM, see `logs`
+    ///
+    /// Only answered when the logs are enabled from the settings menu, after a confirmation on the
+    /// device.
+    #[cbor(n(31))]
+    GetLogs,
+    /// Sign `message` with the key of the external address at `index`, see `bip322`
+    ///
+    /// Only single-sig P2WPKH and P2TR wallets are supported, the others reply with
+    /// `ErrorCode::UnsupportedDescriptor`.
+    #[cbor(n(32))]
+    SignMessage {
+        #[cbor(n(0))]
+        index: u32,
+        #[cbor(n(1))]
+        message: String,
+    },
+    /// Sign `message` with the key of the external address at `index` in the format of Bitcoin
+    /// Core's `signmessage`, see `signmessage`
+    ///
+    /// Only single-sig P2PKH and P2WPKH wallets are supported, the others reply with
+    /// `ErrorCode::UnsupportedDescriptor`.
+    #[cbor(n(33))]
+    SignMessageLegacy {
+        #[cbor(n(0))]
+        index: u32,
+        #[cbor(n(1))]
+        message: String,
+    },
+    /// Like `BeginSignPsbt`, for `count` transactions reviewed one after the other
+    ///
+    /// Each transaction is then sent with `SignPsbt` and answered with its signatures, without
+    /// going back to the idle screen in between. The batch is aborted as soon as one of them isn't
+    /// signed.
+    #[cbor(n(34))]
+    BeginSignPsbtBatch {
+        #[cbor(n(0))]
+        count: u32,
+    },
+    /// Like `BeginSignPsbt`, with signatures whose nonces are checked by the host, see
+    /// `anti_exfil`
+    ///
+    /// After the review the device replies to `SignPsbt` with `Reply::SignerCommitments`, and
+    /// signs once `host_data` is sent with `AntiExfilHostData`. Only ECDSA signatures are
+    /// supported, taproot wallets reply with `ErrorCode::UnsupportedDescriptor`.
+    #[cbor(n(35))]
+    BeginSignPsbtAntiExfil {
+        /// See `anti_exfil::host_commitment()`
+        #[cbor(n(0))]
+        #[cfg_attr(
+            feature = "emulator",
+            serde(
+                serialize_with = "serde_bytevec::serialize",
+                deserialize_with = "serde_bytevec::deserialize_array"
+            )
+        )]
+        host_commitment: Box<ByteArray<32>>,
+    },
+    /// Data committed to with `BeginSignPsbtAntiExfil`, answered with `Reply::SignedPsbt`
+    #[cbor(n(36))]
+    AntiExfilHostData(
+        #[cbor(n(0))]
+        #[cfg_attr(
+            feature = "emulator",
+            serde(
+                serialize_with = "serde_bytevec::serialize",
+                deserialize_with = "serde_bytevec::deserialize_array"
+            )
+        )]
+        Box<ByteArray<32>>,
+    ),
+    /// A part of the PSBT, sent before `SignPsbt` with the last one and answered with `Reply::Ok`
+    ///
+    /// Large PSBTs are split in parts of `psbt_stream::PART_LEN` bytes, which the device parses
+    /// as they are received without keeping the whole PSBT in memory (see `psbt_stream`).
+    #[cbor(n(37))]
+    #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+    SignPsbtPart(#[cbor(n(0))] ByteVec),
+    /// Like `BeginSignPsbt`, for a payjoin proposal (BIP-78) built by the wallet as the receiver
+    ///
+    /// `original` is the consensus-encoded transaction of the payer, signed or not. The proposal
+    /// sent next with `SignPsbt` is checked against it, see `psbt::payjoin_receipt`.
+    #[cbor(n(38))]
+    BeginSignPayjoinReceiver {
+        #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+        #[cbor(n(0))]
+        original: ByteVec,
+    },
+    /// Like `BeginSignPsbt`, for a proof of reserves (BIP-127) committing to `message`
+    ///
+    /// The PSBT sent next with `SignPsbt` must spend `psbt::reserves_commitment(message)` in its
+    /// first input, see `psbt::proof_of_reserves`.
+    #[cbor(n(39))]
+    BeginSignProofOfReserves {
+        #[cbor(n(0))]
+        message: String,
+    },
+    /// Add `address` to the address book after a confirmation on the device, see `address_book`
+    #[cbor(n(40))]
+    AddTrustedAddress {
+        #[cbor(n(0))]
+        address: String,
+    },
+    /// Like `SetDescriptor`, registering the descriptor with a name, see `wallet_policy`
+    ///
+    /// Answered with `Reply::WalletPolicy`. Needs protocol version 22.
+    #[cbor(n(41))]
+    RegisterWalletPolicy {
+        #[cbor(n(0))]
+        name: String,
+        #[cbor(n(1))]
+        variant: SetDescriptorVariant,
+        #[cbor(n(2))]
+        script_type: ScriptType,
+    },
+    /// Reference the registered policy by its HMAC, needed before signing in the current session
+    #[cbor(n(42))]
+    UseWalletPolicy {
+        #[cbor(n(0))]
+        #[cfg_attr(
+            feature = "emulator",
+            serde(
+                serialize_with = "serde_bytevec::serialize",
+                deserialize_with = "serde_bytevec::deserialize_array"
+            )
+        )]
+        hmac: Box<ByteArray<32>>,
+    },
 }
 
 #[derive(Clone, Debug, Encode, Decode)]
@@ -992,16 +1915,31 @@ pub enum Reply {
     Info(#[cbor(n(0))] DeviceInfo),
     #[cbor(n(1))]
     Ok,
+    /// A request failed, `code` says why
+    ///
+    /// `detail` is a description for logs and developers. `code` is `None` for firmwares that
+    /// predate error codes and for codes unknown to this version.
     #[cbor(n(2))]
-    Error(#[cbor(n(0))] String),
+    Error {
+        #[cbor(n(0))]
+        detail: String,
+        #[cbor(n(1))]
+        code: Option<ErrorCode>,
+    },
+    /// The address, and how it was derived (`None` when sent by firmwares that predate it)
     #[cbor(n(3))]
-    Address(#[cbor(n(0))] String),
+    Address(#[cbor(n(0))] String, #[cbor(n(1))] Option<AddressInfo>),
     #[cbor(n(4))]
     Descriptor {
         #[cbor(n(0))]
         external: String,
         #[cbor(n(1))]
         internal: Option<String>,
+        /// Both keychains in a single descriptor, see `multipath`
+        ///
+        /// `None` when sent by firmwares that predate it.
+        #[cbor(n(2))]
+        multipath: Option<String>,
     },
     #[cbor(n(5))]
     UnexpectedMessage,
@@ -1028,7 +1966,326 @@ pub enum Reply {
         xpub: String,
         #[cbor(n(1))]
         bsms: BsmsRound1,
+        /// `None` when sent by firmwares that predate it
+        #[cbor(n(2))]
+        key: Option<XpubKey>,
     },
+    /// Sent in place of a `Pong` while the device is busy with a long operation
+    #[cbor(n(15))]
+    Progress {
+        #[cbor(n(0))]
+        current: u32,
+        #[cbor(n(1))]
+        total: u32,
+    },
+    #[cbor(n(16))]
+    Attestation(#[cbor(n(0))] attestation::Attestation),
+    /// Serialized `backup::Backup`
+    #[cbor(n(17))]
+    #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+    Backup(#[cbor(n(0))] ByteVec),
+    #[cbor(n(18))]
+    SelfTest(#[cbor(n(0))] selftest::SelfTestReport),
+    /// See `bip85::node_seed`
+    #[cbor(n(19))]
+    #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+    NodeSeed(#[cbor(n(0))] ByteVec),
+    /// Sent after `Request::ConfirmWipe`, right before the device goes back to the
+    /// uninitialized state
+    #[cbor(n(20))]
+    Wiped(#[cbor(n(0))] WipeReport),
+    /// Challenge to sign to answer `Request::IdentifyHost`
+    #[cbor(n(21))]
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    HostChallenge(#[cbor(n(0))] Box<ByteArray<32>>),
+    /// Log lines kept by the device, oldest first
+    #[cbor(n(22))]
+    Logs(#[cbor(n(0))] String),
+    /// Signature of a message
+    ///
+    /// In the BIP-322 simple format (see `bip322::encode_simple()`) for `SignMessage`, and the 65
+    /// bytes of a legacy signature (see `signmessage::sign()`) for `SignMessageLegacy`.
+    #[cbor(n(23))]
+    MessageSignature {
+        #[cbor(n(0))]
+        address: String,
+        #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+        #[cbor(n(1))]
+        signature: ByteVec,
+    },
+    /// Nonces committed to for the signatures of a PSBT, see `Request::BeginSignPsbtAntiExfil`
+    #[cbor(n(24))]
+    SignerCommitments(#[cbor(n(0))] Vec<SignerCommitment>),
+    /// Consensus-encoded transaction, signed and ready to be broadcast
+    ///
+    /// Sent instead of `SignedPsbt` when finalizing is asked with `Request::SignPsbt` and every
+    /// input can be finalized.
+    #[cbor(n(25))]
+    #[cfg_attr(feature = "emulator", serde(with = "serde_bytevec"))]
+    SignedTransaction(#[cbor(n(0))] ByteVec),
+    /// HMAC of the policy registered with `Request::RegisterWalletPolicy`
+    #[cbor(n(26))]
+    #[cfg_attr(
+        feature = "emulator",
+        serde(
+            serialize_with = "serde_bytevec::serialize",
+            deserialize_with = "serde_bytevec::deserialize_array"
+        )
+    )]
+    WalletPolicy(#[cbor(n(0))] Box<ByteArray<32>>),
+}
+
+impl Reply {
+    /// Error with the default description of `code`
+    pub fn error(code: ErrorCode) -> Self {
+        Reply::Error {
+            detail: code.to_string(),
+            code: Some(code),
+        }
+    }
+
+    /// Error with a more specific description than the one of `code`
+    pub fn error_with_detail(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Reply::Error {
+            detail: detail.into(),
+            code: Some(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for Reply {
+    fn from(code: ErrorCode) -> Self {
+        Reply::error(code)
+    }
+}
+
+/// Machine-readable reason of a `Reply::Error`
+///
+/// Indices are never reused, new codes must be added at the end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    #[cbor(n(0))]
+    NotProvisioned,
+    #[cbor(n(1))]
+    AlreadyProvisioned,
+    #[cbor(n(2))]
+    InvalidAttestationKey,
+    #[cbor(n(3))]
+    AttestationFailed,
+    #[cbor(n(4))]
+    InvalidPsbt,
+    #[cbor(n(5))]
+    SigningFailed,
+    /// A key in the descriptor can't be parsed or derived
+    #[cbor(n(6))]
+    InvalidKey,
+    /// A key in the descriptor is for a different network than the wallet
+    #[cbor(n(7))]
+    WrongNetwork,
+    /// None of the keys in the descriptor belongs to this device
+    #[cbor(n(8))]
+    LocalKeyMissing,
+    #[cbor(n(9))]
+    UnsupportedDescriptor,
+    #[cbor(n(10))]
+    InvalidThreshold,
+    #[cbor(n(11))]
+    WalletCreationFailed,
+    #[cbor(n(12))]
+    BsmsAddressMismatch,
+    #[cbor(n(13))]
+    InvalidBackup,
+    #[cbor(n(14))]
+    UnsupportedBackupVersion,
+    #[cbor(n(15))]
+    WrongBackupPassword,
+    /// The backup was made with a different seed than the one on the device
+    #[cbor(n(16))]
+    BackupWalletMismatch,
+    /// The device is new but the backup only contains the descriptor
+    #[cbor(n(17))]
+    BackupMissingSeed,
+    #[cbor(n(18))]
+    InvalidSeed,
+    /// The backup decrypted correctly, but its contents don't match its fingerprint
+    #[cbor(n(19))]
+    BackupCorrupted,
+    #[cbor(n(20))]
+    FirmwareTooBig,
+    #[cbor(n(21))]
+    FirmwareTooOld,
+    /// The request was sent by a newer host, see `PROTOCOL_VERSION`
+    #[cbor(n(22))]
+    UnsupportedRequest,
+    /// The seed can't be exported because of `Request::SetSeedExport`
+    #[cbor(n(23))]
+    SeedExportDisabled,
+    /// The code sent with `Request::ConfirmWipe` doesn't match the one on the screen
+    #[cbor(n(24))]
+    WrongWipeCode,
+    /// `Request::BeginSignPayjoin` was sent, but no payment was signed since the device was
+    /// unlocked
+    #[cbor(n(25))]
+    NoPaymentToPayjoin,
+    /// Requests can only skip the confirmation on devices protected by a pair code
+    #[cbor(n(26))]
+    PairCodeRequired,
+    /// The operation was aborted because nobody touched the device for too long, see
+    /// `settings::OperationTimeout`
+    #[cbor(n(27))]
+    Timeout,
+    /// An input of the PSBT doesn't include the output it spends
+    #[cbor(n(28))]
+    MissingUtxo,
+    /// The amounts of the PSBT overflow or the outputs are worth more than the inputs
+    #[cbor(n(29))]
+    InvalidAmount,
+    /// An output of the PSBT doesn't have an address
+    #[cbor(n(30))]
+    NonStandardOutput,
+    /// The signature sent with `Request::IdentifyHost` doesn't answer the last challenge of the
+    /// session
+    #[cbor(n(31))]
+    InvalidHostSignature,
+    /// `host::MAX_TRUSTED_HOSTS` are already paired with the device
+    #[cbor(n(32))]
+    TooManyHosts,
+    /// The logs are disabled in the settings
+    #[cbor(n(33))]
+    LogsDisabled,
+    /// An input of the wallet asks for a sighash other than `SIGHASH_ALL`, which the settings
+    /// don't allow
+    #[cbor(n(34))]
+    NonDefaultSighash,
+    /// The `witness_utxo` of an input doesn't match the output spent in its `non_witness_utxo`
+    #[cbor(n(35))]
+    InconsistentUtxo,
+    /// The address isn't valid for the network of the wallet
+    #[cbor(n(36))]
+    InvalidAddress,
+    /// `address_book::MAX_TRUSTED_ADDRESSES` are already in the address book
+    #[cbor(n(37))]
+    TooManyAddresses,
+    /// The name of a wallet policy is empty, too long or has characters that can't be displayed
+    #[cbor(n(38))]
+    InvalidWalletName,
+    /// A wallet policy is registered, and it wasn't referenced with `Request::UseWalletPolicy` in
+    /// the current session, or the HMAC sent doesn't match it
+    #[cbor(n(39))]
+    WalletPolicyMismatch,
+}
+
+impl ErrorCode {
+    /// Every code, in the order of their indices
+    const ALL: &'static [ErrorCode] = &[
+        ErrorCode::NotProvisioned,
+        ErrorCode::AlreadyProvisioned,
+        ErrorCode::InvalidAttestationKey,
+        ErrorCode::AttestationFailed,
+        ErrorCode::InvalidPsbt,
+        ErrorCode::SigningFailed,
+        ErrorCode::InvalidKey,
+        ErrorCode::WrongNetwork,
+        ErrorCode::LocalKeyMissing,
+        ErrorCode::UnsupportedDescriptor,
+        ErrorCode::InvalidThreshold,
+        ErrorCode::WalletCreationFailed,
+        ErrorCode::BsmsAddressMismatch,
+        ErrorCode::InvalidBackup,
+        ErrorCode::UnsupportedBackupVersion,
+        ErrorCode::WrongBackupPassword,
+        ErrorCode::BackupWalletMismatch,
+        ErrorCode::BackupMissingSeed,
+        ErrorCode::InvalidSeed,
+        ErrorCode::BackupCorrupted,
+        ErrorCode::FirmwareTooBig,
+        ErrorCode::FirmwareTooOld,
+        ErrorCode::UnsupportedRequest,
+        ErrorCode::SeedExportDisabled,
+        ErrorCode::WrongWipeCode,
+        ErrorCode::NoPaymentToPayjoin,
+        ErrorCode::PairCodeRequired,
+        ErrorCode::Timeout,
+        ErrorCode::MissingUtxo,
+        ErrorCode::InvalidAmount,
+        ErrorCode::NonStandardOutput,
+        ErrorCode::InvalidHostSignature,
+        ErrorCode::TooManyHosts,
+        ErrorCode::LogsDisabled,
+        ErrorCode::NonDefaultSighash,
+        ErrorCode::InconsistentUtxo,
+        ErrorCode::InvalidAddress,
+        ErrorCode::TooManyAddresses,
+        ErrorCode::InvalidWalletName,
+        ErrorCode::WalletPolicyMismatch,
+    ];
+
+    /// Index of the code in the encoding, for hosts that can't use the enum directly
+    pub fn index(self) -> u32 {
+        ErrorCode::ALL
+            .iter()
+            .position(|c| *c == self)
+            .expect("Every code is in ALL") as u32
+    }
+
+    pub fn from_index(index: u32) -> Option<Self> {
+        ErrorCode::ALL.get(index as usize).copied()
+    }
+}
+
+impl core::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            ErrorCode::NotProvisioned => "Device not provisioned",
+            ErrorCode::AlreadyProvisioned => "Device already provisioned",
+            ErrorCode::InvalidAttestationKey => "Invalid attestation key",
+            ErrorCode::AttestationFailed => "Unable to attest",
+            ErrorCode::InvalidPsbt => "Invalid PSBT",
+            ErrorCode::SigningFailed => "Unable to sign",
+            ErrorCode::InvalidKey => "Invalid xpub",
+            ErrorCode::WrongNetwork => "Invalid key network",
+            ErrorCode::LocalKeyMissing => "Local key missing",
+            ErrorCode::UnsupportedDescriptor => "Unsupported descriptor",
+            ErrorCode::InvalidThreshold => "Invalid threshold for multisig",
+            ErrorCode::WalletCreationFailed => "Unable to create wallet",
+            ErrorCode::BsmsAddressMismatch => "BSMS address doesn't match",
+            ErrorCode::InvalidBackup => "Invalid backup encoding",
+            ErrorCode::UnsupportedBackupVersion => "Unsupported backup version",
+            ErrorCode::WrongBackupPassword => "Wrong backup password",
+            ErrorCode::BackupWalletMismatch => "Backup belongs to a different wallet",
+            ErrorCode::BackupMissingSeed => "Backup doesn't include the seed",
+            ErrorCode::InvalidSeed => "Invalid seed",
+            ErrorCode::BackupCorrupted => "Backup is corrupted",
+            ErrorCode::FirmwareTooBig => "Firmware file too big",
+            ErrorCode::FirmwareTooOld => "Firmware version too old",
+            ErrorCode::UnsupportedRequest => "Unsupported request",
+            ErrorCode::SeedExportDisabled => "Seed export is disabled",
+            ErrorCode::WrongWipeCode => "Wrong wipe code",
+            ErrorCode::NoPaymentToPayjoin => "No payment to payjoin",
+            ErrorCode::PairCodeRequired => "A pair code is required",
+            ErrorCode::Timeout => "Operation timed out",
+            ErrorCode::MissingUtxo => "Missing previous output of an input",
+            ErrorCode::InvalidAmount => "Invalid transaction amount",
+            ErrorCode::NonStandardOutput => "Non-standard output",
+            ErrorCode::InvalidHostSignature => "Invalid host signature",
+            ErrorCode::TooManyHosts => "Too many paired hosts",
+            ErrorCode::LogsDisabled => "Debug logs are disabled",
+            ErrorCode::NonDefaultSighash => "Non-default sighash",
+            ErrorCode::InconsistentUtxo => "Inconsistent UTXO",
+            ErrorCode::InvalidAddress => "Invalid address",
+            ErrorCode::TooManyAddresses => "Too many trusted addresses",
+            ErrorCode::InvalidWalletName => "Invalid wallet name",
+            ErrorCode::WalletPolicyMismatch => "Wallet policy doesn't match",
+        };
+        f.write_str(msg)
+    }
 }
 
 #[derive(Clone, Debug, Encode, Decode)]
@@ -1153,14 +2410,20 @@ pub enum MessageError {
     IncompleteMessage,
     PartialDeserialization,
     FailedDeserialization,
+    /// The message decrypted correctly, but it contains a variant this version doesn't know
+    UnsupportedMessage,
     DecryptionFailed,
     CardCouldntDecrypt,
     // FailedSerialization(ciborium::ser::Error<()>),
 }
 
 impl From<minicbor::decode::Error> for MessageError {
-    fn from(_: minicbor::decode::Error) -> Self {
-        MessageError::FailedDeserialization
+    fn from(e: minicbor::decode::Error) -> Self {
+        if e.is_unknown_variant() {
+            MessageError::UnsupportedMessage
+        } else {
+            MessageError::FailedDeserialization
+        }
     }
 }
 // impl From<ciborium::ser::Error<()>> for MessageError {
@@ -1182,6 +2445,31 @@ mod tests {
 
     // Model tests
 
+    #[test]
+    fn test_derived_keys_cache() {
+        use core::str::FromStr;
+
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let xprv =
+            bip32::ExtendedPrivKey::new_master(bitcoin::Network::Testnet, &[0x42; 32]).unwrap();
+        let account = bip32::DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let other = bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+
+        let cache = DerivedKeysCache::new(&secp, &xprv, &[account.clone()]).unwrap();
+        let data = minicbor::to_vec(&cache).unwrap();
+        let cache = minicbor::decode::<DerivedKeysCache>(&data).unwrap();
+
+        assert_eq!(
+            cache.account_key(&account),
+            Some(xprv.derive_priv(&secp, &account).unwrap())
+        );
+        assert_eq!(cache.account_key(&other), None);
+        assert!(cache.covers(&[account.clone()]));
+        assert!(!cache.covers(&[account, other]));
+        let fingerprint: bip32::Fingerprint = cache.master_fingerprint.into();
+        assert_eq!(fingerprint, xprv.fingerprint(&secp));
+    }
+
     // Message tests
 
     #[test]
@@ -1211,4 +2499,331 @@ mod tests {
         let frag3 = MessageFragment::from([0x01u8, 0x10].as_slice());
         assert!(message.push_fragment(frag3).is_err());
     }
+
+    #[test]
+    fn test_fragment_invalid_len() {
+        let f = MessageFragment::from([0x01u8, 0xFF].as_slice());
+        assert_eq!(f.len(), MAX_FRAGMENT_LEN - 2);
+
+        let mut message = Message::empty();
+        message.push_fragment(f).unwrap();
+        assert_eq!(message.len(), MAX_FRAGMENT_LEN - 2);
+    }
+
+    #[test]
+    fn test_get_fragments() {
+        assert!(Message::from_slice(&[]).get_fragments().is_empty());
+
+        let data = [0xAA; MAX_FRAGMENT_LEN - 2];
+        let fragments = Message::from_slice(&data).get_fragments();
+        assert_eq!(fragments.len(), 1);
+        assert!(fragments[0].is_eof());
+
+        let data = [0xAA; MAX_FRAGMENT_LEN - 1];
+        let fragments = Message::from_slice(&data).get_fragments();
+        assert_eq!(fragments.len(), 2);
+        assert!(!fragments[0].is_eof());
+        assert!(fragments[1].is_eof());
+        assert_eq!(fragments[1].len(), 1);
+    }
+
+    // Error tests
+
+    /// `Reply::Error` as it was encoded before error codes were added
+    #[derive(Encode, Decode)]
+    enum LegacyReply {
+        #[cbor(n(2))]
+        Error(#[cbor(n(0))] String),
+    }
+
+    #[test]
+    fn test_error_reply_from_legacy_firmware() {
+        let data = minicbor::to_vec(LegacyReply::Error("Local key missing".into())).unwrap();
+        match minicbor::decode::<Reply>(&data).unwrap() {
+            Reply::Error { detail, code } => {
+                assert_eq!(detail, "Local key missing");
+                assert_eq!(code, None);
+            }
+            r => panic!("Unexpected reply {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_error_reply_to_legacy_sdk() {
+        let data = minicbor::to_vec(Reply::error(ErrorCode::LocalKeyMissing)).unwrap();
+        let LegacyReply::Error(detail) = minicbor::decode(&data).unwrap();
+        assert_eq!(detail, "Local key missing");
+    }
+
+    #[test]
+    fn test_error_reply_unknown_code() {
+        #[derive(Encode)]
+        enum FutureErrorCode {
+            #[cbor(n(9999))]
+            SomethingNew,
+        }
+        #[derive(Encode)]
+        enum FutureReply {
+            #[cbor(n(2))]
+            Error(#[cbor(n(0))] String, #[cbor(n(1))] FutureErrorCode),
+        }
+
+        let data = minicbor::to_vec(FutureReply::Error(
+            "Something new".into(),
+            FutureErrorCode::SomethingNew,
+        ))
+        .unwrap();
+        match minicbor::decode::<Reply>(&data).unwrap() {
+            Reply::Error { detail, code } => {
+                assert_eq!(detail, "Something new");
+                assert_eq!(code, None);
+            }
+            r => panic!("Unexpected reply {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_error_code_index() {
+        for (i, code) in ErrorCode::ALL.iter().enumerate() {
+            let data = minicbor::to_vec(code).unwrap();
+            let mut decoder = minicbor::Decoder::new(&data);
+            decoder.array().unwrap();
+            assert_eq!(decoder.u32().unwrap(), i as u32);
+
+            assert_eq!(code.index(), i as u32);
+            assert_eq!(ErrorCode::from_index(i as u32), Some(*code));
+        }
+        assert_eq!(ErrorCode::from_index(ErrorCode::ALL.len() as u32), None);
+    }
+
+    // Versioning tests
+
+    /// `DeviceInfo` as it was encoded before `protocol_version` was added
+    #[derive(Encode, Decode)]
+    struct LegacyDeviceInfo {
+        #[cbor(n(0))]
+        initialized: InitializationStatus,
+        #[cbor(n(1))]
+        firmware_version: Option<String>,
+    }
+
+    #[test]
+    fn test_device_info_from_legacy_firmware() {
+        let data = minicbor::to_vec(LegacyDeviceInfo {
+            initialized: InitializationStatus::Uninitialized,
+            firmware_version: Some("0.2.0".into()),
+        })
+        .unwrap();
+
+        let info = minicbor::decode::<DeviceInfo>(&data).unwrap();
+        assert_eq!(info.firmware_version.as_deref(), Some("0.2.0"));
+        assert_eq!(info.protocol_version, None);
+        assert!(!info.has_capability(capabilities::FULL_SIGNED_PSBT));
+    }
+
+    #[test]
+    fn test_device_info_to_legacy_host() {
+        let data =
+            minicbor::to_vec(Reply::Info(DeviceInfo::new_locked_uninitialized("0.3.0"))).unwrap();
+
+        #[derive(Decode)]
+        enum LegacyReply {
+            #[cbor(n(0))]
+            Info(#[cbor(n(0))] LegacyDeviceInfo),
+        }
+        let LegacyReply::Info(info) = minicbor::decode(&data).unwrap();
+        assert_eq!(info.firmware_version.as_deref(), Some("0.3.0"));
+    }
+
+    #[test]
+    fn test_device_info_descriptor_summary() {
+        let path = SerializedDerivationPath {
+            value: alloc::vec![
+                HARDENED_FLAG | 48,
+                HARDENED_FLAG | 1,
+                HARDENED_FLAG,
+                HARDENED_FLAG | 2
+            ],
+        };
+        let descriptor = WalletDescriptor {
+            variant: DescriptorVariant::MultiSig {
+                threshold: 2,
+                keys: alloc::vec![
+                    MultisigKey::Local(path.clone()),
+                    MultisigKey::Local(path.clone()),
+                    MultisigKey::Local(path)
+                ],
+                is_sorted: true,
+            },
+            script_type: ScriptType::NativeSegwit,
+        };
+        let info = DeviceInfo::new_unlocked_initialized(
+            bitcoin::Network::Signet,
+            [0x42; 4],
+            &descriptor,
+            "0.3.0",
+        );
+
+        let data = minicbor::to_vec(&info).unwrap();
+        match minicbor::decode::<DeviceInfo>(&data).unwrap().initialized {
+            InitializationStatus::Initialized {
+                descriptor: Some(summary),
+                ..
+            } => {
+                assert!(matches!(summary.script_type, ScriptType::NativeSegwit));
+                assert_eq!((summary.threshold, summary.num_keys), (2, 3));
+                assert!(summary.is_sorted);
+            }
+            _ => panic!("Expected a descriptor summary"),
+        }
+    }
+
+    #[test]
+    fn test_tap_tree_summary() {
+        let path = SerializedDerivationPath {
+            value: alloc::vec![HARDENED_FLAG | 86, HARDENED_FLAG | 1, HARDENED_FLAG],
+        };
+        let descriptor = WalletDescriptor {
+            variant: DescriptorVariant::TapTree {
+                internal_key: None,
+                leaves: alloc::vec![
+                    TapLeaf {
+                        depth: 1,
+                        script: TapLeafScript::MultiA {
+                            threshold: 2,
+                            keys: alloc::vec![
+                                MultisigKey::Local(path.clone()),
+                                MultisigKey::Local(path.clone())
+                            ],
+                        },
+                    },
+                    TapLeaf {
+                        depth: 1,
+                        script: TapLeafScript::Key(MultisigKey::Local(path)),
+                    },
+                ],
+            },
+            script_type: ScriptType::Taproot,
+        };
+
+        let data = minicbor::to_vec(&descriptor).unwrap();
+        let descriptor = minicbor::decode::<WalletDescriptor>(&data).unwrap();
+        let summary = DescriptorSummary::from(&descriptor);
+        assert_eq!((summary.threshold, summary.num_keys), (1, 3));
+        assert_eq!(descriptor.variant.variant_name(), "Taproot tree");
+    }
+
+    #[test]
+    fn test_fold_tap_tree() {
+        fn shape(depths: &[u8]) -> Option<alloc::string::String> {
+            let leaves = depths
+                .iter()
+                .enumerate()
+                .map(|(i, depth)| TapLeaf {
+                    depth: *depth,
+                    script: TapLeafScript::Key(i),
+                })
+                .collect::<Vec<_>>();
+            TapLeaf::fold_tree(
+                &leaves,
+                |l| alloc::format!("{}", l.script.keys()[0]),
+                |a, b| alloc::format!("{{{},{}}}", a, b),
+            )
+        }
+
+        assert_eq!(shape(&[0]).as_deref(), Some("0"));
+        assert_eq!(shape(&[1, 1]).as_deref(), Some("{0,1}"));
+        assert_eq!(shape(&[1, 2, 2]).as_deref(), Some("{0,{1,2}}"));
+        assert_eq!(shape(&[2, 2, 1]).as_deref(), Some("{{0,1},2}"));
+        assert_eq!(shape(&[]), None);
+        assert_eq!(shape(&[1]), None);
+        assert_eq!(shape(&[0, 0]), None);
+        assert_eq!(shape(&[1, 1, 1]), None);
+        assert_eq!(shape(&[1, 2]), None);
+    }
+
+    #[test]
+    fn test_request_with_unknown_field() {
+        #[derive(Encode)]
+        enum FutureRequest {
+            #[cbor(n(5))]
+            SignPsbt(
+                #[cbor(n(0))] ByteVec,
+                #[cbor(n(1))] Option<bool>,
+                #[cbor(n(2))] Option<bool>,
+                #[cbor(n(3))] u32,
+            ),
+        }
+
+        let data = minicbor::to_vec(FutureRequest::SignPsbt(
+            vec![0x42; 4].into(),
+            None,
+            Some(true),
+            1234,
+        ))
+        .unwrap();
+        match minicbor::decode::<Request>(&data).unwrap() {
+            Request::SignPsbt(psbt, None, Some(true)) => assert_eq!(psbt.as_slice(), &[0x42; 4]),
+            r => panic!("Unexpected request {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_begin_sign_psbt_from_legacy_host() {
+        #[derive(Encode)]
+        enum LegacyRequest {
+            #[cbor(n(4))]
+            BeginSignPsbt,
+        }
+
+        let data = minicbor::to_vec(LegacyRequest::BeginSignPsbt).unwrap();
+        assert!(matches!(
+            minicbor::decode::<Request>(&data).unwrap(),
+            Request::BeginSignPsbt {
+                fiat_rate: None,
+                cpfp: None
+            }
+        ));
+    }
+
+    #[test]
+    fn test_fiat_rate() {
+        let rate = FiatRate {
+            rate: 50_000.0,
+            currency: "USD".into(),
+        };
+        assert!(rate.is_valid());
+        assert_eq!(
+            rate.format(bitcoin::Amount::from_sat(123_456)),
+            "~61.73 USD"
+        );
+
+        let invalid = |rate, currency: &str| {
+            !FiatRate {
+                rate,
+                currency: currency.into(),
+            }
+            .is_valid()
+        };
+        assert!(invalid(50_000.0, "usd"));
+        assert!(invalid(50_000.0, "USDT"));
+        assert!(invalid(0.0, "USD"));
+        assert!(invalid(f64::NAN, "USD"));
+    }
+
+    #[test]
+    fn test_request_unknown_variant() {
+        #[derive(Encode)]
+        enum FutureRequest {
+            #[cbor(n(9999))]
+            SomethingNew,
+        }
+
+        let data = minicbor::to_vec(FutureRequest::SomethingNew).unwrap();
+        let err = minicbor::decode::<Request>(&data).map_err(MessageError::from);
+        assert!(matches!(err, Err(MessageError::UnsupportedMessage)));
+
+        let err = minicbor::decode::<Request>(&[0xFF, 0x00]).map_err(MessageError::from);
+        assert!(matches!(err, Err(MessageError::FailedDeserialization)));
+    }
 }
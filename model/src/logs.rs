@@ -0,0 +1,143 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Log lines kept in RAM by the firmware, returned to the host with `Request::GetLogs`
+//!
+//! Lines are only kept while enabled from the settings menu (see `settings::DebugLogs`), and they
+//! are redacted before being stored: every run of 32 or more letters and digits, as long as keys,
+//! xpubs, hashes and addresses, is replaced by `<redacted>`, so that a secret logged by mistake
+//! never leaves the device.
+
+use alloc::string::String;
+
+/// Size of the buffer, the oldest lines are dropped once it's full
+pub const LOG_BUFFER_SIZE: usize = 2048;
+
+/// Shortest run of letters and digits that is redacted
+const MIN_REDACTED_LEN: usize = 32;
+const REDACTED: &[u8] = b"<redacted>";
+
+/// Ring buffer of log lines
+pub struct LogBuffer {
+    data: [u8; LOG_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        LogBuffer::new()
+    }
+}
+
+impl LogBuffer {
+    pub const fn new() -> Self {
+        LogBuffer {
+            data: [0; LOG_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            if self.len == LOG_BUFFER_SIZE {
+                self.data[self.start] = *byte;
+                self.start = (self.start + 1) % LOG_BUFFER_SIZE;
+            } else {
+                self.data[(self.start + self.len) % LOG_BUFFER_SIZE] = *byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Append a line, after redacting it
+    pub fn push_line(&mut self, line: &str) {
+        let mut bytes = line.as_bytes();
+        while !bytes.is_empty() {
+            let run = bytes
+                .iter()
+                .take_while(|b| b.is_ascii_alphanumeric())
+                .count();
+            match run {
+                0 if bytes[0] == b'\n' => self.push(b" "),
+                0 => self.push(&bytes[..1]),
+                run if run >= MIN_REDACTED_LEN => self.push(REDACTED),
+                run => self.push(&bytes[..run]),
+            }
+            bytes = &bytes[run.max(1)..];
+        }
+        self.push(b"\n");
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Lines in the buffer, oldest first
+    ///
+    /// Once the buffer is full the oldest line is usually cut, so it's left out.
+    pub fn contents(&self) -> String {
+        let mut bytes = alloc::vec::Vec::with_capacity(self.len);
+        bytes.extend_from_slice(
+            &self.data[self.start..(self.start + self.len).min(LOG_BUFFER_SIZE)],
+        );
+        bytes.extend_from_slice(
+            &self.data[..(self.start + self.len).saturating_sub(LOG_BUFFER_SIZE)],
+        );
+
+        let skip = match self.len {
+            LOG_BUFFER_SIZE => bytes.iter().position(|b| *b == b'\n').map_or(0, |p| p + 1),
+            _ => 0,
+        };
+        String::from_utf8_lossy(&bytes[skip..]).into_owned()
+    }
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let mut buffer = LogBuffer::new();
+        buffer.push_line("INFO handle_get_xpub");
+        buffer.push_line(&alloc::format!("DEBUG key {}\nend", "ab12".repeat(16)));
+
+        assert_eq!(
+            buffer.contents(),
+            "INFO handle_get_xpub\nDEBUG key <redacted> end\n"
+        );
+    }
+
+    #[test]
+    fn test_drop_oldest_lines() {
+        let mut buffer = LogBuffer::new();
+        for i in 0..1000 {
+            buffer.push_line(&alloc::format!("line {}", i));
+        }
+
+        let contents = buffer.contents();
+        assert!(contents.starts_with("line "));
+        assert!(contents.ends_with("line 999\n"));
+        assert!(contents.len() <= LOG_BUFFER_SIZE);
+
+        buffer.clear();
+        assert_eq!(buffer.contents(), "");
+    }
+}
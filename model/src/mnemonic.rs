@@ -0,0 +1,186 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Wordlists of the BIP-39 mnemonics
+//!
+//! Only the wordlists written in the latin alphabet are supported, since the display has no fonts
+//! for the other scripts. The wordlist is part of the seed: the same entropy gives a different
+//! seed in every language, so it's stored together with the entropy (see `Entropy`).
+//!
+//! The French and Spanish words contain accented letters, which BIP-39 stores decomposed (NFKD).
+//! They are composed again to be shown with the ISO-8859-1 fonts, and the ones typed by the user
+//! are decomposed before looking up the words, so both forms are accepted on import.
+
+use alloc::string::String;
+
+use minicbor::{Decode, Encode};
+
+pub use bip39::Mnemonic;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub enum MnemonicLanguage {
+    #[default]
+    #[cbor(n(0))]
+    English,
+    #[cbor(n(1))]
+    Spanish,
+    #[cbor(n(2))]
+    French,
+    #[cbor(n(3))]
+    Italian,
+    #[cbor(n(4))]
+    Czech,
+}
+
+impl MnemonicLanguage {
+    pub const ALL: &'static [Self] = &[
+        MnemonicLanguage::English,
+        MnemonicLanguage::Spanish,
+        MnemonicLanguage::French,
+        MnemonicLanguage::Italian,
+        MnemonicLanguage::Czech,
+    ];
+
+    pub fn wordlist(&self) -> bip39::Language {
+        match self {
+            MnemonicLanguage::English => bip39::Language::English,
+            MnemonicLanguage::Spanish => bip39::Language::Spanish,
+            MnemonicLanguage::French => bip39::Language::French,
+            MnemonicLanguage::Italian => bip39::Language::Italian,
+            MnemonicLanguage::Czech => bip39::Language::Czech,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MnemonicLanguage::English => "English",
+            MnemonicLanguage::Spanish => "Spanish",
+            MnemonicLanguage::French => "French",
+            MnemonicLanguage::Italian => "Italian",
+            MnemonicLanguage::Czech => "Czech",
+        }
+    }
+}
+
+/// Accented letters used by the supported wordlists, with their base letter and combining mark
+const ACCENTED: &[(char, char, char)] = &[
+    ('á', 'a', '\u{301}'),
+    ('é', 'e', '\u{301}'),
+    ('í', 'i', '\u{301}'),
+    ('ó', 'o', '\u{301}'),
+    ('ú', 'u', '\u{301}'),
+    ('è', 'e', '\u{300}'),
+    ('ñ', 'n', '\u{303}'),
+];
+
+fn decompose(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match ACCENTED.iter().find(|(composed, _, _)| *composed == c) {
+            Some((_, base, mark)) => {
+                result.push(*base);
+                result.push(*mark);
+            }
+            None => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Compose the accented letters of a word, so that it can be drawn with an ISO-8859-1 font
+pub fn compose(word: &str) -> String {
+    let mut result = String::with_capacity(word.len());
+    for c in word.chars() {
+        let composed = result.chars().last().and_then(|base| {
+            ACCENTED
+                .iter()
+                .find(|(_, b, mark)| *b == base && *mark == c)
+                .map(|(composed, _, _)| *composed)
+        });
+        match composed {
+            Some(composed) => {
+                result.pop();
+                result.push(composed);
+            }
+            None => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Parse a mnemonic written with any of the supported wordlists
+///
+/// The case of the words is ignored. A few words appear in more than one wordlist, so each one is
+/// tried in turn until the checksum matches.
+pub fn parse(mnemonic: &str) -> Result<(Mnemonic, MnemonicLanguage), bip39::Error> {
+    let normalized = decompose(&mnemonic.to_lowercase());
+
+    let mut first_error = None;
+    for language in MnemonicLanguage::ALL {
+        match Mnemonic::parse_in_normalized(language.wordlist(), &normalized) {
+            Ok(mnemonic) => return Ok((mnemonic, *language)),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    Err(first_error.expect("At least one language"))
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    const ENTROPY: [u8; 16] = [0x42; 16];
+
+    #[test]
+    fn test_parse_every_language() {
+        for language in MnemonicLanguage::ALL {
+            let mnemonic = Mnemonic::from_entropy_in(language.wordlist(), &ENTROPY).unwrap();
+            let (parsed, detected) = parse(&mnemonic.to_string()).unwrap();
+
+            assert_eq!(detected, *language);
+            let (entropy, len) = parsed.to_entropy_array();
+            assert_eq!(&entropy[..len], &ENTROPY);
+        }
+    }
+
+    #[test]
+    fn test_parse_composed_accents() {
+        assert_eq!(compose("a\u{301}baco"), "ábaco");
+        assert_eq!(decompose("ábaco"), "a\u{301}baco");
+
+        let mnemonic = Mnemonic::from_entropy_in(bip39::Language::Spanish, &ENTROPY).unwrap();
+        let composed = mnemonic.word_iter().map(compose).collect::<Vec<_>>();
+        assert!(composed
+            .iter()
+            .all(|w| w.chars().all(|c| (c as u32) < 0x100)));
+
+        let (parsed, detected) = parse(&composed.join(" ").to_uppercase()).unwrap();
+        assert_eq!(detected, MnemonicLanguage::Spanish);
+        assert_eq!(parsed, mnemonic);
+    }
+
+    #[test]
+    fn test_parse_invalid_checksum() {
+        assert!(parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon").is_err());
+    }
+}
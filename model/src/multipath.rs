@@ -0,0 +1,216 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Multipath descriptors (BIP-389)
+//!
+//! The device keeps the descriptors of both keychains, which only differ in the step after the
+//! keys: `/0/*` for the external one and `/1/*` for the internal one. A multipath descriptor
+//! describes both with a single string, writing that step as `/<0;1>/*`.
+
+use alloc::string::String;
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// The only multipath step supported, with the external and internal keychains
+const KEYCHAINS_STEP: &str = "/<0;1>/*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultipathError {
+    /// The checksum after the `#` doesn't match the descriptor
+    InvalidChecksum,
+    /// A multipath step other than `/<0;1>/*`
+    UnsupportedStep,
+}
+
+impl core::fmt::Display for MultipathError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MultipathError::InvalidChecksum => f.write_str("Invalid descriptor checksum"),
+            MultipathError::UnsupportedStep => {
+                f.write_str("Only `/<0;1>/*` is supported as multipath step")
+            }
+        }
+    }
+}
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    for (bit, generator) in [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        if c0 & (1 << bit) > 0 {
+            c ^= generator;
+        }
+    }
+
+    c
+}
+
+/// Checksum of a descriptor (BIP-380), `None` if it has characters that descriptors can't have
+pub fn checksum(descriptor: &str) -> Option<String> {
+    let (mut c, mut cls, mut clscount) = (1, 0, 0);
+    for ch in descriptor.chars() {
+        let pos = INPUT_CHARSET.find(ch)? as u64;
+        c = poly_mod(c, pos & 31);
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    (0..8).for_each(|_| c = poly_mod(c, 0));
+    c ^= 1;
+
+    Some(
+        (0..8)
+            .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+/// Combine the descriptors of the two keychains into a multipath descriptor, with its checksum
+///
+/// `None` if the descriptors differ in anything else than the `/0/*` and `/1/*` steps.
+pub fn combine(external: &str, internal: &str) -> Option<String> {
+    let external = external.split('#').next()?;
+    let internal = internal.split('#').next()?;
+    if external.len() != internal.len() {
+        return None;
+    }
+
+    let mut combined = String::with_capacity(external.len() + 16);
+    for (i, (e, c)) in external.bytes().zip(internal.bytes()).enumerate() {
+        if e == c {
+            combined.push(e as char);
+        } else if (e, c) == (b'0', b'1')
+            && external[..i].ends_with('/')
+            && external[i + 1..].starts_with("/*")
+        {
+            combined.push_str("<0;1>");
+        } else {
+            return None;
+        }
+    }
+
+    let checksum = checksum(&combined)?;
+    combined.push('#');
+    combined.push_str(&checksum);
+    Some(combined)
+}
+
+/// Replace the `/<0;1>/*` steps of a multipath descriptor with `/*`, dropping its checksum
+///
+/// Keys are set on the device without the keychain step, which is added when deriving each
+/// keychain. Descriptors without multipath steps are returned as they are.
+pub fn strip_keychains(descriptor: &str) -> Result<String, MultipathError> {
+    if !descriptor.contains('<') {
+        return Ok(descriptor.into());
+    }
+
+    let (descriptor, expected) = match descriptor.split_once('#') {
+        Some((descriptor, expected)) => (descriptor, Some(expected)),
+        None => (descriptor, None),
+    };
+    if expected.is_some_and(|expected| checksum(descriptor).as_deref() != Some(expected)) {
+        return Err(MultipathError::InvalidChecksum);
+    }
+
+    let stripped = descriptor.replace(KEYCHAINS_STEP, "/*");
+    if stripped.contains('<') {
+        return Err(MultipathError::UnsupportedStep);
+    }
+
+    Ok(stripped)
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    const EXTERNAL: &str = "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/0/*)#2ag6nxcd";
+    const INTERNAL: &str = "wpkh([73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/1/*)#mfdmwng4";
+
+    #[test]
+    fn test_checksum() {
+        let (descriptor, expected) = EXTERNAL.split_once('#').unwrap();
+        assert_eq!(checksum(descriptor).as_deref(), Some(expected));
+        let (descriptor, expected) = INTERNAL.split_once('#').unwrap();
+        assert_eq!(checksum(descriptor).as_deref(), Some(expected));
+
+        assert_eq!(checksum("wpkh(\u{e9})"), None);
+    }
+
+    #[test]
+    fn test_combine() {
+        let combined = combine(EXTERNAL, INTERNAL).unwrap();
+        let (descriptor, expected) = combined.split_once('#').unwrap();
+        assert!(descriptor.ends_with("tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M/<0;1>/*)"));
+        assert_eq!(checksum(descriptor).as_deref(), Some(expected));
+
+        assert_eq!(combine(EXTERNAL, EXTERNAL).as_deref(), Some(EXTERNAL));
+        assert_eq!(combine(INTERNAL, EXTERNAL), None);
+        assert_eq!(combine(EXTERNAL, &INTERNAL.replace("/84'", "/49'")), None);
+    }
+
+    #[test]
+    fn test_strip_keychains() {
+        let combined = combine(EXTERNAL, INTERNAL).unwrap();
+        let stripped = strip_keychains(&combined).unwrap();
+        assert_eq!(
+            stripped,
+            EXTERNAL.split('#').next().unwrap().replace("/0/*", "/*")
+        );
+        assert_eq!(
+            strip_keychains(combined.split('#').next().unwrap()),
+            Ok(stripped)
+        );
+        assert_eq!(strip_keychains(EXTERNAL).as_deref(), Ok(EXTERNAL));
+
+        let mut corrupted = combined.clone();
+        corrupted.pop();
+        corrupted.push('x');
+        assert_eq!(
+            strip_keychains(&corrupted),
+            Err(MultipathError::InvalidChecksum)
+        );
+        assert_eq!(
+            strip_keychains(
+                &combined
+                    .split('#')
+                    .next()
+                    .unwrap()
+                    .replace("<0;1>", "<1;2>")
+            ),
+            Err(MultipathError::UnsupportedStep)
+        );
+    }
+}
@@ -0,0 +1,1012 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! MuSig2 signing (BIP-327) for the participants of the PSBT fields of BIP-373
+//!
+//! A MuSig2 key aggregates the keys of several participants into a single taproot key, which can
+//! be used as the internal key of an output or in the leaves of its script tree. Signing takes two
+//! rounds:
+//!
+//! 1. every participant generates a nonce with `nonce_gen()` and adds its `PubNonce` to the PSBT;
+//! 2. once all the nonces are in the PSBT, every participant `sign()`s with its secret nonce and
+//!    adds its partial signature, which are then combined with `aggregate_partial_sigs()`.
+//!
+//! A secret nonce must never be used for two signatures, otherwise the key can be computed from
+//! them: `SecNonce` can't be copied and `sign()` consumes it.
+
+use alloc::vec::Vec;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{
+    self, schnorr, Message, Parity, PublicKey, Scalar, Secp256k1, SecretKey, Verification,
+    XOnlyPublicKey,
+};
+use bitcoin::util::psbt::{self, raw};
+use bitcoin::util::taproot::{TapBranchHash, TapLeafHash, TapTweakHash};
+use bitcoin::TxOut;
+
+/// Participants of an aggregate key: the key is followed by the keys of the participants
+pub const PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS: u8 = 0x1A;
+/// Public nonce of a participant, for an aggregate key and optionally a leaf
+pub const PSBT_IN_MUSIG2_PUB_NONCE: u8 = 0x1B;
+/// Partial signature of a participant, for an aggregate key and optionally a leaf
+pub const PSBT_IN_MUSIG2_PARTIAL_SIG: u8 = 0x1C;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Musig2Error {
+    InvalidKeys,
+    InvalidTweak,
+    InvalidNonce,
+    InvalidPartialSignature,
+    KeyMismatch,
+}
+
+impl core::fmt::Display for Musig2Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            Musig2Error::InvalidKeys => "Invalid participant keys",
+            Musig2Error::InvalidTweak => "Invalid tweak",
+            Musig2Error::InvalidNonce => "Invalid nonce",
+            Musig2Error::InvalidPartialSignature => "Invalid partial signature",
+            Musig2Error::KeyMismatch => "The key is not a participant",
+        };
+        f.write_str(msg)
+    }
+}
+#[cfg(not(feature = "stm32"))]
+impl std::error::Error for Musig2Error {}
+
+fn tagged_hash(tag: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for part in parts {
+        engine.input(part);
+    }
+
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+// Scalars modulo the order of the curve, with `None` for zero since it can't be a `SecretKey`
+
+fn scalar_from_hash(hash: [u8; 32]) -> SecretKey {
+    // Hashes above the order of the curve or equal to zero are so unlikely that they are simply
+    // rejected
+    SecretKey::from_slice(&hash).expect("Negligible probability")
+}
+
+fn scalar_add(a: Option<SecretKey>, b: Option<SecretKey>) -> Option<SecretKey> {
+    match (a, b) {
+        (Some(a), Some(b)) => a.add_tweak(&Scalar::from(b)).ok(),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn scalar_mul(a: Option<SecretKey>, b: Option<SecretKey>) -> Option<SecretKey> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.mul_tweak(&Scalar::from(b)).expect("Non-zero factors")),
+        _ => None,
+    }
+}
+
+fn scalar_negate_if(a: Option<SecretKey>, negate: bool) -> Option<SecretKey> {
+    match negate {
+        true => a.map(SecretKey::negate),
+        false => a,
+    }
+}
+
+fn scalar_bytes(a: Option<SecretKey>) -> [u8; 32] {
+    a.map(|a| a.secret_bytes()).unwrap_or([0; 32])
+}
+
+fn has_odd_y(point: &PublicKey) -> bool {
+    point.x_only_public_key().1 == Parity::Odd
+}
+
+fn xbytes(point: &PublicKey) -> [u8; 32] {
+    point.x_only_public_key().0.serialize()
+}
+
+/// Aggregate key of a set of participants, with the tweaks applied to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyAggContext {
+    keys: Vec<PublicKey>,
+    list_hash: [u8; 32],
+    second_key: Option<PublicKey>,
+    aggregate: PublicKey,
+    /// Whether the tweaks negated the aggregate key (`gacc` of BIP-327)
+    negated: bool,
+    /// Sum of the tweaks (`tacc` of BIP-327)
+    tweak: Option<SecretKey>,
+}
+
+impl KeyAggContext {
+    /// Aggregate `keys`, in the order given
+    pub fn new<C: Verification>(
+        secp: &Secp256k1<C>,
+        keys: &[PublicKey],
+    ) -> Result<Self, Musig2Error> {
+        let first_key = *keys.first().ok_or(Musig2Error::InvalidKeys)?;
+        let serialized: Vec<_> = keys.iter().map(|k| k.serialize()).collect();
+        let parts: Vec<&[u8]> = serialized.iter().map(|k| &k[..]).collect();
+        let list_hash = tagged_hash(b"KeyAgg list", &parts);
+        let second_key = keys.iter().find(|k| **k != first_key).copied();
+
+        let mut ctx = KeyAggContext {
+            keys: keys.to_vec(),
+            list_hash,
+            second_key,
+            aggregate: first_key,
+            negated: false,
+            tweak: None,
+        };
+        let points = keys
+            .iter()
+            .map(|k| {
+                k.mul_tweak(secp, &Scalar::from(ctx.coefficient(k)))
+                    .map_err(|_| Musig2Error::InvalidKeys)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let points: Vec<_> = points.iter().collect();
+        ctx.aggregate = PublicKey::combine_keys(&points).map_err(|_| Musig2Error::InvalidKeys)?;
+
+        Ok(ctx)
+    }
+
+    fn coefficient(&self, key: &PublicKey) -> SecretKey {
+        if Some(*key) == self.second_key {
+            return secp256k1::ONE_KEY;
+        }
+        scalar_from_hash(tagged_hash(
+            b"KeyAgg coefficient",
+            &[&self.list_hash, &key.serialize()],
+        ))
+    }
+
+    /// Keys of the participants
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    /// Aggregate key, with the tweaks applied so far
+    pub fn aggregate_key(&self) -> PublicKey {
+        self.aggregate
+    }
+
+    /// Add `tweak` to the aggregate key taken as an x-only key, as done by taproot
+    pub fn apply_xonly_tweak<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        tweak: &Scalar,
+    ) -> Result<(), Musig2Error> {
+        let odd = has_odd_y(&self.aggregate);
+        let aggregate = match odd {
+            true => self.aggregate.negate(secp),
+            false => self.aggregate,
+        };
+        self.aggregate = aggregate
+            .add_exp_tweak(secp, tweak)
+            .map_err(|_| Musig2Error::InvalidTweak)?;
+        self.negated ^= odd;
+        self.tweak = scalar_add(
+            SecretKey::from_slice(&tweak.to_be_bytes()).ok(),
+            scalar_negate_if(self.tweak, odd),
+        );
+
+        Ok(())
+    }
+
+    /// Tweak the aggregate key into the output key of a taproot output
+    pub fn apply_tap_tweak<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        merkle_root: Option<TapBranchHash>,
+    ) -> Result<(), Musig2Error> {
+        let internal_key = self.aggregate.x_only_public_key().0;
+        let tweak = TapTweakHash::from_key_and_tweak(internal_key, merkle_root).to_scalar();
+        self.apply_xonly_tweak(secp, &tweak)
+    }
+}
+
+/// Public nonce of a participant, or the aggregate of all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PubNonce {
+    r1: PublicKey,
+    r2: PublicKey,
+}
+
+impl PubNonce {
+    pub fn serialize(&self) -> [u8; 66] {
+        let mut data = [0; 66];
+        data[..33].copy_from_slice(&self.r1.serialize());
+        data[33..].copy_from_slice(&self.r2.serialize());
+        data
+    }
+
+    pub fn from_slice(data: &[u8]) -> Result<Self, Musig2Error> {
+        if data.len() != 66 {
+            return Err(Musig2Error::InvalidNonce);
+        }
+
+        Ok(PubNonce {
+            r1: PublicKey::from_slice(&data[..33]).map_err(|_| Musig2Error::InvalidNonce)?,
+            r2: PublicKey::from_slice(&data[33..]).map_err(|_| Musig2Error::InvalidNonce)?,
+        })
+    }
+}
+
+/// Secret nonce of a participant, which can only be used for one signature
+pub struct SecNonce {
+    k1: SecretKey,
+    k2: SecretKey,
+    pubkey: PublicKey,
+}
+
+impl core::fmt::Debug for SecNonce {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SecNonce")
+            .field("pubkey", &self.pubkey)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Partial signature of a participant
+pub type PartialSignature = [u8; 32];
+
+/// Generate the nonces of `key` for the signature of `message` with `aggregate_key`
+///
+/// `rand` must be fresh random bytes for every call.
+pub fn nonce_gen<C: secp256k1::Signing>(
+    secp: &Secp256k1<C>,
+    rand: &[u8; 32],
+    key: &SecretKey,
+    aggregate_key: &XOnlyPublicKey,
+    message: &Message,
+) -> (SecNonce, PubNonce) {
+    let mut rand = tagged_hash(b"MuSig/aux", &[rand]);
+    rand.iter_mut()
+        .zip(key.secret_bytes())
+        .for_each(|(r, k)| *r ^= k);
+
+    let pubkey = key.public_key(secp);
+    let nonce = |i: u8| {
+        let hash = tagged_hash(
+            b"MuSig/nonce",
+            &[
+                &rand,
+                &[33],
+                &pubkey.serialize(),
+                &[32],
+                &aggregate_key.serialize(),
+                &[1],
+                &32u64.to_be_bytes(),
+                message.as_ref(),
+                &0u32.to_be_bytes(),
+                &[i],
+            ],
+        );
+        scalar_from_hash(hash)
+    };
+    let (k1, k2) = (nonce(0), nonce(1));
+
+    let pubnonce = PubNonce {
+        r1: PublicKey::from_secret_key(secp, &k1),
+        r2: PublicKey::from_secret_key(secp, &k2),
+    };
+    (SecNonce { k1, k2, pubkey }, pubnonce)
+}
+
+/// Combine the public nonces of all the participants
+///
+/// A sum at infinity is only possible if the nonces were chosen to cancel each other out, so it's
+/// rejected rather than encoded as BIP-327 does.
+pub fn aggregate_nonces(nonces: &[PubNonce]) -> Result<PubNonce, Musig2Error> {
+    let r1: Vec<_> = nonces.iter().map(|n| &n.r1).collect();
+    let r2: Vec<_> = nonces.iter().map(|n| &n.r2).collect();
+
+    Ok(PubNonce {
+        r1: PublicKey::combine_keys(&r1).map_err(|_| Musig2Error::InvalidNonce)?,
+        r2: PublicKey::combine_keys(&r2).map_err(|_| Musig2Error::InvalidNonce)?,
+    })
+}
+
+/// Final nonce `R`, nonce coefficient `b` and challenge `e` of a signature
+fn session_values<C: Verification>(
+    secp: &Secp256k1<C>,
+    key_agg: &KeyAggContext,
+    aggregate_nonce: &PubNonce,
+    message: &Message,
+) -> Result<(PublicKey, SecretKey, SecretKey), Musig2Error> {
+    let aggregate_key = xbytes(&key_agg.aggregate);
+    let b = scalar_from_hash(tagged_hash(
+        b"MuSig/noncecoef",
+        &[
+            &aggregate_nonce.serialize(),
+            &aggregate_key,
+            message.as_ref(),
+        ],
+    ));
+    let r = aggregate_nonce
+        .r2
+        .mul_tweak(secp, &Scalar::from(b))
+        .and_then(|r2| aggregate_nonce.r1.combine(&r2))
+        .map_err(|_| Musig2Error::InvalidNonce)?;
+    let e = scalar_from_hash(tagged_hash(
+        b"BIP0340/challenge",
+        &[&xbytes(&r), &aggregate_key, message.as_ref()],
+    ));
+
+    Ok((r, b, e))
+}
+
+/// Sign `message` as the participant with `key`, using the nonce generated for it
+pub fn sign<C: secp256k1::Signing + Verification>(
+    secp: &Secp256k1<C>,
+    secnonce: SecNonce,
+    key: &SecretKey,
+    key_agg: &KeyAggContext,
+    aggregate_nonce: &PubNonce,
+    message: &Message,
+) -> Result<PartialSignature, Musig2Error> {
+    let pubkey = key.public_key(secp);
+    if pubkey != secnonce.pubkey || !key_agg.keys.contains(&pubkey) {
+        return Err(Musig2Error::KeyMismatch);
+    }
+
+    let (r, b, e) = session_values(secp, key_agg, aggregate_nonce, message)?;
+    let k1 = scalar_negate_if(Some(secnonce.k1), has_odd_y(&r));
+    let k2 = scalar_negate_if(Some(secnonce.k2), has_odd_y(&r));
+    let d = scalar_negate_if(Some(*key), has_odd_y(&key_agg.aggregate) ^ key_agg.negated);
+    let a = Some(key_agg.coefficient(&pubkey));
+
+    let s = scalar_add(
+        scalar_add(k1, scalar_mul(Some(b), k2)),
+        scalar_mul(Some(e), scalar_mul(a, d)),
+    );
+    let partial_sig = scalar_bytes(s);
+
+    // Make sure a fault didn't produce a signature that could leak the key
+    let pubnonce = PubNonce {
+        r1: PublicKey::from_secret_key(secp, &secnonce.k1),
+        r2: PublicKey::from_secret_key(secp, &secnonce.k2),
+    };
+    verify_partial_sig(
+        secp,
+        &partial_sig,
+        &pubnonce,
+        &pubkey,
+        key_agg,
+        aggregate_nonce,
+        message,
+    )?;
+
+    Ok(partial_sig)
+}
+
+/// Check the partial signature of the participant with `pubkey` and `pubnonce`
+pub fn verify_partial_sig<C: secp256k1::Signing + Verification>(
+    secp: &Secp256k1<C>,
+    partial_sig: &PartialSignature,
+    pubnonce: &PubNonce,
+    pubkey: &PublicKey,
+    key_agg: &KeyAggContext,
+    aggregate_nonce: &PubNonce,
+    message: &Message,
+) -> Result<(), Musig2Error> {
+    if !key_agg.keys.contains(pubkey) {
+        return Err(Musig2Error::KeyMismatch);
+    }
+    let s = SecretKey::from_slice(partial_sig).map_err(|_| Musig2Error::InvalidPartialSignature)?;
+
+    let (r, b, e) = session_values(secp, key_agg, aggregate_nonce, message)?;
+    let nonce = pubnonce
+        .r2
+        .mul_tweak(secp, &Scalar::from(b))
+        .and_then(|r2| pubnonce.r1.combine(&r2))
+        .map_err(|_| Musig2Error::InvalidNonce)?;
+    let nonce = match has_odd_y(&r) {
+        true => nonce.negate(secp),
+        false => nonce,
+    };
+    let ead = scalar_negate_if(
+        scalar_mul(Some(e), Some(key_agg.coefficient(pubkey))),
+        has_odd_y(&key_agg.aggregate) ^ key_agg.negated,
+    )
+    .ok_or(Musig2Error::InvalidPartialSignature)?;
+
+    let expected = pubkey
+        .mul_tweak(secp, &Scalar::from(ead))
+        .and_then(|key| nonce.combine(&key))
+        .map_err(|_| Musig2Error::InvalidPartialSignature)?;
+    match PublicKey::from_secret_key(secp, &s) == expected {
+        true => Ok(()),
+        false => Err(Musig2Error::InvalidPartialSignature),
+    }
+}
+
+/// Combine the partial signatures of all the participants into a BIP-340 signature
+pub fn aggregate_partial_sigs<C: Verification>(
+    secp: &Secp256k1<C>,
+    key_agg: &KeyAggContext,
+    aggregate_nonce: &PubNonce,
+    message: &Message,
+    partial_sigs: &[PartialSignature],
+) -> Result<schnorr::Signature, Musig2Error> {
+    let (r, _, e) = session_values(secp, key_agg, aggregate_nonce, message)?;
+
+    let mut s = scalar_mul(
+        Some(e),
+        scalar_negate_if(key_agg.tweak, has_odd_y(&key_agg.aggregate)),
+    );
+    for partial_sig in partial_sigs {
+        let partial_sig = match partial_sig.iter().all(|b| *b == 0) {
+            true => None,
+            false => Some(
+                SecretKey::from_slice(partial_sig)
+                    .map_err(|_| Musig2Error::InvalidPartialSignature)?,
+            ),
+        };
+        s = scalar_add(s, partial_sig);
+    }
+
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&xbytes(&r));
+    signature[32..].copy_from_slice(&scalar_bytes(s));
+    schnorr::Signature::from_slice(&signature).map_err(|_| Musig2Error::InvalidPartialSignature)
+}
+
+/// Key of the nonce or partial signature field of `participant`
+pub fn field_key(
+    type_value: u8,
+    participant: &PublicKey,
+    aggregate_key: &PublicKey,
+    leaf_hash: Option<TapLeafHash>,
+) -> raw::Key {
+    let mut key = Vec::with_capacity(98);
+    key.extend_from_slice(&participant.serialize());
+    key.extend_from_slice(&aggregate_key.serialize());
+    if let Some(leaf_hash) = leaf_hash {
+        key.extend_from_slice(&leaf_hash[..]);
+    }
+
+    raw::Key { type_value, key }
+}
+
+/// Aggregate keys of an input, each with the keys of its participants
+///
+/// Malformed fields are ignored.
+pub fn participants(input: &psbt::Input) -> Vec<(PublicKey, Vec<PublicKey>)> {
+    input
+        .unknown
+        .iter()
+        .filter(|(key, _)| key.type_value == PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS)
+        .filter_map(|(key, value)| {
+            let aggregate_key = PublicKey::from_slice(&key.key).ok()?;
+            if value.is_empty() || value.len() % 33 != 0 {
+                return None;
+            }
+            let keys = value
+                .chunks(33)
+                .map(PublicKey::from_slice)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+
+            Some((aggregate_key, keys))
+        })
+        .collect()
+}
+
+/// Public nonce of `participant` in `input`, if present and valid
+pub fn pub_nonce(
+    input: &psbt::Input,
+    participant: &PublicKey,
+    aggregate_key: &PublicKey,
+    leaf_hash: Option<TapLeafHash>,
+) -> Option<PubNonce> {
+    let key = field_key(
+        PSBT_IN_MUSIG2_PUB_NONCE,
+        participant,
+        aggregate_key,
+        leaf_hash,
+    );
+    input
+        .unknown
+        .get(&key)
+        .and_then(|value| PubNonce::from_slice(value).ok())
+}
+
+/// Whether `input` has a partial signature of `participant`
+pub fn has_partial_sig(
+    input: &psbt::Input,
+    participant: &PublicKey,
+    aggregate_key: &PublicKey,
+    leaf_hash: Option<TapLeafHash>,
+) -> bool {
+    let key = field_key(
+        PSBT_IN_MUSIG2_PARTIAL_SIG,
+        participant,
+        aggregate_key,
+        leaf_hash,
+    );
+    input.unknown.contains_key(&key)
+}
+
+/// Whether `key` is the key of a nonce or partial signature field
+pub fn is_signing_field(key: &raw::Key) -> bool {
+    key.type_value == PSBT_IN_MUSIG2_PUB_NONCE || key.type_value == PSBT_IN_MUSIG2_PARTIAL_SIG
+}
+
+/// Whether the nonce or partial signature field `key` with `value` belongs to a participant
+/// listed in `input`
+pub fn is_known_field(input: &psbt::Input, key: &raw::Key, value: &[u8]) -> bool {
+    let expected_len = match key.type_value {
+        PSBT_IN_MUSIG2_PUB_NONCE => 66,
+        PSBT_IN_MUSIG2_PARTIAL_SIG => 32,
+        _ => return false,
+    };
+    if value.len() != expected_len || (key.key.len() != 66 && key.key.len() != 98) {
+        return false;
+    }
+
+    match (
+        PublicKey::from_slice(&key.key[..33]),
+        PublicKey::from_slice(&key.key[33..66]),
+    ) {
+        (Ok(participant), Ok(aggregate_key)) => participants(input)
+            .iter()
+            .any(|(k, keys)| *k == aggregate_key && keys.contains(&participant)),
+        _ => false,
+    }
+}
+
+/// How an input can be spent with an aggregate key
+#[derive(Debug, Clone)]
+pub struct SpendPath {
+    /// Leaf containing the aggregate key, or `None` for the key path
+    pub leaf_hash: Option<TapLeafHash>,
+    /// Aggregate key, tweaked into the output key for the key path
+    pub key_agg: KeyAggContext,
+}
+
+/// Ways `utxo` can be spent with `aggregate_key`, made of the keys in `participants`
+///
+/// The key path is used if the aggregate key is the internal key and its tweak gives the output
+/// key of `utxo`. The leaves are those found by `taproot::signable_leaves` for the aggregate key.
+pub fn spend_paths<C: Verification>(
+    secp: &Secp256k1<C>,
+    input: &psbt::Input,
+    utxo: &TxOut,
+    aggregate_key: &PublicKey,
+    participants: &[PublicKey],
+) -> Vec<SpendPath> {
+    let key_agg = match KeyAggContext::new(secp, participants) {
+        Ok(key_agg) if key_agg.aggregate_key() == *aggregate_key => key_agg,
+        _ => return Vec::new(),
+    };
+    let xonly = aggregate_key.x_only_public_key().0;
+
+    let mut paths = Vec::new();
+    if input.tap_internal_key == Some(xonly) && utxo.script_pubkey.is_v1_p2tr() {
+        let mut tweaked = key_agg.clone();
+        if tweaked.apply_tap_tweak(secp, input.tap_merkle_root).is_ok()
+            && xbytes(&tweaked.aggregate_key())[..] == utxo.script_pubkey[2..]
+        {
+            paths.push(SpendPath {
+                leaf_hash: None,
+                key_agg: tweaked,
+            });
+        }
+    }
+    for (leaf_hash, _) in crate::taproot::signable_leaves(secp, input, utxo, &xonly) {
+        paths.push(SpendPath {
+            leaf_hash: Some(leaf_hash),
+            key_agg: key_agg.clone(),
+        });
+    }
+
+    paths
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use core::str::FromStr;
+
+    use bitcoin::blockdata::script::Script;
+
+    use super::*;
+
+    fn keys(secp: &Secp256k1<secp256k1::All>) -> Vec<(SecretKey, PublicKey)> {
+        [0x11, 0x22, 0x33]
+            .into_iter()
+            .map(|b| {
+                let key = SecretKey::from_slice(&[b; 32]).unwrap();
+                (key, key.public_key(secp))
+            })
+            .collect()
+    }
+
+    fn sign_all(
+        secp: &Secp256k1<secp256k1::All>,
+        keys: &[(SecretKey, PublicKey)],
+        key_agg: &KeyAggContext,
+        message: &Message,
+    ) -> schnorr::Signature {
+        let aggregate_key = key_agg.aggregate_key().x_only_public_key().0;
+        let (secnonces, pubnonces): (Vec<_>, Vec<_>) = keys
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| nonce_gen(secp, &[i as u8; 32], key, &aggregate_key, message))
+            .unzip();
+        let aggregate_nonce = aggregate_nonces(&pubnonces).unwrap();
+
+        let partial_sigs: Vec<_> = keys
+            .iter()
+            .zip(secnonces)
+            .map(|((key, _), secnonce)| {
+                sign(secp, secnonce, key, key_agg, &aggregate_nonce, message).unwrap()
+            })
+            .collect();
+        aggregate_partial_sigs(secp, key_agg, &aggregate_nonce, message, &partial_sigs).unwrap()
+    }
+
+    #[test]
+    fn test_key_agg_vectors() {
+        // From the key aggregation test vectors of BIP-327
+        let secp = Secp256k1::verification_only();
+        let keys: Vec<_> = [
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        ]
+        .into_iter()
+        .map(|k| PublicKey::from_str(k).unwrap())
+        .collect();
+
+        for (indices, expected) in [
+            (
+                &[0, 1, 2][..],
+                "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c",
+            ),
+            (
+                &[2, 1, 0][..],
+                "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b",
+            ),
+            (
+                &[0, 0, 0][..],
+                "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935",
+            ),
+            (
+                &[0, 0, 1, 1][..],
+                "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e",
+            ),
+        ] {
+            let keys: Vec<_> = indices.iter().map(|i| keys[*i]).collect();
+            let key_agg = KeyAggContext::new(&secp, &keys).unwrap();
+            assert_eq!(
+                key_agg.aggregate_key().x_only_public_key().0.to_string(),
+                expected
+            );
+        }
+    }
+
+    fn pubkey(hex: &str) -> PublicKey {
+        PublicKey::from_str(hex).unwrap()
+    }
+
+    fn pubnonce(hex: &str) -> PubNonce {
+        use bitcoin::hashes::hex::FromHex;
+
+        PubNonce::from_slice(&Vec::<u8>::from_hex(hex).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_nonce_agg_vectors() {
+        use bitcoin::hashes::hex::FromHex;
+
+        // From the nonce aggregation test vectors of BIP-327
+        let pnonces = [
+            "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E66603BA47FBC1834437B3212E89A84D8425E7BF12E0245D98262268EBDCB385D50641",
+            "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833",
+            "020151C80F435648DF67A22B749CD798CE54E0321D034B92B709B567D60A42E6660279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "03FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60379BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+        ]
+        .map(pubnonce);
+
+        assert_eq!(
+            aggregate_nonces(&pnonces[..2]).unwrap(),
+            pubnonce("035FE1873B4F2967F52FEA4A06AD5A8ECCBE9D0FD73068012C894E2E87CCB5804B024725377345BDE0E9C33AF3C43C0A29A9249F2F2956FA8CFEB55C8573D0262DC8")
+        );
+        // The second halves sum to infinity, which the vectors encode with zeros
+        assert_eq!(
+            aggregate_nonces(&pnonces[2..]),
+            Err(Musig2Error::InvalidNonce)
+        );
+        // Wrong tag in the first half
+        let invalid = Vec::<u8>::from_hex("04FF406FFD8ADB9CD29877E4985014F66A59F6CD01C0E88CAA8E5F3166B1F676A60248C264CDD57D3C24D79990B0F865674EB62A0F9018277A95011B41BFC193B833").unwrap();
+        assert_eq!(
+            PubNonce::from_slice(&invalid),
+            Err(Musig2Error::InvalidNonce)
+        );
+    }
+
+    #[test]
+    fn test_sign_verify_vectors() {
+        use bitcoin::hashes::hex::{FromHex, ToHex};
+
+        // From the signing and partial signature verification test vectors of BIP-327, the ones
+        // with a 32-byte message
+        let secp = Secp256k1::new();
+        let key =
+            SecretKey::from_str("7FB9E0E687ADA1EEBF7ECFE2F21E73EBDB51A7D450948DFE8D76D7F2D1007671")
+                .unwrap();
+        let pubkeys = [
+            "03935F972DA013F80AE011890FA89B67A27B7BE6CCB24D3274D18B2D4067F261A9",
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA661",
+        ]
+        .map(pubkey);
+        let secnonce = || SecNonce {
+            k1: SecretKey::from_str(
+                "508B81A611F100A6B2B6B29656590898AF488BCF2E1F55CF22E5CFB84421FE61",
+            )
+            .unwrap(),
+            k2: SecretKey::from_str(
+                "FA27FD49B1D50085B481285E1CA205D55C82CC1B31FF5CD54A489829355901F7",
+            )
+            .unwrap(),
+            pubkey: pubkeys[0],
+        };
+        let pnonces = [
+            "0337C87821AFD50A8644D820A8F3E02E499C931865C2360FB43D0A0D20DAFE07EA0287BF891D2A6DEAEBADC909352AA9405D1428C15F4B75F04DAE642A95C2548480",
+            "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F817980279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "032DE2662628C90B03F5E720284EB52FF7D71F4284F627B68A853D78C78E1FFE9303E4C5524E83FFE1493B9077CF1CA6BEB2090C93D930321071AD40B2F44E599046",
+        ]
+        .map(pubnonce);
+        let aggnonce = aggregate_nonces(&pnonces).unwrap();
+        assert_eq!(
+            aggnonce,
+            pubnonce("028465FCF0BBDBCF443AABCCE533D42B4B5A10966AC09A49655E8C42DAAB8FCD61037496A3CC86926D452CAFCFD55D25972CA1675D549310DE296BFF42F72EEEA8C9")
+        );
+        let message = Message::from_slice(
+            &Vec::<u8>::from_hex(
+                "F95466D086770E689964664219266FE5ED215C92AE20BAB5C9D79ADDDDF3C0CF",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(key.public_key(&secp), pubkeys[0]);
+
+        for (indices, expected) in [
+            (
+                [0, 1, 2],
+                "012ABBCB52B3016AC03AD82395A1A415C48B93DEF78718E62A7A90052FE224FB",
+            ),
+            (
+                [1, 0, 2],
+                "9FF2F7AAA856150CC8819254218D3ADEEB0535269051897724F9DB3789513A52",
+            ),
+            (
+                [1, 2, 0],
+                "FA23C359F6FAC4E7796BB93BC9F0532A95468C539BA20FF86D7C76ED92227900",
+            ),
+        ] {
+            let keys = indices.map(|i| pubkeys[i]);
+            let key_agg = KeyAggContext::new(&secp, &keys).unwrap();
+
+            let partial_sig = sign(&secp, secnonce(), &key, &key_agg, &aggnonce, &message).unwrap();
+            assert_eq!(partial_sig.to_hex(), expected.to_lowercase());
+            assert_eq!(
+                verify_partial_sig(
+                    &secp,
+                    &partial_sig,
+                    &pnonces[0],
+                    &pubkeys[0],
+                    &key_agg,
+                    &aggnonce,
+                    &message
+                ),
+                Ok(())
+            );
+        }
+
+        // The failing cases: the negation of a valid signature, a valid signature checked for
+        // another signer and a signature that exceeds the order of the curve
+        let key_agg = KeyAggContext::new(&secp, &pubkeys).unwrap();
+        let partial_sig = sign(&secp, secnonce(), &key, &key_agg, &aggnonce, &message).unwrap();
+        let negated = SecretKey::from_slice(&partial_sig)
+            .unwrap()
+            .negate()
+            .secret_bytes();
+        let mut above_order = [0; 32];
+        above_order.copy_from_slice(
+            &Vec::<u8>::from_hex(
+                "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141",
+            )
+            .unwrap(),
+        );
+        for (partial_sig, signer) in [(negated, 0), (partial_sig, 1), (above_order, 0)] {
+            assert_eq!(
+                verify_partial_sig(
+                    &secp,
+                    &partial_sig,
+                    &pnonces[signer],
+                    &pubkeys[signer],
+                    &key_agg,
+                    &aggnonce,
+                    &message
+                ),
+                Err(Musig2Error::InvalidPartialSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_nonce_gen() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp);
+        let aggregate_key = keys[1].1.x_only_public_key().0;
+        let message = Message::from_slice(&[0x01; 32]).unwrap();
+
+        // The nonces depend on the randomness, the key and the message they are generated for
+        let (secnonce, pubnonce) = nonce_gen(&secp, &[0; 32], &keys[0].0, &aggregate_key, &message);
+        assert_eq!(secnonce.pubkey, keys[0].1);
+        assert_eq!(pubnonce.r1, PublicKey::from_secret_key(&secp, &secnonce.k1));
+        for (rand, key, message) in [
+            ([1; 32], &keys[0].0, message),
+            ([0; 32], &keys[2].0, message),
+            (
+                [0; 32],
+                &keys[0].0,
+                Message::from_slice(&[0x02; 32]).unwrap(),
+            ),
+        ] {
+            let (_, other) = nonce_gen(&secp, &rand, key, &aggregate_key, &message);
+            assert_ne!(other, pubnonce);
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp);
+        let pubkeys: Vec<_> = keys.iter().map(|(_, pk)| *pk).collect();
+        let message = Message::from_slice(&[0x01; 32]).unwrap();
+
+        let key_agg = KeyAggContext::new(&secp, &pubkeys).unwrap();
+        let signature = sign_all(&secp, &keys, &key_agg, &message);
+        assert!(secp
+            .verify_schnorr(
+                &signature,
+                &message,
+                &key_agg.aggregate_key().x_only_public_key().0
+            )
+            .is_ok());
+
+        // The order of the keys matters
+        let mut reversed = pubkeys.clone();
+        reversed.reverse();
+        assert_ne!(
+            KeyAggContext::new(&secp, &reversed)
+                .unwrap()
+                .aggregate_key(),
+            key_agg.aggregate_key()
+        );
+    }
+
+    #[test]
+    fn test_sign_tweaked() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp);
+        let pubkeys: Vec<_> = keys.iter().map(|(_, pk)| *pk).collect();
+        let message = Message::from_slice(&[0x02; 32]).unwrap();
+
+        let mut key_agg = KeyAggContext::new(&secp, &pubkeys).unwrap();
+        let internal_key = key_agg.aggregate_key().x_only_public_key().0;
+        key_agg.apply_tap_tweak(&secp, None).unwrap();
+
+        let signature = sign_all(&secp, &keys, &key_agg, &message);
+        let output_key = key_agg.aggregate_key().x_only_public_key().0;
+        assert!(secp
+            .verify_schnorr(&signature, &message, &output_key)
+            .is_ok());
+        assert_eq!(
+            Script::new_v1_p2tr(&secp, internal_key, None),
+            Script::new_v1_p2tr_tweaked(
+                bitcoin::util::schnorr::TweakedPublicKey::dangerous_assume_tweaked(output_key)
+            )
+        );
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp);
+        let pubkeys: Vec<_> = keys.iter().map(|(_, pk)| *pk).collect();
+        let message = Message::from_slice(&[0x03; 32]).unwrap();
+        let key_agg = KeyAggContext::new(&secp, &pubkeys[..2]).unwrap();
+        let aggregate_key = key_agg.aggregate_key().x_only_public_key().0;
+
+        let (secnonce, pubnonce) = nonce_gen(&secp, &[0; 32], &keys[2].0, &aggregate_key, &message);
+        assert_eq!(
+            sign(&secp, secnonce, &keys[2].0, &key_agg, &pubnonce, &message),
+            Err(Musig2Error::KeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_psbt_fields() {
+        let secp = Secp256k1::new();
+        let keys = keys(&secp);
+        let pubkeys: Vec<_> = keys.iter().map(|(_, pk)| *pk).collect();
+        let mut key_agg = KeyAggContext::new(&secp, &pubkeys).unwrap();
+        let aggregate_key = key_agg.aggregate_key();
+
+        let mut input = psbt::Input::default();
+        input.unknown.insert(
+            raw::Key {
+                type_value: PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS,
+                key: aggregate_key.serialize().to_vec(),
+            },
+            pubkeys.iter().flat_map(|pk| pk.serialize()).collect(),
+        );
+        input.tap_internal_key = Some(aggregate_key.x_only_public_key().0);
+        assert_eq!(participants(&input), vec![(aggregate_key, pubkeys.clone())]);
+
+        let (_, pubnonce) = nonce_gen(
+            &secp,
+            &[0; 32],
+            &keys[0].0,
+            &aggregate_key.x_only_public_key().0,
+            &Message::from_slice(&[0x04; 32]).unwrap(),
+        );
+        let nonce_key = field_key(PSBT_IN_MUSIG2_PUB_NONCE, &pubkeys[0], &aggregate_key, None);
+        assert!(is_known_field(&input, &nonce_key, &pubnonce.serialize()));
+        assert!(!is_known_field(&input, &nonce_key, &[0; 32]));
+        input
+            .unknown
+            .insert(nonce_key, pubnonce.serialize().to_vec());
+        assert_eq!(
+            pub_nonce(&input, &pubkeys[0], &aggregate_key, None),
+            Some(pubnonce)
+        );
+        assert_eq!(pub_nonce(&input, &pubkeys[1], &aggregate_key, None), None);
+
+        // Only the key path whose tweak matches the output is found
+        key_agg.apply_tap_tweak(&secp, None).unwrap();
+        let output_key = key_agg.aggregate_key().x_only_public_key().0;
+        let utxo = TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v1_p2tr_tweaked(
+                bitcoin::util::schnorr::TweakedPublicKey::dangerous_assume_tweaked(output_key),
+            ),
+        };
+        let paths = spend_paths(&secp, &input, &utxo, &aggregate_key, &pubkeys);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].leaf_hash, None);
+        assert_eq!(paths[0].key_agg, key_agg);
+
+        assert!(spend_paths(&secp, &input, &utxo, &aggregate_key, &pubkeys[..2]).is_empty());
+        let other = TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v1_p2tr(&secp, output_key, None),
+        };
+        assert!(spend_paths(&secp, &input, &other, &aggregate_key, &pubkeys).is_empty());
+    }
+}
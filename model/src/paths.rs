@@ -0,0 +1,209 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Standard derivation paths of the accounts
+//!
+//! Wallets look for funds at the paths of BIP-44, 49, 84 and 86 for single-sig and of BIP-45, 48
+//! and 87 for multisig. Keys derived anywhere else are valid, but the funds can be hard to find
+//! again when restoring the seed in a different wallet.
+
+use alloc::vec::Vec;
+
+use bitcoin::util::bip32::DerivationPath;
+use bitcoin::Network;
+
+use crate::ScriptType;
+
+const HARDENED_FLAG: u32 = 0x8000_0000;
+
+/// Purpose and script type number (BIP-48) of the standard templates
+fn templates(script_type: Option<&ScriptType>, multisig: bool) -> Vec<(u32, Option<u32>)> {
+    let single_sig = |script_type: &ScriptType| match script_type {
+        ScriptType::Legacy => 44,
+        ScriptType::WrappedSegwit => 49,
+        ScriptType::NativeSegwit => 84,
+        ScriptType::Taproot => 86,
+    };
+    let bip48 = |script_type: &ScriptType| match script_type {
+        ScriptType::WrappedSegwit => Some(1),
+        ScriptType::NativeSegwit => Some(2),
+        ScriptType::Legacy | ScriptType::Taproot => None,
+    };
+
+    match (script_type, multisig) {
+        (Some(script_type), false) => alloc::vec![(single_sig(script_type), None)],
+        (Some(script_type), true) => {
+            let mut templates = alloc::vec![(87, None)];
+            if let Some(n) = bip48(script_type) {
+                templates.push((48, Some(n)));
+            }
+            templates
+        }
+        (None, _) => alloc::vec![
+            (44, None),
+            (49, None),
+            (84, None),
+            (86, None),
+            (87, None),
+            (48, Some(1)),
+            (48, Some(2)),
+        ],
+    }
+}
+
+/// Whether `path` starts with the account of one of the standard templates for `script_type`
+///
+/// Any script type is accepted when `script_type` is `None`, like when exporting an xpub.
+/// Unhardened steps after the account are ignored.
+pub fn is_standard_path(
+    path: &DerivationPath,
+    network: Network,
+    script_type: Option<&ScriptType>,
+    multisig: bool,
+) -> bool {
+    let hardened = path
+        .into_iter()
+        .take_while(|c| c.is_hardened())
+        .map(|c| u32::from(*c) & !HARDENED_FLAG)
+        .collect::<Vec<_>>();
+    let coin = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+
+    // BIP-45 only has the purpose, followed by the unhardened index of the cosigner
+    let bip45 = matches!(
+        (script_type, multisig),
+        (None, _) | (Some(ScriptType::Legacy), true)
+    );
+    if bip45 && hardened == [45] {
+        return true;
+    }
+
+    templates(script_type, multisig)
+        .into_iter()
+        .any(|(purpose, script)| match (hardened.as_slice(), script) {
+            ([p, c, _], None) => *p == purpose && *c == coin,
+            ([p, c, _, s], Some(script)) => *p == purpose && *c == coin && *s == script,
+            _ => false,
+        })
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    fn path(s: &str) -> DerivationPath {
+        DerivationPath::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_single_sig_paths() {
+        let native = Some(&ScriptType::NativeSegwit);
+
+        assert!(is_standard_path(
+            &path("m/84'/0'/0'"),
+            Network::Bitcoin,
+            native,
+            false
+        ));
+        assert!(is_standard_path(
+            &path("m/84'/1'/3'/0/5"),
+            Network::Testnet,
+            native,
+            false
+        ));
+        // Wrong coin type for the network
+        assert!(!is_standard_path(
+            &path("m/84'/1'/0'"),
+            Network::Bitcoin,
+            native,
+            false
+        ));
+        // Wrong purpose for the script type
+        assert!(!is_standard_path(
+            &path("m/44'/0'/0'"),
+            Network::Bitcoin,
+            native,
+            false
+        ));
+        assert!(!is_standard_path(
+            &path("m/84'/0'"),
+            Network::Bitcoin,
+            native,
+            false
+        ));
+        assert!(is_standard_path(
+            &path("m/86'/0'/0'"),
+            Network::Bitcoin,
+            Some(&ScriptType::Taproot),
+            false
+        ));
+    }
+
+    #[test]
+    fn test_multisig_paths() {
+        assert!(is_standard_path(
+            &path("m/48'/1'/0'/2'"),
+            Network::Signet,
+            Some(&ScriptType::NativeSegwit),
+            true
+        ));
+        assert!(!is_standard_path(
+            &path("m/48'/1'/0'/1'"),
+            Network::Signet,
+            Some(&ScriptType::NativeSegwit),
+            true
+        ));
+        assert!(is_standard_path(
+            &path("m/87'/0'/0'"),
+            Network::Bitcoin,
+            Some(&ScriptType::NativeSegwit),
+            true
+        ));
+        assert!(is_standard_path(
+            &path("m/45'"),
+            Network::Bitcoin,
+            Some(&ScriptType::Legacy),
+            true
+        ));
+    }
+
+    #[test]
+    fn test_any_script_type() {
+        assert!(is_standard_path(
+            &path("m/48'/1'/0'/2'"),
+            Network::Testnet,
+            None,
+            false
+        ));
+        assert!(is_standard_path(
+            &path("m/49'/0'/7'"),
+            Network::Bitcoin,
+            None,
+            false
+        ));
+        assert!(!is_standard_path(
+            &path("m/0'/0'/0'"),
+            Network::Bitcoin,
+            None,
+            false
+        ));
+    }
+}
@@ -0,0 +1,1695 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks performed by the device on a PSBT before asking the user to sign it
+//!
+//! The PSBT comes straight from the host, so none of these functions should ever panic.
+//!
+//! Proprietary and unknown fields are never interpreted, with the exception of the output labels
+//! in the `portal` namespace (see `output_label()`), which are only shown to the user. They don't
+//! affect the checks made here nor the signatures, and they are left untouched in the PSBT
+//! returned by the SDK (see `sig_diff`).
+
+use alloc::vec::Vec;
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::secp256k1::Message;
+use bitcoin::util::address::AddressType;
+use bitcoin::util::bip32::{ChildNumber, Fingerprint};
+use bitcoin::util::psbt::{self, PartiallySignedTransaction};
+use bitcoin::util::sighash::SighashCache;
+use bitcoin::{
+    Address, EcdsaSighashType, Network, OutPoint, PublicKey, Script, Transaction, TxIn, TxOut,
+};
+
+use crate::ErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsbtError {
+    InvalidEncoding,
+    InvalidNonWitnessUtxo,
+    MissingUtxo,
+    InvalidAmount,
+    NonStandardOutput,
+    PayjoinMismatch,
+    NonDefaultSighash,
+    InconsistentUtxo,
+    ReservesMismatch,
+    CpfpParentMismatch,
+}
+
+impl core::fmt::Display for PsbtError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            PsbtError::InvalidEncoding => "Invalid PSBT encoding",
+            PsbtError::InvalidNonWitnessUtxo => "Invalid non_witness_utxo",
+            PsbtError::MissingUtxo => "Missing NonWitnessUtxo",
+            PsbtError::InvalidAmount => "Invalid amount",
+            PsbtError::NonStandardOutput => "Non-standard output",
+            PsbtError::PayjoinMismatch => "Payjoin doesn't match the original payment",
+            PsbtError::NonDefaultSighash => "Non-default sighash",
+            PsbtError::InconsistentUtxo => "witness_utxo doesn't match non_witness_utxo",
+            PsbtError::ReservesMismatch => "Not a proof of reserves for this message",
+            PsbtError::CpfpParentMismatch => "No input spends the parent of the CPFP",
+        };
+        f.write_str(msg)
+    }
+}
+#[cfg(not(feature = "stm32"))]
+impl std::error::Error for PsbtError {}
+
+impl PsbtError {
+    /// Code sent to the host when the PSBT is rejected
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            PsbtError::MissingUtxo => ErrorCode::MissingUtxo,
+            PsbtError::InvalidAmount => ErrorCode::InvalidAmount,
+            PsbtError::NonStandardOutput => ErrorCode::NonStandardOutput,
+            PsbtError::NonDefaultSighash => ErrorCode::NonDefaultSighash,
+            PsbtError::InconsistentUtxo => ErrorCode::InconsistentUtxo,
+            PsbtError::InvalidEncoding
+            | PsbtError::InvalidNonWitnessUtxo
+            | PsbtError::PayjoinMismatch
+            | PsbtError::ReservesMismatch
+            | PsbtError::CpfpParentMismatch => ErrorCode::InvalidPsbt,
+        }
+    }
+}
+
+/// Parse a PSBT, either version 0 or 2 (see `psbt_v2`)
+pub fn parse_psbt(data: &[u8]) -> Result<PartiallySignedTransaction, PsbtError> {
+    if crate::psbt_v2::is_v2(data) {
+        let data = crate::psbt_v2::to_v0(data)?;
+        return bitcoin::consensus::encode::deserialize(&data)
+            .map_err(|_| PsbtError::InvalidEncoding);
+    }
+
+    bitcoin::consensus::encode::deserialize(data).map_err(|_| PsbtError::InvalidEncoding)
+}
+
+/// Return the outputs spent by every input of the transaction
+///
+/// `witness_utxo` is only trusted if `allow_witness_utxo` is set, which should only be the case
+/// for taproot wallets. When an input has both, they must agree: the amount signed for a segwit v0
+/// input comes from `witness_utxo`, so a different one would make the fees shown to the user wrong.
+pub fn prev_utxos(
+    psbt: &PartiallySignedTransaction,
+    allow_witness_utxo: bool,
+) -> Result<Vec<&TxOut>, PsbtError> {
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(psbt.inputs.iter())
+        .map(|(txin, input)| prev_utxo(txin, input, allow_witness_utxo))
+        .collect()
+}
+
+/// Output spent by a single input, see `prev_utxos`
+fn prev_utxo<'a>(
+    txin: &TxIn,
+    input: &'a psbt::Input,
+    allow_witness_utxo: bool,
+) -> Result<&'a TxOut, PsbtError> {
+    if let Some(prev_tx) = &input.non_witness_utxo {
+        if prev_tx.txid() != txin.previous_output.txid {
+            return Err(PsbtError::InvalidNonWitnessUtxo);
+        }
+        let utxo = prev_tx
+            .output
+            .get(txin.previous_output.vout as usize)
+            .ok_or(PsbtError::InvalidNonWitnessUtxo)?;
+        match &input.witness_utxo {
+            Some(witness_utxo) if witness_utxo != utxo => Err(PsbtError::InconsistentUtxo),
+            _ => Ok(utxo),
+        }
+    } else {
+        match &input.witness_utxo {
+            Some(utxo) if allow_witness_utxo => Ok(utxo),
+            _ => Err(PsbtError::MissingUtxo),
+        }
+    }
+}
+
+/// Message signed with ECDSA by `pubkey` in input `index`, spending `utxo`
+///
+/// Only for legacy and segwit v0 scripts. Returns `None` for a P2WSH without its witness script.
+pub fn ecdsa_sighash(
+    cache: &mut SighashCache<&Transaction>,
+    index: usize,
+    utxo: &TxOut,
+    input: &psbt::Input,
+    pubkey: &PublicKey,
+    sighash_type: EcdsaSighashType,
+) -> Option<Message> {
+    let script_pubkey = &utxo.script_pubkey;
+    let inner_script = match &input.redeem_script {
+        Some(redeem_script) if script_pubkey.is_p2sh() => redeem_script,
+        _ => script_pubkey,
+    };
+
+    let sighash = if inner_script.is_v0_p2wpkh() {
+        let script_code = Script::new_p2pkh(&pubkey.pubkey_hash());
+        cache.segwit_signature_hash(index, &script_code, utxo.value, sighash_type)
+    } else if inner_script.is_v0_p2wsh() {
+        let witness_script = input.witness_script.as_ref()?;
+        cache.segwit_signature_hash(index, witness_script, utxo.value, sighash_type)
+    } else {
+        cache.legacy_signature_hash(index, inner_script, sighash_type.to_u32())
+    };
+
+    sighash
+        .ok()
+        .and_then(|sighash| Message::from_slice(&sighash[..]).ok())
+}
+
+/// Compute the fees paid by the transaction
+pub fn fees(psbt: &PartiallySignedTransaction, allow_witness_utxo: bool) -> Result<u64, PsbtError> {
+    let total_input_value = prev_utxos(psbt, allow_witness_utxo)?
+        .iter()
+        .try_fold(0u64, |sum, utxo| sum.checked_add(utxo.value))
+        .ok_or(PsbtError::InvalidAmount)?;
+    let total_output_value = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .try_fold(0u64, |sum, out| sum.checked_add(out.value))
+        .ok_or(PsbtError::InvalidAmount)?;
+
+    total_input_value
+        .checked_sub(total_output_value)
+        .ok_or(PsbtError::InvalidAmount)
+}
+
+/// Estimated weight of the data added to an input spending `script_pubkey` when it's signed
+///
+/// Only known for single-key scripts, assuming 72-byte ECDSA signatures and taproot key spends
+/// with the default sighash. Inputs of the wallet should use the weight computed from its
+/// descriptor instead, which also covers multisig.
+pub fn satisfaction_weight(script_pubkey: &Script) -> Option<u64> {
+    // Number of witness items, signature and public key with their lengths
+    const P2WPKH_WITNESS: u64 = 1 + 1 + 72 + 1 + 33;
+
+    if script_pubkey.is_v0_p2wpkh() {
+        Some(P2WPKH_WITNESS)
+    } else if script_pubkey.is_v1_p2tr() {
+        Some(1 + 1 + 64)
+    } else if script_pubkey.is_p2pkh() {
+        Some(4 * (1 + 72 + 1 + 33))
+    } else {
+        None
+    }
+}
+
+/// Estimate the virtual size of the transaction once signed
+///
+/// `satisfaction_weights` is the weight added to each input when it's signed, see
+/// `satisfaction_weight()`. The segwit marker and flag are always counted, so a transaction
+/// without segwit inputs is overestimated by half a vbyte.
+pub fn estimate_vsize(
+    psbt: &PartiallySignedTransaction,
+    satisfaction_weights: impl IntoIterator<Item = u64>,
+) -> u64 {
+    let weight =
+        psbt.unsigned_tx.weight() as u64 + 2 + satisfaction_weights.into_iter().sum::<u64>();
+    weight.div_ceil(4)
+}
+
+/// Fee rate of the transaction in sat/vB
+pub fn fee_rate(fees: u64, vsize: u64) -> f32 {
+    fees as f32 / vsize.max(1) as f32
+}
+
+/// Whether a sighash type commits to all the inputs and outputs (`SIGHASH_ALL`, or
+/// `SIGHASH_DEFAULT` for taproot)
+fn is_default_sighash(sighash: u32) -> bool {
+    matches!(sighash, 0x00 | 0x01)
+}
+
+/// Short name of a sighash type, like `NONE|ACP`
+pub fn sighash_name(sighash: u32) -> alloc::string::String {
+    let base = match sighash & !0x80 {
+        0x00 => "DEFAULT".into(),
+        0x01 => "ALL".into(),
+        0x02 => "NONE".into(),
+        0x03 => "SINGLE".into(),
+        _ => return alloc::format!("0x{:02x}", sighash),
+    };
+    if sighash & 0x80 != 0 {
+        alloc::format!("{}|ACP", base)
+    } else {
+        base
+    }
+}
+
+/// Return the index and the sighash type of the inputs of the wallet that ask for a sighash other
+/// than the default one
+///
+/// These signatures don't commit to some of the inputs or outputs, which can then be changed
+/// after signing. Inputs of other participants are ignored, since the device doesn't sign them.
+pub fn non_default_sighashes(
+    psbt: &PartiallySignedTransaction,
+    our_inputs: &[bool],
+) -> Vec<(usize, u32)> {
+    psbt.inputs
+        .iter()
+        .zip(our_inputs)
+        .enumerate()
+        .filter(|(_, (_, ours))| **ours)
+        .filter_map(|(i, (input, _))| {
+            input
+                .sighash_type
+                .map(|sighash| sighash.to_u32())
+                .filter(|sighash| !is_default_sighash(*sighash))
+                .map(|sighash| (i, sighash))
+        })
+        .collect()
+}
+
+/// Value moved in and out of the wallet by a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetFlow {
+    /// Total value of the inputs that belong to the wallet
+    pub sent: u64,
+    /// Total value of the outputs that belong to the wallet, including the change
+    pub received: u64,
+}
+
+impl NetFlow {
+    /// Received minus sent, negative when the wallet loses funds
+    pub fn net(&self) -> i64 {
+        self.received as i64 - self.sent as i64
+    }
+}
+
+/// Sum the inputs and outputs flagged in `our_inputs` and `our_outputs`
+pub fn net_flow(
+    psbt: &PartiallySignedTransaction,
+    allow_witness_utxo: bool,
+    our_inputs: &[bool],
+    our_outputs: &[bool],
+) -> Result<NetFlow, PsbtError> {
+    let sent = prev_utxos(psbt, allow_witness_utxo)?
+        .iter()
+        .zip(our_inputs)
+        .filter(|(_, ours)| **ours)
+        .try_fold(0u64, |sum, (utxo, _)| sum.checked_add(utxo.value))
+        .ok_or(PsbtError::InvalidAmount)?;
+    let received = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(our_outputs)
+        .filter(|(_, ours)| **ours)
+        .try_fold(0u64, |sum, (out, _)| sum.checked_add(out.value))
+        .ok_or(PsbtError::InvalidAmount)?;
+
+    Ok(NetFlow { sent, received })
+}
+
+/// Whether the wallet spends some of the inputs and other participants the rest, like in a
+/// coinjoin
+///
+/// Most outputs of these transactions belong to the other participants, so they are better
+/// summarized with `net_flow` than shown one by one.
+pub fn is_collaborative(our_inputs: &[bool]) -> bool {
+    our_inputs.iter().any(|ours| *ours) && our_inputs.iter().any(|ours| !*ours)
+}
+
+/// Short list of the inputs that don't belong to the wallet
+///
+/// Returns `None` if all the inputs are ours. Their value is counted in the total of the inputs
+/// even though the device can't check where it comes from, so they are pointed out to the user.
+pub fn foreign_inputs(our_inputs: &[bool]) -> Option<ShortList> {
+    ShortList::new(
+        our_inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, ours)| !**ours)
+            .map(|(i, _)| i),
+    )
+}
+
+/// Short list of the outputs paying to the script of one of the inputs
+///
+/// Returns `None` if no address is reused. Sending back to an address being spent links the two
+/// on-chain, and can also be the sign of an address swapped in the clipboard, so they are pointed
+/// out to the user. `utxos` are the outputs spent by the inputs, as returned by `prev_utxos`.
+pub fn reused_addresses(psbt: &PartiallySignedTransaction, utxos: &[&TxOut]) -> Option<ShortList> {
+    ShortList::new(
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, out)| {
+                utxos
+                    .iter()
+                    .any(|utxo| utxo.script_pubkey == out.script_pubkey)
+            })
+            .map(|(i, _)| i),
+    )
+}
+
+/// Whether a signature of the key with `fingerprint` is already in `input`
+///
+/// Signatures are matched to their keys through the key origins of the input, ECDSA ones with
+/// `bip32_derivation` and tapscript ones with `tap_key_origins`.
+pub fn has_signature(input: &psbt::Input, fingerprint: Fingerprint) -> bool {
+    let ecdsa = input.partial_sigs.keys().any(|pubkey| {
+        input
+            .bip32_derivation
+            .get(&pubkey.inner)
+            .is_some_and(|(origin, _)| *origin == fingerprint)
+    });
+    let schnorr = input.tap_script_sigs.keys().any(|(pubkey, _)| {
+        input
+            .tap_key_origins
+            .get(pubkey)
+            .is_some_and(|(_, (origin, _))| *origin == fingerprint)
+    });
+
+    ecdsa || schnorr
+}
+
+/// Signatures already collected for the inputs of a multisig wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CosignerSignatures {
+    /// Cosigners that signed, including the device
+    pub count: usize,
+    pub threshold: usize,
+    /// Fingerprints of the first other cosigners that signed
+    pub listed: Vec<Fingerprint>,
+    /// How many other cosigners that signed were left out of `listed`
+    pub more: usize,
+    /// Whether the device signed too
+    pub local: bool,
+}
+
+/// Signatures already collected for the inputs of a multisig wallet
+///
+/// A cosigner counts as signed once its signature is in every input of the wallet. `cosigners`
+/// are the fingerprints of all the keys of the wallet, including `local`, the one of the device.
+pub fn cosigner_signatures(
+    psbt: &PartiallySignedTransaction,
+    our_inputs: &[bool],
+    cosigners: &[Fingerprint],
+    local: Fingerprint,
+    threshold: usize,
+) -> CosignerSignatures {
+    const MAX_LISTED: usize = 3;
+
+    let signed = |fingerprint: Fingerprint| {
+        our_inputs.iter().any(|ours| *ours)
+            && psbt
+                .inputs
+                .iter()
+                .zip(our_inputs)
+                .filter(|(_, ours)| **ours)
+                .all(|(input, _)| has_signature(input, fingerprint))
+    };
+
+    let mut listed: Vec<_> = cosigners
+        .iter()
+        .filter(|fp| **fp != local && signed(**fp))
+        .copied()
+        .collect();
+    let more = listed.len().saturating_sub(MAX_LISTED);
+    listed.truncate(MAX_LISTED);
+
+    CosignerSignatures {
+        count: cosigners.iter().filter(|fp| signed(**fp)).count(),
+        threshold,
+        listed,
+        more,
+        local: signed(local),
+    }
+}
+
+/// List of indexes that fits on a page, like `#2, #5, #7 +3 more`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortList {
+    /// The first indexes, 0-based
+    pub listed: Vec<usize>,
+    /// How many indexes were left out
+    pub more: usize,
+}
+
+impl ShortList {
+    const MAX_LISTED: usize = 3;
+
+    /// `None` if there are no `indexes`
+    fn new(indexes: impl Iterator<Item = usize>) -> Option<Self> {
+        let mut listed: Vec<_> = indexes.collect();
+        if listed.is_empty() {
+            return None;
+        }
+
+        let more = listed.len().saturating_sub(Self::MAX_LISTED);
+        listed.truncate(Self::MAX_LISTED);
+        Some(ShortList { listed, more })
+    }
+
+    /// The list with 1-based indexes, followed by the ones left out and the word `more`
+    pub fn format(&self, more: &str) -> alloc::string::String {
+        let mut list = self
+            .listed
+            .iter()
+            .map(|i| alloc::format!("#{}", i + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if self.more > 0 {
+            list.push_str(&alloc::format!(" +{} {}", self.more, more));
+        }
+        list
+    }
+}
+
+/// Payment confirmed by the user, kept to review a payjoin proposal (BIP-78) built on it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentCheckpoint {
+    /// Inputs of the original transaction, all spent by the wallet
+    pub inputs: Vec<OutPoint>,
+    /// Outputs of the original transaction that don't belong to the wallet
+    pub payments: Vec<TxOut>,
+    /// Value moved by the wallet
+    pub flow: NetFlow,
+    pub fees: u64,
+}
+
+impl PaymentCheckpoint {
+    /// Remember the payment made by `psbt`, which only spends inputs of the wallet
+    pub fn new(
+        psbt: &PartiallySignedTransaction,
+        allow_witness_utxo: bool,
+        our_outputs: &[bool],
+    ) -> Result<Self, PsbtError> {
+        let our_inputs = alloc::vec![true; psbt.inputs.len()];
+
+        Ok(PaymentCheckpoint {
+            inputs: psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect(),
+            payments: psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .zip(our_outputs)
+                .filter(|(_, ours)| !**ours)
+                .map(|(out, _)| out.clone())
+                .collect(),
+            flow: net_flow(psbt, allow_witness_utxo, &our_inputs, our_outputs)?,
+            fees: fees(psbt, allow_witness_utxo)?,
+        })
+    }
+}
+
+/// Changes made by the receiver of a payjoin to the original payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayjoinDelta {
+    /// Number of inputs added by the receiver
+    pub added_inputs: usize,
+    /// Value moved by the wallet in the original payment
+    pub before: NetFlow,
+    /// Value moved by the wallet in the proposal
+    pub after: NetFlow,
+    /// Fees of the proposal
+    pub fees: u64,
+}
+
+impl PayjoinDelta {
+    /// How much more the wallet pays compared to the original payment, usually its contribution
+    /// to the fees of the inputs added by the receiver
+    pub fn extra_cost(&self) -> i64 {
+        self.before.net() - self.after.net()
+    }
+}
+
+/// Compare the payjoin `proposal` with the payment in `checkpoint`
+///
+/// The proposal must spend all the original inputs, the added ones can't belong to the wallet
+/// and every original payment must still be there, with at least the same value.
+pub fn payjoin_delta(
+    checkpoint: &PaymentCheckpoint,
+    proposal: &PartiallySignedTransaction,
+    allow_witness_utxo: bool,
+    our_inputs: &[bool],
+    our_outputs: &[bool],
+) -> Result<PayjoinDelta, PsbtError> {
+    let mut added_inputs = 0;
+    for (txin, ours) in proposal.unsigned_tx.input.iter().zip(our_inputs) {
+        match (checkpoint.inputs.contains(&txin.previous_output), ours) {
+            (true, true) => {}
+            (false, false) => added_inputs += 1,
+            _ => return Err(PsbtError::PayjoinMismatch),
+        }
+    }
+    if added_inputs + checkpoint.inputs.len() != proposal.unsigned_tx.input.len() {
+        return Err(PsbtError::PayjoinMismatch);
+    }
+
+    for payment in &checkpoint.payments {
+        if !proposal
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|out| out.script_pubkey == payment.script_pubkey && out.value >= payment.value)
+        {
+            return Err(PsbtError::PayjoinMismatch);
+        }
+    }
+
+    Ok(PayjoinDelta {
+        added_inputs,
+        before: checkpoint.flow,
+        after: net_flow(proposal, allow_witness_utxo, our_inputs, our_outputs)?,
+        fees: fees(proposal, allow_witness_utxo)?,
+    })
+}
+
+/// Transaction of the payer of a payjoin (BIP-78), kept to check the proposal that the wallet
+/// builds on it as the receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayjoinOriginal {
+    pub inputs: Vec<OutPoint>,
+    pub outputs: Vec<TxOut>,
+}
+
+impl PayjoinOriginal {
+    /// Parse the consensus-encoded original transaction, signed or not
+    pub fn parse(data: &[u8]) -> Result<Self, PsbtError> {
+        let tx: Transaction = bitcoin::consensus::encode::deserialize(data)
+            .map_err(|_| PsbtError::InvalidEncoding)?;
+
+        Ok(PayjoinOriginal {
+            inputs: tx.input.iter().map(|txin| txin.previous_output).collect(),
+            outputs: tx.output,
+        })
+    }
+}
+
+/// What the wallet gets from a payjoin proposal it builds as the receiver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayjoinReceipt {
+    /// Number of inputs added by the wallet
+    pub contributed_inputs: usize,
+    /// Value moved by the wallet in the proposal, `sent` being the value of the inputs added
+    pub flow: NetFlow,
+    /// Value paid to the wallet by the original transaction
+    pub original_received: u64,
+    /// Fees of the proposal
+    pub fees: u64,
+}
+
+impl PayjoinReceipt {
+    /// How much less the wallet receives compared to the original transaction, usually its
+    /// contribution to the fees of the inputs it added
+    pub fn fee_share(&self) -> i64 {
+        self.original_received as i64 - self.flow.net()
+    }
+}
+
+/// Check the payjoin `proposal` built by the wallet, as the receiver, on the `original`
+/// transaction of the payer
+///
+/// The proposal must spend all the original inputs, none of which can belong to the wallet, plus
+/// at least one input of the wallet. Every original output must still be there: the ones of the
+/// payer with the same value, except for what they give up to the higher fees, and the ones of the
+/// wallet with any value. New outputs can only belong to the wallet.
+pub fn payjoin_receipt(
+    original: &PayjoinOriginal,
+    proposal: &PartiallySignedTransaction,
+    allow_witness_utxo: bool,
+    our_inputs: &[bool],
+    our_outputs: &[bool],
+) -> Result<PayjoinReceipt, PsbtError> {
+    let mut contributed_inputs = 0;
+    for (txin, ours) in proposal.unsigned_tx.input.iter().zip(our_inputs) {
+        match (original.inputs.contains(&txin.previous_output), ours) {
+            (true, false) => {}
+            (false, true) => contributed_inputs += 1,
+            _ => return Err(PsbtError::PayjoinMismatch),
+        }
+    }
+    if contributed_inputs == 0
+        || contributed_inputs + original.inputs.len() != proposal.unsigned_tx.input.len()
+    {
+        return Err(PsbtError::PayjoinMismatch);
+    }
+
+    let outputs = &proposal.unsigned_tx.output;
+    let mut matched = alloc::vec![false; outputs.len()];
+    let mut original_received = 0u64;
+    let mut payer_reduction = 0u64;
+    for out in &original.outputs {
+        let index = (0..outputs.len())
+            .find(|i| !matched[*i] && outputs[*i].script_pubkey == out.script_pubkey)
+            .ok_or(PsbtError::PayjoinMismatch)?;
+        matched[index] = true;
+
+        if our_outputs[index] {
+            original_received = original_received
+                .checked_add(out.value)
+                .ok_or(PsbtError::InvalidAmount)?;
+        } else {
+            payer_reduction = payer_reduction
+                .checked_add(out.value.saturating_sub(outputs[index].value))
+                .ok_or(PsbtError::InvalidAmount)?;
+        }
+    }
+    if original_received == 0
+        || matched
+            .iter()
+            .zip(our_outputs)
+            .any(|(matched, ours)| !matched && !ours)
+    {
+        return Err(PsbtError::PayjoinMismatch);
+    }
+
+    // The payer can only give up some of its outputs to the fees added by the proposal
+    let payer_inputs = prev_utxos(proposal, allow_witness_utxo)?
+        .iter()
+        .zip(our_inputs)
+        .filter(|(_, ours)| !**ours)
+        .try_fold(0u64, |sum, (utxo, _)| sum.checked_add(utxo.value))
+        .ok_or(PsbtError::InvalidAmount)?;
+    let original_fees = original
+        .outputs
+        .iter()
+        .try_fold(0u64, |sum, out| sum.checked_add(out.value))
+        .and_then(|outputs| payer_inputs.checked_sub(outputs))
+        .ok_or(PsbtError::InvalidAmount)?;
+    let fees = fees(proposal, allow_witness_utxo)?;
+    if payer_reduction > fees.saturating_sub(original_fees) {
+        return Err(PsbtError::PayjoinMismatch);
+    }
+
+    // Our inputs must go back to our outputs, together with at least part of the payment
+    let flow = net_flow(proposal, allow_witness_utxo, our_inputs, our_outputs)?;
+    if flow.net() <= 0 {
+        return Err(PsbtError::PayjoinMismatch);
+    }
+
+    Ok(PayjoinReceipt {
+        contributed_inputs,
+        flow,
+        original_received,
+        fees,
+    })
+}
+
+/// Outpoint spent by the first input of a proof of reserves (BIP-127) for `message`
+///
+/// Its txid is the SHA-256 hash of the message, prefixed with "Proof-of-Reserves: ", so it
+/// doesn't exist and the transaction can never be mined.
+pub fn reserves_commitment(message: &str) -> OutPoint {
+    use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"Proof-of-Reserves: ");
+    engine.input(message.as_bytes());
+    let hash = sha256::Hash::from_engine(engine);
+    OutPoint::new(bitcoin::Txid::from_inner(hash.into_inner()), 0)
+}
+
+/// Check that `psbt` is a proof of reserves (BIP-127) for `message` and return the outputs spent
+/// by every input after the commitment, the ones whose ownership is proven
+///
+/// The first input spends `reserves_commitment(message)` and has no output to check. As long as
+/// the other inputs are signed with `SIGHASH_ALL` they commit to it, so the transaction can't be
+/// used to move the funds. For the same reason the amounts of `witness_utxo` can't be used to
+/// steal anything: a wrong one only makes the proof invalid.
+pub fn proof_of_reserves<'a>(
+    psbt: &'a PartiallySignedTransaction,
+    message: &str,
+) -> Result<Vec<&'a TxOut>, PsbtError> {
+    match psbt.unsigned_tx.input.first() {
+        Some(commitment)
+            if commitment.previous_output == reserves_commitment(message)
+                && psbt.unsigned_tx.input.len() > 1 => {}
+        _ => return Err(PsbtError::ReservesMismatch),
+    }
+
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(psbt.inputs.iter())
+        .skip(1)
+        .map(|(txin, input)| prev_utxo(txin, input, true))
+        .collect()
+}
+
+/// Check that one of the inputs of `psbt` spends an output of `parent`, the transaction bumped by
+/// a CPFP according to the host
+pub fn check_cpfp_parent(
+    psbt: &PartiallySignedTransaction,
+    parent: &bitcoin::Txid,
+) -> Result<(), PsbtError> {
+    match psbt
+        .unsigned_tx
+        .input
+        .iter()
+        .any(|txin| txin.previous_output.txid == *parent)
+    {
+        true => Ok(()),
+        false => Err(PsbtError::CpfpParentMismatch),
+    }
+}
+
+/// Fees of a transaction that replaces a payment with higher fees (BIP-125)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeBump {
+    pub old_fees: u64,
+    pub new_fees: u64,
+}
+
+/// Whether `psbt` makes the same payment as `checkpoint` with higher `fees`
+///
+/// The transaction must spend exactly the same inputs and make exactly the same payments: only
+/// the outputs flagged in `our_outputs`, the change, can pay for the higher fees.
+pub fn fee_bump(
+    checkpoint: &PaymentCheckpoint,
+    psbt: &PartiallySignedTransaction,
+    fees: u64,
+    our_outputs: &[bool],
+) -> Option<FeeBump> {
+    let inputs = &psbt.unsigned_tx.input;
+    let same_inputs = inputs.len() == checkpoint.inputs.len()
+        && inputs
+            .iter()
+            .all(|txin| checkpoint.inputs.contains(&txin.previous_output));
+
+    let payments = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(our_outputs)
+        .filter(|(_, ours)| !**ours)
+        .map(|(out, _)| out)
+        .collect::<Vec<_>>();
+    let same_payments = payments.len() == checkpoint.payments.len()
+        && checkpoint
+            .payments
+            .iter()
+            .all(|payment| payments.contains(&payment));
+
+    (same_inputs && same_payments && fees > checkpoint.fees).then_some(FeeBump {
+        old_fees: checkpoint.fees,
+        new_fees: fees,
+    })
+}
+
+/// Where an output sends its value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputDestination {
+    Address(Address),
+    /// Data carrier (`OP_RETURN`), with the data pushed after the opcode
+    OpReturn(Vec<u8>),
+}
+
+/// Return the data of an `OP_RETURN` output, `None` for other scripts
+///
+/// The script must only push data after `OP_RETURN`, like the ones relayed by Bitcoin Core.
+pub fn op_return_data(script: &Script) -> Option<Vec<u8>> {
+    if !script.is_op_return() {
+        return None;
+    }
+
+    let mut data = Vec::new();
+    for instruction in script.instructions().skip(1) {
+        match instruction {
+            Ok(Instruction::PushBytes(bytes)) => data.extend_from_slice(bytes),
+            _ => return None,
+        }
+    }
+
+    Some(data)
+}
+
+/// Return the destination of every output of the transaction, in order
+///
+/// Outputs are either addresses or `OP_RETURN` data, any other script is rejected.
+pub fn output_destinations(
+    psbt: &PartiallySignedTransaction,
+    network: Network,
+) -> Result<Vec<OutputDestination>, PsbtError> {
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .map(|out| {
+            if let Ok(address) = Address::from_script(&out.script_pubkey, network) {
+                Ok(OutputDestination::Address(address))
+            } else {
+                op_return_data(&out.script_pubkey)
+                    .map(OutputDestination::OpReturn)
+                    .ok_or(PsbtError::NonStandardOutput)
+            }
+        })
+        .collect()
+}
+
+/// Absolute locktime of a transaction, only set when it's enforced by consensus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxLockTime {
+    None,
+    /// Block height after which the transaction can be mined
+    Height(u32),
+    /// Unix timestamp after which the transaction can be mined, compared with the median time
+    /// past
+    Time(u32),
+}
+
+/// Locktime and replaceability of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxTimelock {
+    pub lock_time: TxLockTime,
+    /// Whether any input signals replaceability (BIP-125)
+    pub rbf: bool,
+}
+
+impl TxTimelock {
+    pub fn new(psbt: &PartiallySignedTransaction) -> Self {
+        let tx = &psbt.unsigned_tx;
+        let lock_time = match tx.lock_time.0 {
+            0 => TxLockTime::None,
+            _ if !tx.is_lock_time_enabled() => TxLockTime::None,
+            v if v < bitcoin::blockdata::locktime::LOCK_TIME_THRESHOLD => TxLockTime::Height(v),
+            v => TxLockTime::Time(v),
+        };
+
+        TxTimelock {
+            lock_time,
+            rbf: tx.input.iter().any(|txin| txin.sequence.is_rbf()),
+        }
+    }
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM`, in UTC
+pub fn format_timestamp(timestamp: u32) -> alloc::string::String {
+    // Days to civil date, from Howard Hinnant's `civil_from_days`
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    let seconds = timestamp % 86400;
+    alloc::format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// Amount above which sending to a legacy or unknown script is worth a warning, in satoshis
+pub const LARGE_LEGACY_AMOUNT: u64 = 1_000_000;
+
+/// Script type of an output, shown next to its address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputType {
+    P2tr,
+    P2wpkh,
+    P2wsh,
+    P2sh,
+    P2pkh,
+    /// Future segwit versions
+    Unknown,
+}
+
+impl OutputType {
+    pub fn from_address(address: &Address) -> Self {
+        match address.address_type() {
+            Some(AddressType::P2tr) => OutputType::P2tr,
+            Some(AddressType::P2wpkh) => OutputType::P2wpkh,
+            Some(AddressType::P2wsh) => OutputType::P2wsh,
+            Some(AddressType::P2sh) => OutputType::P2sh,
+            Some(AddressType::P2pkh) => OutputType::P2pkh,
+            _ => OutputType::Unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputType::P2tr => "P2TR",
+            OutputType::P2wpkh => "P2WPKH",
+            OutputType::P2wsh => "P2WSH",
+            OutputType::P2sh => "P2SH",
+            OutputType::P2pkh => "P2PKH",
+            OutputType::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Whether sending `value` to this type of script should be confirmed with a warning
+    ///
+    /// Legacy addresses are rarely used by new wallets, so a large payment to one of them (or to a
+    /// segwit version nobody can spend yet) is more likely to come from a swapped address.
+    pub fn needs_warning(&self, value: u64) -> bool {
+        matches!(self, OutputType::P2pkh | OutputType::Unknown) && value >= LARGE_LEGACY_AMOUNT
+    }
+}
+
+/// Prefix of the proprietary fields understood by the device
+pub const PROPRIETARY_PREFIX: &[u8] = b"portal";
+/// Proprietary output field with a label for the output, set by the host
+pub const PROPRIETARY_OUT_LABEL: u8 = 0x00;
+/// Labels longer than this are truncated
+pub const MAX_LABEL_LEN: usize = 32;
+
+/// Index of the address of an output of the wallet, from the last step of its key origins
+///
+/// Only the key origins with `fingerprint` are considered, so that the keys of the other
+/// participants of a multisig don't count.
+pub fn output_derivation_index(output: &psbt::Output, fingerprint: Fingerprint) -> Option<u32> {
+    output
+        .bip32_derivation
+        .values()
+        .chain(output.tap_key_origins.values().map(|(_, origin)| origin))
+        .filter(|(key_fingerprint, _)| *key_fingerprint == fingerprint)
+        .find_map(|(_, path)| match path.into_iter().last()? {
+            ChildNumber::Normal { index } => Some(*index),
+            ChildNumber::Hardened { .. } => None,
+        })
+}
+
+/// Return the label attached to an output by the host, if any
+///
+/// Labels that aren't printable ASCII are ignored, since the display couldn't show them.
+pub fn output_label(output: &psbt::Output) -> Option<&str> {
+    let label = output.proprietary.iter().find_map(|(key, value)| {
+        (key.prefix == PROPRIETARY_PREFIX
+            && key.subtype == PROPRIETARY_OUT_LABEL
+            && key.key.is_empty())
+        .then_some(value)
+    })?;
+    if label.is_empty() || !label.iter().all(|c| (0x20..0x7F).contains(c)) {
+        return None;
+    }
+
+    let label = core::str::from_utf8(label).ok()?;
+    Some(&label[..label.len().min(MAX_LABEL_LEN)])
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn};
+    use bitcoin::hashes::Hash;
+
+    fn make_psbt(input_value: u64, output_value: u64) -> PartiallySignedTransaction {
+        let script = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: input_value,
+                script_pubkey: script.clone(),
+            }],
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: output_value,
+                script_pubkey: script,
+            }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        psbt
+    }
+
+    #[test]
+    fn test_fees() {
+        let psbt = make_psbt(10_000, 9_000);
+        assert_eq!(fees(&psbt, false), Ok(1_000));
+
+        let psbt = make_psbt(10_000, 11_000);
+        assert_eq!(fees(&psbt, false), Err(PsbtError::InvalidAmount));
+    }
+
+    #[test]
+    fn test_estimate_vsize() {
+        let psbt = make_psbt(10_000, 9_000);
+        let prev = &psbt.inputs[0].non_witness_utxo.as_ref().unwrap().output[0];
+        let weight = satisfaction_weight(&prev.script_pubkey).unwrap();
+
+        // A P2WPKH input and output, 109.5 vbytes with a 72-byte signature
+        let vsize = estimate_vsize(&psbt, [weight]);
+        assert_eq!(vsize, 110);
+        assert!((fee_rate(1_000, vsize) - 9.09).abs() < 0.01);
+
+        let p2wsh = Script::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros());
+        assert_eq!(satisfaction_weight(&p2wsh), None);
+    }
+
+    #[test]
+    fn test_non_default_sighashes() {
+        use bitcoin::EcdsaSighashType;
+
+        let mut psbt = make_psbt(10_000, 9_000);
+        assert!(non_default_sighashes(&psbt, &[true]).is_empty());
+        psbt.inputs[0].sighash_type = Some(EcdsaSighashType::All.into());
+        assert!(non_default_sighashes(&psbt, &[true]).is_empty());
+
+        psbt.inputs[0].sighash_type = Some(EcdsaSighashType::SinglePlusAnyoneCanPay.into());
+        assert_eq!(non_default_sighashes(&psbt, &[true]), vec![(0, 0x83)]);
+        assert!(non_default_sighashes(&psbt, &[false]).is_empty());
+
+        assert_eq!(sighash_name(0x83), "SINGLE|ACP");
+        assert_eq!(sighash_name(0x02), "NONE");
+        assert_eq!(sighash_name(0x42), "0x42");
+    }
+
+    #[test]
+    fn test_invalid_vout() {
+        let mut psbt = make_psbt(10_000, 9_000);
+        psbt.unsigned_tx.input[0].previous_output.vout = 1;
+        assert_eq!(fees(&psbt, false), Err(PsbtError::InvalidNonWitnessUtxo));
+    }
+
+    #[test]
+    fn test_witness_utxo() {
+        let mut psbt = make_psbt(10_000, 9_000);
+        psbt.inputs[0].witness_utxo = psbt.inputs[0]
+            .non_witness_utxo
+            .take()
+            .map(|tx| tx.output[0].clone());
+
+        assert_eq!(fees(&psbt, false), Err(PsbtError::MissingUtxo));
+        assert_eq!(fees(&psbt, true), Ok(1_000));
+    }
+
+    #[test]
+    fn test_inconsistent_witness_utxo() {
+        let mut psbt = make_psbt(10_000, 9_000);
+        let mut utxo = psbt.inputs[0].non_witness_utxo.as_ref().unwrap().output[0].clone();
+        psbt.inputs[0].witness_utxo = Some(utxo.clone());
+        assert_eq!(fees(&psbt, false), Ok(1_000));
+
+        // A lower amount in `witness_utxo` would be signed while the fees come from the full tx
+        utxo.value = 9_500;
+        psbt.inputs[0].witness_utxo = Some(utxo);
+        assert_eq!(fees(&psbt, false), Err(PsbtError::InconsistentUtxo));
+        assert_eq!(fees(&psbt, true), Err(PsbtError::InconsistentUtxo));
+    }
+
+    #[test]
+    fn test_foreign_inputs() {
+        assert_eq!(foreign_inputs(&[true, true]), None);
+        assert_eq!(
+            foreign_inputs(&[true, false, true, false]).map(|list| list.format("more")),
+            Some("#2, #4".into())
+        );
+        assert_eq!(
+            foreign_inputs(&[false, false, true, false, false, false]),
+            Some(ShortList {
+                listed: vec![0, 1, 3],
+                more: 2,
+            })
+        );
+        assert_eq!(
+            foreign_inputs(&[false, false, true, false, false, false])
+                .map(|list| list.format("more")),
+            Some("#1, #2, #4 +2 more".into())
+        );
+    }
+
+    #[test]
+    fn test_reused_addresses() {
+        // The output pays back to the script of the input
+        let mut psbt = make_psbt(10_000, 6_000);
+        let utxos = prev_utxos(&psbt, false).unwrap();
+        assert_eq!(
+            reused_addresses(&psbt, &utxos).map(|list| list.format("more")),
+            Some("#1".into())
+        );
+
+        let other = TxOut {
+            value: 3_000,
+            script_pubkey: Script::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros()),
+        };
+        psbt.unsigned_tx.output.insert(0, other.clone());
+        let utxos = prev_utxos(&psbt, false).unwrap();
+        assert_eq!(
+            reused_addresses(&psbt, &utxos).map(|list| list.format("more")),
+            Some("#2".into())
+        );
+
+        psbt.unsigned_tx.output.truncate(1);
+        let utxos = prev_utxos(&psbt, false).unwrap();
+        assert_eq!(reused_addresses(&psbt, &utxos), None);
+    }
+
+    #[test]
+    fn test_cosigner_signatures() {
+        use bitcoin::secp256k1::{Secp256k1, SecretKey};
+        use bitcoin::util::bip32::DerivationPath;
+
+        let secp = Secp256k1::signing_only();
+        let pubkey = |byte| {
+            SecretKey::from_slice(&[byte; 32])
+                .unwrap()
+                .public_key(&secp)
+        };
+        let ours = Fingerprint::from(&[0xAA, 0xBB, 0xCC, 0xDD][..]);
+        let other = Fingerprint::from(&[0x11, 0x22, 0x33, 0x44][..]);
+        let third = Fingerprint::from(&[0x55, 0x66, 0x77, 0x88][..]);
+        let cosigners = [ours, other, third];
+
+        let mut psbt = make_psbt(10_000, 9_000);
+        for (byte, fingerprint) in [(0x01, ours), (0x02, other), (0x03, third)] {
+            psbt.inputs[0]
+                .bip32_derivation
+                .insert(pubkey(byte), (fingerprint, DerivationPath::master()));
+        }
+        let none = CosignerSignatures {
+            count: 0,
+            threshold: 2,
+            listed: vec![],
+            more: 0,
+            local: false,
+        };
+        assert_eq!(
+            cosigner_signatures(&psbt, &[true], &cosigners, ours, 2),
+            none
+        );
+
+        // The signature itself isn't checked, only the key it belongs to
+        let sig =
+            bitcoin::EcdsaSig::from_slice(&[0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01])
+                .unwrap();
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(bitcoin::PublicKey::new(pubkey(0x02)), sig);
+        assert_eq!(
+            cosigner_signatures(&psbt, &[true], &cosigners, ours, 2),
+            CosignerSignatures {
+                count: 1,
+                listed: vec![other],
+                ..none.clone()
+            }
+        );
+        // Inputs that aren't ours are ignored
+        assert_eq!(
+            cosigner_signatures(&psbt, &[false], &cosigners, ours, 2),
+            none
+        );
+
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(bitcoin::PublicKey::new(pubkey(0x01)), sig);
+        assert_eq!(
+            cosigner_signatures(&psbt, &[true], &cosigners, ours, 2),
+            CosignerSignatures {
+                count: 2,
+                listed: vec![other],
+                local: true,
+                ..none
+            }
+        );
+    }
+
+    #[test]
+    fn test_proof_of_reserves() {
+        let message = "Reserves of 2024-01-01";
+
+        // The commitment comes first, followed by the inputs whose ownership is proven
+        let mut psbt = make_psbt(10_000, 10_000);
+        psbt.unsigned_tx.input.insert(
+            0,
+            TxIn {
+                previous_output: reserves_commitment(message),
+                ..Default::default()
+            },
+        );
+        psbt.inputs.insert(0, Default::default());
+
+        let utxos = proof_of_reserves(&psbt, message).unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].value, 10_000);
+        assert_eq!(
+            proof_of_reserves(&psbt, "Another message"),
+            Err(PsbtError::ReservesMismatch)
+        );
+
+        // A regular transaction isn't a proof
+        assert_eq!(
+            proof_of_reserves(&make_psbt(10_000, 10_000), message),
+            Err(PsbtError::ReservesMismatch)
+        );
+    }
+
+    #[test]
+    fn test_check_cpfp_parent() {
+        let psbt = make_psbt(10_000, 9_000);
+        let parent = psbt.unsigned_tx.input[0].previous_output.txid;
+        assert_eq!(check_cpfp_parent(&psbt, &parent), Ok(()));
+
+        let other = reserves_commitment("Not the parent").txid;
+        assert_eq!(
+            check_cpfp_parent(&psbt, &other),
+            Err(PsbtError::CpfpParentMismatch)
+        );
+    }
+
+    #[test]
+    fn test_output_derivation_index() {
+        use bitcoin::secp256k1::{Secp256k1, SecretKey};
+        use bitcoin::util::bip32::DerivationPath;
+        use core::str::FromStr;
+
+        let secp = Secp256k1::signing_only();
+        let key = SecretKey::from_slice(&[0x01; 32])
+            .unwrap()
+            .public_key(&secp);
+        let ours = Fingerprint::from(&[0xAA, 0xBB, 0xCC, 0xDD][..]);
+
+        let mut output = psbt::Output::default();
+        assert_eq!(output_derivation_index(&output, ours), None);
+
+        output.bip32_derivation.insert(
+            key,
+            (
+                Fingerprint::default(),
+                DerivationPath::from_str("m/84'/0'/0'/1/7").unwrap(),
+            ),
+        );
+        assert_eq!(output_derivation_index(&output, ours), None);
+
+        output.bip32_derivation.insert(
+            key,
+            (ours, DerivationPath::from_str("m/84'/0'/0'/1/42").unwrap()),
+        );
+        assert_eq!(output_derivation_index(&output, ours), Some(42));
+    }
+
+    #[test]
+    fn test_net_flow() {
+        let mut psbt = make_psbt(10_000, 9_000);
+        let other = make_psbt(20_000, 0);
+        psbt.unsigned_tx
+            .input
+            .push(other.unsigned_tx.input[0].clone());
+        psbt.inputs.push(other.inputs[0].clone());
+        psbt.unsigned_tx.output.push(TxOut {
+            value: 19_000,
+            script_pubkey: psbt.unsigned_tx.output[0].script_pubkey.clone(),
+        });
+
+        assert!(is_collaborative(&[true, false]));
+        assert!(!is_collaborative(&[true, true]));
+        assert!(!is_collaborative(&[false, false]));
+
+        let flow = net_flow(&psbt, false, &[true, false], &[true, false]).unwrap();
+        assert_eq!(
+            flow,
+            NetFlow {
+                sent: 10_000,
+                received: 9_000
+            }
+        );
+        assert_eq!(flow.net(), -1_000);
+    }
+
+    #[test]
+    fn test_payjoin_delta() {
+        let original = make_psbt(10_000, 9_000);
+        let checkpoint = PaymentCheckpoint::new(&original, false, &[false]).unwrap();
+        assert_eq!(checkpoint.fees, 1_000);
+        assert_eq!(checkpoint.flow.net(), -10_000);
+
+        // The receiver adds an input of 20'000 sats to its own output
+        let mut proposal = original.clone();
+        let other = make_psbt(20_000, 0);
+        proposal
+            .unsigned_tx
+            .input
+            .push(other.unsigned_tx.input[0].clone());
+        proposal.inputs.push(other.inputs[0].clone());
+        proposal.unsigned_tx.output[0].value = 28_800;
+
+        let delta = payjoin_delta(&checkpoint, &proposal, false, &[true, false], &[false]).unwrap();
+        assert_eq!(delta.added_inputs, 1);
+        assert_eq!(delta.fees, 1_200);
+        assert_eq!(delta.extra_cost(), 0);
+
+        // Added inputs can't be ours
+        assert_eq!(
+            payjoin_delta(&checkpoint, &proposal, false, &[true, true], &[false]),
+            Err(PsbtError::PayjoinMismatch)
+        );
+
+        // The payment can't be reduced
+        proposal.unsigned_tx.output[0].value = 8_000;
+        assert_eq!(
+            payjoin_delta(&checkpoint, &proposal, false, &[true, false], &[false]),
+            Err(PsbtError::PayjoinMismatch)
+        );
+
+        // All the original inputs must be spent
+        let mut proposal = other;
+        proposal.unsigned_tx.output[0].value = 9_000;
+        proposal.unsigned_tx.output[0].script_pubkey =
+            original.unsigned_tx.output[0].script_pubkey.clone();
+        assert_eq!(
+            payjoin_delta(&checkpoint, &proposal, false, &[false], &[false]),
+            Err(PsbtError::PayjoinMismatch)
+        );
+    }
+
+    #[test]
+    fn test_payjoin_receipt() {
+        let mut original = make_psbt(10_000, 6_000);
+        original.unsigned_tx.output.push(TxOut {
+            value: 3_000,
+            script_pubkey: Script::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros()),
+        });
+        original.outputs.push(Default::default());
+        let original_tx = PayjoinOriginal::parse(&bitcoin::consensus::encode::serialize(
+            &original.unsigned_tx,
+        ))
+        .unwrap();
+
+        // We add an input of 20'000 sats to our output, paying 200 sats for its fees
+        let mut proposal = original.clone();
+        let other = make_psbt(20_000, 0);
+        proposal
+            .unsigned_tx
+            .input
+            .push(other.unsigned_tx.input[0].clone());
+        proposal.inputs.push(other.inputs[0].clone());
+        proposal.unsigned_tx.output[0].value = 25_800;
+
+        let receipt = payjoin_receipt(
+            &original_tx,
+            &proposal,
+            false,
+            &[false, true],
+            &[true, false],
+        )
+        .unwrap();
+        assert_eq!(receipt.contributed_inputs, 1);
+        assert_eq!(receipt.flow.sent, 20_000);
+        assert_eq!(receipt.flow.net(), 5_800);
+        assert_eq!(receipt.original_received, 6_000);
+        assert_eq!(receipt.fee_share(), 200);
+        assert_eq!(receipt.fees, 1_200);
+
+        // The payer can pay for some of the higher fees
+        proposal.unsigned_tx.output[1].value = 2_900;
+        assert!(payjoin_receipt(
+            &original_tx,
+            &proposal,
+            false,
+            &[false, true],
+            &[true, false]
+        )
+        .is_ok());
+
+        // But its outputs can't be moved to ours
+        proposal.unsigned_tx.output[0].value = 26_800;
+        proposal.unsigned_tx.output[1].value = 2_000;
+        assert_eq!(
+            payjoin_receipt(
+                &original_tx,
+                &proposal,
+                false,
+                &[false, true],
+                &[true, false]
+            ),
+            Err(PsbtError::PayjoinMismatch)
+        );
+        proposal.unsigned_tx.output[0].value = 25_800;
+        proposal.unsigned_tx.output[1].value = 3_000;
+
+        // The inputs of the payer can't be ours, and we must add one
+        assert_eq!(
+            payjoin_receipt(
+                &original_tx,
+                &proposal,
+                false,
+                &[true, true],
+                &[true, false]
+            ),
+            Err(PsbtError::PayjoinMismatch)
+        );
+        assert_eq!(
+            payjoin_receipt(
+                &original_tx,
+                &proposal,
+                false,
+                &[false, false],
+                &[true, false]
+            ),
+            Err(PsbtError::PayjoinMismatch)
+        );
+
+        // The original must pay us, and new outputs must be ours
+        assert_eq!(
+            payjoin_receipt(
+                &original_tx,
+                &proposal,
+                false,
+                &[false, true],
+                &[false, false]
+            ),
+            Err(PsbtError::PayjoinMismatch)
+        );
+        proposal.unsigned_tx.output.push(TxOut {
+            value: 0,
+            script_pubkey: Script::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros()),
+        });
+        proposal.outputs.push(Default::default());
+        assert_eq!(
+            payjoin_receipt(
+                &original_tx,
+                &proposal,
+                false,
+                &[false, true],
+                &[true, false, false]
+            ),
+            Err(PsbtError::PayjoinMismatch)
+        );
+    }
+
+    #[test]
+    fn test_fee_bump() {
+        let mut original = make_psbt(10_000, 6_000);
+        original.unsigned_tx.output.push(TxOut {
+            value: 3_000,
+            script_pubkey: Script::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros()),
+        });
+        original.outputs.push(Default::default());
+        let our_outputs = [false, true];
+        let checkpoint = PaymentCheckpoint::new(&original, false, &our_outputs).unwrap();
+
+        // Lower change, higher fees
+        let mut bump = original.clone();
+        bump.unsigned_tx.output[1].value = 2_500;
+        assert_eq!(
+            fee_bump(&checkpoint, &bump, 1_500, &our_outputs),
+            Some(FeeBump {
+                old_fees: 1_000,
+                new_fees: 1_500
+            })
+        );
+        assert_eq!(fee_bump(&checkpoint, &original, 1_000, &our_outputs), None);
+
+        // The payment can't change
+        bump.unsigned_tx.output[0].value = 5_500;
+        bump.unsigned_tx.output[1].value = 3_000;
+        assert_eq!(fee_bump(&checkpoint, &bump, 1_500, &our_outputs), None);
+
+        // Neither can the inputs
+        let other = make_psbt(20_000, 0);
+        let mut bump = original.clone();
+        bump.unsigned_tx
+            .input
+            .push(other.unsigned_tx.input[0].clone());
+        assert_eq!(fee_bump(&checkpoint, &bump, 21_000, &our_outputs), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            parse_psbt(&[0x70, 0x73, 0x62, 0x74, 0xFF]).unwrap_err(),
+            PsbtError::InvalidEncoding
+        );
+    }
+
+    #[test]
+    fn test_output_label() {
+        use bitcoin::util::psbt::raw::ProprietaryKey;
+
+        let key = |prefix: &[u8], subtype| ProprietaryKey {
+            prefix: prefix.to_vec(),
+            subtype,
+            key: vec![],
+        };
+
+        let mut output = psbt::Output::default();
+        assert_eq!(output_label(&output), None);
+
+        output
+            .proprietary
+            .insert(key(b"other", PROPRIETARY_OUT_LABEL), b"Other".to_vec());
+        output
+            .proprietary
+            .insert(key(PROPRIETARY_PREFIX, 0x01), b"Unknown".to_vec());
+        assert_eq!(output_label(&output), None);
+
+        output.proprietary.insert(
+            key(PROPRIETARY_PREFIX, PROPRIETARY_OUT_LABEL),
+            b"Rent".to_vec(),
+        );
+        assert_eq!(output_label(&output), Some("Rent"));
+
+        output.proprietary.insert(
+            key(PROPRIETARY_PREFIX, PROPRIETARY_OUT_LABEL),
+            [b'a'; 40].to_vec(),
+        );
+        assert_eq!(output_label(&output).map(str::len), Some(MAX_LABEL_LEN));
+
+        output.proprietary.insert(
+            key(PROPRIETARY_PREFIX, PROPRIETARY_OUT_LABEL),
+            "Caf\u{e9}\n".as_bytes().to_vec(),
+        );
+        assert_eq!(output_label(&output), None);
+    }
+
+    #[test]
+    fn test_output_destinations() {
+        use bitcoin::blockdata::opcodes::all::{OP_CHECKSIG, OP_RETURN};
+        use bitcoin::blockdata::script::Builder;
+
+        let mut psbt = make_psbt(10_000, 9_000);
+        let op_return = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(b"hello")
+            .into_script();
+        psbt.unsigned_tx.output.push(TxOut {
+            value: 0,
+            script_pubkey: op_return,
+        });
+
+        let destinations = output_destinations(&psbt, Network::Bitcoin).unwrap();
+        assert!(matches!(destinations[0], OutputDestination::Address(_)));
+        assert_eq!(
+            destinations[1],
+            OutputDestination::OpReturn(b"hello".to_vec())
+        );
+
+        let bare = Builder::new().push_opcode(OP_RETURN).into_script();
+        assert_eq!(op_return_data(&bare), Some(vec![]));
+        let non_push = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        assert_eq!(op_return_data(&non_push), None);
+
+        psbt.unsigned_tx.output[1].script_pubkey = non_push;
+        assert_eq!(
+            output_destinations(&psbt, Network::Bitcoin),
+            Err(PsbtError::NonStandardOutput)
+        );
+    }
+
+    #[test]
+    fn test_timelock() {
+        let mut psbt = make_psbt(10_000, 9_000);
+        psbt.unsigned_tx.input[0].sequence = bitcoin::Sequence::MAX;
+        psbt.unsigned_tx.lock_time = bitcoin::PackedLockTime(850_000);
+        assert_eq!(
+            TxTimelock::new(&psbt),
+            TxTimelock {
+                lock_time: TxLockTime::None,
+                rbf: false
+            }
+        );
+
+        psbt.unsigned_tx.input[0].sequence = bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME;
+        assert_eq!(
+            TxTimelock::new(&psbt),
+            TxTimelock {
+                lock_time: TxLockTime::Height(850_000),
+                rbf: true
+            }
+        );
+
+        psbt.unsigned_tx.input[0].sequence = bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF;
+        psbt.unsigned_tx.lock_time = bitcoin::PackedLockTime(1_706_702_400);
+        assert_eq!(
+            TxTimelock::new(&psbt),
+            TxTimelock {
+                lock_time: TxLockTime::Time(1_706_702_400),
+                rbf: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00");
+        assert_eq!(format_timestamp(1_706_702_400), "2024-01-31 12:00");
+        assert_eq!(format_timestamp(u32::MAX), "2106-02-07 06:28");
+    }
+
+    #[test]
+    fn test_output_type() {
+        use core::str::FromStr;
+
+        let cases = [
+            (
+                "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr",
+                OutputType::P2tr,
+            ),
+            (
+                "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+                OutputType::P2wpkh,
+            ),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", OutputType::P2sh),
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", OutputType::P2pkh),
+        ];
+        for (address, expected) in cases {
+            let address = Address::from_str(address).unwrap();
+            assert_eq!(OutputType::from_address(&address), expected);
+        }
+
+        assert!(OutputType::P2pkh.needs_warning(LARGE_LEGACY_AMOUNT));
+        assert!(!OutputType::P2pkh.needs_warning(LARGE_LEGACY_AMOUNT - 1));
+        assert!(OutputType::Unknown.needs_warning(LARGE_LEGACY_AMOUNT));
+        assert!(!OutputType::P2tr.needs_warning(u64::MAX));
+        assert!(!OutputType::P2sh.needs_warning(u64::MAX));
+    }
+}
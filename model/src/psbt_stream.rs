@@ -0,0 +1,469 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Streaming PSBT parsing
+//!
+//! Most of the size of a large PSBT comes from the previous transactions of its inputs
+//! (`non_witness_utxo`), which are only needed to check the amounts being spent. `PsbtStream` is
+//! fed the PSBT in parts, as they are received (see `Request::SignPsbtPart`), and only keeps the
+//! map being parsed: as soon as an input map is complete its previous transaction is checked
+//! against the outpoint of the input and, for segwit outputs, replaced with the output it spends
+//! (`witness_utxo`). This way only one previous transaction is in memory at a time, and the PSBT
+//! parsed at the end is about as large as the unsigned transaction.
+//!
+//! Legacy outputs are kept as they are, since their signatures need the whole previous
+//! transaction.
+
+use alloc::vec::Vec;
+
+use bitcoin::consensus::encode::{deserialize, serialize};
+use bitcoin::hashes::Hash;
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+
+use crate::psbt::PsbtError;
+use crate::psbt_v2::{self, RawMap};
+
+/// Length of the parts a PSBT is split into by the host, see `Request::SignPsbtPart`
+pub const PART_LEN: usize = 4096;
+
+const PSBT_IN_NON_WITNESS_UTXO: u64 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u64 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Magic,
+    Global,
+    Input(usize),
+    Output(usize),
+    Done,
+}
+
+/// A PSBT parsed while it's received, see the module documentation
+#[derive(Debug)]
+pub struct PsbtStream {
+    state: State,
+    /// Bytes received but not parsed yet, at most one field
+    buf: Vec<u8>,
+    /// Fields of the map being parsed
+    map: RawMap,
+    /// The PSBT with the previous transactions replaced
+    out: Vec<u8>,
+    /// Outpoints of the inputs, only known in advance for version 0
+    outpoints: Option<Vec<OutPoint>>,
+    inputs: usize,
+    outputs: usize,
+    utxos_checked: bool,
+    error: Option<PsbtError>,
+}
+
+impl Default for PsbtStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PsbtStream {
+    pub fn new() -> Self {
+        PsbtStream {
+            state: State::Magic,
+            buf: Vec::new(),
+            map: Vec::new(),
+            out: Vec::new(),
+            outpoints: None,
+            inputs: 0,
+            outputs: 0,
+            utxos_checked: true,
+            error: None,
+        }
+    }
+
+    /// Parse the next part of the PSBT
+    ///
+    /// Errors are only reported by `finish()`, once the whole PSBT has been received.
+    pub fn push(&mut self, data: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        self.buf.extend_from_slice(data);
+        if let Err(e) = self.process() {
+            self.error = Some(e);
+            self.buf = Vec::new();
+            self.map = Vec::new();
+            self.out = Vec::new();
+        }
+    }
+
+    /// Parse the PSBT received, returning it together with whether the previous transaction of
+    /// every input was checked
+    ///
+    /// When that's the case `witness_utxo` can be trusted for all the inputs, including the ones
+    /// whose previous transaction was dropped.
+    pub fn finish(self) -> Result<(PartiallySignedTransaction, bool), PsbtError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if self.state != State::Done || !self.buf.is_empty() {
+            return Err(PsbtError::InvalidEncoding);
+        }
+
+        let psbt = crate::psbt::parse_psbt(&self.out)?;
+        Ok((psbt, self.utxos_checked))
+    }
+
+    fn process(&mut self) -> Result<(), PsbtError> {
+        if self.state == State::Magic {
+            if self.buf.len() < psbt_v2::PSBT_MAGIC.len() {
+                return Ok(());
+            }
+            if !self.buf.starts_with(&psbt_v2::PSBT_MAGIC) {
+                return Err(PsbtError::InvalidEncoding);
+            }
+
+            self.buf.drain(..psbt_v2::PSBT_MAGIC.len());
+            self.out.extend_from_slice(&psbt_v2::PSBT_MAGIC);
+            self.state = State::Global;
+        }
+
+        let mut consumed = 0;
+        while let Some((len, field)) = read_field(&self.buf[consumed..])? {
+            consumed += len;
+
+            match field {
+                _ if self.state == State::Done => return Err(PsbtError::InvalidEncoding),
+                Some((key_type, key, value)) => {
+                    if self.map.iter().any(|(t, k, _)| *t == key_type && *k == key) {
+                        return Err(PsbtError::InvalidEncoding);
+                    }
+                    self.map.push((key_type, key, value));
+                }
+                None => self.end_map()?,
+            }
+        }
+        self.buf.drain(..consumed);
+
+        Ok(())
+    }
+
+    fn end_map(&mut self) -> Result<(), PsbtError> {
+        let mut map = core::mem::take(&mut self.map);
+
+        let next = match self.state {
+            State::Global => {
+                match psbt_v2::get_u32(&map, psbt_v2::PSBT_GLOBAL_VERSION)?.unwrap_or(0) {
+                    0 => {
+                        let tx: Transaction = psbt_v2::get(&map, psbt_v2::PSBT_GLOBAL_UNSIGNED_TX)
+                            .and_then(|tx| deserialize(tx).ok())
+                            .ok_or(PsbtError::InvalidEncoding)?;
+                        self.inputs = tx.input.len();
+                        self.outputs = tx.output.len();
+                        self.outpoints =
+                            Some(tx.input.iter().map(|txin| txin.previous_output).collect());
+                    }
+                    2 => {
+                        self.inputs = psbt_v2::get_count(&map, psbt_v2::PSBT_GLOBAL_INPUT_COUNT)?;
+                        self.outputs = psbt_v2::get_count(&map, psbt_v2::PSBT_GLOBAL_OUTPUT_COUNT)?;
+                    }
+                    _ => return Err(PsbtError::InvalidEncoding),
+                }
+
+                self.first_after_inputs(0)
+            }
+            State::Input(index) => {
+                self.compact_input(index, &mut map)?;
+                self.first_after_inputs(index + 1)
+            }
+            State::Output(index) if index + 1 < self.outputs => State::Output(index + 1),
+            State::Output(_) => State::Done,
+            State::Magic | State::Done => unreachable!(),
+        };
+
+        psbt_v2::encode_map(&map, &mut self.out);
+        self.state = next;
+        Ok(())
+    }
+
+    /// State after the input map `index - 1`
+    fn first_after_inputs(&self, index: usize) -> State {
+        if index < self.inputs {
+            State::Input(index)
+        } else if self.outputs > 0 {
+            State::Output(0)
+        } else {
+            State::Done
+        }
+    }
+
+    /// Check the previous transaction of input `index` and replace it with the output spent
+    fn compact_input(&mut self, index: usize, map: &mut RawMap) -> Result<(), PsbtError> {
+        let position = map
+            .iter()
+            .position(|(t, k, _)| *t == PSBT_IN_NON_WITNESS_UTXO && k.is_empty());
+        let position = match position {
+            Some(position) => position,
+            None => {
+                self.utxos_checked = false;
+                return Ok(());
+            }
+        };
+
+        let outpoint = match &self.outpoints {
+            Some(outpoints) => outpoints[index],
+            None => {
+                let txid = psbt_v2::get(map, psbt_v2::PSBT_IN_PREVIOUS_TXID)
+                    .and_then(|txid| Txid::from_slice(txid).ok())
+                    .ok_or(PsbtError::InvalidEncoding)?;
+                let vout = psbt_v2::get_u32(map, psbt_v2::PSBT_IN_OUTPUT_INDEX)?
+                    .ok_or(PsbtError::InvalidEncoding)?;
+                OutPoint::new(txid, vout)
+            }
+        };
+
+        let prev_tx: Transaction =
+            deserialize(&map[position].2).map_err(|_| PsbtError::InvalidNonWitnessUtxo)?;
+        if prev_tx.txid() != outpoint.txid {
+            return Err(PsbtError::InvalidNonWitnessUtxo);
+        }
+        let utxo = prev_tx
+            .output
+            .into_iter()
+            .nth(outpoint.vout as usize)
+            .ok_or(PsbtError::InvalidNonWitnessUtxo)?;
+
+        let witness_utxo = psbt_v2::get(map, PSBT_IN_WITNESS_UTXO)
+            .map(|utxo| deserialize::<TxOut>(utxo).map_err(|_| PsbtError::InvalidEncoding))
+            .transpose()?;
+        match witness_utxo {
+            Some(witness_utxo) if witness_utxo != utxo => return Err(PsbtError::InconsistentUtxo),
+            Some(_) => {}
+            None if utxo.script_pubkey.is_witness_program() => {
+                map.push((PSBT_IN_WITNESS_UTXO, Vec::new(), serialize(&utxo)))
+            }
+            None => {}
+        }
+
+        if utxo.script_pubkey.is_witness_program() {
+            map.remove(position);
+        }
+
+        Ok(())
+    }
+}
+
+/// Read a compact size, `None` if `data` is too short
+fn read_compact_size(data: &[u8]) -> Result<Option<(u64, usize)>, PsbtError> {
+    let (len, min) = match data.first() {
+        None => return Ok(None),
+        Some(byte @ 0..=0xFC) => return Ok(Some((*byte as u64, 1))),
+        Some(0xFD) => (2, 0xFD),
+        Some(0xFE) => (4, 0x10000),
+        Some(0xFF) => (8, 0x100000000),
+    };
+    let bytes = match data.get(1..1 + len) {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+
+    let mut value = [0u8; 8];
+    value[..len].copy_from_slice(bytes);
+    let value = u64::from_le_bytes(value);
+    if value < min {
+        return Err(PsbtError::InvalidEncoding);
+    }
+
+    Ok(Some((value, 1 + len)))
+}
+
+/// Read a length-prefixed field of `data`, starting at `offset`
+fn read_bytes(data: &[u8], offset: usize) -> Result<Option<(&[u8], usize)>, PsbtError> {
+    let (len, prefix) = match read_compact_size(&data[offset..])? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let start = offset + prefix;
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| start.checked_add(len))
+        .ok_or(PsbtError::InvalidEncoding)?;
+
+    Ok(data.get(start..end).map(|bytes| (bytes, end)))
+}
+
+/// Read the next key-value pair at the start of `data`, `None` if `data` is too short
+///
+/// Returns the number of bytes read and the field, or `None` for the separator at the end of a map.
+#[allow(clippy::type_complexity)]
+fn read_field(data: &[u8]) -> Result<Option<(usize, Option<(u64, Vec<u8>, Vec<u8>)>)>, PsbtError> {
+    let (key, end) = match read_bytes(data, 0)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    if key.is_empty() {
+        return Ok(Some((end, None)));
+    }
+    let (value, end) = match read_bytes(data, end)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let (key_type, prefix) = read_compact_size(key)?.ok_or(PsbtError::InvalidEncoding)?;
+    Ok(Some((
+        end,
+        Some((key_type, key[prefix..].to_vec(), value.to_vec())),
+    )))
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use bitcoin::{PackedLockTime, Script, TxIn};
+
+    fn make_psbt(scripts: &[Script]) -> PartiallySignedTransaction {
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: scripts
+                .iter()
+                .map(|script| TxOut {
+                    value: 10_000,
+                    script_pubkey: script.clone(),
+                })
+                .collect(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: (0..scripts.len())
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(prev_tx.txid(), vout as u32),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: scripts[0].clone(),
+            }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        for input in &mut psbt.inputs {
+            input.non_witness_utxo = Some(prev_tx.clone());
+        }
+        psbt
+    }
+
+    fn stream(
+        data: &[u8],
+        part_len: usize,
+    ) -> Result<(PartiallySignedTransaction, bool), PsbtError> {
+        let mut stream = PsbtStream::new();
+        for part in data.chunks(part_len) {
+            stream.push(part);
+        }
+        stream.finish()
+    }
+
+    #[test]
+    fn test_compact() {
+        let p2wpkh = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        let p2pkh = Script::new_p2pkh(&bitcoin::PubkeyHash::all_zeros());
+        let psbt = make_psbt(&[p2wpkh.clone(), p2pkh]);
+        let data = serialize(&psbt);
+
+        for part_len in [1, 7, PART_LEN] {
+            let (compact, checked) = stream(&data, part_len).unwrap();
+            assert!(checked);
+            assert_eq!(compact.unsigned_tx, psbt.unsigned_tx);
+
+            // Segwit outputs don't need the whole transaction
+            assert_eq!(compact.inputs[0].non_witness_utxo, None);
+            assert_eq!(
+                compact.inputs[0].witness_utxo,
+                Some(TxOut {
+                    value: 10_000,
+                    script_pubkey: p2wpkh.clone(),
+                })
+            );
+            assert_eq!(compact.inputs[1], psbt.inputs[1]);
+            assert!(serialize(&compact).len() < data.len());
+        }
+    }
+
+    #[test]
+    fn test_missing_prev_tx() {
+        let p2wpkh = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        let mut psbt = make_psbt(&[p2wpkh.clone(), p2wpkh]);
+        psbt.inputs[1].witness_utxo = psbt.inputs[1]
+            .non_witness_utxo
+            .take()
+            .map(|tx| tx.output[1].clone());
+
+        let (compact, checked) = stream(&serialize(&psbt), 16).unwrap();
+        assert!(!checked);
+        assert_eq!(compact.inputs[1], psbt.inputs[1]);
+    }
+
+    #[test]
+    fn test_invalid_prev_tx() {
+        let p2wpkh = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+
+        let mut psbt = make_psbt(&[p2wpkh.clone()]);
+        psbt.unsigned_tx.input[0].previous_output.vout = 1;
+        assert_eq!(
+            stream(&serialize(&psbt), 16).unwrap_err(),
+            PsbtError::InvalidNonWitnessUtxo
+        );
+
+        let mut psbt = make_psbt(&[p2wpkh.clone()]);
+        psbt.unsigned_tx.input[0].previous_output.txid = Txid::all_zeros();
+        assert_eq!(
+            stream(&serialize(&psbt), 16).unwrap_err(),
+            PsbtError::InvalidNonWitnessUtxo
+        );
+
+        let mut psbt = make_psbt(&[p2wpkh.clone()]);
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 9_500,
+            script_pubkey: p2wpkh,
+        });
+        assert_eq!(
+            stream(&serialize(&psbt), 16).unwrap_err(),
+            PsbtError::InconsistentUtxo
+        );
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        let p2wpkh = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros());
+        let data = serialize(&make_psbt(&[p2wpkh]));
+
+        assert_eq!(
+            stream(&data[..data.len() - 1], 16).unwrap_err(),
+            PsbtError::InvalidEncoding
+        );
+        let mut extra = data.clone();
+        extra.push(0x00);
+        assert_eq!(stream(&extra, 16).unwrap_err(), PsbtError::InvalidEncoding);
+        assert_eq!(
+            stream(&data[1..], 16).unwrap_err(),
+            PsbtError::InvalidEncoding
+        );
+    }
+}
@@ -0,0 +1,477 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! PSBT version 2 (BIP-370)
+//!
+//! Version 2 PSBTs don't have an unsigned transaction in the global map: it's described by new
+//! global fields and by fields of the input and output maps instead. The version of rust-bitcoin
+//! used here only parses version 0, so `to_v0()` builds the unsigned transaction from these fields
+//! and removes them, leaving every other field as it is. Once signed, `update_inputs()` puts the
+//! input maps back into the original version 2 PSBT.
+
+use alloc::vec::Vec;
+
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::hashes::Hash;
+use bitcoin::util::psbt;
+use bitcoin::{OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Txid, VarInt};
+
+use crate::psbt::PsbtError;
+
+pub(crate) const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xFF];
+
+pub(crate) const PSBT_GLOBAL_UNSIGNED_TX: u64 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u64 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+pub(crate) const PSBT_GLOBAL_INPUT_COUNT: u64 = 0x04;
+pub(crate) const PSBT_GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u64 = 0x06;
+pub(crate) const PSBT_GLOBAL_VERSION: u64 = 0xFB;
+
+pub(crate) const PSBT_IN_PREVIOUS_TXID: u64 = 0x0E;
+pub(crate) const PSBT_IN_OUTPUT_INDEX: u64 = 0x0F;
+const PSBT_IN_SEQUENCE: u64 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
+
+const PSBT_OUT_AMOUNT: u64 = 0x03;
+const PSBT_OUT_SCRIPT: u64 = 0x04;
+
+/// Global fields that only exist in version 2
+const GLOBAL_V2_FIELDS: [u64; 6] = [
+    PSBT_GLOBAL_TX_VERSION,
+    PSBT_GLOBAL_FALLBACK_LOCKTIME,
+    PSBT_GLOBAL_INPUT_COUNT,
+    PSBT_GLOBAL_OUTPUT_COUNT,
+    PSBT_GLOBAL_TX_MODIFIABLE,
+    PSBT_GLOBAL_VERSION,
+];
+/// Input fields that only exist in version 2
+const INPUT_V2_FIELDS: [u64; 5] = [
+    PSBT_IN_PREVIOUS_TXID,
+    PSBT_IN_OUTPUT_INDEX,
+    PSBT_IN_SEQUENCE,
+    PSBT_IN_REQUIRED_TIME_LOCKTIME,
+    PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+];
+/// Output fields that only exist in version 2
+const OUTPUT_V2_FIELDS: [u64; 2] = [PSBT_OUT_AMOUNT, PSBT_OUT_SCRIPT];
+
+/// Key type, key data and value of each field of a map, in order
+pub(crate) type RawMap = Vec<(u64, Vec<u8>, Vec<u8>)>;
+
+fn parse_map(data: &mut &[u8]) -> Result<RawMap, PsbtError> {
+    let mut map: RawMap = Vec::new();
+
+    loop {
+        let key = Vec::<u8>::consensus_decode(data).map_err(|_| PsbtError::InvalidEncoding)?;
+        if key.is_empty() {
+            break;
+        }
+
+        let mut key = key.as_slice();
+        let key_type = VarInt::consensus_decode(&mut key)
+            .map_err(|_| PsbtError::InvalidEncoding)?
+            .0;
+        let value = Vec::<u8>::consensus_decode(data).map_err(|_| PsbtError::InvalidEncoding)?;
+        if map.iter().any(|(t, k, _)| *t == key_type && k == key) {
+            return Err(PsbtError::InvalidEncoding);
+        }
+
+        map.push((key_type, key.to_vec(), value));
+    }
+
+    Ok(map)
+}
+
+pub(crate) fn encode_map(map: &RawMap, data: &mut Vec<u8>) {
+    for (key_type, key, value) in map {
+        let mut full_key = Vec::new();
+        VarInt(*key_type)
+            .consensus_encode(&mut full_key)
+            .expect("Encoding to a vec succeeds");
+        full_key.extend_from_slice(key);
+
+        full_key
+            .consensus_encode(data)
+            .expect("Encoding to a vec succeeds");
+        value
+            .consensus_encode(data)
+            .expect("Encoding to a vec succeeds");
+    }
+    data.push(0x00);
+}
+
+/// Value of the field `key_type` with an empty key
+pub(crate) fn get(map: &RawMap, key_type: u64) -> Option<&[u8]> {
+    map.iter()
+        .find(|(t, k, _)| *t == key_type && k.is_empty())
+        .map(|(_, _, v)| v.as_slice())
+}
+
+pub(crate) fn get_u32(map: &RawMap, key_type: u64) -> Result<Option<u32>, PsbtError> {
+    get(map, key_type)
+        .map(|v| {
+            v.try_into()
+                .map(u32::from_le_bytes)
+                .map_err(|_| PsbtError::InvalidEncoding)
+        })
+        .transpose()
+}
+
+pub(crate) fn get_count(map: &RawMap, key_type: u64) -> Result<usize, PsbtError> {
+    let mut value = get(map, key_type).ok_or(PsbtError::InvalidEncoding)?;
+    let count = VarInt::consensus_decode(&mut value).map_err(|_| PsbtError::InvalidEncoding)?;
+    if !value.is_empty() {
+        return Err(PsbtError::InvalidEncoding);
+    }
+    usize::try_from(count.0).map_err(|_| PsbtError::InvalidEncoding)
+}
+
+fn without(map: RawMap, fields: &[u64]) -> RawMap {
+    map.into_iter()
+        .filter(|(t, _, _)| !fields.contains(t))
+        .collect()
+}
+
+/// The maps of a version 2 PSBT
+struct RawPsbt {
+    global: RawMap,
+    inputs: Vec<RawMap>,
+    outputs: Vec<RawMap>,
+}
+
+impl RawPsbt {
+    fn parse(data: &[u8]) -> Result<Self, PsbtError> {
+        let mut data = data
+            .strip_prefix(&PSBT_MAGIC)
+            .ok_or(PsbtError::InvalidEncoding)?;
+
+        let global = parse_map(&mut data)?;
+        if get_u32(&global, PSBT_GLOBAL_VERSION)? != Some(2)
+            || get(&global, PSBT_GLOBAL_UNSIGNED_TX).is_some()
+        {
+            return Err(PsbtError::InvalidEncoding);
+        }
+
+        let input_count = get_count(&global, PSBT_GLOBAL_INPUT_COUNT)?;
+        let output_count = get_count(&global, PSBT_GLOBAL_OUTPUT_COUNT)?;
+        // Every map is at least one byte long, so bogus counts are rejected before allocating
+        if input_count.saturating_add(output_count) > data.len() {
+            return Err(PsbtError::InvalidEncoding);
+        }
+        let inputs = (0..input_count)
+            .map(|_| parse_map(&mut data))
+            .collect::<Result<Vec<_>, _>>()?;
+        let outputs = (0..output_count)
+            .map(|_| parse_map(&mut data))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !data.is_empty() {
+            return Err(PsbtError::InvalidEncoding);
+        }
+
+        Ok(RawPsbt {
+            global,
+            inputs,
+            outputs,
+        })
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = PSBT_MAGIC.to_vec();
+        encode_map(&self.global, &mut data);
+        for map in self.inputs.iter().chain(self.outputs.iter()) {
+            encode_map(map, &mut data);
+        }
+        data
+    }
+
+    /// Locktime of the transaction, chosen as described in BIP-370
+    fn lock_time(&self) -> Result<u32, PsbtError> {
+        let mut time = Vec::new();
+        let mut height = Vec::new();
+        let mut all_time = true;
+        let mut all_height = true;
+        for input in &self.inputs {
+            let input_time = get_u32(input, PSBT_IN_REQUIRED_TIME_LOCKTIME)?;
+            let input_height = get_u32(input, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)?;
+            if input_time.is_none() && input_height.is_none() {
+                continue;
+            }
+
+            all_time &= input_time.is_some();
+            all_height &= input_height.is_some();
+            time.extend(input_time);
+            height.extend(input_height);
+        }
+
+        match (time.is_empty() && height.is_empty(), all_height, all_time) {
+            (true, _, _) => Ok(get_u32(&self.global, PSBT_GLOBAL_FALLBACK_LOCKTIME)?.unwrap_or(0)),
+            (false, true, _) => Ok(height.into_iter().max().unwrap_or(0)),
+            (false, false, true) => Ok(time.into_iter().max().unwrap_or(0)),
+            (false, false, false) => Err(PsbtError::InvalidEncoding),
+        }
+    }
+
+    fn unsigned_tx(&self) -> Result<Transaction, PsbtError> {
+        let version =
+            get_u32(&self.global, PSBT_GLOBAL_TX_VERSION)?.ok_or(PsbtError::InvalidEncoding)?;
+
+        let input = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let txid = get(input, PSBT_IN_PREVIOUS_TXID).ok_or(PsbtError::InvalidEncoding)?;
+                let txid = Txid::from_slice(txid).map_err(|_| PsbtError::InvalidEncoding)?;
+                let vout =
+                    get_u32(input, PSBT_IN_OUTPUT_INDEX)?.ok_or(PsbtError::InvalidEncoding)?;
+                let sequence = get_u32(input, PSBT_IN_SEQUENCE)?.unwrap_or(0xFFFFFFFF);
+
+                Ok(TxIn {
+                    previous_output: OutPoint::new(txid, vout),
+                    sequence: Sequence(sequence),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = self
+            .outputs
+            .iter()
+            .map(|output| {
+                let value = get(output, PSBT_OUT_AMOUNT)
+                    .and_then(|v| v.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .ok_or(PsbtError::InvalidEncoding)?;
+                // Amounts are signed in BIP-370
+                if value > i64::MAX as u64 {
+                    return Err(PsbtError::InvalidAmount);
+                }
+                let script = get(output, PSBT_OUT_SCRIPT).ok_or(PsbtError::InvalidEncoding)?;
+
+                Ok(TxOut {
+                    value,
+                    script_pubkey: Script::from(script.to_vec()),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Transaction {
+            version: version as i32,
+            lock_time: PackedLockTime(self.lock_time()?),
+            input,
+            output,
+        })
+    }
+}
+
+/// Whether `data` looks like a version 2 PSBT, which has to go through `to_v0()` to be parsed
+pub fn is_v2(data: &[u8]) -> bool {
+    let mut data = match data.strip_prefix(&PSBT_MAGIC) {
+        Some(data) => data,
+        None => return false,
+    };
+
+    parse_map(&mut data)
+        .ok()
+        .and_then(|global| get_u32(&global, PSBT_GLOBAL_VERSION).ok().flatten())
+        == Some(2)
+}
+
+/// Convert a version 2 PSBT into the equivalent version 0 one
+pub fn to_v0(data: &[u8]) -> Result<Vec<u8>, PsbtError> {
+    let psbt = RawPsbt::parse(data)?;
+
+    let mut unsigned_tx = Vec::new();
+    psbt.unsigned_tx()?
+        .consensus_encode(&mut unsigned_tx)
+        .expect("Encoding to a vec succeeds");
+
+    let mut global = alloc::vec![(PSBT_GLOBAL_UNSIGNED_TX, Vec::new(), unsigned_tx)];
+    global.extend(without(psbt.global, &GLOBAL_V2_FIELDS));
+
+    let v0 = RawPsbt {
+        global,
+        inputs: psbt
+            .inputs
+            .into_iter()
+            .map(|m| without(m, &INPUT_V2_FIELDS))
+            .collect(),
+        outputs: psbt
+            .outputs
+            .into_iter()
+            .map(|m| without(m, &OUTPUT_V2_FIELDS))
+            .collect(),
+    };
+    Ok(v0.serialize())
+}
+
+/// Replace the input maps of the version 2 PSBT `data` with `inputs`, which come from the PSBT
+/// returned by `to_v0()`, keeping the fields that only exist in version 2
+pub fn update_inputs(data: &[u8], inputs: &[psbt::Input]) -> Result<Vec<u8>, PsbtError> {
+    let mut psbt = RawPsbt::parse(data)?;
+    if inputs.len() != psbt.inputs.len() {
+        return Err(PsbtError::InvalidEncoding);
+    }
+
+    for (map, input) in psbt.inputs.iter_mut().zip(inputs) {
+        let mut encoded = Vec::new();
+        input
+            .consensus_encode(&mut encoded)
+            .expect("Encoding to a vec succeeds");
+        let mut updated = parse_map(&mut encoded.as_slice())?;
+
+        updated.extend(
+            map.drain(..)
+                .filter(|(t, _, _)| INPUT_V2_FIELDS.contains(t)),
+        );
+        *map = updated;
+    }
+
+    Ok(psbt.serialize())
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use bitcoin::consensus::{deserialize, serialize};
+    use bitcoin::util::psbt::PartiallySignedTransaction;
+
+    use super::*;
+
+    fn field(key_type: u64, value: &[u8]) -> (u64, Vec<u8>, Vec<u8>) {
+        (key_type, Vec::new(), value.to_vec())
+    }
+
+    fn make_v2(inputs: Vec<RawMap>) -> RawPsbt {
+        RawPsbt {
+            global: alloc::vec![
+                field(PSBT_GLOBAL_TX_VERSION, &2u32.to_le_bytes()),
+                field(PSBT_GLOBAL_FALLBACK_LOCKTIME, &100u32.to_le_bytes()),
+                field(PSBT_GLOBAL_INPUT_COUNT, &[inputs.len() as u8]),
+                field(PSBT_GLOBAL_OUTPUT_COUNT, &[1]),
+                field(PSBT_GLOBAL_VERSION, &2u32.to_le_bytes()),
+            ],
+            inputs,
+            outputs: alloc::vec![alloc::vec![
+                field(PSBT_OUT_AMOUNT, &9_000u64.to_le_bytes()),
+                field(PSBT_OUT_SCRIPT, &[0x00, 0x14, 0x42]),
+                (0xFC, b"\x03abc\x01".to_vec(), b"label".to_vec()),
+            ]],
+        }
+    }
+
+    fn input(vout: u32) -> RawMap {
+        alloc::vec![
+            field(PSBT_IN_PREVIOUS_TXID, &[0x11; 32]),
+            field(PSBT_IN_OUTPUT_INDEX, &vout.to_le_bytes()),
+            field(PSBT_IN_SEQUENCE, &0xFFFFFFFDu32.to_le_bytes()),
+        ]
+    }
+
+    #[test]
+    fn test_to_v0() {
+        let data = make_v2(alloc::vec![input(0), input(1)]).serialize();
+        assert!(is_v2(&data));
+
+        let psbt: PartiallySignedTransaction = deserialize(&to_v0(&data).unwrap()).unwrap();
+        assert!(!is_v2(&serialize(&psbt)));
+
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.version, 2);
+        assert_eq!(tx.lock_time, PackedLockTime(100));
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[1].previous_output.vout, 1);
+        assert_eq!(tx.input[1].sequence, Sequence(0xFFFFFFFD));
+        assert_eq!(tx.output[0].value, 9_000);
+        assert_eq!(tx.output[0].script_pubkey.as_bytes(), &[0x00, 0x14, 0x42]);
+        // Other fields are kept
+        assert_eq!(psbt.outputs[0].proprietary.len(), 1);
+    }
+
+    #[test]
+    fn test_lock_time() {
+        let mut first = input(0);
+        first.push(field(
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+            &800_000u32.to_le_bytes(),
+        ));
+        first.push(field(
+            PSBT_IN_REQUIRED_TIME_LOCKTIME,
+            &1_700_000_000u32.to_le_bytes(),
+        ));
+        let mut second = input(1);
+        second.push(field(
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+            &800_100u32.to_le_bytes(),
+        ));
+
+        // Height is supported by both inputs, the third one doesn't care
+        let psbt = make_v2(alloc::vec![first.clone(), second, input(2)]);
+        assert_eq!(psbt.lock_time(), Ok(800_100));
+
+        // Time is the only one supported by both inputs
+        let mut second = input(1);
+        second.push(field(
+            PSBT_IN_REQUIRED_TIME_LOCKTIME,
+            &1_700_000_100u32.to_le_bytes(),
+        ));
+        let psbt = make_v2(alloc::vec![first, second.clone()]);
+        assert_eq!(psbt.lock_time(), Ok(1_700_000_100));
+
+        // No locktime satisfies both
+        let mut first = input(0);
+        first.push(field(
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+            &800_000u32.to_le_bytes(),
+        ));
+        let psbt = make_v2(alloc::vec![first, second]);
+        assert_eq!(psbt.lock_time(), Err(PsbtError::InvalidEncoding));
+    }
+
+    #[test]
+    fn test_update_inputs() {
+        let data = make_v2(alloc::vec![input(0)]).serialize();
+        let mut psbt: PartiallySignedTransaction = deserialize(&to_v0(&data).unwrap()).unwrap();
+        psbt.inputs[0].final_script_sig = Some(Script::from(alloc::vec![0x51]));
+
+        let updated = update_inputs(&data, &psbt.inputs).unwrap();
+        assert!(is_v2(&updated));
+        let converted: PartiallySignedTransaction = deserialize(&to_v0(&updated).unwrap()).unwrap();
+        assert_eq!(converted, psbt);
+
+        assert_eq!(update_inputs(&data, &[]), Err(PsbtError::InvalidEncoding));
+    }
+
+    #[test]
+    fn test_invalid() {
+        // Missing the output count
+        let mut psbt = make_v2(alloc::vec![input(0)]);
+        psbt.global
+            .retain(|(t, _, _)| *t != PSBT_GLOBAL_OUTPUT_COUNT);
+        assert_eq!(to_v0(&psbt.serialize()), Err(PsbtError::InvalidEncoding));
+
+        // Missing the previous txid
+        let mut first = input(0);
+        first.remove(0);
+        let psbt = make_v2(alloc::vec![first]);
+        assert_eq!(to_v0(&psbt.serialize()), Err(PsbtError::InvalidEncoding));
+
+        // Duplicate keys
+        let mut psbt = make_v2(alloc::vec![input(0)]);
+        psbt.inputs[0].push(field(PSBT_IN_SEQUENCE, &0u32.to_le_bytes()));
+        assert_eq!(to_v0(&psbt.serialize()), Err(PsbtError::InvalidEncoding));
+    }
+}
@@ -0,0 +1,190 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Results of the hardware self-test
+//!
+//! Also contains the health tests run on the raw output of the hardware RNG, which are the
+//! "repetition count" and "adaptive proportion" tests described in NIST SP 800-90B, section 4.4,
+//! applied to bytes with a conservative estimate of 4 bits of entropy per byte. They run
+//! continuously, on every sample read from the RNG, see `RngHealthMonitor`.
+
+use alloc::string::String;
+
+use minicbor::{Decode, Encode};
+
+/// Cutoff of the repetition count test, for a false positive rate of 2^-40 with H = 4
+pub const REPETITION_COUNT_CUTOFF: usize = 11;
+/// Window of the adaptive proportion test for non-binary samples
+pub const ADAPTIVE_PROPORTION_WINDOW: usize = 512;
+/// Cutoff of the adaptive proportion test, for a false positive rate of 2^-40 with H = 4
+pub const ADAPTIVE_PROPORTION_CUTOFF: usize = 78;
+
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub enum TestOutcome {
+    #[cbor(n(0))]
+    Passed,
+    #[cbor(n(1))]
+    Failed(#[cbor(n(0))] String),
+    /// The test can't run on this device or in this state
+    #[cbor(n(2))]
+    Skipped,
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+#[cfg_attr(feature = "emulator", derive(serde::Serialize, serde::Deserialize))]
+pub struct SelfTestReport {
+    /// Write and read back of the config pages
+    #[cbor(n(0))]
+    pub flash: TestOutcome,
+    /// Health tests of the hardware RNG, run on the samples taken at boot
+    #[cbor(n(1))]
+    pub rng: TestOutcome,
+    /// Drawing the test patterns
+    #[cbor(n(2))]
+    pub display: TestOutcome,
+    /// Whether the user touched the button when asked to
+    #[cbor(n(3))]
+    pub touch: TestOutcome,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngHealthError {
+    RepetitionCount,
+    AdaptiveProportion,
+}
+
+impl core::fmt::Display for RngHealthError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            RngHealthError::RepetitionCount => "Repetition count test failed",
+            RngHealthError::AdaptiveProportion => "Adaptive proportion test failed",
+        };
+        f.write_str(msg)
+    }
+}
+#[cfg(not(feature = "stm32"))]
+impl std::error::Error for RngHealthError {}
+
+/// Continuous health tests, fed with every sample read from the RNG
+///
+/// Once a test fails the monitor stays failed, since the samples that follow can't be trusted
+/// either.
+#[derive(Debug, Clone, Default)]
+pub struct RngHealthMonitor {
+    last: Option<u8>,
+    run: usize,
+    reference: u8,
+    position: usize,
+    count: usize,
+    failed: Option<RngHealthError>,
+}
+
+impl RngHealthMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, sample: u8) -> Result<(), RngHealthError> {
+        if let Some(e) = self.failed {
+            return Err(e);
+        }
+
+        // Repetition count test
+        if self.last == Some(sample) {
+            self.run += 1;
+        } else {
+            self.last = Some(sample);
+            self.run = 1;
+        }
+        if self.run >= REPETITION_COUNT_CUTOFF {
+            self.failed = Some(RngHealthError::RepetitionCount);
+        }
+
+        // Adaptive proportion test, every window starts with a new reference sample
+        if self.position == 0 {
+            self.reference = sample;
+            self.count = 0;
+        }
+        if sample == self.reference {
+            self.count += 1;
+        }
+        self.position = (self.position + 1) % ADAPTIVE_PROPORTION_WINDOW;
+        if self.count >= ADAPTIVE_PROPORTION_CUTOFF {
+            self.failed
+                .get_or_insert(RngHealthError::AdaptiveProportion);
+        }
+
+        self.failed.map_or(Ok(()), Err)
+    }
+
+    pub fn feed_all(&mut self, samples: &[u8]) -> Result<(), RngHealthError> {
+        samples.iter().try_for_each(|s| self.feed(*s))
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.failed.is_none()
+    }
+}
+
+/// Run the health tests on raw samples from the RNG
+pub fn rng_health(samples: &[u8]) -> Result<(), RngHealthError> {
+    RngHealthMonitor::new().feed_all(samples)
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_health_ok() {
+        // Not random at all, but it never repeats
+        let samples = (0..2048)
+            .map(|i| (i * 7) as u8)
+            .collect::<alloc::vec::Vec<_>>();
+        assert_eq!(rng_health(&samples), Ok(()));
+    }
+
+    #[test]
+    fn test_rng_stuck() {
+        let mut samples = (0..2048).map(|i| i as u8).collect::<alloc::vec::Vec<_>>();
+        samples[100..100 + REPETITION_COUNT_CUTOFF].fill(0x42);
+        assert_eq!(rng_health(&samples), Err(RngHealthError::RepetitionCount));
+    }
+
+    #[test]
+    fn test_rng_monitor_stays_failed() {
+        let mut monitor = RngHealthMonitor::new();
+        monitor
+            .feed_all(&[0x42; REPETITION_COUNT_CUTOFF - 1])
+            .unwrap();
+        assert_eq!(monitor.feed(0x42), Err(RngHealthError::RepetitionCount));
+        assert_eq!(monitor.feed(0x43), Err(RngHealthError::RepetitionCount));
+        assert!(!monitor.is_healthy());
+    }
+
+    #[test]
+    fn test_rng_biased() {
+        let samples = (0..ADAPTIVE_PROPORTION_WINDOW)
+            .map(|i| if i % 4 == 0 { 0x00 } else { i as u8 | 0x01 })
+            .collect::<alloc::vec::Vec<_>>();
+        assert_eq!(
+            rng_health(&samples),
+            Err(RngHealthError::AdaptiveProportion)
+        );
+    }
+}
@@ -0,0 +1,725 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! User settings, changed from the settings menu on the device
+//!
+//! The settings are stored unencrypted in the config, so that they also apply while the device is
+//! locked.
+
+use minicbor::{Decode, Encode};
+
+/// A setting that can be changed from the menu, cycling through all its values
+pub trait SettingValue: Copy + PartialEq + 'static {
+    /// All the values, in the order they are shown
+    const ALL: &'static [Self];
+
+    fn name(&self) -> &'static str;
+
+    fn next(self) -> Self {
+        let pos = Self::ALL.iter().position(|v| *v == self).unwrap_or(0);
+        Self::ALL[(pos + 1) % Self::ALL.len()]
+    }
+}
+
+/// How quickly the confirmation bar fills while the button is held
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ConfirmSpeed {
+    #[cbor(n(0))]
+    Slow,
+    #[default]
+    #[cbor(n(1))]
+    Normal,
+    #[cbor(n(2))]
+    Fast,
+}
+
+impl ConfirmSpeed {
+    /// Value added to the confirmation bar on every tick
+    pub fn step(&self) -> u32 {
+        match self {
+            ConfirmSpeed::Slow => 10,
+            ConfirmSpeed::Normal => 15,
+            ConfirmSpeed::Fast => 25,
+        }
+    }
+}
+
+impl SettingValue for ConfirmSpeed {
+    const ALL: &'static [Self] = &[ConfirmSpeed::Slow, ConfirmSpeed::Normal, ConfirmSpeed::Fast];
+
+    fn name(&self) -> &'static str {
+        match self {
+            ConfirmSpeed::Slow => "Slow",
+            ConfirmSpeed::Normal => "Normal",
+            ConfirmSpeed::Fast => "Fast",
+        }
+    }
+}
+
+/// How quickly long addresses scroll on the screen
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ScrollSpeed {
+    #[cbor(n(0))]
+    Slow,
+    #[default]
+    #[cbor(n(1))]
+    Normal,
+    #[cbor(n(2))]
+    Fast,
+}
+
+impl ScrollSpeed {
+    /// Number of scrolling steps to take on the `tick`-th tick
+    pub fn steps(&self, tick: usize) -> usize {
+        match self {
+            ScrollSpeed::Slow => tick % 2,
+            ScrollSpeed::Normal => 1,
+            ScrollSpeed::Fast => 2,
+        }
+    }
+}
+
+impl SettingValue for ScrollSpeed {
+    const ALL: &'static [Self] = &[ScrollSpeed::Slow, ScrollSpeed::Normal, ScrollSpeed::Fast];
+
+    fn name(&self) -> &'static str {
+        match self {
+            ScrollSpeed::Slow => "Slow",
+            ScrollSpeed::Normal => "Normal",
+            ScrollSpeed::Fast => "Fast",
+        }
+    }
+}
+
+/// How long the device stays unlocked without receiving any request
+///
+/// Only applies to devices with a pair code, since the others can't be locked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum AutoLock {
+    #[default]
+    #[cbor(n(0))]
+    Never,
+    #[cbor(n(1))]
+    OneMinute,
+    #[cbor(n(2))]
+    FiveMinutes,
+    #[cbor(n(3))]
+    FifteenMinutes,
+}
+
+impl AutoLock {
+    pub fn seconds(&self) -> Option<u32> {
+        match self {
+            AutoLock::Never => None,
+            AutoLock::OneMinute => Some(60),
+            AutoLock::FiveMinutes => Some(5 * 60),
+            AutoLock::FifteenMinutes => Some(15 * 60),
+        }
+    }
+}
+
+impl SettingValue for AutoLock {
+    const ALL: &'static [Self] = &[
+        AutoLock::Never,
+        AutoLock::OneMinute,
+        AutoLock::FiveMinutes,
+        AutoLock::FifteenMinutes,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            AutoLock::Never => "Never",
+            AutoLock::OneMinute => "1 min",
+            AutoLock::FiveMinutes => "5 min",
+            AutoLock::FifteenMinutes => "15 min",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Brightness {
+    #[default]
+    #[cbor(n(0))]
+    Low,
+    #[cbor(n(1))]
+    Medium,
+    #[cbor(n(2))]
+    High,
+}
+
+impl SettingValue for Brightness {
+    const ALL: &'static [Self] = &[Brightness::Low, Brightness::Medium, Brightness::High];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Brightness::Low => "Low",
+            Brightness::Medium => "Medium",
+            Brightness::High => "High",
+        }
+    }
+}
+
+/// Font used for amounts, addresses and fees
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum TextSize {
+    #[default]
+    #[cbor(n(0))]
+    Normal,
+    /// Larger font, split over multiple pages when the text doesn't fit
+    #[cbor(n(1))]
+    Large,
+}
+
+impl SettingValue for TextSize {
+    const ALL: &'static [Self] = &[TextSize::Normal, TextSize::Large];
+
+    fn name(&self) -> &'static str {
+        match self {
+            TextSize::Normal => "Normal",
+            TextSize::Large => "Large",
+        }
+    }
+}
+
+/// What the device shows while it's waiting for requests
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum IdleScreen {
+    #[default]
+    #[cbor(n(0))]
+    Plain,
+    /// Fingerprint of the wallet and an identicon derived from it
+    #[cbor(n(1))]
+    Fingerprint,
+}
+
+impl SettingValue for IdleScreen {
+    const ALL: &'static [Self] = &[IdleScreen::Plain, IdleScreen::Fingerprint];
+
+    fn name(&self) -> &'static str {
+        match self {
+            IdleScreen::Plain => "Plain",
+            IdleScreen::Fingerprint => "Fingerprint",
+        }
+    }
+}
+
+/// Whether the inputs of a transaction are shown before its outputs when signing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ReviewInputs {
+    #[default]
+    #[cbor(n(0))]
+    Off,
+    /// Show the outpoint, the value and the owner of every input
+    #[cbor(n(1))]
+    On,
+}
+
+impl SettingValue for ReviewInputs {
+    const ALL: &'static [Self] = &[ReviewInputs::Off, ReviewInputs::On];
+
+    fn name(&self) -> &'static str {
+        match self {
+            ReviewInputs::Off => "Off",
+            ReviewInputs::On => "On",
+        }
+    }
+}
+
+/// How light a touch is enough to press the button
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum TouchSensitivity {
+    #[cbor(n(0))]
+    Low,
+    #[default]
+    #[cbor(n(1))]
+    Normal,
+    /// For thick cases or gloves
+    #[cbor(n(2))]
+    High,
+}
+
+impl TouchSensitivity {
+    /// Position of the threshold between the touched and the released readings, in percent
+    fn percent(&self) -> u32 {
+        match self {
+            TouchSensitivity::Low => 30,
+            TouchSensitivity::Normal => 50,
+            TouchSensitivity::High => 70,
+        }
+    }
+}
+
+impl SettingValue for TouchSensitivity {
+    const ALL: &'static [Self] = &[
+        TouchSensitivity::Low,
+        TouchSensitivity::Normal,
+        TouchSensitivity::High,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            TouchSensitivity::Low => "Low",
+            TouchSensitivity::Normal => "Normal",
+            TouchSensitivity::High => "High",
+        }
+    }
+}
+
+/// How long an operation requested by the host waits for the user before being aborted
+///
+/// The window restarts every time the button is touched or a request is received.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum OperationTimeout {
+    #[cbor(n(0))]
+    Never,
+    #[cbor(n(1))]
+    OneMinute,
+    #[default]
+    #[cbor(n(2))]
+    TwoMinutes,
+    #[cbor(n(3))]
+    FiveMinutes,
+}
+
+impl OperationTimeout {
+    pub fn seconds(&self) -> Option<u32> {
+        match self {
+            OperationTimeout::Never => None,
+            OperationTimeout::OneMinute => Some(60),
+            OperationTimeout::TwoMinutes => Some(2 * 60),
+            OperationTimeout::FiveMinutes => Some(5 * 60),
+        }
+    }
+}
+
+impl SettingValue for OperationTimeout {
+    const ALL: &'static [Self] = &[
+        OperationTimeout::Never,
+        OperationTimeout::OneMinute,
+        OperationTimeout::TwoMinutes,
+        OperationTimeout::FiveMinutes,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            OperationTimeout::Never => "Never",
+            OperationTimeout::OneMinute => "1 min",
+            OperationTimeout::TwoMinutes => "2 min",
+            OperationTimeout::FiveMinutes => "5 min",
+        }
+    }
+}
+
+/// Whether the last log lines are kept in RAM, to be exported with `Request::GetLogs`
+///
+/// Meant to help reproducing issues, see `logs`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum DebugLogs {
+    #[default]
+    #[cbor(n(0))]
+    Off,
+    #[cbor(n(1))]
+    On,
+}
+
+impl SettingValue for DebugLogs {
+    const ALL: &'static [Self] = &[DebugLogs::Off, DebugLogs::On];
+
+    fn name(&self) -> &'static str {
+        match self {
+            DebugLogs::Off => "Off",
+            DebugLogs::On => "On",
+        }
+    }
+}
+
+/// Fees, as a percentage of the value of the outputs, above which a warning is shown before
+/// signing a transaction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum FeeWarningPercent {
+    #[cbor(n(0))]
+    Off,
+    #[cbor(n(1))]
+    Five,
+    #[default]
+    #[cbor(n(2))]
+    Ten,
+    #[cbor(n(3))]
+    TwentyFive,
+}
+
+impl FeeWarningPercent {
+    pub fn percent(&self) -> Option<u64> {
+        match self {
+            FeeWarningPercent::Off => None,
+            FeeWarningPercent::Five => Some(5),
+            FeeWarningPercent::Ten => Some(10),
+            FeeWarningPercent::TwentyFive => Some(25),
+        }
+    }
+
+    /// Whether `fees` are above the threshold for outputs worth `output_value` in total
+    pub fn exceeded(&self, fees: u64, output_value: u64) -> bool {
+        self.percent()
+            .is_some_and(|percent| fees as u128 * 100 > output_value as u128 * percent as u128)
+    }
+}
+
+impl SettingValue for FeeWarningPercent {
+    const ALL: &'static [Self] = &[
+        FeeWarningPercent::Off,
+        FeeWarningPercent::Five,
+        FeeWarningPercent::Ten,
+        FeeWarningPercent::TwentyFive,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            FeeWarningPercent::Off => "Off",
+            FeeWarningPercent::Five => "5%",
+            FeeWarningPercent::Ten => "10%",
+            FeeWarningPercent::TwentyFive => "25%",
+        }
+    }
+}
+
+/// Fee rate above which a warning is shown before signing a transaction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum FeeWarningRate {
+    #[cbor(n(0))]
+    Off,
+    #[cbor(n(1))]
+    OneHundred,
+    #[default]
+    #[cbor(n(2))]
+    FiveHundred,
+    #[cbor(n(3))]
+    OneThousand,
+}
+
+impl FeeWarningRate {
+    pub fn sat_per_vb(&self) -> Option<u32> {
+        match self {
+            FeeWarningRate::Off => None,
+            FeeWarningRate::OneHundred => Some(100),
+            FeeWarningRate::FiveHundred => Some(500),
+            FeeWarningRate::OneThousand => Some(1000),
+        }
+    }
+
+    /// Whether `fee_rate`, in sat/vB, is above the threshold
+    pub fn exceeded(&self, fee_rate: f32) -> bool {
+        self.sat_per_vb().is_some_and(|max| fee_rate > max as f32)
+    }
+}
+
+impl SettingValue for FeeWarningRate {
+    const ALL: &'static [Self] = &[
+        FeeWarningRate::Off,
+        FeeWarningRate::OneHundred,
+        FeeWarningRate::FiveHundred,
+        FeeWarningRate::OneThousand,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            FeeWarningRate::Off => "Off",
+            FeeWarningRate::OneHundred => "100 sat/vB",
+            FeeWarningRate::FiveHundred => "500 sat/vB",
+            FeeWarningRate::OneThousand => "1000 sat/vB",
+        }
+    }
+}
+
+/// What to do with transactions that ask to sign an input with a sighash other than
+/// `SIGHASH_ALL`, see `psbt::non_default_sighashes()`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum NonDefaultSighash {
+    #[default]
+    #[cbor(n(0))]
+    Reject,
+    /// Sign them after a warning page for every such input
+    #[cbor(n(1))]
+    Warn,
+}
+
+impl SettingValue for NonDefaultSighash {
+    const ALL: &'static [Self] = &[NonDefaultSighash::Reject, NonDefaultSighash::Warn];
+
+    fn name(&self) -> &'static str {
+        match self {
+            NonDefaultSighash::Reject => "Reject",
+            NonDefaultSighash::Warn => "Warn",
+        }
+    }
+}
+
+/// Which inputs have to include the whole previous transaction (`non_witness_utxo`)
+///
+/// Segwit v0 wallets always need it, since their signatures only commit to the amount of the input
+/// being signed. Taproot signatures commit to all the amounts, so `witness_utxo` is enough for
+/// taproot wallets unless this is `Strict`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum UtxoVerification {
+    #[default]
+    #[cbor(n(0))]
+    Standard,
+    #[cbor(n(1))]
+    Strict,
+}
+
+impl SettingValue for UtxoVerification {
+    const ALL: &'static [Self] = &[UtxoVerification::Standard, UtxoVerification::Strict];
+
+    fn name(&self) -> &'static str {
+        match self {
+            UtxoVerification::Standard => "Standard",
+            UtxoVerification::Strict => "Strict",
+        }
+    }
+}
+
+/// Whether the change outputs of a transaction are shown before signing it
+///
+/// They are normally skipped, since the device checks that they go back to the wallet. Users who
+/// want to see it for themselves can have every change output shown with its address and index.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ChangeOutputs {
+    #[default]
+    #[cbor(n(0))]
+    Hide,
+    #[cbor(n(1))]
+    Show,
+}
+
+impl SettingValue for ChangeOutputs {
+    const ALL: &'static [Self] = &[ChangeOutputs::Hide, ChangeOutputs::Show];
+
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeOutputs::Hide => "Hide",
+            ChangeOutputs::Show => "Show",
+        }
+    }
+}
+
+/// Unit the amounts are shown in, on every page that shows one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum AmountUnit {
+    #[default]
+    #[cbor(n(0))]
+    Btc,
+    #[cbor(n(1))]
+    Sats,
+}
+
+impl AmountUnit {
+    pub fn format(&self, amount: bitcoin::Amount) -> alloc::string::String {
+        match self {
+            AmountUnit::Btc => alloc::format!(
+                "{:.8} BTC",
+                amount.display_in(bitcoin::Denomination::Bitcoin)
+            ),
+            AmountUnit::Sats => alloc::format!("{} sats", amount.to_sat()),
+        }
+    }
+}
+
+impl SettingValue for AmountUnit {
+    const ALL: &'static [Self] = &[AmountUnit::Btc, AmountUnit::Sats];
+
+    fn name(&self) -> &'static str {
+        match self {
+            AmountUnit::Btc => "BTC",
+            AmountUnit::Sats => "sats",
+        }
+    }
+}
+
+/// Readings of the touch sensor below this value count as a touch, until it's calibrated
+pub const DEFAULT_TOUCH_THRESHOLD: u16 = 1200;
+
+/// Readings of the touch sensor measured on this device, through its case
+///
+/// The reading drops while the button is touched, by an amount that depends on the case and on
+/// what the button is touched with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct TouchCalibration {
+    #[cbor(n(0))]
+    pub released: u16,
+    #[cbor(n(1))]
+    pub touched: u16,
+}
+
+impl TouchCalibration {
+    /// Minimum drop of the reading for a touch to be told apart from the noise
+    pub const MIN_DIFFERENCE: u16 = 40;
+
+    /// Returns `None` if the touch didn't change the reading enough
+    pub fn new(released: u16, touched: u16) -> Option<Self> {
+        (released >= touched.saturating_add(Self::MIN_DIFFERENCE))
+            .then_some(TouchCalibration { released, touched })
+    }
+}
+
+/// Language of the text shown on the device
+///
+/// Strings that haven't been translated yet are shown in English.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Language {
+    #[default]
+    #[cbor(n(0))]
+    English,
+    #[cbor(n(1))]
+    Italian,
+}
+
+impl SettingValue for Language {
+    const ALL: &'static [Self] = &[Language::English, Language::Italian];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Italian => "Italiano",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct DeviceSettings {
+    #[cbor(n(0))]
+    pub confirm_speed: ConfirmSpeed,
+    #[cbor(n(1))]
+    pub scroll_speed: ScrollSpeed,
+    #[cbor(n(2))]
+    pub auto_lock: AutoLock,
+    #[cbor(n(3))]
+    pub brightness: Brightness,
+    #[cbor(n(4))]
+    pub language: Language,
+    #[cbor(n(5))]
+    pub text_size: TextSize,
+    #[cbor(n(6))]
+    pub idle_screen: IdleScreen,
+    #[cbor(n(7))]
+    pub review_inputs: ReviewInputs,
+    #[cbor(n(8))]
+    pub touch_sensitivity: TouchSensitivity,
+    /// `None` until the touch calibration is run from the menu
+    #[cbor(n(9))]
+    pub touch_calibration: Option<TouchCalibration>,
+    #[cbor(n(10))]
+    pub operation_timeout: OperationTimeout,
+    #[cbor(n(11))]
+    pub debug_logs: DebugLogs,
+    #[cbor(n(12))]
+    pub fee_warning_percent: FeeWarningPercent,
+    #[cbor(n(13))]
+    pub fee_warning_rate: FeeWarningRate,
+    #[cbor(n(14))]
+    pub non_default_sighash: NonDefaultSighash,
+    #[cbor(n(15))]
+    pub utxo_verification: UtxoVerification,
+    #[cbor(n(16))]
+    pub change_outputs: ChangeOutputs,
+    #[cbor(n(17))]
+    pub amount_unit: AmountUnit,
+}
+
+impl DeviceSettings {
+    /// Readings of the touch sensor below this value count as a touch
+    pub fn touch_threshold(&self) -> u16 {
+        let percent = self.touch_sensitivity.percent();
+        match self.touch_calibration {
+            Some(TouchCalibration { released, touched }) => {
+                touched + ((released - touched) as u32 * percent / 100) as u16
+            }
+            None => (DEFAULT_TOUCH_THRESHOLD as u32 * (percent + 150) / 200) as u16,
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_wraps_around() {
+        assert_eq!(AutoLock::Never.next(), AutoLock::OneMinute);
+        assert_eq!(AutoLock::FifteenMinutes.next(), AutoLock::Never);
+    }
+
+    #[test]
+    fn test_settings_roundtrip() {
+        let settings = DeviceSettings {
+            confirm_speed: ConfirmSpeed::Fast,
+            scroll_speed: ScrollSpeed::Slow,
+            auto_lock: AutoLock::FiveMinutes,
+            brightness: Brightness::High,
+            language: Language::Italian,
+            text_size: TextSize::Large,
+            idle_screen: IdleScreen::Fingerprint,
+            review_inputs: ReviewInputs::On,
+            touch_sensitivity: TouchSensitivity::High,
+            touch_calibration: TouchCalibration::new(1500, 900),
+            operation_timeout: OperationTimeout::Never,
+            debug_logs: DebugLogs::On,
+            fee_warning_percent: FeeWarningPercent::Five,
+            fee_warning_rate: FeeWarningRate::Off,
+            non_default_sighash: NonDefaultSighash::Warn,
+            utxo_verification: UtxoVerification::Strict,
+            change_outputs: ChangeOutputs::Show,
+            amount_unit: AmountUnit::Sats,
+        };
+        let data = minicbor::to_vec(&settings).unwrap();
+
+        assert_eq!(minicbor::decode::<DeviceSettings>(&data).unwrap(), settings);
+    }
+
+    #[test]
+    fn test_fee_warning() {
+        assert!(!FeeWarningPercent::Ten.exceeded(1_000, 10_000));
+        assert!(FeeWarningPercent::Ten.exceeded(1_001, 10_000));
+        assert!(FeeWarningPercent::Five.exceeded(u64::MAX, u64::MAX / 2));
+        assert!(!FeeWarningPercent::Off.exceeded(10_000, 0));
+
+        assert!(!FeeWarningRate::FiveHundred.exceeded(500.0));
+        assert!(FeeWarningRate::FiveHundred.exceeded(500.5));
+        assert!(!FeeWarningRate::Off.exceeded(f32::MAX));
+    }
+
+    #[test]
+    fn test_amount_unit() {
+        let amount = bitcoin::Amount::from_sat(123_456);
+        assert_eq!(AmountUnit::Btc.format(amount), "0.00123456 BTC");
+        assert_eq!(AmountUnit::Sats.format(amount), "123456 sats");
+    }
+
+    #[test]
+    fn test_touch_threshold() {
+        let mut settings = DeviceSettings::default();
+        assert_eq!(settings.touch_threshold(), DEFAULT_TOUCH_THRESHOLD);
+        settings.touch_sensitivity = TouchSensitivity::High;
+        assert!(settings.touch_threshold() > DEFAULT_TOUCH_THRESHOLD);
+
+        assert_eq!(TouchCalibration::new(1000, 980), None);
+        settings.touch_calibration = TouchCalibration::new(1500, 900);
+        assert_eq!(settings.touch_threshold(), 1320);
+        settings.touch_sensitivity = TouchSensitivity::Low;
+        assert_eq!(settings.touch_threshold(), 1080);
+    }
+}
@@ -0,0 +1,441 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Signatures returned by the device after signing a PSBT
+//!
+//! Instead of sending back the whole PSBT the device only replies with the signatures it added.
+//! These are encoded as a PSBT with an empty unsigned transaction in the global map, followed by
+//! one input map per input of the original transaction containing only the new signatures.
+//!
+//! Since the number of input maps doesn't match the (empty) transaction, this can't be parsed as
+//! a regular PSBT: use `decode()` to read it and `merge()` to add the signatures to the original
+//! PSBT.
+//!
+//! The MuSig2 nonces and partial signatures of BIP-373 (see `musig2`) are also part of the diff.
+//! Other proprietary (`0xFC`) and unknown fields never are: the device ignores them when decoding,
+//! and `merge()` only adds signatures, so every other field of the original PSBT, including the
+//! proprietary and unknown ones of the global, input and output maps, is left as the host sent
+//! it.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::hashes::Hash;
+use bitcoin::util::psbt::{raw, Input, PartiallySignedTransaction};
+use bitcoin::util::taproot::TapLeafHash;
+use bitcoin::{EcdsaSig, PublicKey, SchnorrSig, VarInt, XOnlyPublicKey};
+
+use crate::musig2;
+
+const PSBT_MAGIC: [u8; 5] = [0x70, 0x73, 0x62, 0x74, 0xFF];
+
+const PSBT_IN_PARTIAL_SIG: u64 = 0x02;
+const PSBT_IN_TAP_KEY_SIG: u64 = 0x13;
+const PSBT_IN_TAP_SCRIPT_SIG: u64 = 0x14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigDiffError {
+    InvalidMagic,
+    InvalidData,
+    InputCountMismatch,
+    UnknownKey,
+}
+
+impl core::fmt::Display for SigDiffError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let msg = match self {
+            SigDiffError::InvalidMagic => "Invalid PSBT magic",
+            SigDiffError::InvalidData => "Invalid signature data",
+            SigDiffError::InputCountMismatch => "Number of inputs doesn't match the PSBT",
+            SigDiffError::UnknownKey => "Signature for a key not present in the PSBT",
+        };
+        f.write_str(msg)
+    }
+}
+#[cfg(not(feature = "stm32"))]
+impl std::error::Error for SigDiffError {}
+
+impl From<bitcoin::consensus::encode::Error> for SigDiffError {
+    fn from(_: bitcoin::consensus::encode::Error) -> Self {
+        SigDiffError::InvalidData
+    }
+}
+
+/// Encode the signatures for every input
+///
+/// Only the signature fields of the inputs are expected to be set.
+pub fn encode(inputs: &[Input]) -> Vec<u8> {
+    #[rustfmt::skip]
+    let mut data = alloc::vec![
+        0x70, 0x73, 0x62, 0x74, 0xFF, // PSBT magic
+            0x01, 0x00, 0x33, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, // Empty raw tx
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+            0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00 // End global map
+    ];
+
+    for input in inputs {
+        input
+            .consensus_encode(&mut data)
+            .expect("Encoding to a vec succeeds");
+    }
+
+    data
+}
+
+type RawMap = BTreeMap<(u64, Vec<u8>), Vec<u8>>;
+
+/// Parse a map, advancing `data` past its separator
+fn parse_map(data: &mut &[u8]) -> Result<RawMap, SigDiffError> {
+    let mut map = BTreeMap::new();
+
+    loop {
+        let key = Vec::<u8>::consensus_decode(data)?;
+        if key.is_empty() {
+            break;
+        }
+
+        let mut key = key.as_slice();
+        let key_type = VarInt::consensus_decode(&mut key)?;
+        let value = Vec::<u8>::consensus_decode(data)?;
+
+        map.insert((key_type.0, key.to_vec()), value);
+    }
+
+    Ok(map)
+}
+
+fn map_to_input(map: RawMap) -> Result<Input, SigDiffError> {
+    fn map_err<E>(_: E) -> SigDiffError {
+        SigDiffError::InvalidData
+    }
+
+    map.into_iter()
+        .try_fold(Input::default(), |mut input, ((ty, key), value)| {
+            match ty {
+                PSBT_IN_PARTIAL_SIG => {
+                    let pk = PublicKey::from_slice(&key).map_err(map_err)?;
+                    let sig = EcdsaSig::from_slice(&value).map_err(map_err)?;
+
+                    input.partial_sigs.insert(pk, sig);
+                }
+                PSBT_IN_TAP_KEY_SIG => {
+                    input.tap_key_sig = Some(SchnorrSig::from_slice(&value).map_err(map_err)?);
+                }
+                PSBT_IN_TAP_SCRIPT_SIG => {
+                    if key.len() != 64 {
+                        return Err(SigDiffError::InvalidData);
+                    }
+
+                    let pk = XOnlyPublicKey::from_slice(&key[..32]).map_err(map_err)?;
+                    let lh = TapLeafHash::from_slice(&key[32..]).map_err(map_err)?;
+                    let sig = SchnorrSig::from_slice(&value).map_err(map_err)?;
+
+                    input.tap_script_sigs.insert((pk, lh), sig);
+                }
+
+                ty if ty == musig2::PSBT_IN_MUSIG2_PUB_NONCE as u64
+                    || ty == musig2::PSBT_IN_MUSIG2_PARTIAL_SIG as u64 =>
+                {
+                    let key = raw::Key {
+                        type_value: ty as u8,
+                        key,
+                    };
+                    input.unknown.insert(key, value);
+                }
+
+                // Proprietary and other unknown types don't carry signatures
+                _ => {}
+            }
+
+            Ok(input)
+        })
+}
+
+/// Decode the signatures for every input
+pub fn decode(data: &[u8]) -> Result<Vec<Input>, SigDiffError> {
+    let mut data = data
+        .strip_prefix(&PSBT_MAGIC)
+        .ok_or(SigDiffError::InvalidMagic)?;
+
+    let _global = parse_map(&mut data)?;
+
+    // Inputs with no new signatures are encoded as empty maps, so keep going until the end
+    let mut inputs = Vec::new();
+    while !data.is_empty() {
+        inputs.push(map_to_input(parse_map(&mut data)?)?);
+    }
+
+    Ok(inputs)
+}
+
+/// Add the signatures to the original PSBT
+///
+/// Every signature must belong to a key listed in the corresponding input of the PSBT, otherwise
+/// the whole PSBT is left untouched.
+pub fn merge(
+    psbt: &mut PartiallySignedTransaction,
+    inputs: Vec<Input>,
+) -> Result<(), SigDiffError> {
+    if inputs.len() != psbt.inputs.len() {
+        return Err(SigDiffError::InputCountMismatch);
+    }
+
+    for (original, diff) in psbt.inputs.iter().zip(inputs.iter()) {
+        let known_ecdsa = diff
+            .partial_sigs
+            .keys()
+            .all(|pk| original.bip32_derivation.contains_key(&pk.inner));
+        let known_tap_key = diff.tap_key_sig.is_none() || original.tap_internal_key.is_some();
+        let known_tap_script = diff.tap_script_sigs.keys().all(|(pk, lh)| {
+            original
+                .tap_key_origins
+                .get(pk)
+                .map(|(leaves, _)| leaves.contains(lh))
+                .unwrap_or(false)
+        });
+
+        let known_musig2 = diff
+            .unknown
+            .iter()
+            .all(|(key, value)| musig2::is_known_field(original, key, value));
+
+        if !(known_ecdsa && known_tap_key && known_tap_script && known_musig2) {
+            return Err(SigDiffError::UnknownKey);
+        }
+    }
+
+    for (original, diff) in psbt.inputs.iter_mut().zip(inputs) {
+        original.partial_sigs.extend(diff.partial_sigs);
+        original.tap_script_sigs.extend(diff.tap_script_sigs);
+        if diff.tap_key_sig.is_some() {
+            original.tap_key_sig = diff.tap_key_sig;
+        }
+        original.unknown.extend(diff.unknown);
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use bitcoin::blockdata::transaction::{Transaction, TxIn};
+    use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+
+    const SIG_DIFF: [u8; 384] = [
+        0x70, 0x73, 0x62, 0x74, 0xFF, 0x01, 0x00, 0x33, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x22, 0x02, 0x02, 0xC5, 0x1A, 0x19, 0x85, 0xE7, 0x6C, 0x6C, 0x31, 0xB8, 0xB0, 0xB4, 0x3E,
+        0x85, 0x04, 0x9D, 0xF5, 0x9F, 0xBD, 0x1D, 0x17, 0x14, 0xF5, 0xF9, 0x5C, 0xC3, 0x0F, 0x27,
+        0x76, 0xA5, 0x3A, 0xB4, 0x40, 0x47, 0x30, 0x44, 0x02, 0x20, 0x77, 0x51, 0x39, 0xD4, 0x42,
+        0xF7, 0xA1, 0x2A, 0xCA, 0x1A, 0x20, 0xD8, 0xA4, 0x51, 0x9F, 0x70, 0x7E, 0xA0, 0xC1, 0x65,
+        0xFF, 0x08, 0x98, 0xF5, 0x50, 0xE4, 0xF1, 0x70, 0xD1, 0x14, 0x81, 0x3E, 0x02, 0x20, 0x40,
+        0xDC, 0x09, 0x28, 0x16, 0x20, 0xF5, 0xC0, 0xB3, 0x87, 0x43, 0x1A, 0x75, 0x17, 0x3A, 0x3E,
+        0x33, 0xC2, 0xBB, 0xDF, 0x89, 0xCF, 0xFE, 0x25, 0xC0, 0xF6, 0x61, 0xAD, 0x2F, 0x18, 0xE0,
+        0x63, 0x01, 0x00, 0x22, 0x02, 0x03, 0x2B, 0x64, 0xB3, 0x42, 0xD0, 0x68, 0x0C, 0x4E, 0x03,
+        0x99, 0xE4, 0x69, 0x61, 0xAC, 0x04, 0x2F, 0x4C, 0x91, 0xD6, 0x7C, 0x1E, 0xF6, 0x1A, 0x73,
+        0x1C, 0x7D, 0x65, 0x3E, 0x31, 0x72, 0x0D, 0xCF, 0x47, 0x30, 0x44, 0x02, 0x20, 0x02, 0x3F,
+        0xA0, 0x7E, 0x82, 0x59, 0x78, 0xDA, 0x9A, 0xB7, 0xC7, 0x58, 0x6D, 0x8B, 0x0E, 0x05, 0x2C,
+        0x07, 0x55, 0xDE, 0xA0, 0xB4, 0x23, 0x63, 0xF5, 0x39, 0x40, 0xAC, 0xB7, 0xB6, 0xD0, 0x1A,
+        0x02, 0x20, 0x6C, 0x6D, 0xCE, 0xA4, 0x4E, 0x3A, 0x35, 0x29, 0x06, 0xB7, 0x82, 0xC2, 0xA0,
+        0x9A, 0x2B, 0xA8, 0x96, 0x16, 0x5B, 0x0E, 0xBD, 0x92, 0x34, 0xE9, 0x99, 0x63, 0xC1, 0xC7,
+        0x00, 0xCF, 0xD5, 0xAF, 0x01, 0x00, 0x22, 0x02, 0x02, 0xC5, 0x1A, 0x19, 0x85, 0xE7, 0x6C,
+        0x6C, 0x31, 0xB8, 0xB0, 0xB4, 0x3E, 0x85, 0x04, 0x9D, 0xF5, 0x9F, 0xBD, 0x1D, 0x17, 0x14,
+        0xF5, 0xF9, 0x5C, 0xC3, 0x0F, 0x27, 0x76, 0xA5, 0x3A, 0xB4, 0x40, 0x47, 0x30, 0x44, 0x02,
+        0x20, 0x25, 0xC4, 0x14, 0x8D, 0x39, 0xF1, 0xAE, 0x3E, 0x4E, 0x53, 0x65, 0x8A, 0x81, 0xB0,
+        0x0D, 0x27, 0x91, 0xE0, 0xDC, 0xDD, 0x49, 0x1A, 0x5E, 0x9A, 0x57, 0x71, 0xA5, 0xD4, 0xDD,
+        0x1D, 0x42, 0xB6, 0x02, 0x20, 0x5C, 0x6E, 0x5D, 0xA6, 0xEC, 0xFB, 0xE2, 0xEB, 0xE0, 0x9B,
+        0x1C, 0xDA, 0xB8, 0x18, 0x13, 0x79, 0xBB, 0xFC, 0xAE, 0xE3, 0xA5, 0x48, 0x39, 0xFA, 0x16,
+        0xF8, 0x0D, 0x8E, 0xF2, 0x15, 0x4A, 0xB2, 0x01, 0x00,
+    ];
+
+    fn make_psbt(inputs: &[Input]) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime::ZERO,
+            input: inputs.iter().map(|_| TxIn::default()).collect(),
+            output: vec![],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        for (input, diff) in psbt.inputs.iter_mut().zip(inputs) {
+            for pk in diff.partial_sigs.keys() {
+                input.bip32_derivation.insert(
+                    pk.inner,
+                    (Fingerprint::default(), DerivationPath::default()),
+                );
+            }
+        }
+
+        psbt
+    }
+
+    #[test]
+    fn test_decode_multiple_inputs() {
+        let inputs = decode(&SIG_DIFF).unwrap();
+
+        assert_eq!(inputs.len(), 3);
+        for input in &inputs {
+            assert_eq!(input.partial_sigs.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let inputs = decode(&SIG_DIFF).unwrap();
+        assert_eq!(encode(&inputs), SIG_DIFF);
+    }
+
+    #[test]
+    fn test_decode_empty_input() {
+        let mut inputs = decode(&SIG_DIFF).unwrap();
+        inputs[0] = Input::default();
+
+        let decoded = decode(&encode(&inputs)).unwrap();
+        assert_eq!(decoded, inputs);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(decode(&[]), Err(SigDiffError::InvalidMagic));
+        assert_eq!(
+            decode(&SIG_DIFF[..SIG_DIFF.len() - 1]),
+            Err(SigDiffError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let inputs = decode(&SIG_DIFF).unwrap();
+
+        let mut psbt = make_psbt(&inputs);
+        merge(&mut psbt, inputs.clone()).unwrap();
+        for (input, diff) in psbt.inputs.iter().zip(inputs.iter()) {
+            assert_eq!(input.partial_sigs, diff.partial_sigs);
+        }
+
+        let mut psbt = make_psbt(&inputs[..2]);
+        assert_eq!(
+            merge(&mut psbt, inputs.clone()),
+            Err(SigDiffError::InputCountMismatch)
+        );
+
+        let mut psbt = make_psbt(&inputs);
+        psbt.inputs[2].bip32_derivation.clear();
+        assert_eq!(merge(&mut psbt, inputs), Err(SigDiffError::UnknownKey));
+        assert!(psbt.inputs.iter().all(|i| i.partial_sigs.is_empty()));
+    }
+
+    #[test]
+    fn test_merge_preserves_extra_fields() {
+        use bitcoin::consensus::{deserialize, serialize};
+        use bitcoin::util::psbt::raw;
+
+        let inputs = decode(&SIG_DIFF).unwrap();
+        let proprietary = raw::ProprietaryKey {
+            prefix: b"vendor".to_vec(),
+            subtype: 0x42,
+            key: vec![0x01],
+        };
+        let unknown = raw::Key {
+            type_value: 0xF0,
+            key: vec![0x02],
+        };
+
+        let mut psbt = make_psbt(&inputs);
+        psbt.proprietary.insert(proprietary.clone(), vec![0xAA]);
+        psbt.unknown.insert(unknown.clone(), vec![0xBB]);
+        psbt.inputs[1]
+            .proprietary
+            .insert(proprietary.clone(), vec![0xCC]);
+        psbt.inputs[1].unknown.insert(unknown.clone(), vec![0xDD]);
+
+        // The extra fields in the diff itself are ignored
+        let mut with_extra = inputs.clone();
+        with_extra[0].proprietary.insert(proprietary, vec![0xEE]);
+        with_extra[0].unknown.insert(unknown, vec![0xFF]);
+        let decoded = decode(&encode(&with_extra)).unwrap();
+        assert_eq!(decoded, inputs);
+
+        let mut expected = psbt.clone();
+        merge(&mut psbt, decoded).unwrap();
+        let psbt: PartiallySignedTransaction = deserialize(&serialize(&psbt)).unwrap();
+
+        for (input, diff) in expected.inputs.iter_mut().zip(inputs) {
+            input.partial_sigs = diff.partial_sigs;
+        }
+        assert_eq!(psbt, expected);
+    }
+
+    #[test]
+    fn test_merge_musig2() {
+        use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+        let secp = Secp256k1::new();
+        let participant = SecretKey::from_slice(&[0x11; 32])
+            .unwrap()
+            .public_key(&secp);
+        let other = SecretKey::from_slice(&[0x22; 32])
+            .unwrap()
+            .public_key(&secp);
+        let aggregate_key = musig2::KeyAggContext::new(&secp, &[participant, other])
+            .unwrap()
+            .aggregate_key();
+
+        let mut diff = Input::default();
+        diff.unknown.insert(
+            musig2::field_key(
+                musig2::PSBT_IN_MUSIG2_PARTIAL_SIG,
+                &participant,
+                &aggregate_key,
+                None,
+            ),
+            vec![0x42; 32],
+        );
+        let decoded = decode(&encode(&[diff.clone()])).unwrap();
+        assert_eq!(decoded, vec![diff.clone()]);
+
+        // Only for the participants listed in the PSBT
+        let mut psbt = make_psbt(&[Input::default()]);
+        assert_eq!(
+            merge(&mut psbt, decoded.clone()),
+            Err(SigDiffError::UnknownKey)
+        );
+
+        psbt.inputs[0].unknown.insert(
+            raw::Key {
+                type_value: musig2::PSBT_IN_MUSIG2_PARTICIPANT_PUBKEYS,
+                key: aggregate_key.serialize().to_vec(),
+            },
+            [participant.serialize(), other.serialize()].concat(),
+        );
+        merge(&mut psbt, decoded).unwrap();
+        assert_eq!(psbt.inputs[0].unknown.len(), 2);
+        assert!(psbt.inputs[0]
+            .unknown
+            .iter()
+            .any(|(k, v)| diff.unknown.get(k) == Some(v)));
+    }
+}
@@ -0,0 +1,200 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Legacy message signatures, as made by Bitcoin Core's `signmessage`
+//!
+//! The signature is a recoverable ECDSA signature of the double-SHA256 of the message prefixed
+//! with "Bitcoin Signed Message:\n", serialized in 65 bytes with a header byte that encodes the
+//! recovery id. Bitcoin Core only signs with P2PKH addresses; P2WPKH ones use the header of
+//! BIP-137 (39 to 42), which is what Electrum and most other wallets expect.
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey, Signing, Verification};
+use bitcoin::util::misc::signed_msg_hash;
+use bitcoin::PublicKey;
+
+/// Header of a signature made with a compressed key for a P2PKH address
+const HEADER_P2PKH: u8 = 31;
+/// Header of a signature made for a P2WPKH address (BIP-137)
+const HEADER_P2WPKH: u8 = 39;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignMessageError {
+    /// Only P2PKH and P2WPKH addresses are supported
+    UnsupportedAddress,
+    /// The key doesn't match the address
+    KeyMismatch,
+}
+
+fn header(script_pubkey: &Script) -> Option<u8> {
+    if script_pubkey.is_p2pkh() {
+        Some(HEADER_P2PKH)
+    } else if script_pubkey.is_v0_p2wpkh() {
+        Some(HEADER_P2WPKH)
+    } else {
+        None
+    }
+}
+
+fn matches_key(script_pubkey: &Script, pubkey: &PublicKey) -> bool {
+    if script_pubkey.is_p2pkh() {
+        Script::new_p2pkh(&pubkey.pubkey_hash()) == *script_pubkey
+    } else {
+        pubkey.wpubkey_hash().map(|h| Script::new_v0_p2wpkh(&h)) == Some(script_pubkey.clone())
+    }
+}
+
+fn message_hash(message: &str) -> Message {
+    Message::from_slice(signed_msg_hash(message).as_ref()).expect("Correct length")
+}
+
+/// Sign `message` for the address of `script_pubkey` with `key`
+pub fn sign<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    script_pubkey: &Script,
+    message: &str,
+    key: &SecretKey,
+) -> Result<[u8; 65], SignMessageError> {
+    let header = header(script_pubkey).ok_or(SignMessageError::UnsupportedAddress)?;
+    if !matches_key(script_pubkey, &PublicKey::new(key.public_key(secp))) {
+        return Err(SignMessageError::KeyMismatch);
+    }
+
+    let (recovery_id, signature) = secp
+        .sign_ecdsa_recoverable(&message_hash(message), key)
+        .serialize_compact();
+
+    let mut serialized = [0u8; 65];
+    serialized[0] = header + recovery_id.to_i32() as u8;
+    serialized[1..].copy_from_slice(&signature);
+    Ok(serialized)
+}
+
+/// Check a signature made by `sign`
+///
+/// P2WPKH signatures are also accepted with the P2PKH header, as some wallets make them.
+pub fn verify<C: Verification>(
+    secp: &Secp256k1<C>,
+    script_pubkey: &Script,
+    message: &str,
+    signature: &[u8],
+) -> bool {
+    let expected = match header(script_pubkey) {
+        Some(header) => header,
+        None => return false,
+    };
+    let (header, compact) = match signature.split_first() {
+        Some((header, compact)) if compact.len() == 64 => (*header, compact),
+        _ => return false,
+    };
+    let recovery_id = if (expected..expected + 4).contains(&header) {
+        header - expected
+    } else if (HEADER_P2PKH..HEADER_P2PKH + 4).contains(&header) {
+        header - HEADER_P2PKH
+    } else {
+        return false;
+    };
+
+    let pubkey = RecoveryId::from_i32(recovery_id as i32)
+        .and_then(|id| RecoverableSignature::from_compact(compact, id))
+        .and_then(|signature| secp.recover_ecdsa(&message_hash(message), &signature));
+    match pubkey {
+        Ok(pubkey) => matches_key(script_pubkey, &PublicKey::new(pubkey)),
+        Err(_) => false,
+    }
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use core::str::FromStr;
+
+    use bitcoin::{Address, Network, PrivateKey};
+
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+
+    #[test]
+    fn test_sign_p2pkh() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let address = Address::p2pkh(&key.public_key(&secp), Network::Bitcoin);
+
+        let signature = sign(&secp, &address.script_pubkey(), "Hello World", &key.inner).unwrap();
+        assert!((31..35).contains(&signature[0]));
+        assert!(verify(
+            &secp,
+            &address.script_pubkey(),
+            "Hello World",
+            &signature
+        ));
+        assert!(!verify(
+            &secp,
+            &address.script_pubkey(),
+            "Hello",
+            &signature
+        ));
+
+        // Same format as `MessageSignature`, used by Bitcoin Core
+        let parsed = bitcoin::util::misc::MessageSignature::from_slice(&signature).unwrap();
+        assert!(parsed
+            .is_signed_by_address(&secp, &address, signed_msg_hash("Hello World"))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_sign_p2wpkh() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let script_pubkey = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l")
+            .unwrap()
+            .script_pubkey();
+
+        let mut signature = sign(&secp, &script_pubkey, "Hello World", &key.inner).unwrap();
+        assert!((39..43).contains(&signature[0]));
+        assert!(verify(&secp, &script_pubkey, "Hello World", &signature));
+
+        signature[0] -= 8;
+        assert!(verify(&secp, &script_pubkey, "Hello World", &signature));
+        signature[0] -= 4;
+        assert!(!verify(&secp, &script_pubkey, "Hello World", &signature));
+    }
+
+    #[test]
+    fn test_unsupported_address() {
+        let secp = Secp256k1::new();
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let p2tr = Address::p2tr(
+            &secp,
+            key.public_key(&secp).inner.x_only_public_key().0,
+            None,
+            Network::Bitcoin,
+        );
+        assert_eq!(
+            sign(&secp, &p2tr.script_pubkey(), "", &key.inner),
+            Err(SignMessageError::UnsupportedAddress)
+        );
+
+        let other = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let p2pkh = Address::p2pkh(&key.public_key(&secp), Network::Bitcoin);
+        assert_eq!(
+            sign(&secp, &p2pkh.script_pubkey(), "", &other),
+            Err(SignMessageError::KeyMismatch)
+        );
+    }
+}
@@ -0,0 +1,461 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Taproot multisig with a single `sortedmulti_a` leaf
+//!
+//! These wallets are described as `tr(NUMS, sortedmulti_a(k, ...))`: the internal key is
+//! unspendable, so funds can only be spent through the script. The miniscript version used by
+//! BDK doesn't know about `sortedmulti_a`, so the script, the leaf hash and the output key are
+//! computed here from the keys derived at a given index.
+
+use alloc::vec::Vec;
+
+use bitcoin::blockdata::locktime::LOCK_TIME_THRESHOLD;
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKSIG, OP_CHECKSIGADD, OP_CLTV, OP_CSV, OP_NUMEQUAL, OP_PUSHNUM_1, OP_PUSHNUM_16,
+};
+use bitcoin::blockdata::script::{read_scriptint, Builder, Instruction, Script};
+use bitcoin::secp256k1::{Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::util::psbt;
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash, TaprootBuilder};
+use bitcoin::{Address, Network, TxOut};
+
+/// The "H" point of BIP-341, a key with no known private key
+pub const NUMS_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Internal key of the wallets, see `NUMS_KEY`
+pub fn nums_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&NUMS_KEY).expect("Valid point")
+}
+
+/// Script of the leaf, with the keys sorted like `sortedmulti_a` does
+pub fn sortedmulti_a_script(threshold: usize, keys: &[XOnlyPublicKey]) -> Script {
+    let mut keys: Vec<_> = keys.iter().map(|k| k.serialize()).collect();
+    keys.sort();
+
+    let mut builder = Builder::new();
+    for (i, key) in keys.iter().enumerate() {
+        builder = builder.push_slice(key).push_opcode(match i {
+            0 => OP_CHECKSIG,
+            _ => OP_CHECKSIGADD,
+        });
+    }
+    builder
+        .push_int(threshold as i64)
+        .push_opcode(OP_NUMEQUAL)
+        .into_script()
+}
+
+/// Hash of the leaf, which is signed together with the transaction
+pub fn sortedmulti_a_leaf_hash(threshold: usize, keys: &[XOnlyPublicKey]) -> TapLeafHash {
+    TapLeafHash::from_script(
+        &sortedmulti_a_script(threshold, keys),
+        LeafVersion::TapScript,
+    )
+}
+
+/// Address of the wallet for the keys derived at one index
+pub fn sortedmulti_a_address<C: Verification>(
+    secp: &Secp256k1<C>,
+    threshold: usize,
+    keys: &[XOnlyPublicKey],
+    network: Network,
+) -> Address {
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, sortedmulti_a_script(threshold, keys))
+        .expect("A single leaf at depth zero")
+        .finalize(secp, nums_key())
+        .expect("The tree is complete");
+
+    Address::p2tr_tweaked(spend_info.output_key(), network)
+}
+
+/// Leaves of `input` that `key` can sign through the script path
+///
+/// Only the leaves listed for `key` in `tap_key_origins` are returned, and only if their script
+/// is in `tap_scripts`, contains `key` and has a control block that commits to the output key of
+/// `utxo`. Otherwise the host could have the device sign for a script it never saw.
+pub fn signable_leaves<C: Verification>(
+    secp: &Secp256k1<C>,
+    input: &psbt::Input,
+    utxo: &TxOut,
+    key: &XOnlyPublicKey,
+) -> Vec<(TapLeafHash, Script)> {
+    if !utxo.script_pubkey.is_v1_p2tr() {
+        return Vec::new();
+    }
+    let output_key = match XOnlyPublicKey::from_slice(&utxo.script_pubkey[2..]) {
+        Ok(key) => key,
+        Err(_) => return Vec::new(),
+    };
+    let leaf_hashes = match input.tap_key_origins.get(key) {
+        Some((leaf_hashes, _)) => leaf_hashes,
+        None => return Vec::new(),
+    };
+    let serialized_key = key.serialize();
+
+    input
+        .tap_scripts
+        .iter()
+        .filter(|(_, (_, version))| *version == LeafVersion::TapScript)
+        .filter(|(control_block, (script, _))| {
+            control_block.verify_taproot_commitment(secp, output_key, script)
+        })
+        .map(|(_, (script, version))| (TapLeafHash::from_script(script, *version), script))
+        .filter(|(leaf_hash, _)| leaf_hashes.contains(leaf_hash))
+        .filter(|(_, script)| {
+            script
+                .instructions()
+                .any(|i| matches!(i, Ok(Instruction::PushBytes(data)) if data == serialized_key))
+        })
+        .map(|(leaf_hash, script)| (leaf_hash, script.clone()))
+        .collect()
+}
+
+/// Timelock that a tapscript leaf enforces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LeafTimelock {
+    /// `older(n)`, in blocks since the output was confirmed
+    Blocks(u16),
+    /// `older(n)` with the time flag of BIP-68, in seconds since the output was confirmed
+    Seconds(u32),
+    /// `after(n)`, block height from which the leaf can be spent
+    Height(u32),
+    /// `after(n)`, Unix timestamp from which the leaf can be spent
+    Time(u32),
+}
+
+/// Timelocks of `script`, from the `<n> OP_CHECKSEQUENCEVERIFY` and `<n> OP_CHECKLOCKTIMEVERIFY`
+/// that miniscript writes for `older(n)` and `after(n)`
+pub fn leaf_timelocks(script: &Script) -> Vec<LeafTimelock> {
+    let mut timelocks = Vec::new();
+    let mut previous = None;
+    for instruction in script.instructions() {
+        previous = match instruction {
+            Ok(Instruction::PushBytes(data)) => read_scriptint(data).ok(),
+            Ok(Instruction::Op(op))
+                if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+            {
+                Some((op.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as i64)
+            }
+            Ok(Instruction::Op(op)) => {
+                let n = previous.and_then(|n| u32::try_from(n).ok());
+                match n {
+                    // BIP-68: bit 22 selects units of 512 seconds, the value is in the low 16 bits
+                    Some(n) if op == OP_CSV && n & (1 << 22) != 0 => {
+                        timelocks.push(LeafTimelock::Seconds((n & 0xFFFF) * 512))
+                    }
+                    Some(n) if op == OP_CSV => timelocks.push(LeafTimelock::Blocks(n as u16)),
+                    Some(n) if op == OP_CLTV && n < LOCK_TIME_THRESHOLD => {
+                        timelocks.push(LeafTimelock::Height(n))
+                    }
+                    Some(n) if op == OP_CLTV => timelocks.push(LeafTimelock::Time(n)),
+                    _ => {}
+                }
+                None
+            }
+            Err(_) => break,
+        };
+    }
+
+    timelocks
+}
+
+/// Path of a taproot output through which an input is spent
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpendingPath {
+    KeyPath,
+    ScriptPath {
+        leaf_hash: TapLeafHash,
+        timelocks: Vec<LeafTimelock>,
+    },
+}
+
+/// Paths through which `input` is spent, empty if its output has no script tree
+///
+/// The signatures already in the input decide: `tap_key_sig` is only valid for the key path and
+/// `tap_script_sigs` commit to the leaf they sign. Without them the paths are the leaves of
+/// `signed_leaves`, which are about to be signed, or the key path if there are none.
+pub fn spending_paths(input: &psbt::Input, signed_leaves: &[TapLeafHash]) -> Vec<SpendingPath> {
+    if input.tap_scripts.is_empty() {
+        return Vec::new();
+    }
+    if input.tap_key_sig.is_some() {
+        return alloc::vec![SpendingPath::KeyPath];
+    }
+
+    let mut leaf_hashes = input
+        .tap_script_sigs
+        .keys()
+        .map(|(_, leaf_hash)| *leaf_hash)
+        .collect::<Vec<_>>();
+    if leaf_hashes.is_empty() {
+        leaf_hashes = signed_leaves.to_vec();
+    }
+    if leaf_hashes.is_empty() {
+        return alloc::vec![SpendingPath::KeyPath];
+    }
+    leaf_hashes.sort();
+    leaf_hashes.dedup();
+
+    leaf_hashes
+        .into_iter()
+        .map(|leaf_hash| SpendingPath::ScriptPath {
+            leaf_hash,
+            timelocks: input
+                .tap_scripts
+                .values()
+                .find(|(script, version)| TapLeafHash::from_script(script, *version) == leaf_hash)
+                .map(|(script, _)| leaf_timelocks(script))
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    fn keys() -> Vec<XOnlyPublicKey> {
+        [
+            "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9",
+            "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5",
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        ]
+        .into_iter()
+        .map(|k| XOnlyPublicKey::from_str(k).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_signable_leaves() {
+        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+
+        let secp = Secp256k1::verification_only();
+        let keys = keys();
+        let script = sortedmulti_a_script(2, &keys);
+        let leaf_hash = sortedmulti_a_leaf_hash(2, &keys);
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, script.clone())
+            .unwrap()
+            .finalize(&secp, nums_key())
+            .unwrap();
+        let control_block = spend_info
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .unwrap();
+
+        let mut input = psbt::Input::default();
+        input
+            .tap_scripts
+            .insert(control_block, (script.clone(), LeafVersion::TapScript));
+        for key in &keys {
+            input.tap_key_origins.insert(
+                *key,
+                (
+                    vec![leaf_hash],
+                    (Fingerprint::default(), DerivationPath::default()),
+                ),
+            );
+        }
+        let utxo = TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v1_p2tr_tweaked(spend_info.output_key()),
+        };
+
+        assert_eq!(
+            signable_leaves(&secp, &input, &utxo, &keys[0]),
+            vec![(leaf_hash, script.clone())]
+        );
+
+        // A key that isn't in the script
+        let other = nums_key();
+        input.tap_key_origins.insert(
+            other,
+            (
+                vec![leaf_hash],
+                (Fingerprint::default(), DerivationPath::default()),
+            ),
+        );
+        assert!(signable_leaves(&secp, &input, &utxo, &other).is_empty());
+
+        // An output that doesn't commit to the script
+        let utxo = TxOut {
+            value: 10_000,
+            script_pubkey: sortedmulti_a_address(&secp, 3, &keys, Network::Bitcoin).script_pubkey(),
+        };
+        assert!(signable_leaves(&secp, &input, &utxo, &keys[0]).is_empty());
+    }
+
+    #[test]
+    fn test_leaf_timelocks() {
+        use bitcoin::blockdata::opcodes::all::OP_CHECKSIGVERIFY;
+
+        let key = keys()[0].serialize();
+        let leaf = |n: i64, op| {
+            Builder::new()
+                .push_slice(&key)
+                .push_opcode(OP_CHECKSIGVERIFY)
+                .push_int(n)
+                .push_opcode(op)
+                .into_script()
+        };
+
+        assert_eq!(
+            leaf_timelocks(&leaf(144, OP_CSV)),
+            vec![LeafTimelock::Blocks(144)]
+        );
+        assert_eq!(
+            leaf_timelocks(&leaf(5, OP_CSV)),
+            vec![LeafTimelock::Blocks(5)]
+        );
+        assert_eq!(
+            leaf_timelocks(&leaf((1 << 22) | 10, OP_CSV)),
+            vec![LeafTimelock::Seconds(5120)]
+        );
+        assert_eq!(
+            leaf_timelocks(&leaf(840_000, OP_CLTV)),
+            vec![LeafTimelock::Height(840_000)]
+        );
+        assert_eq!(
+            leaf_timelocks(&leaf(1_700_000_000, OP_CLTV)),
+            vec![LeafTimelock::Time(1_700_000_000)]
+        );
+        assert!(leaf_timelocks(&sortedmulti_a_script(2, &keys())).is_empty());
+    }
+
+    #[test]
+    fn test_spending_paths() {
+        use bitcoin::blockdata::opcodes::all::OP_CHECKSIGVERIFY;
+        use bitcoin::secp256k1::schnorr::Signature;
+        use bitcoin::{SchnorrSig, SchnorrSighashType};
+
+        let secp = Secp256k1::verification_only();
+        let keys = keys();
+        let multi = sortedmulti_a_script(2, &keys[..2]);
+        let recovery = Builder::new()
+            .push_slice(&keys[2].serialize())
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_int(144)
+            .push_opcode(OP_CSV)
+            .into_script();
+        let multi_hash = TapLeafHash::from_script(&multi, LeafVersion::TapScript);
+        let recovery_hash = TapLeafHash::from_script(&recovery, LeafVersion::TapScript);
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, multi.clone())
+            .unwrap()
+            .add_leaf(1, recovery.clone())
+            .unwrap()
+            .finalize(&secp, nums_key())
+            .unwrap();
+
+        let mut input = psbt::Input::default();
+        assert!(spending_paths(&input, &[recovery_hash]).is_empty());
+
+        for script in [&multi, &recovery] {
+            let control_block = spend_info
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .unwrap();
+            input
+                .tap_scripts
+                .insert(control_block, (script.clone(), LeafVersion::TapScript));
+        }
+        assert_eq!(spending_paths(&input, &[]), vec![SpendingPath::KeyPath]);
+        assert_eq!(
+            spending_paths(&input, &[recovery_hash]),
+            vec![SpendingPath::ScriptPath {
+                leaf_hash: recovery_hash,
+                timelocks: vec![LeafTimelock::Blocks(144)],
+            }]
+        );
+
+        // The leaf already signed by a cosigner is the one being spent
+        let sig = SchnorrSig {
+            sig: Signature::from_slice(&[1; 64]).unwrap(),
+            hash_ty: SchnorrSighashType::Default,
+        };
+        input.tap_script_sigs.insert((keys[0], multi_hash), sig);
+        assert_eq!(
+            spending_paths(&input, &[recovery_hash]),
+            vec![SpendingPath::ScriptPath {
+                leaf_hash: multi_hash,
+                timelocks: vec![],
+            }]
+        );
+
+        input.tap_key_sig = Some(sig);
+        assert_eq!(
+            spending_paths(&input, &[recovery_hash]),
+            vec![SpendingPath::KeyPath]
+        );
+    }
+
+    #[test]
+    fn test_nums_key() {
+        assert_eq!(nums_key().serialize(), NUMS_KEY);
+    }
+
+    #[test]
+    fn test_sortedmulti_a_script() {
+        let keys = keys();
+        let script = sortedmulti_a_script(2, &keys);
+
+        let mut sorted = keys.clone();
+        sorted.sort_by_key(|k| k.serialize());
+        let expected = Builder::new()
+            .push_slice(&sorted[0].serialize())
+            .push_opcode(OP_CHECKSIG)
+            .push_slice(&sorted[1].serialize())
+            .push_opcode(OP_CHECKSIGADD)
+            .push_slice(&sorted[2].serialize())
+            .push_opcode(OP_CHECKSIGADD)
+            .push_int(2)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script();
+        assert_eq!(script, expected);
+    }
+
+    #[test]
+    fn test_sortedmulti_a_order() {
+        let secp = Secp256k1::verification_only();
+        let keys = keys();
+        let mut reversed = keys.clone();
+        reversed.reverse();
+
+        assert_eq!(
+            sortedmulti_a_leaf_hash(2, &keys),
+            sortedmulti_a_leaf_hash(2, &reversed)
+        );
+        assert_eq!(
+            sortedmulti_a_address(&secp, 2, &keys, Network::Bitcoin),
+            sortedmulti_a_address(&secp, 2, &reversed, Network::Bitcoin)
+        );
+        // Same as `tr(NUMS, multi_a(2, ...))` with the keys already sorted
+        assert_eq!(
+            sortedmulti_a_address(&secp, 2, &keys, Network::Bitcoin).to_string(),
+            "bc1pm5jn9xnjz3v9xm7jjw2yheajy92pps5fdazdpfnmvzfymu787hhs2vktyy"
+        );
+        assert_ne!(
+            sortedmulti_a_leaf_hash(2, &keys),
+            sortedmulti_a_leaf_hash(3, &keys)
+        );
+    }
+}
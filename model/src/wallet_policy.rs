@@ -0,0 +1,124 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Wallet policies registered with a name
+//!
+//! The descriptor of the device can be registered with a name (`Request::RegisterWalletPolicy`),
+//! which is confirmed on the device with the descriptor itself. The device replies with an HMAC
+//! of the name and the descriptor, keyed by the seed, and from then on the host has to reference
+//! the policy with it (`Request::UseWalletPolicy`) before any PSBT is signed in a session. A host
+//! that doesn't know the registered policy, or that expects another descriptor, is refused, and
+//! the name of the wallet is shown on the idle screen and when signing.
+
+use alloc::string::String;
+
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use bitcoin::util::bip32;
+
+use minicbor::bytes::ByteArray;
+use minicbor::{Decode, Encode};
+
+use crate::WalletDescriptor;
+
+/// Maximum length of the name of a policy, which must fit on one line of the display
+pub const MAX_NAME_LEN: usize = 16;
+
+const HMAC_KEY_TAG: &[u8] = b"portal-wallet-policy";
+
+/// Policy registered for the descriptor of the device
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct WalletPolicy {
+    #[cbor(n(0))]
+    pub name: String,
+    /// See `policy_hmac()`
+    #[cbor(n(1))]
+    pub hmac: ByteArray<32>,
+}
+
+impl WalletPolicy {
+    pub fn new(key: &[u8; 32], name: String, descriptor: &WalletDescriptor) -> Self {
+        let hmac = policy_hmac(key, &name, descriptor);
+        WalletPolicy {
+            name,
+            hmac: hmac.into(),
+        }
+    }
+}
+
+/// Whether `name` can be used for a policy
+///
+/// Printable ASCII characters, without spaces at the ends, up to `MAX_NAME_LEN` long.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.bytes().all(|c| c == b' ' || c.is_ascii_graphic())
+        && name.trim() == name
+}
+
+/// Key of the HMACs of the policies, derived from the master key of the seed
+pub fn hmac_key(xprv: &bip32::ExtendedPrivKey) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(HMAC_KEY_TAG);
+    engine.input(&xprv.private_key.secret_bytes());
+    engine.input(&xprv.chain_code[..]);
+    hmac::Hmac::from_engine(engine).into_inner()
+}
+
+/// HMAC committing to the name of a policy and its descriptor
+pub fn policy_hmac(key: &[u8; 32], name: &str, descriptor: &WalletDescriptor) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(&[name.len() as u8]);
+    engine.input(name.as_bytes());
+    engine.input(&minicbor::to_vec(descriptor).expect("Always serializable"));
+    hmac::Hmac::from_engine(engine).into_inner()
+}
+
+#[cfg(all(test, not(feature = "stm32")))]
+mod tests {
+    use super::*;
+
+    use bitcoin::Network;
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("Savings"));
+        assert!(is_valid_name("Cold storage #2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name(" Savings"));
+        assert!(!is_valid_name("Savings\n"));
+        assert!(!is_valid_name("Sävings"));
+        assert!(!is_valid_name("A very long wallet name"));
+    }
+
+    #[test]
+    fn test_policy_hmac() {
+        let key = [0x42; 32];
+        let descriptor = WalletDescriptor::make_bip84(Network::Bitcoin);
+        let hmac = policy_hmac(&key, "Savings", &descriptor);
+
+        assert_eq!(hmac, policy_hmac(&key, "Savings", &descriptor));
+        assert_ne!(hmac, policy_hmac(&[0x43; 32], "Savings", &descriptor));
+        assert_ne!(hmac, policy_hmac(&key, "Spending", &descriptor));
+        assert_ne!(
+            hmac,
+            policy_hmac(
+                &key,
+                "Savings",
+                &WalletDescriptor::make_bip84(Network::Testnet)
+            )
+        );
+    }
+}
@@ -0,0 +1,23 @@
+#!/bin/bash
+# This script builds the native library and the corresponding Python language bindings.
+# The results of this script can be used for locally testing your integration by adding the
+# libportal-python directory to the PYTHONPATH.
+
+set -xeo pipefail
+
+# Run the script from the libportal-python root directory, ie: ./build-local-python.sh
+
+pushd ../
+
+cargo build --features bindings --release
+
+case "$(uname -s)" in
+    Darwin) LIBNAME=libportal.dylib ;;
+    *) LIBNAME=libportal.so ;;
+esac
+
+cargo run --bin uniffi-bindgen --features bindings generate --library ../target/release/$LIBNAME --out-dir ./libportal-python/libportal --language python --no-format
+
+popd
+
+cp ../../target/release/$LIBNAME libportal/
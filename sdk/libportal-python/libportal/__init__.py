@@ -0,0 +1 @@
+from .portal import *
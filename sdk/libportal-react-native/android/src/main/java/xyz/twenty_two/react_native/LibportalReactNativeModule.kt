@@ -5,6 +5,7 @@ import com.facebook.react.bridge.ReactContextBaseJavaModule
 import com.facebook.react.bridge.ReactMethod
 import com.facebook.react.bridge.Promise
 import com.facebook.react.bridge.ReadableArray
+import com.facebook.react.bridge.ReadableMap
 import com.facebook.react.bridge.ReadableType
 import com.facebook.react.bridge.WritableNativeArray
 import com.facebook.react.bridge.WritableNativeMap
@@ -15,6 +16,9 @@ import kotlinx.coroutines.launch
 
 import xyz.twenty_two.PortalSdk
 import xyz.twenty_two.GenerateMnemonicWords
+import xyz.twenty_two.SdkException
+import xyz.twenty_two.SetDescriptorBsmsData
+import xyz.twenty_two.sdkErrorCode
 
 class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
   ReactContextBaseJavaModule(reactContext) {
@@ -26,6 +30,15 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
     return NAME
   }
 
+  // Errors of the SDK are rejected with their stable numeric code, see `SdkError::code()`
+  fun reject(promise: Promise, e: Exception) {
+    if (e is SdkException) {
+      promise.reject(sdkErrorCode(e).toString(), e.message, e)
+    } else {
+      promise.reject(e)
+    }
+  }
+
   @ReactMethod
   fun constructor(useFastOps: Boolean, promise: Promise) {
     if (instance == null) {
@@ -47,7 +60,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
 
         promise.resolve(map)
       } catch (e: Exception) {
-        promise.reject(e)
+        reject(promise, e)
       }
     }
   }
@@ -59,7 +72,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         instance!!.newTag()
         promise.resolve(null)
       } catch (e: Exception) {
-        promise.reject(e)
+        reject(promise, e)
       }
     }
   }
@@ -85,7 +98,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(null)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -104,7 +117,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(map)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -118,11 +131,11 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
 
     try {
         scope.launch {
-          instance!!.generateMnemonic(numWordsParsed, network, pair_code)
+          instance!!.generateMnemonic(numWordsParsed, network, pair_code, false, null)
           promise.resolve(null)
         }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -130,11 +143,11 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
   fun restoreMnemonic(mnemonic: String, network: String, pair_code: String?, promise: Promise) {
     try {
       scope.launch {
-        instance!!.restoreMnemonic(mnemonic, network, pair_code)
+        instance!!.restoreMnemonic(mnemonic, network, pair_code, false)
         promise.resolve(null)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -146,7 +159,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(null)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -158,7 +171,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(null)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -169,7 +182,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(instance!!.displayAddress(index.toUInt()))
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -180,7 +193,66 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(instance!!.signPsbt(psbt))
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
+    }
+  }
+
+  @ReactMethod
+  fun signMessage(index: Int, message: String, promise: Promise) {
+    scope.launch {
+      try {
+        val signature = instance!!.signMessage(index.toUInt(), message)
+
+        val map = WritableNativeMap()
+        map.putString("address", signature.address)
+        map.putString("signature", signature.signature)
+
+        promise.resolve(map)
+      } catch (e: Exception) {
+        reject(promise, e)
+      }
+    }
+  }
+
+  @ReactMethod
+  fun getXpub(path: String, promise: Promise) {
+    scope.launch {
+      try {
+        val xpub = instance!!.getXpub(path)
+
+        val bsms = WritableNativeMap()
+        bsms.putString("version", xpub.bsms.version)
+        bsms.putString("token", xpub.bsms.token)
+        bsms.putString("keyName", xpub.bsms.keyName)
+        bsms.putString("signature", xpub.bsms.signature)
+
+        val map = WritableNativeMap()
+        map.putString("xpub", xpub.xpub)
+        map.putMap("bsms", bsms)
+
+        promise.resolve(map)
+      } catch (e: Exception) {
+        reject(promise, e)
+      }
+    }
+  }
+
+  @ReactMethod
+  fun setDescriptor(descriptor: String, bsms: ReadableMap?, promise: Promise) {
+    scope.launch {
+      try {
+        val bsmsParsed = bsms?.let {
+          SetDescriptorBsmsData(
+            it.getString("version")!!,
+            it.getString("pathRestrictions")!!,
+            it.getString("firstAddress")!!,
+          )
+        }
+        instance!!.setDescriptor(descriptor, bsmsParsed)
+        promise.resolve(null)
+      } catch (e: Exception) {
+        reject(promise, e)
+      }
     }
   }
 
@@ -199,7 +271,7 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(map)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
     }
   }
 
@@ -211,7 +283,35 @@ class LibportalReactNativeModule(reactContext: ReactApplicationContext) :
         promise.resolve(null)
       }
     } catch (e: Exception) {
-      promise.reject(e)
+      reject(promise, e)
+    }
+  }
+
+  @ReactMethod
+  fun firmwareUpdateOffset(binary: ReadableArray, promise: Promise) {
+    scope.launch {
+      try {
+        promise.resolve(instance!!.firmwareUpdateOffset(convertByteArray(binary)).toDouble())
+      } catch (e: Exception) {
+        reject(promise, e)
+      }
+    }
+  }
+
+  @ReactMethod
+  fun progress(promise: Promise) {
+    scope.launch {
+      try {
+        val progress = instance!!.progress()
+
+        val map = WritableNativeMap()
+        map.putInt("current", progress.current.toInt())
+        map.putInt("total", progress.total.toInt())
+
+        promise.resolve(map)
+      } catch (e: Exception) {
+        reject(promise, e)
+      }
     }
   }
 
@@ -21,6 +21,14 @@ func convertNSArrayToData(nsNumberArray: NSArray) -> Data? {
     return Data(byteArray)
 }
 
+// Errors of the SDK are rejected with their stable numeric code, see `SdkError::code()`
+func errorCode(_ error: Error) -> String {
+    if let error = error as? SdkError {
+        return String(sdkErrorCode(error: error))
+    }
+    return "Error"
+}
+
 @objc(LibportalReactNative)
 class LibportalReactNative: NSObject {
     private var sdk: PortalSdk? = nil
@@ -41,7 +49,7 @@ class LibportalReactNative: NSObject {
                 resolve(dict)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -53,7 +61,7 @@ class LibportalReactNative: NSObject {
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -67,7 +75,7 @@ class LibportalReactNative: NSObject {
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -84,7 +92,7 @@ class LibportalReactNative: NSObject {
                 resolve(dict)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -105,11 +113,11 @@ class LibportalReactNative: NSObject {
         
         Task {
             do {
-                try await self.sdk?.generateMnemonic(numWords: numWords, network: network, password: pair_code)
+                try await self.sdk?.generateMnemonic(numWords: numWords, network: network, password: pair_code, disableSeedExport: false, language: nil)
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -121,11 +129,11 @@ class LibportalReactNative: NSObject {
         
         Task {
             do {
-                try await self.sdk?.restoreMnemonic(mnemonic: words, network: network, password: pair_code)
+                try await self.sdk?.restoreMnemonic(mnemonic: words, network: network, password: pair_code, disableSeedExport: false)
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -139,7 +147,7 @@ class LibportalReactNative: NSObject {
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -151,7 +159,7 @@ class LibportalReactNative: NSObject {
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -165,7 +173,7 @@ class LibportalReactNative: NSObject {
                 resolve(address)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -179,7 +187,74 @@ class LibportalReactNative: NSObject {
                 resolve(psbt)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
+            }
+        }
+    }
+    
+    @objc func signMessage(_ index: NSNumber, message: NSString, withResolver resolve: @escaping RCTPromiseResolveBlock, withRejecter reject: @escaping RCTPromiseRejectBlock) -> Void {
+        let index = UInt32(truncating: index)
+        let message = message as String
+        
+        Task {
+            do {
+                let signature = try await self.sdk?.signMessage(index: index, message: message)
+                let dict: NSDictionary = [
+                    "address": signature?.address as Any,
+                    "signature": signature?.signature as Any,
+                ]
+                resolve(dict)
+            }
+            catch {
+                reject(errorCode(error), error.localizedDescription, error)
+            }
+        }
+    }
+    
+    @objc func getXpub(_ path: NSString, withResolver resolve: @escaping RCTPromiseResolveBlock, withRejecter reject: @escaping RCTPromiseRejectBlock) -> Void {
+        let path = path as String
+        
+        Task {
+            do {
+                let xpub = try await self.sdk?.getXpub(path: path)
+                let bsms: NSDictionary = [
+                    "version": xpub?.bsms.version as Any,
+                    "token": xpub?.bsms.token as Any,
+                    "keyName": xpub?.bsms.keyName as Any,
+                    "signature": xpub?.bsms.signature as Any,
+                ]
+                let dict: NSDictionary = [
+                    "xpub": xpub?.xpub as Any,
+                    "bsms": bsms,
+                ]
+                resolve(dict)
+            }
+            catch {
+                reject(errorCode(error), error.localizedDescription, error)
+            }
+        }
+    }
+    
+    @objc func setDescriptor(_ descriptor: NSString, bsms: NSDictionary?, withResolver resolve: @escaping RCTPromiseResolveBlock, withRejecter reject: @escaping RCTPromiseRejectBlock) -> Void {
+        let descriptor = descriptor as String
+        var bsmsData: SetDescriptorBsmsData? = nil
+        if let bsms = bsms {
+            guard let version = bsms["version"] as? String,
+                  let pathRestrictions = bsms["pathRestrictions"] as? String,
+                  let firstAddress = bsms["firstAddress"] as? String else {
+                reject("Error", "Invalid BSMS data", nil)
+                return
+            }
+            bsmsData = SetDescriptorBsmsData(version: version, pathRestrictions: pathRestrictions, firstAddress: firstAddress)
+        }
+        
+        Task {
+            do {
+                try await self.sdk?.setDescriptor(descriptor: descriptor, bsms: bsmsData)
+                resolve(nil)
+            }
+            catch {
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -195,7 +270,7 @@ class LibportalReactNative: NSObject {
                 resolve(dict)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -209,7 +284,37 @@ class LibportalReactNative: NSObject {
                 resolve(nil)
             }
             catch {
-                reject("Error", error.localizedDescription, error)
+                reject(errorCode(error), error.localizedDescription, error)
+            }
+        }
+    }
+    
+    @objc func firmwareUpdateOffset(_ binary: NSArray, withResolver resolve: @escaping RCTPromiseResolveBlock, withRejecter reject: @escaping RCTPromiseRejectBlock) -> Void {
+        let binary = convertNSArrayToData(nsNumberArray: binary)
+        
+        Task {
+            do {
+                let offset = try await self.sdk?.firmwareUpdateOffset(binary: binary!)
+                resolve(offset)
+            }
+            catch {
+                reject(errorCode(error), error.localizedDescription, error)
+            }
+        }
+    }
+    
+    @objc func progress(_ resolve: @escaping RCTPromiseResolveBlock, withRejecter reject: @escaping RCTPromiseRejectBlock) -> Void {
+        Task {
+            do {
+                let progress = try await self.sdk?.progress()
+                let dict: NSDictionary = [
+                    "current": progress?.current as Any,
+                    "total": progress?.total as Any,
+                ]
+                resolve(dict)
+            }
+            catch {
+                reject(errorCode(error), error.localizedDescription, error)
             }
         }
     }
@@ -70,6 +70,18 @@ export class PortalSdk {
     return LibportalReactNative.signPsbt(psbt);
   }
 
+  signMessage(index: number, message: string): Promise<MessageSignature> {
+    return LibportalReactNative.signMessage(index, message);
+  }
+
+  getXpub(path: string): Promise<DeviceXpub> {
+    return LibportalReactNative.getXpub(path);
+  }
+
+  setDescriptor(descriptor: string, bsms?: SetDescriptorBsmsData): Promise<void> {
+    return LibportalReactNative.setDescriptor(descriptor, bsms);
+  }
+
   publicDescriptors(): Promise<Descriptors> {
     return LibportalReactNative.publicDescriptors();
   }
@@ -77,6 +89,19 @@ export class PortalSdk {
   updateFirmware(bytes: number[]): Promise<void> {
     return LibportalReactNative.updateFirmware(bytes);
   }
+
+  /**
+   * Number of bytes of `bytes` already written by an interrupted update, which `updateFirmware()`
+   * resumes from
+   */
+  firmwareUpdateOffset(bytes: number[]): Promise<number> {
+    return LibportalReactNative.firmwareUpdateOffset(bytes);
+  }
+
+  /** Next progress update of a long operation, like signing or a firmware update */
+  progress(): Promise<OperationProgress> {
+    return LibportalReactNative.progress();
+  }
 }
 
 export enum Network {
@@ -106,4 +131,32 @@ export interface CardStatus {
   readonly unverified?: boolean,
   readonly unlocked: boolean,
   readonly network?: Network,
+}
+
+export interface MessageSignature {
+  readonly address: string,
+  readonly signature: string,
+}
+
+export interface GetXpubBsmsData {
+  readonly version: string,
+  readonly token: string,
+  readonly keyName: string,
+  readonly signature: string,
+}
+
+export interface DeviceXpub {
+  readonly xpub: string,
+  readonly bsms: GetXpubBsmsData,
+}
+
+export interface SetDescriptorBsmsData {
+  readonly version: string,
+  readonly pathRestrictions: string,
+  readonly firstAddress: string,
+}
+
+export interface OperationProgress {
+  readonly current: number,
+  readonly total: number,
 }
\ No newline at end of file
@@ -0,0 +1,104 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+
+use model::attestation::{Attestation, AttestationError};
+use model::bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+
+use crate::SdkError;
+
+impl From<AttestationError> for SdkError {
+    fn from(e: AttestationError) -> Self {
+        SdkError::InvalidAttestation {
+            detail: e.to_string(),
+        }
+    }
+}
+
+/// Device that passed the genuine check
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct DeviceAttestation {
+    /// Hex-encoded attestation key of the device, unique to every device
+    pub device_key: String,
+    /// Hex-encoded SHA256 of the firmware area of the flash
+    pub firmware_hash: String,
+}
+
+/// Verify the reply of the device to an attestation request made with `challenge`
+///
+/// `root_key` is the hex-encoded x-only public key of the manufacturer.
+pub fn verify_attestation(
+    root_key: &str,
+    challenge: &[u8; 32],
+    attestation: &Attestation,
+) -> Result<DeviceAttestation, SdkError> {
+    let root_key =
+        XOnlyPublicKey::from_str(root_key).map_err(|_| SdkError::InvalidAttestation {
+            detail: "Invalid root key".into(),
+        })?;
+
+    let device_key = attestation.verify(&Secp256k1::verification_only(), &root_key, challenge)?;
+
+    Ok(DeviceAttestation {
+        device_key: device_key.to_string(),
+        firmware_hash: attestation
+            .firmware_hash
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use model::attestation::{AttestationCertificate, AttestationKey};
+    use model::bitcoin::secp256k1::KeyPair;
+
+    #[test]
+    fn test_verify_attestation() {
+        let ctx = Secp256k1::new();
+        let root = KeyPair::from_seckey_slice(&ctx, &[0x01; 32]).unwrap();
+        let device = KeyPair::from_seckey_slice(&ctx, &[0x02; 32]).unwrap();
+        let key = AttestationKey {
+            secret_key: Box::new(device.secret_bytes().into()),
+            chain: vec![AttestationCertificate::new(
+                &ctx,
+                &root,
+                &device.x_only_public_key().0,
+            )],
+        };
+        let attestation = key.attest(&ctx, &[0x42; 32], &[0xAA; 32]).unwrap();
+
+        let root_key = root.x_only_public_key().0.to_string();
+        let result = verify_attestation(&root_key, &[0x42; 32], &attestation).unwrap();
+        assert_eq!(result.device_key, device.x_only_public_key().0.to_string());
+        assert_eq!(result.firmware_hash, "aa".repeat(32));
+
+        assert!(matches!(
+            verify_attestation(&root_key, &[0x43; 32], &attestation),
+            Err(SdkError::InvalidAttestation { .. })
+        ));
+        assert!(matches!(
+            verify_attestation("00", &[0x42; 32], &attestation),
+            Err(SdkError::InvalidAttestation { .. })
+        ));
+    }
+}
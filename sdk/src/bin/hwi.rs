@@ -0,0 +1,526 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Bridge exposing the HWI command line interface on top of the Portal SDK
+//!
+//! Wallets like Sparrow, Specter and Bitcoin Core can be pointed to this binary in place of
+//! `hwi`. Every invocation runs a single command, waiting for the Portal to be tapped on the
+//! reader, and prints the result as JSON on stdout.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+
+use serde_json::{json, Value};
+
+use miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use miniscript::ForEachKey;
+
+use model::bitcoin::util::bip32::{ChildNumber, DerivationPath};
+use model::bitcoin::Network;
+
+use portal::*;
+
+// Error codes used by HWI
+const MISSING_ARGUMENTS: i32 = -2;
+const DEVICE_CONN_ERROR: i32 = -3;
+const INVALID_TX: i32 = -5;
+const NO_PASSWORD: i32 = -6;
+const BAD_ARGUMENT: i32 = -7;
+const NOT_IMPLEMENTED: i32 = -8;
+const DEVICE_NOT_READY: i32 = -12;
+const UNKNOWN_ERROR: i32 = -13;
+const ACTION_CANCELED: i32 = -14;
+const DEVICE_NOT_INITIALIZED: i32 = -18;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct CliArgs {
+    /// Device type, only `portal` is supported
+    #[clap(long, short = 't')]
+    device_type: Option<String>,
+
+    /// Device path, ignored since the device is always reached through the first NFC reader
+    #[clap(long, short = 'd')]
+    device_path: Option<String>,
+
+    /// Fingerprint of the device to use
+    #[clap(long, short = 'f')]
+    fingerprint: Option<String>,
+
+    /// Pair code used to unlock the device
+    #[clap(long, short = 'p')]
+    password: Option<String>,
+
+    /// Use testnet prefixes
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value_t = false)]
+    testnet: bool,
+
+    /// Select the chain to work with
+    #[clap(long, value_parser = parse_chain)]
+    chain: Option<Network>,
+
+    /// Read commands from stdin
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value_t = false)]
+    stdin: bool,
+
+    /// Use the commands interactively, telling on stderr when to tap the Portal on the reader
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value_t = false)]
+    interactive: bool,
+
+    /// Show advanced information
+    #[clap(long, action = clap::ArgAction::SetTrue, default_value_t = false)]
+    expert: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List all the available devices
+    Enumerate,
+    /// Get the xpub for the default account
+    Getmasterxpub {
+        #[clap(long, default_value_t = 0)]
+        account: u32,
+    },
+    /// Get an xpub at the given derivation path
+    Getxpub { path: String },
+    /// Sign a PSBT
+    Signtx { psbt: String },
+    /// Show an address on the device
+    Displayaddress {
+        #[clap(long)]
+        path: Option<String>,
+        #[clap(long)]
+        desc: Option<String>,
+    },
+    /// Register a wallet policy on the device
+    #[clap(alias = "registerpolicy")]
+    Register {
+        #[clap(long)]
+        desc: String,
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// Print the file to import the wallet in another software (not part of HWI)
+    Exportwallet {
+        /// One of `electrum`, `sparrow`, `specter` or `bluewallet`
+        #[clap(long, value_parser = parse_export_format)]
+        format: WalletExportFormat,
+        #[clap(long, default_value = "Portal")]
+        name: String,
+    },
+    /// Print the cosigner file for a multisig wallet (not part of HWI)
+    Exportcosigner {
+        #[clap(long, default_value_t = 0)]
+        account: u32,
+        /// One of `wsh`, `sh-wsh` or `sh`
+        #[clap(long, value_parser = parse_script_type, default_value = "wsh")]
+        script: BsmsScriptType,
+    },
+}
+
+fn parse_chain(s: &str) -> Result<Network, String> {
+    match s {
+        "main" => Ok(Network::Bitcoin),
+        "test" => Ok(Network::Testnet),
+        "signet" => Ok(Network::Signet),
+        "regtest" => Ok(Network::Regtest),
+        _ => Err(format!("Invalid chain: {}", s)),
+    }
+}
+
+fn parse_export_format(s: &str) -> Result<WalletExportFormat, String> {
+    match s {
+        "electrum" => Ok(WalletExportFormat::Electrum),
+        "sparrow" => Ok(WalletExportFormat::Sparrow),
+        "specter" => Ok(WalletExportFormat::Specter),
+        "bluewallet" => Ok(WalletExportFormat::BlueWallet),
+        _ => Err(format!("Invalid format: {}", s)),
+    }
+}
+
+fn parse_script_type(s: &str) -> Result<BsmsScriptType, String> {
+    match s {
+        "wsh" => Ok(BsmsScriptType::NativeSegwit),
+        "sh-wsh" => Ok(BsmsScriptType::WrappedSegwit),
+        "sh" => Ok(BsmsScriptType::Legacy),
+        _ => Err(format!("Invalid script type: {}", s)),
+    }
+}
+
+struct HwiError {
+    code: i32,
+    message: String,
+}
+
+impl HwiError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        HwiError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<SdkError> for HwiError {
+    fn from(e: SdkError) -> Self {
+        let code = match &e {
+            SdkError::Locked => NO_PASSWORD,
+            SdkError::ChannelError
+            | SdkError::CommunicationError
+            | SdkError::DifferentUid
+            | SdkError::Timeout => DEVICE_CONN_ERROR,
+            SdkError::DeserializationError
+            | SdkError::Base64
+            | SdkError::InvalidSignatures { .. } => INVALID_TX,
+            SdkError::InvalidDescriptor { .. } | SdkError::UnsupportedDescriptor { .. } => {
+                BAD_ARGUMENT
+            }
+            SdkError::UnexpectedMessage => ACTION_CANCELED,
+            SdkError::DeviceError {
+                code: Some(DeviceErrorCode::InvalidPsbt),
+                ..
+            } => INVALID_TX,
+            SdkError::DeviceError {
+                code:
+                    Some(
+                        DeviceErrorCode::InvalidKey
+                        | DeviceErrorCode::WrongNetwork
+                        | DeviceErrorCode::LocalKeyMissing
+                        | DeviceErrorCode::UnsupportedDescriptor
+                        | DeviceErrorCode::InvalidThreshold,
+                    ),
+                ..
+            } => BAD_ARGUMENT,
+            SdkError::InvalidFirmware
+            | SdkError::InvalidAttestation { .. }
+            | SdkError::DeviceError { .. } => UNKNOWN_ERROR,
+        };
+
+        HwiError::new(code, e.to_string())
+    }
+}
+
+/// Extract the address index from the last step of a derivation path
+fn index_from_path(path: &DerivationPath) -> Result<u32, HwiError> {
+    match path.as_ref().last() {
+        Some(ChildNumber::Normal { index }) => Ok(*index),
+        _ => Err(HwiError::new(
+            BAD_ARGUMENT,
+            "The path must end with an unhardened index",
+        )),
+    }
+}
+
+async fn check_device(sdk: &PortalSdk, args: &CliArgs) -> Result<CardStatus, HwiError> {
+    let mut status = sdk.get_status().await?;
+    if !status.initialized {
+        return Err(HwiError::new(
+            DEVICE_NOT_INITIALIZED,
+            "The device is not initialized",
+        ));
+    }
+
+    if !status.unlocked {
+        match &args.password {
+            Some(password) => {
+                sdk.unlock(password.clone()).await?;
+                status = sdk.get_status().await?;
+            }
+            None => {
+                return Err(HwiError::new(
+                    NO_PASSWORD,
+                    "The device is locked, pass the pair code with `--password`",
+                ))
+            }
+        }
+    }
+
+    if let Some(fingerprint) = &args.fingerprint {
+        let matches = status
+            .fingerprint
+            .map(|f| f.to_string() == fingerprint.to_lowercase())
+            .unwrap_or(false);
+        if !matches {
+            return Err(HwiError::new(
+                DEVICE_CONN_ERROR,
+                format!("Could not find a device with fingerprint {}", fingerprint),
+            ));
+        }
+    }
+
+    let chain = match (args.chain, args.testnet) {
+        (Some(chain), _) => Some(chain),
+        (None, true) => Some(Network::Testnet),
+        (None, false) => None,
+    };
+    if let (Some(chain), Some(network)) = (chain, status.network) {
+        // Testnet and signet share the same prefixes and coin type
+        let is_mainnet = |n: Network| n == Network::Bitcoin;
+        if is_mainnet(chain) != is_mainnet(network) {
+            return Err(HwiError::new(
+                DEVICE_NOT_READY,
+                format!("The device is configured for {}", network),
+            ));
+        }
+    }
+
+    Ok(status)
+}
+
+async fn run_command(sdk: Arc<PortalSdk>, args: CliArgs) -> Result<Value, HwiError> {
+    if args.stdin {
+        return Err(HwiError::new(
+            NOT_IMPLEMENTED,
+            "Reading commands from stdin is not supported",
+        ));
+    }
+    match args.device_type.as_deref() {
+        None | Some("portal") => {}
+        Some(other) => {
+            return Err(HwiError::new(
+                BAD_ARGUMENT,
+                format!("Unsupported device type: {}", other),
+            ))
+        }
+    }
+
+    match &args.command {
+        Command::Enumerate => {
+            let status = sdk.get_status().await?;
+            let mut device = json!({
+                "type": "portal",
+                "model": "portal",
+                "label": null,
+                "path": "nfc",
+                "needs_pin_sent": false,
+                "needs_passphrase_sent": status.initialized && !status.unlocked,
+            });
+            match status.fingerprint {
+                Some(fingerprint) => device["fingerprint"] = json!(fingerprint.to_string()),
+                None if !status.initialized => {
+                    device["error"] = json!("Not initialized");
+                    device["code"] = json!(DEVICE_NOT_INITIALIZED);
+                }
+                None => {}
+            }
+
+            Ok(json!([device]))
+        }
+        Command::Getmasterxpub { account } => {
+            let status = check_device(&sdk, &args).await?;
+            let coin_type = match status.network {
+                Some(Network::Bitcoin) => 0,
+                _ => 1,
+            };
+            let path = DerivationPath::from_str(&format!("m/84'/{}'/{}'", coin_type, account))
+                .expect("Valid path");
+            let xpub = sdk.get_xpub(path).await?;
+
+            Ok(json!({ "xpub": xpub.xpub }))
+        }
+        Command::Getxpub { path } => {
+            let path = DerivationPath::from_str(path)
+                .map_err(|_| HwiError::new(BAD_ARGUMENT, "Invalid derivation path"))?;
+            check_device(&sdk, &args).await?;
+            let xpub = sdk.get_xpub(path).await?;
+
+            Ok(json!({ "xpub": xpub.xpub }))
+        }
+        Command::Signtx { psbt } => {
+            check_device(&sdk, &args).await?;
+            let signed = sdk.sign_psbt(psbt.clone()).await?;
+
+            Ok(json!({ "psbt": signed, "signed": signed != *psbt }))
+        }
+        Command::Displayaddress { path, desc } => {
+            let index = match (path, desc) {
+                (Some(path), None) => {
+                    let path = DerivationPath::from_str(path)
+                        .map_err(|_| HwiError::new(BAD_ARGUMENT, "Invalid derivation path"))?;
+                    index_from_path(&path)?
+                }
+                (None, Some(desc)) => {
+                    let desc = Descriptor::<DescriptorPublicKey>::from_str(desc)
+                        .map_err(|e| HwiError::new(BAD_ARGUMENT, e.to_string()))?;
+
+                    let mut indexes = vec![];
+                    desc.for_each_key(|pk| {
+                        if let DescriptorPublicKey::XPub(xpub) = pk {
+                            indexes.push(index_from_path(&xpub.derivation_path));
+                        }
+                        true
+                    });
+                    indexes
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| HwiError::new(BAD_ARGUMENT, "Missing extended key"))??
+                }
+                _ => {
+                    return Err(HwiError::new(
+                        MISSING_ARGUMENTS,
+                        "Exactly one of `--path` or `--desc` must be specified",
+                    ))
+                }
+            };
+
+            check_device(&sdk, &args).await?;
+            let address = sdk.display_address(index).await?;
+
+            Ok(json!({ "address": address.to_string() }))
+        }
+        Command::Register { desc, name } => {
+            if name.is_some() {
+                log::warn!("Wallet names are not supported, ignoring");
+            }
+
+            check_device(&sdk, &args).await?;
+            sdk.set_descriptor(desc.clone(), None).await?;
+
+            // The descriptor is stored on the device, so there's no HMAC to return
+            Ok(json!({ "hmac": null }))
+        }
+        Command::Exportwallet { format, name } => {
+            check_device(&sdk, &args).await?;
+            let descriptors = sdk.public_descriptors().await?;
+            let file = sdk.export_wallet(descriptors.external, *format, name.clone())?;
+
+            Ok(json!({ "file": file }))
+        }
+        Command::Exportcosigner { account, script } => {
+            let status = check_device(&sdk, &args).await?;
+            let coin_type = match status.network {
+                Some(Network::Bitcoin) => 0,
+                _ => 1,
+            };
+            let path = match script {
+                BsmsScriptType::NativeSegwit => format!("m/48'/{}'/{}'/2'", coin_type, account),
+                BsmsScriptType::WrappedSegwit => format!("m/48'/{}'/{}'/1'", coin_type, account),
+                BsmsScriptType::Legacy => "m/45'".into(),
+            };
+            let path = DerivationPath::from_str(&path).expect("Valid path");
+            let xpub = sdk.get_xpub(path).await?;
+            let file = sdk.export_cosigner(xpub, *script)?;
+
+            Ok(json!({ "file": file }))
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> nfc1::Result<()> {
+    // Keep stdout clean, it's only used to print the JSON result
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .target(env_logger::Target::Stderr)
+        .init();
+
+    let args = CliArgs::parse();
+
+    let mut context = nfc1::Context::new()?;
+    let mut device = context.open()?;
+
+    device.initiator_init()?;
+
+    log::info!(
+        "NFC device {:?} opened through connection {:?}",
+        device.name(),
+        device.connstring()
+    );
+
+    let modulation = nfc1::Modulation {
+        modulation_type: nfc1::ModulationType::Iso14443a,
+        baud_rate: nfc1::BaudRate::Baud106,
+    };
+    let sdk = PortalSdk::new(false);
+
+    if args.interactive {
+        eprintln!("Tap the Portal on the reader...");
+    }
+
+    let mut handle = tokio::task::spawn(run_command(Arc::clone(&sdk), args));
+
+    let result = 'outer: loop {
+        if handle.is_finished() {
+            break (&mut handle).await;
+        }
+
+        let devices = device.initiator_list_passive_targets(&modulation, 1)?;
+        let target = match devices.get(0) {
+            Some(t) => t,
+            None => continue,
+        };
+
+        let uid = if let nfc1::target_info::TargetInfo::Iso14443a(target_info) = target.target_info
+        {
+            target_info.uid[..target_info.uid_len].to_vec()
+        } else {
+            return Err(nfc1::Error::DeviceNotSupported);
+        };
+        if uid.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        }
+
+        log::info!("Found ISO/IEC 14443-A target: {:?}", uid);
+
+        sdk.new_tag().await.unwrap();
+
+        loop {
+            if handle.is_finished() {
+                break 'outer (&mut handle).await;
+            }
+
+            let NfcOut { msg_index, data } =
+                match tokio::time::timeout(Duration::from_millis(100), sdk.poll()).await {
+                    Ok(Ok(out)) => out,
+                    Ok(Err(_)) => break,
+                    Err(_) => continue,
+                };
+
+            let in_data = match device.initiator_transceive_bytes(
+                &data,
+                MAX_READ_FRAME,
+                nfc1::Timeout::Default,
+            ) {
+                Ok(v) => v,
+                Err(e) => {
+                    log::warn!("{:?}", e);
+
+                    tokio::time::sleep(Duration::from_millis(25)).await;
+                    continue 'outer;
+                }
+            };
+            sdk.incoming_data(msg_index, in_data).await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+
+    let output = match result {
+        Ok(Ok(value)) => value,
+        Ok(Err(HwiError { code, message })) => json!({ "error": message, "code": code }),
+        Err(e) => json!({ "error": e.to_string(), "code": UNKNOWN_ERROR }),
+    };
+    println!("{}", output);
+
+    Ok(())
+}
@@ -0,0 +1,358 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Coordinator of a multisig setup with BSMS (BIP-129)
+//!
+//! The coordinator collects the key records of every signer (Round 1), checks their signatures,
+//! assembles the descriptor and produces the descriptor record (Round 2) that each signer
+//! verifies before saving the wallet. Only records without encryption (token `00`) are
+//! supported, which is what Portal devices produce.
+
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use miniscript::descriptor::{Descriptor, DescriptorPublicKey, Wildcard};
+
+use model::bitcoin::secp256k1::Secp256k1;
+use model::bitcoin::util::misc::{signed_msg_hash, MessageSignature};
+use model::bitcoin::Network;
+
+use super::*;
+
+const VERSION: &str = "1.0";
+const NO_ENCRYPTION: &str = "00";
+const PATH_RESTRICTIONS: &str = "/0/*,/1/*";
+
+fn invalid(cause: &str) -> SdkError {
+    SdkError::InvalidDescriptor {
+        detail: cause.into(),
+    }
+}
+
+/// Script used for the multisig wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum BsmsScriptType {
+    /// `wsh(sortedmulti(...))`
+    NativeSegwit,
+    /// `sh(wsh(sortedmulti(...)))`
+    WrappedSegwit,
+    /// `sh(sortedmulti(...))`
+    Legacy,
+}
+
+impl BsmsScriptType {
+    fn wrap(&self, multi: &str) -> String {
+        match self {
+            BsmsScriptType::NativeSegwit => format!("wsh({})", multi),
+            BsmsScriptType::WrappedSegwit => format!("sh(wsh({}))", multi),
+            BsmsScriptType::Legacy => format!("sh({})", multi),
+        }
+    }
+}
+
+/// Key record sent by a signer in the first round
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct BsmsKeyRecord {
+    pub token: String,
+    /// Key with its origin, e.g. `[fingerprint/48'/0'/0'/2']xpub...`
+    pub key: String,
+    pub description: String,
+    /// Base64-encoded signature of the record made with `key`
+    pub signature: String,
+}
+
+impl BsmsKeyRecord {
+    /// Parse the text of a record, as exchanged with third-party signers
+    pub fn parse(record: &str) -> Result<Self, SdkError> {
+        let lines = record.trim().lines().map(str::trim).collect::<Vec<_>>();
+        let [header, token, key, description, signature] = lines[..] else {
+            return Err(invalid("A key record must have exactly 5 lines"));
+        };
+        if header != format!("BSMS {}", VERSION) {
+            return Err(SdkError::UnsupportedDescriptor {
+                detail: "Unsupported BSMS version".into(),
+            });
+        }
+
+        Ok(BsmsKeyRecord {
+            token: token.into(),
+            key: key.into(),
+            description: description.into(),
+            signature: signature.into(),
+        })
+    }
+
+    /// Text of the record
+    pub fn serialize(&self) -> String {
+        format!(
+            "BSMS {}\n{}\n{}\n{}\n{}",
+            VERSION, self.token, self.key, self.description, self.signature
+        )
+    }
+
+    /// Check that the record is signed by its own key and return the key
+    fn verify(&self) -> Result<DescriptorPublicKey, SdkError> {
+        if self.token != NO_ENCRYPTION {
+            return Err(SdkError::UnsupportedDescriptor {
+                detail: "Only records without encryption (token `00`) are supported".into(),
+            });
+        }
+
+        let key = DescriptorPublicKey::from_str(&self.key).map_err(|e| invalid(&e.to_string()))?;
+        let xkey = match &key {
+            DescriptorPublicKey::XPub(xkey)
+                if xkey.origin.is_some()
+                    && xkey.wildcard == Wildcard::None
+                    && xkey.derivation_path.is_master() =>
+            {
+                xkey
+            }
+            _ => return Err(invalid("The key must be an xpub with its origin")),
+        };
+
+        let message = format!(
+            "BSMS {}\n{}\n{}\n{}",
+            VERSION, self.token, self.key, self.description
+        );
+        let signature = base64::decode(&self.signature)?;
+        let signer = MessageSignature::from_slice(&signature)
+            .and_then(|s| {
+                s.recover_pubkey(&Secp256k1::verification_only(), signed_msg_hash(&message))
+            })
+            .map_err(|_| SdkError::InvalidSignatures {
+                detail: "Invalid key record signature".into(),
+            })?;
+        if signer.inner != xkey.xkey.public_key {
+            return Err(SdkError::InvalidSignatures {
+                detail: format!(
+                    "The key record of `{}` isn't signed by its key",
+                    self.description
+                ),
+            });
+        }
+
+        Ok(key)
+    }
+}
+
+impl From<DeviceXpub> for BsmsKeyRecord {
+    fn from(xpub: DeviceXpub) -> Self {
+        BsmsKeyRecord {
+            token: xpub.bsms.token,
+            key: xpub.xpub,
+            description: xpub.bsms.key_name,
+            signature: xpub.bsms.signature,
+        }
+    }
+}
+
+/// Descriptor record sent to every signer in the second round
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct BsmsDescriptorRecord {
+    /// Descriptor template, with every key ending in `/**`
+    pub descriptor: String,
+    /// Data to pass to `PortalSdk::set_descriptor()` together with `descriptor`
+    pub bsms: SetDescriptorBsmsData,
+    /// Text of the record, for third-party signers
+    pub record: String,
+}
+
+/// Collect the keys of the signers and assemble the multisig descriptor
+#[cfg_attr(feature = "bindings", derive(uniffi::Object))]
+pub struct BsmsCoordinator {
+    threshold: u32,
+    script_type: BsmsScriptType,
+    network: Network,
+    keys: Mutex<Vec<(BsmsKeyRecord, DescriptorPublicKey)>>,
+}
+
+#[cfg_attr(feature = "bindings", uniffi::export)]
+impl BsmsCoordinator {
+    #[uniffi::constructor]
+    pub fn new(threshold: u32, script_type: BsmsScriptType, network: Network) -> Arc<Self> {
+        Arc::new(BsmsCoordinator {
+            threshold,
+            script_type,
+            network,
+            keys: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Add the key of a signer, after checking the signature of its record
+    pub fn add_key_record(&self, record: BsmsKeyRecord) -> Result<(), SdkError> {
+        let key = record.verify()?;
+
+        let mut keys = self.keys.lock().expect("Lock poisoned");
+        if keys.iter().any(|(_, k)| k == &key) {
+            return Err(invalid("The key was already added"));
+        }
+        keys.push((record, key));
+
+        Ok(())
+    }
+
+    /// Add the key of a signer from the text of its record
+    pub fn add_key_record_text(&self, record: String) -> Result<(), SdkError> {
+        self.add_key_record(BsmsKeyRecord::parse(&record)?)
+    }
+
+    /// Keys added so far, in the order they were added
+    pub fn key_records(&self) -> Vec<BsmsKeyRecord> {
+        self.keys
+            .lock()
+            .expect("Lock poisoned")
+            .iter()
+            .map(|(record, _)| record.clone())
+            .collect()
+    }
+
+    /// Assemble the descriptor with the keys added so far
+    pub fn descriptor_record(&self) -> Result<BsmsDescriptorRecord, SdkError> {
+        let keys = self.keys.lock().expect("Lock poisoned");
+        if self.threshold == 0 || keys.len() < self.threshold as usize {
+            return Err(invalid("Not enough keys for the threshold"));
+        }
+
+        let multi = |suffix: &str| {
+            let keys = keys
+                .iter()
+                .map(|(_, key)| format!("{}{}", key, suffix))
+                .collect::<Vec<_>>();
+            self.script_type.wrap(&format!(
+                "sortedmulti({},{})",
+                self.threshold,
+                keys.join(",")
+            ))
+        };
+
+        let first_address = Descriptor::<DescriptorPublicKey>::from_str(&multi("/0/*"))
+            .map_err(|e| invalid(&e.to_string()))?
+            .at_derivation_index(0)
+            .address(self.network)
+            .map_err(|e| invalid(&e.to_string()))?
+            .to_string();
+
+        let descriptor = multi("/**");
+        let record = format!(
+            "BSMS {}\n{}\n{}\n{}",
+            VERSION, descriptor, PATH_RESTRICTIONS, first_address
+        );
+
+        Ok(BsmsDescriptorRecord {
+            descriptor,
+            bsms: SetDescriptorBsmsData {
+                version: VERSION.into(),
+                path_restrictions: PATH_RESTRICTIONS.into(),
+                first_address,
+            },
+            record,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use model::bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+
+    fn signer(seed: u8) -> BsmsKeyRecord {
+        let ctx = Secp256k1::new();
+        let path = bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &[seed; 32]).unwrap();
+        let derived = master.derive_priv(&ctx, &path).unwrap();
+        let key = format!(
+            "[{}/48'/1'/0'/2']{}",
+            master.fingerprint(&ctx),
+            ExtendedPubKey::from_priv(&ctx, &derived)
+        );
+
+        let description = format!("Signer {}", seed);
+        let round1 = model::BsmsRound1::new(
+            VERSION,
+            NO_ENCRYPTION,
+            description.clone(),
+            &key,
+            &derived.private_key,
+            &ctx,
+        );
+        BsmsKeyRecord {
+            token: round1.token,
+            key,
+            description,
+            signature: base64::encode(round1.signature.deref().as_ref()),
+        }
+    }
+
+    #[test]
+    fn test_key_record_roundtrip() {
+        let record = signer(1);
+        assert_eq!(BsmsKeyRecord::parse(&record.serialize()).unwrap(), record);
+        assert!(BsmsKeyRecord::parse("BSMS 1.0\n00").is_err());
+    }
+
+    #[test]
+    fn test_descriptor_record() {
+        let coordinator = BsmsCoordinator::new(2, BsmsScriptType::NativeSegwit, Network::Testnet);
+        coordinator.add_key_record(signer(1)).unwrap();
+        assert!(coordinator.descriptor_record().is_err());
+        coordinator
+            .add_key_record_text(signer(2).serialize())
+            .unwrap();
+        coordinator.add_key_record(signer(3)).unwrap();
+        assert!(coordinator.add_key_record(signer(3)).is_err());
+
+        let result = coordinator.descriptor_record().unwrap();
+        assert!(result.descriptor.starts_with("wsh(sortedmulti(2,["));
+        assert_eq!(result.descriptor.matches("/**").count(), 3);
+        assert!(Descriptor::<String>::from_str(&result.descriptor).is_ok());
+
+        let lines = result.record.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "BSMS 1.0");
+        assert_eq!(lines[1], result.descriptor);
+        assert_eq!(lines[2], "/0/*,/1/*");
+        assert_eq!(lines[3], result.bsms.first_address);
+        assert!(result.bsms.first_address.starts_with("tb1q"));
+    }
+
+    #[test]
+    fn test_reject_invalid_signature() {
+        let coordinator = BsmsCoordinator::new(1, BsmsScriptType::Legacy, Network::Testnet);
+
+        let mut record = signer(1);
+        record.description = "Someone else".into();
+        assert!(matches!(
+            coordinator.add_key_record(record),
+            Err(SdkError::InvalidSignatures { .. })
+        ));
+
+        let mut record = signer(1);
+        record.signature = signer(2).signature;
+        assert!(coordinator.add_key_record(record).is_err());
+
+        let mut record = signer(1);
+        record.token = "a1b2c3d4e5f60718".into();
+        assert!(matches!(
+            coordinator.add_key_record(record),
+            Err(SdkError::UnsupportedDescriptor { .. })
+        ));
+    }
+}
@@ -0,0 +1,493 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Wallet files to import the wallet of the device in other software
+//!
+//! The files are built from the external descriptor returned by `PortalSdk::public_descriptors()`
+//! (or assembled by the `BsmsCoordinator`), so every key must have its origin and end with
+//! `/0/*`. Electrum and BlueWallet don't read descriptors: they get the account keys with the
+//! SLIP-132 prefix of the script type (`zpub`, `Zpub`, ...), so only single-sig and `sortedmulti`
+//! wallets are supported there, and taproot isn't. Sparrow and Specter read the descriptor itself.
+//!
+//! Cosigner files are the ones exported by Coldcard for a multisig setup, which most wallets
+//! accept to add a signer.
+
+use std::str::FromStr;
+
+use serde_json::json;
+
+use miniscript::descriptor::{
+    Descriptor, DescriptorPublicKey, DescriptorType, ShInner, Wildcard, WshInner,
+};
+use miniscript::ForEachKey;
+
+use model::bitcoin::util::base58;
+use model::bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use model::bitcoin::Network;
+
+use super::*;
+
+fn unsupported(cause: &str) -> SdkError {
+    SdkError::UnsupportedDescriptor {
+        detail: cause.into(),
+    }
+}
+
+/// Software the wallet file is meant for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum WalletExportFormat {
+    /// Watch-only wallet file, opened with "File > Open"
+    Electrum,
+    /// Output descriptor, imported with "File > Import Wallet"
+    Sparrow,
+    /// Wallet JSON, imported with "Add new wallet > Import from wallet software"
+    Specter,
+    /// Single-sig key or multisig setup file, imported with "Import wallet"
+    BlueWallet,
+}
+
+/// Script of the wallet, as far as the SLIP-132 prefixes are concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScriptKind {
+    Pkh,
+    ShWpkh,
+    Wpkh,
+    Sh,
+    ShWsh,
+    Wsh,
+}
+
+impl ScriptKind {
+    /// Version bytes of the extended keys, for mainnet and the test networks
+    fn version(&self, network: Network) -> [u8; 4] {
+        let (main, test) = match self {
+            ScriptKind::Pkh | ScriptKind::Sh => (0x0488b21e, 0x043587cf),
+            ScriptKind::ShWpkh => (0x049d7cb2, 0x044a5262),
+            ScriptKind::Wpkh => (0x04b24746, 0x045f1cf6),
+            ScriptKind::ShWsh => (0x0295b43f, 0x024289ef),
+            ScriptKind::Wsh => (0x02aa7ed3, 0x02575483),
+        };
+        let version: u32 = if network == Network::Bitcoin {
+            main
+        } else {
+            test
+        };
+        version.to_be_bytes()
+    }
+
+    /// Name used by Coldcard in multisig files
+    fn coldcard_name(&self) -> &'static str {
+        match self {
+            ScriptKind::Pkh | ScriptKind::Sh => "p2sh",
+            ScriptKind::ShWpkh | ScriptKind::ShWsh => "p2sh_p2wsh",
+            ScriptKind::Wpkh | ScriptKind::Wsh => "p2wsh",
+        }
+    }
+}
+
+impl From<BsmsScriptType> for ScriptKind {
+    fn from(script_type: BsmsScriptType) -> Self {
+        match script_type {
+            BsmsScriptType::NativeSegwit => ScriptKind::Wsh,
+            BsmsScriptType::WrappedSegwit => ScriptKind::ShWsh,
+            BsmsScriptType::Legacy => ScriptKind::Sh,
+        }
+    }
+}
+
+/// Account key of one of the signers
+struct AccountKey {
+    fingerprint: Fingerprint,
+    path: DerivationPath,
+    xpub: ExtendedPubKey,
+}
+
+impl AccountKey {
+    /// Key with the SLIP-132 prefix of `script`
+    fn slip132(&self, script: ScriptKind) -> String {
+        let mut data = self.xpub.encode();
+        data[..4].copy_from_slice(&script.version(self.xpub.network));
+        base58::check_encode_slice(&data)
+    }
+
+    fn electrum_keystore(&self, script: ScriptKind, label: &str) -> serde_json::Value {
+        json!({
+            "type": "bip32",
+            "xpub": self.slip132(script),
+            "xprv": null,
+            "derivation": self.path.to_string(),
+            "root_fingerprint": self.fingerprint.to_string(),
+            "label": label,
+        })
+    }
+}
+
+/// What other wallets need to know about the descriptor
+struct WalletInfo {
+    descriptor: Descriptor<DescriptorPublicKey>,
+    script: Option<ScriptKind>,
+    /// Signatures required, `None` for single-sig wallets
+    threshold: Option<usize>,
+    keys: Vec<AccountKey>,
+}
+
+impl WalletInfo {
+    fn parse(descriptor: &str) -> Result<Self, SdkError> {
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(descriptor).map_err(|e| {
+            SdkError::InvalidDescriptor {
+                detail: e.to_string(),
+            }
+        })?;
+
+        let script = match descriptor.desc_type() {
+            DescriptorType::Pkh => Some(ScriptKind::Pkh),
+            DescriptorType::ShWpkh => Some(ScriptKind::ShWpkh),
+            DescriptorType::Wpkh => Some(ScriptKind::Wpkh),
+            DescriptorType::ShSortedMulti => Some(ScriptKind::Sh),
+            DescriptorType::ShWshSortedMulti => Some(ScriptKind::ShWsh),
+            DescriptorType::WshSortedMulti => Some(ScriptKind::Wsh),
+            DescriptorType::Tr => None,
+            _ => {
+                return Err(unsupported(
+                    "Only single-sig, sortedmulti and taproot are supported",
+                ))
+            }
+        };
+        let threshold = match &descriptor {
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::SortedMulti(multi) => Some(multi.k),
+                ShInner::Wsh(wsh) => match wsh.as_inner() {
+                    WshInner::SortedMulti(multi) => Some(multi.k),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Descriptor::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(multi) => Some(multi.k),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut keys = vec![];
+        let mut invalid_key = false;
+        descriptor.for_each_key(|key| {
+            match key {
+                DescriptorPublicKey::XPub(xkey)
+                    if xkey.wildcard == Wildcard::Unhardened
+                        && xkey.derivation_path.as_ref() == [ChildNumber::from(0)] =>
+                {
+                    match &xkey.origin {
+                        Some((fingerprint, path)) => keys.push(AccountKey {
+                            fingerprint: *fingerprint,
+                            path: path.clone(),
+                            xpub: xkey.xkey,
+                        }),
+                        None => invalid_key = true,
+                    }
+                }
+                _ => invalid_key = true,
+            }
+            true
+        });
+        if invalid_key {
+            return Err(SdkError::InvalidDescriptor {
+                detail: "Every key must be an xpub with its origin, ending with `/0/*`".into(),
+            });
+        }
+
+        Ok(WalletInfo {
+            descriptor,
+            script,
+            threshold,
+            keys,
+        })
+    }
+
+    fn script(&self, format: &str) -> Result<ScriptKind, SdkError> {
+        self.script
+            .ok_or_else(|| unsupported(&format!("{} doesn't support taproot wallets", format)))
+    }
+
+    /// Descriptor with both the external and the internal keychain (BIP-389)
+    fn multipath_descriptor(&self) -> String {
+        format!("{:#}", self.descriptor).replace("/0/*", "/<0;1>/*")
+    }
+
+    fn electrum(&self, name: &str) -> Result<String, SdkError> {
+        let script = self.script("Electrum")?;
+
+        let mut wallet = json!({
+            "seed_version": 17,
+            "use_encryption": false,
+        });
+        match self.threshold {
+            None => {
+                wallet["wallet_type"] = json!("standard");
+                wallet["keystore"] = self.keys[0].electrum_keystore(script, name);
+            }
+            Some(threshold) => {
+                wallet["wallet_type"] = json!(format!("{}of{}", threshold, self.keys.len()));
+                for (i, key) in self.keys.iter().enumerate() {
+                    let label = format!("{} #{}", name, i + 1);
+                    wallet[format!("x{}/", i + 1)] = key.electrum_keystore(script, &label);
+                }
+            }
+        }
+
+        Ok(serde_json::to_string_pretty(&wallet).expect("Valid JSON"))
+    }
+
+    fn specter(&self, name: &str) -> String {
+        let devices = self
+            .keys
+            .iter()
+            .map(|key| json!({ "type": "other", "label": format!("{} {}", name, key.fingerprint) }))
+            .collect::<Vec<_>>();
+        let wallet = json!({
+            "label": name,
+            "blockheight": 0,
+            "descriptor": self.descriptor.to_string(),
+            "devices": devices,
+        });
+
+        serde_json::to_string_pretty(&wallet).expect("Valid JSON")
+    }
+
+    fn bluewallet(&self, name: &str) -> Result<String, SdkError> {
+        let script = self.script("BlueWallet")?;
+
+        match self.threshold {
+            None => {
+                let key = &self.keys[0];
+                let wallet = json!({
+                    "ExtPubKey": key.slip132(script),
+                    "MasterFingerprint": key.fingerprint.to_string().to_uppercase(),
+                    "AccountKeyPath": key.path.to_string().trim_start_matches("m/"),
+                });
+                Ok(serde_json::to_string_pretty(&wallet).expect("Valid JSON"))
+            }
+            Some(threshold) => {
+                let format = match script {
+                    ScriptKind::Wsh => "P2WSH",
+                    ScriptKind::ShWsh => "P2SH-P2WSH",
+                    _ => "P2SH",
+                };
+                let mut file = format!(
+                    "# Multisig setup file exported by Portal\nName: {}\nPolicy: {} of {}\nFormat: {}\n",
+                    name,
+                    threshold,
+                    self.keys.len(),
+                    format
+                );
+                for key in &self.keys {
+                    file += &format!(
+                        "\nDerivation: {}\n{}: {}\n",
+                        key.path,
+                        key.fingerprint.to_string().to_uppercase(),
+                        key.slip132(script)
+                    );
+                }
+                Ok(file)
+            }
+        }
+    }
+}
+
+/// Build the file to import the wallet of `descriptor` in another software
+///
+/// `descriptor` is the external descriptor of the wallet and `name` the label given to it.
+pub fn wallet_file(
+    descriptor: &str,
+    format: WalletExportFormat,
+    name: &str,
+) -> Result<String, SdkError> {
+    let info = WalletInfo::parse(descriptor)?;
+    match format {
+        WalletExportFormat::Electrum => info.electrum(name),
+        WalletExportFormat::Sparrow => Ok(info.multipath_descriptor()),
+        WalletExportFormat::Specter => Ok(info.specter(name)),
+        WalletExportFormat::BlueWallet => info.bluewallet(name),
+    }
+}
+
+/// Build the Coldcard-style cosigner file for a key returned by `PortalSdk::get_xpub()`
+///
+/// The file is read by Sparrow, Specter, BlueWallet and Coldcard to add the device as a signer of
+/// a multisig wallet with `script_type`.
+pub fn cosigner_file(xpub: &DeviceXpub, script_type: BsmsScriptType) -> Result<String, SdkError> {
+    let key =
+        DescriptorPublicKey::from_str(&xpub.xpub).map_err(|e| SdkError::InvalidDescriptor {
+            detail: e.to_string(),
+        })?;
+    let key = match key {
+        DescriptorPublicKey::XPub(xkey)
+            if xkey.wildcard == Wildcard::None && xkey.derivation_path.is_master() =>
+        {
+            match xkey.origin {
+                Some((fingerprint, path)) => AccountKey {
+                    fingerprint,
+                    path,
+                    xpub: xkey.xkey,
+                },
+                None => {
+                    return Err(SdkError::InvalidDescriptor {
+                        detail: "The key must have its origin".into(),
+                    })
+                }
+            }
+        }
+        _ => {
+            return Err(SdkError::InvalidDescriptor {
+                detail: "The key must be an xpub with its origin".into(),
+            })
+        }
+    };
+
+    let script = ScriptKind::from(script_type);
+    let name = script.coldcard_name();
+    let mut file = json!({ "xfp": key.fingerprint.to_string().to_uppercase() });
+    file[format!("{}_deriv", name)] = json!(key.path.to_string());
+    file[name] = json!(key.slip132(script));
+
+    Ok(serde_json::to_string_pretty(&file).expect("Valid JSON"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use model::bitcoin::secp256k1::Secp256k1;
+    use model::bitcoin::util::bip32::ExtendedPrivKey;
+
+    fn account_key(seed: u8, path: &str) -> String {
+        let ctx = Secp256k1::new();
+        let path = DerivationPath::from_str(path).unwrap();
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &[seed; 32]).unwrap();
+        let derived = master.derive_priv(&ctx, &path).unwrap();
+        format!(
+            "[{}{}]{}",
+            master.fingerprint(&ctx),
+            path.to_string().trim_start_matches('m'),
+            ExtendedPubKey::from_priv(&ctx, &derived)
+        )
+    }
+
+    fn multisig() -> String {
+        format!(
+            "wsh(sortedmulti(2,{}/0/*,{}/0/*))",
+            account_key(1, "m/48'/1'/0'/2'"),
+            account_key(2, "m/48'/1'/0'/2'")
+        )
+    }
+
+    #[test]
+    fn test_slip132_prefix() {
+        let descriptor = format!("wpkh({}/0/*)", account_key(1, "m/84'/1'/0'"));
+        let info = WalletInfo::parse(&descriptor).unwrap();
+        let key = &info.keys[0];
+
+        let vpub = key.slip132(ScriptKind::Wpkh);
+        assert!(vpub.starts_with("vpub"));
+        let data = base58::from_check(&vpub).unwrap();
+        assert_eq!(&data[4..], &key.xpub.encode()[4..]);
+        assert!(key.slip132(ScriptKind::Pkh).starts_with("tpub"));
+    }
+
+    #[test]
+    fn test_single_sig_files() {
+        let descriptor = format!("wpkh({}/0/*)", account_key(1, "m/84'/1'/0'"));
+
+        let electrum = wallet_file(&descriptor, WalletExportFormat::Electrum, "Portal").unwrap();
+        let electrum: serde_json::Value = serde_json::from_str(&electrum).unwrap();
+        assert_eq!(electrum["wallet_type"], "standard");
+        assert_eq!(electrum["keystore"]["derivation"], "m/84'/1'/0'");
+        assert!(electrum["keystore"]["xpub"]
+            .as_str()
+            .unwrap()
+            .starts_with("vpub"));
+
+        let sparrow = wallet_file(&descriptor, WalletExportFormat::Sparrow, "Portal").unwrap();
+        assert!(sparrow.starts_with("wpkh(["));
+        assert!(sparrow.ends_with("/<0;1>/*)"));
+
+        let bluewallet =
+            wallet_file(&descriptor, WalletExportFormat::BlueWallet, "Portal").unwrap();
+        let bluewallet: serde_json::Value = serde_json::from_str(&bluewallet).unwrap();
+        assert_eq!(bluewallet["AccountKeyPath"], "84'/1'/0'");
+    }
+
+    #[test]
+    fn test_multisig_files() {
+        let descriptor = multisig();
+
+        let electrum = wallet_file(&descriptor, WalletExportFormat::Electrum, "Vault").unwrap();
+        let electrum: serde_json::Value = serde_json::from_str(&electrum).unwrap();
+        assert_eq!(electrum["wallet_type"], "2of2");
+        assert!(electrum["x2/"]["xpub"]
+            .as_str()
+            .unwrap()
+            .starts_with("Vpub"));
+
+        let specter = wallet_file(&descriptor, WalletExportFormat::Specter, "Vault").unwrap();
+        let specter: serde_json::Value = serde_json::from_str(&specter).unwrap();
+        assert_eq!(specter["devices"].as_array().unwrap().len(), 2);
+        assert!(specter["descriptor"].as_str().unwrap().contains('#'));
+
+        let bluewallet = wallet_file(&descriptor, WalletExportFormat::BlueWallet, "Vault").unwrap();
+        assert!(bluewallet.contains("Policy: 2 of 2\n"));
+        assert!(bluewallet.contains("Format: P2WSH\n"));
+        assert_eq!(bluewallet.matches("Derivation: m/48'/1'/0'/2'").count(), 2);
+    }
+
+    #[test]
+    fn test_unsupported_descriptors() {
+        let taproot = format!("tr({}/0/*)", account_key(1, "m/86'/1'/0'"));
+        assert!(wallet_file(&taproot, WalletExportFormat::Specter, "Portal").is_ok());
+        assert!(matches!(
+            wallet_file(&taproot, WalletExportFormat::Electrum, "Portal"),
+            Err(SdkError::UnsupportedDescriptor { .. })
+        ));
+
+        let internal = format!("wpkh({}/1/*)", account_key(1, "m/84'/1'/0'"));
+        assert!(matches!(
+            wallet_file(&internal, WalletExportFormat::Sparrow, "Portal"),
+            Err(SdkError::InvalidDescriptor { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cosigner_file() {
+        let xpub = DeviceXpub {
+            xpub: account_key(1, "m/48'/1'/0'/2'"),
+            bsms: GetXpubBsmsData {
+                version: "1.0".into(),
+                token: "00".into(),
+                key_name: "Portal".into(),
+                signature: String::new(),
+            },
+            key: None,
+        };
+
+        let file = cosigner_file(&xpub, BsmsScriptType::NativeSegwit).unwrap();
+        let file: serde_json::Value = serde_json::from_str(&file).unwrap();
+        assert_eq!(file["p2wsh_deriv"], "m/48'/1'/0'/2'");
+        assert!(file["p2wsh"].as_str().unwrap().starts_with("Vpub"));
+        assert_eq!(file["xfp"].as_str().unwrap().len(), 8);
+    }
+}
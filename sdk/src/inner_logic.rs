@@ -177,7 +177,19 @@ pub(crate) async fn inner_future(
 
         let msg = recv_message(nfc, use_fast_ops).await?;
         let mut decrypt_buf = Vec::new();
-        let reply: Reply = msg.deserialize(&mut decrypt_buf, decrypt)?;
+        let reply: Reply = match msg.deserialize(&mut decrypt_buf, decrypt) {
+            Ok(reply) => reply,
+            Err(model::MessageError::UnsupportedMessage) => {
+                // Sent by a newer firmware: fail the request but keep the session going, the
+                // message was decrypted correctly
+                log::warn!("Unsupported reply from the device");
+
+                replies.send(Err(FutureError::UnsupportedReply)).await?;
+                on_drop.defuse();
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         #[cfg(feature = "debug")]
         debug.send(super::DebugMessage::In(reply.clone())).await?;
@@ -328,6 +340,7 @@ impl<F: FnOnce()> Drop for OnDrop<F> {
 #[derive(Debug, Clone)]
 pub(crate) enum FutureError {
     Message,
+    UnsupportedReply,
     ChannelError,
     Timeout,
     Canceled,
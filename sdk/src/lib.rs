@@ -31,17 +31,34 @@ use inner_logic::FutureError;
 use miniscript::TranslatePk;
 
 use model::bitcoin::util::bip32;
+use model::mnemonic::MnemonicLanguage;
 use model::{
     BsmsRound2, ExtendedKey, InitializationStatus, NumWordsMnemonic, Reply, Request, ScriptType,
-    SetDescriptorVariant,
+    SetDescriptorVariant, TapLeaf, TapLeafScript,
 };
 
+pub mod attestation;
+pub mod bsms;
+pub mod export;
 mod inner_logic;
-mod psbt;
+pub mod psbt;
+mod session;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod wasm;
+
+pub use attestation::DeviceAttestation;
+pub use bsms::{BsmsCoordinator, BsmsScriptType};
+pub use export::WalletExportFormat;
+pub use model::ErrorCode as DeviceErrorCode;
+pub use psbt::{InputSignaturesReport, SignedPsbtReport};
+pub use session::SessionManager;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub use wasm::PortalWeb;
 
 pub const MAX_READ_FRAME: usize = 16;
 
 const MAX_RETRIES: usize = 5;
+const MAX_QUEUED_PROGRESS: usize = 8;
 
 const SRAM1_BASE: u32 = 0x2000_0000;
 const SRAM1_SIZE: u32 = 96 * 1024;
@@ -52,9 +69,12 @@ const SRAM2_SIZE: u32 = 32 * 1024;
 const SRAM2_END: u32 = SRAM2_BASE + SRAM2_SIZE;
 
 const FLASH_BASE: u32 = 0x0800_0000;
-const FLASH_SIZE: u32 = 510 * 2048;
+const FLASH_SIZE: u32 = model::flash::MAX_FIRMWARE_SIZE as u32;
 const FLASH_END: u32 = FLASH_BASE + FLASH_SIZE;
 
+/// Size of the version and variant appended at the end of the firmware image
+const FW_TAIL_SIZE: usize = 5;
+
 #[cfg(feature = "bindings")]
 pub use model::bitcoin::{
     util::bip32::{DerivationPath, Fingerprint},
@@ -107,15 +127,21 @@ macro_rules! send_with_retry {
                     // TODO: count attempts for timeout
                     send_ping = true;
                 },
+                Ok(Reply::Progress { current, total }) => {
+                    log::trace!("Got progress {}/{}, sending ping", current, total);
+
+                    $channels.report_progress(OperationProgress { current, total });
+                    send_ping = true;
+                },
                 Ok(Reply::Busy) => {
                     async_std::task::sleep(Duration::from_millis(50)).await;
                     continue;
                 },
-                Ok(Reply::Error(cause)) => {
-                    break Err(SdkError::DeviceError { cause })
+                Ok(Reply::Error { detail, code }) => {
+                    break Err(SdkError::DeviceError { code, detail })
                 }
                 Ok(Reply::Unverified) => {
-                    break Err(SdkError::DeviceError { cause: "Unverified mnemonic".into() })
+                    break Err(SdkError::DeviceError { code: None, detail: "Unverified mnemonic".into() })
                 }
                 Ok(Reply::Locked) => {
                     break Err(SdkError::Locked)
@@ -123,6 +149,9 @@ macro_rules! send_with_retry {
                 Ok(Reply::UnexpectedMessage) => {
                     break Err(SdkError::UnexpectedMessage)
                 }
+                Err(FutureError::UnsupportedReply) => {
+                    break Err(SdkError::UnexpectedMessage)
+                }
                 _ => {
                     i += 1; // Only increment when there's some kind of failure
                 },
@@ -200,14 +229,20 @@ impl PortalSdk {
                 network,
                 unlocked,
                 fingerprint,
-                ..
+                descriptor,
             } => Ok(CardStatus {
                 initialized: true,
                 unverified: None,
                 unlocked,
                 network: Some(network),
                 version: device_info.firmware_version,
+                protocol_version: device_info.protocol_version,
                 fingerprint: fingerprint.map(|bytes| bip32::Fingerprint::from(bytes.as_slice())),
+                descriptor: descriptor.map(Into::into),
+                firmware_hash: device_info.firmware_hash,
+                hardware_revision: device_info.hardware_revision,
+                serial: device_info.serial,
+                capabilities: device_info.capabilities,
             }),
             InitializationStatus::Uninitialized => Ok(CardStatus {
                 initialized: false,
@@ -215,7 +250,13 @@ impl PortalSdk {
                 unlocked: true,
                 network: None,
                 version: device_info.firmware_version,
+                protocol_version: device_info.protocol_version,
                 fingerprint: None,
+                descriptor: None,
+                firmware_hash: device_info.firmware_hash,
+                hardware_revision: device_info.hardware_revision,
+                serial: device_info.serial,
+                capabilities: device_info.capabilities,
             }),
             InitializationStatus::Unverified { with_code, network } => Ok(CardStatus {
                 initialized: false,
@@ -223,36 +264,220 @@ impl PortalSdk {
                 unlocked: true,
                 network: Some(network),
                 version: device_info.firmware_version,
+                protocol_version: device_info.protocol_version,
                 fingerprint: None,
+                descriptor: None,
+                firmware_hash: device_info.firmware_hash,
+                hardware_revision: device_info.hardware_revision,
+                serial: device_info.serial,
+                capabilities: device_info.capabilities,
             }),
         }
     }
 
+    /// Fail if the firmware doesn't know about the seed export policy
+    ///
+    /// Older firmwares silently ignore `disable_seed_export` during the initialization.
+    async fn check_seed_export_policy_support(&self) -> Result<(), SdkError> {
+        let status = self.get_status().await?;
+        if status.protocol_version.unwrap_or(0) < 2 {
+            return Err(SdkError::DeviceError {
+                code: Some(DeviceErrorCode::UnsupportedRequest),
+                detail: "The firmware can't disable the seed export".into(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Generate a new seed
+    ///
+    /// With `disable_seed_export` the seed is never exported, not even in encrypted backups,
+    /// until it's allowed again with `set_seed_export`. The words are shown with the wordlist of
+    /// `language`, English if `None`.
     pub async fn generate_mnemonic(
         &self,
         num_words: GenerateMnemonicWords,
         network: model::bitcoin::Network,
         password: Option<String>,
+        disable_seed_export: bool,
+        language: Option<GenerateMnemonicLanguage>,
     ) -> Result<(), SdkError> {
         let num_words = match num_words {
             GenerateMnemonicWords::Words12 => NumWordsMnemonic::Words12,
             GenerateMnemonicWords::Words24 => NumWordsMnemonic::Words24,
         };
+        if disable_seed_export {
+            self.check_seed_export_policy_support().await?;
+        }
+        let language = language.map(MnemonicLanguage::from);
+        if language.unwrap_or_default() != MnemonicLanguage::English {
+            let status = self.get_status().await?;
+            if status.protocol_version.unwrap_or(0) < 8 {
+                return Err(SdkError::DeviceError {
+                    code: Some(DeviceErrorCode::UnsupportedRequest),
+                    detail: "The firmware only supports English mnemonics".into(),
+                });
+            }
+        }
 
-        send_with_retry!(self.requests, Request::GenerateMnemonic { num_words, network, password: password.clone() }, Ok(Reply::Ok) => break Ok(()))?;
+        send_with_retry!(self.requests, Request::GenerateMnemonic { num_words, network, password: password.clone(), disable_seed_export: Some(disable_seed_export), language }, Ok(Reply::Ok) => break Ok(()))?;
         Ok(())
     }
 
+    /// Import an existing seed, see `generate_mnemonic` for `disable_seed_export`
+    ///
+    /// Mnemonics of 12, 15, 18, 21 or 24 words are accepted, the device refuses the ones with an
+    /// invalid checksum. The wordlist is detected by the device, see `GenerateMnemonicLanguage`
+    /// for the supported ones.
     pub async fn restore_mnemonic(
         &self,
         mnemonic: String,
         network: model::bitcoin::Network,
         password: Option<String>,
+        disable_seed_export: bool,
     ) -> Result<(), SdkError> {
-        send_with_retry!(self.requests, Request::SetMnemonic { mnemonic: mnemonic.clone(), network, password: password.clone() }, Ok(Reply::Ok) => break Ok(()))?;
+        if disable_seed_export {
+            self.check_seed_export_policy_support().await?;
+        }
+
+        send_with_retry!(self.requests, Request::SetMnemonic { mnemonic: mnemonic.clone(), network, password: password.clone(), disable_seed_export: Some(disable_seed_export) }, Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
+    /// Allow or forbid exporting the seed, which must be confirmed on the device
+    pub async fn set_seed_export(&self, allowed: bool) -> Result<(), SdkError> {
+        self.check_seed_export_policy_support().await?;
+
+        send_with_retry!(self.requests, Request::SetSeedExport { allowed }, Ok(Reply::Ok) => break Ok(()))?;
         Ok(())
     }
 
+    /// Choose which requests the device answers without a confirmation, which must be confirmed
+    /// on the device
+    ///
+    /// Only devices with a pair code can skip confirmations, the others reply with
+    /// `DeviceErrorCode::PairCodeRequired`. Confirmations are only skipped after the host has
+    /// identified itself with `identify_host` in the current session. Signing always requires a
+    /// confirmation.
+    pub async fn set_confirmation_policy(
+        &self,
+        silent_get_xpub: bool,
+        silent_public_descriptor: bool,
+    ) -> Result<(), SdkError> {
+        let policy = model::ConfirmationPolicy {
+            silent_get_xpub,
+            silent_public_descriptor,
+        };
+        send_with_retry!(self.requests, Request::SetConfirmationPolicy(policy), Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
+    /// Add `address` to the address book of the device, which must be confirmed on the device
+    ///
+    /// Outputs paying to the address book are then reviewed together on a single page with their
+    /// total value. Addresses of another network are refused with
+    /// `DeviceErrorCode::InvalidAddress`, and once the book is full with
+    /// `DeviceErrorCode::TooManyAddresses`.
+    pub async fn add_trusted_address(&self, address: String) -> Result<(), SdkError> {
+        let status = self.get_status().await?;
+        if status.protocol_version.unwrap_or(0) < 20 {
+            return Err(SdkError::DeviceError {
+                code: Some(DeviceErrorCode::UnsupportedRequest),
+                detail: "The firmware doesn't have an address book".into(),
+            });
+        }
+
+        send_with_retry!(self.requests, Request::AddTrustedAddress { address: address.clone() }, Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
+    /// Identify the host to the device with its static secret key (32 bytes)
+    ///
+    /// The device sends a challenge that is signed with `host_key`. The first time a key is used
+    /// its fingerprint (see `host_fingerprint`) is shown on the device and the pairing must be
+    /// confirmed, afterwards the host is recognized silently. The identification lasts until the
+    /// end of the NFC session.
+    pub async fn identify_host(&self, host_key: Vec<u8>) -> Result<(), SdkError> {
+        let secp = model::bitcoin::secp256k1::Secp256k1::signing_only();
+        let host_key = parse_host_key(&host_key)?;
+
+        let challenge = send_with_retry!(self.requests, Request::GetHostChallenge, Ok(Reply::HostChallenge(challenge)) => break Ok(challenge))?;
+        let signature = secp
+            .sign_ecdsa(&model::host::challenge_message(&challenge), &host_key)
+            .serialize_compact();
+        let request = Request::IdentifyHost {
+            pubkey: Box::new(host_key.public_key(&secp).serialize().into()),
+            signature: Box::new(signature.into()),
+        };
+        send_with_retry!(self.requests, request.clone(), Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
+    /// Fingerprint of a host key, as shown on the device while pairing it
+    pub fn host_fingerprint(&self, host_key: Vec<u8>) -> Result<String, SdkError> {
+        let secp = model::bitcoin::secp256k1::Secp256k1::signing_only();
+        let host_key = parse_host_key(&host_key)?;
+
+        Ok(model::host::fingerprint(
+            &host_key.public_key(&secp).serialize(),
+        ))
+    }
+
+    /// Export the last log lines kept by the device, which must be confirmed on the device
+    ///
+    /// The logs are only kept while they are enabled in the settings menu of the device, otherwise
+    /// it replies with `DeviceErrorCode::LogsDisabled`. Long strings like keys and addresses are
+    /// redacted by the device.
+    pub async fn get_logs(&self) -> Result<String, SdkError> {
+        let logs = send_with_retry!(self.requests, Request::GetLogs, Ok(Reply::Logs(logs)) => break Ok(logs))?;
+        Ok(logs)
+    }
+
+    /// Start wiping the device, which shows a random code on the screen
+    ///
+    /// The user has to read the code and pass it to `confirm_wipe`, so the device can't be wiped
+    /// by the host alone.
+    pub async fn begin_wipe(&self) -> Result<(), SdkError> {
+        send_with_retry!(self.requests, Request::BeginWipe, Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
+    /// Send the code shown after `begin_wipe`, the wipe must then be confirmed on the device
+    ///
+    /// A wrong code cancels the wipe. Returns the pages erased and whether they all read back
+    /// blank, or `None` for firmwares older than protocol version 9 which don't report it.
+    pub async fn confirm_wipe(&self, code: String) -> Result<Option<WipeReport>, SdkError> {
+        let report = send_with_retry!(self.requests, Request::ConfirmWipe { code: code.clone() }, Ok(Reply::Wiped(report)) => break Ok(Some(report)), Ok(Reply::Ok) => break Ok(None))?;
+        Ok(report.map(Into::into))
+    }
+
+    /// Cheaply check that the device is still there
+    ///
+    /// The device replies right away without drawing anything, and the request doesn't count as
+    /// activity for the auto-lock. Firmwares older than protocol version 4 reply with
+    /// `DeviceErrorCode::UnsupportedRequest`.
+    pub async fn heartbeat(&self) -> Result<(), SdkError> {
+        // The device replies with `Pong`, which `send_with_retry` takes as a delayed reply, and
+        // a failure is reported right away instead of retrying
+        loop {
+            self.requests.o.send(Request::Heartbeat).await?;
+
+            match self.requests.i.recv().await? {
+                Ok(Reply::Pong) => break Ok(()),
+                Ok(Reply::Busy) => {
+                    async_std::task::sleep(Duration::from_millis(50)).await;
+                }
+                Ok(Reply::Error { detail, code }) => {
+                    break Err(SdkError::DeviceError { code, detail })
+                }
+                Ok(Reply::Locked) => break Err(SdkError::Locked),
+                Ok(_) => break Err(SdkError::UnexpectedMessage),
+                Err(e) => break Err(e.into()),
+            }
+        }
+    }
+
     pub async fn unlock(&self, password: String) -> Result<(), SdkError> {
         send_with_retry!(self.requests, Request::Unlock { password: password.clone()  }, Ok(Reply::Ok) => break Ok(()))?;
         Ok(())
@@ -264,46 +489,410 @@ impl PortalSdk {
     }
 
     pub async fn display_address(&self, index: u32) -> Result<model::bitcoin::Address, SdkError> {
-        let address = send_with_retry!(self.requests, Request::DisplayAddress(index), Ok(Reply::Address(s)) => break Ok(s))?;
-        let address = address
+        Ok(self.display_address_with_info(index).await?.address)
+    }
+
+    /// Same as `display_address`, also returning how the address was derived
+    ///
+    /// The info can be used to derive the address again from the descriptor registered on the
+    /// device. It's `None` with firmwares that don't send it.
+    pub async fn display_address_with_info(&self, index: u32) -> Result<DeviceAddress, SdkError> {
+        let (address, info) = send_with_retry!(self.requests, Request::DisplayAddress(index), Ok(Reply::Address(s, info)) => break Ok((s, info)))?;
+        let address: model::bitcoin::Address = address
             .parse()
             .map_err(|_| SdkError::DeserializationError)?;
-        Ok(address)
+
+        if let Some(info) = &info {
+            if info.script_pubkey.as_slice() != address.script_pubkey().as_bytes() {
+                log::warn!("The address doesn't match the script sent with it");
+                return Err(SdkError::DeserializationError);
+            }
+        }
+
+        Ok(DeviceAddress {
+            address,
+            info: info.map(Into::into),
+        })
+    }
+
+    /// Sign a message with the key of an external address (BIP-322)
+    ///
+    /// Only single-sig native segwit and taproot wallets can sign messages. The signature is in
+    /// the "simple" format and is checked against the address before being returned.
+    pub async fn sign_message(
+        &self,
+        index: u32,
+        message: String,
+    ) -> Result<MessageSignature, SdkError> {
+        let (address, signature) = send_with_retry!(self.requests, Request::SignMessage { index, message: message.clone() }, Ok(Reply::MessageSignature { address, signature }) => break Ok((address, signature)))?;
+        message_signature(address, &signature, |script_pubkey, signature| {
+            model::bip322::decode_simple(signature)
+                .map(|witness| {
+                    model::bip322::verify(
+                        &model::bitcoin::secp256k1::Secp256k1::verification_only(),
+                        script_pubkey,
+                        message.as_bytes(),
+                        &witness,
+                    )
+                })
+                .unwrap_or(false)
+        })
     }
 
+    /// Sign a message with the key of an external address, in the format of Bitcoin Core's
+    /// `signmessage`
+    ///
+    /// Only single-sig legacy and native segwit wallets can sign these messages. P2WPKH addresses
+    /// use the header of BIP-137, as Electrum does. The signature is checked against the address
+    /// before being returned.
+    pub async fn sign_message_legacy(
+        &self,
+        index: u32,
+        message: String,
+    ) -> Result<MessageSignature, SdkError> {
+        let (address, signature) = send_with_retry!(self.requests, Request::SignMessageLegacy { index, message: message.clone() }, Ok(Reply::MessageSignature { address, signature }) => break Ok((address, signature)))?;
+        message_signature(address, &signature, |script_pubkey, signature| {
+            model::signmessage::verify(
+                &model::bitcoin::secp256k1::Secp256k1::verification_only(),
+                script_pubkey,
+                &message,
+                signature,
+            )
+        })
+    }
+
+    /// Sign a base64-encoded PSBT, returning it with the signatures of the device added
+    ///
+    /// Only the new signatures are sent back by the device, so every other field of `psbt`,
+    /// including the proprietary and unknown ones, is returned unchanged. A label can be shown
+    /// with an output by setting the proprietary field described in `model::psbt::output_label`.
+    /// Version 2 PSBTs (BIP-370) are returned as version 2.
     pub async fn sign_psbt(&self, psbt: String) -> Result<String, SdkError> {
-        use model::bitcoin::consensus::{deserialize, serialize};
+        self.sign_psbt_with_context(psbt, None, None).await
+    }
+
+    /// Like `sign_psbt()`, showing the approximate value of every output in `currency` too
+    ///
+    /// `rate` is the value of one bitcoin in `currency`, an ISO 4217 code like "USD". The device
+    /// can't check it, so the user is told that it comes from the host. Invalid rates and
+    /// firmwares without `model::capabilities::FIAT_RATE` only show the amounts in bitcoin.
+    pub async fn sign_psbt_with_fiat_rate(
+        &self,
+        psbt: String,
+        rate: f64,
+        currency: String,
+    ) -> Result<String, SdkError> {
+        self.sign_psbt_with_context(psbt, Some(model::FiatRate { rate, currency }), None)
+            .await
+    }
+
+    /// Like `sign_psbt()`, for a child that pays for `parent_txid` (CPFP)
+    ///
+    /// `fee_rate` is the fee rate of the parent and the child together in sat/vB. The device
+    /// checks that one of the inputs spends the parent but can't check the fee rate, which it
+    /// shows before the outputs. Firmwares without `model::capabilities::CPFP_INFO` sign the
+    /// PSBT like `sign_psbt()`.
+    pub async fn sign_psbt_with_cpfp(
+        &self,
+        psbt: String,
+        parent_txid: String,
+        fee_rate: f32,
+    ) -> Result<String, SdkError> {
+        let parent_txid: model::bitcoin::Txid = parent_txid
+            .parse()
+            .map_err(|_| SdkError::DeserializationError)?;
+        self.sign_psbt_with_context(
+            psbt,
+            None,
+            Some(model::CpfpInfo::new(&parent_txid, fee_rate)),
+        )
+        .await
+    }
 
-        let psbt = base64::decode(&psbt)?;
-        let mut original_psbt: model::bitcoin::util::psbt::Psbt =
-            deserialize(&psbt).map_err(|_| SdkError::DeserializationError)?;
+    /// Sign a base64-encoded PSBT and finalize it on the device, for hosts that can't finalize
+    /// it themselves
+    ///
+    /// If the device adds the last signatures needed, the hex-encoded transaction ready to be
+    /// broadcast is returned in `transaction`. Otherwise, like with a multisig that other
+    /// cosigners still have to sign, the PSBT is returned in `psbt` as with `sign_psbt()`.
+    pub async fn sign_and_finalize_psbt(&self, psbt: String) -> Result<FinalizedPsbt, SdkError> {
+        use model::bitcoin::hashes::hex::ToHex;
+
+        let (raw_psbt, _) = psbt::decode(&psbt)?;
+
+        let status = self.get_status().await?;
+        if !status
+            .capabilities
+            .is_some_and(|c| c & model::capabilities::FINALIZE_PSBT != 0)
+        {
+            return Err(SdkError::DeviceError {
+                code: Some(DeviceErrorCode::UnsupportedRequest),
+                detail: "The firmware can't finalize PSBTs".into(),
+            });
+        }
+        let (parts, last) = self.split_psbt(raw_psbt).await?;
 
-        send_with_retry!(self.requests, Request::BeginSignPsbt, Ok(Reply::Ok) => break Ok(()))?;
+        send_with_retry!(self.requests, Request::BeginSignPsbt { fiat_rate: None, cpfp: None }, Ok(Reply::Ok) => break Ok(()))?;
+
+        self.send_psbt_parts(parts).await?;
+        let reply = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, Some(true)), Ok(Reply::SignedTransaction(tx)) => break Ok(Ok(tx)), Ok(Reply::SignedPsbt(s)) => break Ok(Err(s)))?;
+
+        match reply {
+            Ok(tx) => Ok(FinalizedPsbt {
+                transaction: Some(tx.to_hex()),
+                psbt: None,
+            }),
+            Err(sig_diff) => Ok(FinalizedPsbt {
+                transaction: None,
+                psbt: Some(psbt::merge_signatures(&psbt, &sig_diff)?),
+            }),
+        }
+    }
 
-        let psbt = send_with_retry!(self.requests, Request::SignPsbt(psbt.clone().into()), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+    /// Sign several base64-encoded PSBTs in a row, returning them in the same order with the
+    /// signatures of the device added
+    ///
+    /// The user reviews the transactions one after the other without going back to the idle
+    /// screen, and sees the total fees once all of them are signed. The batch stops at the first
+    /// transaction that isn't signed, whose error is returned.
+    pub async fn sign_psbt_batch(&self, psbts: Vec<String>) -> Result<Vec<String>, SdkError> {
+        let raw_psbts = psbts
+            .iter()
+            .map(|psbt| psbt::decode(psbt).map(|(raw_psbt, _)| raw_psbt))
+            .collect::<Result<Vec<_>, SdkError>>()?;
+        let mut split = Vec::with_capacity(raw_psbts.len());
+        for raw_psbt in raw_psbts {
+            split.push(self.split_psbt(raw_psbt).await?);
+        }
 
-        // We encode the signatures in a format that's almost psbt but incompatible in some cases,
-        // so we parse it manually here
-        let inputs =
-            psbt::PortalPsbt::parse(psbt.deref()).map_err(|_| SdkError::DeserializationError)?;
-        let mut psbt =
-            model::bitcoin::util::psbt::Psbt::from_unsigned_tx(original_psbt.unsigned_tx.clone())
-                .expect("Valid unsigned tx");
-        psbt.inputs = inputs.inputs;
+        let count = split.len() as u32;
+        send_with_retry!(self.requests, Request::BeginSignPsbtBatch { count }, Ok(Reply::Ok) => break Ok(()))?;
 
-        original_psbt
-            .combine(psbt)
-            .map_err(|_| SdkError::DeserializationError)?;
-        let original_psbt = serialize(&original_psbt);
+        let mut signed = Vec::with_capacity(psbts.len());
+        for (psbt, (parts, last)) in psbts.iter().zip(split) {
+            self.send_psbt_parts(parts).await?;
+            let sig_diff = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, None), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+            signed.push(psbt::merge_signatures(psbt, &sig_diff)?);
+        }
 
-        Ok(base64::encode(&original_psbt))
+        Ok(signed)
+    }
+
+    /// Like `sign_psbt()`, checking that the device didn't pick the nonces of its signatures
+    ///
+    /// The nonces are tweaked with random data chosen here, after the device commits to them, so
+    /// that a malicious firmware can't leak its keys through the signatures (see
+    /// `model::anti_exfil`). Signatures that don't use the committed nonces are reported as
+    /// `InvalidSignatures`. Taproot wallets aren't supported.
+    pub async fn sign_psbt_anti_exfil(&self, psbt: String) -> Result<String, SdkError> {
+        let (raw_psbt, original) = psbt::decode(&psbt)?;
+        let (parts, last) = self.split_psbt(raw_psbt).await?;
+
+        let host_data: [u8; 32] = rand::random();
+        let host_commitment = model::anti_exfil::host_commitment(&host_data);
+        send_with_retry!(self.requests, Request::BeginSignPsbtAntiExfil { host_commitment: Box::new(host_commitment.into()) }, Ok(Reply::Ok) => break Ok(()))?;
+
+        self.send_psbt_parts(parts).await?;
+        let commitments = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, None), Ok(Reply::SignerCommitments(c)) => break Ok(c))?;
+        let sig_diff = send_with_retry!(self.requests, Request::AntiExfilHostData(Box::new(host_data.into())), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+
+        let signed = psbt::merge_signatures(&psbt, &sig_diff)?;
+        let signed_psbt = psbt::parse(&base64::decode(&signed)?)?;
+        psbt::verify_anti_exfil(&original, &signed_psbt, &commitments, &host_data)?;
+
+        Ok(signed)
+    }
+
+    /// Sign a payjoin proposal (BIP-78) built on the last payment signed with `sign_psbt()`
+    ///
+    /// The device only shows what the receiver changed in the original payment. It replies with
+    /// `DeviceErrorCode::NoPaymentToPayjoin` if no payment was signed since it was unlocked, and
+    /// with `DeviceErrorCode::InvalidPsbt` if the proposal doesn't match the payment.
+    pub async fn sign_payjoin_psbt(&self, psbt: String) -> Result<String, SdkError> {
+        let (raw_psbt, _) = psbt::decode(&psbt)?;
+        let (parts, last) = self.split_psbt(raw_psbt).await?;
+
+        send_with_retry!(self.requests, Request::BeginSignPayjoin, Ok(Reply::Ok) => break Ok(()))?;
+
+        self.send_psbt_parts(parts).await?;
+        let sig_diff = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, None), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+
+        psbt::merge_signatures(&psbt, &sig_diff)
+    }
+
+    /// Sign a payjoin proposal (BIP-78) built by the wallet as the receiver of `original`, the
+    /// base64-encoded PSBT sent by the payer
+    ///
+    /// The device checks that the proposal still makes the original payments and shows what the
+    /// wallet receives and contributes instead of the outputs. It replies with
+    /// `DeviceErrorCode::InvalidPsbt` if the proposal doesn't match the original transaction or
+    /// if its outputs aren't described with their key origins.
+    pub async fn sign_payjoin_receiver_psbt(
+        &self,
+        original: String,
+        proposal: String,
+    ) -> Result<String, SdkError> {
+        use model::bitcoin::consensus::serialize;
+
+        let original = psbt::parse(&base64::decode(&original)?)?;
+        let (raw_psbt, _) = psbt::decode(&proposal)?;
+
+        let status = self.get_status().await?;
+        if status.protocol_version.unwrap_or(0) < 17 {
+            return Err(SdkError::DeviceError {
+                code: Some(DeviceErrorCode::UnsupportedRequest),
+                detail: "The firmware can't sign payjoins as the receiver".into(),
+            });
+        }
+        let (parts, last) = self.split_psbt(raw_psbt).await?;
+
+        let original = serialize(&original.unsigned_tx);
+        send_with_retry!(self.requests, Request::BeginSignPayjoinReceiver { original: original.clone().into() }, Ok(Reply::Ok) => break Ok(()))?;
+
+        self.send_psbt_parts(parts).await?;
+        let sig_diff = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, None), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+
+        psbt::merge_signatures(&proposal, &sig_diff)
+    }
+
+    /// Sign a base64-encoded proof of reserves (BIP-127) committing to `message`
+    ///
+    /// The first input of the PSBT must spend `model::psbt::reserves_commitment(message)`, which
+    /// doesn't exist, so the signed transaction can't move any funds. The device shows the message
+    /// and the total of the inputs of the wallet instead of the outputs. It replies with
+    /// `DeviceErrorCode::InvalidPsbt` if the PSBT doesn't commit to `message`.
+    pub async fn sign_proof_of_reserves(
+        &self,
+        psbt: String,
+        message: String,
+    ) -> Result<String, SdkError> {
+        let (raw_psbt, _) = psbt::decode(&psbt)?;
+
+        let status = self.get_status().await?;
+        if status.protocol_version.unwrap_or(0) < 19 {
+            return Err(SdkError::DeviceError {
+                code: Some(DeviceErrorCode::UnsupportedRequest),
+                detail: "The firmware can't sign proofs of reserves".into(),
+            });
+        }
+        let (parts, last) = self.split_psbt(raw_psbt).await?;
+
+        send_with_retry!(self.requests, Request::BeginSignProofOfReserves { message: message.clone() }, Ok(Reply::Ok) => break Ok(()))?;
+
+        self.send_psbt_parts(parts).await?;
+        let sig_diff = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, None), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+
+        psbt::merge_signatures(&psbt, &sig_diff)
+    }
+
+    /// Build the file to import the wallet in Electrum, Sparrow, Specter or BlueWallet, see
+    /// `export::wallet_file()`
+    ///
+    /// `descriptor` is the external descriptor returned by `public_descriptors()`. This doesn't
+    /// talk to the device.
+    pub fn export_wallet(
+        &self,
+        descriptor: String,
+        format: WalletExportFormat,
+        name: String,
+    ) -> Result<String, SdkError> {
+        export::wallet_file(&descriptor, format, &name)
+    }
+
+    /// Build the file to add the device as a cosigner of a multisig wallet, from a key returned by
+    /// `get_xpub()`
+    pub fn export_cosigner(
+        &self,
+        xpub: DeviceXpub,
+        script_type: BsmsScriptType,
+    ) -> Result<String, SdkError> {
+        export::cosigner_file(&xpub, script_type)
+    }
+
+    /// Check the signatures added by the device, see `psbt::verify_signed_psbt()`
+    ///
+    /// `original` is the PSBT passed to `sign_psbt()` and `signed` the one it returned. This
+    /// doesn't talk to the device, it's a second check on the host that the signatures commit to
+    /// the transaction shown to the user.
+    pub fn verify_signed_psbt(
+        &self,
+        original: String,
+        signed: String,
+    ) -> Result<SignedPsbtReport, SdkError> {
+        psbt::verify_signed_psbt(&original, &signed)
+    }
+
+    /// Check that the device is genuine
+    ///
+    /// The device signs a random challenge with its attestation key, which must be certified by
+    /// `root_key`, the hex-encoded x-only public key of the manufacturer.
+    pub async fn attest(&self, root_key: String) -> Result<DeviceAttestation, SdkError> {
+        let challenge: [u8; 32] = rand::random();
+        let attestation = send_with_retry!(self.requests, Request::Attest(Box::new(challenge.into())), Ok(Reply::Attestation(attestation)) => break Ok(attestation))?;
+
+        attestation::verify_attestation(&root_key, &challenge, &attestation)
+    }
+
+    /// Export an encrypted backup of the device config
+    ///
+    /// The backup is encrypted with `password` and can be restored with `restore_backup()`,
+    /// even on a different device if `include_seed` is set.
+    pub async fn export_backup(
+        &self,
+        password: String,
+        include_seed: bool,
+    ) -> Result<Vec<u8>, SdkError> {
+        let backup = send_with_retry!(self.requests, Request::ExportBackup { password: password.clone(), include_seed }, Ok(Reply::Backup(backup)) => break Ok(backup))?;
+        Ok(backup.to_vec())
+    }
+
+    /// Restore a backup made with `export_backup()`
+    ///
+    /// On a new device the backup must include the seed and `pair_code` becomes the new pair
+    /// code. On an unlocked device only the settings are restored.
+    pub async fn restore_backup(
+        &self,
+        backup: Vec<u8>,
+        password: String,
+        pair_code: Option<String>,
+    ) -> Result<(), SdkError> {
+        // Make sure the backup is valid before sending it to the device
+        model::backup::Backup::deserialize(&backup).map_err(|_| SdkError::DeserializationError)?;
+
+        send_with_retry!(self.requests, Request::RestoreBackup { backup: backup.clone().into(), password: password.clone(), pair_code: pair_code.clone() }, Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
+    /// Run the hardware self-test
+    ///
+    /// The device shows some test patterns and then asks the user to touch the button, so this
+    /// takes a few seconds.
+    pub async fn self_test(&self) -> Result<SelfTestReport, SdkError> {
+        let report = send_with_retry!(self.requests, Request::SelfTest, Ok(Reply::SelfTest(report)) => break Ok(report))?;
+
+        Ok(SelfTestReport {
+            flash: report.flash.into(),
+            rng: report.rng.into(),
+            display: report.display.into(),
+            touch: report.touch.into(),
+        })
+    }
+
+    /// Derive the seed of the Lightning node number `index`, after a confirmation on the device
+    ///
+    /// The seed is derived with BIP-85 and can be used with LDK's `KeysManager`, so the node can
+    /// be restored from the device at any time.
+    pub async fn derive_node_seed(&self, index: u32) -> Result<Vec<u8>, SdkError> {
+        let seed = send_with_retry!(self.requests, Request::DeriveNodeSeed { index }, Ok(Reply::NodeSeed(seed)) => break Ok(seed))?;
+        Ok(seed.to_vec())
     }
 
     pub async fn get_xpub(&self, path: bip32::DerivationPath) -> Result<DeviceXpub, SdkError> {
-        let (xpub, bsms) = send_with_retry!(self.requests, Request::GetXpub(path.clone().into()), Ok(Reply::Xpub { xpub, bsms }) => break Ok((xpub, bsms)))?;
+        let (xpub, bsms, key) = send_with_retry!(self.requests, Request::GetXpub(path.clone().into()), Ok(Reply::Xpub { xpub, bsms, key }) => break Ok((xpub, bsms, key)))?;
 
         Ok(DeviceXpub {
             xpub,
+            key: key.map(Into::into),
             bsms: GetXpubBsmsData {
                 version: bsms.version,
                 token: bsms.token,
@@ -318,90 +907,33 @@ impl PortalSdk {
         descriptor: String,
         bsms: Option<SetDescriptorBsmsData>,
     ) -> Result<(), SdkError> {
-        use miniscript::{descriptor::*, Miniscript};
+        use miniscript::descriptor::*;
         use std::str::FromStr;
 
-        fn map_key(pk: &DescriptorPublicKey) -> Result<ExtendedKey, SdkError> {
-            let pk = match pk {
-                DescriptorPublicKey::Single(_) => {
-                    return Err(SdkError::UnsupportedDescriptor {
-                        cause: "Single public keys are not supported".to_string(),
-                    })
-                }
-                DescriptorPublicKey::XPub(xpub) => xpub,
-            };
-
-            if pk.wildcard != Wildcard::Unhardened {
-                return Err(SdkError::UnsupportedDescriptor {
-                    cause: "Invalid wildcard".to_string(),
-                });
-            }
-
-            Ok(ExtendedKey {
-                key: pk.xkey.into(),
-                origin: pk
-                    .origin
-                    .as_ref()
-                    .map(|(f, d)| ((*f).into(), d.clone().into())),
-                path: pk.derivation_path.clone().into(),
-            })
-        }
-        fn make_multisig(
-            k: usize,
-            pks: &[DescriptorPublicKey],
-            is_sorted: bool,
-        ) -> Result<SetDescriptorVariant, SdkError> {
-            if !is_sorted {
-                return Err(SdkError::UnsupportedDescriptor {
-                    cause: "Only `sortedmulti` descriptors are supported".into(),
-                });
-            }
-
-            let keys = pks
-                .into_iter()
-                .map(|pk| map_key(pk))
-                .collect::<Result<Vec<_>, _>>()?;
-            Ok(SetDescriptorVariant::MultiSig {
-                threshold: k,
-                keys,
-                is_sorted,
-            })
-        }
-        fn process_wsh(wsh: &Wsh<DescriptorPublicKey>) -> Result<SetDescriptorVariant, SdkError> {
-            match wsh.as_inner() {
-                WshInner::Ms(Miniscript {
-                    node: miniscript::Terminal::Multi(k, pks),
-                    ..
-                }) => make_multisig(*k, pks, false),
-                WshInner::SortedMulti(SortedMultiVec { k, pks, .. }) => {
-                    make_multisig(*k, pks, true)
-                }
-                _ => {
-                    return Err(SdkError::UnsupportedDescriptor {
-                        cause: "Arbitrary descriptors are not supported".to_string(),
-                    })
-                }
-            }
+        if descriptor.contains("sortedmulti_a(") && bsms.is_some() {
+            return Err(SdkError::UnsupportedDescriptor {
+                detail: "BSMS is not supported with `sortedmulti_a`".into(),
+            });
         }
 
         let (descriptor, bsms) = if let Some(bsms) = bsms {
             if bsms.version != "1.0" {
                 return Err(SdkError::UnsupportedDescriptor {
-                    cause: "Unsupported BSMS version".to_string(),
+                    detail: "Unsupported BSMS version".to_string(),
                 });
             }
 
             // We only support one specific path-restriction, which is `/0/*` for external and `/1/*` for internal
             if bsms.path_restrictions != "/0/*,/1/*" {
                 return Err(SdkError::UnsupportedDescriptor {
-                    cause: "Only `/0/*,/1/*` is supported as path restriction".to_string(),
+                    detail: "Only `/0/*,/1/*` is supported as path restriction".to_string(),
                 });
             }
 
             // If we have BSMS data we expect path restrictions in the descriptor, so we remove them here first
             let parsed = Descriptor::<String>::from_str(&descriptor).map_err(|e| {
                 SdkError::InvalidDescriptor {
-                    cause: e.to_string(),
+                    detail: e.to_string(),
                 }
             })?;
             let parsed = parsed.translate_pk(&mut BsmsTranslator)?;
@@ -417,46 +949,17 @@ impl PortalSdk {
             (descriptor, None)
         };
 
-        let parsed = Descriptor::<DescriptorPublicKey>::from_str(&descriptor).map_err(|e| {
-            SdkError::InvalidDescriptor {
-                cause: e.to_string(),
-            }
-        })?;
-        let (variant, script_type) = match parsed {
-            Descriptor::Wpkh(wpkh) => (
-                SetDescriptorVariant::SingleSig(map_key(wpkh.as_inner())?),
-                ScriptType::NativeSegwit,
-            ),
-            Descriptor::Pkh(pkh) => (
-                SetDescriptorVariant::SingleSig(map_key(pkh.as_inner())?),
-                ScriptType::Legacy,
-            ),
-            Descriptor::Sh(sh) => match sh.as_inner() {
-                ShInner::Wpkh(wpkh) => (
-                    SetDescriptorVariant::SingleSig(map_key(wpkh.as_inner())?),
-                    ScriptType::WrappedSegwit,
-                ),
-                ShInner::Wsh(wsh) => (process_wsh(wsh)?, ScriptType::WrappedSegwit),
-                ShInner::Ms(Miniscript {
-                    node: miniscript::Terminal::Multi(k, pks),
-                    ..
-                }) => (make_multisig(*k, pks, false)?, ScriptType::Legacy),
-                ShInner::SortedMulti(SortedMultiVec { k, pks, .. }) => {
-                    (make_multisig(*k, pks, true)?, ScriptType::Legacy)
-                }
-                _ => {
-                    return Err(SdkError::UnsupportedDescriptor {
-                        cause: "Arbitrary descriptors are not supported".to_string(),
-                    })
-                }
-            },
-            Descriptor::Wsh(wsh) => (process_wsh(&wsh)?, ScriptType::NativeSegwit),
-            _ => {
-                return Err(SdkError::UnsupportedDescriptor {
-                    cause: "Unsupported descriptor type".into(),
-                })
+        let (variant, script_type) = parse_descriptor(&descriptor)?;
+
+        if matches!(variant, SetDescriptorVariant::TapTree { .. }) {
+            let status = self.get_status().await?;
+            if status.protocol_version.unwrap_or(0) < 21 {
+                return Err(SdkError::DeviceError {
+                    code: Some(DeviceErrorCode::UnsupportedRequest),
+                    detail: "The firmware doesn't support script trees".into(),
+                });
             }
-        };
+        }
 
         let request = Request::SetDescriptor {
             variant,
@@ -468,64 +971,80 @@ impl PortalSdk {
         Ok(())
     }
 
+    /// Set the descriptor of the device, registering it with `name`
+    ///
+    /// The name is confirmed on the device with the descriptor and shown when signing. The device
+    /// replies with the HMAC of the policy (32 bytes), which must be kept by the host and passed to
+    /// `use_wallet_policy` before signing. Registering again, or setting a descriptor with
+    /// `set_descriptor`, replaces the policy. Names are up to 16 printable ASCII characters,
+    /// otherwise the device replies with `DeviceErrorCode::InvalidWalletName`.
+    pub async fn register_wallet_policy(
+        &self,
+        name: String,
+        descriptor: String,
+    ) -> Result<Vec<u8>, SdkError> {
+        let status = self.get_status().await?;
+        if status.protocol_version.unwrap_or(0) < 22 {
+            return Err(SdkError::DeviceError {
+                code: Some(DeviceErrorCode::UnsupportedRequest),
+                detail: "The firmware doesn't support wallet policies".into(),
+            });
+        }
+
+        let (variant, script_type) = parse_descriptor(&descriptor)?;
+        let request = Request::RegisterWalletPolicy {
+            name,
+            variant,
+            script_type,
+        };
+        let hmac = send_with_retry!(self.requests, request.clone(), Ok(Reply::WalletPolicy(hmac)) => break Ok(hmac))?;
+        Ok(hmac.to_vec())
+    }
+
+    /// Reference the policy registered with `register_wallet_policy` by its HMAC
+    ///
+    /// Once a policy is registered the device refuses to sign with
+    /// `DeviceErrorCode::WalletPolicyMismatch` until the host references it, which lasts until the
+    /// end of the NFC session.
+    pub async fn use_wallet_policy(&self, hmac: Vec<u8>) -> Result<(), SdkError> {
+        // Would be refused by the device all the same
+        let hmac: [u8; 32] = hmac.try_into().map_err(|_| SdkError::DeviceError {
+            code: Some(DeviceErrorCode::WalletPolicyMismatch),
+            detail: "Invalid wallet policy HMAC".into(),
+        })?;
+        let request = Request::UseWalletPolicy {
+            hmac: Box::new(hmac.into()),
+        };
+        send_with_retry!(self.requests, request.clone(), Ok(Reply::Ok) => break Ok(()))?;
+        Ok(())
+    }
+
     pub async fn public_descriptors(&self) -> Result<Descriptors, SdkError> {
-        let descriptor = send_with_retry!(self.requests, Request::PublicDescriptor, Ok(Reply::Descriptor{ external, internal }) => break Ok(Descriptors { external, internal }))?;
+        let descriptor = send_with_retry!(self.requests, Request::PublicDescriptor, Ok(Reply::Descriptor{ external, internal, multipath }) => break Ok(Descriptors { external, internal, multipath }))?;
         Ok(descriptor)
     }
 
     pub async fn update_firmware(&self, binary: Vec<u8>) -> Result<(), SdkError> {
-        // First 64 bytes are the signature, then there's the actual firmware.
-        // We expect at least two pages (4K)
-        if binary.len() < 64 + 4096 || binary.len() > 64 + 510 * 2048 {
-            return Err(SdkError::InvalidFirmware);
-        }
-
-        let signature: [u8; 64] = binary[..64].try_into().expect("Correct length");
+        let header = make_fw_update_header(&binary)?;
         let binary = &binary[64..];
 
-        // The dword is the stack pointer. It must be within RAM
-        let sp = u32::from_le_bytes(binary[..4].try_into().unwrap());
-        // The dword is the reset handler. It must be within FLASH
-        let reset = u32::from_le_bytes(binary[4..8].try_into().unwrap());
-
-        match sp {
-            SRAM1_BASE..=SRAM1_END | SRAM2_BASE..=SRAM2_END => {}
-            _ => return Err(SdkError::InvalidFirmware),
-        }
-        match reset {
-            FLASH_BASE..=FLASH_END => {}
-            _ => return Err(SdkError::InvalidFirmware),
-        }
-
-        let get_page = |i: usize| {
-            let mut buf: Box<model::ByteArray<2048>> = Box::new([0u8; 2048].into());
-            if binary.len() < i * 2048 {
-                return None;
-            }
-            let end = std::cmp::min(binary.len(), (i + 1) * 2048);
-            let chunk = &binary[i * 2048..end];
-            buf.deref_mut()[..chunk.len()].copy_from_slice(&chunk);
+        // If a previous update of the same image was interrupted the device will ask for the
+        // page where it stopped instead of the first one
+        let mut page = send_with_retry!(self.requests, model::Request::BeginFwUpdate(header.clone()), Ok(Reply::NextPage(page)) => break Ok(Some(page)), Ok(Reply::Ok) => break Ok(None))?;
 
-            Some(buf)
-        };
+        // One extra step for the final verification
+        let total_pages = (binary.len().div_ceil(2048) + 1) as u32;
 
-        use model::bitcoin::hashes::HashEngine;
-        let mut first_page_midstate = model::bitcoin::hashes::sha256::HashEngine::default();
-        first_page_midstate.input(get_page(0).unwrap().deref().deref());
-        let first_page_midstate = first_page_midstate.midstate();
-        let header = model::FwUpdateHeader {
-            variant: model::FwVariant::VANILLA,
-            signature: Box::new(signature.into()),
-            size: binary.len(),
-            first_page_midstate: Box::new(first_page_midstate.into_inner().into()),
-        };
-
-        let mut page = send_with_retry!(self.requests, model::Request::BeginFwUpdate(header.clone()), Ok(Reply::NextPage(page)) => break Ok(Some(page)), Ok(Reply::Ok) => break Ok(None))?;
         while let Some(p) = page {
-            let is_last = get_page(p).is_none();
-            let get_req = || match get_page(p) {
-                Some(data) => model::Request::FwUpdateChunk(data.clone()),
-                None => model::Request::CompleteFwUpdate(get_page(0).unwrap()),
+            self.requests.report_progress(OperationProgress {
+                current: p as u32,
+                total: total_pages,
+            });
+
+            let is_last = get_fw_page(binary, p).is_none();
+            let get_req = || match get_fw_page(binary, p) {
+                Some(data) => model::Request::FwUpdateChunk(data),
+                None => model::Request::CompleteFwUpdate(get_fw_page(binary, 0).unwrap()),
             };
 
             page = send_with_retry!(self.requests, get_req(), Ok(Reply::NextPage(page)) => break Ok(Some(page)), Ok(Reply::Ok) => break Ok(None))?;
@@ -533,10 +1052,47 @@ impl PortalSdk {
                 return Err(SdkError::UnexpectedMessage);
             }
         }
+        self.requests.report_progress(OperationProgress {
+            current: total_pages,
+            total: total_pages,
+        });
 
         Ok(())
     }
 
+    /// Number of bytes of `binary` already written by a previous, interrupted update
+    ///
+    /// Calling `update_firmware()` with the same image will resume from there. Returns 0 if the
+    /// transfer would start from scratch.
+    pub async fn firmware_update_offset(&self, binary: Vec<u8>) -> Result<u64, SdkError> {
+        let header = make_fw_update_header(&binary)?;
+        let page = send_with_retry!(self.requests, model::Request::GetFwUpdateOffset(header.clone()), Ok(Reply::NextPage(page)) => break Ok(page))?;
+
+        // The first page is only sent at the end
+        Ok((page.saturating_sub(1) * 2048) as u64)
+    }
+
+    /// Wait for the next progress update of a long operation
+    ///
+    /// Updates are reported while a large PSBT is sent in parts, while the device signs it and
+    /// while flashing a firmware update. Only the most recent updates are kept if nobody is
+    /// listening.
+    pub async fn progress(&self) -> Result<OperationProgress, SdkError> {
+        Ok(self.requests.progress_r.recv().await?)
+    }
+
+    /// Register a callback for the progress updates, as an alternative to polling `progress()`
+    ///
+    /// The listener gets the same updates as `progress()`, and is called from the task that
+    /// sends the request, so it should return quickly.
+    pub fn set_progress_listener(&self, listener: Box<dyn ProgressListener>) {
+        *self.requests.progress_listener.lock().unwrap() = Some(listener);
+    }
+
+    pub fn clear_progress_listener(&self) {
+        *self.requests.progress_listener.lock().unwrap() = None;
+    }
+
     #[cfg(feature = "debug")]
     pub async fn debug_msg(&self) -> Result<DebugMessage, SdkError> {
         Ok(self.debug_channels.recv.recv().await?)
@@ -548,6 +1104,322 @@ impl PortalSdk {
     }
 }
 
+// Helpers that can't be exported to the bindings
+impl PortalSdk {
+    /// Split a PSBT larger than `model::psbt_stream::PART_LEN` into the parts sent with
+    /// `SignPsbtPart` and the last one, sent with `SignPsbt`
+    ///
+    /// The device parses the parts as they are received, so that large PSBTs don't have to fit
+    /// in its memory. Firmwares that don't support `SignPsbtPart` get the whole PSBT at once. This
+    /// talks to the device, so it must be called before `BeginSignPsbt` and the like.
+    async fn split_psbt(&self, raw_psbt: Vec<u8>) -> Result<(Vec<Vec<u8>>, Vec<u8>), SdkError> {
+        use model::psbt_stream::PART_LEN;
+
+        if raw_psbt.len() <= PART_LEN || self.get_status().await?.protocol_version.unwrap_or(0) < 16
+        {
+            return Ok((vec![], raw_psbt));
+        }
+
+        let mut parts = raw_psbt
+            .chunks(PART_LEN)
+            .map(<[u8]>::to_vec)
+            .collect::<Vec<_>>();
+        let last = parts.pop().expect("The PSBT is not empty");
+        Ok((parts, last))
+    }
+
+    /// Send the parts returned by `split_psbt`, reporting the progress of the transfer
+    async fn send_psbt_parts(&self, parts: Vec<Vec<u8>>) -> Result<(), SdkError> {
+        // The last part is sent with the signing request
+        let total = parts.len() as u32 + 1;
+        for (i, part) in parts.into_iter().enumerate() {
+            self.requests.report_progress(OperationProgress {
+                current: i as u32,
+                total,
+            });
+            send_with_retry!(self.requests, Request::SignPsbtPart(part.clone().into()), Ok(Reply::Ok) => break Ok(()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn sign_psbt_with_context(
+        &self,
+        psbt: String,
+        fiat_rate: Option<model::FiatRate>,
+        cpfp: Option<model::CpfpInfo>,
+    ) -> Result<String, SdkError> {
+        let (raw_psbt, _) = psbt::decode(&psbt)?;
+        let (parts, last) = self.split_psbt(raw_psbt).await?;
+
+        send_with_retry!(self.requests, Request::BeginSignPsbt { fiat_rate: fiat_rate.clone(), cpfp: cpfp.clone() }, Ok(Reply::Ok) => break Ok(()))?;
+
+        self.send_psbt_parts(parts).await?;
+        let sig_diff = send_with_retry!(self.requests, Request::SignPsbt(last.clone().into(), None, None), Ok(Reply::SignedPsbt(s)) => break Ok(s))?;
+
+        psbt::merge_signatures(&psbt, &sig_diff)
+    }
+}
+
+/// Parse a descriptor into the variant and the script type sent to the device
+///
+/// Keys are expected without the keychain step (`xpub/*`), or with both keychains as a multipath
+/// step (`xpub/<0;1>/*`).
+fn parse_descriptor(descriptor: &str) -> Result<(SetDescriptorVariant, ScriptType), SdkError> {
+    use miniscript::{descriptor::*, Miniscript};
+    use std::str::FromStr;
+
+    fn map_key(pk: &DescriptorPublicKey) -> Result<ExtendedKey, SdkError> {
+        let pk = match pk {
+            DescriptorPublicKey::Single(_) => {
+                return Err(SdkError::UnsupportedDescriptor {
+                    detail: "Single public keys are not supported".to_string(),
+                })
+            }
+            DescriptorPublicKey::XPub(xpub) => xpub,
+        };
+
+        if pk.wildcard != Wildcard::Unhardened {
+            return Err(SdkError::UnsupportedDescriptor {
+                detail: "Invalid wildcard".to_string(),
+            });
+        }
+
+        Ok(ExtendedKey {
+            key: pk.xkey.into(),
+            origin: pk
+                .origin
+                .as_ref()
+                .map(|(f, d)| ((*f).into(), d.clone().into())),
+            path: pk.derivation_path.clone().into(),
+        })
+    }
+    fn make_multisig(
+        k: usize,
+        pks: &[DescriptorPublicKey],
+        is_sorted: bool,
+    ) -> Result<SetDescriptorVariant, SdkError> {
+        let keys = pks
+            .into_iter()
+            .map(|pk| map_key(pk))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SetDescriptorVariant::MultiSig {
+            threshold: k,
+            keys,
+            is_sorted,
+        })
+    }
+    fn process_wsh(wsh: &Wsh<DescriptorPublicKey>) -> Result<SetDescriptorVariant, SdkError> {
+        match wsh.as_inner() {
+            WshInner::Ms(Miniscript {
+                node: miniscript::Terminal::Multi(k, pks),
+                ..
+            }) => make_multisig(*k, pks, false),
+            WshInner::SortedMulti(SortedMultiVec { k, pks, .. }) => make_multisig(*k, pks, true),
+            _ => {
+                return Err(SdkError::UnsupportedDescriptor {
+                    detail: "Arbitrary descriptors are not supported".to_string(),
+                })
+            }
+        }
+    }
+
+    fn process_tap_tree(tr: &Tr<DescriptorPublicKey>) -> Result<SetDescriptorVariant, SdkError> {
+        use miniscript::Terminal;
+
+        let internal_key = match tr.internal_key() {
+            DescriptorPublicKey::Single(SinglePub {
+                key: SinglePubKey::XOnly(key),
+                ..
+            }) if key.serialize() == model::taproot::NUMS_KEY => None,
+            key => Some(map_key(key)?),
+        };
+        let leaves = tr
+            .iter_scripts()
+            .map(|(depth, ms)| {
+                let script = match &ms.node {
+                    Terminal::Check(inner) => match &inner.node {
+                        Terminal::PkK(pk) => TapLeafScript::Key(map_key(pk)?),
+                        _ => {
+                            return Err(SdkError::UnsupportedDescriptor {
+                                detail: "Arbitrary script paths are not supported".to_string(),
+                            })
+                        }
+                    },
+                    Terminal::MultiA(k, pks) => TapLeafScript::MultiA {
+                        threshold: *k,
+                        keys: pks.iter().map(map_key).collect::<Result<Vec<_>, _>>()?,
+                    },
+                    _ => {
+                        return Err(SdkError::UnsupportedDescriptor {
+                            detail: "Arbitrary script paths are not supported".to_string(),
+                        })
+                    }
+                };
+                Ok(TapLeaf { depth, script })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(SetDescriptorVariant::TapTree {
+            internal_key,
+            leaves,
+        })
+    }
+
+    // `tr(NUMS, sortedmulti_a(k, ...))`, which miniscript can't parse yet
+    fn parse_sortedmulti_a(descriptor: &str) -> Result<SetDescriptorVariant, SdkError> {
+        use model::bitcoin::hashes::hex::ToHex;
+
+        let nums = model::taproot::NUMS_KEY.to_hex();
+        let inner = descriptor
+            .split('#')
+            .next()
+            .and_then(|d| d.strip_prefix("tr("))
+            .and_then(|d| d.strip_suffix("))"))
+            .and_then(|d| d.split_once(",sortedmulti_a("))
+            .filter(|(internal, _)| *internal == nums)
+            .map(|(_, inner)| inner)
+            .ok_or_else(|| SdkError::UnsupportedDescriptor {
+                detail: "Only `sortedmulti_a` with the NUMS internal key is supported".into(),
+            })?;
+
+        let mut parts = inner.split(',');
+        let k = parts
+            .next()
+            .and_then(|k| k.parse::<usize>().ok())
+            .ok_or_else(|| SdkError::InvalidDescriptor {
+                detail: "Invalid threshold".into(),
+            })?;
+        let pks = parts
+            .map(|pk| {
+                DescriptorPublicKey::from_str(pk).map_err(|e| SdkError::InvalidDescriptor {
+                    detail: e.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        make_multisig(k, &pks, true)
+    }
+
+    // The device adds the keychain step itself, and miniscript can't parse multipath keys yet
+    let descriptor = &model::multipath::strip_keychains(descriptor).map_err(|e| match e {
+        model::multipath::MultipathError::InvalidChecksum => SdkError::InvalidDescriptor {
+            detail: e.to_string(),
+        },
+        model::multipath::MultipathError::UnsupportedStep => SdkError::UnsupportedDescriptor {
+            detail: e.to_string(),
+        },
+    })?;
+
+    if descriptor.contains("sortedmulti_a(") {
+        return Ok((parse_sortedmulti_a(descriptor)?, ScriptType::Taproot));
+    }
+
+    let parsed = Descriptor::<DescriptorPublicKey>::from_str(descriptor).map_err(|e| {
+        SdkError::InvalidDescriptor {
+            detail: e.to_string(),
+        }
+    })?;
+    let parsed = match parsed {
+        Descriptor::Wpkh(wpkh) => (
+            SetDescriptorVariant::SingleSig(map_key(wpkh.as_inner())?),
+            ScriptType::NativeSegwit,
+        ),
+        Descriptor::Pkh(pkh) => (
+            SetDescriptorVariant::SingleSig(map_key(pkh.as_inner())?),
+            ScriptType::Legacy,
+        ),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wpkh(wpkh) => (
+                SetDescriptorVariant::SingleSig(map_key(wpkh.as_inner())?),
+                ScriptType::WrappedSegwit,
+            ),
+            ShInner::Wsh(wsh) => (process_wsh(wsh)?, ScriptType::WrappedSegwit),
+            ShInner::Ms(Miniscript {
+                node: miniscript::Terminal::Multi(k, pks),
+                ..
+            }) => (make_multisig(*k, pks, false)?, ScriptType::Legacy),
+            ShInner::SortedMulti(SortedMultiVec { k, pks, .. }) => {
+                (make_multisig(*k, pks, true)?, ScriptType::Legacy)
+            }
+            _ => {
+                return Err(SdkError::UnsupportedDescriptor {
+                    detail: "Arbitrary descriptors are not supported".to_string(),
+                })
+            }
+        },
+        Descriptor::Wsh(wsh) => (process_wsh(&wsh)?, ScriptType::NativeSegwit),
+        Descriptor::Tr(tr) if tr.taptree().is_none() => (
+            SetDescriptorVariant::SingleSig(map_key(tr.internal_key())?),
+            ScriptType::Taproot,
+        ),
+        Descriptor::Tr(tr) => (process_tap_tree(&tr)?, ScriptType::Taproot),
+        _ => {
+            return Err(SdkError::UnsupportedDescriptor {
+                detail: "Unsupported descriptor type".into(),
+            })
+        }
+    };
+
+    Ok(parsed)
+}
+
+/// Validate a signed firmware image and build the header for its update
+fn make_fw_update_header(binary: &[u8]) -> Result<model::FwUpdateHeader, SdkError> {
+    // First 64 bytes are the signature, then there's the actual firmware.
+    // We expect at least two pages (4K)
+    if binary.len() < 64 + 4096 || binary.len() > 64 + FLASH_SIZE as usize {
+        return Err(SdkError::InvalidFirmware);
+    }
+
+    let signature: [u8; 64] = binary[..64].try_into().expect("Correct length");
+    let binary = &binary[64..];
+
+    // The dword is the stack pointer. It must be within RAM
+    let sp = u32::from_le_bytes(binary[..4].try_into().unwrap());
+    // The dword is the reset handler. It must be within FLASH
+    let reset = u32::from_le_bytes(binary[4..8].try_into().unwrap());
+
+    match sp {
+        SRAM1_BASE..=SRAM1_END | SRAM2_BASE..=SRAM2_END => {}
+        _ => return Err(SdkError::InvalidFirmware),
+    }
+    match reset {
+        FLASH_BASE..=FLASH_END => {}
+        _ => return Err(SdkError::InvalidFirmware),
+    }
+
+    use model::bitcoin::hashes::{sha256, Hash, HashEngine};
+    let mut first_page_midstate = sha256::HashEngine::default();
+    first_page_midstate.input(get_fw_page(binary, 0).unwrap().deref().deref());
+    let first_page_midstate = first_page_midstate.midstate();
+
+    // The image ends with the version (4 bytes, big endian) followed by the variant
+    let tail = &binary[binary.len() - FW_TAIL_SIZE..];
+    let version = u32::from_be_bytes(tail[..4].try_into().unwrap());
+
+    Ok(model::FwUpdateHeader {
+        variant: model::FwVariant::VANILLA,
+        signature: Box::new(signature.into()),
+        size: binary.len(),
+        first_page_midstate: Box::new(first_page_midstate.into_inner().into()),
+        version,
+        hash: Box::new(sha256::Hash::hash(binary).into_inner().into()),
+    })
+}
+
+/// Page `i` of the firmware image, padded with zeros
+fn get_fw_page(binary: &[u8], i: usize) -> Option<Box<model::ByteArray<2048>>> {
+    let mut buf: Box<model::ByteArray<2048>> = Box::new([0u8; 2048].into());
+    if binary.len() < i * 2048 {
+        return None;
+    }
+    let end = std::cmp::min(binary.len(), (i + 1) * 2048);
+    let chunk = &binary[i * 2048..end];
+    buf.deref_mut()[..chunk.len()].copy_from_slice(chunk);
+
+    Some(buf)
+}
+
 struct BsmsTranslator;
 impl miniscript::Translator<String, String, SdkError> for BsmsTranslator {
     fn pk(&mut self, pk: &String) -> Result<String, SdkError> {
@@ -558,7 +1430,7 @@ impl miniscript::Translator<String, String, SdkError> for BsmsTranslator {
             Ok(pk)
         } else {
             Err(SdkError::UnsupportedDescriptor {
-                cause:
+                detail:
                     "When using BSMS all the keys must end with descriptor template syntax (`/**`)"
                         .into(),
             })
@@ -597,6 +1469,23 @@ impl miniscript::Translator<String, String, SdkError> for BsmsTranslator {
 struct RequestChannels {
     o: channel::Sender<Request>,
     i: channel::Receiver<Result<Reply, FutureError>>,
+    progress_s: channel::Sender<OperationProgress>,
+    progress_r: channel::Receiver<OperationProgress>,
+    progress_listener: std::sync::Mutex<Option<Box<dyn ProgressListener>>>,
+}
+
+impl RequestChannels {
+    fn report_progress(&self, progress: OperationProgress) {
+        if let Some(listener) = self.progress_listener.lock().unwrap().as_ref() {
+            listener.on_progress(progress.clone());
+        }
+
+        // Drop the oldest update to make room if nobody is reading them
+        if self.progress_s.is_full() {
+            let _ = self.progress_r.try_recv();
+        }
+        let _ = self.progress_s.try_send(progress);
+    }
 }
 
 struct NfcChannels {
@@ -662,6 +1551,7 @@ impl InnerManager {
         let (nfc_out_s, nfc_out_r) = channel::unbounded();
         let (nfc_in_s, nfc_in_r) = channel::unbounded();
         let (stop_s, stop_r) = channel::unbounded();
+        let (progress_s, progress_r) = channel::bounded(MAX_QUEUED_PROGRESS);
 
         #[cfg(feature = "debug")]
         let (debug_out, debug_in, debug) = {
@@ -701,6 +1591,9 @@ impl InnerManager {
         let req_channels = RequestChannels {
             o: requests_s,
             i: replies_r,
+            progress_s,
+            progress_r,
+            progress_listener: std::sync::Mutex::new(None),
         };
         let nfc_channels = NfcChannels {
             o: nfc_in_s,
@@ -751,6 +1644,43 @@ pub struct CardStatus {
     ///
     /// Only available when the device is initialized and unlocked
     pub fingerprint: Option<bip32::Fingerprint>,
+    /// Version of the message encoding used by the firmware, `None` before versioning was added
+    ///
+    /// Requests added in later versions aren't supported by the device.
+    pub protocol_version: Option<u32>,
+    /// Only available when the device is initialized and unlocked
+    pub descriptor: Option<DescriptorSummary>,
+    /// Git hash the firmware was built from
+    pub firmware_hash: Option<String>,
+    /// Identifier and revision of the microcontroller
+    pub hardware_revision: Option<String>,
+    /// Serial number programmed at manufacture
+    pub serial: Option<String>,
+    /// Bits from `model::capabilities`, `None` for firmwares that predate them
+    pub capabilities: Option<u32>,
+}
+
+/// Script type and policy of the wallet, without the keys
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct DescriptorSummary {
+    /// Name of the script type, like "Native Segwit"
+    pub script_type: String,
+    /// Single-sig wallets are 1-of-1
+    pub threshold: u32,
+    pub num_keys: u32,
+    pub is_sorted: bool,
+}
+
+impl From<model::DescriptorSummary> for DescriptorSummary {
+    fn from(summary: model::DescriptorSummary) -> Self {
+        DescriptorSummary {
+            script_type: summary.script_type.display_name().to_string(),
+            threshold: summary.threshold as u32,
+            num_keys: summary.num_keys as u32,
+            is_sorted: summary.is_sorted,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -758,6 +1688,49 @@ pub struct CardStatus {
 pub struct Descriptors {
     pub external: String,
     pub internal: Option<String>,
+    /// Both keychains in a single BIP-389 descriptor (`/<0;1>/*`), `None` with older firmwares
+    pub multipath: Option<String>,
+}
+
+/// Check the signature of a message returned by the device and encode it in base64
+fn message_signature(
+    address: String,
+    signature: &[u8],
+    verify: impl FnOnce(&model::bitcoin::Script, &[u8]) -> bool,
+) -> Result<MessageSignature, SdkError> {
+    let parsed: model::bitcoin::Address = address
+        .parse()
+        .map_err(|_| SdkError::DeserializationError)?;
+    if !verify(&parsed.script_pubkey(), signature) {
+        return Err(SdkError::InvalidSignatures {
+            detail: "The message signature doesn't match the address".into(),
+        });
+    }
+
+    Ok(MessageSignature {
+        address,
+        signature: base64::encode(signature),
+    })
+}
+
+/// Result of `PortalSdk::sign_and_finalize_psbt()`, only one of the two is set
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct FinalizedPsbt {
+    /// Hex-encoded transaction, ready to be broadcast
+    pub transaction: Option<String>,
+    /// Base64-encoded PSBT with the signatures of the device, if more are needed
+    pub psbt: Option<String>,
+}
+
+/// Signature of a message, see `PortalSdk::sign_message()` and
+/// `PortalSdk::sign_message_legacy()`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct MessageSignature {
+    pub address: String,
+    /// Base64-encoded signature, in the BIP-322 "simple" format or the 65 bytes of a legacy one
+    pub signature: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -777,11 +1750,169 @@ pub struct SetDescriptorBsmsData {
     pub first_address: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct OperationProgress {
+    pub current: u32,
+    pub total: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum SelfTestOutcome {
+    Passed,
+    Failed { cause: String },
+    Skipped,
+}
+
+impl From<model::selftest::TestOutcome> for SelfTestOutcome {
+    fn from(outcome: model::selftest::TestOutcome) -> Self {
+        match outcome {
+            model::selftest::TestOutcome::Passed => SelfTestOutcome::Passed,
+            model::selftest::TestOutcome::Failed(cause) => SelfTestOutcome::Failed { cause },
+            model::selftest::TestOutcome::Skipped => SelfTestOutcome::Skipped,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct SelfTestReport {
+    pub flash: SelfTestOutcome,
+    pub rng: SelfTestOutcome,
+    pub display: SelfTestOutcome,
+    pub touch: SelfTestOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct WipeReport {
+    pub pages_checked: u32,
+    pub pages_erased: u32,
+    pub passes: u32,
+    /// Pages that still weren't blank after the last erase pass
+    pub failed_pages: Vec<u32>,
+    pub update_checkpoints: bool,
+    /// Whether every page read back blank
+    pub verified: bool,
+}
+
+impl From<model::WipeReport> for WipeReport {
+    fn from(report: model::WipeReport) -> Self {
+        WipeReport {
+            verified: report.is_verified(),
+            pages_checked: report.pages_checked,
+            pages_erased: report.pages_erased,
+            passes: report.passes,
+            failed_pages: report.failed_pages,
+            update_checkpoints: report.update_checkpoints,
+        }
+    }
+}
+
+#[cfg_attr(feature = "bindings", uniffi::export(callback_interface))]
+pub trait ProgressListener: Send + Sync {
+    fn on_progress(&self, progress: OperationProgress);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum AddressKeychain {
+    External,
+    Internal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum AddressScriptType {
+    Legacy,
+    WrappedSegwit,
+    NativeSegwit,
+    Taproot,
+}
+
+impl From<ScriptType> for AddressScriptType {
+    fn from(script_type: ScriptType) -> Self {
+        match script_type {
+            ScriptType::Legacy => AddressScriptType::Legacy,
+            ScriptType::WrappedSegwit => AddressScriptType::WrappedSegwit,
+            ScriptType::NativeSegwit => AddressScriptType::NativeSegwit,
+            ScriptType::Taproot => AddressScriptType::Taproot,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct DeviceAddressInfo {
+    pub keychain: AddressKeychain,
+    pub index: u32,
+    /// Full path of the key of the device, including the keychain and the index
+    pub derivation_path: bip32::DerivationPath,
+    pub script_type: AddressScriptType,
+    /// Output script the address pays to
+    pub script_pubkey: Vec<u8>,
+}
+
+impl From<model::AddressInfo> for DeviceAddressInfo {
+    fn from(info: model::AddressInfo) -> Self {
+        DeviceAddressInfo {
+            keychain: match info.keychain {
+                model::Keychain::External => AddressKeychain::External,
+                model::Keychain::Internal => AddressKeychain::Internal,
+            },
+            index: info.index,
+            derivation_path: info.path.into(),
+            script_type: info.script_type.into(),
+            script_pubkey: info.script_pubkey.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct DeviceAddress {
+    pub address: model::bitcoin::Address,
+    pub info: Option<DeviceAddressInfo>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "bindings", derive(uniffi::Record))]
 pub struct DeviceXpub {
+    /// Key with its origin, e.g. `[fingerprint/84'/0'/0']xpub...`
     pub xpub: String,
     pub bsms: GetXpubBsmsData,
+    /// Same key as `xpub`, `None` for firmwares that don't send it
+    pub key: Option<DeviceXpubKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct DeviceXpubKey {
+    /// BIP-32 serialization of the key
+    pub xpub: Vec<u8>,
+    pub parent_fingerprint: bip32::Fingerprint,
+    pub master_fingerprint: bip32::Fingerprint,
+    pub origin_path: bip32::DerivationPath,
+    pub network: model::bitcoin::Network,
+}
+
+impl From<model::XpubKey> for DeviceXpubKey {
+    fn from(key: model::XpubKey) -> Self {
+        DeviceXpubKey {
+            xpub: key.key.value.to_vec(),
+            parent_fingerprint: key.parent_fingerprint.into(),
+            master_fingerprint: key.master_fingerprint.into(),
+            origin_path: key.origin_path.into(),
+            network: key.network,
+        }
+    }
+}
+
+impl DeviceXpubKey {
+    pub fn as_xpub(&self) -> Result<bip32::ExtendedPubKey, bip32::Error> {
+        bip32::ExtendedPubKey::decode(&self.xpub)
+    }
 }
 
 #[derive(Debug)]
@@ -791,9 +1922,43 @@ pub enum GenerateMnemonicWords {
     Words24,
 }
 
+/// Wordlist of a new mnemonic, see `model::mnemonic`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Enum))]
+pub enum GenerateMnemonicLanguage {
+    English,
+    Spanish,
+    French,
+    Italian,
+    Czech,
+}
+
+impl From<GenerateMnemonicLanguage> for MnemonicLanguage {
+    fn from(value: GenerateMnemonicLanguage) -> Self {
+        match value {
+            GenerateMnemonicLanguage::English => MnemonicLanguage::English,
+            GenerateMnemonicLanguage::Spanish => MnemonicLanguage::Spanish,
+            GenerateMnemonicLanguage::French => MnemonicLanguage::French,
+            GenerateMnemonicLanguage::Italian => MnemonicLanguage::Italian,
+            GenerateMnemonicLanguage::Czech => MnemonicLanguage::Czech,
+        }
+    }
+}
+
+fn parse_host_key(host_key: &[u8]) -> Result<model::bitcoin::secp256k1::SecretKey, SdkError> {
+    model::bitcoin::secp256k1::SecretKey::from_slice(host_key).map_err(|_| {
+        SdkError::InvalidSignatures {
+            detail: "Invalid host key".into(),
+        }
+    })
+}
+
+/// Errors of the SDK
+///
+/// The fields are exported to the bindings too. Descriptions are called `detail` rather than
+/// `cause`, which would clash with the `cause` of Kotlin exceptions.
 #[derive(Debug)]
 #[cfg_attr(feature = "bindings", derive(uniffi::Error))]
-#[cfg_attr(feature = "bindings", uniffi(flat_error))]
 pub enum SdkError {
     ChannelError,
     CommunicationError,
@@ -804,9 +1969,59 @@ pub enum SdkError {
     Base64,
     InvalidFirmware,
     Locked,
-    DeviceError { cause: String },
-    InvalidDescriptor { cause: String },
-    UnsupportedDescriptor { cause: String },
+    /// The device refused the request
+    ///
+    /// `code` should be used to decide what to show to the user, `detail` is only meant for logs.
+    /// It's `None` with older firmwares that don't send error codes.
+    DeviceError {
+        code: Option<DeviceErrorCode>,
+        detail: String,
+    },
+    InvalidDescriptor {
+        detail: String,
+    },
+    UnsupportedDescriptor {
+        detail: String,
+    },
+    InvalidSignatures {
+        detail: String,
+    },
+    InvalidAttestation {
+        detail: String,
+    },
+}
+
+impl SdkError {
+    /// Stable numeric code for the error
+    ///
+    /// Codes are never reused or renumbered, so they can be relied upon by bindings that can't
+    /// match on the error variants directly.
+    pub fn code(&self) -> u32 {
+        match self {
+            SdkError::ChannelError => 1,
+            SdkError::CommunicationError => 2,
+            SdkError::DifferentUid => 3,
+            SdkError::UnexpectedMessage => 4,
+            SdkError::DeserializationError => 5,
+            SdkError::Timeout => 6,
+            SdkError::Base64 => 7,
+            SdkError::InvalidFirmware => 8,
+            SdkError::Locked => 9,
+            SdkError::DeviceError { .. } => 10,
+            SdkError::InvalidDescriptor { .. } => 11,
+            SdkError::UnsupportedDescriptor { .. } => 12,
+            SdkError::InvalidSignatures { .. } => 13,
+            SdkError::InvalidAttestation { .. } => 14,
+        }
+    }
+}
+
+/// Stable numeric code of `error`, see `SdkError::code()`
+///
+/// Methods can't be exported on errors, so this is how the bindings get the code.
+#[cfg_attr(feature = "bindings", uniffi::export)]
+pub fn sdk_error_code(error: SdkError) -> u32 {
+    error.code()
 }
 
 impl core::fmt::Display for SdkError {
@@ -843,6 +2058,7 @@ impl From<FutureError> for SdkError {
             FutureError::ChannelError => SdkError::ChannelError,
             FutureError::Timeout => SdkError::Timeout,
             FutureError::Message => SdkError::CommunicationError,
+            FutureError::UnsupportedReply => SdkError::UnexpectedMessage,
             FutureError::Canceled => SdkError::CommunicationError,
         }
     }
@@ -860,22 +2076,44 @@ mod ffi {
 
     use super::*;
 
-    impl<T: FromStr + ToString> UniffiCustomTypeConverter for T {
-        type Builtin = String;
+    macro_rules! string_custom_type {
+        ($($ty:ty),*) => {
+            $(
+                impl UniffiCustomTypeConverter for $ty {
+                    type Builtin = String;
+
+                    fn into_custom(val: Self::Builtin) -> uniffi::Result<Self> {
+                        <$ty>::from_str(&val)
+                            .map_err(|_| uniffi::deps::anyhow::Error::msg("Invalid string"))
+                    }
+
+                    fn from_custom(obj: Self) -> Self::Builtin {
+                        obj.to_string()
+                    }
+                }
+
+                uniffi::custom_type!($ty, String);
+            )*
+        };
+    }
+
+    string_custom_type!(Network, Address, DerivationPath, Fingerprint);
+
+    // Sent as the index of the code, see `DeviceErrorCode::index()`
+    impl UniffiCustomTypeConverter for DeviceErrorCode {
+        type Builtin = u32;
 
         fn into_custom(val: Self::Builtin) -> uniffi::Result<Self> {
-            T::from_str(&val).map_err(|_| uniffi::deps::anyhow::Error::msg("Invalid string"))
+            DeviceErrorCode::from_index(val)
+                .ok_or_else(|| uniffi::deps::anyhow::Error::msg("Invalid error code"))
         }
 
         fn from_custom(obj: Self) -> Self::Builtin {
-            obj.to_string()
+            obj.index()
         }
     }
 
-    uniffi::custom_type!(Network, String);
-    uniffi::custom_type!(Address, String);
-    uniffi::custom_type!(DerivationPath, String);
-    uniffi::custom_type!(Fingerprint, String);
+    uniffi::custom_type!(DeviceErrorCode, u32);
 }
 
 #[cfg(feature = "bindings")]
@@ -1,163 +1,450 @@
-use std::collections::HashMap;
+use model::bitcoin::consensus::serialize;
+use model::bitcoin::secp256k1::{Message, PublicKey, Secp256k1, Verification};
+use model::bitcoin::util::psbt::{Input, PartiallySignedTransaction};
+use model::bitcoin::util::sighash::{Prevouts, SighashCache};
+use model::bitcoin::{EcdsaSighashType, SchnorrSighashType, Transaction, TxOut, XOnlyPublicKey};
+use model::psbt_v2;
+use model::sig_diff::{self, SigDiffError};
+use model::SignerCommitment;
 
-use model::bitcoin::consensus::Decodable;
-use model::bitcoin::util::psbt;
+use crate::SdkError;
 
-#[derive(Debug)]
-pub struct PortalPsbt {
-    pub inputs: Vec<psbt::Input>,
+impl From<SigDiffError> for SdkError {
+    fn from(e: SigDiffError) -> Self {
+        match e {
+            SigDiffError::InvalidMagic | SigDiffError::InvalidData => {
+                SdkError::DeserializationError
+            }
+            SigDiffError::InputCountMismatch | SigDiffError::UnknownKey => {
+                SdkError::InvalidSignatures {
+                    detail: e.to_string(),
+                }
+            }
+        }
+    }
 }
 
-impl PortalPsbt {
-    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
-        if &data[..5] != &[0x70, 0x73, 0x62, 0x74, 0xFF] {
-            return Err(ParseError::InvalidMagic);
-        }
-        let data = &data[5..];
+/// Merge the signatures returned by the device into the original PSBT
+///
+/// `psbt` is the base64-encoded PSBT sent to the device and `sig_diff` the content of the
+/// `SignedPsbt` reply. Returns the base64-encoded PSBT with the new signatures added.
+pub fn merge_signatures(psbt: &str, sig_diff: &[u8]) -> Result<String, SdkError> {
+    let (raw_psbt, mut psbt) = decode(psbt)?;
 
-        let (_global, data) = RawMap::parse(data)?;
-        let mut inputs = vec![];
+    let inputs = sig_diff::decode(sig_diff)?;
+    sig_diff::merge(&mut psbt, inputs)?;
 
-        let mut data = data;
-        loop {
-            let (input_map, remainder) = RawMap::parse(data)?;
-            if input_map.0.is_empty() {
-                break;
-            }
+    // Version 2 PSBTs are returned as version 2, with only their input maps updated
+    if psbt_v2::is_v2(&raw_psbt) {
+        let raw_psbt = psbt_v2::update_inputs(&raw_psbt, &psbt.inputs)
+            .map_err(|_| SdkError::DeserializationError)?;
+        return Ok(base64::encode(raw_psbt));
+    }
 
-            inputs.push(input_map.into_psbt_input()?);
+    Ok(base64::encode(serialize(&psbt)))
+}
 
-            data = remainder;
-        }
+/// Parse a PSBT, either version 0 or 2
+pub(crate) fn parse(raw_psbt: &[u8]) -> Result<PartiallySignedTransaction, SdkError> {
+    model::psbt::parse_psbt(raw_psbt).map_err(|_| SdkError::DeserializationError)
+}
+
+/// Decode a base64-encoded PSBT and make sure it's valid before sending it to the device
+///
+/// Returns the raw PSBT, in the version it was given, along with the parsed one.
+pub(crate) fn decode(psbt: &str) -> Result<(Vec<u8>, PartiallySignedTransaction), SdkError> {
+    let raw_psbt = base64::decode(psbt)?;
+    let parsed = parse(&raw_psbt)?;
+    Ok((raw_psbt, parsed))
+}
+
+/// Result of the host-side check of a PSBT signed by the device, see `verify_signed_psbt()`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct SignedPsbtReport {
+    pub txid: String,
+    /// Fees committed to by the signatures, `None` if the value of an input isn't in the PSBT
+    pub fees: Option<u64>,
+    /// Total value of the outputs committed to by the signatures
+    pub output_value: u64,
+    pub inputs: Vec<InputSignaturesReport>,
+    /// Whether at least one signature was added and all of them are valid and commit to every
+    /// input and output
+    pub valid: bool,
+}
+
+/// Signatures added to an input
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bindings", derive(uniffi::Record))]
+pub struct InputSignaturesReport {
+    pub new_signatures: u32,
+    /// Signatures that don't verify against the original transaction
+    pub invalid_signatures: u32,
+    /// Signatures with a sighash type other than `ALL` (or `DEFAULT` for taproot), which don't
+    /// commit to the whole transaction
+    pub partial_commitments: u32,
+}
 
-        Ok(PortalPsbt { inputs })
+fn signature_mismatch(cause: &str) -> SdkError {
+    SdkError::InvalidSignatures {
+        detail: cause.into(),
     }
 }
 
-#[derive(Debug)]
-pub struct RawMap(HashMap<(u64, Vec<u8>), Vec<u8>>);
+/// Check the signatures added by the device to a PSBT
+///
+/// `original` is the base64-encoded PSBT sent to the device and `signed` the one returned by
+/// `PortalSdk::sign_psbt()`. The transaction and its inputs must be the same in both, and every
+/// signature that wasn't in `original` is verified against the transaction and the values of the
+/// outputs it spends, so that a valid signature proves the device signed the outputs and fees
+/// that the user approved.
+pub fn verify_signed_psbt(original: &str, signed: &str) -> Result<SignedPsbtReport, SdkError> {
+    let decode = |psbt: &str| -> Result<PartiallySignedTransaction, SdkError> {
+        parse(&base64::decode(psbt)?)
+    };
+    let original = decode(original)?;
+    let signed = decode(signed)?;
 
-impl RawMap {
-    pub fn parse(mut data: &[u8]) -> Result<(Self, &[u8]), ParseError> {
-        let mut map = HashMap::new();
+    if signed.unsigned_tx != original.unsigned_tx {
+        return Err(signature_mismatch("The signed transaction is different"));
+    }
+    if signed.inputs.len() != original.inputs.len() {
+        return Err(signature_mismatch("The number of inputs is different"));
+    }
 
-        while !data.is_empty() {
-            let mut cursor = std::io::Cursor::new(data);
+    // The values are committed to by the segwit and taproot signatures, so `witness_utxo` can be
+    // trusted here
+    let utxos = model::psbt::prev_utxos(&original, true)
+        .map_err(|e| signature_mismatch(&e.to_string()))?
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
 
-            let key = Vec::<u8>::consensus_decode(&mut cursor)?;
-            if key.is_empty() {
-                data = &data[1..];
-                break;
-            }
-            let key_type = model::bitcoin::VarInt::consensus_decode(&mut key.as_slice())?;
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(&original.unsigned_tx);
+    let inputs = signed
+        .inputs
+        .iter()
+        .zip(original.inputs.iter())
+        .enumerate()
+        .map(|(index, (signed, original))| {
+            verify_input(&secp, &mut cache, &utxos, index, original, signed)
+        })
+        .collect::<Vec<_>>();
 
-            let value = Vec::<u8>::consensus_decode(&mut cursor)?;
-            let key = key.into_iter().skip(key_type.len()).collect();
+    let output_value = original
+        .unsigned_tx
+        .output
+        .iter()
+        .try_fold(0u64, |sum, out| sum.checked_add(out.value))
+        .ok_or_else(|| signature_mismatch("Invalid output value"))?;
+    let valid = inputs.iter().any(|i| i.new_signatures > 0)
+        && inputs
+            .iter()
+            .all(|i| i.invalid_signatures == 0 && i.partial_commitments == 0);
 
-            map.insert((key_type.0, key), value);
-            data = &data[cursor.position() as usize..];
-        }
+    Ok(SignedPsbtReport {
+        txid: original.unsigned_tx.txid().to_string(),
+        fees: model::psbt::fees(&original, true).ok(),
+        output_value,
+        inputs,
+        valid,
+    })
+}
 
-        Ok((RawMap(map), data))
-    }
+/// Verify the signatures in `signed` that aren't in `original`
+fn verify_input<C: Verification>(
+    secp: &Secp256k1<C>,
+    cache: &mut SighashCache<&Transaction>,
+    utxos: &[TxOut],
+    index: usize,
+    original: &Input,
+    signed: &Input,
+) -> InputSignaturesReport {
+    let mut report = InputSignaturesReport {
+        new_signatures: 0,
+        invalid_signatures: 0,
+        partial_commitments: 0,
+    };
+    let mut check = |valid: bool, commits_to_all: bool| {
+        report.new_signatures += 1;
+        report.invalid_signatures += u32::from(!valid);
+        report.partial_commitments += u32::from(!commits_to_all);
+    };
+
+    let utxo = &utxos[index];
+    let script_pubkey = &utxo.script_pubkey;
 
-    pub fn into_psbt_input(self) -> Result<psbt::Input, ParseError> {
-        fn map_err<E>(_: E) -> ParseError {
-            ParseError::InvalidData
+    for (pk, sig) in &signed.partial_sigs {
+        if original.partial_sigs.contains_key(pk) {
+            continue;
         }
 
-        self.0
-            .into_iter()
-            .try_fold(psbt::Input::default(), |mut state, ((ty, key), data)| {
-                match ty {
-                    // PSBT_IN_PARTIAL_SIG
-                    0x02 => {
-                        let pk = model::bitcoin::PublicKey::from_slice(&key).map_err(map_err)?;
-                        let sig = model::bitcoin::EcdsaSig::from_slice(&data).map_err(map_err)?;
-
-                        state.partial_sigs.insert(pk, sig);
-                    }
-                    // PSBT_IN_TAP_KEY_SIG
-                    0x13 => {
-                        let sig = model::bitcoin::SchnorrSig::from_slice(&data).map_err(map_err)?;
-
-                        state.tap_key_sig = Some(sig);
-                    }
-                    // PSBT_IN_TAP_SCRIPT_SIG
-                    0x14 => {
-                        use model::bitcoin::hashes::Hash;
-
-                        let pk = model::bitcoin::XOnlyPublicKey::from_slice(&key[..32])
-                            .map_err(map_err)?;
-                        let lh = model::bitcoin::util::taproot::TapLeafHash::from_slice(&key[32..])
-                            .map_err(map_err)?;
-                        let sig = model::bitcoin::SchnorrSig::from_slice(&data).map_err(map_err)?;
-
-                        state.tap_script_sigs.insert((pk, lh), sig);
-                    }
-
-                    // Ignore unknown types
-                    _ => {}
-                }
+        let valid = model::psbt::ecdsa_sighash(cache, index, utxo, original, pk, sig.hash_ty)
+            .map(|msg| secp.verify_ecdsa(&msg, &sig.sig, &pk.inner).is_ok())
+            .unwrap_or(false);
+        check(valid, sig.hash_ty == EcdsaSighashType::All);
+    }
 
-                Ok(state)
+    let prevouts = Prevouts::All(utxos);
+    let commits_to_all = |ty: SchnorrSighashType| {
+        matches!(ty, SchnorrSighashType::All | SchnorrSighashType::Default)
+    };
+
+    if let (Some(sig), None) = (&signed.tap_key_sig, &original.tap_key_sig) {
+        let output_key = script_pubkey
+            .is_v1_p2tr()
+            .then(|| XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).ok())
+            .flatten();
+        let valid = output_key
+            .zip(
+                cache
+                    .taproot_key_spend_signature_hash(index, &prevouts, sig.hash_ty)
+                    .ok(),
+            )
+            .and_then(|(key, sighash)| {
+                Message::from_slice(&sighash[..])
+                    .ok()
+                    .map(|msg| secp.verify_schnorr(&sig.sig, &msg, &key).is_ok())
             })
+            .unwrap_or(false);
+        check(valid, commits_to_all(sig.hash_ty));
     }
-}
 
-#[derive(Debug, Clone)]
-pub enum ParseError {
-    InvalidMagic,
-    InvalidData,
+    for ((pk, leaf_hash), sig) in &signed.tap_script_sigs {
+        if original.tap_script_sigs.contains_key(&(*pk, *leaf_hash)) {
+            continue;
+        }
+
+        let valid = cache
+            .taproot_script_spend_signature_hash(index, &prevouts, *leaf_hash, sig.hash_ty)
+            .ok()
+            .and_then(|sighash| Message::from_slice(&sighash[..]).ok())
+            .map(|msg| secp.verify_schnorr(&sig.sig, &msg, pk).is_ok())
+            .unwrap_or(false);
+        check(valid, commits_to_all(sig.hash_ty));
+    }
+
+    report
 }
 
-impl From<model::bitcoin::consensus::encode::Error> for ParseError {
-    fn from(_: model::bitcoin::consensus::encode::Error) -> Self {
-        ParseError::InvalidData
+/// Check that every signature added by the device to `original` uses the nonce it committed to
+///
+/// `commitments` is the `SignerCommitments` reply and `host_data` the data committed to in
+/// `BeginSignPsbtAntiExfil`, see `model::anti_exfil`. A signature without a commitment, or with a
+/// different nonce, means that the device may be leaking its keys.
+pub fn verify_anti_exfil(
+    original: &PartiallySignedTransaction,
+    signed: &PartiallySignedTransaction,
+    commitments: &[SignerCommitment],
+    host_data: &[u8; 32],
+) -> Result<(), SdkError> {
+    let utxos =
+        model::psbt::prev_utxos(original, true).map_err(|e| signature_mismatch(&e.to_string()))?;
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(&original.unsigned_tx);
+
+    for (index, (signed, original)) in signed.inputs.iter().zip(&original.inputs).enumerate() {
+        for (pk, sig) in &signed.partial_sigs {
+            if original.partial_sigs.contains_key(pk) {
+                continue;
+            }
+
+            let nonce = commitments
+                .iter()
+                .find(|c| c.input as usize == index && c.pubkey[..] == pk.inner.serialize())
+                .and_then(|c| PublicKey::from_slice(&c.nonce[..]).ok())
+                .ok_or_else(|| signature_mismatch("Signature without a nonce commitment"))?;
+            let valid = model::psbt::ecdsa_sighash(
+                &mut cache,
+                index,
+                utxos[index],
+                original,
+                pk,
+                sig.hash_ty,
+            )
+            .map(|msg| {
+                model::anti_exfil::verify(&secp, &pk.inner, &msg, &sig.sig, &nonce, host_data)
+            })
+            .unwrap_or(false);
+            if !valid {
+                return Err(signature_mismatch(
+                    "The signature doesn't use the committed nonce",
+                ));
+            }
+        }
+
+        if signed.tap_key_sig.is_some() != original.tap_key_sig.is_some()
+            || signed.tap_script_sigs.len() != original.tap_script_sigs.len()
+        {
+            return Err(signature_mismatch("Unexpected taproot signature"));
+        }
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
-mod test {
+mod tests {
     use super::*;
 
+    use model::bitcoin::blockdata::transaction::{OutPoint, TxIn};
+    use model::bitcoin::hashes::Hash;
+    use model::bitcoin::secp256k1::{KeyPair, SecretKey};
+    use model::bitcoin::util::schnorr::TapTweak;
+    use model::bitcoin::{EcdsaSig, PackedLockTime, PublicKey, SchnorrSig, Script, Txid};
+
+    fn make_psbt(script_pubkey: Script) -> PartiallySignedTransaction {
+        let utxo = TxOut {
+            value: 10_000,
+            script_pubkey,
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new_v0_p2wpkh(&model::bitcoin::WPubkeyHash::all_zeros()),
+            }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(utxo);
+        psbt
+    }
+
+    fn encode(psbt: &PartiallySignedTransaction) -> String {
+        base64::encode(serialize(psbt))
+    }
+
+    fn sign_p2wpkh(psbt: &mut PartiallySignedTransaction, key: &SecretKey) {
+        let secp = Secp256k1::new();
+        let pk = PublicKey::new(key.public_key(&secp));
+        let utxo = psbt.inputs[0].witness_utxo.clone().unwrap();
+        let sighash = SighashCache::new(&psbt.unsigned_tx)
+            .segwit_signature_hash(
+                0,
+                &Script::new_p2pkh(&pk.pubkey_hash()),
+                utxo.value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let sig = secp.sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), key);
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(pk, EcdsaSig::sighash_all(sig));
+    }
+
     #[test]
-    fn test_parse_multiple_inputs() {
-        let data = vec![
-            0x70, 0x73, 0x62, 0x74, 0xFF, 0x01, 0x00, 0x33, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x22, 0x02, 0x02, 0xC5, 0x1A, 0x19, 0x85, 0xE7, 0x6C, 0x6C,
-            0x31, 0xB8, 0xB0, 0xB4, 0x3E, 0x85, 0x04, 0x9D, 0xF5, 0x9F, 0xBD, 0x1D, 0x17, 0x14,
-            0xF5, 0xF9, 0x5C, 0xC3, 0x0F, 0x27, 0x76, 0xA5, 0x3A, 0xB4, 0x40, 0x47, 0x30, 0x44,
-            0x02, 0x20, 0x77, 0x51, 0x39, 0xD4, 0x42, 0xF7, 0xA1, 0x2A, 0xCA, 0x1A, 0x20, 0xD8,
-            0xA4, 0x51, 0x9F, 0x70, 0x7E, 0xA0, 0xC1, 0x65, 0xFF, 0x08, 0x98, 0xF5, 0x50, 0xE4,
-            0xF1, 0x70, 0xD1, 0x14, 0x81, 0x3E, 0x02, 0x20, 0x40, 0xDC, 0x09, 0x28, 0x16, 0x20,
-            0xF5, 0xC0, 0xB3, 0x87, 0x43, 0x1A, 0x75, 0x17, 0x3A, 0x3E, 0x33, 0xC2, 0xBB, 0xDF,
-            0x89, 0xCF, 0xFE, 0x25, 0xC0, 0xF6, 0x61, 0xAD, 0x2F, 0x18, 0xE0, 0x63, 0x01, 0x00,
-            0x22, 0x02, 0x03, 0x2B, 0x64, 0xB3, 0x42, 0xD0, 0x68, 0x0C, 0x4E, 0x03, 0x99, 0xE4,
-            0x69, 0x61, 0xAC, 0x04, 0x2F, 0x4C, 0x91, 0xD6, 0x7C, 0x1E, 0xF6, 0x1A, 0x73, 0x1C,
-            0x7D, 0x65, 0x3E, 0x31, 0x72, 0x0D, 0xCF, 0x47, 0x30, 0x44, 0x02, 0x20, 0x02, 0x3F,
-            0xA0, 0x7E, 0x82, 0x59, 0x78, 0xDA, 0x9A, 0xB7, 0xC7, 0x58, 0x6D, 0x8B, 0x0E, 0x05,
-            0x2C, 0x07, 0x55, 0xDE, 0xA0, 0xB4, 0x23, 0x63, 0xF5, 0x39, 0x40, 0xAC, 0xB7, 0xB6,
-            0xD0, 0x1A, 0x02, 0x20, 0x6C, 0x6D, 0xCE, 0xA4, 0x4E, 0x3A, 0x35, 0x29, 0x06, 0xB7,
-            0x82, 0xC2, 0xA0, 0x9A, 0x2B, 0xA8, 0x96, 0x16, 0x5B, 0x0E, 0xBD, 0x92, 0x34, 0xE9,
-            0x99, 0x63, 0xC1, 0xC7, 0x00, 0xCF, 0xD5, 0xAF, 0x01, 0x00, 0x22, 0x02, 0x02, 0xC5,
-            0x1A, 0x19, 0x85, 0xE7, 0x6C, 0x6C, 0x31, 0xB8, 0xB0, 0xB4, 0x3E, 0x85, 0x04, 0x9D,
-            0xF5, 0x9F, 0xBD, 0x1D, 0x17, 0x14, 0xF5, 0xF9, 0x5C, 0xC3, 0x0F, 0x27, 0x76, 0xA5,
-            0x3A, 0xB4, 0x40, 0x47, 0x30, 0x44, 0x02, 0x20, 0x25, 0xC4, 0x14, 0x8D, 0x39, 0xF1,
-            0xAE, 0x3E, 0x4E, 0x53, 0x65, 0x8A, 0x81, 0xB0, 0x0D, 0x27, 0x91, 0xE0, 0xDC, 0xDD,
-            0x49, 0x1A, 0x5E, 0x9A, 0x57, 0x71, 0xA5, 0xD4, 0xDD, 0x1D, 0x42, 0xB6, 0x02, 0x20,
-            0x5C, 0x6E, 0x5D, 0xA6, 0xEC, 0xFB, 0xE2, 0xEB, 0xE0, 0x9B, 0x1C, 0xDA, 0xB8, 0x18,
-            0x13, 0x79, 0xBB, 0xFC, 0xAE, 0xE3, 0xA5, 0x48, 0x39, 0xFA, 0x16, 0xF8, 0x0D, 0x8E,
-            0xF2, 0x15, 0x4A, 0xB2, 0x01, 0x00,
-        ];
-        let parsed = PortalPsbt::parse(&data).unwrap().inputs;
-
-        assert_eq!(parsed.len(), 3);
-        for input in &parsed {
-            assert_eq!(input.partial_sigs.len(), 1);
-        }
+    fn test_verify_p2wpkh() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pk = PublicKey::new(key.public_key(&secp));
+        let original = make_psbt(Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()));
+
+        let mut signed = original.clone();
+        sign_p2wpkh(&mut signed, &key);
+        let report = verify_signed_psbt(&encode(&original), &encode(&signed)).unwrap();
+        assert!(report.valid);
+        assert_eq!(report.fees, Some(1_000));
+        assert_eq!(report.output_value, 9_000);
+        assert_eq!(report.inputs[0].new_signatures, 1);
+
+        // Nothing was signed
+        let report = verify_signed_psbt(&encode(&original), &encode(&original)).unwrap();
+        assert!(!report.valid);
+
+        // Signed with a different value for the input, i.e. with different fees
+        let mut tampered = original.clone();
+        tampered.inputs[0].witness_utxo.as_mut().unwrap().value = 20_000;
+        sign_p2wpkh(&mut tampered, &key);
+        let mut signed = original.clone();
+        signed.inputs[0].partial_sigs = tampered.inputs[0].partial_sigs.clone();
+        let report = verify_signed_psbt(&encode(&original), &encode(&signed)).unwrap();
+        assert!(!report.valid);
+        assert_eq!(report.inputs[0].invalid_signatures, 1);
+
+        // An input was added
+        let mut signed = original.clone();
+        signed.unsigned_tx.input.push(TxIn::default());
+        signed.inputs.push(Default::default());
+        assert!(matches!(
+            verify_signed_psbt(&encode(&original), &encode(&signed)),
+            Err(SdkError::InvalidSignatures { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_anti_exfil() {
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pk = PublicKey::new(key.public_key(&secp));
+        let original = make_psbt(Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap()));
+        let host_data = [0x07; 32];
+
+        let utxo = original.inputs[0].witness_utxo.clone().unwrap();
+        let msg = model::psbt::ecdsa_sighash(
+            &mut SighashCache::new(&original.unsigned_tx),
+            0,
+            &utxo,
+            &original.inputs[0],
+            &pk,
+            EcdsaSighashType::All,
+        )
+        .unwrap();
+        let host_commitment = model::anti_exfil::host_commitment(&host_data);
+        let nonce = model::anti_exfil::signer_commitment(&secp, &key, &msg, &host_commitment);
+        let commitments = [SignerCommitment {
+            input: 0,
+            pubkey: Box::new(pk.inner.serialize().into()),
+            nonce: Box::new(nonce.serialize().into()),
+        }];
+
+        let mut signed = original.clone();
+        let sig = model::anti_exfil::sign(&secp, &key, &msg, &host_data);
+        signed.inputs[0]
+            .partial_sigs
+            .insert(pk, EcdsaSig::sighash_all(sig));
+        assert!(verify_anti_exfil(&original, &signed, &commitments, &host_data).is_ok());
+        assert!(verify_anti_exfil(&original, &signed, &[], &host_data).is_err());
+
+        // Valid, but with a nonce chosen by the device
+        let mut signed = original.clone();
+        sign_p2wpkh(&mut signed, &key);
+        assert!(verify_anti_exfil(&original, &signed, &commitments, &host_data).is_err());
+    }
+
+    #[test]
+    fn test_verify_taproot_key_spend() {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[0x42; 32]).unwrap();
+        let (internal_key, _) = keypair.x_only_public_key();
+        let original = make_psbt(Script::new_v1_p2tr(&secp, internal_key, None));
+
+        let utxos = [original.inputs[0].witness_utxo.clone().unwrap()];
+        let sign = |ty: SchnorrSighashType| {
+            let sighash = SighashCache::new(&original.unsigned_tx)
+                .taproot_key_spend_signature_hash(0, &Prevouts::All(&utxos), ty)
+                .unwrap();
+            let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+            let sig = secp
+                .sign_schnorr_no_aux_rand(&Message::from_slice(&sighash[..]).unwrap(), &tweaked);
+
+            let mut signed = original.clone();
+            signed.inputs[0].tap_key_sig = Some(SchnorrSig { sig, hash_ty: ty });
+            verify_signed_psbt(&encode(&original), &encode(&signed)).unwrap()
+        };
+
+        assert!(sign(SchnorrSighashType::Default).valid);
+
+        // Valid, but it doesn't commit to the other outputs
+        let report = sign(SchnorrSighashType::Single);
+        assert!(!report.valid);
+        assert_eq!(report.inputs[0].invalid_signatures, 0);
+        assert_eq!(report.inputs[0].partial_commitments, 1);
     }
 }
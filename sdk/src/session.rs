@@ -0,0 +1,106 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_std::sync::Mutex;
+
+use super::*;
+
+/// Manage sessions with multiple devices
+///
+/// Every card is identified by its NFC UID and gets its own `PortalSdk` instance, so that
+/// multiple devices can be tapped in sequence (for example during a multisig ceremony) without
+/// losing track of which one is which.
+///
+/// The transport should call `card_detected()` whenever a card enters the field of any reader,
+/// and then drive the returned `PortalSdk` as usual.
+#[cfg_attr(feature = "bindings", derive(uniffi::Object))]
+pub struct SessionManager {
+    use_fast_ops: bool,
+    sessions: Mutex<HashMap<Vec<u8>, Arc<PortalSdk>>>,
+    active: Mutex<Option<Vec<u8>>>,
+}
+
+#[cfg_attr(feature = "bindings", uniffi::export)]
+impl SessionManager {
+    #[uniffi::constructor]
+    pub fn new(use_fast_ops: bool) -> Arc<Self> {
+        Arc::new(SessionManager {
+            use_fast_ops,
+            sessions: Mutex::new(HashMap::new()),
+            active: Mutex::new(None),
+        })
+    }
+
+    /// Notify that the card with the given UID is in the field of a reader
+    ///
+    /// Returns the session for the card, creating a new one if it's the first time we see it.
+    pub async fn card_detected(&self, uid: Vec<u8>) -> Result<Arc<PortalSdk>, SdkError> {
+        if uid.is_empty() {
+            return Err(SdkError::DifferentUid);
+        }
+
+        let sdk = Arc::clone(
+            self.sessions
+                .lock()
+                .await
+                .entry(uid.clone())
+                .or_insert_with(|| PortalSdk::new(self.use_fast_ops)),
+        );
+        sdk.new_tag().await?;
+        *self.active.lock().await = Some(uid);
+
+        Ok(sdk)
+    }
+
+    /// Notify that the active card left the field
+    pub async fn card_removed(&self) {
+        *self.active.lock().await = None;
+    }
+
+    /// UID of the card currently in the field, if any
+    pub async fn active_uid(&self) -> Option<Vec<u8>> {
+        self.active.lock().await.clone()
+    }
+
+    /// Session of the card currently in the field, if any
+    pub async fn active(&self) -> Option<Arc<PortalSdk>> {
+        let uid = self.active.lock().await.clone()?;
+        self.get(uid).await
+    }
+
+    pub async fn get(&self, uid: Vec<u8>) -> Option<Arc<PortalSdk>> {
+        self.sessions.lock().await.get(&uid).cloned()
+    }
+
+    /// UIDs of all the cards seen so far
+    pub async fn list(&self) -> Vec<Vec<u8>> {
+        self.sessions.lock().await.keys().cloned().collect()
+    }
+
+    /// Forget about a card, stopping its session
+    pub async fn remove(&self, uid: Vec<u8>) {
+        let mut active = self.active.lock().await;
+        if active.as_ref() == Some(&uid) {
+            *active = None;
+        }
+
+        self.sessions.lock().await.remove(&uid);
+    }
+}
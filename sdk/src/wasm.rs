@@ -0,0 +1,577 @@
+// Portal Hardware Wallet firmware and supporting software libraries
+//
+// Copyright (C) 2024 Alekos Filini
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! JavaScript bindings for the SDK
+//!
+//! The NFC transport is provided by the page as an async `transceive` function that takes a
+//! `Uint8Array`, sends it to the card and resolves to the `Uint8Array` received in response.
+//! This way the same bindings can be used with Web NFC or any other transport available in the
+//! browser.
+
+use std::sync::Arc;
+
+use js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+use super::*;
+
+fn to_js_error(e: SdkError) -> JsValue {
+    let error = js_sys::Error::new(&e.to_string());
+    let _ = Reflect::set(&error, &"code".into(), &e.code().into());
+    if let SdkError::DeviceError {
+        code: Some(code), ..
+    } = &e
+    {
+        // Name of the `DeviceErrorCode` variant, e.g. "LocalKeyMissing"
+        let _ = Reflect::set(&error, &"deviceCode".into(), &format!("{:?}", code).into());
+    }
+    error.into()
+}
+
+fn set(obj: &Object, key: &str, value: JsValue) {
+    Reflect::set(obj, &key.into(), &value).expect("Setting a property on a plain object");
+}
+
+#[wasm_bindgen]
+pub struct PortalWeb {
+    sdk: Arc<PortalSdk>,
+    transceive: Function,
+}
+
+#[wasm_bindgen]
+impl PortalWeb {
+    #[wasm_bindgen(constructor)]
+    pub fn new(transceive: Function, use_fast_ops: bool) -> PortalWeb {
+        PortalWeb {
+            sdk: PortalSdk::new(use_fast_ops),
+            transceive,
+        }
+    }
+
+    /// Exchange messages with the card until the transport fails
+    ///
+    /// This must be called every time a card is tapped, before or concurrently to any other
+    /// method. It resolves when `transceive` throws, which usually means the card was removed.
+    #[wasm_bindgen(js_name = runTransport)]
+    pub async fn run_transport(&self) -> Result<(), JsValue> {
+        self.sdk.new_tag().await.map_err(to_js_error)?;
+
+        loop {
+            let NfcOut { msg_index, data } = self.sdk.poll().await.map_err(to_js_error)?;
+
+            let promise: Promise = self
+                .transceive
+                .call1(&JsValue::NULL, &Uint8Array::from(data.as_slice()))?
+                .dyn_into()?;
+            let reply = JsFuture::from(promise).await?;
+            let reply = Uint8Array::new(&reply).to_vec();
+
+            self.sdk
+                .incoming_data(msg_index, reply)
+                .await
+                .map_err(to_js_error)?;
+        }
+    }
+
+    #[wasm_bindgen(js_name = getStatus)]
+    pub async fn get_status(&self) -> Result<Object, JsValue> {
+        let status = self.sdk.get_status().await.map_err(to_js_error)?;
+
+        let obj = Object::new();
+        set(&obj, "initialized", status.initialized.into());
+        set(&obj, "unverified", status.unverified.into());
+        set(&obj, "unlocked", status.unlocked.into());
+        set(
+            &obj,
+            "network",
+            status.network.map(|n| n.to_string()).into(),
+        );
+        set(&obj, "version", status.version.into());
+        set(
+            &obj,
+            "fingerprint",
+            status.fingerprint.map(|f| f.to_string()).into(),
+        );
+        set(&obj, "protocolVersion", status.protocol_version.into());
+        if let Some(descriptor) = status.descriptor {
+            let summary = Object::new();
+            set(&summary, "scriptType", descriptor.script_type.into());
+            set(&summary, "threshold", descriptor.threshold.into());
+            set(&summary, "numKeys", descriptor.num_keys.into());
+            set(&summary, "isSorted", descriptor.is_sorted.into());
+            set(&obj, "descriptor", summary.into());
+        }
+        set(&obj, "firmwareHash", status.firmware_hash.into());
+        set(&obj, "hardwareRevision", status.hardware_revision.into());
+        set(&obj, "serial", status.serial.into());
+        set(&obj, "capabilities", status.capabilities.into());
+
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = generateMnemonic)]
+    pub async fn generate_mnemonic(
+        &self,
+        words24: bool,
+        network: String,
+        password: Option<String>,
+        disable_seed_export: Option<bool>,
+        language: Option<String>,
+    ) -> Result<(), JsValue> {
+        let num_words = match words24 {
+            true => GenerateMnemonicWords::Words24,
+            false => GenerateMnemonicWords::Words12,
+        };
+        let network = network
+            .parse()
+            .map_err(|_| JsValue::from_str("Invalid network"))?;
+        let language = match language.as_deref() {
+            None | Some("english") => None,
+            Some("spanish") => Some(GenerateMnemonicLanguage::Spanish),
+            Some("french") => Some(GenerateMnemonicLanguage::French),
+            Some("italian") => Some(GenerateMnemonicLanguage::Italian),
+            Some("czech") => Some(GenerateMnemonicLanguage::Czech),
+            Some(_) => return Err(JsValue::from_str("Invalid language")),
+        };
+
+        self.sdk
+            .generate_mnemonic(
+                num_words,
+                network,
+                password,
+                disable_seed_export.unwrap_or(false),
+                language,
+            )
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = restoreMnemonic)]
+    pub async fn restore_mnemonic(
+        &self,
+        mnemonic: String,
+        network: String,
+        password: Option<String>,
+        disable_seed_export: Option<bool>,
+    ) -> Result<(), JsValue> {
+        let network = network
+            .parse()
+            .map_err(|_| JsValue::from_str("Invalid network"))?;
+
+        self.sdk
+            .restore_mnemonic(
+                mnemonic,
+                network,
+                password,
+                disable_seed_export.unwrap_or(false),
+            )
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = setSeedExport)]
+    pub async fn set_seed_export(&self, allowed: bool) -> Result<(), JsValue> {
+        self.sdk.set_seed_export(allowed).await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = setConfirmationPolicy)]
+    pub async fn set_confirmation_policy(
+        &self,
+        silent_get_xpub: bool,
+        silent_public_descriptor: bool,
+    ) -> Result<(), JsValue> {
+        self.sdk
+            .set_confirmation_policy(silent_get_xpub, silent_public_descriptor)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = addTrustedAddress)]
+    pub async fn add_trusted_address(&self, address: String) -> Result<(), JsValue> {
+        self.sdk
+            .add_trusted_address(address)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = beginWipe)]
+    pub async fn begin_wipe(&self) -> Result<(), JsValue> {
+        self.sdk.begin_wipe().await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = confirmWipe)]
+    pub async fn confirm_wipe(&self, code: String) -> Result<JsValue, JsValue> {
+        let report = match self.sdk.confirm_wipe(code).await.map_err(to_js_error)? {
+            Some(report) => report,
+            None => return Ok(JsValue::NULL),
+        };
+
+        let failed_pages = report
+            .failed_pages
+            .into_iter()
+            .map(JsValue::from)
+            .collect::<Array>();
+
+        let obj = Object::new();
+        set(&obj, "pagesChecked", report.pages_checked.into());
+        set(&obj, "pagesErased", report.pages_erased.into());
+        set(&obj, "passes", report.passes.into());
+        set(&obj, "failedPages", failed_pages.into());
+        set(&obj, "updateCheckpoints", report.update_checkpoints.into());
+        set(&obj, "verified", report.verified.into());
+
+        Ok(obj.into())
+    }
+
+    pub async fn heartbeat(&self) -> Result<(), JsValue> {
+        self.sdk.heartbeat().await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = deriveNodeSeed)]
+    pub async fn derive_node_seed(&self, index: u32) -> Result<Vec<u8>, JsValue> {
+        self.sdk.derive_node_seed(index).await.map_err(to_js_error)
+    }
+
+    pub async fn unlock(&self, password: String) -> Result<(), JsValue> {
+        self.sdk.unlock(password).await.map_err(to_js_error)
+    }
+
+    pub async fn resume(&self) -> Result<(), JsValue> {
+        self.sdk.resume().await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = displayAddress)]
+    pub async fn display_address(&self, index: u32) -> Result<String, JsValue> {
+        let address = self.sdk.display_address(index).await.map_err(to_js_error)?;
+        Ok(address.to_string())
+    }
+
+    #[wasm_bindgen(js_name = displayAddressWithInfo)]
+    pub async fn display_address_with_info(&self, index: u32) -> Result<Object, JsValue> {
+        let address = self
+            .sdk
+            .display_address_with_info(index)
+            .await
+            .map_err(to_js_error)?;
+
+        let info = match address.info {
+            Some(info) => {
+                let obj = Object::new();
+                let keychain = match info.keychain {
+                    AddressKeychain::External => "external",
+                    AddressKeychain::Internal => "internal",
+                };
+                let script_type = match info.script_type {
+                    AddressScriptType::Legacy => "legacy",
+                    AddressScriptType::WrappedSegwit => "wrappedSegwit",
+                    AddressScriptType::NativeSegwit => "nativeSegwit",
+                    AddressScriptType::Taproot => "taproot",
+                };
+                set(&obj, "keychain", keychain.into());
+                set(&obj, "index", info.index.into());
+                set(
+                    &obj,
+                    "derivationPath",
+                    info.derivation_path.to_string().into(),
+                );
+                set(&obj, "scriptType", script_type.into());
+                set(
+                    &obj,
+                    "scriptPubkey",
+                    Uint8Array::from(info.script_pubkey.as_slice()).into(),
+                );
+                obj.into()
+            }
+            None => JsValue::NULL,
+        };
+
+        let obj = Object::new();
+        set(&obj, "address", address.address.to_string().into());
+        set(&obj, "info", info);
+
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = signPsbt)]
+    pub async fn sign_psbt(&self, psbt: String) -> Result<String, JsValue> {
+        self.sdk.sign_psbt(psbt).await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = signPsbtWithFiatRate)]
+    pub async fn sign_psbt_with_fiat_rate(
+        &self,
+        psbt: String,
+        rate: f64,
+        currency: String,
+    ) -> Result<String, JsValue> {
+        self.sdk
+            .sign_psbt_with_fiat_rate(psbt, rate, currency)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = signPsbtWithCpfp)]
+    pub async fn sign_psbt_with_cpfp(
+        &self,
+        psbt: String,
+        parent_txid: String,
+        fee_rate: f32,
+    ) -> Result<String, JsValue> {
+        self.sdk
+            .sign_psbt_with_cpfp(psbt, parent_txid, fee_rate)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Resolve to `{transaction}` if the device finalized the PSBT, to `{psbt}` otherwise
+    #[wasm_bindgen(js_name = signAndFinalizePsbt)]
+    pub async fn sign_and_finalize_psbt(&self, psbt: String) -> Result<Object, JsValue> {
+        let finalized = self
+            .sdk
+            .sign_and_finalize_psbt(psbt)
+            .await
+            .map_err(to_js_error)?;
+
+        let obj = Object::new();
+        set(&obj, "transaction", finalized.transaction.into());
+        set(&obj, "psbt", finalized.psbt.into());
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = signPsbtAntiExfil)]
+    pub async fn sign_psbt_anti_exfil(&self, psbt: String) -> Result<String, JsValue> {
+        self.sdk
+            .sign_psbt_anti_exfil(psbt)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Resolve to the signed PSBTs, in the same order as `psbts`
+    #[wasm_bindgen(js_name = signPsbtBatch)]
+    pub async fn sign_psbt_batch(&self, psbts: Array) -> Result<Array, JsValue> {
+        let psbts = psbts
+            .iter()
+            .map(|psbt| {
+                psbt.as_string()
+                    .ok_or_else(|| JsValue::from_str("Invalid PSBT"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let signed = self.sdk.sign_psbt_batch(psbts).await.map_err(to_js_error)?;
+
+        Ok(signed.into_iter().map(JsValue::from).collect())
+    }
+
+    #[wasm_bindgen(js_name = signPayjoinPsbt)]
+    pub async fn sign_payjoin_psbt(&self, psbt: String) -> Result<String, JsValue> {
+        self.sdk.sign_payjoin_psbt(psbt).await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = signPayjoinReceiverPsbt)]
+    pub async fn sign_payjoin_receiver_psbt(
+        &self,
+        original: String,
+        proposal: String,
+    ) -> Result<String, JsValue> {
+        self.sdk
+            .sign_payjoin_receiver_psbt(original, proposal)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = signProofOfReserves)]
+    pub async fn sign_proof_of_reserves(
+        &self,
+        psbt: String,
+        message: String,
+    ) -> Result<String, JsValue> {
+        self.sdk
+            .sign_proof_of_reserves(psbt, message)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Resolve to `{deviceKey, firmwareHash}` if the device is certified by `rootKey`
+    pub async fn attest(&self, root_key: String) -> Result<Object, JsValue> {
+        let attestation = self.sdk.attest(root_key).await.map_err(to_js_error)?;
+
+        let obj = Object::new();
+        set(&obj, "deviceKey", attestation.device_key.into());
+        set(&obj, "firmwareHash", attestation.firmware_hash.into());
+
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = exportBackup)]
+    pub async fn export_backup(
+        &self,
+        password: String,
+        include_seed: bool,
+    ) -> Result<Vec<u8>, JsValue> {
+        self.sdk
+            .export_backup(password, include_seed)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = restoreBackup)]
+    pub async fn restore_backup(
+        &self,
+        backup: Vec<u8>,
+        password: String,
+        pair_code: Option<String>,
+    ) -> Result<(), JsValue> {
+        self.sdk
+            .restore_backup(backup, password, pair_code)
+            .await
+            .map_err(to_js_error)
+    }
+
+    /// Resolve to `{flash, rng, display, touch}`, each either "passed", "skipped" or
+    /// "failed: <cause>"
+    #[wasm_bindgen(js_name = selfTest)]
+    pub async fn self_test(&self) -> Result<Object, JsValue> {
+        let report = self.sdk.self_test().await.map_err(to_js_error)?;
+
+        let outcome = |outcome: SelfTestOutcome| -> JsValue {
+            match outcome {
+                SelfTestOutcome::Passed => "passed".into(),
+                SelfTestOutcome::Failed { cause } => format!("failed: {}", cause).into(),
+                SelfTestOutcome::Skipped => "skipped".into(),
+            }
+        };
+
+        let obj = Object::new();
+        set(&obj, "flash", outcome(report.flash));
+        set(&obj, "rng", outcome(report.rng));
+        set(&obj, "display", outcome(report.display));
+        set(&obj, "touch", outcome(report.touch));
+
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = getXpub)]
+    pub async fn get_xpub(&self, path: String) -> Result<Object, JsValue> {
+        let path = path
+            .parse()
+            .map_err(|_| JsValue::from_str("Invalid derivation path"))?;
+        let xpub = self.sdk.get_xpub(path).await.map_err(to_js_error)?;
+
+        let bsms = Object::new();
+        set(&bsms, "version", xpub.bsms.version.into());
+        set(&bsms, "token", xpub.bsms.token.into());
+        set(&bsms, "keyName", xpub.bsms.key_name.into());
+        set(&bsms, "signature", xpub.bsms.signature.into());
+
+        let key = match xpub.key {
+            Some(key) => {
+                let obj = Object::new();
+                set(&obj, "xpub", Uint8Array::from(key.xpub.as_slice()).into());
+                set(
+                    &obj,
+                    "parentFingerprint",
+                    key.parent_fingerprint.to_string().into(),
+                );
+                set(
+                    &obj,
+                    "masterFingerprint",
+                    key.master_fingerprint.to_string().into(),
+                );
+                set(&obj, "originPath", key.origin_path.to_string().into());
+                set(&obj, "network", key.network.to_string().into());
+                obj.into()
+            }
+            None => JsValue::NULL,
+        };
+
+        let obj = Object::new();
+        set(&obj, "xpub", xpub.xpub.into());
+        set(&obj, "bsms", bsms.into());
+        set(&obj, "key", key);
+
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = setDescriptor)]
+    pub async fn set_descriptor(
+        &self,
+        descriptor: String,
+        bsms_first_address: Option<String>,
+    ) -> Result<(), JsValue> {
+        let bsms = bsms_first_address.map(|first_address| SetDescriptorBsmsData {
+            version: "1.0".into(),
+            path_restrictions: "/0/*,/1/*".into(),
+            first_address,
+        });
+
+        self.sdk
+            .set_descriptor(descriptor, bsms)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = registerWalletPolicy)]
+    pub async fn register_wallet_policy(
+        &self,
+        name: String,
+        descriptor: String,
+    ) -> Result<Vec<u8>, JsValue> {
+        self.sdk
+            .register_wallet_policy(name, descriptor)
+            .await
+            .map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = useWalletPolicy)]
+    pub async fn use_wallet_policy(&self, hmac: Vec<u8>) -> Result<(), JsValue> {
+        self.sdk.use_wallet_policy(hmac).await.map_err(to_js_error)
+    }
+
+    #[wasm_bindgen(js_name = publicDescriptors)]
+    pub async fn public_descriptors(&self) -> Result<Object, JsValue> {
+        let descriptors = self.sdk.public_descriptors().await.map_err(to_js_error)?;
+
+        let obj = Object::new();
+        set(&obj, "external", descriptors.external.into());
+        set(&obj, "internal", descriptors.internal.into());
+        set(&obj, "multipath", descriptors.multipath.into());
+
+        Ok(obj)
+    }
+
+    #[wasm_bindgen(js_name = updateFirmware)]
+    pub async fn update_firmware(&self, binary: Vec<u8>) -> Result<(), JsValue> {
+        self.sdk.update_firmware(binary).await.map_err(to_js_error)
+    }
+
+    /// Resolve to the number of bytes already transferred by an interrupted update of `binary`
+    #[wasm_bindgen(js_name = firmwareUpdateOffset)]
+    pub async fn firmware_update_offset(&self, binary: Vec<u8>) -> Result<f64, JsValue> {
+        let offset = self
+            .sdk
+            .firmware_update_offset(binary)
+            .await
+            .map_err(to_js_error)?;
+        Ok(offset as f64)
+    }
+
+    /// Resolve to the next progress update as a `[current, total]` array
+    pub async fn progress(&self) -> Result<Vec<u32>, JsValue> {
+        let progress = self.sdk.progress().await.map_err(to_js_error)?;
+        Ok(vec![progress.current, progress.total])
+    }
+}